@@ -0,0 +1,163 @@
+//! Micro-benchmarks of the hot in-process paths a FUSE op goes through:
+//! path sanitation, the inode table, index location lookups and policy
+//! evaluation. `cargo bench --bench internals`; criterion keeps the last
+//! run under `target/criterion`, so a refactor shows up as a change
+//! against it.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use rhss::backend::sanitize_rel;
+use rhss::config::PolicyOptions;
+use rhss::fuse::InodeMap;
+use rhss::index::{FileRow, FileState, Location, Mutability, PathIndex, SqlitePathIndex, TierId};
+use rhss::policy::{ema_step, ReloadablePolicy, TieringPolicy};
+
+const FILES: usize = 10_000;
+
+fn logical(n: usize) -> PathBuf {
+    PathBuf::from(format!("/data/d{:03}/file-{n:05}.bin", n % 100))
+}
+
+fn sanitation(c: &mut Criterion) {
+    let mut g = c.benchmark_group("sanitize_rel");
+    g.bench_function("plain", |b| {
+        b.iter(|| sanitize_rel(black_box(Path::new("data/d042/file-00042.bin"))))
+    });
+    g.bench_function("deep", |b| {
+        let deep: PathBuf = (0..32).map(|i| format!("level{i}")).collect();
+        b.iter(|| sanitize_rel(black_box(&deep)))
+    });
+    g.bench_function("rejected", |b| {
+        b.iter(|| sanitize_rel(black_box(Path::new("data/../../etc/passwd"))))
+    });
+    g.finish();
+}
+
+fn inode_table(c: &mut Criterion) {
+    let mut g = c.benchmark_group("inode_map");
+    let mut map = InodeMap::new(FILES * 2);
+    let inos: Vec<u64> = (0..FILES).map(|n| map.lookup_ref(logical(n))).collect();
+    g.bench_function("lookup_path", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % inos.len();
+            map.lookup_path(black_box(inos[i]))
+        })
+    });
+    g.bench_function("ino_of", |b| {
+        let paths: Vec<PathBuf> = (0..FILES).map(logical).collect();
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % paths.len();
+            map.ino_of(black_box(&paths[i]))
+        })
+    });
+    g.bench_function("allocate_evicting", |b| {
+        // At the cap, every new path evicts the least recently used one.
+        let mut full = InodeMap::new(FILES);
+        for n in 0..FILES {
+            full.allocate(logical(n));
+        }
+        let mut n = FILES;
+        b.iter(|| {
+            n += 1;
+            full.allocate(logical(n))
+        })
+    });
+    g.bench_function("rename_dir", |b| {
+        b.iter_batched(
+            || {
+                let mut m = InodeMap::new(FILES * 2);
+                for n in 0..FILES {
+                    m.lookup_ref(logical(n));
+                }
+                m
+            },
+            |mut m| m.rename(Path::new("/data/d042"), PathBuf::from("/data/moved")),
+            BatchSize::LargeInput,
+        )
+    });
+    g.finish();
+}
+
+fn location_lookups(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let index = SqlitePathIndex::open(dir.path().join("index.db")).unwrap();
+    for n in 0..FILES {
+        let path = logical(n);
+        index
+            .insert(FileRow {
+                location: Location {
+                    tier: TierId::Fast,
+                    backend_id: "ssd".into(),
+                    backend_path: path.strip_prefix("/").unwrap().to_path_buf(),
+                    size: 4096,
+                },
+                logical_path: path,
+                replicas: Vec::new(),
+                last_access: SystemTime::now(),
+                hit_count: 0,
+                popularity: 0.0,
+                pinned_tier: None,
+                pinned_until: None,
+                state: FileState::Stable,
+                mutability: Mutability::Unknown,
+                compressed: false,
+                content_hash: None,
+            })
+            .unwrap();
+    }
+    let mut g = c.benchmark_group("index");
+    g.bench_function("locate_hit", |b| {
+        let mut n = 0;
+        b.iter(|| {
+            n = (n + 7) % FILES;
+            index.locate(black_box(&logical(n))).unwrap()
+        })
+    });
+    g.bench_function("locate_miss", |b| {
+        b.iter(|| {
+            index
+                .locate(black_box(Path::new("/data/none.bin")))
+                .unwrap()
+        })
+    });
+    g.bench_function("get", |b| {
+        let mut n = 0;
+        b.iter(|| {
+            n = (n + 7) % FILES;
+            index.get(black_box(&logical(n))).unwrap()
+        })
+    });
+    g.finish();
+}
+
+fn policy(c: &mut Criterion) {
+    let mut g = c.benchmark_group("policy");
+    g.bench_function("ema_step", |b| {
+        b.iter(|| {
+            ema_step(
+                black_box(60.0),
+                black_box(12),
+                black_box(0.4),
+                black_box(86_400.0),
+            )
+        })
+    });
+    let direct: Arc<dyn TieringPolicy> = Arc::new(PolicyOptions::default().to_policy());
+    g.bench_function("tier_for_create", |b| {
+        b.iter(|| direct.tier_for_create(black_box(0.93)))
+    });
+    let reloadable: Arc<dyn TieringPolicy> = ReloadablePolicy::new(Arc::clone(&direct));
+    g.bench_function("tier_for_create_reloadable", |b| {
+        b.iter(|| reloadable.tier_for_create(black_box(0.93)))
+    });
+    g.finish();
+}
+
+criterion_group!(benches, sanitation, inode_table, location_lookups, policy);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+#ifndef RHSS_H
+#define RHSS_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define RHSS_TIER_FAST 0
+
+#define RHSS_TIER_SLOW 1
+
+#define RHSS_TIER_ARCHIVE 2
+
+// Tier reported for paths the index doesn't track (directories).
+#define RHSS_TIER_NONE -1
+
+// An initialized rhss instance. Opaque to C.
+typedef struct rhss_t rhss_t;
+
+// Attributes filled in by `rhss_stat`. Times are seconds since the epoch.
+typedef struct {
+  uint64_t size;
+  uint32_t mode;
+  uint32_t uid;
+  uint32_t gid;
+  bool is_dir;
+  // One of `RHSS_TIER_*`.
+  int tier;
+  int64_t atime;
+  int64_t mtime;
+  int64_t ctime;
+} rhss_stat_t;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Open the storage described by the TOML config at `config_path`: index
+// the backends and start the tierer. Returns
+// NULL on failure; see `rhss_last_error`.
+//
+// # Safety
+// `config_path` is NULL or a NUL-terminated string.
+rhss_t *rhss_init(const char *config_path);
+
+// Mount the namespace at `mount_point`, served from background threads.
+// `-EBUSY` if this handle is already mounted.
+//
+// # Safety
+// `h` is a live handle; `mount_point` is a NUL-terminated string.
+int rhss_mount(rhss_t *h, const char *mount_point);
+
+// Unmount what `rhss_mount` mounted. `-EINVAL` if nothing is mounted.
+//
+// # Safety
+// `h` is a live handle.
+int rhss_unmount(rhss_t *h);
+
+// Read up to `len` bytes of file `path` at `offset` into `buf`. Returns
+// the byte count, 0 at end of file.
+//
+// # Safety
+// `h` is a live handle, `path` a NUL-terminated string and `buf` valid
+// for `len` bytes of writes.
+int64_t rhss_read(rhss_t *h, const char *path, uint64_t offset, uint8_t *buf, size_t len);
+
+// Write `len` bytes from `buf` into file `path` at `offset`, creating
+// the file (placed by the tiering policy) if it doesn't exist. Returns
+// the byte count.
+//
+// # Safety
+// `h` is a live handle, `path` a NUL-terminated string and `buf` valid
+// for `len` bytes of reads.
+int64_t rhss_write(rhss_t *h, const char *path, uint64_t offset, const uint8_t *buf, size_t len);
+
+// Fill `out` with the attributes and current tier of `path`.
+//
+// # Safety
+// `h` is a live handle, `path` a NUL-terminated string and `out` valid
+// for writes.
+int rhss_stat(rhss_t *h, const char *path, rhss_stat_t *out);
+
+// Move file `path` to `tier` (`RHSS_TIER_*`) now. Returns 1 if it moved,
+// 0 if it was skipped: already there, open or pinned.
+//
+// # Safety
+// `h` is a live handle and `path` a NUL-terminated string.
+int rhss_migrate(rhss_t *h, const char *path, int tier);
+
+// Unmount if mounted, stop the tierer and free `h`. NULL is ignored.
+//
+// # Safety
+// `h` is NULL or a live handle, not used again afterwards.
+void rhss_shutdown(rhss_t *h);
+
+// Message for the last failed call on this thread, or NULL. Valid until
+// the next failing call on the same thread.
+const char *rhss_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RHSS_H */
@@ -1,5 +1,6 @@
 //! `AccessTracker` — batches access events and writes them to the index in
 //! 5-second windows, so the FUSE hot path doesn't pay a SQLite write per IO.
+//! `trace` records the same events, and more, to a file for offline tuning.
 
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -12,6 +13,8 @@ use tracing::{debug, warn};
 
 use crate::index::PathIndex;
 
+pub mod trace;
+
 /// Construct with `start()`; drops shut down the worker.
 pub struct AccessTracker {
     tx: Sender<Event>,
@@ -118,6 +121,7 @@ mod tests {
             hit_count: 0,
             popularity: 0.0,
             pinned_tier: None,
+            pinned_until: None,
             state: FileState::Stable,
             replicas: Vec::new(),
             mutability: crate::index::Mutability::Unknown,
@@ -0,0 +1,526 @@
+//! Access trace recording (`[access_trace] enabled = true`): every read,
+//! write, create and delete on the mount, as `(time, op, path, size,
+//! tier)`, in a compact binary file that `rhss simulate` replays and
+//! `rhss analyze` summarizes.
+//!
+//! Like the audit trail, FUSE workers only hand records to a writer thread
+//! through a bounded queue and drop them (counted, and logged by the
+//! writer) when it is full. The writer folds a run of reads or writes of
+//! one file into a single record as long as they follow each other within
+//! `COALESCE`, so streaming a file costs one record, not one per chunk.
+//!
+//! For reads and writes, `size` is how far into the file the op reached
+//! (offset + length), a lower bound of the file's size; for creates and
+//! deletes it is the file's size.
+//!
+//! # Format
+//!
+//! The file starts with `MAGIC`. Each time the trace is opened, a segment
+//! starts: tag `0` and the segment's start time (u64 LE, Unix ms). A
+//! record is a tag byte (op in bits 0-2, tier in bits 3-4, bit 5 when the
+//! path is new), the milliseconds since the previous record (or the
+//! segment start), the size, and either the new path (length and bytes)
+//! or the number of a path already seen in the segment. Numbers are
+//! LEB128 varints. A record cut short by a crash ends the trace.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::error::{FsError, Result};
+use crate::index::TierId;
+
+/// First bytes of every trace file.
+pub const MAGIC: &[u8; 8] = b"RHSSTRC1";
+
+/// Records queued before new ones are dropped.
+pub const DEFAULT_BUFFER: usize = 16384;
+
+/// Reads (or writes) of one file this close together are one record.
+pub const COALESCE: Duration = Duration::from_secs(1);
+
+/// A segment's path table is reset past this many paths, bounding the
+/// writer's memory.
+const MAX_PATHS: usize = 1 << 20;
+
+const NEW_PATH: u8 = 0x20;
+
+/// `<db.parent>/.rhss/access.trace`.
+pub fn trace_path_for(db: &Path) -> PathBuf {
+    db.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .join(".rhss")
+        .join("access.trace")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceOp {
+    Read,
+    Write,
+    Create,
+    Delete,
+}
+
+impl TraceOp {
+    fn code(self) -> u8 {
+        match self {
+            Self::Read => 1,
+            Self::Write => 2,
+            Self::Create => 3,
+            Self::Delete => 4,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::Read),
+            2 => Some(Self::Write),
+            3 => Some(Self::Create),
+            4 => Some(Self::Delete),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for TraceOp {
+    type Err = FsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "create" => Ok(Self::Create),
+            "delete" => Ok(Self::Delete),
+            _ => Err(FsError::InvalidOperation(format!(
+                "unknown trace op {s:?} (read, write, create, delete)"
+            ))),
+        }
+    }
+}
+
+fn tier_code(tier: Option<TierId>) -> u8 {
+    match tier {
+        None => 0,
+        Some(TierId::Fast) => 1,
+        Some(TierId::Slow) => 2,
+        Some(TierId::Archive) => 3,
+    }
+}
+
+/// One recorded access.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Milliseconds since the Unix epoch.
+    pub ts_ms: u64,
+    pub op: TraceOp,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Tier the op was served from (a delete: the file was on); `None`
+    /// when not known.
+    pub tier: Option<TierId>,
+}
+
+/// Handle to the writer thread. Dropping it drains the queue and flushes.
+pub struct AccessTrace {
+    tx: Option<SyncSender<TraceRecord>>,
+    dropped: Arc<AtomicU64>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for AccessTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessTrace")
+            .field("dropped", &self.dropped.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl AccessTrace {
+    /// Append to the trace at `path` (created if missing) and start the
+    /// writer. A torn record at the end of an existing trace is cut off
+    /// first. `buffer` is clamped to at least 1.
+    pub fn open(path: &Path, buffer: usize) -> Result<Arc<Self>> {
+        let io_err = |e: std::io::Error| FsError::from_io(e, path.display());
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| FsError::from_io(e, parent.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(io_err)?;
+        let existing = std::fs::read(path).map_err(io_err)?;
+        if existing.is_empty() {
+            file.write_all(MAGIC).map_err(io_err)?;
+        } else {
+            let (_, valid) = decode_prefix(&existing)
+                .map_err(|e| FsError::InvalidOperation(format!("{}: {e}", path.display())))?;
+            if valid < existing.len() {
+                warn!(
+                    "access trace {}: cutting off {} bytes of a torn record",
+                    path.display(),
+                    existing.len() - valid
+                );
+                file.set_len(valid as u64).map_err(io_err)?;
+            }
+        }
+        let mut out = Encoder::new(BufWriter::new(file));
+        out.segment(now_ms()).map_err(io_err)?;
+
+        let (tx, rx) = mpsc::sync_channel(buffer.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let worker = std::thread::Builder::new()
+            .name("rhss-access-trace".into())
+            .spawn({
+                let dropped = Arc::clone(&dropped);
+                move || drain(rx, out, &dropped)
+            })
+            .map_err(|e| FsError::Storage(format!("access trace: spawn writer: {e}")))?;
+        Ok(Arc::new(Self {
+            tx: Some(tx),
+            dropped,
+            worker: Some(worker),
+        }))
+    }
+
+    /// Queue an access that happened now, without blocking.
+    pub fn record(&self, op: TraceOp, path: PathBuf, size: u64, tier: Option<TierId>) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let rec = TraceRecord {
+            ts_ms: now_ms(),
+            op,
+            path,
+            size,
+            tier,
+        };
+        match tx.try_send(rec) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Drop for AccessTrace {
+    fn drop(&mut self) {
+        self.tx.take();
+        if let Some(w) = self.worker.take() {
+            let _ = w.join();
+        }
+    }
+}
+
+fn drain(rx: Receiver<TraceRecord>, mut out: Encoder<BufWriter<File>>, dropped: &AtomicU64) {
+    let mut failing = false;
+    let mut emit = |out: &mut Encoder<BufWriter<File>>, rec: &TraceRecord| match out.record(rec) {
+        Ok(()) => failing = false,
+        Err(e) if !failing => {
+            warn!("access trace: write failed: {e}");
+            failing = true;
+        }
+        Err(_) => {}
+    };
+    // The run being folded, and when its last op arrived.
+    let mut pending: Option<(TraceRecord, u64)> = None;
+    loop {
+        let next = match rx.recv_timeout(COALESCE) {
+            Ok(rec) => Some(rec),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        match (&mut pending, next) {
+            (Some((run, last)), Some(rec))
+                if matches!(rec.op, TraceOp::Read | TraceOp::Write)
+                    && rec.op == run.op
+                    && rec.path == run.path
+                    && rec.ts_ms.saturating_sub(*last) < COALESCE.as_millis() as u64 =>
+            {
+                run.size = run.size.max(rec.size);
+                run.tier = rec.tier.or(run.tier);
+                *last = rec.ts_ms;
+            }
+            (_, Some(rec)) => {
+                if let Some((run, _)) = pending.take() {
+                    emit(&mut out, &run);
+                }
+                let last = rec.ts_ms;
+                pending = Some((rec, last));
+            }
+            (_, None) => {
+                if let Some((run, _)) = pending.take() {
+                    emit(&mut out, &run);
+                }
+                let lost = dropped.swap(0, Ordering::Relaxed);
+                if lost > 0 {
+                    warn!("access trace: dropped {lost} records, queue full");
+                }
+                if let Err(e) = out.flush() {
+                    warn!("access trace: flush: {e}");
+                }
+            }
+        }
+    }
+    if let Some((run, _)) = pending {
+        emit(&mut out, &run);
+    }
+    if let Err(e) = out.flush() {
+        warn!("access trace: flush: {e}");
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Writes segments and records.
+struct Encoder<W> {
+    w: W,
+    paths: HashMap<PathBuf, u64>,
+    last_ms: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(w: W) -> Self {
+        Self {
+            w,
+            paths: HashMap::new(),
+            last_ms: 0,
+        }
+    }
+
+    fn segment(&mut self, start_ms: u64) -> std::io::Result<()> {
+        self.paths.clear();
+        self.last_ms = start_ms;
+        self.w.write_all(&[0])?;
+        self.w.write_all(&start_ms.to_le_bytes())
+    }
+
+    fn record(&mut self, rec: &TraceRecord) -> std::io::Result<()> {
+        if self.paths.len() >= MAX_PATHS {
+            self.segment(self.last_ms)?;
+        }
+        let id = self.paths.get(&rec.path).copied();
+        let mut tag = rec.op.code() | tier_code(rec.tier) << 3;
+        if id.is_none() {
+            tag |= NEW_PATH;
+        }
+        let mut buf = vec![tag];
+        // Records from racing workers can arrive slightly out of order.
+        let ts = rec.ts_ms.max(self.last_ms);
+        put_varint(&mut buf, ts - self.last_ms);
+        self.last_ms = ts;
+        put_varint(&mut buf, rec.size);
+        match id {
+            Some(id) => put_varint(&mut buf, id),
+            None => {
+                let bytes = rec.path.as_os_str().as_bytes();
+                put_varint(&mut buf, bytes.len() as u64);
+                buf.extend_from_slice(bytes);
+                let next = self.paths.len() as u64;
+                self.paths.insert(rec.path.clone(), next);
+            }
+        }
+        self.w.write_all(&buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.w.flush()
+    }
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// Whether `bytes` look like a trace file.
+pub fn is_trace(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Every record of a trace file, in file order.
+pub fn read(path: &Path) -> Result<Vec<TraceRecord>> {
+    let bytes = std::fs::read(path).map_err(|e| FsError::from_io(e, path.display()))?;
+    decode(&bytes).map_err(|e| FsError::InvalidOperation(format!("{}: {e}", path.display())))
+}
+
+/// Every record of the trace in `bytes`, in file order.
+pub fn decode(bytes: &[u8]) -> Result<Vec<TraceRecord>> {
+    decode_prefix(bytes).map(|(records, _)| records)
+}
+
+/// The records, and how many bytes of `bytes` they span; a torn record at
+/// the end is left out of both.
+fn decode_prefix(bytes: &[u8]) -> Result<(Vec<TraceRecord>, usize)> {
+    if !is_trace(bytes) {
+        return Err(FsError::InvalidOperation("not an rhss access trace".into()));
+    }
+    let mut r = Reader {
+        bytes,
+        pos: MAGIC.len(),
+    };
+    let mut out = Vec::new();
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut last_ms = 0u64;
+    let mut in_segment = false;
+    let mut valid = r.pos;
+    while r.pos < bytes.len() {
+        let at = r.pos;
+        let corrupt = |what: &str| FsError::InvalidOperation(format!("{what} at byte {at}"));
+        let tag = bytes[at];
+        r.pos += 1;
+        if tag == 0 {
+            let Some(start) = r.take(8) else { break };
+            last_ms = u64::from_le_bytes(start.try_into().expect("8 bytes"));
+            paths.clear();
+            in_segment = true;
+            valid = r.pos;
+            continue;
+        }
+        if !in_segment {
+            return Err(corrupt("record before any segment"));
+        }
+        if tag & !(0x07 | 0x18 | NEW_PATH) != 0 {
+            return Err(corrupt("bad tag"));
+        }
+        let op = TraceOp::from_code(tag & 0x07).ok_or_else(|| corrupt("bad op"))?;
+        let tier = match (tag >> 3) & 0x03 {
+            0 => None,
+            1 => Some(TierId::Fast),
+            2 => Some(TierId::Slow),
+            _ => Some(TierId::Archive),
+        };
+        let (Some(delta), Some(size)) = (r.varint(), r.varint()) else {
+            break;
+        };
+        let path = if tag & NEW_PATH != 0 {
+            let Some(bytes) = r.varint().and_then(|n| r.take(n as usize)) else {
+                break;
+            };
+            let path = PathBuf::from(std::ffi::OsString::from_vec(bytes.to_vec()));
+            paths.push(path.clone());
+            path
+        } else {
+            let Some(id) = r.varint() else { break };
+            paths
+                .get(id as usize)
+                .cloned()
+                .ok_or_else(|| corrupt("unknown path number"))?
+        };
+        last_ms += delta;
+        out.push(TraceRecord {
+            ts_ms: last_ms,
+            op,
+            path,
+            size,
+            tier,
+        });
+        valid = r.pos;
+    }
+    Ok((out, valid))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|e| *e <= self.bytes.len())?;
+        let out = &self.bytes[self.pos..end];
+        self.pos = end;
+        Some(out)
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self.bytes.get(self.pos)?;
+            self.pos += 1;
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Some(v);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip_and_runs_fold() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t/access.trace");
+        let trace = AccessTrace::open(&path, 64).unwrap();
+        trace.record(TraceOp::Create, "/a".into(), 0, Some(TierId::Fast));
+        for chunk in 1..=8 {
+            trace.record(
+                TraceOp::Write,
+                "/a".into(),
+                chunk * 4096,
+                Some(TierId::Fast),
+            );
+        }
+        trace.record(TraceOp::Read, "/b".into(), 100, Some(TierId::Slow));
+        trace.record(TraceOp::Read, "/a".into(), 4096, None);
+        trace.record(TraceOp::Delete, "/a".into(), 32768, Some(TierId::Fast));
+        drop(trace);
+
+        let got = read(&path).unwrap();
+        let summary: Vec<_> = got
+            .iter()
+            .map(|r| (r.op, r.path.to_str().unwrap(), r.size, r.tier))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (TraceOp::Create, "/a", 0, Some(TierId::Fast)),
+                (TraceOp::Write, "/a", 32768, Some(TierId::Fast)),
+                (TraceOp::Read, "/b", 100, Some(TierId::Slow)),
+                (TraceOp::Read, "/a", 4096, None),
+                (TraceOp::Delete, "/a", 32768, Some(TierId::Fast)),
+            ]
+        );
+        assert!(got.windows(2).all(|w| w[0].ts_ms <= w[1].ts_ms));
+
+        // A crash mid-record: reopening cuts it off and appends a segment.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.extend_from_slice(&[NEW_PATH | 1, 0, 5, 40]);
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(read(&path).unwrap().len(), 5);
+        let trace = AccessTrace::open(&path, 64).unwrap();
+        trace.record(TraceOp::Read, "/a".into(), 1, None);
+        drop(trace);
+        let got = read(&path).unwrap();
+        assert_eq!(got.len(), 6);
+        assert_eq!(got[5].path, PathBuf::from("/a"));
+
+        assert!(decode(b"not a trace").is_err());
+    }
+}
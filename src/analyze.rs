@@ -0,0 +1,410 @@
+//! Workload shape for configuring a mount: `rhss analyze`.
+//!
+//! From the index: how file sizes are distributed, how much is hot (read
+//! within `hot_window`) or cold and on which tier, and how many bytes
+//! dedup already saves. From an access trace (`crate::access::trace`),
+//! when there is one: which directories churn most, and which files on
+//! the slow tier are read often enough to be worth promoting. Compression
+//! savings are estimated by compressing a sample of the files that aren't
+//! compressed yet (`compression_ratio`).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::access::trace::{TraceOp, TraceRecord};
+use crate::backend::Backend;
+use crate::index::{FileRow, TierId};
+
+/// Upper bounds of the size buckets; the last bucket is everything above.
+const SIZE_BUCKETS: [u64; 6] = [4 << 10, 64 << 10, 1 << 20, 16 << 20, 256 << 20, 4 << 30];
+
+const TIERS: [TierId; 3] = [TierId::Fast, TierId::Slow, TierId::Archive];
+
+/// Bytes of each sampled file that are compressed.
+const SAMPLE_BYTES: u32 = 1 << 20;
+
+/// zstd level of the estimate; the tierer compresses harder, so the
+/// estimate errs low.
+const SAMPLE_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct AnalyzeOptions {
+    /// Files read (or written) this recently count as hot.
+    pub hot_window: Duration,
+    /// Rows of the churn and promotion lists.
+    pub top: usize,
+}
+
+impl Default for AnalyzeOptions {
+    fn default() -> Self {
+        Self {
+            hot_window: Duration::from_secs(7 * 86_400),
+            top: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct WorkloadReport {
+    pub files: u64,
+    pub bytes: u64,
+    pub sizes: Vec<SizeBucket>,
+    pub tiers: Vec<TierShape>,
+    pub dedup: DedupSavings,
+    /// Filled in by the caller from `compression_ratio`.
+    pub compression: Option<CompressionEstimate>,
+    /// `None` without a trace.
+    pub trace: Option<TraceSummary>,
+}
+
+/// Files no larger than `max` (and larger than the previous bucket's).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SizeBucket {
+    /// `None` for the last bucket.
+    pub max: Option<u64>,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// What is on a tier, split by temperature.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TierShape {
+    pub tier: &'static str,
+    pub hot_files: u64,
+    pub hot_bytes: u64,
+    pub cold_files: u64,
+    pub cold_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DedupSavings {
+    /// Files whose content is shared with another file.
+    pub shared_files: u64,
+    /// Bytes stored once instead of for every copy.
+    pub saved_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CompressionEstimate {
+    pub compressed_files: u64,
+    /// Files not yet compressed, and their bytes.
+    pub candidate_files: u64,
+    pub candidate_bytes: u64,
+    pub sampled_files: u64,
+    /// Compressed over raw size of the sample.
+    pub ratio: f64,
+    pub estimated_savings: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TraceSummary {
+    pub records: u64,
+    pub span_secs: u64,
+    pub reads: u64,
+    /// Reads served by the fast tier, of those with a known tier.
+    pub fast_read_ratio: f64,
+    pub writes: u64,
+    pub creates: u64,
+    pub deletes: u64,
+    /// Most creates, writes and deletes first.
+    pub churn: Vec<DirChurn>,
+    /// Files read from the slow or archive tier, most reads first.
+    pub promotion_candidates: Vec<Candidate>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DirChurn {
+    pub dir: PathBuf,
+    /// Creates, writes and deletes.
+    pub ops: u64,
+    /// Reached by writes and creates.
+    pub bytes_written: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub reads: u64,
+    pub size: u64,
+}
+
+/// Shape of `rows` as of `now`, and of `trace` if it has anything.
+pub fn analyze(
+    rows: &[FileRow],
+    trace: &[TraceRecord],
+    now: SystemTime,
+    opts: &AnalyzeOptions,
+) -> WorkloadReport {
+    let mut report = WorkloadReport {
+        sizes: SIZE_BUCKETS
+            .iter()
+            .map(|max| Some(*max))
+            .chain([None])
+            .map(|max| SizeBucket {
+                max,
+                ..Default::default()
+            })
+            .collect(),
+        tiers: TIERS
+            .into_iter()
+            .map(|t| TierShape {
+                tier: t.as_str(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let mut hashes: HashMap<&str, (u64, u64)> = HashMap::new();
+    for row in rows {
+        let size = row.location.size;
+        report.files += 1;
+        report.bytes += size;
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|max| size <= *max)
+            .unwrap_or(SIZE_BUCKETS.len());
+        report.sizes[bucket].files += 1;
+        report.sizes[bucket].bytes += size;
+
+        let t = TIERS.iter().position(|t| *t == row.location.tier);
+        let shape = &mut report.tiers[t.expect("every tier listed")];
+        let idle = now.duration_since(row.last_access).unwrap_or_default();
+        if idle <= opts.hot_window {
+            shape.hot_files += 1;
+            shape.hot_bytes += size;
+        } else {
+            shape.cold_files += 1;
+            shape.cold_bytes += size;
+        }
+        if let Some(hash) = &row.content_hash {
+            let e = hashes.entry(hash).or_default();
+            e.0 += 1;
+            e.1 = size;
+        }
+    }
+    for (copies, size) in hashes.into_values().filter(|(n, _)| *n > 1) {
+        report.dedup.shared_files += copies;
+        report.dedup.saved_bytes += (copies - 1) * size;
+    }
+    if !trace.is_empty() {
+        report.trace = Some(summarize(trace, opts.top));
+    }
+    report
+}
+
+fn summarize(trace: &[TraceRecord], top: usize) -> TraceSummary {
+    let mut s = TraceSummary {
+        records: trace.len() as u64,
+        ..Default::default()
+    };
+    let first = trace.iter().map(|r| r.ts_ms).min().unwrap_or(0);
+    let last = trace.iter().map(|r| r.ts_ms).max().unwrap_or(0);
+    s.span_secs = (last - first) / 1000;
+
+    let (mut tiered_reads, mut fast_reads) = (0u64, 0u64);
+    let mut churn: HashMap<&Path, DirChurn> = HashMap::new();
+    let mut cold_reads: HashMap<&Path, Candidate> = HashMap::new();
+    for r in trace {
+        match r.op {
+            TraceOp::Read => {
+                s.reads += 1;
+                if let Some(tier) = r.tier {
+                    tiered_reads += 1;
+                    if tier == TierId::Fast {
+                        fast_reads += 1;
+                    } else {
+                        let c = cold_reads.entry(&r.path).or_default();
+                        c.reads += 1;
+                        c.size = c.size.max(r.size);
+                    }
+                }
+                continue;
+            }
+            TraceOp::Write => s.writes += 1,
+            TraceOp::Create => s.creates += 1,
+            TraceOp::Delete => {
+                s.deletes += 1;
+                // Deleted files aren't worth promoting.
+                cold_reads.remove(r.path.as_path());
+            }
+        }
+        let dir = r.path.parent().unwrap_or(Path::new("/"));
+        let c = churn.entry(dir).or_default();
+        c.ops += 1;
+        if r.op != TraceOp::Delete {
+            c.bytes_written += r.size;
+        }
+    }
+    if tiered_reads > 0 {
+        s.fast_read_ratio = fast_reads as f64 / tiered_reads as f64;
+    }
+
+    let mut churn: Vec<DirChurn> = churn
+        .into_iter()
+        .map(|(dir, c)| DirChurn {
+            dir: dir.to_path_buf(),
+            ..c
+        })
+        .collect();
+    churn.sort_by(|a, b| b.ops.cmp(&a.ops).then_with(|| a.dir.cmp(&b.dir)));
+    churn.truncate(top);
+    s.churn = churn;
+
+    // Only files read more than once: a single read promotes nothing.
+    let mut candidates: Vec<Candidate> = cold_reads
+        .into_iter()
+        .filter(|(_, c)| c.reads > 1)
+        .map(|(path, c)| Candidate {
+            path: path.to_path_buf(),
+            ..c
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.reads.cmp(&a.reads).then_with(|| a.path.cmp(&b.path)));
+    candidates.truncate(top);
+    s.promotion_candidates = candidates;
+    s
+}
+
+/// Compressed over raw size of up to `SAMPLE_BYTES` of each file in
+/// `sample`; `None` if nothing could be read.
+pub fn compression_ratio(sample: &[(Arc<dyn Backend>, PathBuf)]) -> Option<(u64, f64)> {
+    let (mut files, mut raw, mut packed) = (0u64, 0u64, 0u64);
+    for (backend, path) in sample {
+        let Ok(data) = backend.read_at(path, 0, SAMPLE_BYTES) else {
+            continue;
+        };
+        if data.is_empty() {
+            continue;
+        }
+        let Ok(out) = zstd::bulk::compress(&data, SAMPLE_LEVEL) else {
+            continue;
+        };
+        files += 1;
+        raw += data.len() as u64;
+        packed += (out.len() as u64).min(data.len() as u64);
+    }
+    (raw > 0).then(|| (files, packed as f64 / raw as f64))
+}
+
+/// Up to `n` uncompressed files of `rows`, spread evenly over them, so a
+/// sample isn't just the first directory.
+pub fn sample_rows(rows: &[FileRow], n: usize) -> Vec<&FileRow> {
+    let candidates: Vec<&FileRow> = rows
+        .iter()
+        .filter(|r| !r.compressed && r.location.size > 0)
+        .collect();
+    if n == 0 || candidates.is_empty() {
+        return Vec::new();
+    }
+    let step = candidates.len().div_ceil(n);
+    candidates.into_iter().step_by(step).collect()
+}
+
+/// `CompressionEstimate` for `rows`, given a sampled `ratio`.
+pub fn compression_estimate(rows: &[FileRow], sampled: u64, ratio: f64) -> CompressionEstimate {
+    let mut est = CompressionEstimate {
+        sampled_files: sampled,
+        ratio,
+        ..Default::default()
+    };
+    for r in rows {
+        if r.compressed {
+            est.compressed_files += 1;
+        } else {
+            est.candidate_files += 1;
+            est.candidate_bytes += r.location.size;
+        }
+    }
+    est.estimated_savings = (est.candidate_bytes as f64 * (1.0 - ratio).max(0.0)) as u64;
+    est
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{FileState, Location, Mutability};
+
+    fn row(path: &str, tier: TierId, size: u64, idle_days: u64, hash: Option<&str>) -> FileRow {
+        FileRow {
+            logical_path: path.into(),
+            location: Location {
+                tier,
+                backend_id: tier.as_str().into(),
+                backend_path: path.trim_start_matches('/').into(),
+                size,
+            },
+            replicas: Vec::new(),
+            last_access: SystemTime::UNIX_EPOCH
+                + Duration::from_secs(100 * 86_400 - idle_days * 86_400),
+            hit_count: 0,
+            popularity: 0.0,
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            mutability: Mutability::Unknown,
+            compressed: false,
+            content_hash: hash.map(Into::into),
+        }
+    }
+
+    fn rec(secs: u64, op: TraceOp, path: &str, size: u64, tier: Option<TierId>) -> TraceRecord {
+        TraceRecord {
+            ts_ms: secs * 1000,
+            op,
+            path: path.into(),
+            size,
+            tier,
+        }
+    }
+
+    #[test]
+    fn reports_sizes_temperature_churn_and_candidates() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100 * 86_400);
+        let rows = [
+            row("/a/small", TierId::Fast, 100, 1, None),
+            row("/a/copy1", TierId::Slow, 2 << 20, 30, Some("h")),
+            row("/a/copy2", TierId::Slow, 2 << 20, 2, Some("h")),
+            row("/b/huge", TierId::Slow, 8 << 30, 90, None),
+        ];
+        let trace = [
+            rec(0, TraceOp::Create, "/tmp/x", 0, Some(TierId::Fast)),
+            rec(1, TraceOp::Write, "/tmp/x", 4096, Some(TierId::Fast)),
+            rec(2, TraceOp::Delete, "/tmp/x", 4096, Some(TierId::Fast)),
+            rec(3, TraceOp::Read, "/a/copy1", 2 << 20, Some(TierId::Slow)),
+            rec(4, TraceOp::Read, "/a/copy1", 2 << 20, Some(TierId::Slow)),
+            rec(5, TraceOp::Read, "/a/small", 100, Some(TierId::Fast)),
+            rec(9, TraceOp::Read, "/b/huge", 1 << 20, Some(TierId::Slow)),
+        ];
+        let r = analyze(&rows, &trace, now, &AnalyzeOptions::default());
+        assert_eq!((r.files, r.bytes), (4, 100 + (4 << 20) + (8 << 30)));
+        let files: Vec<u64> = r.sizes.iter().map(|b| b.files).collect();
+        assert_eq!(files, [1, 0, 0, 2, 0, 0, 1]);
+        assert_eq!((r.tiers[0].hot_files, r.tiers[0].cold_files), (1, 0));
+        assert_eq!((r.tiers[1].hot_files, r.tiers[1].cold_files), (1, 2));
+        assert_eq!(
+            r.dedup,
+            DedupSavings {
+                shared_files: 2,
+                saved_bytes: 2 << 20
+            }
+        );
+
+        let t = r.trace.unwrap();
+        assert_eq!((t.records, t.span_secs, t.reads, t.writes), (7, 9, 4, 1));
+        assert_eq!(t.fast_read_ratio, 0.25);
+        assert_eq!(t.churn[0].dir, PathBuf::from("/tmp"));
+        assert_eq!((t.churn[0].ops, t.churn[0].bytes_written), (3, 4096));
+        // Read twice from slow; the huge file only once.
+        assert_eq!(t.promotion_candidates.len(), 1);
+        assert_eq!(t.promotion_candidates[0].path, PathBuf::from("/a/copy1"));
+
+        let est = compression_estimate(&rows, 1, 0.25);
+        assert_eq!(est.candidate_files, 4);
+        assert_eq!(est.estimated_savings, est.candidate_bytes * 3 / 4);
+        assert_eq!(sample_rows(&rows, 2).len(), 2);
+    }
+}
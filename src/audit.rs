@@ -0,0 +1,421 @@
+//! Audit trail of mutating FUSE operations (`[audit] enabled = true`).
+//!
+//! Every `create`, `mkdir`, `unlink`, `rmdir`, `rename`, `setattr`,
+//! `write`, `copy_file_range` and `fallocate` produces one `AuditRecord`:
+//! op, logical path(s), the caller's uid/gid, outcome and latency. Records
+//! go to an append-only JSONL file or to syslog, one JSON object per line.
+//!
+//! FUSE workers never wait on the sink: records are handed to a writer
+//! thread through a bounded queue. When the queue is full the record is
+//! dropped and counted, and the writer logs an `audit-dropped` record with
+//! the count once it catches up, so gaps are visible in the trail itself.
+//!
+//! The daemon records a few events of its own through `AuditLog::event`,
+//! such as `force-lock` when `--force` takes over the storage lock.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::error::{FsError, Result};
+
+/// Records queued before new ones are dropped.
+pub const DEFAULT_BUFFER: usize = 8192;
+
+/// `<db.parent>/.rhss/audit.jsonl`.
+pub fn audit_path_for(db: &Path) -> PathBuf {
+    db.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .join(".rhss")
+        .join("audit.jsonl")
+}
+
+/// Where records end up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditSink {
+    /// Appended as JSONL.
+    File(PathBuf),
+    /// `LOG_INFO` on the `LOG_AUTHPRIV` facility, ident `rhss`.
+    Syslog,
+}
+
+/// One audited operation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch, taken when the op finished.
+    pub ts_ms: u64,
+    pub op: String,
+    pub path: PathBuf,
+    /// Rename destination.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<PathBuf>,
+    pub uid: u32,
+    pub gid: u32,
+    pub ok: bool,
+    /// errno sent to the kernel on failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno: Option<i32>,
+    /// On failures reported with `AuditSpan::fail`: the error, and the
+    /// tier and backend it came from when known (`crate::error::ErrorContext`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    pub latency_us: u64,
+    /// On `audit-dropped` records only: how many records were lost.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dropped: Option<u64>,
+    /// On daemon events (`AuditLog::event`): what happened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+enum Out {
+    File(BufWriter<File>),
+    Syslog,
+}
+
+impl Out {
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        match self {
+            Out::File(w) => {
+                w.write_all(line.as_bytes())?;
+                w.write_all(b"\n")
+            }
+            Out::Syslog => {
+                let msg = std::ffi::CString::new(line)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                unsafe { libc::syslog(libc::LOG_INFO, c"%s".as_ptr(), msg.as_ptr()) };
+                Ok(())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Out::File(w) => w.flush(),
+            Out::Syslog => Ok(()),
+        }
+    }
+}
+
+/// Handle to the writer thread. Dropping it drains the queue and flushes.
+pub struct AuditLog {
+    tx: Option<SyncSender<AuditRecord>>,
+    dropped: Arc<AtomicU64>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AuditLog {
+    /// Open `sink` and start the writer. `buffer` is clamped to at least 1.
+    pub fn open(sink: AuditSink, buffer: usize) -> Result<Arc<Self>> {
+        let out = match &sink {
+            AuditSink::File(path) => {
+                if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(parent)
+                        .map_err(|e| FsError::from_io(e, parent.display()))?;
+                }
+                let f = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| FsError::from_io(e, path.display()))?;
+                Out::File(BufWriter::new(f))
+            }
+            AuditSink::Syslog => {
+                unsafe { libc::openlog(c"rhss".as_ptr(), libc::LOG_PID, libc::LOG_AUTHPRIV) };
+                Out::Syslog
+            }
+        };
+        let (tx, rx) = mpsc::sync_channel(buffer.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let worker = std::thread::Builder::new()
+            .name("rhss-audit".into())
+            .spawn({
+                let dropped = Arc::clone(&dropped);
+                move || drain(rx, out, &dropped)
+            })
+            .map_err(|e| FsError::Storage(format!("audit: spawn writer: {e}")))?;
+        Ok(Arc::new(Self {
+            tx: Some(tx),
+            dropped,
+            worker: Some(worker),
+        }))
+    }
+
+    /// Start timing `op` on `path` for the caller `uid`/`gid`.
+    pub fn begin(
+        self: &Arc<Self>,
+        op: &'static str,
+        path: PathBuf,
+        uid: u32,
+        gid: u32,
+    ) -> AuditSpan {
+        AuditSpan {
+            log: Arc::clone(self),
+            op,
+            path,
+            to: None,
+            uid,
+            gid,
+            started: Instant::now(),
+        }
+    }
+
+    /// Queue `rec` without blocking; counts it as dropped if the queue is full.
+    pub fn record(&self, rec: AuditRecord) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        match tx.try_send(rec) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Record a daemon event rather than a FUSE op, as the daemon's own
+    /// uid/gid.
+    pub fn event(&self, op: &str, path: PathBuf, note: String) {
+        self.record(AuditRecord {
+            ts_ms: now_ms(),
+            op: op.to_string(),
+            path,
+            to: None,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            ok: true,
+            errno: None,
+            error: None,
+            tier: None,
+            backend: None,
+            latency_us: 0,
+            dropped: None,
+            note: Some(note),
+        });
+    }
+
+    /// Records lost to a full queue since the last `audit-dropped` line.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        // Closing the channel lets the writer drain what's queued and exit.
+        self.tx.take();
+        if let Some(w) = self.worker.take() {
+            let _ = w.join();
+        }
+    }
+}
+
+fn drain(rx: Receiver<AuditRecord>, mut out: Out, dropped: &AtomicU64) {
+    let mut failing = false;
+    let mut emit = |out: &mut Out, rec: &AuditRecord| {
+        let res = serde_json::to_string(rec)
+            .map_err(std::io::Error::other)
+            .and_then(|line| out.write(&line));
+        match res {
+            Ok(()) => failing = false,
+            Err(e) if !failing => {
+                warn!("audit: write failed: {e}");
+                failing = true;
+            }
+            Err(_) => {}
+        }
+    };
+    while let Ok(first) = rx.recv() {
+        emit(&mut out, &first);
+        // Batch whatever queued up meanwhile, then flush once.
+        while let Ok(rec) = rx.try_recv() {
+            emit(&mut out, &rec);
+        }
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            emit(
+                &mut out,
+                &AuditRecord {
+                    ts_ms: now_ms(),
+                    op: "audit-dropped".into(),
+                    path: PathBuf::new(),
+                    to: None,
+                    uid: 0,
+                    gid: 0,
+                    ok: false,
+                    errno: None,
+                    error: None,
+                    tier: None,
+                    backend: None,
+                    latency_us: 0,
+                    dropped: Some(lost),
+                    note: None,
+                },
+            );
+        }
+        if let Err(e) = out.flush() {
+            warn!("audit: flush: {e}");
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// An operation in progress. `finish` records it.
+#[must_use]
+pub struct AuditSpan {
+    log: Arc<AuditLog>,
+    op: &'static str,
+    path: PathBuf,
+    to: Option<PathBuf>,
+    uid: u32,
+    gid: u32,
+    started: Instant,
+}
+
+impl AuditSpan {
+    pub fn with_target(mut self, to: PathBuf) -> Self {
+        self.to = Some(to);
+        self
+    }
+
+    /// `errno = None` for success.
+    pub fn finish(self, errno: Option<i32>) {
+        self.record(errno, None);
+    }
+
+    /// A failure with the error itself, so the record says which tier
+    /// and backend it came from.
+    pub fn fail(self, err: &FsError) {
+        self.record(Some(err.to_errno()), Some(err));
+    }
+
+    fn record(self, errno: Option<i32>, err: Option<&FsError>) {
+        let context = err.and_then(FsError::context);
+        self.log.record(AuditRecord {
+            ts_ms: now_ms(),
+            op: self.op.to_string(),
+            path: self.path,
+            to: self.to,
+            uid: self.uid,
+            gid: self.gid,
+            ok: errno.is_none(),
+            errno,
+            error: err.map(|e| e.to_string()),
+            tier: context.and_then(|c| c.tier).map(|t| t.as_str().to_string()),
+            backend: context.and_then(|c| c.backend.clone()),
+            latency_us: self.started.elapsed().as_micros() as u64,
+            dropped: None,
+            note: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &Path) -> Vec<AuditRecord> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn records_land_in_jsonl() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit/trail.jsonl");
+        let log = AuditLog::open(AuditSink::File(path.clone()), 16).unwrap();
+        log.begin("unlink", "/a".into(), 1000, 100).finish(None);
+        log.begin("rename", "/b".into(), 0, 0)
+            .with_target("/c".into())
+            .finish(Some(libc::EXDEV));
+        log.event("force-lock", "/data/.rhss.lock".into(), "took over".into());
+        drop(log);
+
+        let recs = read(&path);
+        assert_eq!(recs.len(), 3);
+        assert_eq!(recs[0].op, "unlink");
+        assert_eq!((recs[0].uid, recs[0].gid, recs[0].ok), (1000, 100, true));
+        assert_eq!(recs[1].to.as_deref(), Some(Path::new("/c")));
+        assert_eq!(recs[1].errno, Some(libc::EXDEV));
+        assert_eq!(recs[2].note.as_deref(), Some("took over"));
+        assert!(recs[1].note.is_none());
+    }
+
+    #[test]
+    fn failures_carry_their_context() {
+        use crate::error::ErrorContext;
+        use crate::index::TierId;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trail.jsonl");
+        let log = AuditLog::open(AuditSink::File(path.clone()), 16).unwrap();
+        let err = FsError::NoSpace("docs/a.txt".into()).with_context(
+            ErrorContext::new("write")
+                .tier(TierId::Fast)
+                .backend("ssd")
+                .path("docs/a.txt"),
+        );
+        log.begin("write", "/docs/a.txt".into(), 0, 0).fail(&err);
+        drop(log);
+
+        let rec = &read(&path)[0];
+        assert_eq!(rec.errno, Some(libc::ENOSPC));
+        assert_eq!(rec.tier.as_deref(), Some("fast"));
+        assert_eq!(rec.backend.as_deref(), Some("ssd"));
+        assert!(rec
+            .error
+            .as_deref()
+            .unwrap()
+            .starts_with("write docs/a.txt on fast/ssd"));
+    }
+
+    #[test]
+    fn full_queue_drops_and_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trail.jsonl");
+        let log = AuditLog::open(AuditSink::File(path.clone()), 1).unwrap();
+        for _ in 0..10_000 {
+            log.begin("write", "/f".into(), 1, 1).finish(None);
+        }
+        drop(log);
+
+        let recs = read(&path);
+        let writes = recs.iter().filter(|r| r.op == "write").count() as u64;
+        let lost: u64 = recs
+            .iter()
+            .filter(|r| r.op == "audit-dropped")
+            .filter_map(|r| r.dropped)
+            .sum();
+        assert_eq!(writes + lost, 10_000);
+    }
+}
@@ -0,0 +1,429 @@
+//! `BreakerBackend` — retries and a circuit breaker for cold backends.
+//!
+//! The slow and archive tiers are often a NAS or an object store, and
+//! those go away: a switch reboots, an endpoint returns 503 for a minute.
+//! The decorator retries idempotent calls that failed in a way worth
+//! repeating (`FsError::is_retryable`) with exponential backoff. After
+//! `trip_after` outages (`FsError::is_transient`) in a row the
+//! circuit opens: every call fails at once with `EIO` instead of waiting
+//! out another timeout, so `ls` and `cat` on cold files return promptly
+//! while files on the fast tier, which is never wrapped, keep working. A
+//! background thread probes the backend with `statvfs` and closes the
+//! circuit when it answers.
+//!
+//! Errors the backend *answered* with (`ENOENT`, `EACCES`, `ENOSPC`, ...)
+//! are not outages; they reset the failure count and pass straight
+//! through. Non-idempotent calls (create, remove, rename, copy) are not
+//! retried, since a retry after a lost reply would fail on its own first
+//! attempt's effect.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use tracing::{info, warn};
+
+use super::{Backend, BackendStats, FileMetadata};
+use crate::error::{FsError, Result};
+
+/// Retry and trip settings, `[breaker]` in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerConfig {
+    /// Extra attempts for an idempotent call that failed like an outage.
+    pub retries: u32,
+    /// Wait before the first retry; doubled for each one after.
+    pub backoff: Duration,
+    /// Outage failures in a row that open the circuit. 0 = never open.
+    pub trip_after: u32,
+    /// How often an open circuit probes the backend.
+    pub probe_every: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(200),
+            trip_after: 5,
+            probe_every: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Circuit {
+    open: AtomicBool,
+    failures: AtomicU32,
+}
+
+pub struct BreakerBackend {
+    inner: Arc<dyn Backend>,
+    cfg: BreakerConfig,
+    circuit: Arc<Circuit>,
+}
+
+impl BreakerBackend {
+    pub fn new(inner: Arc<dyn Backend>, cfg: BreakerConfig) -> Arc<Self> {
+        Arc::new(Self {
+            inner,
+            cfg,
+            circuit: Arc::default(),
+        })
+    }
+
+    /// Run `f` against the inner backend, retrying retryable failures
+    /// (`FsError::is_retryable`) when `retry`. Only transient ones count
+    /// toward opening the circuit.
+    fn call<T, F>(&self, op: &str, path: &Path, retry: bool, f: F) -> Result<T>
+    where
+        F: Fn(&dyn Backend) -> Result<T>,
+    {
+        if self.circuit.open.load(Ordering::Acquire) {
+            return Err(FsError::Offline(format!(
+                "{op} {}: backend {} is unavailable",
+                path.display(),
+                self.inner.id()
+            )));
+        }
+        let mut delay = self.cfg.backoff;
+        let mut attempt = 0;
+        loop {
+            match f(self.inner.as_ref()) {
+                Err(e) if retry && attempt < self.cfg.retries && e.is_retryable() => {
+                    attempt += 1;
+                    thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+                Err(e) if e.is_transient() => {
+                    self.failed(&e);
+                    return Err(e);
+                }
+                r => {
+                    self.circuit.failures.store(0, Ordering::Relaxed);
+                    return r;
+                }
+            }
+        }
+    }
+
+    fn failed(&self, e: &FsError) {
+        let n = self.circuit.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.cfg.trip_after == 0 || n < self.cfg.trip_after {
+            return;
+        }
+        if self.circuit.open.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        warn!(
+            "backend {} unavailable after {n} failures ({e}); failing fast until it recovers",
+            self.inner.id()
+        );
+        let inner = Arc::clone(&self.inner);
+        let circuit = Arc::downgrade(&self.circuit);
+        let every = self.cfg.probe_every;
+        let spawned = thread::Builder::new()
+            .name("rhss-breaker-probe".into())
+            .spawn(move || probe(inner, circuit, every));
+        if let Err(e) = spawned {
+            warn!("spawn breaker probe: {e}; closing the circuit");
+            self.circuit.open.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Poll `statvfs` until the backend answers, then close the circuit.
+/// Exits early when the breaker is dropped.
+fn probe(inner: Arc<dyn Backend>, circuit: Weak<Circuit>, every: Duration) {
+    loop {
+        thread::sleep(every);
+        let Some(circuit) = circuit.upgrade() else {
+            return;
+        };
+        if inner.statvfs().is_ok() {
+            circuit.failures.store(0, Ordering::Relaxed);
+            circuit.open.store(false, Ordering::Release);
+            info!("backend {} recovered", inner.id());
+            return;
+        }
+    }
+}
+
+impl Backend for BreakerBackend {
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    fn root(&self) -> &Path {
+        self.inner.root()
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        self.call("read", path, true, |b| b.read_at(path, offset, size))
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        self.call("write", path, true, |b| b.write_at(path, offset, data))
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        self.call("truncate", path, true, |b| b.truncate(path, size))
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        self.call("fsync", path, true, |b| b.fsync(path))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        self.call("stat", path, true, |b| b.metadata(path))
+    }
+
+    fn allocate(&self, path: &Path, offset: u64, len: u64, mode: i32) -> Result<()> {
+        self.call("fallocate", path, false, |b| {
+            b.allocate(path, offset, len, mode)
+        })
+    }
+
+    fn next_data(&self, path: &Path, offset: u64) -> Result<Option<u64>> {
+        self.call("seek_data", path, true, |b| b.next_data(path, offset))
+    }
+
+    fn next_hole(&self, path: &Path, offset: u64) -> Result<u64> {
+        self.call("seek_hole", path, true, |b| b.next_hole(path, offset))
+    }
+
+    fn copy_range(
+        &self,
+        src: &Path,
+        src_off: u64,
+        dst: &Path,
+        dst_off: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.call("copy_file_range", src, true, |b| {
+            b.copy_range(src, src_off, dst, dst_off, len)
+        })
+    }
+
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.call("copy", src, false, |b| b.copy(src, dst))
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        self.call("access", path, true, |b| {
+            b.check_access(path, uid, gid, mask)
+        })
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        self.call("exists", path, true, |b| b.exists(path))
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        self.call("readdir", path, true, |b| b.list_dir(path))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.call("mkdir", path, false, |b| b.create_dir(path))
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        self.call("create", path, false, |b| b.create_file(path))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.call("remove", path, false, |b| b.remove(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.call("rename", from, false, |b| b.rename(from, to))
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.call("chmod", path, true, |b| b.set_permissions(path, mode))
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        self.call("chown", path, true, |b| b.set_owner(path, uid, gid))
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        self.call("utimes", path, true, |b| b.set_times(path, atime, mtime))
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        self.call("statvfs", Path::new("/"), true, |b| b.statvfs())
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.inner.resolve(path)
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        self.inner.walk_files()
+    }
+
+    fn sync_replicas(&self, path: &Path) -> Result<()> {
+        self.call("resync", path, true, |b| b.sync_replicas(path))
+    }
+
+    fn resume_copy(&self, path: &Path, src: &FileMetadata) -> Result<u64> {
+        self.call("resume_copy", path, true, |b| b.resume_copy(path, src))
+    }
+
+    fn direct_io_above(&self) -> Option<u64> {
+        self.inner.direct_io_above()
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.inner.cost_per_gb_month()
+    }
+
+    fn available(&self) -> bool {
+        !self.circuit.open.load(Ordering::Acquire) && self.inner.available()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use std::io;
+
+    /// Memory backend whose reads and `statvfs` fail with `EIO` while
+    /// `down`, counting the reads that reach it.
+    struct Flaky {
+        inner: MemoryBackend,
+        down: AtomicBool,
+        reads: AtomicU32,
+    }
+
+    impl Flaky {
+        fn eio<T>(&self) -> Result<T> {
+            Err(FsError::Io(io::Error::from_raw_os_error(libc::EIO)))
+        }
+    }
+
+    impl Backend for Flaky {
+        fn id(&self) -> &str {
+            self.inner.id()
+        }
+        fn root(&self) -> &Path {
+            self.inner.root()
+        }
+        fn read_at(&self, p: &Path, o: u64, s: u32) -> Result<Vec<u8>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                return self.eio();
+            }
+            self.inner.read_at(p, o, s)
+        }
+        fn write_at(&self, p: &Path, o: u64, d: &[u8]) -> Result<u32> {
+            self.inner.write_at(p, o, d)
+        }
+        fn truncate(&self, p: &Path, s: u64) -> Result<()> {
+            self.inner.truncate(p, s)
+        }
+        fn fsync(&self, p: &Path) -> Result<()> {
+            self.inner.fsync(p)
+        }
+        fn metadata(&self, p: &Path) -> Result<FileMetadata> {
+            self.inner.metadata(p)
+        }
+        fn exists(&self, p: &Path) -> Result<bool> {
+            self.inner.exists(p)
+        }
+        fn list_dir(&self, p: &Path) -> Result<Vec<String>> {
+            self.inner.list_dir(p)
+        }
+        fn create_dir(&self, p: &Path) -> Result<()> {
+            self.inner.create_dir(p)
+        }
+        fn create_file(&self, p: &Path) -> Result<()> {
+            self.inner.create_file(p)
+        }
+        fn remove(&self, p: &Path) -> Result<()> {
+            self.inner.remove(p)
+        }
+        fn rename(&self, f: &Path, t: &Path) -> Result<()> {
+            self.inner.rename(f, t)
+        }
+        fn set_permissions(&self, p: &Path, m: u32) -> Result<()> {
+            self.inner.set_permissions(p, m)
+        }
+        fn set_times(&self, p: &Path, a: Option<SystemTime>, m: Option<SystemTime>) -> Result<()> {
+            self.inner.set_times(p, a, m)
+        }
+        fn statvfs(&self) -> Result<BackendStats> {
+            if self.down.load(Ordering::SeqCst) {
+                return self.eio();
+            }
+            self.inner.statvfs()
+        }
+        fn resolve(&self, p: &Path) -> PathBuf {
+            self.inner.resolve(p)
+        }
+    }
+
+    fn setup(cfg: BreakerConfig) -> (Arc<Flaky>, Arc<BreakerBackend>) {
+        let flaky = Arc::new(Flaky {
+            inner: MemoryBackend::new("nas", 1 << 20),
+            down: AtomicBool::new(false),
+            reads: AtomicU32::new(0),
+        });
+        flaky.create_file(Path::new("f")).unwrap();
+        flaky.write_at(Path::new("f"), 0, b"cold").unwrap();
+        let b = BreakerBackend::new(Arc::clone(&flaky) as Arc<dyn Backend>, cfg);
+        (flaky, b)
+    }
+
+    #[test]
+    fn outages_are_retried_and_answers_are_not() {
+        let (flaky, b) = setup(BreakerConfig {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        });
+        flaky.down.store(true, Ordering::SeqCst);
+        assert!(b.read_at(Path::new("f"), 0, 4).is_err());
+        assert_eq!(flaky.reads.load(Ordering::SeqCst), 3);
+        // ENOENT is the backend answering: no retry, no failure counted.
+        assert!(matches!(
+            b.metadata(Path::new("nope")),
+            Err(FsError::NotFound(_))
+        ));
+        assert_eq!(b.circuit.failures.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn open_circuit_fails_fast_until_a_probe_succeeds() {
+        let (flaky, b) = setup(BreakerConfig {
+            retries: 0,
+            backoff: Duration::from_millis(1),
+            trip_after: 2,
+            probe_every: Duration::from_millis(20),
+        });
+        flaky.down.store(true, Ordering::SeqCst);
+        for _ in 0..2 {
+            assert!(b.read_at(Path::new("f"), 0, 4).is_err());
+        }
+        assert!(!b.available());
+        // Refused without reaching the backend, as EIO.
+        let err = b.read_at(Path::new("f"), 0, 4).unwrap_err();
+        assert_eq!(err.to_errno(), libc::EIO);
+        assert_eq!(flaky.reads.load(Ordering::SeqCst), 2);
+
+        flaky.down.store(false, Ordering::SeqCst);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !b.available() && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(b.read_at(Path::new("f"), 0, 4).unwrap(), b"cold");
+    }
+}
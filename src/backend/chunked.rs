@@ -0,0 +1,684 @@
+//! `ChunkedBackend` — store very large files on a wrapped backend as
+//! fixed-size chunks (`chunk_large_files = "1G"` on a tier backend).
+//!
+//! A file that grows past the limit is moved into
+//! `.rhss_chunks/<id>/<n>` pieces of `CHUNK_SIZE` bytes, and its own path
+//! keeps a small fixed-width manifest (chunk id, chunk size, file size).
+//! Reads touch only the chunks covering the range, so a partial read of a
+//! 100 GB file on an object store fetches a few chunk objects instead of
+//! the whole thing; writes go to the chunks they cover and `fsync` flushes
+//! just the dirty ones. Renames move only the manifest. A chunk that was
+//! never written reads as zeros.
+//!
+//! Migrations resume chunk by chunk: `resume_copy` records the source
+//! being copied in the manifest, every chunk a sequential copy completes
+//! is synced and counted, and a later attempt at the same source (same
+//! size and mtime) continues after the last counted chunk.
+//!
+//! Files that reach the wrapped backend directly (the `copy_file_range`
+//! fast path writes to `resolve()`) are chunked on their next `fsync`;
+//! `resolve()` of a file already chunked is unopenable, so nothing copies
+//! its manifest by mistake.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use tracing::{debug, warn};
+
+use super::{Backend, BackendStats, FileMetadata};
+use crate::error::{FsError, Result};
+
+/// Managed dir on the wrapped backend; hidden from listings and walks.
+const CHUNK_DIR: &str = ".rhss_chunks";
+pub const CHUNK_SIZE: u64 = 64 << 20;
+/// Bytes moved per call when chunking an existing file.
+const COPY_PIECE: u64 = 8 << 20;
+const MAGIC: &str = "rhss-chunked 1";
+
+/// What a chunked file's path holds. Encoded at a fixed width so updates
+/// are a single in-place write.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Manifest {
+    id: String,
+    chunk_size: u64,
+    size: u64,
+    /// Leading chunks a copy has completed and synced.
+    synced: u64,
+    /// Size and mtime (secs, nanos) of the source being copied in.
+    origin: Option<(u64, u64, u32)>,
+}
+
+impl Manifest {
+    fn encode(&self) -> Vec<u8> {
+        let (osize, osecs, onanos) = self.origin.unwrap_or_default();
+        format!(
+            "{MAGIC} {:>24} {:020} {:020} {:020} {:020} {:020} {:010}\n",
+            self.id, self.chunk_size, self.size, self.synced, osize, osecs, onanos
+        )
+        .into_bytes()
+    }
+
+    fn decode(b: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(b).ok()?.strip_prefix(MAGIC)?;
+        let f: Vec<&str> = text.split_whitespace().collect();
+        let [id, cs, size, synced, osize, osecs, onanos] = f[..] else {
+            return None;
+        };
+        let osize: u64 = osize.parse().ok()?;
+        Some(Self {
+            id: id.to_string(),
+            chunk_size: cs.parse().ok().filter(|&c| c > 0)?,
+            size: size.parse().ok()?,
+            synced: synced.parse().ok()?,
+            origin: match osize {
+                0 => None,
+                _ => Some((osize, osecs.parse().ok()?, onanos.parse().ok()?)),
+            },
+        })
+    }
+
+    fn len() -> u64 {
+        Self::default().encode().len() as u64
+    }
+
+    fn chunk(&self, n: u64) -> PathBuf {
+        Path::new(CHUNK_DIR).join(&self.id).join(format!("{n:08}"))
+    }
+}
+
+fn origin_of(src: &FileMetadata) -> (u64, u64, u32) {
+    let d = src.mtime.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (src.size, d.as_secs(), d.subsec_nanos())
+}
+
+/// A chunked file seen since startup.
+struct Open {
+    m: Manifest,
+    /// Chunks written since the last `fsync`.
+    dirty: BTreeSet<u64>,
+    /// `m` differs from the manifest on disk.
+    changed: bool,
+}
+
+/// `path` as a cache key: relative, without `.` components.
+fn key(path: &Path) -> Result<PathBuf> {
+    Ok(super::sanitize_rel(path)?.components().collect())
+}
+
+pub struct ChunkedBackend {
+    inner: Arc<dyn Backend>,
+    limit: u64,
+    chunk_size: u64,
+    files: Mutex<HashMap<PathBuf, Open>>,
+    /// Sources announced by `resume_copy` for files not chunked yet.
+    origins: Mutex<HashMap<PathBuf, (u64, u64, u32)>>,
+    /// Serializes turning plain files into chunked ones.
+    converting: Mutex<()>,
+    seq: AtomicU64,
+}
+
+impl ChunkedBackend {
+    /// Chunk files on `inner` once they grow past `limit` bytes.
+    pub fn new(inner: Arc<dyn Backend>, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            chunk_size: CHUNK_SIZE,
+            files: Mutex::new(HashMap::new()),
+            origins: Mutex::new(HashMap::new()),
+            converting: Mutex::new(()),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    /// The manifest of `k`, or `None` for a plain file or a directory.
+    fn manifest(&self, k: &Path) -> Result<Option<Manifest>> {
+        if let Some(o) = self.files.lock().get(k) {
+            return Ok(Some(o.m.clone()));
+        }
+        let meta = self.inner.metadata(k)?;
+        if meta.is_dir || meta.size != Manifest::len() {
+            return Ok(None);
+        }
+        let Some(m) = Manifest::decode(&self.inner.read_at(k, 0, meta.size as u32)?) else {
+            return Ok(None);
+        };
+        self.files.lock().entry(k.to_path_buf()).or_insert(Open {
+            m: m.clone(),
+            dirty: BTreeSet::new(),
+            changed: false,
+        });
+        Ok(Some(m))
+    }
+
+    fn save_manifest(&self, k: &Path, m: &Manifest) -> Result<()> {
+        self.inner.write_at(k, 0, &m.encode())?;
+        self.inner.fsync(k)
+    }
+
+    /// Move plain file `k` (or nothing, if it doesn't exist) into chunks.
+    fn convert(&self, k: &Path) -> Result<Manifest> {
+        let _guard = self.converting.lock();
+        if let Some(m) = self.manifest(k).ok().flatten() {
+            return Ok(m);
+        }
+        let plain = match self.inner.metadata(k) {
+            Ok(m) => Some(m),
+            Err(FsError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut m = Manifest {
+            id: format!(
+                "{nanos:016x}{:08x}",
+                self.seq.fetch_add(1, Ordering::Relaxed)
+            ),
+            chunk_size: self.chunk_size,
+            size: plain.as_ref().map_or(0, |p| p.size),
+            synced: 0,
+            origin: self.origins.lock().remove(k),
+        };
+        let dir = Path::new(CHUNK_DIR).join(&m.id);
+        self.inner.create_dir(&dir)?;
+        let cs = m.chunk_size;
+        let mut off = 0;
+        while off < m.size {
+            let want = COPY_PIECE.min(cs - off % cs).min(m.size - off);
+            let data = self.inner.read_at(k, off, want as u32)?;
+            if data.is_empty() {
+                m.size = off;
+                break;
+            }
+            let n = off / cs;
+            self.inner.write_at(&m.chunk(n), off % cs, &data)?;
+            off += data.len() as u64;
+            if off.is_multiple_of(cs) || off >= m.size {
+                self.inner.fsync(&m.chunk(n))?;
+            }
+        }
+        if m.origin.is_some() {
+            // Everything so far came from the copy and is now synced.
+            m.synced = m.size / cs;
+        }
+        let tmp = dir.join("manifest");
+        self.inner.create_file(&tmp)?;
+        self.save_manifest(&tmp, &m)?;
+        self.inner.rename(&tmp, k)?;
+        if let Some(p) = plain {
+            self.inner.set_permissions(k, p.mode & 0o7777)?;
+            self.inner.set_times(k, Some(p.atime), Some(p.mtime))?;
+            let _ = self.inner.set_owner(k, p.uid, p.gid);
+        }
+        debug!(
+            "{}: chunked {} ({} bytes) as {}",
+            self.inner.id(),
+            k.display(),
+            m.size,
+            m.id
+        );
+        self.files.lock().insert(
+            k.to_path_buf(),
+            Open {
+                m: m.clone(),
+                dirty: BTreeSet::new(),
+                changed: false,
+            },
+        );
+        Ok(m)
+    }
+
+    fn remove_chunks(&self, m: &Manifest) {
+        let dir = Path::new(CHUNK_DIR).join(&m.id);
+        for name in self.inner.list_dir(&dir).unwrap_or_default() {
+            let _ = self.inner.remove(&dir.join(name));
+        }
+        if let Err(e) = self.inner.remove(&dir) {
+            warn!("{}: remove {}: {e}", self.inner.id(), dir.display());
+        }
+    }
+
+    /// Write `data` at `off` across the chunks of `k`.
+    fn write_chunks(&self, k: &Path, m: &Manifest, off: u64, data: &[u8]) -> Result<u32> {
+        let cs = m.chunk_size;
+        let mut done = 0usize;
+        let mut touched = BTreeSet::new();
+        while done < data.len() {
+            let at = off + done as u64;
+            let n = at / cs;
+            let room = (cs - at % cs) as usize;
+            let part = &data[done..(done + room).min(data.len())];
+            let w = self.inner.write_at(&m.chunk(n), at % cs, part)? as usize;
+            touched.insert(n);
+            done += w;
+            if w < part.len() {
+                break;
+            }
+        }
+        let end = off + done as u64;
+        let first = off / cs;
+        // A copy in progress: chunks it just finished become resumable.
+        let finished = if m.origin.is_some() && first <= m.synced {
+            end / cs
+        } else {
+            0
+        };
+        for n in m.synced..finished {
+            self.inner.fsync(&m.chunk(n))?;
+        }
+        let mut files = self.files.lock();
+        let Some(o) = files.get_mut(k) else {
+            return Ok(done as u32);
+        };
+        o.m.size = o.m.size.max(end);
+        o.dirty.extend(touched.iter().filter(|&&n| n >= finished));
+        o.changed = true;
+        if first < o.m.synced {
+            // Rewritten under a completed chunk; it no longer matches the
+            // source.
+            o.m.synced = first;
+        }
+        if finished > o.m.synced {
+            o.m.synced = finished;
+            let m = o.m.clone();
+            drop(files);
+            self.save_manifest(k, &m)?;
+            if let Some(o) = self.files.lock().get_mut(k) {
+                o.changed = o.m != m;
+            }
+        }
+        Ok(done as u32)
+    }
+}
+
+impl Backend for ChunkedBackend {
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    fn root(&self) -> &Path {
+        self.inner.root()
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        // A chunked file's own path holds just the manifest; a kernel-side
+        // copy of it would duplicate the manifest, not the data. Hand out a
+        // path every syscall rejects so such copies stream instead.
+        match key(path).map(|k| self.manifest(&k)) {
+            Ok(Ok(Some(_))) => self.inner.root().join("\0"),
+            _ => self.inner.resolve(path),
+        }
+    }
+
+    fn direct_io_above(&self) -> Option<u64> {
+        self.inner.direct_io_above()
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.inner.cost_per_gb_month()
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        Box::new(
+            self.inner
+                .walk_files()
+                .filter(|r| !matches!(r, Ok(p) if p.starts_with(CHUNK_DIR))),
+        )
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        let k = key(path)?;
+        let Some(m) = self.manifest(&k)? else {
+            return self.inner.read_at(&k, offset, size);
+        };
+        let end = m.size.min(offset + size as u64);
+        let mut out = Vec::with_capacity(end.saturating_sub(offset) as usize);
+        let mut at = offset;
+        while at < end {
+            let n = at / m.chunk_size;
+            let want = (m.chunk_size - at % m.chunk_size).min(end - at);
+            let mut data = match self
+                .inner
+                .read_at(&m.chunk(n), at % m.chunk_size, want as u32)
+            {
+                Ok(d) => d,
+                Err(FsError::NotFound(_)) => Vec::new(),
+                Err(e) => return Err(e),
+            };
+            // Holes and short chunks read as zeros.
+            data.resize(want as usize, 0);
+            out.extend_from_slice(&data);
+            at += want;
+        }
+        Ok(out)
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        let k = key(path)?;
+        let m = match self.manifest(&k) {
+            Ok(Some(m)) => m,
+            Ok(None) | Err(FsError::NotFound(_)) => {
+                if offset + data.len() as u64 <= self.limit {
+                    return self.inner.write_at(&k, offset, data);
+                }
+                self.convert(&k)?
+            }
+            Err(e) => return Err(e),
+        };
+        self.write_chunks(&k, &m, offset, data)
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        let k = key(path)?;
+        let m = match self.manifest(&k)? {
+            Some(m) => m,
+            None if size <= self.limit => return self.inner.truncate(&k, size),
+            None => self.convert(&k)?,
+        };
+        let cs = m.chunk_size;
+        if size < m.size {
+            let keep = size.div_ceil(cs);
+            for n in keep..m.size.div_ceil(cs) {
+                match self.inner.remove(&m.chunk(n)) {
+                    Ok(()) | Err(FsError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if !size.is_multiple_of(cs) {
+                let last = m.chunk(size / cs);
+                if self.inner.exists(&last)? {
+                    self.inner.truncate(&last, size % cs)?;
+                }
+            }
+        }
+        let mut m = m;
+        m.size = size;
+        m.synced = m.synced.min(size / cs);
+        self.save_manifest(&k, &m)?;
+        if let Some(o) = self.files.lock().get_mut(&k) {
+            o.m.size = size;
+            o.m.synced = m.synced;
+            o.changed = false;
+        }
+        Ok(())
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        let k = key(path)?;
+        let Some(m) = self.manifest(&k)? else {
+            self.inner.fsync(&k)?;
+            if self.inner.metadata(&k)?.size > self.limit {
+                self.convert(&k)?;
+            }
+            return Ok(());
+        };
+        let (dirty, changed) = match self.files.lock().get_mut(&k) {
+            Some(o) => (std::mem::take(&mut o.dirty), std::mem::take(&mut o.changed)),
+            None => return Ok(()),
+        };
+        for n in dirty {
+            match self.inner.fsync(&m.chunk(n)) {
+                Ok(()) | Err(FsError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if changed {
+            self.save_manifest(&k, &m)?;
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let k = key(path)?;
+        let mut meta = self.inner.metadata(&k)?;
+        if let Some(m) = self.manifest(&k)? {
+            meta.size = m.size;
+        }
+        Ok(meta)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        self.inner.exists(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        let mut names = self.inner.list_dir(path)?;
+        if key(path)?.as_os_str().is_empty() {
+            names.retain(|n| n != CHUNK_DIR);
+        }
+        Ok(names)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        self.inner.create_file(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let k = key(path)?;
+        let m = self.manifest(&k)?;
+        self.inner.remove(&k)?;
+        self.files.lock().remove(&k);
+        self.origins.lock().remove(&k);
+        if let Some(m) = m {
+            self.remove_chunks(&m);
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let (from, to) = (key(from)?, key(to)?);
+        let replaced = self.manifest(&to).ok().flatten();
+        self.inner.rename(&from, &to)?;
+        let mut files = self.files.lock();
+        files.remove(&to);
+        let moved: Vec<PathBuf> = files
+            .keys()
+            .filter(|p| p.starts_with(&from))
+            .cloned()
+            .collect();
+        for p in moved {
+            if let Some(o) = files.remove(&p) {
+                files.insert(to.join(p.strip_prefix(&from).unwrap_or(&p)), o);
+            }
+        }
+        drop(files);
+        if let Some(m) = replaced {
+            self.remove_chunks(&m);
+        }
+        Ok(())
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.inner.set_permissions(path, mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        self.inner.set_times(path, atime, mtime)
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        self.inner.set_owner(path, uid, gid)
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        self.inner.check_access(path, uid, gid, mask)
+    }
+
+    fn sync_replicas(&self, path: &Path) -> Result<()> {
+        self.inner.sync_replicas(path)
+    }
+
+    fn resume_copy(&self, path: &Path, src: &FileMetadata) -> Result<u64> {
+        let k = key(path)?;
+        let origin = origin_of(src);
+        let m = match self.manifest(&k) {
+            Ok(Some(m)) => m,
+            Ok(None) | Err(FsError::NotFound(_)) => {
+                self.origins.lock().insert(k, origin);
+                return Ok(0);
+            }
+            Err(e) => return Err(e),
+        };
+        if m.origin == Some(origin) {
+            let done = (m.synced * m.chunk_size).min(m.size);
+            if done > 0 {
+                debug!(
+                    "{}: resuming copy into {} at {done}",
+                    self.inner.id(),
+                    k.display()
+                );
+            }
+            return Ok(done);
+        }
+        // Leftovers from some other source: start from an empty file.
+        self.truncate(&k, 0)?;
+        let mut m = m;
+        m.size = 0;
+        m.origin = Some(origin);
+        m.synced = 0;
+        self.save_manifest(&k, &m)?;
+        if let Some(o) = self.files.lock().get_mut(&k) {
+            o.m.origin = m.origin;
+            o.m.synced = 0;
+        }
+        Ok(0)
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        self.inner.statvfs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn setup(dir: &Path) -> ChunkedBackend {
+        let inner: Arc<dyn Backend> = Arc::new(PosixBackend::new("hdd", dir).unwrap());
+        let mut b = ChunkedBackend::new(inner, 10_000);
+        b.chunk_size = 4096;
+        b
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn large_files_are_chunked() {
+        let dir = TempDir::new().unwrap();
+        let b = setup(dir.path());
+        let p = Path::new("vm.img");
+        let data = pattern(30_000);
+        b.write_at(p, 0, &data[..8000]).unwrap();
+        assert!(b.manifest(p).unwrap().is_none());
+        // Crossing the limit moves the file into chunks.
+        b.write_at(p, 8000, &data[8000..]).unwrap();
+        b.fsync(p).unwrap();
+        let m = b.manifest(p).unwrap().unwrap();
+        assert_eq!(
+            std::fs::metadata(dir.path().join(p)).unwrap().len(),
+            Manifest::len()
+        );
+        assert_eq!(b.metadata(p).unwrap().size, 30_000);
+        assert_eq!(b.read_at(p, 4000, 5000).unwrap(), &data[4000..9000]);
+        assert_eq!(b.read_at(p, 29_990, 99).unwrap(), &data[29_990..]);
+        assert_eq!(b.list_dir(Path::new("")).unwrap(), ["vm.img"]);
+
+        // Holes read as zeros; shrinking drops the chunks past the end.
+        b.truncate(p, 50_000).unwrap();
+        assert_eq!(b.read_at(p, 40_000, 4).unwrap(), [0, 0, 0, 0]);
+        b.truncate(p, 5000).unwrap();
+        assert_eq!(b.read_at(p, 0, 99_999).unwrap(), &data[..5000]);
+        let chunks = dir.path().join(CHUNK_DIR).join(&m.id);
+        assert_eq!(std::fs::read_dir(&chunks).unwrap().count(), 2);
+
+        b.rename(p, Path::new("moved.img")).unwrap();
+        assert_eq!(
+            b.read_at(Path::new("moved.img"), 0, 10).unwrap(),
+            &data[..10]
+        );
+        b.remove(Path::new("moved.img")).unwrap();
+        assert!(!chunks.exists());
+    }
+
+    #[test]
+    fn manifest_survives_restart() {
+        let dir = TempDir::new().unwrap();
+        let data = pattern(20_000);
+        {
+            let b = setup(dir.path());
+            b.write_at(Path::new("big"), 0, &data).unwrap();
+            b.fsync(Path::new("big")).unwrap();
+        }
+        let b = setup(dir.path());
+        assert_eq!(b.read_at(Path::new("big"), 0, 20_000).unwrap(), data);
+        let files: Vec<PathBuf> = b.walk_files().map(Result::unwrap).collect();
+        assert_eq!(files, [PathBuf::from("big")]);
+    }
+
+    #[test]
+    fn plain_files_written_directly_are_chunked_on_fsync() {
+        let dir = TempDir::new().unwrap();
+        let b = setup(dir.path());
+        let data = pattern(12_345);
+        std::fs::write(b.resolve(Path::new("fast")), &data).unwrap();
+        b.fsync(Path::new("fast")).unwrap();
+        assert!(b.manifest(Path::new("fast")).unwrap().is_some());
+        assert!(std::fs::File::open(b.resolve(Path::new("fast"))).is_err());
+        assert_eq!(b.read_at(Path::new("fast"), 0, 20_000).unwrap(), data);
+    }
+
+    #[test]
+    fn interrupted_copies_resume() {
+        let dir = TempDir::new().unwrap();
+        let src = FileMetadata {
+            size: 20_000,
+            is_dir: false,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH + Duration::from_secs(1234),
+            ctime: UNIX_EPOCH,
+        };
+        let data = pattern(20_000);
+        let p = Path::new("copy");
+        {
+            let b = setup(dir.path());
+            assert_eq!(b.resume_copy(p, &src).unwrap(), 0);
+            // The copy dies after 13 000 bytes, without an fsync.
+            for off in (0..13_000).step_by(1000) {
+                b.write_at(p, off as u64, &data[off..off + 1000]).unwrap();
+            }
+        }
+        let b = setup(dir.path());
+        // Three whole chunks were finished and synced along the way.
+        assert_eq!(b.resume_copy(p, &src).unwrap(), 3 * 4096);
+        // A different source starts over.
+        let other = FileMetadata {
+            size: 1,
+            ..src.clone()
+        };
+        assert_eq!(b.resume_copy(p, &other).unwrap(), 0);
+        assert_eq!(b.resume_copy(p, &src).unwrap(), 0);
+    }
+}
@@ -0,0 +1,720 @@
+//! Block-level deduplicating backend (`dedup = "fixed"` or `"cdc"` on a
+//! tier backend), for cold disks full of near-identical VM images and
+//! build artifacts.
+//!
+//! Files are cut into chunks, either fixed 1 MiB blocks or content-defined
+//! (gear-hash CDC, ~1 MiB average) so an insertion early in a file only
+//! changes the chunks around it. Each chunk is stored once under
+//! `<root>/.rhss_dedup/chunks/` by its SHA-256; the file itself becomes a
+//! small recipe at its usual path listing the chunks in order. Reads pick
+//! the chunks covering the range straight off disk.
+//!
+//! Writes follow the `S3Backend` model: the file is reassembled into
+//! `<root>/.rhss_dedup/staging/` on the first write, and `fsync` chunks
+//! the staged copy, swaps in the new recipe and drops the copy. Chunk
+//! refcounts live in memory and are rebuilt from the recipes on startup,
+//! which also sweeps chunks a crash left unreferenced.
+//!
+//! Files already under the root when dedup is switched on are served as
+//! they are and get deduplicated the next time they're rewritten.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::error::{FsError, Result};
+
+use super::{Backend, BackendStats, FileMetadata, PosixBackend};
+
+/// How files are cut into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode {
+    /// Fixed-size blocks; cheap, but an insertion shifts every later block.
+    Fixed,
+    /// Content-defined boundaries that survive insertions and deletions.
+    Cdc,
+}
+
+/// Managed dir under the root; hidden from listings and walks.
+const STORE_DIR: &str = ".rhss_dedup";
+/// Fixed chunk size, and the average CDC chunk size.
+const DEFAULT_CHUNK: usize = 1 << 20;
+/// First line of every recipe.
+const MAGIC: &[u8] = b"rhss-dedup 1\n";
+
+/// Gear table for CDC: 256 pseudo-random words (splitmix64). Fixed so the
+/// same content always cuts the same way.
+const GEAR: [u64; 256] = {
+    let mut t = [0u64; 256];
+    let mut s: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        s = s.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = s;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        t[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    t
+};
+
+/// One chunk of a recipe.
+#[derive(Debug, Clone)]
+struct ChunkRef {
+    hash: String,
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Debug, Default)]
+struct Recipe {
+    size: u64,
+    chunks: Vec<ChunkRef>,
+}
+
+impl Recipe {
+    fn parse(text: &[u8]) -> Option<Self> {
+        let body = std::str::from_utf8(text.strip_prefix(MAGIC)?).ok()?;
+        let mut r = Recipe::default();
+        for line in body.lines() {
+            let (hash, len) = line.split_once(' ')?;
+            let len: u64 = len.parse().ok()?;
+            r.chunks.push(ChunkRef {
+                hash: hash.to_string(),
+                offset: r.size,
+                len,
+            });
+            r.size += len;
+        }
+        Some(r)
+    }
+}
+
+/// Splits a stream into chunks.
+struct Chunker<R> {
+    r: R,
+    mode: DedupMode,
+    avg: usize,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    fn max(&self) -> usize {
+        match self.mode {
+            DedupMode::Fixed => self.avg,
+            DedupMode::Cdc => self.avg * 4,
+        }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let max = self.max();
+        let mut filled = self.buf.len();
+        if !self.eof && filled < max {
+            self.buf.resize(max, 0);
+            while filled < max {
+                let n = self.r.read(&mut self.buf[filled..])?;
+                if n == 0 {
+                    self.eof = true;
+                    break;
+                }
+                filled += n;
+            }
+            self.buf.truncate(filled);
+        }
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let cut = match self.mode {
+            DedupMode::Fixed => self.buf.len().min(max),
+            DedupMode::Cdc => cdc_cut(&self.buf, self.avg),
+        };
+        let rest = self.buf.split_off(cut);
+        Ok(Some(std::mem::replace(&mut self.buf, rest)))
+    }
+}
+
+/// Length of the first chunk of `data`: the first position past
+/// `avg / 4` where the gear hash's low bits are all zero, capped at
+/// `avg * 4`.
+fn cdc_cut(data: &[u8], avg: usize) -> usize {
+    let (min, max) = (avg / 4, avg * 4);
+    let end = data.len().min(max);
+    if end <= min {
+        return end;
+    }
+    let mask = avg.next_power_of_two() as u64 - 1;
+    let mut h = 0u64;
+    for (i, &b) in data.iter().enumerate().take(end).skip(min) {
+        h = (h << 1).wrapping_add(GEAR[b as usize]);
+        if h & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Size and mtime of a recipe file when it was parsed, and what it held
+/// (`None` for a plain, not yet deduplicated file).
+type CachedRecipe = ((u64, SystemTime), Option<Arc<Recipe>>);
+
+pub struct DedupBackend {
+    disk: PosixBackend,
+    staging: PosixBackend,
+    chunks: PathBuf,
+    mode: DedupMode,
+    /// Fixed chunk size / average CDC chunk size.
+    chunk_size: usize,
+    /// Chunk hash → number of recipe entries using it.
+    refs: Mutex<HashMap<String, u64>>,
+    /// Parsed recipes, valid while the file's size and mtime match.
+    recipes: Mutex<HashMap<PathBuf, CachedRecipe>>,
+}
+
+impl DedupBackend {
+    /// Serve `root` (which must exist) with chunks stored under it. Reads
+    /// every recipe to count chunk references.
+    pub fn new(
+        id: impl Into<String>,
+        root: impl Into<PathBuf>,
+        mode: DedupMode,
+        cost_per_gb_month: Option<f64>,
+    ) -> Result<Self> {
+        let id = id.into();
+        let disk = PosixBackend::with_cost(id.clone(), root, cost_per_gb_month)?;
+        let store = disk.root().join(STORE_DIR);
+        let chunks = store.join("chunks");
+        fs::create_dir_all(&chunks).map_err(FsError::Io)?;
+        fs::create_dir_all(store.join("staging")).map_err(FsError::Io)?;
+        let staging = PosixBackend::new(format!("{id}-staging"), store.join("staging"))?;
+        let b = Self {
+            disk,
+            staging,
+            chunks,
+            mode,
+            chunk_size: DEFAULT_CHUNK,
+            refs: Mutex::new(HashMap::new()),
+            recipes: Mutex::new(HashMap::new()),
+        };
+        b.count_refs()?;
+        Ok(b)
+    }
+
+    fn count_refs(&self) -> Result<()> {
+        let mut refs = HashMap::new();
+        for rel in self.walk_files() {
+            if let Some(r) = self.recipe(&rel?)? {
+                for c in &r.chunks {
+                    *refs.entry(c.hash.clone()).or_insert(0u64) += 1;
+                }
+            }
+        }
+        let mut swept = 0;
+        for dir in fs::read_dir(&self.chunks).map_err(FsError::Io)? {
+            for f in fs::read_dir(dir.map_err(FsError::Io)?.path()).map_err(FsError::Io)? {
+                let f = f.map_err(FsError::Io)?;
+                let name = f.file_name();
+                if !refs.contains_key(name.to_str().unwrap_or_default()) {
+                    let _ = fs::remove_file(f.path());
+                    swept += 1;
+                }
+            }
+        }
+        if swept > 0 {
+            info!(
+                "dedup {}: removed {swept} unreferenced chunks",
+                self.disk.id()
+            );
+        }
+        *self.refs.lock() = refs;
+        Ok(())
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.chunks.join(&hash[..2.min(hash.len())]).join(hash)
+    }
+
+    /// The recipe at `path`, `None` for a plain file or a directory.
+    fn recipe(&self, path: &Path) -> Result<Option<Arc<Recipe>>> {
+        let full = self.disk.resolve(path);
+        let m = fs::metadata(&full).map_err(|e| FsError::from_io(e, path.display()))?;
+        if m.is_dir() {
+            return Ok(None);
+        }
+        let stamp = (m.len(), m.modified().map_err(FsError::Io)?);
+        if let Some((s, r)) = self.recipes.lock().get(path) {
+            if *s == stamp {
+                return Ok(r.clone());
+            }
+        }
+        let mut f = File::open(&full).map_err(FsError::Io)?;
+        let mut head = vec![0u8; MAGIC.len()];
+        let is_recipe = f.read_exact(&mut head).is_ok() && head == MAGIC;
+        let r = if is_recipe {
+            let mut text = head;
+            f.read_to_end(&mut text).map_err(FsError::Io)?;
+            let r = Recipe::parse(&text).ok_or_else(|| {
+                FsError::Storage(format!("dedup: corrupt recipe {}", path.display()))
+            })?;
+            Some(Arc::new(r))
+        } else {
+            None
+        };
+        self.recipes
+            .lock()
+            .insert(path.to_path_buf(), (stamp, r.clone()));
+        Ok(r)
+    }
+
+    fn forget(&self, path: &Path) {
+        self.recipes.lock().retain(|k, _| !k.starts_with(path));
+    }
+
+    /// Store `data` as a chunk (unless it's there) and take a reference.
+    fn put_chunk(&self, data: &[u8]) -> Result<String> {
+        let hash = format!("{:x}", Sha256::digest(data));
+        let mut refs = self.refs.lock();
+        let n = refs.entry(hash.clone()).or_insert(0);
+        let dst = self.chunk_path(&hash);
+        if *n == 0 && !dst.exists() {
+            fs::create_dir_all(dst.parent().unwrap_or(&self.chunks)).map_err(FsError::Io)?;
+            let tmp = dst.with_extension("part");
+            let mut f = File::create(&tmp).map_err(FsError::Io)?;
+            f.write_all(data).map_err(FsError::Io)?;
+            f.sync_all().map_err(FsError::Io)?;
+            fs::rename(&tmp, &dst).map_err(FsError::Io)?;
+        }
+        *n += 1;
+        Ok(hash)
+    }
+
+    /// Drop one reference to each chunk, deleting chunks nobody uses.
+    fn release<'a>(&self, hashes: impl IntoIterator<Item = &'a str>) {
+        let mut refs = self.refs.lock();
+        for h in hashes {
+            let Some(n) = refs.get_mut(h) else {
+                continue;
+            };
+            *n -= 1;
+            if *n == 0 {
+                refs.remove(h);
+                if let Err(e) = fs::remove_file(self.chunk_path(h)) {
+                    warn!("dedup {}: remove chunk {h}: {e}", self.disk.id());
+                }
+            }
+        }
+    }
+
+    /// Chunk the staged copy of `path` and make it the file's recipe.
+    fn ingest(&self, path: &Path) -> Result<()> {
+        let staged = self.staging.resolve(path);
+        let mtime = fs::metadata(&staged)
+            .and_then(|m| m.modified())
+            .map_err(FsError::Io)?;
+        let mut chunker = Chunker {
+            r: File::open(&staged).map_err(FsError::Io)?,
+            mode: self.mode,
+            avg: self.chunk_size,
+            buf: Vec::new(),
+            eof: false,
+        };
+        let mut hashes = Vec::new();
+        let mut text = MAGIC.to_vec();
+        let stored = (|| -> Result<()> {
+            while let Some(c) = chunker.next_chunk().map_err(FsError::Io)? {
+                let h = self.put_chunk(&c)?;
+                writeln!(text, "{h} {}", c.len()).map_err(FsError::Io)?;
+                hashes.push(h);
+            }
+            Ok(())
+        })();
+        if let Err(e) = stored {
+            self.release(hashes.iter().map(String::as_str));
+            return Err(e);
+        }
+        let old = self.recipe(path).ok().flatten();
+        let written = (|| -> Result<()> {
+            let full = self.disk.resolve(path);
+            if let Some(parent) = full.parent() {
+                fs::create_dir_all(parent).map_err(FsError::Io)?;
+            }
+            let mut f = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(full)
+                .map_err(FsError::Io)?;
+            f.write_all(&text).map_err(FsError::Io)?;
+            f.sync_all().map_err(FsError::Io)
+        })();
+        if let Err(e) = written {
+            self.release(hashes.iter().map(String::as_str));
+            return Err(e);
+        }
+        self.disk.set_times(path, None, Some(mtime))?;
+        self.forget(path);
+        if let Some(old) = old {
+            self.release(old.chunks.iter().map(|c| c.hash.as_str()));
+        }
+        debug!(
+            "dedup {}: {} stored as {} chunks",
+            self.disk.id(),
+            path.display(),
+            hashes.len()
+        );
+        self.staging.remove(path)
+    }
+
+    /// Reassemble `path` into staging for writing, unless it's there.
+    /// A path that doesn't exist yet is created empty.
+    fn ensure_staged(&self, path: &Path) -> Result<()> {
+        if self.staging.exists(path)? {
+            return Ok(());
+        }
+        if !self.disk.exists(path)? {
+            self.disk.create_file(path)?;
+        }
+        self.staging.create_file(path)?;
+        let mut out = OpenOptions::new()
+            .write(true)
+            .open(self.staging.resolve(path))
+            .map_err(FsError::Io)?;
+        match self.recipe(path)? {
+            Some(r) => {
+                for c in &r.chunks {
+                    let data = fs::read(self.chunk_path(&c.hash)).map_err(FsError::Io)?;
+                    out.write_all(&data).map_err(FsError::Io)?;
+                }
+            }
+            None => {
+                let mut src = File::open(self.disk.resolve(path)).map_err(FsError::Io)?;
+                io::copy(&mut src, &mut out).map_err(FsError::Io)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Backend for DedupBackend {
+    fn id(&self) -> &str {
+        self.disk.id()
+    }
+
+    fn root(&self) -> &Path {
+        self.disk.root()
+    }
+
+    /// The staged copy: direct opens (the migration fast path) write there
+    /// and the following `fsync` ingests it.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.staging.resolve(path)
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.disk.cost_per_gb_month()
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        let root = self.disk.root();
+        let store = root.join(STORE_DIR);
+        Box::new(
+            walkdir::WalkDir::new(root)
+                .follow_links(false)
+                .into_iter()
+                .filter_entry(move |e| e.path() != store)
+                .filter_map(move |entry| match entry {
+                    Err(e) => Some(Err(FsError::Storage(format!("walk: {e}")))),
+                    Ok(e) if e.file_type().is_file() => e
+                        .path()
+                        .strip_prefix(root)
+                        .ok()
+                        .map(|r| Ok(r.to_path_buf())),
+                    Ok(_) => None,
+                }),
+        )
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        if self.staging.exists(path)? {
+            return self.staging.read_at(path, offset, size);
+        }
+        let Some(r) = self.recipe(path)? else {
+            return self.disk.read_at(path, offset, size);
+        };
+        let end = r.size.min(offset + size as u64);
+        let mut out = Vec::with_capacity(end.saturating_sub(offset) as usize);
+        let first = r.chunks.partition_point(|c| c.offset + c.len <= offset);
+        for c in r.chunks[first..].iter().take_while(|c| c.offset < end) {
+            let from = offset.max(c.offset);
+            let to = end.min(c.offset + c.len);
+            let mut buf = vec![0u8; (to - from) as usize];
+            let f = File::open(self.chunk_path(&c.hash)).map_err(FsError::Io)?;
+            f.read_exact_at(&mut buf, from - c.offset)
+                .map_err(FsError::Io)?;
+            out.extend_from_slice(&buf);
+        }
+        Ok(out)
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        self.ensure_staged(path)?;
+        self.staging.write_at(path, offset, data)
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        self.ensure_staged(path)?;
+        self.staging.truncate(path, size)
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        if !self.staging.exists(path)? {
+            return Ok(());
+        }
+        self.ingest(path)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let mut m = self.disk.metadata(path)?;
+        if m.is_dir {
+            return Ok(m);
+        }
+        if self.staging.exists(path)? {
+            let s = self.staging.metadata(path)?;
+            m.size = s.size;
+            m.mtime = s.mtime;
+        } else if let Some(r) = self.recipe(path)? {
+            m.size = r.size;
+        }
+        Ok(m)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        self.disk.exists(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        let mut names = self.disk.list_dir(path)?;
+        if super::sanitize_rel(path)?.as_os_str().is_empty() {
+            names.retain(|n| n != STORE_DIR);
+        }
+        Ok(names)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.disk.create_dir(path)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        // An empty plain file; the first write stages it.
+        self.disk.create_file(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let recipe = self.recipe(path)?;
+        self.disk.remove(path)?;
+        if self.staging.exists(path)? {
+            let _ = self.staging.remove(path);
+        }
+        self.forget(path);
+        if let Some(r) = recipe {
+            self.release(r.chunks.iter().map(|c| c.hash.as_str()));
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        // A file renamed over gives up its chunks.
+        let replaced = self.recipe(to).ok().flatten();
+        self.disk.rename(from, to)?;
+        if self.staging.exists(from)? {
+            if let Some(parent) = self.staging.resolve(to).parent() {
+                fs::create_dir_all(parent).map_err(FsError::Io)?;
+            }
+            self.staging.rename(from, to)?;
+        } else if self.staging.exists(to)? {
+            let _ = self.staging.remove(to);
+        }
+        self.forget(from);
+        self.forget(to);
+        if let Some(r) = replaced {
+            self.release(r.chunks.iter().map(|c| c.hash.as_str()));
+        }
+        Ok(())
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.disk.set_permissions(path, mode)
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        self.disk.set_owner(path, uid, gid)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        if self.staging.exists(path)? {
+            self.staging.set_times(path, atime, mtime)?;
+        }
+        self.disk.set_times(path, atime, mtime)?;
+        self.forget(path);
+        Ok(())
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        self.disk.statvfs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Deterministic incompressible bytes.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut s = seed;
+        (0..len)
+            .map(|_| {
+                s = s.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                (s >> 33) as u8
+            })
+            .collect()
+    }
+
+    fn backend(dir: &Path, mode: DedupMode) -> DedupBackend {
+        let mut b = DedupBackend::new("cold", dir, mode, None).unwrap();
+        b.chunk_size = 4096;
+        b
+    }
+
+    fn put(b: &DedupBackend, path: &str, data: &[u8]) {
+        let p = Path::new(path);
+        b.write_at(p, 0, data).unwrap();
+        b.fsync(p).unwrap();
+    }
+
+    fn chunk_count(b: &DedupBackend) -> usize {
+        b.refs.lock().len()
+    }
+
+    #[test]
+    fn identical_files_share_chunks() {
+        let dir = TempDir::new().unwrap();
+        let b = backend(dir.path(), DedupMode::Fixed);
+        let data = noise(1, 10 * 4096 + 100);
+        put(&b, "a.img", &data);
+        assert_eq!(chunk_count(&b), 11);
+        b.create_dir(Path::new("d")).unwrap();
+        put(&b, "d/b.img", &data);
+        assert_eq!(chunk_count(&b), 11);
+
+        let p = Path::new("d/b.img");
+        assert_eq!(b.metadata(p).unwrap().size, data.len() as u64);
+        // A range straddling chunk boundaries, and one running past EOF.
+        assert_eq!(b.read_at(p, 4000, 5000).unwrap(), &data[4000..9000]);
+        assert_eq!(b.read_at(p, 40_900, 999).unwrap(), &data[40_900..]);
+        assert!(b.read_at(p, 50_000, 10).unwrap().is_empty());
+        // The recipe is small; the data lives in the chunks.
+        assert!(fs::metadata(dir.path().join("d/b.img")).unwrap().len() < 2000);
+        assert_eq!(b.list_dir(Path::new("")).unwrap().len(), 2);
+
+        b.remove(Path::new("a.img")).unwrap();
+        assert_eq!(chunk_count(&b), 11);
+        b.remove(p).unwrap();
+        assert_eq!(chunk_count(&b), 0);
+        let left = fs::read_dir(dir.path().join(STORE_DIR).join("chunks"))
+            .unwrap()
+            .flat_map(|d| fs::read_dir(d.unwrap().path()).unwrap())
+            .count();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn cdc_survives_insertions() {
+        let dir = TempDir::new().unwrap();
+        let b = backend(dir.path(), DedupMode::Cdc);
+        let data = noise(2, 64 * 4096);
+        put(&b, "v1.img", &data);
+        let first = chunk_count(&b);
+        let mut shifted = b"a few inserted bytes".to_vec();
+        shifted.extend_from_slice(&data);
+        put(&b, "v2.img", &shifted);
+        // Only the chunks around the insertion are new.
+        assert!(
+            chunk_count(&b) <= first + 3,
+            "{} vs {first}",
+            chunk_count(&b)
+        );
+        assert_eq!(
+            b.read_at(Path::new("v2.img"), 0, u32::MAX).unwrap(),
+            shifted
+        );
+    }
+
+    #[test]
+    fn rewrites_release_old_chunks() {
+        let dir = TempDir::new().unwrap();
+        let b = backend(dir.path(), DedupMode::Fixed);
+        let p = Path::new("f");
+        put(&b, "f", &noise(3, 3 * 4096));
+        b.write_at(p, 4096, &[7u8; 4096]).unwrap();
+        // Unsynced writes are visible before the fsync.
+        assert_eq!(b.read_at(p, 4096, 2).unwrap(), [7, 7]);
+        b.fsync(p).unwrap();
+        assert_eq!(chunk_count(&b), 3);
+        assert!(!b.staging.exists(p).unwrap());
+        b.truncate(p, 100).unwrap();
+        b.fsync(p).unwrap();
+        assert_eq!(chunk_count(&b), 1);
+        assert_eq!(b.metadata(p).unwrap().size, 100);
+
+        // Renaming over a file releases the one replaced.
+        put(&b, "g", &noise(4, 4096));
+        assert_eq!(chunk_count(&b), 2);
+        b.rename(Path::new("g"), p).unwrap();
+        assert_eq!(chunk_count(&b), 1);
+        assert_eq!(b.read_at(p, 0, 10).unwrap(), &noise(4, 10)[..]);
+    }
+
+    #[test]
+    fn plain_files_and_restart() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("old.txt"), b"from before dedup").unwrap();
+        let b = backend(dir.path(), DedupMode::Fixed);
+        let p = Path::new("old.txt");
+        assert_eq!(b.read_at(p, 5, 6).unwrap(), b"before");
+        b.write_at(p, 0, b"FROM").unwrap();
+        b.fsync(p).unwrap();
+        assert_eq!(chunk_count(&b), 1);
+        put(&b, "x", &noise(5, 8192));
+        drop(b);
+
+        // A chunk nothing refers to, as left by a crash mid-ingest.
+        let stray = dir.path().join(STORE_DIR).join("chunks/ff/ffff");
+        fs::create_dir_all(stray.parent().unwrap()).unwrap();
+        fs::write(&stray, b"junk").unwrap();
+
+        let b = backend(dir.path(), DedupMode::Fixed);
+        assert_eq!(chunk_count(&b), 3);
+        assert!(!stray.exists());
+        assert_eq!(b.read_at(p, 0, 99).unwrap(), b"FROM before dedup");
+        let mut files: Vec<PathBuf> = b.walk_files().map(Result::unwrap).collect();
+        files.sort();
+        assert_eq!(files, [PathBuf::from("old.txt"), PathBuf::from("x")]);
+    }
+}
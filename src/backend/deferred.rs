@@ -0,0 +1,267 @@
+//! `DeferredBackend` — a slow-tier backend that wasn't there at mount.
+//!
+//! A NAS that is unmounted, or a USB disk that isn't plugged in, used to
+//! fail the whole mount. Instead the backend starts *detached*: the mount
+//! comes up, files on the fast tier work as usual, and files whose index
+//! row points at the missing backend still show up in listings but fail
+//! with `EIO` when opened or stat'ed. A background thread retries opening
+//! the backend and, once that works, every call goes to it again.
+//!
+//! Files that appeared on the backend while it was away are not indexed
+//! until the next mount's scan.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use tracing::{info, warn};
+
+use super::{Backend, BackendStats, FileMetadata};
+use crate::error::{FsError, Result};
+
+/// How often a detached backend retries opening.
+pub const REATTACH_EVERY: Duration = Duration::from_secs(30);
+
+type Slot = RwLock<Option<Arc<dyn Backend>>>;
+
+pub struct DeferredBackend {
+    id: String,
+    root: PathBuf,
+    inner: Arc<Slot>,
+}
+
+impl DeferredBackend {
+    /// Open the backend with `open`. If that fails, return a detached
+    /// stand-in for `id` at `root` that retries `open` every `every`.
+    pub fn open<F>(id: &str, root: &Path, every: Duration, open: F) -> Arc<dyn Backend>
+    where
+        F: Fn() -> Result<Arc<dyn Backend>> + Send + 'static,
+    {
+        let err = match open() {
+            Ok(b) => return b,
+            Err(e) => e,
+        };
+        warn!(
+            "backend {id} unavailable ({err}); mounting without it, \
+             its files fail with EIO until it returns"
+        );
+        let inner: Arc<Slot> = Arc::default();
+        let slot = Arc::downgrade(&inner);
+        let name = id.to_string();
+        let spawned = thread::Builder::new()
+            .name("rhss-reattach".into())
+            .spawn(move || reattach(&name, slot, every, open));
+        if let Err(e) = spawned {
+            warn!("spawn reattach thread for {id}: {e}");
+        }
+        Arc::new(Self {
+            id: id.to_string(),
+            root: root.to_path_buf(),
+            inner,
+        })
+    }
+
+    fn get(&self) -> Result<Arc<dyn Backend>> {
+        self.inner
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| FsError::Io(io::Error::other(format!("backend {} is offline", self.id))))
+    }
+}
+
+/// Retry `open` until it works, then attach the result. Exits early when
+/// the stand-in is dropped.
+fn reattach<F>(id: &str, slot: Weak<Slot>, every: Duration, open: F)
+where
+    F: Fn() -> Result<Arc<dyn Backend>>,
+{
+    loop {
+        thread::sleep(every);
+        let Some(slot) = slot.upgrade() else {
+            return;
+        };
+        if let Ok(b) = open() {
+            *slot.write().unwrap_or_else(|e| e.into_inner()) = Some(b);
+            info!("backend {id} is back; cold files on it are available again");
+            return;
+        }
+    }
+}
+
+impl Backend for DeferredBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        self.get()?.read_at(path, offset, size)
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        self.get()?.write_at(path, offset, data)
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        self.get()?.truncate(path, size)
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        self.get()?.fsync(path)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        self.get()?.metadata(path)
+    }
+
+    fn allocate(&self, path: &Path, offset: u64, len: u64, mode: i32) -> Result<()> {
+        self.get()?.allocate(path, offset, len, mode)
+    }
+
+    fn next_data(&self, path: &Path, offset: u64) -> Result<Option<u64>> {
+        self.get()?.next_data(path, offset)
+    }
+
+    fn next_hole(&self, path: &Path, offset: u64) -> Result<u64> {
+        self.get()?.next_hole(path, offset)
+    }
+
+    fn copy_range(
+        &self,
+        src: &Path,
+        src_off: u64,
+        dst: &Path,
+        dst_off: u64,
+        len: u64,
+    ) -> Result<u64> {
+        self.get()?.copy_range(src, src_off, dst, dst_off, len)
+    }
+
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.get()?.copy(src, dst)
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        self.get()?.check_access(path, uid, gid, mask)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        self.get()?.exists(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        self.get()?.list_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.get()?.create_dir(path)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        self.get()?.create_file(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.get()?.remove(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.get()?.rename(from, to)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.get()?.set_permissions(path, mode)
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        self.get()?.set_owner(path, uid, gid)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        self.get()?.set_times(path, atime, mtime)
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        self.get()?.statvfs()
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        match self.get() {
+            Ok(b) => b.resolve(path),
+            Err(_) => self.root.join(path.strip_prefix("/").unwrap_or(path)),
+        }
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        // Nothing to scan while detached. Once attached, the listing is
+        // collected because the walk can't borrow from the slot's guard.
+        match self.get() {
+            Ok(b) => Box::new(b.walk_files().collect::<Vec<_>>().into_iter()),
+            Err(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    fn sync_replicas(&self, path: &Path) -> Result<()> {
+        self.get()?.sync_replicas(path)
+    }
+
+    fn resume_copy(&self, path: &Path, src: &FileMetadata) -> Result<u64> {
+        self.get()?.resume_copy(path, src)
+    }
+
+    fn direct_io_above(&self) -> Option<u64> {
+        self.get().ok()?.direct_io_above()
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.get().ok()?.cost_per_gb_month()
+    }
+
+    fn available(&self) -> bool {
+        self.get().is_ok_and(|b| b.available())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use tempfile::TempDir;
+
+    #[test]
+    fn missing_root_mounts_detached_and_reattaches() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("nas");
+        let r = root.clone();
+        let b = DeferredBackend::open("nas", &root, Duration::from_millis(20), move || {
+            Ok(Arc::new(PosixBackend::new("nas", r.clone())?) as Arc<dyn Backend>)
+        });
+        assert!(!b.available());
+        assert_eq!(
+            b.metadata(Path::new("f")).unwrap_err().to_errno(),
+            libc::EIO
+        );
+        assert_eq!(b.walk_files().count(), 0);
+
+        // The disk comes back.
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("f"), b"cold").unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !b.available() && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(b.read_at(Path::new("f"), 0, 4).unwrap(), b"cold");
+        assert_eq!(b.walk_files().count(), 1);
+    }
+}
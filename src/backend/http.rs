@@ -0,0 +1,725 @@
+//! Archive backend on a plain HTTP / WebDAV server (`url =
+//! "http://host[:port]/path/"`), e.g. nginx with `dav_methods` or another
+//! rhss running `serve-webdav`.
+//!
+//! Reads are range `GET`s straight off the server. Writes follow the
+//! `S3Backend` model: the file is staged locally (fetched whole first if
+//! it exists), and `fsync` `PUT`s the staged copy. Deletes, renames and
+//! directories map to `DELETE`, `MOVE` and `MKCOL`; attributes come from
+//! `PROPFIND`, falling back to `HEAD` on servers without WebDAV.
+//!
+//! `read_only` backends serve existing archives and fail every mutation
+//! with `EROFS`, so a plain static file server is enough for them.
+//!
+//! Only `http://` is spoken; put a TLS-terminating proxy on the archive
+//! host's loopback side if the link needs encrypting. Each request uses
+//! its own connection, with optional Basic auth from `credential_env`
+//! (`username` / `password`).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::debug;
+
+use crate::error::{FsError, Result};
+use crate::webdav::http::{percent_decode, percent_encode, read_response_head, Body, ResponseHead};
+
+use super::s3::parse_rfc1123;
+use super::{Backend, BackendStats, FileMetadata, PosixBackend};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct HttpConfig {
+    pub id: String,
+    pub url: String,
+    /// `username` / `password` → name of the env var holding it.
+    pub credential_env: BTreeMap<String, String>,
+    pub read_only: bool,
+    pub staging_root: PathBuf,
+    pub cost_per_gb_month: Option<f64>,
+}
+
+pub struct HttpBackend {
+    id: String,
+    /// `host:port` to connect to.
+    addr: String,
+    /// `Host` header value.
+    host: String,
+    /// URL path of the archive root, without a trailing slash.
+    base: String,
+    /// Precomputed `Authorization` header value.
+    auth: Option<String>,
+    read_only: bool,
+    staging: PosixBackend,
+    cost_per_gb_month: Option<f64>,
+}
+
+struct Reply {
+    status: u16,
+    head: ResponseHead,
+    body: Vec<u8>,
+}
+
+/// One `<response>` of a `PROPFIND` multistatus.
+#[derive(Debug, Default, PartialEq)]
+struct DavEntry {
+    href: String,
+    is_dir: bool,
+    size: u64,
+    mtime: Option<SystemTime>,
+}
+
+/// Split `http://host[:port]/path` into the address to connect to, the
+/// `Host` header value and the path. Shared with `crate::otlp`.
+pub(crate) fn split_url(url: &str) -> Result<(String, &str, &str)> {
+    let bad = |why: &str| FsError::Storage(format!("http url {url:?}: {why}"));
+    let rest = match url.split_once("://") {
+        Some(("http", rest)) => rest,
+        Some(("https", _)) => {
+            return Err(bad(
+                "https is not supported; terminate TLS in a proxy and use http://",
+            ))
+        }
+        _ => return Err(bad("expected http://")),
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if authority.is_empty() || authority.contains('@') {
+        return Err(bad("expected http://host[:port]/path"));
+    }
+    let addr = if authority
+        .rsplit_once(':')
+        .is_some_and(|(_, p)| !p.contains(']'))
+    {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+    Ok((addr, authority, path))
+}
+
+impl HttpBackend {
+    pub fn new(cfg: HttpConfig) -> Result<Self> {
+        let (addr, authority, path) = split_url(&cfg.url)?;
+
+        let mut user = None;
+        let mut pass = None;
+        for (key, var) in &cfg.credential_env {
+            let value = std::env::var(var).map_err(|_| {
+                FsError::Storage(format!("credential {key}: env var {var} is not set"))
+            })?;
+            match key.as_str() {
+                "username" => user = Some(value),
+                "password" => pass = Some(value),
+                _ => {
+                    return Err(FsError::Storage(format!(
+                        "credential key {key}: expected username or password"
+                    )))
+                }
+            }
+        }
+        let auth = match (user, pass) {
+            (None, None) => None,
+            (user, pass) => Some(format!(
+                "Basic {}",
+                base64(
+                    format!("{}:{}", user.unwrap_or_default(), pass.unwrap_or_default()).as_bytes()
+                )
+            )),
+        };
+
+        fs::create_dir_all(&cfg.staging_root).map_err(FsError::Io)?;
+        let staging = PosixBackend::new(format!("{}-staging", cfg.id), cfg.staging_root)?;
+        Ok(Self {
+            id: cfg.id,
+            addr,
+            host: authority.to_string(),
+            base: path.trim_end_matches('/').to_string(),
+            auth,
+            read_only: cfg.read_only,
+            staging,
+            cost_per_gb_month: cfg.cost_per_gb_month,
+        })
+    }
+
+    /// Request target for `path`; collections get a trailing slash, which
+    /// nginx insists on for `MKCOL` and directory `PROPFIND`s.
+    fn target(&self, path: &Path, dir: bool) -> String {
+        let rel = path.strip_prefix("/").unwrap_or(path);
+        let mut t = format!(
+            "{}/{}",
+            self.base,
+            percent_encode(rel.as_os_str().as_bytes())
+        );
+        if dir && !t.ends_with('/') {
+            t.push('/');
+        }
+        t
+    }
+
+    fn writable(&self, path: &Path) -> Result<()> {
+        if self.read_only {
+            debug!(
+                "http {}: {} refused, backend is read-only",
+                self.id,
+                path.display()
+            );
+            return Err(FsError::ReadOnly(format!(
+                "http {}: {}",
+                self.id,
+                path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(s) => {
+                    s.set_read_timeout(Some(IO_TIMEOUT))?;
+                    s.set_write_timeout(Some(IO_TIMEOUT))?;
+                    return Ok(s);
+                }
+                Err(e) => last = Some(e),
+            }
+        }
+        Err(last.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses")))
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        target: &str,
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<Reply> {
+        debug!("http {method} {target} ({} bytes)", body.len());
+        let err = |e: io::Error| FsError::Storage(format!("{method} {target}: {e}"));
+        let mut s = self
+            .connect()
+            .map_err(|e| FsError::Offline(format!("http {}: {method} {target}: {e}", self.id)))?;
+        let mut head = format!(
+            "{method} {target} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rhss\r\n\
+             Connection: close\r\nContent-Length: {}\r\n",
+            self.host,
+            body.len()
+        );
+        if let Some(auth) = &self.auth {
+            head.push_str(&format!("Authorization: {auth}\r\n"));
+        }
+        for (k, v) in headers {
+            head.push_str(&format!("{k}: {v}\r\n"));
+        }
+        head.push_str("\r\n");
+        s.write_all(head.as_bytes()).map_err(err)?;
+        s.write_all(body).map_err(err)?;
+
+        let mut r = BufReader::new(s);
+        let resp = read_response_head(&mut r).map_err(err)?;
+        let mut data = Vec::new();
+        Body::of_response(method, &resp, &mut r)
+            .and_then(|mut b| b.read_to_end(&mut data))
+            .map_err(err)?;
+        Ok(Reply {
+            status: resp.status,
+            head: resp,
+            body: data,
+        })
+    }
+
+    fn status_err(&self, method: &str, path: &Path, status: u16) -> FsError {
+        let what = path.display().to_string();
+        match status {
+            404 | 410 => FsError::NotFound(what),
+            _ => FsError::from_status(status, format!("http {}: {method} {what}", self.id)),
+        }
+    }
+
+    fn propfind(&self, path: &Path, depth: u8) -> Result<Option<Vec<DavEntry>>> {
+        let reply = self.send(
+            "PROPFIND",
+            &self.target(path, depth > 0),
+            &[("Depth", depth.to_string())],
+            b"",
+        )?;
+        match reply.status {
+            207 => Ok(Some(parse_multistatus(&String::from_utf8_lossy(
+                &reply.body,
+            )))),
+            // Not a WebDAV server.
+            405 | 501 => Ok(None),
+            s => Err(self.status_err("PROPFIND", path, s)),
+        }
+    }
+
+    /// Fetch `path` into its staging file unless it's there already. A
+    /// missing object (created, not yet fsync'd) stages as empty.
+    fn ensure_staged(&self, path: &Path) -> Result<()> {
+        if self.staging.exists(path)? {
+            return Ok(());
+        }
+        let reply = self.send("GET", &self.target(path, false), &[], b"")?;
+        let data = match reply.status {
+            200 => Some(reply.body),
+            404 => None,
+            s => return Err(self.status_err("GET", path, s)),
+        };
+        self.create_staged(path)?;
+        if let Some(data) = data {
+            self.staging.write_at(path, 0, &data)?;
+        }
+        Ok(())
+    }
+
+    fn create_staged(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = self.staging.resolve(path).parent() {
+            fs::create_dir_all(parent).map_err(FsError::Io)?;
+        }
+        self.staging.create_file(path)
+    }
+
+    fn entry_metadata(e: &DavEntry) -> FileMetadata {
+        let mtime = e.mtime.unwrap_or(UNIX_EPOCH);
+        FileMetadata {
+            size: if e.is_dir { 0 } else { e.size },
+            is_dir: e.is_dir,
+            mode: if e.is_dir { 0o755 } else { 0o644 },
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+        }
+    }
+}
+
+impl Backend for HttpBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn root(&self) -> &Path {
+        self.staging.root()
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.staging.resolve(path)
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.cost_per_gb_month
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        if self.staging.exists(path)? {
+            return self.staging.read_at(path, offset, size);
+        }
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let range = format!("bytes={offset}-{}", offset + size as u64 - 1);
+        let reply = self.send("GET", &self.target(path, false), &[("Range", range)], b"")?;
+        match reply.status {
+            206 => Ok(reply.body),
+            // Server ignored the range.
+            200 => {
+                let start = (offset as usize).min(reply.body.len());
+                let end = (start + size as usize).min(reply.body.len());
+                Ok(reply.body[start..end].to_vec())
+            }
+            // Past EOF.
+            416 => Ok(Vec::new()),
+            s => Err(self.status_err("GET", path, s)),
+        }
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        self.writable(path)?;
+        self.ensure_staged(path)?;
+        self.staging.write_at(path, offset, data)
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        self.writable(path)?;
+        self.ensure_staged(path)?;
+        self.staging.truncate(path, size)
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        if !self.staging.exists(path)? {
+            return Ok(());
+        }
+        let data = fs::read(self.staging.resolve(path)).map_err(FsError::Io)?;
+        let reply = self.send("PUT", &self.target(path, false), &[], &data)?;
+        match reply.status {
+            200 | 201 | 204 => Ok(()),
+            s => Err(self.status_err("PUT", path, s)),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        if self.staging.exists(path)? {
+            return self.staging.metadata(path);
+        }
+        if let Some(entries) = self.propfind(path, 0)? {
+            let e = entries
+                .first()
+                .ok_or_else(|| FsError::NotFound(path.display().to_string()))?;
+            return Ok(Self::entry_metadata(e));
+        }
+        let reply = self.send("HEAD", &self.target(path, false), &[], b"")?;
+        if reply.status != 200 {
+            return Err(self.status_err("HEAD", path, reply.status));
+        }
+        Ok(Self::entry_metadata(&DavEntry {
+            size: reply
+                .head
+                .header("content-length")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            mtime: reply.head.header("last-modified").and_then(parse_rfc1123),
+            ..Default::default()
+        }))
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        match self.metadata(path) {
+            Ok(_) => Ok(true),
+            Err(FsError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        // Without WebDAV there is no listing to ask for.
+        let Some(entries) = self.propfind(path, 1)? else {
+            return Ok(Vec::new());
+        };
+        let own = href_path(&self.target(path, false));
+        Ok(entries
+            .iter()
+            .map(|e| href_path(&e.href))
+            .filter(|p| *p != own)
+            .filter_map(|p| p.rsplit('/').next().map(str::to_string))
+            .filter(|name| !name.is_empty())
+            .collect())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.writable(path)?;
+        let reply = self.send("MKCOL", &self.target(path, true), &[], b"")?;
+        match reply.status {
+            // 405: already there.
+            200 | 201 | 204 | 405 => Ok(()),
+            s => Err(self.status_err("MKCOL", path, s)),
+        }
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        self.writable(path)?;
+        // Uploaded on the first fsync.
+        self.create_staged(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.writable(path)?;
+        if self.staging.exists(path)? {
+            let _ = self.staging.remove(path);
+        }
+        let mut reply = self.send("DELETE", &self.target(path, false), &[], b"")?;
+        if reply.status == 409 {
+            // nginx wants collections addressed with a trailing slash.
+            reply = self.send("DELETE", &self.target(path, true), &[], b"")?;
+        }
+        match reply.status {
+            200 | 202 | 204 | 404 => Ok(()),
+            s => Err(self.status_err("DELETE", path, s)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.writable(from)?;
+        let dest = format!("http://{}{}", self.host, self.target(to, false));
+        let reply = self.send(
+            "MOVE",
+            &self.target(from, false),
+            &[("Destination", dest), ("Overwrite", "T".to_string())],
+            b"",
+        )?;
+        // A staged file that was never fsync'd isn't on the server yet.
+        let staged = self.staging.exists(from)?;
+        match reply.status {
+            200 | 201 | 204 => {}
+            404 if staged => {}
+            s => return Err(self.status_err("MOVE", from, s)),
+        }
+        if staged {
+            if let Some(parent) = self.staging.resolve(to).parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let _ = self.staging.rename(from, to);
+        }
+        Ok(())
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.writable(path)?;
+        // The server keeps no mode; keep it on the staged copy.
+        if self.staging.exists(path)? {
+            self.staging.set_permissions(path, mode)?;
+        }
+        Ok(())
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        self.writable(path)?;
+        if self.staging.exists(path)? {
+            self.staging.set_times(path, atime, mtime)?;
+        }
+        Ok(())
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        // Unknown; reported as 1 PiB like S3.
+        const UNLIMITED: u64 = 1 << 50;
+        Ok(BackendStats {
+            total_bytes: UNLIMITED,
+            free_bytes: if self.read_only { 0 } else { UNLIMITED },
+            used_bytes: 0,
+        })
+    }
+}
+
+/// Decoded path of an `href`, which may be a full URL, without a trailing
+/// slash.
+fn href_path(href: &str) -> String {
+    let path = match href.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+        None => href,
+    };
+    let decoded = percent_decode(path).unwrap_or_else(|| path.as_bytes().to_vec());
+    String::from_utf8_lossy(&decoded)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Pull the `<response>` entries out of a `PROPFIND` reply. Not an XML
+/// parser: tags are matched by local name, whatever the namespace prefix,
+/// and only the handful of properties we use are read.
+fn parse_multistatus(xml: &str) -> Vec<DavEntry> {
+    let mut out = Vec::new();
+    let mut cur: Option<DavEntry> = None;
+    let (mut i, mut text_start) = (0, 0);
+    while let Some(lt) = xml[i..].find('<').map(|p| p + i) {
+        let Some(gt) = xml[lt..].find('>').map(|p| p + lt) else {
+            break;
+        };
+        let tag = &xml[lt + 1..gt];
+        i = gt + 1;
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .trim_end_matches('/')
+            .split_whitespace()
+            .next()
+            .unwrap_or("");
+        let local = name.rsplit(':').next().unwrap_or(name).to_ascii_lowercase();
+        if !closing {
+            text_start = i;
+            match local.as_str() {
+                "response" if !tag.ends_with('/') => cur = Some(DavEntry::default()),
+                "collection" => {
+                    if let Some(e) = &mut cur {
+                        e.is_dir = true;
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        let text = xml_unescape(xml[text_start..lt].trim());
+        match (local.as_str(), &mut cur) {
+            ("href", Some(e)) if e.href.is_empty() => e.href = text,
+            ("getcontentlength", Some(e)) => e.size = text.parse().unwrap_or(0),
+            ("getlastmodified", Some(e)) => e.mtime = parse_rfc1123(&text),
+            ("response", Some(_)) => out.extend(cur.take()),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn base64(b: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(b.len().div_ceil(3) * 4);
+    for chunk in b.chunks(3) {
+        let n = chunk.iter().fold(0u32, |n, &c| n << 8 | c as u32) << (8 * (3 - chunk.len()));
+        for k in 0..4 {
+            if k <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * k)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::index::{PathIndex, SqlitePathIndex, TierId};
+    use crate::namespace::Namespace;
+    use crate::policy::PopularityPolicy;
+    use crate::tier::{MostFreePlacement, Tier, TierRouter};
+    use crate::tierer::OpenFileTracker;
+    use crate::webdav::WebDavServer;
+
+    /// rhss's own WebDAV frontend stands in for the archive server.
+    fn serve(dir: &Path) -> WebDavServer {
+        let tier = |id, name: &str| {
+            let root = dir.join(name);
+            fs::create_dir_all(&root).unwrap();
+            let b: Arc<dyn Backend> = Arc::new(PosixBackend::new(name, root).unwrap());
+            Tier::new(id, vec![b], Box::new(MostFreePlacement)).unwrap()
+        };
+        let router = Arc::new(TierRouter::new(
+            tier(TierId::Fast, "ssd"),
+            tier(TierId::Slow, "hdd"),
+        ));
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(dir.join("idx.db")).unwrap();
+        let ns = Namespace::new(
+            router,
+            index,
+            Arc::new(PopularityPolicy::default()),
+            Arc::new(OpenFileTracker::new()),
+        );
+        WebDavServer::start("127.0.0.1:0".parse().unwrap(), Arc::new(ns), false).unwrap()
+    }
+
+    fn backend(srv: &WebDavServer, staging: PathBuf, read_only: bool) -> HttpBackend {
+        HttpBackend::new(HttpConfig {
+            id: "dav".into(),
+            url: format!("http://{}/", srv.local_addr()),
+            credential_env: BTreeMap::new(),
+            read_only,
+            staging_root: staging,
+            cost_per_gb_month: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn stages_puts_and_range_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let srv = serve(&dir.path().join("srv"));
+        let b = backend(&srv, dir.path().join("a"), false);
+        let p = Path::new("my docs/x.bin");
+        b.create_dir(Path::new("my docs")).unwrap();
+        b.create_dir(Path::new("my docs")).unwrap();
+        b.create_file(p).unwrap();
+        b.write_at(p, 0, b"hello world").unwrap();
+        b.fsync(p).unwrap();
+        assert_eq!(b.list_dir(Path::new("my docs")).unwrap(), vec!["x.bin"]);
+
+        // A fresh backend (empty staging) reads ranges off the server.
+        let b2 = backend(&srv, dir.path().join("b"), false);
+        let meta = b2.metadata(p).unwrap();
+        assert_eq!((meta.size, meta.is_dir), (11, false));
+        assert!(b2.metadata(Path::new("my docs")).unwrap().is_dir);
+        assert_eq!(b2.read_at(p, 6, 100).unwrap(), b"world");
+        assert!(b2.read_at(p, 50, 10).unwrap().is_empty());
+        assert!(!b2.staging.exists(p).unwrap());
+        b2.rename(p, Path::new("my docs/y.bin")).unwrap();
+        assert!(!b2.exists(p).unwrap());
+        b2.remove(Path::new("my docs/y.bin")).unwrap();
+        assert!(b2.list_dir(Path::new("my docs")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_only_refuses_mutations() {
+        let dir = tempfile::tempdir().unwrap();
+        let srv = serve(&dir.path().join("srv"));
+        let rw = backend(&srv, dir.path().join("a"), false);
+        let p = Path::new("x");
+        rw.write_at(p, 0, b"abc").unwrap();
+        rw.fsync(p).unwrap();
+
+        let ro = backend(&srv, dir.path().join("b"), true);
+        assert_eq!(ro.read_at(p, 0, 10).unwrap(), b"abc");
+        let erofs = |r: Result<()>| r.unwrap_err().to_errno() == libc::EROFS;
+        assert!(erofs(ro.write_at(p, 0, b"x").map(drop)));
+        assert!(erofs(ro.remove(p)));
+        assert!(erofs(ro.create_dir(Path::new("d"))));
+        assert!(erofs(ro.rename(p, Path::new("y"))));
+        assert!(ro.exists(p).unwrap());
+    }
+
+    #[test]
+    fn parses_foreign_multistatus() {
+        let xml = r#"<?xml version="1.0"?>
+            <a:multistatus xmlns:a="DAV:">
+             <a:response><a:href>http://h/arc/d%20x/</a:href>
+              <a:propstat><a:prop><a:resourcetype><a:collection /></a:resourcetype>
+              </a:prop></a:propstat></a:response>
+             <a:response><a:href>/arc/d%20x/f&amp;g</a:href>
+              <a:propstat><a:prop><a:resourcetype/>
+               <a:getcontentlength>42</a:getcontentlength>
+               <a:getlastmodified>Wed, 21 Oct 2015 07:28:00 GMT</a:getlastmodified>
+              </a:prop></a:propstat></a:response>
+            </a:multistatus>"#;
+        let e = parse_multistatus(xml);
+        assert_eq!(e.len(), 2);
+        assert!(e[0].is_dir);
+        assert_eq!(href_path(&e[0].href), "/arc/d x");
+        assert_eq!((e[1].is_dir, e[1].size), (false, 42));
+        assert_eq!(href_path(&e[1].href), "/arc/d x/f&g");
+        assert!(e[1].mtime.is_some());
+    }
+
+    #[test]
+    fn rejects_unsupported_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = |url: &str| HttpConfig {
+            id: "dav".into(),
+            url: url.into(),
+            credential_env: BTreeMap::new(),
+            read_only: true,
+            staging_root: dir.path().to_path_buf(),
+            cost_per_gb_month: None,
+        };
+        assert!(HttpBackend::new(cfg("https://h/arc")).is_err());
+        assert!(HttpBackend::new(cfg("ftp://h/arc")).is_err());
+        assert!(HttpBackend::new(cfg("http://u:p@h/arc")).is_err());
+        let b = HttpBackend::new(cfg("http://h/arc/")).unwrap();
+        assert_eq!((b.addr.as_str(), b.base.as_str()), ("h:80", "/arc"));
+        assert_eq!(b.target(Path::new("/a b"), true), "/arc/a%20b/");
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64(b"ab"), "YWI=");
+    }
+}
@@ -0,0 +1,507 @@
+//! In-memory backend: a ramdisk tier (`root = "mem://512M"`) for scratch
+//! workloads, and a disk-free stand-in for `PosixBackend` in tests.
+//!
+//! Everything lives in one ordered map from backend-relative path to node,
+//! behind a `RwLock`; reads share it, mutations take it exclusively.
+//! Ordering keeps a directory's descendants contiguous, so listing and
+//! renaming a subtree are range scans. Nothing survives the process.
+//!
+//! There is no file on disk behind a path. `root()` and `resolve()` point
+//! under `/dev/null`, which callers that walk or open backend paths
+//! directly (first scan, the kernel copy fast path) see as an empty tree or
+//! a failed open, falling back to the trait methods.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use parking_lot::RwLock;
+
+use crate::error::{FsError, Result};
+
+use super::{Backend, BackendStats, FileMetadata};
+
+struct Node {
+    /// File contents; `None` for a directory.
+    data: Option<Vec<u8>>,
+    /// Permission bits only; the type bits come from `data`.
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    atime: SystemTime,
+    mtime: SystemTime,
+    ctime: SystemTime,
+}
+
+impl Node {
+    fn new(data: Option<Vec<u8>>) -> Self {
+        let now = SystemTime::now();
+        Self {
+            mode: if data.is_some() { 0o644 } else { 0o755 },
+            data,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.data.as_ref().map_or(0, |d| d.len() as u64)
+    }
+}
+
+struct Tree {
+    /// Keyed by normalized relative path; `""` is the root directory.
+    nodes: BTreeMap<PathBuf, Node>,
+    /// Sum of file sizes, charged against `capacity`.
+    used: u64,
+}
+
+impl Tree {
+    fn node(&self, key: &Path) -> Result<&Node> {
+        self.nodes
+            .get(key)
+            .ok_or_else(|| FsError::NotFound(key.display().to_string()))
+    }
+
+    fn node_mut(&mut self, key: &Path) -> Result<&mut Node> {
+        self.nodes
+            .get_mut(key)
+            .ok_or_else(|| FsError::NotFound(key.display().to_string()))
+    }
+
+    fn file_mut(&mut self, key: &Path) -> Result<&mut Vec<u8>> {
+        self.node_mut(key)?
+            .data
+            .as_mut()
+            .ok_or_else(|| FsError::IsDirectory(key.display().to_string()))
+    }
+
+    /// `key`'s parent must be an existing directory.
+    fn check_parent(&self, key: &Path) -> Result<()> {
+        let parent = key.parent().unwrap_or(Path::new(""));
+        if self.node(parent)?.data.is_some() {
+            return Err(FsError::NotDirectory(parent.display().to_string()));
+        }
+        Ok(())
+    }
+
+    /// `mkdir -p` of `key` and everything above it.
+    fn create_dirs(&mut self, key: &Path) -> Result<()> {
+        for dir in key.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            match self.nodes.get(dir) {
+                Some(n) if n.data.is_some() => {
+                    return Err(FsError::NotDirectory(dir.display().to_string()))
+                }
+                Some(_) => {}
+                None => {
+                    self.nodes.insert(dir.to_path_buf(), Node::new(None));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keys strictly below `key`.
+    fn descendants<'a>(&'a self, key: &'a Path) -> impl Iterator<Item = &'a PathBuf> + 'a {
+        self.nodes
+            .range::<Path, _>((Bound::Excluded(key), Bound::Unbounded))
+            .map(|(k, _)| k)
+            .take_while(move |k| k.starts_with(key))
+    }
+
+    /// Make room for `key` to grow from `old` to `new` bytes.
+    fn charge(&mut self, key: &Path, old: u64, new: u64, capacity: u64) -> Result<()> {
+        let used = self.used - old + new;
+        if new > old && used > capacity {
+            return Err(FsError::NoSpace(key.display().to_string()));
+        }
+        self.used = used;
+        Ok(())
+    }
+}
+
+pub struct MemoryBackend {
+    id: String,
+    root: PathBuf,
+    capacity: u64,
+    cost_per_gb_month: Option<f64>,
+    tree: RwLock<Tree>,
+}
+
+impl MemoryBackend {
+    /// An empty backend holding at most `capacity` bytes of file data.
+    pub fn new(id: impl Into<String>, capacity: u64) -> Self {
+        Self::with_cost(id, capacity, None)
+    }
+
+    /// Create with an explicit cost-per-GiB-per-month declaration (D26).
+    pub fn with_cost(id: impl Into<String>, capacity: u64, cost_per_gb_month: Option<f64>) -> Self {
+        let id = id.into();
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::new(), Node::new(None));
+        Self {
+            root: PathBuf::from("/dev/null"),
+            id,
+            capacity,
+            cost_per_gb_month,
+            tree: RwLock::new(Tree { nodes, used: 0 }),
+        }
+    }
+
+    /// Normalized map key for `path` (no leading `/`, `.` or trailing
+    /// slash). Unsafe paths are refused like on disk (`super::sanitize_rel`).
+    fn key(path: &Path) -> Result<PathBuf> {
+        Ok(super::sanitize_rel(path)?.components().collect())
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.cost_per_gb_month
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        let key = Self::key(path)?;
+        let tree = self.tree.read();
+        let data = tree
+            .node(&key)?
+            .data
+            .as_ref()
+            .ok_or_else(|| FsError::IsDirectory(key.display().to_string()))?;
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(size as usize).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        let key = Self::key(path)?;
+        let mut tree = self.tree.write();
+        // Like `pwrite` on an `O_CREAT` fd: the file appears, its directory
+        // must already exist.
+        if !tree.nodes.contains_key(&key) {
+            tree.check_parent(&key)?;
+            tree.nodes.insert(key.clone(), Node::new(Some(Vec::new())));
+        }
+        let old = tree.file_mut(&key)?.len() as u64;
+        let new = old.max(offset + data.len() as u64);
+        tree.charge(&key, old, new, self.capacity)?;
+        let file = tree.file_mut(&key)?;
+        if new > old {
+            file.resize(new as usize, 0);
+        }
+        file[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+        let node = tree.node_mut(&key)?;
+        node.mtime = SystemTime::now();
+        node.ctime = node.mtime;
+        Ok(data.len() as u32)
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        let key = Self::key(path)?;
+        let mut tree = self.tree.write();
+        let old = tree.file_mut(&key)?.len() as u64;
+        tree.charge(&key, old, size, self.capacity)?;
+        tree.file_mut(&key)?.resize(size as usize, 0);
+        let node = tree.node_mut(&key)?;
+        node.mtime = SystemTime::now();
+        node.ctime = node.mtime;
+        Ok(())
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        // Nothing to flush, but the file has to be there.
+        let key = Self::key(path)?;
+        self.tree.read().node(&key).map(drop)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let key = Self::key(path)?;
+        let tree = self.tree.read();
+        let n = tree.node(&key)?;
+        let kind = if n.data.is_some() {
+            libc::S_IFREG
+        } else {
+            libc::S_IFDIR
+        };
+        Ok(FileMetadata {
+            size: n.len(),
+            is_dir: n.data.is_none(),
+            mode: kind | n.mode,
+            uid: n.uid,
+            gid: n.gid,
+            atime: n.atime,
+            mtime: n.mtime,
+            ctime: n.ctime,
+        })
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        let key = Self::key(path)?;
+        let tree = self.tree.read();
+        let n = tree.node(&key)?;
+        if super::mode_permits(n.mode, n.data.is_none(), n.uid, n.gid, uid, gid, mask) {
+            Ok(())
+        } else {
+            Err(FsError::PermissionDenied(path.display().to_string()))
+        }
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        let key = Self::key(path)?;
+        Ok(self.tree.read().nodes.contains_key(&key))
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        let key = Self::key(path)?;
+        let tree = self.tree.read();
+        if tree.node(&key)?.data.is_some() {
+            return Err(FsError::NotDirectory(key.display().to_string()));
+        }
+        Ok(tree
+            .descendants(&key)
+            .filter(|k| k.parent() == Some(key.as_path()))
+            .filter_map(|k| k.file_name()?.to_str().map(str::to_string))
+            .collect())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let key = Self::key(path)?;
+        self.tree.write().create_dirs(&key)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        let key = Self::key(path)?;
+        let mut tree = self.tree.write();
+        if let Some(parent) = key.parent() {
+            tree.create_dirs(parent)?;
+        }
+        if tree.nodes.contains_key(&key) {
+            return Err(FsError::AlreadyExists(key.display().to_string()));
+        }
+        tree.nodes.insert(key, Node::new(Some(Vec::new())));
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let key = Self::key(path)?;
+        if key.as_os_str().is_empty() {
+            return Err(FsError::InvalidOperation("remove backend root".into()));
+        }
+        let mut tree = self.tree.write();
+        if tree.descendants(&key).next().is_some() {
+            return Err(FsError::NotEmpty(key.display().to_string()));
+        }
+        let n = tree
+            .nodes
+            .remove(&key)
+            .ok_or_else(|| FsError::NotFound(key.display().to_string()))?;
+        tree.used -= n.len();
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let (src, dst) = (Self::key(from)?, Self::key(to)?);
+        if src == dst {
+            return Ok(());
+        }
+        if src.as_os_str().is_empty() || dst.starts_with(&src) {
+            return Err(FsError::InvalidOperation(format!(
+                "rename {} into itself",
+                src.display()
+            )));
+        }
+        let mut tree = self.tree.write();
+        let src_dir = tree.node(&src)?.data.is_none();
+        tree.check_parent(&dst)?;
+        // rename(2) rules for an existing target.
+        if let Some(existing) = tree.nodes.get(&dst) {
+            let what = dst.display().to_string();
+            match (src_dir, existing.data.is_none()) {
+                (true, false) => return Err(FsError::NotDirectory(what)),
+                (false, true) => return Err(FsError::IsDirectory(what)),
+                _ if tree.descendants(&dst).next().is_some() => {
+                    return Err(FsError::NotEmpty(what))
+                }
+                _ => {
+                    let old = tree.nodes.remove(&dst).map_or(0, |n| n.len());
+                    tree.used -= old;
+                }
+            }
+        }
+        let moved: Vec<PathBuf> = std::iter::once(src.clone())
+            .chain(tree.descendants(&src).cloned())
+            .collect();
+        for k in moved {
+            let node = tree.nodes.remove(&k).expect("listed above");
+            let rest = k.strip_prefix(&src).expect("under src");
+            tree.nodes.insert(dst.join(rest), node);
+        }
+        tree.node_mut(&dst)?.ctime = SystemTime::now();
+        Ok(())
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        let key = Self::key(path)?;
+        let mut tree = self.tree.write();
+        let n = tree.node_mut(&key)?;
+        n.mode = mode & 0o7777;
+        n.ctime = SystemTime::now();
+        Ok(())
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        let key = Self::key(path)?;
+        let mut tree = self.tree.write();
+        let n = tree.node_mut(&key)?;
+        (n.uid, n.gid) = (uid, gid);
+        n.ctime = SystemTime::now();
+        Ok(())
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        let key = Self::key(path)?;
+        let mut tree = self.tree.write();
+        let n = tree.node_mut(&key)?;
+        if let Some(t) = atime {
+            n.atime = t;
+        }
+        if let Some(t) = mtime {
+            n.mtime = t;
+        }
+        n.ctime = SystemTime::now();
+        Ok(())
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        let used = self.tree.read().used;
+        Ok(BackendStats {
+            total_bytes: self.capacity,
+            free_bytes: self.capacity.saturating_sub(used),
+            used_bytes: used,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::backend::PosixBackend;
+    use crate::index::{PathIndex, SqlitePathIndex, TierId};
+    use crate::namespace::Namespace;
+    use crate::policy::PopularityPolicy;
+    use crate::tier::{MostFreePlacement, Tier, TierRouter};
+    use crate::tierer::OpenFileTracker;
+
+    #[test]
+    fn files_and_directories() {
+        let b = MemoryBackend::new("mem", 1 << 20);
+        let p = Path::new("/a/b/c.bin");
+        assert!(matches!(b.write_at(p, 0, b"x"), Err(FsError::NotFound(_))));
+        b.create_file(p).unwrap();
+        assert!(matches!(b.create_file(p), Err(FsError::AlreadyExists(_))));
+        b.write_at(p, 4, b"data").unwrap();
+        assert_eq!(b.read_at(p, 0, 100).unwrap(), b"\0\0\0\0data");
+        assert_eq!(b.read_at(p, 6, 100).unwrap(), b"ta");
+        assert!(b.read_at(p, 100, 1).unwrap().is_empty());
+        assert!(b.metadata(Path::new("a/b")).unwrap().is_dir);
+        assert_eq!(b.list_dir(Path::new("")).unwrap(), vec!["a"]);
+        assert_eq!(b.list_dir(Path::new("a/b/")).unwrap(), vec!["c.bin"]);
+
+        assert!(matches!(
+            b.remove(Path::new("a")),
+            Err(FsError::NotEmpty(_))
+        ));
+        b.rename(Path::new("a"), Path::new("z")).unwrap();
+        assert!(!b.exists(p).unwrap());
+        assert_eq!(b.read_at(Path::new("z/b/c.bin"), 4, 4).unwrap(), b"data");
+        assert!(b.rename(Path::new("z"), Path::new("z/b/y")).is_err());
+        b.remove(Path::new("z/b/c.bin")).unwrap();
+        assert_eq!(b.statvfs().unwrap().used_bytes, 0);
+        assert!(b.read_at(Path::new("../etc/passwd"), 0, 1).is_err());
+    }
+
+    #[test]
+    fn capacity_is_enforced() {
+        let b = MemoryBackend::new("mem", 10);
+        let p = Path::new("f");
+        b.write_at(p, 0, &[1; 8]).unwrap();
+        assert!(matches!(
+            b.write_at(p, 8, &[1; 3]),
+            Err(FsError::NoSpace(_))
+        ));
+        assert!(matches!(b.truncate(p, 11), Err(FsError::NoSpace(_))));
+        b.truncate(p, 2).unwrap();
+        b.write_at(Path::new("g"), 0, &[1; 8]).unwrap();
+        let s = b.statvfs().unwrap();
+        assert_eq!((s.used_bytes, s.free_bytes), (10, 0));
+    }
+
+    #[test]
+    fn attributes_round_trip() {
+        let b = MemoryBackend::new("mem", 1 << 20);
+        let p = Path::new("f");
+        b.create_file(p).unwrap();
+        b.set_permissions(p, 0o600).unwrap();
+        let t = SystemTime::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 5);
+        b.set_times(p, None, Some(t)).unwrap();
+        let m = b.metadata(p).unwrap();
+        assert_eq!((m.mode & 0o7777, m.mtime), (0o600, t));
+        assert_eq!(m.mode & libc::S_IFMT, libc::S_IFREG);
+        assert!(b.check_access(p, 54321, 54321, libc::R_OK).is_err());
+        b.set_owner(p, 54321, 54321).unwrap();
+        b.check_access(p, 54321, 54321, libc::R_OK).unwrap();
+    }
+
+    /// As a fast tier under a `Namespace`, migrating to a disk slow tier.
+    #[test]
+    fn serves_as_a_tier() {
+        let dir = tempfile::tempdir().unwrap();
+        let fast: Arc<dyn Backend> = Arc::new(MemoryBackend::new("ram", 1 << 20));
+        let slow: Arc<dyn Backend> = Arc::new(PosixBackend::new("hdd", dir.path()).unwrap());
+        let router = Arc::new(TierRouter::new(
+            Tier::new(TierId::Fast, vec![fast], Box::new(MostFreePlacement)).unwrap(),
+            Tier::new(TierId::Slow, vec![slow], Box::new(MostFreePlacement)).unwrap(),
+        ));
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(dir.path().join("idx.db")).unwrap();
+        let ns = Namespace::new(
+            router,
+            index,
+            Arc::new(PopularityPolicy::default()),
+            Arc::new(OpenFileTracker::new()),
+        );
+        let p = Path::new("/scratch/x.bin");
+        ns.mkdir(Path::new("/scratch")).unwrap();
+        ns.create_file(p).unwrap();
+        ns.write(p, 0, b"hello").unwrap();
+        assert_eq!(ns.tier_of(p).unwrap(), Some(TierId::Fast));
+        assert!(ns.migrate(p, TierId::Slow).unwrap());
+        assert_eq!(
+            std::fs::read(dir.path().join("scratch/x.bin")).unwrap(),
+            b"hello"
+        );
+        assert_eq!(ns.read(p, 0, 16).unwrap(), b"hello");
+    }
+}
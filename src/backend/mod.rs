@@ -11,13 +11,159 @@
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
+pub mod breaker;
+pub mod chunked;
+pub mod dedup;
+pub mod deferred;
+pub mod http;
+pub mod memory;
+#[cfg(feature = "object-store")]
+pub mod object;
+pub mod packed;
 pub mod posix;
+pub mod replicated;
 pub mod s3;
+pub mod sharded;
+pub mod smallfile;
+pub mod timeout;
 
+pub use breaker::{BreakerBackend, BreakerConfig};
+pub use chunked::ChunkedBackend;
+pub use dedup::{DedupBackend, DedupMode};
+pub use deferred::DeferredBackend;
+pub use http::{HttpBackend, HttpConfig};
+pub use memory::MemoryBackend;
+#[cfg(feature = "object-store")]
+pub use object::{ObjectStoreBackend, ObjectStoreConfig};
+pub use packed::PackedBackend;
 pub use posix::PosixBackend;
+pub use replicated::{ReplicatedBackend, ReplicationMode};
 pub use s3::{S3Backend, S3Config};
+pub use sharded::ShardedBackend;
+pub use smallfile::SmallFileBackend;
+pub use timeout::TimeoutBackend;
 
-use crate::error::Result;
+use crate::error::{FsError, Result};
+
+const COPY_CHUNK: u64 = 1 << 20;
+
+/// Stream `len` bytes between two (possibly different) backends in 1 MiB
+/// chunks. Returns the bytes copied, short if `src` hits EOF.
+pub fn copy_between<S: Backend + ?Sized, D: Backend + ?Sized>(
+    src: &S,
+    src_path: &Path,
+    src_off: u64,
+    dst: &D,
+    dst_path: &Path,
+    dst_off: u64,
+    len: u64,
+) -> Result<u64> {
+    let mut done = 0u64;
+    while done < len {
+        let want = (len - done).min(COPY_CHUNK) as u32;
+        let chunk = src.read_at(src_path, src_off + done, want)?;
+        if chunk.is_empty() {
+            break;
+        }
+        let n = dst.write_at(dst_path, dst_off + done, &chunk)? as u64;
+        done += n;
+        if n < want as u64 {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+/// Whole-file copy between backends that keeps holes: only data segments
+/// (per `next_data`/`next_hole`) and non-zero chunks within them are
+/// written, then `dst` is sized to match. `dst` is created or emptied
+/// first. Used by migration so sparse VM images stay sparse on the cold
+/// tier. Returns the logical size.
+pub fn copy_sparse<S: Backend + ?Sized, D: Backend + ?Sized>(
+    src: &S,
+    src_path: &Path,
+    dst: &D,
+    dst_path: &Path,
+) -> Result<u64> {
+    let size = src.metadata(src_path)?.size;
+    if !dst.exists(dst_path)? {
+        dst.create_file(dst_path)?;
+    }
+    dst.truncate(dst_path, 0)?;
+    let mut pos = 0u64;
+    while pos < size {
+        let Some(start) = src.next_data(src_path, pos)? else {
+            break;
+        };
+        let end = src.next_hole(src_path, start)?.min(size);
+        let mut off = start;
+        while off < end {
+            let want = (end - off).min(COPY_CHUNK) as u32;
+            let chunk = src.read_at(src_path, off, want)?;
+            if chunk.is_empty() {
+                break;
+            }
+            crate::throttle::take(crate::throttle::Class::Migration, chunk.len() as u64);
+            if chunk.iter().any(|&b| b != 0) {
+                dst.write_at(dst_path, off, &chunk)?;
+            }
+            off += chunk.len() as u64;
+        }
+        pos = end.max(start + 1);
+    }
+    dst.truncate(dst_path, size)?;
+    Ok(size)
+}
+
+/// `path` relative to a backend root, or `InvalidOperation` (`EINVAL`) if
+/// it could name something outside it: a `..` component, a root or drive
+/// prefix past the single leading `/` every caller may pass, or a NUL byte.
+pub fn sanitize_rel(path: &Path) -> Result<&Path> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Component;
+
+    let rel = path.strip_prefix("/").unwrap_or(path);
+    let bad = rel.as_os_str().as_bytes().contains(&0)
+        || rel
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if bad {
+        return Err(FsError::InvalidOperation(format!(
+            "unsafe path {:?}",
+            path.as_os_str()
+        )));
+    }
+    Ok(rel)
+}
+
+/// Classic owner/group/other permission check. Only the caller's primary gid
+/// is considered (FUSE requests don't carry supplementary groups). Root may
+/// read and write anything, and execute anything with at least one x bit.
+pub fn mode_permits(
+    mode: u32,
+    is_dir: bool,
+    owner_uid: u32,
+    owner_gid: u32,
+    uid: u32,
+    gid: u32,
+    mask: i32,
+) -> bool {
+    let mask = (mask & (libc::R_OK | libc::W_OK | libc::X_OK)) as u32;
+    if mask == 0 {
+        return true;
+    }
+    if uid == 0 {
+        return mask & libc::X_OK as u32 == 0 || is_dir || mode & 0o111 != 0;
+    }
+    let bits = if uid == owner_uid {
+        (mode >> 6) & 0o7
+    } else if gid == owner_gid {
+        (mode >> 3) & 0o7
+    } else {
+        mode & 0o7
+    };
+    bits & mask == mask
+}
 
 /// File metadata returned by `Backend::metadata`.
 #[derive(Debug, Clone)]
@@ -25,6 +171,10 @@ pub struct FileMetadata {
     pub size: u64,
     pub is_dir: bool,
     pub mode: u32,
+    /// Owner as stored on the backing disk. Backends without ownership (S3)
+    /// report the daemon's uid/gid.
+    pub uid: u32,
+    pub gid: u32,
     pub atime: SystemTime,
     pub mtime: SystemTime,
     pub ctime: SystemTime,
@@ -103,6 +253,139 @@ pub trait Backend: Send + Sync {
     /// Used by FUSE `open` to get the real fd that goes into `fi->fh`.
     fn resolve(&self, path: &Path) -> PathBuf;
 
+    /// Every regular file on this backend, as backend-relative paths. The
+    /// first scan and the orphan check index from this. The default walks
+    /// `root()` on disk, skipping symlinks and special files; backends
+    /// whose files aren't laid out there list their own.
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        let root = self.root();
+        Box::new(
+            walkdir::WalkDir::new(root)
+                .follow_links(false)
+                .into_iter()
+                .filter_map(move |entry| match entry {
+                    Err(e) => Some(Err(FsError::Storage(format!("walk: {e}")))),
+                    Ok(e) if e.file_type().is_file() => e
+                        .path()
+                        .strip_prefix(root)
+                        .ok()
+                        .map(|r| Ok(r.to_path_buf())),
+                    Ok(_) => None,
+                }),
+        )
+    }
+
+    /// Copy `len` bytes from `src` at `src_off` to `dst` at `dst_off`, both
+    /// on this backend. Returns the bytes copied (short at EOF). The default
+    /// streams through `read_at`/`write_at`.
+    fn copy_range(
+        &self,
+        src: &Path,
+        src_off: u64,
+        dst: &Path,
+        dst_off: u64,
+        len: u64,
+    ) -> Result<u64> {
+        copy_between(self, src, src_off, self, dst, dst_off, len)
+    }
+
+    /// Whole-file copy within this backend; `dst` is created or replaced.
+    /// Local backends clone (reflink) where the filesystem supports it.
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        let size = self.metadata(src)?.size;
+        if !self.exists(dst)? {
+            self.create_file(dst)?;
+        }
+        self.truncate(dst, 0)?;
+        self.copy_range(src, 0, dst, 0, size)?;
+        Ok(())
+    }
+
+    /// `fallocate(2)`: reserve `[offset, offset + len)`. `mode` carries the
+    /// Linux `FALLOC_FL_*` bits (`KEEP_SIZE`, `PUNCH_HOLE`, ...). The default
+    /// only handles plain preallocation, by growing the file; anything else
+    /// is `Unsupported` (→ `EOPNOTSUPP`, which callers fall back from).
+    fn allocate(&self, path: &Path, offset: u64, len: u64, mode: i32) -> Result<()> {
+        if mode != 0 {
+            return Err(FsError::Unsupported(format!(
+                "fallocate mode {mode:#x} on {}",
+                self.id()
+            )));
+        }
+        let end = offset.saturating_add(len);
+        if self.metadata(path)?.size < end {
+            self.truncate(path, end)?;
+        }
+        Ok(())
+    }
+
+    /// `lseek(SEEK_DATA)`: first data byte at or after `offset`, or `None`
+    /// if only hole remains. The default treats the whole file as data.
+    fn next_data(&self, path: &Path, offset: u64) -> Result<Option<u64>> {
+        let size = self.metadata(path)?.size;
+        Ok((offset < size).then_some(offset))
+    }
+
+    /// `lseek(SEEK_HOLE)`: first hole byte at or after `offset` (EOF counts
+    /// as a hole). `ENXIO` when `offset` is at or past EOF.
+    fn next_hole(&self, path: &Path, offset: u64) -> Result<u64> {
+        let size = self.metadata(path)?.size;
+        if offset >= size {
+            return Err(FsError::Io(std::io::Error::from_raw_os_error(libc::ENXIO)));
+        }
+        Ok(size)
+    }
+
+    /// `access(2)` semantics: may `uid`/`gid` access `path` with `mask`
+    /// (`R_OK | W_OK | X_OK`, or `F_OK` for existence)? `Err(PermissionDenied)`
+    /// when not. The default treats the file as owned by the daemon user,
+    /// which is what backends without real ownership (S3) report in attrs.
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        let meta = self.metadata(path)?;
+        let (owner_uid, owner_gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        if mode_permits(meta.mode, meta.is_dir, owner_uid, owner_gid, uid, gid, mask) {
+            Ok(())
+        } else {
+            Err(FsError::PermissionDenied(path.display().to_string()))
+        }
+    }
+
+    /// `chown(2)`. Backends without real ownership (S3) keep reporting the
+    /// daemon user and return `Unsupported`.
+    fn set_owner(&self, path: &Path, _uid: u32, _gid: u32) -> Result<()> {
+        Err(FsError::Unsupported(format!("chown {}", path.display())))
+    }
+
+    /// `path` was written directly at `resolve(path)` (kernel copy, zstd
+    /// stream) rather than through `write_at`; bring any replicas of it up
+    /// to date. No-op unless the backend mirrors (`ReplicatedBackend`).
+    fn sync_replicas(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// About to copy a file described by `src` into `path`: how many
+    /// leading bytes an interrupted copy of the same source already made
+    /// durable here, so the copy can pick up from there. Remembers `src`
+    /// for the next attempt. The default keeps no partial copies (0).
+    fn resume_copy(&self, _path: &Path, _src: &FileMetadata) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Files at least this large are read bypassing the page cache, and
+    /// FUSE opens them with `direct_io`, so multi-GB sequential reads of
+    /// cold data don't evict what the hot tier relies on. `None` (the
+    /// default) leaves caching alone.
+    fn direct_io_above(&self) -> Option<u64> {
+        None
+    }
+
+    /// `false` while calls are refused because the backend is down
+    /// (`BreakerBackend` with its circuit open), so callers can report
+    /// `EIO` rather than a missing file. The default is always `true`.
+    fn available(&self) -> bool {
+        true
+    }
+
     /// D26: declared cost per GiB per month. `None` means the backend
     /// hasn't declared a cost (treat as free for placement purposes). Used
     /// by `CostAwarePlacement` and `rhss cost`.
@@ -0,0 +1,636 @@
+//! Read-only archive backend over a directory of packed files (`url =
+//! "packed:///srv/old-archives"`): every `.tar`, `.tar.zst` / `.tzst` and
+//! `.zip` under the root shows up as a directory of its contents, so
+//! years of packed archives can be browsed through the mount without
+//! unpacking them. Anything else under the root is passed through as-is.
+//!
+//! A pack's member list is read on first access and kept until the pack
+//! file changes. Members of a plain `.tar` are read in place at their
+//! offset; compressed members (zstd-wrapped tars, deflated zips) have no
+//! random access, so the first read extracts the whole member into the
+//! staging dir and later reads come from there.
+//!
+//! Every mutation fails with `EROFS`. `statvfs` reports no free space, so
+//! placement never picks this backend for new archive data.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use tracing::{debug, warn};
+
+use crate::error::{FsError, Result};
+
+use super::{Backend, BackendStats, FileMetadata, PosixBackend};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PackKind {
+    Tar,
+    TarZst,
+    Zip,
+}
+
+impl PackKind {
+    fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Where a member's bytes are.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Dir,
+    /// Uncompressed, at this offset of the pack file.
+    At(u64),
+    /// The n-th zip entry.
+    Zip(usize),
+    /// Somewhere in a compressed tar stream; found by name.
+    Stream,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Member {
+    size: u64,
+    mode: u32,
+    mtime: SystemTime,
+    source: Source,
+}
+
+struct Pack {
+    kind: PackKind,
+    /// Size and mtime of the pack file when it was read.
+    stamp: (u64, SystemTime),
+    /// Keyed by normalized path inside the pack; `""` is its root.
+    members: BTreeMap<PathBuf, Member>,
+}
+
+/// A backend path, split at the pack file it goes through (if any).
+enum Located {
+    Disk(PathBuf),
+    Member { pack: PathBuf, inner: PathBuf },
+}
+
+pub struct PackedBackend {
+    id: String,
+    disk: PosixBackend,
+    staging_root: PathBuf,
+    cost_per_gb_month: Option<f64>,
+    packs: Mutex<HashMap<PathBuf, Arc<Pack>>>,
+    /// Serializes extraction so two readers don't race on a staging file.
+    extracting: Mutex<()>,
+}
+
+fn erofs() -> FsError {
+    FsError::ReadOnly("packed backend".into())
+}
+
+fn pack_err(pack: &Path, e: impl std::fmt::Display) -> FsError {
+    FsError::Storage(format!("pack {}: {e}", pack.display()))
+}
+
+/// `path` inside a pack as a relative path, or `None` if it could point
+/// outside it.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(n) => out.push(n),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(out)
+}
+
+/// Zip timestamps are civil time without a zone; taken as UTC.
+fn zip_time(t: zip::DateTime) -> SystemTime {
+    let days = crate::civil::days_from_civil(t.year().into(), t.month().into(), t.day().into());
+    let secs = days * 86_400 + t.hour() as i64 * 3600 + t.minute() as i64 * 60 + t.second() as i64;
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+fn read_tar<R: Read>(
+    entries: tar::Entries<'_, R>,
+    pack: &Path,
+    in_place: bool,
+    members: &mut BTreeMap<PathBuf, Member>,
+) -> Result<()> {
+    for entry in entries {
+        let entry = entry.map_err(|e| pack_err(pack, e))?;
+        let kind = entry.header().entry_type();
+        // Links and devices aren't exposed.
+        if !(kind.is_file() || kind.is_dir()) {
+            continue;
+        }
+        let path = entry.path().map_err(|e| pack_err(pack, e))?;
+        let Some(key) = normalize(&path) else {
+            continue;
+        };
+        let source = if kind.is_dir() {
+            Source::Dir
+        } else if in_place {
+            Source::At(entry.raw_file_position())
+        } else {
+            Source::Stream
+        };
+        let h = entry.header();
+        members.insert(
+            key,
+            Member {
+                size: if kind.is_dir() { 0 } else { entry.size() },
+                mode: h.mode().unwrap_or(0o644) & 0o7777,
+                mtime: UNIX_EPOCH + Duration::from_secs(h.mtime().unwrap_or(0)),
+                source,
+            },
+        );
+    }
+    Ok(())
+}
+
+fn read_zip(file: File, pack: &Path, members: &mut BTreeMap<PathBuf, Member>) -> Result<()> {
+    let mut zip = zip::ZipArchive::new(file).map_err(|e| pack_err(pack, e))?;
+    for i in 0..zip.len() {
+        let z = zip.by_index_raw(i).map_err(|e| pack_err(pack, e))?;
+        let Some(key) = z.enclosed_name().as_deref().and_then(normalize) else {
+            continue;
+        };
+        let is_dir = z.is_dir();
+        members.insert(
+            key,
+            Member {
+                size: if is_dir { 0 } else { z.size() },
+                mode: z
+                    .unix_mode()
+                    .map_or(if is_dir { 0o755 } else { 0o644 }, |m| m & 0o7777),
+                mtime: z.last_modified().map_or(UNIX_EPOCH, zip_time),
+                source: if is_dir { Source::Dir } else { Source::Zip(i) },
+            },
+        );
+    }
+    Ok(())
+}
+
+impl PackedBackend {
+    /// Serve the packs under `root`; compressed members are extracted
+    /// under `staging_root`.
+    pub fn new(
+        id: impl Into<String>,
+        root: impl Into<PathBuf>,
+        staging_root: PathBuf,
+        cost_per_gb_month: Option<f64>,
+    ) -> Result<Self> {
+        let id = id.into();
+        let disk = PosixBackend::new(id.clone(), root)?;
+        fs::create_dir_all(&staging_root).map_err(FsError::Io)?;
+        Ok(Self {
+            id,
+            disk,
+            staging_root,
+            cost_per_gb_month,
+            packs: Mutex::new(HashMap::new()),
+            extracting: Mutex::new(()),
+        })
+    }
+
+    fn locate(&self, path: &Path) -> Result<Located> {
+        let rel = super::sanitize_rel(path)?;
+        let mut on_disk = PathBuf::new();
+        let mut rest = rel.components();
+        while let Some(c) = rest.next() {
+            on_disk.push(c);
+            if PackKind::of(&on_disk).is_some() && self.disk.resolve(&on_disk).is_file() {
+                return Ok(Located::Member {
+                    pack: on_disk,
+                    inner: rest.as_path().components().collect(),
+                });
+            }
+        }
+        Ok(Located::Disk(rel.to_path_buf()))
+    }
+
+    /// The member list of `pack`, read again if the file changed.
+    fn pack(&self, pack: &Path) -> Result<Arc<Pack>> {
+        let abs = self.disk.resolve(pack);
+        let m = fs::metadata(&abs).map_err(|e| FsError::from_io(e, pack.display()))?;
+        let stamp = (m.len(), m.modified().map_err(FsError::Io)?);
+        if let Some(p) = self.packs.lock().get(pack) {
+            if p.stamp == stamp {
+                return Ok(Arc::clone(p));
+            }
+        }
+        let kind = PackKind::of(pack).ok_or_else(|| pack_err(pack, "not a pack"))?;
+        debug!(
+            "packed {}: reading member list of {}",
+            self.id,
+            pack.display()
+        );
+        let file = File::open(&abs).map_err(FsError::Io)?;
+        let mut members = BTreeMap::new();
+        match kind {
+            PackKind::Tar => {
+                let mut ar = tar::Archive::new(file);
+                let entries = ar.entries_with_seek().map_err(|e| pack_err(pack, e))?;
+                read_tar(entries, pack, true, &mut members)?;
+            }
+            PackKind::TarZst => {
+                let z = zstd::Decoder::new(file).map_err(|e| pack_err(pack, e))?;
+                let mut ar = tar::Archive::new(z);
+                let entries = ar.entries().map_err(|e| pack_err(pack, e))?;
+                read_tar(entries, pack, false, &mut members)?;
+            }
+            PackKind::Zip => read_zip(file, pack, &mut members)?,
+        }
+        // Parents that have no entry of their own.
+        let dirs: Vec<PathBuf> = members
+            .keys()
+            .flat_map(|k| k.ancestors().skip(1).map(Path::to_path_buf))
+            .collect();
+        for d in dirs {
+            members.entry(d).or_insert(Member {
+                size: 0,
+                mode: 0o755,
+                mtime: stamp.1,
+                source: Source::Dir,
+            });
+        }
+        let p = Arc::new(Pack {
+            kind,
+            stamp,
+            members,
+        });
+        self.packs.lock().insert(pack.to_path_buf(), Arc::clone(&p));
+        Ok(p)
+    }
+
+    fn member(&self, pack: &Path, inner: &Path) -> Result<(Arc<Pack>, Member)> {
+        let p = self.pack(pack)?;
+        let m = p
+            .members
+            .get(inner)
+            .copied()
+            .ok_or_else(|| FsError::NotFound(pack.join(inner).display().to_string()))?;
+        Ok((p, m))
+    }
+
+    fn staged(&self, pack: &Path, inner: &Path) -> PathBuf {
+        self.staging_root.join(pack).join(inner)
+    }
+
+    /// Unpack a compressed member into the staging dir unless it's there.
+    fn extract(&self, pack: &Path, inner: &Path, p: &Pack, m: &Member) -> Result<PathBuf> {
+        let dst = self.staged(pack, inner);
+        let _guard = self.extracting.lock();
+        let fresh = |d: &Path| {
+            fs::metadata(d).is_ok_and(|s| s.len() == m.size && s.modified().ok() >= Some(p.stamp.1))
+        };
+        if fresh(&dst) {
+            return Ok(dst);
+        }
+        debug!(
+            "packed {}: extracting {}",
+            self.id,
+            pack.join(inner).display()
+        );
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent).map_err(FsError::Io)?;
+        }
+        let mut part = dst.clone().into_os_string();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        let mut out = File::create(&part).map_err(FsError::Io)?;
+        let file = File::open(self.disk.resolve(pack)).map_err(FsError::Io)?;
+        match (p.kind, m.source) {
+            (_, Source::Zip(i)) => {
+                let mut zip = zip::ZipArchive::new(file).map_err(|e| pack_err(pack, e))?;
+                let mut z = zip.by_index(i).map_err(|e| pack_err(pack, e))?;
+                io::copy(&mut z, &mut out).map_err(|e| pack_err(pack, e))?;
+            }
+            (PackKind::TarZst, Source::Stream) => {
+                let z = zstd::Decoder::new(file).map_err(|e| pack_err(pack, e))?;
+                let mut ar = tar::Archive::new(z);
+                let mut found = false;
+                for entry in ar.entries().map_err(|e| pack_err(pack, e))? {
+                    let mut entry = entry.map_err(|e| pack_err(pack, e))?;
+                    let path = entry.path().map_err(|e| pack_err(pack, e))?;
+                    if normalize(&path).as_deref() == Some(inner) {
+                        io::copy(&mut entry, &mut out).map_err(|e| pack_err(pack, e))?;
+                        found = true;
+                        break;
+                    }
+                }
+                if !found {
+                    return Err(FsError::NotFound(pack.join(inner).display().to_string()));
+                }
+            }
+            _ => return Err(pack_err(pack, "member is not compressed")),
+        }
+        fs::rename(&part, &dst).map_err(FsError::Io)?;
+        Ok(dst)
+    }
+
+    /// Attributes shared by a pack's root and its members: the pack
+    /// file's owner, no write bits.
+    fn member_metadata(&self, pack: &Path, m: &Member) -> Result<FileMetadata> {
+        let file = fs::metadata(self.disk.resolve(pack)).map_err(FsError::Io)?;
+        let is_dir = matches!(m.source, Source::Dir);
+        let kind = if is_dir { libc::S_IFDIR } else { libc::S_IFREG };
+        Ok(FileMetadata {
+            size: m.size,
+            is_dir,
+            mode: kind | (m.mode & !0o222),
+            uid: file.uid(),
+            gid: file.gid(),
+            atime: m.mtime,
+            mtime: m.mtime,
+            ctime: m.mtime,
+        })
+    }
+
+    fn children(&self, pack: &Path, inner: &Path) -> Result<Vec<String>> {
+        let p = self.pack(pack)?;
+        if !inner.as_os_str().is_empty() {
+            match p.members.get(inner) {
+                Some(m) if matches!(m.source, Source::Dir) => {}
+                Some(_) => return Err(FsError::NotDirectory(inner.display().to_string())),
+                None => return Err(FsError::NotFound(inner.display().to_string())),
+            }
+        }
+        Ok(p.members
+            .keys()
+            .filter(|k| k.parent() == Some(inner))
+            .filter_map(|k| k.file_name()?.to_str().map(str::to_string))
+            .collect())
+    }
+}
+
+impl Backend for PackedBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn root(&self) -> &Path {
+        self.disk.root()
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        match self.locate(path) {
+            Ok(Located::Member { pack, inner }) => self.staged(&pack, &inner),
+            _ => self.disk.resolve(path),
+        }
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.cost_per_gb_month
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        Box::new(self.disk.walk_files().flat_map(move |rel| {
+            let members: Vec<Result<PathBuf>> = match rel {
+                Ok(rel) if PackKind::of(&rel).is_some() => match self.pack(&rel) {
+                    Ok(p) => p
+                        .members
+                        .iter()
+                        .filter(|(_, m)| !matches!(m.source, Source::Dir))
+                        .map(|(k, _)| Ok(rel.join(k)))
+                        .collect(),
+                    Err(e) => {
+                        // One unreadable pack shouldn't hide the rest.
+                        warn!("packed {}: skipping {}: {e}", self.id, rel.display());
+                        Vec::new()
+                    }
+                },
+                other => vec![other],
+            };
+            members
+        }))
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        let (pack, inner) = match self.locate(path)? {
+            Located::Disk(rel) => return self.disk.read_at(&rel, offset, size),
+            Located::Member { pack, inner } => (pack, inner),
+        };
+        let (p, m) = self.member(&pack, &inner)?;
+        let want = (size as u64).min(m.size.saturating_sub(offset)) as usize;
+        let mut buf = vec![0u8; want];
+        let n = match m.source {
+            Source::Dir => return Err(FsError::IsDirectory(path.display().to_string())),
+            Source::At(start) => {
+                let f = File::open(self.disk.resolve(&pack)).map_err(FsError::Io)?;
+                f.read_at(&mut buf, start + offset).map_err(FsError::Io)?
+            }
+            Source::Zip(_) | Source::Stream => {
+                let staged = self.extract(&pack, &inner, &p, &m)?;
+                let f = File::open(staged).map_err(FsError::Io)?;
+                f.read_at(&mut buf, offset).map_err(FsError::Io)?
+            }
+        };
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    fn write_at(&self, _path: &Path, _offset: u64, _data: &[u8]) -> Result<u32> {
+        Err(erofs())
+    }
+
+    fn truncate(&self, _path: &Path, _size: u64) -> Result<()> {
+        Err(erofs())
+    }
+
+    fn fsync(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        match self.locate(path)? {
+            Located::Disk(rel) => {
+                let mut m = self.disk.metadata(&rel)?;
+                m.mode &= !0o222;
+                Ok(m)
+            }
+            Located::Member { pack, inner } => {
+                let (_, m) = self.member(&pack, &inner)?;
+                self.member_metadata(&pack, &m)
+            }
+        }
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        match self.metadata(path) {
+            Ok(_) => Ok(true),
+            Err(FsError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        match self.locate(path)? {
+            Located::Disk(rel) => self.disk.list_dir(&rel),
+            Located::Member { pack, inner } => self.children(&pack, &inner),
+        }
+    }
+
+    fn create_dir(&self, _path: &Path) -> Result<()> {
+        Err(erofs())
+    }
+
+    fn create_file(&self, _path: &Path) -> Result<()> {
+        Err(erofs())
+    }
+
+    fn remove(&self, _path: &Path) -> Result<()> {
+        Err(erofs())
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> Result<()> {
+        Err(erofs())
+    }
+
+    fn set_permissions(&self, _path: &Path, _mode: u32) -> Result<()> {
+        Err(erofs())
+    }
+
+    fn set_times(
+        &self,
+        _path: &Path,
+        _atime: Option<SystemTime>,
+        _mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        Err(erofs())
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        let s = self.disk.statvfs()?;
+        Ok(BackendStats {
+            total_bytes: s.total_bytes,
+            free_bytes: 0,
+            used_bytes: s.total_bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn tar_bytes() -> Vec<u8> {
+        let mut b = tar::Builder::new(Vec::new());
+        for (path, data) in [
+            ("./docs/a.txt", &b"hello tar"[..]),
+            ("b.bin", b"0123456789"),
+        ] {
+            let mut h = tar::Header::new_gnu();
+            h.set_size(data.len() as u64);
+            h.set_mode(0o640);
+            h.set_mtime(1_500_000_000);
+            h.set_cksum();
+            b.append_data(&mut h, path, data).unwrap();
+        }
+        b.into_inner().unwrap()
+    }
+
+    fn setup() -> (tempfile::TempDir, PackedBackend) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("packs");
+        fs::create_dir_all(root.join("2019")).unwrap();
+        fs::write(root.join("2019/plain.txt"), b"plain").unwrap();
+        fs::write(root.join("2019/old.tar"), tar_bytes()).unwrap();
+        fs::write(
+            root.join("older.tar.zst"),
+            zstd::encode_all(&tar_bytes()[..], 3).unwrap(),
+        )
+        .unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(root.join("misc.zip")).unwrap());
+        let opts = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("x/y/z.txt", opts).unwrap();
+        zip.write_all(b"zipped data").unwrap();
+        zip.finish().unwrap();
+
+        let b = PackedBackend::new("packs", root, dir.path().join("staging"), None).unwrap();
+        (dir, b)
+    }
+
+    #[test]
+    fn browses_and_reads_members() {
+        let (_dir, b) = setup();
+        let mut names = b.list_dir(Path::new("")).unwrap();
+        names.sort();
+        assert_eq!(names, vec!["2019", "misc.zip", "older.tar.zst"]);
+        assert!(b.metadata(Path::new("2019/old.tar")).unwrap().is_dir);
+        assert_eq!(
+            b.list_dir(Path::new("2019/old.tar/docs")).unwrap(),
+            vec!["a.txt"]
+        );
+        let m = b.metadata(Path::new("2019/old.tar/docs/a.txt")).unwrap();
+        assert_eq!((m.size, m.mode & 0o777), (9, 0o440));
+        assert_eq!(m.mtime, UNIX_EPOCH + Duration::from_secs(1_500_000_000));
+
+        assert_eq!(
+            b.read_at(Path::new("2019/old.tar/b.bin"), 3, 4).unwrap(),
+            b"3456"
+        );
+        assert_eq!(
+            b.read_at(Path::new("older.tar.zst/docs/a.txt"), 6, 99)
+                .unwrap(),
+            b"tar"
+        );
+        assert_eq!(
+            b.read_at(Path::new("misc.zip/x/y/z.txt"), 0, 6).unwrap(),
+            b"zipped"
+        );
+        assert!(b.metadata(Path::new("misc.zip/x")).unwrap().is_dir);
+        assert_eq!(
+            b.read_at(Path::new("2019/plain.txt"), 0, 99).unwrap(),
+            b"plain"
+        );
+        assert!(!b.exists(Path::new("misc.zip/nope")).unwrap());
+    }
+
+    #[test]
+    fn walks_members_as_files() {
+        let (_dir, b) = setup();
+        let mut files: Vec<PathBuf> = b.walk_files().map(Result::unwrap).collect();
+        files.sort();
+        let want: Vec<PathBuf> = [
+            "2019/old.tar/b.bin",
+            "2019/old.tar/docs/a.txt",
+            "2019/plain.txt",
+            "misc.zip/x/y/z.txt",
+            "older.tar.zst/b.bin",
+            "older.tar.zst/docs/a.txt",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(files, want);
+    }
+
+    #[test]
+    fn refuses_mutations() {
+        let (_dir, b) = setup();
+        let p = Path::new("2019/old.tar/b.bin");
+        let erofs = |r: Result<()>| r.unwrap_err().to_errno() == libc::EROFS;
+        assert!(erofs(b.write_at(p, 0, b"x").map(drop)));
+        assert!(erofs(b.remove(p)));
+        assert!(erofs(b.create_file(Path::new("new"))));
+        assert!(erofs(b.rename(p, Path::new("y"))));
+        assert_eq!(b.statvfs().unwrap().free_bytes, 0);
+        assert_eq!(b.read_at(p, 0, 99).unwrap(), b"0123456789");
+    }
+}
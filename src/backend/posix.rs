@@ -1,14 +1,40 @@
 //! POSIX filesystem backend.
 //!
-//! Uses `std::os::unix::fs::FileExt` for positional IO (`pread`/`pwrite`),
-//! `File::set_len` for truncate, and `libc::statvfs` for capacity stats.
-
-use std::fs::{self, File, OpenOptions};
-use std::os::unix::fs::{FileExt, MetadataExt, PermissionsExt};
+//! Holds the root directory open and issues every per-file syscall
+//! relative to that fd (`openat`/`fstatat`/`getdents`/`renameat`/...), so
+//! there is no re-walk of the root's absolute path on each call and a root
+//! renamed or over-mounted under us keeps pointing at the same directory.
+//! Positional IO is `pread`/`pwrite` via `std::os::unix::fs::FileExt`,
+//! and capacity comes from `fstatvfs` on the root fd. With
+//! `with_direct_io`, reads of large files bypass the page cache
+//! (`O_DIRECT` on Linux, `F_NOCACHE` on macOS).
+//!
+//! The calls block; callers are FUSE worker threads and the tierer's own
+//! thread, never an async executor.
+//!
+//! That fd also outlives the disk: after `umount -l` it keeps writing to a
+//! detached filesystem, and the root's path now names the bare mountpoint
+//! on the parent filesystem, or nothing. So the backend remembers the
+//! root's device and inode and re-checks the path at most once a second.
+//! While it doesn't match, the root is lost: mutations fail with
+//! `FsError::Offline` (EIO) instead of landing on the wrong filesystem, a
+//! lookup that fails with ENOENT reports the same instead of a missing
+//! file, `available()` is false, and a `root-lost` event goes to the hooks.
+//! Remounting the same disk brings the backend back.
+
+use std::fs::File;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use rustix::fs::{AtFlags, Mode, OFlags, Stat};
+use tracing::{error, info};
 
 use crate::error::{FsError, Result};
+use crate::hooks::{emit, Event};
 
 use super::{Backend, BackendStats, FileMetadata};
 
@@ -16,9 +42,24 @@ use super::{Backend, BackendStats, FileMetadata};
 pub struct PosixBackend {
     id: String,
     root: PathBuf,
+    /// `root`, opened once; every path is resolved relative to it.
+    dir: OwnedFd,
+    /// Device and inode of `dir`, to tell whether `root` still names it.
+    root_id: (u64, u64),
+    /// `root` no longer names `dir`.
+    lost: AtomicBool,
+    root_checked: Mutex<Instant>,
     cost_per_gb_month: Option<f64>,
+    direct_io_above: Option<u64>,
 }
 
+/// `O_DIRECT` wants offsets, lengths and buffers aligned to the logical
+/// block size; 4 KiB covers every common device.
+const DIRECT_ALIGN: u64 = 4096;
+
+/// How stale the answer to "is the root still there" may be.
+const ROOT_CHECK_EVERY: Duration = Duration::from_secs(1);
+
 impl PosixBackend {
     /// Create a new backend rooted at `root`. The directory must exist.
     pub fn new(id: impl Into<String>, root: impl Into<PathBuf>) -> Result<Self> {
@@ -39,17 +80,136 @@ impl PosixBackend {
                 root.display()
             )));
         }
+        let dir = rustix::fs::open(
+            &root,
+            OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC,
+            Mode::empty(),
+        )
+        .map_err(|e| FsError::Storage(format!("open backend root {}: {e}", root.display())))?;
+        let st = rustix::fs::fstat(&dir)
+            .map_err(|e| FsError::Storage(format!("stat backend root {}: {e}", root.display())))?;
         Ok(Self {
             id,
             root,
+            dir,
+            root_id: dev_ino(&st),
+            lost: AtomicBool::new(false),
+            root_checked: Mutex::new(Instant::now()),
             cost_per_gb_month,
+            direct_io_above: None,
         })
     }
 
-    fn full(&self, rel: &Path) -> PathBuf {
-        // Strip leading "/" so join treats `rel` as relative.
-        let rel = rel.strip_prefix("/").unwrap_or(rel);
-        self.root.join(rel)
+    /// Read files of at least `above` bytes without going through the page
+    /// cache, so streaming a huge cold file doesn't evict the hot set.
+    pub fn with_direct_io(mut self, above: u64) -> Self {
+        self.direct_io_above = Some(above);
+        self
+    }
+
+    /// Whether `root` still names the directory opened at startup; see the
+    /// module docs. Answers from the last check unless it is older than
+    /// `ROOT_CHECK_EVERY` or `force`.
+    fn root_present(&self, force: bool) -> bool {
+        {
+            let mut checked = self.root_checked.lock();
+            if !force && checked.elapsed() < ROOT_CHECK_EVERY {
+                return !self.lost.load(Ordering::Acquire);
+            }
+            *checked = Instant::now();
+        }
+        let present = rustix::fs::stat(&self.root).is_ok_and(|st| dev_ino(&st) == self.root_id);
+        let was_lost = self.lost.swap(!present, Ordering::AcqRel);
+        if !present && !was_lost {
+            error!(
+                "backend {}: root {} is gone or was replaced (disk unmounted?); refusing writes",
+                self.id,
+                self.root.display()
+            );
+            emit(Event::RootLost {
+                backend: self.id.clone(),
+                root: self.root.clone(),
+            });
+        } else if present && was_lost {
+            info!("backend {}: root {} is back", self.id, self.root.display());
+        }
+        present
+    }
+
+    fn root_lost(&self) -> FsError {
+        FsError::Offline(format!("{}: root {} is gone", self.id, self.root.display()))
+    }
+
+    /// Refuse a mutation while the root is lost.
+    fn writable(&self) -> Result<()> {
+        if self.root_present(false) {
+            Ok(())
+        } else {
+            Err(self.root_lost())
+        }
+    }
+
+    /// A lookup that found nothing because the whole root is gone.
+    fn missing(&self, e: FsError) -> FsError {
+        match e {
+            FsError::NotFound(_) if !self.root_present(false) => self.root_lost(),
+            e => e,
+        }
+    }
+
+    /// `rel` under the root. Paths that could escape it are refused
+    /// (`super::sanitize_rel`).
+    fn full(&self, rel: &Path) -> Result<PathBuf> {
+        Ok(self.root.join(super::sanitize_rel(rel)?))
+    }
+
+    /// `rel` as a path relative to `self.dir`: sanitized, `.` for the root.
+    fn at<'a>(&self, rel: &'a Path) -> Result<&'a Path> {
+        let rel = super::sanitize_rel(rel)?;
+        Ok(if rel.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            rel
+        })
+    }
+
+    /// `openat(2)` of `rel`; new files get `0666 & ~umask` like `File::create`.
+    fn open(&self, rel: &Path, flags: OFlags) -> Result<File> {
+        if flags.intersects(OFlags::WRONLY | OFlags::RDWR | OFlags::CREATE) {
+            self.writable()?;
+        }
+        let fd = rustix::fs::openat(
+            &self.dir,
+            self.at(rel)?,
+            flags | OFlags::CLOEXEC,
+            Mode::from_bits_truncate(0o666),
+        )
+        .map_err(|e| self.missing(errno(rel)(e)))?;
+        Ok(File::from(fd))
+    }
+
+    /// `mkdirat(2)` of `rel` and any missing parents, like
+    /// `fs::create_dir_all`.
+    fn mkdirs(&self, rel: &Path) -> Result<()> {
+        self.writable()?;
+        let mut at = PathBuf::new();
+        for c in rel.components() {
+            at.push(c);
+            match rustix::fs::mkdirat(&self.dir, &at, Mode::from_bits_truncate(0o777)) {
+                Ok(()) => {}
+                Err(rustix::io::Errno::EXIST)
+                    if rustix::fs::statat(&self.dir, &at, AtFlags::empty())
+                        .is_ok_and(|st| is_dir(&st)) => {}
+                Err(e) => return Err(errno(rel)(e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// `fstatat(2)` of `rel`, not following a final symlink.
+    fn stat(&self, rel: &Path) -> Result<Stat> {
+        rustix::fs::statat(&self.dir, self.at(rel)?, AtFlags::SYMLINK_NOFOLLOW)
+            .map_err(|e| self.missing(errno(rel)(e)))
     }
 }
 
@@ -63,35 +223,37 @@ impl Backend for PosixBackend {
     }
 
     fn resolve(&self, path: &Path) -> PathBuf {
-        self.full(path)
+        // No error channel here: an unsafe path becomes one every syscall
+        // rejects with EINVAL.
+        self.full(path).unwrap_or_else(|_| self.root.join("\0"))
     }
 
     fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
-        let f = File::open(self.full(path))?;
+        let f = self.open(path, OFlags::RDONLY)?;
+        let large = |t| f.metadata().is_ok_and(|m| m.len() >= t);
+        if self.direct_io_above.is_some_and(large) && set_uncached(&f) {
+            return read_direct(&f, offset, size).map_err(io_err(path));
+        }
         let mut buf = vec![0u8; size as usize];
-        let n = f.read_at(&mut buf, offset)?;
+        let n = f.read_at(&mut buf, offset).map_err(io_err(path))?;
         buf.truncate(n);
         Ok(buf)
     }
 
     fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
-        let f = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(self.full(path))?;
-        let n = f.write_at(data, offset)?;
+        let f = self.open(path, OFlags::WRONLY | OFlags::CREATE)?;
+        let n = f.write_at(data, offset).map_err(io_err(path))?;
         Ok(n as u32)
     }
 
     fn truncate(&self, path: &Path, size: u64) -> Result<()> {
-        let f = OpenOptions::new().write(true).open(self.full(path))?;
-        f.set_len(size)?;
+        let f = self.open(path, OFlags::WRONLY)?;
+        f.set_len(size).map_err(io_err(path))?;
         Ok(())
     }
 
     fn fsync(&self, path: &Path) -> Result<()> {
-        let f = OpenOptions::new().write(true).open(self.full(path))?;
+        let f = self.open(path, OFlags::WRONLY)?;
         // On macOS, fsync only flushes to the drive's internal cache.
         // F_FULLFSYNC actually pushes data to platters/cells. Use it at
         // critical persistence points (the migrate path is the main caller).
@@ -103,80 +265,219 @@ impl Backend for PosixBackend {
             // fall back to a normal sync_all on failure.
             let rc = unsafe { libc::fcntl(f.as_raw_fd(), libc::F_FULLFSYNC) };
             if rc == -1 {
-                f.sync_all()?;
+                f.sync_all().map_err(io_err(path))?;
             }
         }
         #[cfg(not(target_os = "macos"))]
         {
-            f.sync_all()?;
+            f.sync_all().map_err(io_err(path))?;
         }
         Ok(())
     }
 
     fn metadata(&self, path: &Path) -> Result<FileMetadata> {
-        let m = fs::symlink_metadata(self.full(path))?;
+        let st = self.stat(path)?;
         Ok(FileMetadata {
-            size: m.len(),
-            is_dir: m.is_dir(),
-            mode: m.permissions().mode(),
-            atime: ts_from_secs(m.atime()),
-            mtime: ts_from_secs(m.mtime()),
-            ctime: ts_from_secs(m.ctime()),
+            size: st.st_size as u64,
+            is_dir: is_dir(&st),
+            mode: st.st_mode,
+            uid: st.st_uid,
+            gid: st.st_gid,
+            atime: ts_from(st.st_atime, st.st_atime_nsec as i64),
+            mtime: ts_from(st.st_mtime, st.st_mtime_nsec as i64),
+            ctime: ts_from(st.st_ctime, st.st_ctime_nsec as i64),
         })
     }
 
+    fn allocate(&self, path: &Path, offset: u64, len: u64, mode: i32) -> Result<()> {
+        let f = self.open(path, OFlags::WRONLY)?;
+        #[cfg(target_os = "linux")]
+        {
+            let flags = rustix::fs::FallocateFlags::from_bits_retain(mode as u32);
+            rustix::fs::fallocate(&f, flags, offset, len)
+                .map_err(|e| FsError::from_io(e.into(), path.display()))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            // No fallocate(2); plain preallocation just grows the file.
+            if mode != 0 {
+                return Err(FsError::Unsupported(format!(
+                    "fallocate mode {mode:#x} on {}",
+                    self.id
+                )));
+            }
+            let end = offset.saturating_add(len);
+            if f.metadata().map_err(io_err(path))?.len() < end {
+                f.set_len(end).map_err(io_err(path))?;
+            }
+            Ok(())
+        }
+    }
+
+    fn next_data(&self, path: &Path, offset: u64) -> Result<Option<u64>> {
+        let f = self.open(path, OFlags::RDONLY)?;
+        match rustix::fs::seek(&f, rustix::fs::SeekFrom::Data(offset)) {
+            Ok(pos) => Ok(Some(pos)),
+            Err(rustix::io::Errno::NXIO) => Ok(None),
+            Err(e) => Err(FsError::from_io(e.into(), path.display())),
+        }
+    }
+
+    fn next_hole(&self, path: &Path, offset: u64) -> Result<u64> {
+        let f = self.open(path, OFlags::RDONLY)?;
+        rustix::fs::seek(&f, rustix::fs::SeekFrom::Hole(offset))
+            .map_err(|e| FsError::from_io(e.into(), path.display()))
+    }
+
+    fn copy_range(
+        &self,
+        src: &Path,
+        src_off: u64,
+        dst: &Path,
+        dst_off: u64,
+        len: u64,
+    ) -> Result<u64> {
+        #[cfg(target_os = "linux")]
+        {
+            let s = self.open(src, OFlags::RDONLY)?;
+            let d = self.open(dst, OFlags::WRONLY | OFlags::CREATE)?;
+            let (mut off_in, mut off_out) = (src_off, dst_off);
+            let mut done = 0u64;
+            while done < len {
+                let want = (len - done).min(isize::MAX as u64) as usize;
+                let r = rustix::fs::copy_file_range(
+                    &s,
+                    Some(&mut off_in),
+                    &d,
+                    Some(&mut off_out),
+                    want,
+                );
+                match r {
+                    Ok(0) => return Ok(done),
+                    Ok(n) => done += n as u64,
+                    // Filesystems without in-kernel copy: stream the rest.
+                    Err(e) if done == 0
+                        && matches!(
+                            e,
+                            rustix::io::Errno::XDEV
+                                | rustix::io::Errno::NOSYS
+                                | rustix::io::Errno::INVAL
+                                | rustix::io::Errno::OPNOTSUPP
+                        ) =>
+                    {
+                        break;
+                    }
+                    Err(e) => return Err(FsError::from_io(e.into(), src.display())),
+                }
+            }
+            if done == len {
+                return Ok(done);
+            }
+        }
+        super::copy_between(self, src, src_off, self, dst, dst_off, len)
+    }
+
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        if let Some(parent) = super::sanitize_rel(dst)?.parent() {
+            self.mkdirs(parent)?;
+        }
+        // Linux: try a reflink first (btrfs/XFS). macOS `fs::copy` already
+        // clones on APFS via fclonefileat.
+        #[cfg(target_os = "linux")]
+        {
+            let s = self.open(src, OFlags::RDONLY)?;
+            let d = self.open(dst, OFlags::WRONLY | OFlags::CREATE | OFlags::TRUNC)?;
+            if rustix::fs::ioctl_ficlone(&d, &s).is_ok() {
+                return Ok(());
+            }
+            let len = s.metadata().map_err(io_err(src))?.len();
+            self.copy_range(src, 0, dst, 0, len)?;
+            Ok(())
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            std::fs::copy(self.full(src)?, self.full(dst)?).map_err(io_err(src))?;
+            Ok(())
+        }
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        let st = self.stat(path)?;
+        let dir = is_dir(&st);
+        if super::mode_permits(st.st_mode, dir, st.st_uid, st.st_gid, uid, gid, mask) {
+            Ok(())
+        } else {
+            Err(FsError::PermissionDenied(path.display().to_string()))
+        }
+    }
+
     fn exists(&self, path: &Path) -> Result<bool> {
-        Ok(self.full(path).exists())
+        // Follows symlinks, like `Path::exists`.
+        match rustix::fs::statat(&self.dir, self.at(path)?, AtFlags::empty()) {
+            Ok(_) => Ok(true),
+            Err(_) if !self.root_present(false) => Err(self.root_lost()),
+            Err(_) => Ok(false),
+        }
     }
 
     fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        let fd = self.open(path, OFlags::RDONLY | OFlags::DIRECTORY)?;
         let mut out = Vec::new();
-        for entry in fs::read_dir(self.full(path))? {
-            let entry = entry?;
-            if let Some(name) = entry.file_name().to_str() {
-                out.push(name.to_string());
+        for entry in rustix::fs::Dir::read_from(&fd).map_err(errno(path))? {
+            let entry = entry.map_err(errno(path))?;
+            match entry.file_name().to_str() {
+                Ok("." | "..") | Err(_) => {}
+                Ok(name) => out.push(name.to_string()),
             }
         }
         Ok(out)
     }
 
     fn create_dir(&self, path: &Path) -> Result<()> {
-        fs::create_dir_all(self.full(path))?;
-        Ok(())
+        self.mkdirs(super::sanitize_rel(path)?)
     }
 
     fn create_file(&self, path: &Path) -> Result<()> {
-        let full = self.full(path);
-        if let Some(parent) = full.parent() {
-            fs::create_dir_all(parent)?;
+        if let Some(parent) = super::sanitize_rel(path)?.parent() {
+            self.mkdirs(parent)?;
         }
-        OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&full)?;
+        self.open(path, OFlags::WRONLY | OFlags::CREATE | OFlags::EXCL)?;
         Ok(())
     }
 
     fn remove(&self, path: &Path) -> Result<()> {
-        let full = self.full(path);
-        let m = fs::symlink_metadata(&full)?;
-        if m.is_dir() {
-            fs::remove_dir(&full)?;
+        self.writable()?;
+        let flags = if is_dir(&self.stat(path)?) {
+            AtFlags::REMOVEDIR
         } else {
-            fs::remove_file(&full)?;
-        }
-        Ok(())
+            AtFlags::empty()
+        };
+        rustix::fs::unlinkat(&self.dir, self.at(path)?, flags).map_err(errno(path))
     }
 
     fn rename(&self, from: &Path, to: &Path) -> Result<()> {
-        fs::rename(self.full(from), self.full(to))?;
-        Ok(())
+        self.writable()?;
+        rustix::fs::renameat(&self.dir, self.at(from)?, &self.dir, self.at(to)?)
+            .map_err(errno(from))
     }
 
     fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
-        let perms = fs::Permissions::from_mode(mode);
-        fs::set_permissions(self.full(path), perms)?;
-        Ok(())
+        self.writable()?;
+        let mode = Mode::from_bits_truncate(mode);
+        rustix::fs::chmodat(&self.dir, self.at(path)?, mode, AtFlags::empty()).map_err(errno(path))
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        use rustix::fs::{Gid, Uid};
+        self.writable()?;
+        rustix::fs::chownat(
+            &self.dir,
+            self.at(path)?,
+            Some(Uid::from_raw(uid)),
+            Some(Gid::from_raw(gid)),
+            AtFlags::empty(),
+        )
+        .map_err(errno(path))
     }
 
     fn set_times(
@@ -186,7 +487,7 @@ impl Backend for PosixBackend {
         mtime: Option<SystemTime>,
     ) -> Result<()> {
         // Use rustix to call utimensat with proper UTIME_OMIT for None values.
-        use rustix::fs::{utimensat, AtFlags, Timestamps};
+        use rustix::fs::{utimensat, Timestamps};
 
         let to_ts = |opt: Option<SystemTime>| -> rustix::fs::Timespec {
             match opt {
@@ -208,24 +509,24 @@ impl Backend for PosixBackend {
             last_access: to_ts(atime),
             last_modification: to_ts(mtime),
         };
-        utimensat(
-            rustix::fs::CWD,
-            self.full(path).as_os_str(),
-            &ts,
-            AtFlags::empty(),
-        )
-        .map_err(|e| FsError::Io(std::io::Error::from(e)))?;
-        Ok(())
+        self.writable()?;
+        utimensat(&self.dir, self.at(path)?, &ts, AtFlags::empty()).map_err(errno(path))
     }
 
     fn cost_per_gb_month(&self) -> Option<f64> {
         self.cost_per_gb_month
     }
 
+    fn direct_io_above(&self) -> Option<u64> {
+        self.direct_io_above
+    }
+
+    fn available(&self) -> bool {
+        self.root_present(false)
+    }
+
     fn statvfs(&self) -> Result<BackendStats> {
-        use rustix::fs::statvfs;
-        let s = statvfs(self.root.as_os_str())
-            .map_err(|e| FsError::Io(std::io::Error::from(e)))?;
+        let s = rustix::fs::fstatvfs(&self.dir).map_err(|e| FsError::Io(e.into()))?;
         let block_size = s.f_frsize as u64;
         let total = s.f_blocks as u64 * block_size;
         let free = s.f_bavail as u64 * block_size;
@@ -237,17 +538,79 @@ impl Backend for PosixBackend {
     }
 }
 
-fn ts_from_secs(secs: i64) -> SystemTime {
-    if secs >= 0 {
+/// Classify an `io::Error` with the backend-relative path as context, so
+/// FUSE gets a specific errno (EEXIST, ENOTEMPTY, ENOSPC, ...) back.
+fn io_err(path: &Path) -> impl FnOnce(std::io::Error) -> FsError + '_ {
+    move |e| FsError::from_io(e, path.display())
+}
+
+/// `io_err` for the `rustix` calls.
+fn errno(path: &Path) -> impl FnOnce(rustix::io::Errno) -> FsError + '_ {
+    move |e| FsError::from_io(e.into(), path.display())
+}
+
+/// Turn off page caching on `f`. False if the filesystem won't (tmpfs
+/// before 6.6, some FUSE mounts); the read then stays buffered.
+fn set_uncached(f: &File) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        rustix::fs::fcntl_getfl(f)
+            .and_then(|fl| rustix::fs::fcntl_setfl(f, fl | OFlags::DIRECT))
+            .is_ok()
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::fd::AsRawFd;
+        // SAFETY: f is a valid open file; F_NOCACHE takes an int flag.
+        unsafe { libc::fcntl(f.as_raw_fd(), libc::F_NOCACHE, 1) != -1 }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = f;
+        false
+    }
+}
+
+/// `pread` of `size` bytes at `offset` for an uncached file: the range is
+/// widened to `DIRECT_ALIGN` boundaries, read into an aligned buffer, and
+/// cut back out.
+fn read_direct(f: &File, offset: u64, size: u32) -> std::io::Result<Vec<u8>> {
+    let start = offset - offset % DIRECT_ALIGN;
+    let end = (offset + size as u64).next_multiple_of(DIRECT_ALIGN);
+    let len = (end - start) as usize;
+    let mut raw = vec![0u8; len + DIRECT_ALIGN as usize];
+    let pad = raw.as_ptr().align_offset(DIRECT_ALIGN as usize);
+    let buf = &mut raw[pad..pad + len];
+    let n = f.read_at(buf, start)?;
+    let skip = ((offset - start) as usize).min(n);
+    Ok(buf[skip..n.min(skip + size as usize)].to_vec())
+}
+
+fn is_dir(st: &Stat) -> bool {
+    st.st_mode & libc::S_IFMT == libc::S_IFDIR
+}
+
+#[allow(clippy::unnecessary_cast)] // not u64 on every target
+fn dev_ino(st: &Stat) -> (u64, u64) {
+    (st.st_dev as u64, st.st_ino as u64)
+}
+
+/// `st_*time` + `st_*time_nsec` → `SystemTime`, keeping nanoseconds so
+/// `make` and `rsync --update` see the same mtime the disk has.
+pub(crate) fn ts_from(secs: i64, nsec: i64) -> SystemTime {
+    let base = if secs >= 0 {
         UNIX_EPOCH + Duration::from_secs(secs as u64)
     } else {
         UNIX_EPOCH - Duration::from_secs((-secs) as u64)
-    }
+    };
+    base + Duration::from_nanos(nsec.clamp(0, 999_999_999) as u64)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
     use tempfile::TempDir;
 
     fn make_backend() -> (TempDir, PosixBackend) {
@@ -268,6 +631,33 @@ mod tests {
         assert_eq!(got, data);
     }
 
+    #[test]
+    fn lost_root_refuses_writes_until_it_is_back() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("disk");
+        fs::create_dir(&root).unwrap();
+        let b = PosixBackend::new("lost", &root).unwrap();
+        b.write_at(Path::new("a"), 0, b"x").unwrap();
+
+        // The disk goes away and an empty mountpoint is left behind.
+        fs::rename(&root, dir.path().join("away")).unwrap();
+        fs::create_dir(&root).unwrap();
+        assert!(!b.root_present(true));
+        assert!(!b.available());
+        let err = b.write_at(Path::new("b"), 0, b"x").unwrap_err();
+        assert!(matches!(err, FsError::Offline(_)), "{err}");
+        let offline = |r: Result<_>| matches!(r, Err(FsError::Offline(_)));
+        assert!(offline(b.create_dir(Path::new("d"))));
+        assert!(offline(b.metadata(Path::new("nope")).map(drop)));
+        assert!(!root.join("b").exists() && !root.join("d").exists());
+
+        fs::remove_dir(&root).unwrap();
+        fs::rename(dir.path().join("away"), &root).unwrap();
+        assert!(b.root_present(true));
+        b.write_at(Path::new("b"), 0, b"x").unwrap();
+        assert!(root.join("b").exists());
+    }
+
     #[test]
     fn write_at_offset_does_not_truncate() {
         let (_dir, b) = make_backend();
@@ -329,4 +719,159 @@ mod tests {
         assert!(!b.exists(Path::new("old.bin")).unwrap());
         assert!(b.exists(Path::new("new.bin")).unwrap());
     }
+
+    #[test]
+    fn check_access_uses_other_bits_for_strangers() {
+        let (_dir, b) = make_backend();
+        let p = Path::new("secret.txt");
+        b.create_file(p).unwrap();
+        b.set_permissions(p, 0o640).unwrap();
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        b.check_access(p, uid, gid, libc::R_OK).unwrap();
+        let err = b.check_access(p, 54321, 54321, libc::R_OK).unwrap_err();
+        assert!(matches!(err, FsError::PermissionDenied(_)));
+        b.set_permissions(p, 0o644).unwrap();
+        b.check_access(p, 54321, 54321, libc::R_OK).unwrap();
+        assert!(b.check_access(p, 54321, 54321, libc::W_OK).is_err());
+    }
+
+    #[test]
+    fn metadata_reports_owner_and_subsecond_mtime() {
+        let (_dir, b) = make_backend();
+        let p = Path::new("m.bin");
+        b.create_file(p).unwrap();
+        let t = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        b.set_times(p, Some(t), Some(t)).unwrap();
+        let m = b.metadata(p).unwrap();
+        assert_eq!(m.mtime, t);
+        assert_eq!(m.uid, unsafe { libc::getuid() });
+        assert_eq!(m.gid, unsafe { libc::getgid() });
+    }
+
+    #[test]
+    fn copy_range_and_copy_within_backend() {
+        let (_dir, b) = make_backend();
+        b.write_at(Path::new("src.bin"), 0, b"0123456789").unwrap();
+        b.write_at(Path::new("dst.bin"), 0, b"abcdefghij").unwrap();
+        let n = b
+            .copy_range(Path::new("src.bin"), 2, Path::new("dst.bin"), 4, 3)
+            .unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(b.read_at(Path::new("dst.bin"), 0, 10).unwrap(), b"abcd234hij");
+        // Reading past EOF copies short.
+        let n = b
+            .copy_range(Path::new("src.bin"), 8, Path::new("dst.bin"), 0, 100)
+            .unwrap();
+        assert_eq!(n, 2);
+
+        b.copy(Path::new("src.bin"), Path::new("sub/clone.bin")).unwrap();
+        assert_eq!(
+            b.read_at(Path::new("sub/clone.bin"), 0, 64).unwrap(),
+            b"0123456789"
+        );
+    }
+
+    #[test]
+    fn allocate_grows_and_punches_holes() {
+        let (_dir, b) = make_backend();
+        let p = Path::new("pre.bin");
+        b.write_at(p, 0, &[7u8; 8192]).unwrap();
+        b.allocate(p, 0, 1 << 20, 0).unwrap();
+        assert_eq!(b.metadata(p).unwrap().size, 1 << 20);
+
+        #[cfg(target_os = "linux")]
+        {
+            let punch = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+            match b.allocate(p, 0, 4096, punch) {
+                Ok(()) => {
+                    assert_eq!(b.read_at(p, 0, 4096).unwrap(), vec![0u8; 4096]);
+                    assert_eq!(b.read_at(p, 4096, 4).unwrap(), vec![7u8; 4]);
+                    assert_eq!(b.metadata(p).unwrap().size, 1 << 20);
+                }
+                // tmpfs on old kernels / overlayfs may not punch.
+                Err(e) => assert_eq!(e.to_errno(), libc::EOPNOTSUPP),
+            }
+        }
+    }
+
+    #[test]
+    fn copy_sparse_keeps_holes_and_data() {
+        let (_dir, a) = make_backend();
+        let (_dir2, b) = make_backend();
+        let p = Path::new("vm.img");
+        let size = 8u64 << 20;
+        a.write_at(p, 0, b"head").unwrap();
+        a.write_at(p, size - 4, b"tail").unwrap();
+        assert_eq!(a.next_data(p, 0).unwrap(), Some(0));
+        assert!(a.next_hole(p, 0).unwrap() <= size);
+
+        assert_eq!(super::super::copy_sparse(&a, p, &b, p).unwrap(), size);
+        assert_eq!(b.metadata(p).unwrap().size, size);
+        assert_eq!(b.read_at(p, 0, 4).unwrap(), b"head");
+        assert_eq!(b.read_at(p, size - 4, 4).unwrap(), b"tail");
+        assert_eq!(b.read_at(p, 1 << 20, 4).unwrap(), vec![0u8; 4]);
+        // The copy is no denser than the zero-skipping writes allow.
+        let blocks = fs::metadata(b.resolve(p)).unwrap().blocks();
+        assert!(blocks * 512 < size, "destination was inflated");
+    }
+
+    #[test]
+    fn paths_cannot_escape_the_root() {
+        let outer = TempDir::new().unwrap();
+        let root = outer.path().join("root");
+        fs::create_dir(&root).unwrap();
+        let b = PosixBackend::new("test", &root).unwrap();
+        fs::write(outer.path().join("secret"), b"x").unwrap();
+        for bad in ["../secret", "a/../../secret", "a\0b"] {
+            let p = Path::new(bad);
+            for err in [
+                b.read_at(p, 0, 1).unwrap_err(),
+                b.write_at(p, 0, b"y").unwrap_err(),
+                b.metadata(p).unwrap_err(),
+                b.remove(p).unwrap_err(),
+                b.rename(Path::new("x"), p).unwrap_err(),
+            ] {
+                assert_eq!(err.to_errno(), libc::EINVAL, "{bad:?}");
+            }
+            assert!(b.exists(p).is_err());
+        }
+        assert_eq!(fs::read(outer.path().join("secret")).unwrap(), b"x");
+        // One leading `/` is the usual logical-path form and stays fine.
+        b.write_at(Path::new("/ok"), 0, b"y").unwrap();
+        assert!(root.join("ok").exists());
+    }
+
+    #[test]
+    fn calls_are_relative_to_the_opened_root() {
+        let outer = TempDir::new().unwrap();
+        let root = outer.path().join("root");
+        fs::create_dir(&root).unwrap();
+        let b = PosixBackend::new("test", &root).unwrap();
+        b.write_at(Path::new("a"), 0, b"data").unwrap();
+        fs::rename(&root, outer.path().join("moved")).unwrap();
+        assert_eq!(b.read_at(Path::new("a"), 0, 4).unwrap(), b"data");
+        b.create_file(Path::new("b")).unwrap();
+        b.create_dir(Path::new("c/d")).unwrap();
+        assert!(b.metadata(Path::new("c")).unwrap().is_dir);
+        let mut names = b.list_dir(Path::new("")).unwrap();
+        names.sort();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert!(!root.exists());
+    }
+
+    #[test]
+    fn direct_io_reads_unaligned_ranges() {
+        let dir = TempDir::new().unwrap();
+        let b = PosixBackend::new("test", dir.path())
+            .unwrap()
+            .with_direct_io(1);
+        let p = Path::new("big");
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        b.write_at(p, 0, &data).unwrap();
+        assert_eq!(b.direct_io_above(), Some(1));
+        assert_eq!(b.read_at(p, 4095, 3).unwrap(), &data[4095..4098]);
+        assert_eq!(b.read_at(p, 100, 19_000).unwrap(), &data[100..19_100]);
+        assert_eq!(b.read_at(p, 19_990, 4096).unwrap(), &data[19_990..]);
+        assert!(b.read_at(p, 30_000, 10).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,565 @@
+//! `ReplicatedBackend` — mirror one backend's writes onto replicas.
+//!
+//! Meant for the slow tier, where a dead HDD would otherwise take the only
+//! copy of archived data with it. Every mutating call goes to the primary
+//! first and its result is what the caller sees; the same call is then
+//! applied to each replica, inline (`Sync`) or from a background thread
+//! (`Async`: writes return at primary latency, but a crash can lose the
+//! unapplied tail of the queue). Replica failures are logged, not surfaced.
+//! A replica that turns out to be missing the file gets a full copy from
+//! the primary instead.
+//!
+//! Reads go to the primary and fall back to the replicas in order when it
+//! fails with a device-level error (`EIO`, timeout), so a failing primary
+//! keeps serving. `root`/`resolve` are the primary's: bytes written there
+//! directly (kernel copies, zstd streams) reach the replicas only through
+//! `sync_replicas`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::SystemTime;
+
+use crossbeam_channel::{bounded, unbounded, Sender};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use super::{copy_between, Backend, BackendStats, FileMetadata};
+use crate::error::{FsError, Result};
+
+/// When replicas see a write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicationMode {
+    /// Before the call returns.
+    #[default]
+    Sync,
+    /// From a background queue, in order.
+    Async,
+}
+
+/// One mutating call, replayed on each replica.
+enum Op {
+    Write(PathBuf, u64, Vec<u8>),
+    Truncate(PathBuf, u64),
+    Fsync(PathBuf),
+    Allocate(PathBuf, u64, u64, i32),
+    CreateDir(PathBuf),
+    CreateFile(PathBuf),
+    Remove(PathBuf),
+    Rename(PathBuf, PathBuf),
+    Chmod(PathBuf, u32),
+    Chown(PathBuf, u32, u32),
+    Times(PathBuf, Option<SystemTime>, Option<SystemTime>),
+    Copy(PathBuf, PathBuf),
+    /// Whole file from the primary.
+    Resync(PathBuf),
+}
+
+impl Op {
+    /// Call name and (first) path, for logs.
+    fn what(&self) -> (&'static str, &Path) {
+        match self {
+            Op::Write(p, ..) => ("write", p),
+            Op::Truncate(p, _) => ("truncate", p),
+            Op::Fsync(p) => ("fsync", p),
+            Op::Allocate(p, ..) => ("fallocate", p),
+            Op::CreateDir(p) => ("mkdir", p),
+            Op::CreateFile(p) => ("create", p),
+            Op::Remove(p) => ("remove", p),
+            Op::Rename(p, _) => ("rename", p),
+            Op::Chmod(p, _) => ("chmod", p),
+            Op::Chown(p, ..) => ("chown", p),
+            Op::Times(p, ..) => ("utimes", p),
+            Op::Copy(p, _) => ("copy", p),
+            Op::Resync(p) => ("resync", p),
+        }
+    }
+
+    /// File the op changes in place; a replica lacking it gets a `Resync`.
+    fn file(&self) -> Option<&Path> {
+        match self {
+            Op::Write(p, ..) | Op::Truncate(p, _) | Op::Fsync(p) | Op::Allocate(p, ..) => Some(p),
+            Op::Chmod(p, _) | Op::Chown(p, ..) | Op::Times(p, ..) => Some(p),
+            Op::Copy(_, dst) => Some(dst),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, primary: &dyn Backend, replica: &dyn Backend) -> Result<()> {
+        match self {
+            Op::Write(p, off, data) => replica.write_at(p, *off, data).map(|_| ()),
+            Op::Truncate(p, size) => replica.truncate(p, *size),
+            Op::Fsync(p) => replica.fsync(p),
+            Op::Allocate(p, off, len, mode) => replica.allocate(p, *off, *len, *mode),
+            Op::CreateDir(p) => replica.create_dir(p),
+            Op::CreateFile(p) => replica.create_file(p),
+            Op::Remove(p) => match replica.remove(p) {
+                Err(FsError::NotFound(_)) => Ok(()),
+                r => r,
+            },
+            Op::Rename(from, to) => replica.rename(from, to),
+            Op::Chmod(p, mode) => replica.set_permissions(p, *mode),
+            Op::Chown(p, uid, gid) => replica.set_owner(p, *uid, *gid),
+            Op::Times(p, atime, mtime) => replica.set_times(p, *atime, *mtime),
+            Op::Copy(src, dst) => replica.copy(src, dst),
+            Op::Resync(p) => resync(primary, replica, p),
+        }
+    }
+}
+
+/// Make `replica`'s copy of `path` match the primary's.
+fn resync(primary: &dyn Backend, replica: &dyn Backend, path: &Path) -> Result<()> {
+    let meta = primary.metadata(path)?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        replica.create_dir(parent)?;
+    }
+    if !replica.exists(path)? {
+        replica.create_file(path)?;
+    }
+    replica.truncate(path, 0)?;
+    copy_between(primary, path, 0, replica, path, 0, meta.size)?;
+    replica.set_permissions(path, meta.mode & 0o7777)?;
+    replica.set_times(path, Some(meta.atime), Some(meta.mtime))
+}
+
+/// Apply `op` to every replica, logging (not returning) failures.
+fn replay(primary: &dyn Backend, replicas: &[Arc<dyn Backend>], op: &Op) {
+    for r in replicas {
+        // Replaying a write onto a file the replica never got would leave
+        // a partial copy; ship the whole file instead. If the primary has
+        // moved on since (async queue behind a rename), replay as-is and
+        // let the later ops catch up.
+        let res = match op.file() {
+            Some(path) if matches!(r.exists(path), Ok(false)) => resync(primary, r.as_ref(), path)
+                .or_else(|e| {
+                    debug!("resync {} to {}: {e}", path.display(), r.id());
+                    op.apply(primary, r.as_ref())
+                }),
+            _ => op.apply(primary, r.as_ref()),
+        };
+        if let Err(e) = res {
+            let (call, path) = op.what();
+            warn!("replicate {call} {} to {}: {e}", path.display(), r.id());
+        }
+    }
+}
+
+/// Errors that say the device is in trouble rather than answering the
+/// question; only these send reads to a replica.
+fn is_device_fault(e: &FsError) -> bool {
+    matches!(
+        e,
+        FsError::Io(_) | FsError::Storage(_) | FsError::TimedOut(_)
+    )
+}
+
+enum Msg {
+    Op(Op),
+    Flush(Sender<()>),
+}
+
+pub struct ReplicatedBackend {
+    primary: Arc<dyn Backend>,
+    replicas: Vec<Arc<dyn Backend>>,
+    /// Background queue in `Async` mode. The worker exits when this drops.
+    queue: Option<Sender<Msg>>,
+}
+
+impl ReplicatedBackend {
+    pub fn new(
+        primary: Arc<dyn Backend>,
+        replicas: Vec<Arc<dyn Backend>>,
+        mode: ReplicationMode,
+    ) -> Arc<Self> {
+        let mut queue = None;
+        if mode == ReplicationMode::Async && !replicas.is_empty() {
+            let (tx, rx) = unbounded::<Msg>();
+            let (p, rs) = (Arc::clone(&primary), replicas.clone());
+            let spawned = thread::Builder::new()
+                .name(format!("rhss-replicate-{}", primary.id()))
+                .spawn(move || {
+                    for msg in rx {
+                        match msg {
+                            Msg::Op(op) => replay(p.as_ref(), &rs, &op),
+                            Msg::Flush(done) => {
+                                let _ = done.send(());
+                            }
+                        }
+                    }
+                });
+            match spawned {
+                Ok(_) => queue = Some(tx),
+                Err(e) => warn!("spawn replication thread: {e}; replicating synchronously"),
+            }
+        }
+        Arc::new(Self {
+            primary,
+            replicas,
+            queue,
+        })
+    }
+
+    /// Block until every queued replica write has been applied. Returns at
+    /// once in `Sync` mode.
+    pub fn flush(&self) {
+        if let Some(q) = &self.queue {
+            let (tx, rx) = bounded(1);
+            if q.send(Msg::Flush(tx)).is_ok() {
+                let _ = rx.recv();
+            }
+        }
+    }
+
+    /// Pass on the primary's result; if it succeeded, hand `replica_op` to
+    /// the replicas.
+    fn mutate<T>(&self, primary_res: Result<T>, replica_op: impl FnOnce() -> Op) -> Result<T> {
+        let out = primary_res?;
+        if self.replicas.is_empty() {
+            return Ok(out);
+        }
+        let op = replica_op();
+        match &self.queue {
+            Some(q) => {
+                if let Err(e) = q.send(Msg::Op(op)) {
+                    // Worker gone (panicked): fall back to inline.
+                    if let Msg::Op(op) = e.into_inner() {
+                        replay(self.primary.as_ref(), &self.replicas, &op);
+                    }
+                }
+            }
+            None => replay(self.primary.as_ref(), &self.replicas, &op),
+        }
+        Ok(out)
+    }
+
+    /// Read from the primary, falling back to each replica on device errors.
+    fn read<T>(&self, what: &str, path: &Path, f: impl Fn(&dyn Backend) -> Result<T>) -> Result<T> {
+        let err = match f(self.primary.as_ref()) {
+            Err(e) if is_device_fault(&e) && !self.replicas.is_empty() => e,
+            res => return res,
+        };
+        // Queued writes must land before a replica can answer for them.
+        self.flush();
+        for r in &self.replicas {
+            match f(r.as_ref()) {
+                Ok(v) => {
+                    warn!(
+                        "{what} {} failed on {} ({err}); served from replica {}",
+                        path.display(),
+                        self.primary.id(),
+                        r.id()
+                    );
+                    return Ok(v);
+                }
+                Err(e) => debug!("{what} {} on replica {}: {e}", path.display(), r.id()),
+            }
+        }
+        Err(err)
+    }
+}
+
+impl Backend for ReplicatedBackend {
+    fn id(&self) -> &str {
+        self.primary.id()
+    }
+
+    fn root(&self) -> &Path {
+        self.primary.root()
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        self.read("read", path, |b| b.read_at(path, offset, size))
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        let res = self.primary.write_at(path, offset, data);
+        // Replicate exactly what the primary accepted.
+        let n = res.as_ref().map(|&n| n as usize).unwrap_or(0);
+        self.mutate(res, || Op::Write(path.into(), offset, data[..n].to_vec()))
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        self.mutate(self.primary.truncate(path, size), || {
+            Op::Truncate(path.into(), size)
+        })
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        self.mutate(self.primary.fsync(path), || Op::Fsync(path.into()))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        self.read("stat", path, |b| b.metadata(path))
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        self.read("exists", path, |b| b.exists(path))
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        self.read("readdir", path, |b| b.list_dir(path))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.mutate(self.primary.create_dir(path), || Op::CreateDir(path.into()))
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        self.mutate(self.primary.create_file(path), || {
+            Op::CreateFile(path.into())
+        })
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.mutate(self.primary.remove(path), || Op::Remove(path.into()))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.mutate(self.primary.rename(from, to), || {
+            Op::Rename(from.into(), to.into())
+        })
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.mutate(self.primary.set_permissions(path, mode), || {
+            Op::Chmod(path.into(), mode)
+        })
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        self.mutate(self.primary.set_owner(path, uid, gid), || {
+            Op::Chown(path.into(), uid, gid)
+        })
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        self.mutate(self.primary.set_times(path, atime, mtime), || {
+            Op::Times(path.into(), atime, mtime)
+        })
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        self.primary.statvfs()
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.primary.resolve(path)
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        self.primary.walk_files()
+    }
+
+    fn copy_range(
+        &self,
+        src: &Path,
+        src_off: u64,
+        dst: &Path,
+        dst_off: u64,
+        len: u64,
+    ) -> Result<u64> {
+        // Replicas may not hold `src` at the same offsets yet; re-copy the
+        // whole destination from the primary instead.
+        self.mutate(
+            self.primary.copy_range(src, src_off, dst, dst_off, len),
+            || Op::Resync(dst.into()),
+        )
+    }
+
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        self.mutate(self.primary.copy(src, dst), || {
+            Op::Copy(src.into(), dst.into())
+        })
+    }
+
+    fn allocate(&self, path: &Path, offset: u64, len: u64, mode: i32) -> Result<()> {
+        self.mutate(self.primary.allocate(path, offset, len, mode), || {
+            Op::Allocate(path.into(), offset, len, mode)
+        })
+    }
+
+    fn next_data(&self, path: &Path, offset: u64) -> Result<Option<u64>> {
+        self.read("seek_data", path, |b| b.next_data(path, offset))
+    }
+
+    fn next_hole(&self, path: &Path, offset: u64) -> Result<u64> {
+        self.read("seek_hole", path, |b| b.next_hole(path, offset))
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        self.read("access", path, |b| b.check_access(path, uid, gid, mask))
+    }
+
+    fn sync_replicas(&self, path: &Path) -> Result<()> {
+        self.mutate(Ok(()), || Op::Resync(path.into()))
+    }
+
+    fn direct_io_above(&self) -> Option<u64> {
+        self.primary.direct_io_above()
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        // Every byte is stored 1 + replicas times.
+        self.primary
+            .cost_per_gb_month()
+            .map(|c| c * (1 + self.replicas.len()) as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use tempfile::TempDir;
+
+    /// Posix backend whose reads fail with `EIO` once `dead` is set.
+    struct Dying {
+        inner: PosixBackend,
+        dead: std::sync::atomic::AtomicBool,
+    }
+
+    impl Dying {
+        fn check(&self) -> Result<()> {
+            if self.dead.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(FsError::Io(std::io::Error::from_raw_os_error(libc::EIO)));
+            }
+            Ok(())
+        }
+    }
+
+    impl Backend for Dying {
+        fn id(&self) -> &str {
+            self.inner.id()
+        }
+        fn root(&self) -> &Path {
+            self.inner.root()
+        }
+        fn read_at(&self, p: &Path, o: u64, s: u32) -> Result<Vec<u8>> {
+            self.check()?;
+            self.inner.read_at(p, o, s)
+        }
+        fn write_at(&self, p: &Path, o: u64, d: &[u8]) -> Result<u32> {
+            self.inner.write_at(p, o, d)
+        }
+        fn truncate(&self, p: &Path, s: u64) -> Result<()> {
+            self.inner.truncate(p, s)
+        }
+        fn fsync(&self, p: &Path) -> Result<()> {
+            self.inner.fsync(p)
+        }
+        fn metadata(&self, p: &Path) -> Result<FileMetadata> {
+            self.check()?;
+            self.inner.metadata(p)
+        }
+        fn exists(&self, p: &Path) -> Result<bool> {
+            self.inner.exists(p)
+        }
+        fn list_dir(&self, p: &Path) -> Result<Vec<String>> {
+            self.inner.list_dir(p)
+        }
+        fn create_dir(&self, p: &Path) -> Result<()> {
+            self.inner.create_dir(p)
+        }
+        fn create_file(&self, p: &Path) -> Result<()> {
+            self.inner.create_file(p)
+        }
+        fn remove(&self, p: &Path) -> Result<()> {
+            self.inner.remove(p)
+        }
+        fn rename(&self, f: &Path, t: &Path) -> Result<()> {
+            self.inner.rename(f, t)
+        }
+        fn set_permissions(&self, p: &Path, m: u32) -> Result<()> {
+            self.inner.set_permissions(p, m)
+        }
+        fn set_times(&self, p: &Path, a: Option<SystemTime>, m: Option<SystemTime>) -> Result<()> {
+            self.inner.set_times(p, a, m)
+        }
+        fn statvfs(&self) -> Result<BackendStats> {
+            self.inner.statvfs()
+        }
+        fn resolve(&self, p: &Path) -> PathBuf {
+            self.inner.resolve(p)
+        }
+    }
+
+    struct Fixture {
+        dirs: [TempDir; 2],
+        primary: Arc<Dying>,
+        b: Arc<ReplicatedBackend>,
+    }
+
+    fn setup(mode: ReplicationMode) -> Fixture {
+        let dirs = [TempDir::new().unwrap(), TempDir::new().unwrap()];
+        let primary = Arc::new(Dying {
+            inner: PosixBackend::new("hdd", dirs[0].path()).unwrap(),
+            dead: Default::default(),
+        });
+        let replica = Arc::new(PosixBackend::new("hdd~1", dirs[1].path()).unwrap());
+        let b = ReplicatedBackend::new(
+            Arc::clone(&primary) as Arc<dyn Backend>,
+            vec![replica as Arc<dyn Backend>],
+            mode,
+        );
+        Fixture { dirs, primary, b }
+    }
+
+    #[test]
+    fn writes_reach_replicas_in_both_modes() {
+        for mode in [ReplicationMode::Sync, ReplicationMode::Async] {
+            let Fixture { dirs, b, .. } = setup(mode);
+            b.create_dir(Path::new("a")).unwrap();
+            b.write_at(Path::new("a/f"), 0, b"hello world").unwrap();
+            b.truncate(Path::new("a/f"), 5).unwrap();
+            b.rename(Path::new("a/f"), Path::new("a/g")).unwrap();
+            b.flush();
+            assert_eq!(std::fs::read(dirs[1].path().join("a/g")).unwrap(), b"hello");
+            assert!(!dirs[1].path().join("a/f").exists());
+
+            b.remove(Path::new("a/g")).unwrap();
+            b.flush();
+            assert!(!dirs[1].path().join("a/g").exists());
+        }
+    }
+
+    #[test]
+    fn replica_missing_the_file_is_resynced() {
+        let Fixture { dirs, b, .. } = setup(ReplicationMode::Sync);
+        std::fs::write(dirs[0].path().join("old"), b"archived").unwrap();
+        b.write_at(Path::new("old"), 0, b"A").unwrap();
+        assert_eq!(
+            std::fs::read(dirs[1].path().join("old")).unwrap(),
+            b"Archived"
+        );
+
+        // Bytes written behind the backend's back.
+        std::fs::write(dirs[0].path().join("z"), b"stream").unwrap();
+        b.sync_replicas(Path::new("z")).unwrap();
+        assert_eq!(std::fs::read(dirs[1].path().join("z")).unwrap(), b"stream");
+    }
+
+    #[test]
+    fn reads_fall_back_to_replica_when_primary_fails() {
+        let Fixture { dirs, primary, b } = setup(ReplicationMode::Async);
+        b.write_at(Path::new("f"), 0, b"data").unwrap();
+
+        // A clean "not found" from the primary is an answer, not a fault.
+        std::fs::write(dirs[1].path().join("gone"), b"x").unwrap();
+        assert!(matches!(
+            b.read_at(Path::new("gone"), 0, 1),
+            Err(FsError::NotFound(_))
+        ));
+
+        primary
+            .dead
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(b.read_at(Path::new("f"), 0, 4).unwrap(), b"data");
+        assert_eq!(b.metadata(Path::new("f")).unwrap().size, 4);
+    }
+}
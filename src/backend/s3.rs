@@ -35,7 +35,7 @@ use std::io::{Read, Write};
 use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use parking_lot::Mutex;
 use s3::bucket::Bucket;
@@ -45,6 +45,7 @@ use tracing::debug;
 
 use crate::error::{FsError, Result};
 
+use super::posix::ts_from;
 use super::{Backend, BackendStats, FileMetadata};
 
 pub struct S3Backend {
@@ -140,10 +141,10 @@ impl S3Backend {
                 File::create(&staged).map_err(FsError::Io)?;
             }
             Ok(resp) => {
-                return Err(FsError::Storage(format!(
-                    "s3 GET {key}: status {}",
-                    resp.status_code()
-                )));
+                return Err(FsError::from_status(
+                    resp.status_code(),
+                    format!("s3 GET {key}"),
+                ));
             }
             Err(e) => return Err(FsError::Storage(format!("s3 GET {key}: {e}"))),
         }
@@ -175,10 +176,10 @@ impl S3Backend {
             .put_object(&key, &buf)
             .map_err(|e| FsError::Storage(format!("s3 PUT {key}: {e}")))?;
         if resp.status_code() != 200 {
-            return Err(FsError::Storage(format!(
-                "s3 PUT {key}: status {}",
-                resp.status_code()
-            )));
+            return Err(FsError::from_status(
+                resp.status_code(),
+                format!("s3 PUT {key}"),
+            ));
         }
         Ok(())
     }
@@ -257,28 +258,37 @@ impl Backend for S3Backend {
                 size: m.len(),
                 is_dir: m.is_dir(),
                 mode: m.permissions().mode(),
-                atime: ts_from_secs(m.atime()),
-                mtime: ts_from_secs(m.mtime()),
-                ctime: ts_from_secs(m.ctime()),
+                uid: m.uid(),
+                gid: m.gid(),
+                atime: ts_from(m.atime(), m.atime_nsec()),
+                mtime: ts_from(m.mtime(), m.mtime_nsec()),
+                ctime: ts_from(m.ctime(), m.ctime_nsec()),
             });
         }
         // Otherwise HEAD the object.
         let key = self.object_key(path);
         match self.bucket.head_object(&key) {
-            Ok((info, 200)) => Ok(FileMetadata {
-                size: info.content_length.unwrap_or(0) as u64,
-                is_dir: false,
-                mode: 0o644,
-                atime: SystemTime::now(),
-                mtime: info
+            Ok((info, 200)) => {
+                // No atime/ctime on S3; report Last-Modified for all three so
+                // the file looks stable across stats.
+                let mtime = info
                     .last_modified
                     .as_deref()
-                    .map(parse_rfc1123)
-                    .unwrap_or(SystemTime::now()),
-                ctime: SystemTime::now(),
-            }),
+                    .and_then(parse_rfc1123)
+                    .unwrap_or(UNIX_EPOCH);
+                Ok(FileMetadata {
+                    size: info.content_length.unwrap_or(0) as u64,
+                    is_dir: false,
+                    mode: 0o644,
+                    uid: unsafe { libc::getuid() },
+                    gid: unsafe { libc::getgid() },
+                    atime: mtime,
+                    mtime,
+                    ctime: mtime,
+                })
+            }
             Ok((_, 404)) => Err(FsError::NotFound(key)),
-            Ok((_, code)) => Err(FsError::Storage(format!("s3 HEAD {key}: status {code}"))),
+            Ok((_, code)) => Err(FsError::from_status(code, format!("s3 HEAD {key}"))),
             Err(e) => Err(FsError::Storage(format!("s3 HEAD {key}: {e}"))),
         }
     }
@@ -291,7 +301,7 @@ impl Backend for S3Backend {
         match self.bucket.head_object(&key) {
             Ok((_, 200)) => Ok(true),
             Ok((_, 404)) => Ok(false),
-            Ok((_, code)) => Err(FsError::Storage(format!("s3 HEAD {key}: status {code}"))),
+            Ok((_, code)) => Err(FsError::from_status(code, format!("s3 HEAD {key}"))),
             Err(e) => Err(FsError::Storage(format!("s3 HEAD {key}: {e}"))),
         }
     }
@@ -354,10 +364,10 @@ impl Backend for S3Backend {
         let key = self.object_key(path);
         match self.bucket.delete_object(&key) {
             Ok(resp) if resp.status_code() < 300 => Ok(()),
-            Ok(resp) => Err(FsError::Storage(format!(
-                "s3 DELETE {key}: status {}",
-                resp.status_code()
-            ))),
+            Ok(resp) => Err(FsError::from_status(
+                resp.status_code(),
+                format!("s3 DELETE {key}"),
+            )),
             Err(e) => Err(FsError::Storage(format!("s3 DELETE {key}: {e}"))),
         }
     }
@@ -369,11 +379,7 @@ impl Backend for S3Backend {
         debug!("S3 COPY {src} → {dst}");
         match self.bucket.copy_object_internal(&src, &dst) {
             Ok(code) if (200..300).contains(&code) => {}
-            Ok(code) => {
-                return Err(FsError::Storage(format!(
-                    "s3 COPY {src}->{dst}: status {code}"
-                )))
-            }
+            Ok(code) => return Err(FsError::from_status(code, format!("s3 COPY {src}->{dst}"))),
             Err(e) => return Err(FsError::Storage(format!("s3 COPY {src}->{dst}: {e}"))),
         }
         let _ = self.bucket.delete_object(&src);
@@ -418,7 +424,7 @@ impl Backend for S3Backend {
             match opt {
                 Some(t) => {
                     let dur = t
-                        .duration_since(std::time::UNIX_EPOCH)
+                        .duration_since(UNIX_EPOCH)
                         .unwrap_or(Duration::ZERO);
                     rustix::fs::Timespec {
                         tv_sec: dur.as_secs() as _,
@@ -453,18 +459,46 @@ impl Backend for S3Backend {
     }
 }
 
-fn ts_from_secs(secs: i64) -> SystemTime {
-    use std::time::UNIX_EPOCH;
-    if secs >= 0 {
-        UNIX_EPOCH + Duration::from_secs(secs as u64)
-    } else {
-        UNIX_EPOCH - Duration::from_secs((-secs) as u64)
+/// Parse an HTTP-date (`Wed, 21 Oct 2015 07:28:00 GMT`), the format S3
+/// uses for `Last-Modified`. `None` on anything else.
+pub(super) fn parse_rfc1123(s: &str) -> Option<SystemTime> {
+    let mut it = s.split_whitespace();
+    let _weekday = it.next()?;
+    let day: i64 = it.next()?.parse().ok()?;
+    let month = match it.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: i64 = it.next()?.parse().ok()?;
+    let mut hms = it.next()?.split(':').map(|p| p.parse::<i64>().ok());
+    let (h, m, sec) = (hms.next()??, hms.next()??, hms.next()??);
+    if it.next()? != "GMT" {
+        return None;
     }
+    let days = crate::civil::days_from_civil(year, month, day);
+    let secs = days * 86_400 + h * 3_600 + m * 60 + sec;
+    Some(ts_from(secs, 0))
 }
 
-/// We don't parse S3 Last-Modified to a real SystemTime in MVP. Future
-/// improvement: pull in `httpdate` or hand-roll a tiny RFC1123 parser.
-/// Returning `now()` is safe — only metadata.mtime is affected.
-fn parse_rfc1123(_s: &str) -> SystemTime {
-    SystemTime::now()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_http_date() {
+        let t = parse_rfc1123("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(t, UNIX_EPOCH + Duration::from_secs(1_445_412_480));
+        assert!(parse_rfc1123("2015-10-21T07:28:00Z").is_none());
+    }
 }
@@ -0,0 +1,547 @@
+//! `ShardedBackend` — spread the files of very large directories over
+//! hash-prefix subdirectories of a wrapped backend (`shard_dirs_above =
+//! 10000` on a tier backend).
+//!
+//! Once a directory holds more than the threshold of entries, it gets a
+//! `.rhss-shards/` subdirectory and every file created in it from then on
+//! lands in `.rhss-shards/<hh>/<name>`, where `<hh>` is the first byte of
+//! a hash of the name. Files that were there before stay where they are;
+//! subdirectories are never moved. Lookups try the shard first and the
+//! directory itself second, listings merge the two, and walks report the
+//! logical paths, so the index and the mount never see the shards.
+//!
+//! The layout describes itself: a directory is sharded exactly when it
+//! has a `.rhss-shards/` entry, so nothing beyond the disk has to agree
+//! with it, and a rescan or a `--shared` view reads it as is. Entry counts
+//! of directories not yet sharded are kept in memory, counted once per
+//! directory and approximate after that. Whether a directory is sharded is
+//! looked up once too: for good when it is, and for `UNSHARDED_TTL` when
+//! it isn't, so reads and writes don't stat `.rhss-shards/` every time.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use parking_lot::Mutex;
+use tracing::info;
+
+use super::{Backend, BackendStats, FileMetadata};
+use crate::error::{FsError, Result};
+
+/// Managed dir inside every sharded directory; hidden from listings and
+/// walks.
+pub const SHARD_DIR: &str = ".rhss-shards";
+
+/// How long a directory found unsharded is taken to stay so. Only the
+/// owner of the storage lock writes, and its own sharding is seen at once;
+/// this bounds how late a `--shared` view notices the owner's.
+const UNSHARDED_TTL: Duration = Duration::from_secs(5);
+
+/// `path` as a cache key: relative, without `.` components.
+fn key(path: &Path) -> Result<PathBuf> {
+    Ok(super::sanitize_rel(path)?.components().collect())
+}
+
+/// Bucket of `name`: the first byte of its FNV-1a hash, in hex. Fixed so
+/// every process and every version agrees where a file lives.
+fn bucket(name: &OsStr) -> String {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in name.as_encoded_bytes() {
+        h = (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:02x}", h >> 56)
+}
+
+/// Where `dir/name` goes in a sharded `dir`.
+fn shard_path(dir: &Path, name: &OsStr) -> PathBuf {
+    dir.join(SHARD_DIR).join(bucket(name)).join(name)
+}
+
+/// `path` with any `.rhss-shards/<hh>` pairs taken out.
+fn logical(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    let mut parts = path.components();
+    while let Some(c) = parts.next() {
+        if c == Component::Normal(OsStr::new(SHARD_DIR)) {
+            parts.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+pub struct ShardedBackend {
+    inner: Arc<dyn Backend>,
+    threshold: usize,
+    /// Directories known to be sharded. They stay sharded until removed.
+    sharded: Mutex<HashSet<PathBuf>>,
+    /// Directories found unsharded, and when.
+    unsharded: Mutex<HashMap<PathBuf, Instant>>,
+    /// Entry counts of directories written to that aren't sharded yet.
+    counts: Mutex<HashMap<PathBuf, usize>>,
+}
+
+impl ShardedBackend {
+    /// Shard directories on `inner` once they hold more than `threshold`
+    /// entries.
+    pub fn new(inner: Arc<dyn Backend>, threshold: usize) -> Self {
+        Self {
+            inner,
+            threshold,
+            sharded: Mutex::new(HashSet::new()),
+            unsharded: Mutex::new(HashMap::new()),
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_sharded(&self, dir: &Path) -> Result<bool> {
+        if self.sharded.lock().contains(dir) {
+            return Ok(true);
+        }
+        if let Some(at) = self.unsharded.lock().get(dir) {
+            if at.elapsed() < UNSHARDED_TTL {
+                return Ok(false);
+            }
+        }
+        let on = self.inner.exists(&dir.join(SHARD_DIR))?;
+        self.seen(dir, on);
+        Ok(on)
+    }
+
+    /// Record whether `dir` is sharded.
+    fn seen(&self, dir: &Path, on: bool) {
+        if on {
+            self.unsharded.lock().remove(dir);
+            self.sharded.lock().insert(dir.to_path_buf());
+        } else {
+            self.unsharded
+                .lock()
+                .insert(dir.to_path_buf(), Instant::now());
+        }
+    }
+
+    /// Where `path` is on the wrapped backend: in its shard if it is
+    /// there, else at `path` itself.
+    fn actual(&self, path: &Path) -> Result<PathBuf> {
+        let k = key(path)?;
+        let (Some(dir), Some(name)) = (k.parent(), k.file_name()) else {
+            return Ok(k);
+        };
+        if self.is_sharded(dir)? {
+            let s = shard_path(dir, name);
+            if self.inner.exists(&s)? {
+                return Ok(s);
+            }
+        }
+        Ok(k)
+    }
+
+    /// Where a write to `path` goes: where it already is, or where a new
+    /// file belongs. May shard its directory.
+    fn placed(&self, path: &Path) -> Result<PathBuf> {
+        let found = self.actual(path)?;
+        let (Some(dir), Some(name)) = (found.parent(), found.file_name()) else {
+            return Ok(found);
+        };
+        if found.starts_with(dir.join(SHARD_DIR)) || self.inner.exists(&found)? {
+            return Ok(found);
+        }
+        if !self.is_sharded(dir)? && !self.grow(dir)? {
+            return Ok(found);
+        }
+        let s = shard_path(dir, name);
+        if let Some(b) = s.parent() {
+            self.inner.create_dir(b)?;
+        }
+        Ok(s)
+    }
+
+    /// Count one more entry in the unsharded `dir`; shard it and return
+    /// `true` if that takes it over the threshold.
+    fn grow(&self, dir: &Path) -> Result<bool> {
+        let mut counts = self.counts.lock();
+        let n = match counts.get(dir) {
+            Some(n) => *n,
+            None => match self.inner.list_dir(dir) {
+                Ok(names) => names.len(),
+                Err(FsError::NotFound(_)) => 0,
+                Err(e) => return Err(e),
+            },
+        };
+        if n < self.threshold {
+            counts.insert(dir.to_path_buf(), n + 1);
+            return Ok(false);
+        }
+        counts.remove(dir);
+        drop(counts);
+        self.inner.create_dir(&dir.join(SHARD_DIR))?;
+        self.seen(dir, true);
+        info!(
+            "{}: {} has {n} entries; sharding new files",
+            self.inner.id(),
+            dir.display()
+        );
+        Ok(true)
+    }
+
+    /// Count one entry less in `dir`, if it is being counted.
+    fn shrink(&self, dir: &Path) {
+        if let Some(n) = self.counts.lock().get_mut(dir) {
+            *n = n.saturating_sub(1);
+        }
+    }
+
+    /// Remove the empty shard buckets of `dir`, and its `.rhss-shards/`.
+    fn unshard(&self, dir: &Path) -> Result<()> {
+        let shards = dir.join(SHARD_DIR);
+        for b in self.inner.list_dir(&shards)? {
+            self.inner.remove(&shards.join(b))?;
+        }
+        self.inner.remove(&shards)?;
+        self.sharded.lock().remove(dir);
+        self.seen(dir, false);
+        Ok(())
+    }
+
+    /// Forget cached state under `dir`, which moved or went away.
+    fn forget(&self, dir: &Path) {
+        self.sharded.lock().retain(|d| !d.starts_with(dir));
+        self.unsharded.lock().retain(|d, _| !d.starts_with(dir));
+        self.counts.lock().retain(|d, _| !d.starts_with(dir));
+    }
+}
+
+impl Backend for ShardedBackend {
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    fn root(&self) -> &Path {
+        self.inner.root()
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        match self.actual(path) {
+            Ok(p) => self.inner.resolve(&p),
+            Err(_) => self.inner.resolve(path),
+        }
+    }
+
+    fn direct_io_above(&self) -> Option<u64> {
+        self.inner.direct_io_above()
+    }
+
+    fn available(&self) -> bool {
+        self.inner.available()
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.inner.cost_per_gb_month()
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        Box::new(self.inner.walk_files().map(|r| r.map(|p| logical(&p))))
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        self.inner.read_at(&self.actual(path)?, offset, size)
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        self.inner.write_at(&self.placed(path)?, offset, data)
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        self.inner.truncate(&self.actual(path)?, size)
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        self.inner.fsync(&self.actual(path)?)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        self.inner.metadata(&self.actual(path)?)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        self.inner.exists(&self.actual(path)?)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        let dir = key(path)?;
+        let mut names = self.inner.list_dir(&dir)?;
+        let before = names.len();
+        names.retain(|n| n != SHARD_DIR);
+        self.seen(&dir, names.len() < before);
+        if names.len() < before {
+            let shards = dir.join(SHARD_DIR);
+            for b in self.inner.list_dir(&shards)? {
+                names.extend(self.inner.list_dir(&shards.join(b))?);
+            }
+        }
+        Ok(names)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let k = key(path)?;
+        if let Some(dir) = k.parent() {
+            if !self.is_sharded(dir)? {
+                // Subdirectories count, but always stay in place.
+                self.grow(dir)?;
+            }
+        }
+        self.inner.create_dir(&k)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        self.inner.create_file(&self.placed(path)?)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let p = self.actual(path)?;
+        if self.inner.metadata(&p)?.is_dir {
+            if self.is_sharded(&p)? {
+                self.unshard(&p)?;
+            }
+            self.inner.remove(&p)?;
+            self.forget(&p);
+        } else {
+            self.inner.remove(&p)?;
+        }
+        if let Some(dir) = key(path)?.parent() {
+            self.shrink(dir);
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let src = self.actual(from)?;
+        let is_dir = self.inner.metadata(&src)?.is_dir;
+        let dst = if is_dir { key(to)? } else { self.placed(to)? };
+        self.inner.rename(&src, &dst)?;
+        if is_dir {
+            self.forget(&src);
+            self.forget(&dst);
+        }
+        if let Some(dir) = key(from)?.parent() {
+            self.shrink(dir);
+        }
+        Ok(())
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.inner.set_permissions(&self.actual(path)?, mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        self.inner.set_times(&self.actual(path)?, atime, mtime)
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        self.inner.set_owner(&self.actual(path)?, uid, gid)
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        self.inner.check_access(&self.actual(path)?, uid, gid, mask)
+    }
+
+    fn copy_range(
+        &self,
+        src: &Path,
+        src_off: u64,
+        dst: &Path,
+        dst_off: u64,
+        len: u64,
+    ) -> Result<u64> {
+        let src = self.actual(src)?;
+        self.inner
+            .copy_range(&src, src_off, &self.placed(dst)?, dst_off, len)
+    }
+
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        let src = self.actual(src)?;
+        self.inner.copy(&src, &self.placed(dst)?)
+    }
+
+    fn allocate(&self, path: &Path, offset: u64, len: u64, mode: i32) -> Result<()> {
+        self.inner.allocate(&self.actual(path)?, offset, len, mode)
+    }
+
+    fn next_data(&self, path: &Path, offset: u64) -> Result<Option<u64>> {
+        self.inner.next_data(&self.actual(path)?, offset)
+    }
+
+    fn next_hole(&self, path: &Path, offset: u64) -> Result<u64> {
+        self.inner.next_hole(&self.actual(path)?, offset)
+    }
+
+    fn sync_replicas(&self, path: &Path) -> Result<()> {
+        self.inner.sync_replicas(&self.actual(path)?)
+    }
+
+    fn resume_copy(&self, path: &Path, src: &FileMetadata) -> Result<u64> {
+        self.inner.resume_copy(&self.actual(path)?, src)
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        self.inner.statvfs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tempfile::TempDir;
+
+    fn setup(dir: &Path) -> ShardedBackend {
+        let inner: Arc<dyn Backend> = Arc::new(PosixBackend::new("hdd", dir).unwrap());
+        ShardedBackend::new(inner, 4)
+    }
+
+    #[test]
+    fn big_directories_shard_new_files() {
+        let dir = TempDir::new().unwrap();
+        let b = setup(dir.path());
+        b.create_dir(Path::new("big")).unwrap();
+        let names: Vec<String> = (0..10).map(|i| format!("f{i}")).collect();
+        for n in &names {
+            b.write_at(&Path::new("big").join(n), 0, n.as_bytes())
+                .unwrap();
+        }
+        b.create_dir(Path::new("big/sub")).unwrap();
+        assert!(dir.path().join("big").join(SHARD_DIR).is_dir());
+        // The first ones stayed put; the rest went into shards.
+        assert!(dir.path().join("big/f0").is_file());
+        assert!(!dir.path().join("big/f9").exists());
+        assert!(dir.path().join("big/sub").is_dir());
+
+        let mut listed = b.list_dir(Path::new("big")).unwrap();
+        listed.sort();
+        let mut expected = names.clone();
+        expected.push("sub".into());
+        expected.sort();
+        assert_eq!(listed, expected);
+        assert_eq!(b.read_at(Path::new("big/f9"), 0, 16).unwrap(), b"f9");
+        let mut walked: Vec<PathBuf> = b.walk_files().map(Result::unwrap).collect();
+        walked.sort();
+        assert_eq!(walked.len(), 10);
+        assert!(walked.contains(&PathBuf::from("big/f9")));
+
+        // A fresh instance (another process, a restart) finds them too.
+        let again = setup(dir.path());
+        assert!(again.exists(Path::new("big/f9")).unwrap());
+        again
+            .rename(Path::new("big/f9"), Path::new("big/g9"))
+            .unwrap();
+        assert_eq!(again.read_at(Path::new("big/g9"), 0, 16).unwrap(), b"f9");
+        again.rename(Path::new("big/g9"), Path::new("g9")).unwrap();
+        assert!(dir.path().join("g9").is_file());
+
+        for n in &names[..9] {
+            again.remove(&Path::new("big").join(n)).unwrap();
+        }
+        again.remove(Path::new("big/sub")).unwrap();
+        again.remove(Path::new("big")).unwrap();
+        assert!(!dir.path().join("big").exists());
+    }
+
+    /// A `PosixBackend` counting the lookups of `.rhss-shards/`.
+    struct Counting {
+        inner: PosixBackend,
+        probes: AtomicU32,
+    }
+
+    impl Backend for Counting {
+        fn id(&self) -> &str {
+            self.inner.id()
+        }
+        fn root(&self) -> &Path {
+            self.inner.root()
+        }
+        fn read_at(&self, p: &Path, o: u64, s: u32) -> Result<Vec<u8>> {
+            self.inner.read_at(p, o, s)
+        }
+        fn write_at(&self, p: &Path, o: u64, d: &[u8]) -> Result<u32> {
+            self.inner.write_at(p, o, d)
+        }
+        fn truncate(&self, p: &Path, s: u64) -> Result<()> {
+            self.inner.truncate(p, s)
+        }
+        fn fsync(&self, p: &Path) -> Result<()> {
+            self.inner.fsync(p)
+        }
+        fn metadata(&self, p: &Path) -> Result<FileMetadata> {
+            self.inner.metadata(p)
+        }
+        fn exists(&self, p: &Path) -> Result<bool> {
+            if p.ends_with(SHARD_DIR) {
+                self.probes.fetch_add(1, Ordering::SeqCst);
+            }
+            self.inner.exists(p)
+        }
+        fn list_dir(&self, p: &Path) -> Result<Vec<String>> {
+            self.inner.list_dir(p)
+        }
+        fn create_dir(&self, p: &Path) -> Result<()> {
+            self.inner.create_dir(p)
+        }
+        fn create_file(&self, p: &Path) -> Result<()> {
+            self.inner.create_file(p)
+        }
+        fn remove(&self, p: &Path) -> Result<()> {
+            self.inner.remove(p)
+        }
+        fn rename(&self, f: &Path, t: &Path) -> Result<()> {
+            self.inner.rename(f, t)
+        }
+        fn set_permissions(&self, p: &Path, m: u32) -> Result<()> {
+            self.inner.set_permissions(p, m)
+        }
+        fn set_times(&self, p: &Path, a: Option<SystemTime>, m: Option<SystemTime>) -> Result<()> {
+            self.inner.set_times(p, a, m)
+        }
+        fn statvfs(&self) -> Result<BackendStats> {
+            self.inner.statvfs()
+        }
+        fn resolve(&self, p: &Path) -> PathBuf {
+            self.inner.resolve(p)
+        }
+    }
+
+    #[test]
+    fn sharding_is_looked_up_once_per_directory() {
+        let dir = TempDir::new().unwrap();
+        let counting = Arc::new(Counting {
+            inner: PosixBackend::new("hdd", dir.path()).unwrap(),
+            probes: AtomicU32::new(0),
+        });
+        let b = ShardedBackend::new(counting.clone(), 4);
+        b.create_dir(Path::new("small")).unwrap();
+        b.write_at(Path::new("small/f"), 0, b"x").unwrap();
+        for _ in 0..10 {
+            assert_eq!(b.read_at(Path::new("small/f"), 0, 1).unwrap(), b"x");
+            b.metadata(Path::new("small/f")).unwrap();
+        }
+        // The root and `small`, once each.
+        assert_eq!(counting.probes.load(Ordering::SeqCst), 2);
+
+        // Sharding it here is seen at once, without another lookup.
+        for i in 0..5 {
+            b.write_at(&Path::new("small").join(format!("g{i}")), 0, b"y")
+                .unwrap();
+        }
+        assert!(dir.path().join("small").join(SHARD_DIR).is_dir());
+        assert_eq!(b.read_at(Path::new("small/g4"), 0, 1).unwrap(), b"y");
+        assert_eq!(counting.probes.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,723 @@
+//! `SmallFileBackend` — pack small files on a wrapped backend into shared
+//! pack files (`pack_small_files = "64K"` on a tier backend).
+//!
+//! Cold HDDs seek themselves to death over millions of tiny files, and
+//! object stores charge per request. Once a file below the threshold is
+//! `fsync`ed it is queued; when the queue holds `PACK_TARGET` bytes (or
+//! `PACK_MAX_FILES` files) the batch is written as one immutable
+//! `.rhss_packs/<n>.pack` plus a JSON `<n>.idx` naming each member's path,
+//! offset and attributes, and the originals are removed. Directories stay
+//! on the wrapped backend; packed files are listed from the index.
+//!
+//! Reads of a packed file are ranged reads of its pack. Writing to one
+//! unpacks it first (copies it back out and drops it from the index), and
+//! deleting or renaming one only rewrites its pack's index. A pack whose
+//! members are all gone is deleted; a partly dead pack keeps its space
+//! until then.
+//!
+//! A crash while unpacking can leave the original next to its packed
+//! copy. Both hold the same bytes; the index wins and the next write
+//! replaces the original.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use super::{Backend, BackendStats, FileMetadata};
+use crate::error::{FsError, Result};
+
+/// Managed dir on the wrapped backend; hidden from listings and walks.
+const PACK_DIR: &str = ".rhss_packs";
+/// Pack the queue once it holds this many bytes...
+const PACK_TARGET: u64 = 8 << 20;
+/// ...or this many files.
+const PACK_MAX_FILES: usize = 4096;
+/// Largest accepted threshold: a member must fit one `read_at`.
+pub const MAX_PACK_THRESHOLD: u64 = 16 << 20;
+
+/// One packed file, as stored in its pack's index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Member {
+    path: PathBuf,
+    offset: u64,
+    len: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime_secs: u64,
+    mtime_nsecs: u32,
+}
+
+impl Member {
+    fn mtime(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::new(self.mtime_secs, self.mtime_nsecs)
+    }
+
+    fn set_mtime(&mut self, t: SystemTime) {
+        let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.mtime_secs = d.as_secs();
+        self.mtime_nsecs = d.subsec_nanos();
+    }
+}
+
+#[derive(Default)]
+struct Index {
+    /// Packed path → (pack number, member).
+    files: BTreeMap<PathBuf, (u64, Member)>,
+    /// Pack number → paths still in it.
+    packs: HashMap<u64, BTreeSet<PathBuf>>,
+    next_pack: u64,
+}
+
+impl Index {
+    fn insert(&mut self, pack: u64, m: Member) {
+        self.packs.entry(pack).or_default().insert(m.path.clone());
+        self.files.insert(m.path.clone(), (pack, m));
+    }
+
+    fn remove(&mut self, path: &Path) -> Option<(u64, Member)> {
+        let (pack, m) = self.files.remove(path)?;
+        if let Some(paths) = self.packs.get_mut(&pack) {
+            paths.remove(path);
+        }
+        Some((pack, m))
+    }
+
+    /// Packed paths strictly below `dir`.
+    fn below(&self, dir: &Path) -> Vec<PathBuf> {
+        self.files
+            .range(dir.to_path_buf()..)
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(dir))
+            .filter(|k| *k != dir)
+            .cloned()
+            .collect()
+    }
+}
+
+fn pack_path(n: u64) -> PathBuf {
+    Path::new(PACK_DIR).join(format!("{n:08}.pack"))
+}
+
+fn idx_path(n: u64) -> PathBuf {
+    Path::new(PACK_DIR).join(format!("{n:08}.idx"))
+}
+
+/// `path` as an index key: relative, without `.` components.
+fn key(path: &Path) -> Result<PathBuf> {
+    Ok(super::sanitize_rel(path)?.components().collect())
+}
+
+pub struct SmallFileBackend {
+    inner: Arc<dyn Backend>,
+    threshold: u64,
+    index: RwLock<Index>,
+    /// Small files fsync'd since the last pack, with their sizes.
+    queue: Mutex<Vec<(PathBuf, u64)>>,
+}
+
+impl SmallFileBackend {
+    /// Pack files smaller than `threshold` bytes on `inner`. Reads the
+    /// indexes of the packs already there.
+    pub fn new(inner: Arc<dyn Backend>, threshold: u64) -> Result<Self> {
+        let mut index = Index::default();
+        let names = match inner.list_dir(Path::new(PACK_DIR)) {
+            Ok(names) => names,
+            Err(FsError::NotFound(_)) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut orphans = Vec::new();
+        for name in &names {
+            let Some(n) = name.split('.').next().and_then(|s| s.parse::<u64>().ok()) else {
+                continue;
+            };
+            index.next_pack = index.next_pack.max(n + 1);
+            if name.ends_with(".idx") {
+                let p = idx_path(n);
+                let size = inner.metadata(&p)?.size;
+                let data = inner.read_at(&p, 0, size as u32)?;
+                let members: Vec<Member> = serde_json::from_slice(&data).map_err(|e| {
+                    FsError::Storage(format!("{}: bad pack index {name}: {e}", inner.id()))
+                })?;
+                for m in members {
+                    index.insert(n, m);
+                }
+            } else if name.ends_with(".pack") {
+                if !names.contains(&format!("{n:08}.idx")) {
+                    // Written, but the crash came before its index.
+                    orphans.push(pack_path(n));
+                }
+            } else {
+                orphans.push(Path::new(PACK_DIR).join(name));
+            }
+        }
+        for p in orphans {
+            warn!("{}: removing unfinished {}", inner.id(), p.display());
+            let _ = inner.remove(&p);
+        }
+        if !index.files.is_empty() {
+            info!(
+                "{}: {} files in {} packs",
+                inner.id(),
+                index.files.len(),
+                index.packs.len()
+            );
+        }
+        Ok(Self {
+            inner,
+            threshold,
+            index: RwLock::new(index),
+            queue: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Write pack `n`'s index from the in-memory one, or delete the pack
+    /// if nothing in it is left.
+    fn save_index(&self, idx: &mut Index, n: u64) -> Result<()> {
+        let members: Vec<&Member> = idx
+            .packs
+            .get(&n)
+            .into_iter()
+            .flatten()
+            .filter_map(|p| idx.files.get(p).map(|(_, m)| m))
+            .collect();
+        if members.is_empty() {
+            idx.packs.remove(&n);
+            self.inner.remove(&idx_path(n))?;
+            debug!("{}: pack {n} emptied", self.inner.id());
+            return self.inner.remove(&pack_path(n));
+        }
+        let json = serde_json::to_vec(&members)
+            .map_err(|e| FsError::Storage(format!("encode pack index: {e}")))?;
+        let tmp = Path::new(PACK_DIR).join(format!("{n:08}.idx.tmp"));
+        if self.inner.exists(&tmp)? {
+            self.inner.remove(&tmp)?;
+        }
+        self.inner.create_file(&tmp)?;
+        self.inner.write_at(&tmp, 0, &json)?;
+        self.inner.fsync(&tmp)?;
+        self.inner.rename(&tmp, &idx_path(n))
+    }
+
+    /// Pack whatever is queued. Files that grew past the threshold, went
+    /// away or were packed meanwhile are skipped.
+    pub fn pack_queued(&self) -> Result<()> {
+        let mut idx = self.index.write();
+        let queued = std::mem::take(&mut *self.queue.lock());
+        let n = idx.next_pack;
+        let mut data = Vec::new();
+        let mut members = Vec::new();
+        for (path, _) in queued {
+            if idx.files.contains_key(&path) || path.to_str().is_none() {
+                continue;
+            }
+            let Ok(meta) = self.inner.metadata(&path) else {
+                continue;
+            };
+            if meta.is_dir || meta.size >= self.threshold {
+                continue;
+            }
+            let bytes = self.inner.read_at(&path, 0, meta.size as u32)?;
+            if bytes.len() as u64 != meta.size {
+                continue;
+            }
+            let mut m = Member {
+                path,
+                offset: data.len() as u64,
+                len: meta.size,
+                mode: meta.mode & 0o7777,
+                uid: meta.uid,
+                gid: meta.gid,
+                mtime_secs: 0,
+                mtime_nsecs: 0,
+            };
+            m.set_mtime(meta.mtime);
+            data.extend_from_slice(&bytes);
+            members.push(m);
+        }
+        if members.is_empty() {
+            return Ok(());
+        }
+        self.inner.create_dir(Path::new(PACK_DIR))?;
+        self.inner.create_file(&pack_path(n))?;
+        if !data.is_empty() {
+            self.inner.write_at(&pack_path(n), 0, &data)?;
+        }
+        self.inner.fsync(&pack_path(n))?;
+        idx.next_pack = n + 1;
+        let paths: Vec<PathBuf> = members.iter().map(|m| m.path.clone()).collect();
+        for m in members {
+            idx.insert(n, m);
+        }
+        if let Err(e) = self.save_index(&mut idx, n) {
+            for p in &paths {
+                idx.remove(p);
+            }
+            idx.packs.remove(&n);
+            let _ = self.inner.remove(&pack_path(n));
+            return Err(e);
+        }
+        for p in &paths {
+            if let Err(e) = self.inner.remove(p) {
+                warn!(
+                    "{}: packed {} but kept original: {e}",
+                    self.inner.id(),
+                    p.display()
+                );
+            }
+        }
+        debug!(
+            "{}: packed {} files ({} bytes) into pack {n}",
+            self.inner.id(),
+            paths.len(),
+            data.len()
+        );
+        Ok(())
+    }
+
+    /// Copy a packed file back out to the wrapped backend.
+    fn unpack(&self, idx: &mut Index, path: &Path) -> Result<()> {
+        let Some((n, m)) = idx.files.get(path).cloned() else {
+            return Ok(());
+        };
+        let data = self.inner.read_at(&pack_path(n), m.offset, m.len as u32)?;
+        if self.inner.exists(path)? {
+            self.inner.truncate(path, 0)?;
+        } else {
+            self.inner.create_file(path)?;
+        }
+        self.inner.write_at(path, 0, &data)?;
+        self.inner.set_permissions(path, m.mode)?;
+        self.inner.set_times(path, None, Some(m.mtime()))?;
+        let _ = self.inner.set_owner(path, m.uid, m.gid);
+        self.inner.fsync(path)?;
+        idx.remove(path);
+        self.save_index(idx, n)
+    }
+
+    /// Run `f` on the wrapped backend once `path` is no longer packed.
+    fn unpacked<T>(&self, path: &Path, f: impl Fn(&Path) -> Result<T>) -> Result<T> {
+        let k = key(path)?;
+        loop {
+            {
+                let idx = self.index.read();
+                if !idx.files.contains_key(&k) {
+                    return f(&k);
+                }
+            }
+            let mut idx = self.index.write();
+            self.unpack(&mut idx, &k)?;
+        }
+    }
+
+    /// Change a packed file's recorded attributes, or forward to the
+    /// wrapped backend.
+    fn update(
+        &self,
+        path: &Path,
+        edit: impl FnOnce(&mut Member),
+        fallback: impl FnOnce(&Path) -> Result<()>,
+    ) -> Result<()> {
+        let k = key(path)?;
+        let mut idx = self.index.write();
+        let n = match idx.files.get_mut(&k) {
+            Some((n, m)) => {
+                edit(m);
+                *n
+            }
+            None => {
+                drop(idx);
+                return fallback(&k);
+            }
+        };
+        self.save_index(&mut idx, n)
+    }
+
+    fn packed_metadata(m: &Member) -> FileMetadata {
+        FileMetadata {
+            size: m.len,
+            is_dir: false,
+            mode: libc::S_IFREG | m.mode,
+            uid: m.uid,
+            gid: m.gid,
+            atime: m.mtime(),
+            mtime: m.mtime(),
+            ctime: m.mtime(),
+        }
+    }
+}
+
+impl Backend for SmallFileBackend {
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    fn root(&self) -> &Path {
+        self.inner.root()
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.inner.resolve(path)
+    }
+
+    fn direct_io_above(&self) -> Option<u64> {
+        self.inner.direct_io_above()
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.inner.cost_per_gb_month()
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        let packed: Vec<PathBuf> = self.index.read().files.keys().cloned().collect();
+        let on_disk = self.inner.walk_files().filter(move |r| match r {
+            Ok(p) => !p.starts_with(PACK_DIR) && !self.index.read().files.contains_key(p),
+            Err(_) => true,
+        });
+        Box::new(on_disk.chain(packed.into_iter().map(Ok)))
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        let k = key(path)?;
+        let idx = self.index.read();
+        let Some((n, m)) = idx.files.get(&k) else {
+            return self.inner.read_at(&k, offset, size);
+        };
+        if offset >= m.len {
+            return Ok(Vec::new());
+        }
+        let size = (size as u64).min(m.len - offset) as u32;
+        self.inner.read_at(&pack_path(*n), m.offset + offset, size)
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        self.unpacked(path, |p| self.inner.write_at(p, offset, data))
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        self.unpacked(path, |p| self.inner.truncate(p, size))
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        let k = key(path)?;
+        let size = {
+            let idx = self.index.read();
+            if idx.files.contains_key(&k) {
+                // Already durable in its pack.
+                return Ok(());
+            }
+            self.inner.fsync(&k)?;
+            self.inner.metadata(&k)?.size
+        };
+        if size >= self.threshold {
+            return Ok(());
+        }
+        let full = {
+            let mut q = self.queue.lock();
+            if !q.iter().any(|(p, _)| *p == k) {
+                q.push((k, size));
+            }
+            q.len() >= PACK_MAX_FILES || q.iter().map(|(_, s)| s).sum::<u64>() >= PACK_TARGET
+        };
+        if full {
+            self.pack_queued()?;
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let k = key(path)?;
+        match self.index.read().files.get(&k) {
+            Some((_, m)) => Ok(Self::packed_metadata(m)),
+            None => self.inner.metadata(&k),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        let k = key(path)?;
+        if self.index.read().files.contains_key(&k) {
+            return Ok(true);
+        }
+        self.inner.exists(&k)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        let k = key(path)?;
+        let mut names: BTreeSet<String> = self.inner.list_dir(&k)?.into_iter().collect();
+        if k.as_os_str().is_empty() {
+            names.remove(PACK_DIR);
+        }
+        let idx = self.index.read();
+        for p in idx.below(&k) {
+            if p.parent() == Some(k.as_path()) {
+                if let Some(name) = p.file_name().and_then(|n| n.to_str()) {
+                    names.insert(name.to_string());
+                }
+            }
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        let k = key(path)?;
+        if self.index.read().files.contains_key(&k) {
+            return Err(FsError::AlreadyExists(k.display().to_string()));
+        }
+        self.inner.create_file(&k)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let k = key(path)?;
+        let mut idx = self.index.write();
+        self.queue.lock().retain(|(p, _)| *p != k);
+        if let Some((n, _)) = idx.remove(&k) {
+            self.save_index(&mut idx, n)?;
+            if self.inner.exists(&k)? {
+                // Left behind by an interrupted unpack.
+                let _ = self.inner.remove(&k);
+            }
+            return Ok(());
+        }
+        if !idx.below(&k).is_empty() {
+            return Err(FsError::NotEmpty(k.display().to_string()));
+        }
+        self.inner.remove(&k)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let (from, to) = (key(from)?, key(to)?);
+        let mut idx = self.index.write();
+        let mut dirty = BTreeSet::new();
+        if let Some((n, _)) = idx.remove(&to) {
+            dirty.insert(n);
+        }
+        if let Some((n, mut m)) = idx.remove(&from) {
+            // A plain file at `to` is replaced.
+            if self.inner.metadata(&to).is_ok_and(|t| !t.is_dir) {
+                self.inner.remove(&to)?;
+            }
+            m.path = to.clone();
+            idx.insert(n, m);
+            dirty.insert(n);
+        } else {
+            self.inner.rename(&from, &to)?;
+            for p in idx.below(&from) {
+                if let Some((n, mut m)) = idx.remove(&p) {
+                    m.path = to.join(p.strip_prefix(&from).unwrap_or(&p));
+                    idx.insert(n, m);
+                    dirty.insert(n);
+                }
+            }
+        }
+        for (p, _) in self.queue.lock().iter_mut() {
+            if let Ok(rest) = p.strip_prefix(&from) {
+                *p = to.join(rest);
+            }
+        }
+        for n in dirty {
+            self.save_index(&mut idx, n)?;
+        }
+        Ok(())
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.update(
+            path,
+            |m| m.mode = mode & 0o7777,
+            |p| self.inner.set_permissions(p, mode),
+        )
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        self.update(
+            path,
+            |m| {
+                if let Some(t) = mtime {
+                    m.set_mtime(t);
+                }
+            },
+            |p| self.inner.set_times(p, atime, mtime),
+        )
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        self.update(
+            path,
+            |m| (m.uid, m.gid) = (uid, gid),
+            |p| self.inner.set_owner(p, uid, gid),
+        )
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        let k = key(path)?;
+        let m = match self.index.read().files.get(&k) {
+            Some((_, m)) => m.clone(),
+            None => return self.inner.check_access(&k, uid, gid, mask),
+        };
+        if super::mode_permits(m.mode, false, m.uid, m.gid, uid, gid, mask) {
+            Ok(())
+        } else {
+            Err(FsError::PermissionDenied(k.display().to_string()))
+        }
+    }
+
+    fn next_data(&self, path: &Path, offset: u64) -> Result<Option<u64>> {
+        let k = key(path)?;
+        match self.index.read().files.get(&k) {
+            Some((_, m)) => Ok((offset < m.len).then_some(offset)),
+            None => self.inner.next_data(&k, offset),
+        }
+    }
+
+    fn next_hole(&self, path: &Path, offset: u64) -> Result<u64> {
+        let k = key(path)?;
+        match self.index.read().files.get(&k) {
+            Some((_, m)) if offset < m.len => Ok(m.len),
+            Some(_) => Err(FsError::Io(std::io::Error::from_raw_os_error(libc::ENXIO))),
+            None => self.inner.next_hole(&k, offset),
+        }
+    }
+
+    fn sync_replicas(&self, path: &Path) -> Result<()> {
+        let k = key(path)?;
+        if self.index.read().files.contains_key(&k) {
+            return Ok(());
+        }
+        self.inner.sync_replicas(&k)
+    }
+
+    fn resume_copy(&self, path: &Path, src: &FileMetadata) -> Result<u64> {
+        let k = key(path)?;
+        if self.index.read().files.contains_key(&k) {
+            return Ok(0);
+        }
+        self.inner.resume_copy(&k, src)
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        self.inner.statvfs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use tempfile::TempDir;
+
+    fn setup() -> (TempDir, SmallFileBackend) {
+        let dir = TempDir::new().unwrap();
+        let inner: Arc<dyn Backend> = Arc::new(PosixBackend::new("hdd", dir.path()).unwrap());
+        (dir, SmallFileBackend::new(inner, 1024).unwrap())
+    }
+
+    fn put(b: &SmallFileBackend, path: &str, data: &[u8]) {
+        let p = Path::new(path);
+        b.create_file(p).unwrap();
+        b.write_at(p, 0, data).unwrap();
+        b.fsync(p).unwrap();
+    }
+
+    #[test]
+    fn packs_small_files_and_serves_them() {
+        let (dir, b) = setup();
+        b.create_dir(Path::new("logs")).unwrap();
+        put(&b, "logs/a.log", b"alpha");
+        put(&b, "logs/b.log", b"bravo!");
+        put(&b, "big.bin", &[9u8; 4096]);
+        b.set_permissions(Path::new("logs/a.log"), 0o600).unwrap();
+        b.pack_queued().unwrap();
+
+        // Originals are gone; the big file was never queued.
+        assert!(!dir.path().join("logs/a.log").exists());
+        assert!(dir.path().join("big.bin").exists());
+        assert!(dir.path().join(PACK_DIR).join("00000000.pack").exists());
+
+        let a = Path::new("logs/a.log");
+        assert_eq!(b.read_at(a, 1, 99).unwrap(), b"lpha");
+        assert_eq!(
+            b.read_at(Path::new("logs/b.log"), 0, 99).unwrap(),
+            b"bravo!"
+        );
+        let m = b.metadata(a).unwrap();
+        assert_eq!((m.size, m.mode & 0o777, m.is_dir), (5, 0o600, false));
+        assert_eq!(b.list_dir(Path::new("logs")).unwrap(), ["a.log", "b.log"]);
+        assert_eq!(b.list_dir(Path::new("")).unwrap(), ["big.bin", "logs"]);
+        let mut files: Vec<PathBuf> = b.walk_files().map(Result::unwrap).collect();
+        files.sort();
+        assert_eq!(
+            files,
+            ["big.bin", "logs/a.log", "logs/b.log"].map(PathBuf::from)
+        );
+        assert!(matches!(
+            b.remove(Path::new("logs")),
+            Err(FsError::NotEmpty(_))
+        ));
+    }
+
+    #[test]
+    fn writes_unpack_and_deletes_drop_packs() {
+        let (dir, b) = setup();
+        put(&b, "x", b"one");
+        put(&b, "y", b"two");
+        b.pack_queued().unwrap();
+
+        let x = Path::new("x");
+        b.write_at(x, 3, b"+more").unwrap();
+        assert!(dir.path().join("x").exists());
+        assert_eq!(b.read_at(x, 0, 99).unwrap(), b"one+more");
+
+        b.rename(Path::new("y"), Path::new("z")).unwrap();
+        assert_eq!(b.read_at(Path::new("z"), 0, 99).unwrap(), b"two");
+        assert!(!b.exists(Path::new("y")).unwrap());
+        b.remove(Path::new("z")).unwrap();
+        assert!(!dir.path().join(PACK_DIR).join("00000000.pack").exists());
+        assert!(!dir.path().join(PACK_DIR).join("00000000.idx").exists());
+    }
+
+    #[test]
+    fn index_survives_restart() {
+        let (dir, b) = setup();
+        b.create_dir(Path::new("d")).unwrap();
+        put(&b, "d/f", b"kept");
+        b.set_times(
+            Path::new("d/f"),
+            None,
+            Some(UNIX_EPOCH + Duration::from_secs(1_000_000)),
+        )
+        .unwrap();
+        b.pack_queued().unwrap();
+        b.rename(Path::new("d"), Path::new("e")).unwrap();
+        drop(b);
+        // A pack whose index never made it to disk.
+        std::fs::write(dir.path().join(PACK_DIR).join("00000007.pack"), b"junk").unwrap();
+
+        let inner: Arc<dyn Backend> = Arc::new(PosixBackend::new("hdd", dir.path()).unwrap());
+        let b = SmallFileBackend::new(inner, 1024).unwrap();
+        let f = Path::new("e/f");
+        assert_eq!(b.read_at(f, 0, 99).unwrap(), b"kept");
+        assert_eq!(
+            b.metadata(f).unwrap().mtime,
+            UNIX_EPOCH + Duration::from_secs(1_000_000)
+        );
+        assert!(!dir.path().join(PACK_DIR).join("00000007.pack").exists());
+        // New packs don't reuse the orphan's number.
+        put(&b, "g", b"new");
+        b.pack_queued().unwrap();
+        assert!(dir.path().join(PACK_DIR).join("00000008.pack").exists());
+    }
+}
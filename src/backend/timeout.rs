@@ -0,0 +1,412 @@
+//! `TimeoutBackend` — bound every call on a wrapped backend.
+//!
+//! A hung disk (dead USB enclosure) or a stalled S3 endpoint would otherwise
+//! park the FUSE worker inside the kernel request forever, and `ls` on the
+//! mount becomes unkillable. The decorator runs each call on a helper thread
+//! and gives up after `timeout` with `FsError::TimedOut` (→ `ETIMEDOUT`).
+//!
+//! Each call runs inside a debug-level `backend` span (backend id, op,
+//! path) whose parent is the caller's span, so a FUSE request can be
+//! followed onto the helper thread.
+//!
+//! The abandoned call keeps running on its helper thread; if it eventually
+//! completes, its result is dropped. Note that S3's first `read_at` stages
+//! the whole object, so very large cold files may need a longer timeout.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+use tracing::{debug_span, warn};
+
+use super::{Backend, BackendStats, FileMetadata};
+use crate::error::{FsError, Result};
+
+/// Default per-call timeout when `[fuse] op_timeout_secs` is unset.
+pub const DEFAULT_OP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Idle helper threads exit after this long without work.
+const HELPER_IDLE: Duration = Duration::from_secs(60);
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Elastic helper pool shared by every `TimeoutBackend`. Threads are spawned
+/// on demand whenever none is idle, so a few hung calls never starve the
+/// rest.
+struct Helpers {
+    tx: Sender<Job>,
+    rx: Receiver<Job>,
+    idle: Arc<AtomicUsize>,
+}
+
+impl Helpers {
+    fn global() -> &'static Helpers {
+        static HELPERS: OnceLock<Helpers> = OnceLock::new();
+        HELPERS.get_or_init(|| {
+            let (tx, rx) = unbounded();
+            Helpers {
+                tx,
+                rx,
+                idle: Arc::new(AtomicUsize::new(0)),
+            }
+        })
+    }
+
+    fn submit(&self, job: Job) {
+        if self.idle.load(Ordering::SeqCst) == 0 {
+            let rx = self.rx.clone();
+            let idle = Arc::clone(&self.idle);
+            let spawned = thread::Builder::new()
+                .name("rhss-backend-call".into())
+                .spawn(move || loop {
+                    idle.fetch_add(1, Ordering::SeqCst);
+                    let next = rx.recv_timeout(HELPER_IDLE);
+                    idle.fetch_sub(1, Ordering::SeqCst);
+                    match next {
+                        Ok(job) => job(),
+                        Err(_) => return,
+                    }
+                });
+            if let Err(e) = spawned {
+                warn!("spawn backend helper thread: {e}");
+            }
+        }
+        // The receiver lives in the static, so this cannot fail.
+        let _ = self.tx.send(job);
+    }
+}
+
+pub struct TimeoutBackend {
+    inner: Arc<dyn Backend>,
+    timeout: Duration,
+}
+
+impl TimeoutBackend {
+    pub fn new(inner: Arc<dyn Backend>, timeout: Duration) -> Arc<Self> {
+        Arc::new(Self { inner, timeout })
+    }
+
+    /// Run `f` against the inner backend on a helper thread, waiting at most
+    /// `self.timeout` for the result.
+    fn call<T, F>(&self, op: &str, path: &Path, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Backend) -> Result<T> + Send + 'static,
+    {
+        let _profiled = crate::profile::phase(|| format!("backend:{}:{op}", self.inner.id()));
+        let (tx, rx) = bounded(1);
+        let inner = Arc::clone(&self.inner);
+        let span = debug_span!("backend", id = self.inner.id(), op, path = %path.display());
+        Helpers::global().submit(Box::new(move || {
+            let _ = tx.send(span.in_scope(|| f(inner.as_ref())));
+        }));
+        match rx.recv_timeout(self.timeout) {
+            Ok(r) => r,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(
+                    "{} {} on {} timed out after {:?}",
+                    op,
+                    path.display(),
+                    self.inner.id(),
+                    self.timeout
+                );
+                Err(FsError::TimedOut(format!(
+                    "{op} {} on {}",
+                    path.display(),
+                    self.inner.id()
+                )))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(FsError::Storage(format!(
+                "{op} {}: backend call panicked",
+                path.display()
+            ))),
+        }
+    }
+}
+
+impl Backend for TimeoutBackend {
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    fn root(&self) -> &Path {
+        self.inner.root()
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>> {
+        let p = path.to_path_buf();
+        self.call("read", path, move |b| b.read_at(&p, offset, size))
+    }
+
+    fn write_at(&self, path: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        let p = path.to_path_buf();
+        let data = data.to_vec();
+        self.call("write", path, move |b| b.write_at(&p, offset, &data))
+    }
+
+    fn truncate(&self, path: &Path, size: u64) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("truncate", path, move |b| b.truncate(&p, size))
+    }
+
+    fn fsync(&self, path: &Path) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("fsync", path, move |b| b.fsync(&p))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let p = path.to_path_buf();
+        self.call("stat", path, move |b| b.metadata(&p))
+    }
+
+    fn allocate(&self, path: &Path, offset: u64, len: u64, mode: i32) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("fallocate", path, move |b| b.allocate(&p, offset, len, mode))
+    }
+
+    fn next_data(&self, path: &Path, offset: u64) -> Result<Option<u64>> {
+        let p = path.to_path_buf();
+        self.call("seek_data", path, move |b| b.next_data(&p, offset))
+    }
+
+    fn next_hole(&self, path: &Path, offset: u64) -> Result<u64> {
+        let p = path.to_path_buf();
+        self.call("seek_hole", path, move |b| b.next_hole(&p, offset))
+    }
+
+    fn copy_range(
+        &self,
+        src: &Path,
+        src_off: u64,
+        dst: &Path,
+        dst_off: u64,
+        len: u64,
+    ) -> Result<u64> {
+        let (s, d) = (src.to_path_buf(), dst.to_path_buf());
+        self.call("copy_file_range", src, move |b| {
+            b.copy_range(&s, src_off, &d, dst_off, len)
+        })
+    }
+
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        let (s, d) = (src.to_path_buf(), dst.to_path_buf());
+        self.call("copy", src, move |b| b.copy(&s, &d))
+    }
+
+    fn check_access(&self, path: &Path, uid: u32, gid: u32, mask: i32) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("access", path, move |b| b.check_access(&p, uid, gid, mask))
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        let p = path.to_path_buf();
+        self.call("exists", path, move |b| b.exists(&p))
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<String>> {
+        let p = path.to_path_buf();
+        self.call("readdir", path, move |b| b.list_dir(&p))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("mkdir", path, move |b| b.create_dir(&p))
+    }
+
+    fn create_file(&self, path: &Path) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("create", path, move |b| b.create_file(&p))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("remove", path, move |b| b.remove(&p))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let (f, t) = (from.to_path_buf(), to.to_path_buf());
+        self.call("rename", from, move |b| b.rename(&f, &t))
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("chmod", path, move |b| b.set_permissions(&p, mode))
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("chown", path, move |b| b.set_owner(&p, uid, gid))
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("utimes", path, move |b| b.set_times(&p, atime, mtime))
+    }
+
+    fn statvfs(&self) -> Result<BackendStats> {
+        self.call("statvfs", Path::new("/"), |b| b.statvfs())
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.inner.resolve(path)
+    }
+
+    fn walk_files(&self) -> Box<dyn Iterator<Item = Result<PathBuf>> + '_> {
+        // A long walk, not one op; no deadline.
+        self.inner.walk_files()
+    }
+
+    fn sync_replicas(&self, path: &Path) -> Result<()> {
+        let p = path.to_path_buf();
+        self.call("resync", path, move |b| b.sync_replicas(&p))
+    }
+
+    fn resume_copy(&self, path: &Path, src: &FileMetadata) -> Result<u64> {
+        let (p, src) = (path.to_path_buf(), src.clone());
+        self.call("resume_copy", path, move |b| b.resume_copy(&p, &src))
+    }
+
+    fn direct_io_above(&self) -> Option<u64> {
+        self.inner.direct_io_above()
+    }
+
+    fn cost_per_gb_month(&self) -> Option<f64> {
+        self.inner.cost_per_gb_month()
+    }
+
+    fn available(&self) -> bool {
+        self.inner.available()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+
+    /// Backend whose `fsync` never returns in time.
+    struct Stuck(PosixBackend);
+
+    impl Backend for Stuck {
+        fn id(&self) -> &str {
+            self.0.id()
+        }
+        fn root(&self) -> &Path {
+            self.0.root()
+        }
+        fn read_at(&self, p: &Path, o: u64, s: u32) -> Result<Vec<u8>> {
+            self.0.read_at(p, o, s)
+        }
+        fn write_at(&self, p: &Path, o: u64, d: &[u8]) -> Result<u32> {
+            self.0.write_at(p, o, d)
+        }
+        fn truncate(&self, p: &Path, s: u64) -> Result<()> {
+            self.0.truncate(p, s)
+        }
+        fn fsync(&self, _p: &Path) -> Result<()> {
+            thread::sleep(Duration::from_secs(2));
+            Ok(())
+        }
+        fn metadata(&self, p: &Path) -> Result<FileMetadata> {
+            self.0.metadata(p)
+        }
+        fn exists(&self, p: &Path) -> Result<bool> {
+            self.0.exists(p)
+        }
+        fn list_dir(&self, p: &Path) -> Result<Vec<String>> {
+            self.0.list_dir(p)
+        }
+        fn create_dir(&self, p: &Path) -> Result<()> {
+            self.0.create_dir(p)
+        }
+        fn create_file(&self, p: &Path) -> Result<()> {
+            self.0.create_file(p)
+        }
+        fn remove(&self, p: &Path) -> Result<()> {
+            self.0.remove(p)
+        }
+        fn rename(&self, f: &Path, t: &Path) -> Result<()> {
+            self.0.rename(f, t)
+        }
+        fn set_permissions(&self, p: &Path, m: u32) -> Result<()> {
+            self.0.set_permissions(p, m)
+        }
+        fn set_times(
+            &self,
+            p: &Path,
+            a: Option<SystemTime>,
+            m: Option<SystemTime>,
+        ) -> Result<()> {
+            self.0.set_times(p, a, m)
+        }
+        fn statvfs(&self) -> Result<BackendStats> {
+            self.0.statvfs()
+        }
+        fn resolve(&self, p: &Path) -> PathBuf {
+            self.0.resolve(p)
+        }
+    }
+
+    #[test]
+    fn hung_call_times_out_and_others_proceed() {
+        let dir = tempfile::tempdir().unwrap();
+        let posix = PosixBackend::new("d", dir.path().to_path_buf()).unwrap();
+        let b = TimeoutBackend::new(Arc::new(Stuck(posix)), Duration::from_millis(100));
+
+        b.create_file(Path::new("f")).unwrap();
+        let err = b.fsync(Path::new("f")).unwrap_err();
+        assert_eq!(err.to_errno(), libc::ETIMEDOUT);
+        // The stuck helper doesn't block the next call.
+        assert_eq!(b.write_at(Path::new("f"), 0, b"hi").unwrap(), 2);
+        assert_eq!(b.read_at(Path::new("f"), 0, 2).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn backend_span_follows_the_caller_onto_the_helper() {
+        use std::sync::Mutex;
+        use tracing::span::{Attributes, Id};
+        use tracing::Subscriber;
+        use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+        use tracing_subscriber::registry::LookupSpan;
+
+        /// Records `(span, parent)` on creation and `(span, thread)` on entry.
+        #[derive(Clone, Default)]
+        struct Seen(Arc<Mutex<Vec<(String, String)>>>);
+
+        impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Seen {
+            fn on_new_span(&self, _: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+                let span = ctx.span(id).unwrap();
+                let parent = span.parent().map(|p| p.name()).unwrap_or("-");
+                let entry = (span.name().to_string(), format!("parent {parent}"));
+                self.0.lock().unwrap().push(entry);
+            }
+            fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+                let name = ctx.span(id).unwrap().name().to_string();
+                let thread = thread::current().name().unwrap_or("-").to_string();
+                self.0.lock().unwrap().push((name, format!("on {thread}")));
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let posix = PosixBackend::new("d", dir.path().to_path_buf()).unwrap();
+        let b = TimeoutBackend::new(Arc::new(posix), Duration::from_secs(5));
+        let seen = Seen::default();
+        let subscriber = tracing_subscriber::registry().with(seen.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug_span!("fuse").in_scope(|| b.exists(Path::new("f")).unwrap());
+        });
+
+        let seen = seen.0.lock().unwrap();
+        let has = |name: &str, what: &str| seen.iter().any(|(n, w)| n == name && w == what);
+        assert!(has("backend", "parent fuse"), "{seen:?}");
+        assert!(has("backend", "on rhss-backend-call"), "{seen:?}");
+    }
+}
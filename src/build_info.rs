@@ -0,0 +1,99 @@
+//! What this binary was built from: version, git commit, cargo features
+//! and the FUSE ABI it speaks. Printed by `rhss --version --build-info`
+//! and recorded in the storage lock, `rhss status` and the OTLP resource,
+//! so a report can be matched to a build.
+//!
+//! The commit and the `fuser` version come from `build.rs`. The FUSE ABI
+//! is the newest one this build can speak; the kernel may settle on an
+//! older one at mount, which fuser logs at debug level.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Short hash of the commit built, `-dirty` with local changes, or
+/// "unknown" outside a git checkout.
+pub const GIT_COMMIT: &str = env!("RHSS_GIT_COMMIT");
+
+const FUSER_VERSION: &str = env!("RHSS_FUSER_VERSION");
+
+/// Matches the `abi-7-*` feature picked for fuser in Cargo.toml.
+const FUSE_ABI: &str = if cfg!(target_os = "linux") {
+    "7.28"
+} else {
+    "7.16"
+};
+
+/// Optional cargo features, with whether this build has them.
+const FEATURES: &[(&str, bool)] = &[
+    ("grpc", cfg!(feature = "grpc")),
+    ("object-store", cfg!(feature = "object-store")),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_commit: String,
+    /// Enabled optional cargo features.
+    pub features: Vec<String>,
+    pub fuser: String,
+    pub fuse_abi: String,
+    /// `<os>-<arch>`.
+    pub target: String,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: VERSION.into(),
+            git_commit: GIT_COMMIT.into(),
+            features: FEATURES
+                .iter()
+                .filter(|(_, on)| *on)
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            fuser: FUSER_VERSION.into(),
+            fuse_abi: FUSE_ABI.into(),
+            target: format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+        }
+    }
+}
+
+/// One field per line, for `--build-info`.
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features = if self.features.is_empty() {
+            "none".to_string()
+        } else {
+            self.features.join(", ")
+        };
+        writeln!(f, "rhss {}", self.version)?;
+        writeln!(f, "commit:    {}", self.git_commit)?;
+        writeln!(f, "features:  {features}")?;
+        writeln!(f, "fuser:     {} (FUSE ABI {})", self.fuser, self.fuse_abi)?;
+        write!(f, "target:    {}", self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_features_built_in() {
+        let info = BuildInfo::current();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            info.features.contains(&"grpc".into()),
+            cfg!(feature = "grpc")
+        );
+        assert!(!info.git_commit.is_empty());
+        let text = info.to_string();
+        assert!(
+            text.starts_with("rhss ") && text.contains("FUSE ABI"),
+            "{text}"
+        );
+    }
+}
@@ -0,0 +1,884 @@
+//! `RhssBuilder` — the setup `rhss mount` does before mounting (tiers,
+//! index, journal recovery, first scan, tierer, FUSE adapter), for
+//! applications that embed the hybrid filesystem as a library.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use rhss::{PosixBackend, RhssBuilder};
+//!
+//! let rhss = RhssBuilder::new("/var/lib/app/index.db")
+//!     .with_fast(Arc::new(PosixBackend::new("ssd", "/ssd/.rhss_managed")?))
+//!     .with_slow(Arc::new(PosixBackend::new("hdd", "/hdd/.rhss_managed")?))
+//!     .build()?;
+//! let _session = rhss.mount("/mnt/app".as_ref())?;
+//! rhss.tierer().trigger_oneshot();
+//! # Ok::<(), rhss::FsError>(())
+//! ```
+//!
+//! Tests and embedders swap parts with `with_policy`, `with_placement`,
+//! `with_cache` (the index's lookup cache), `with_clock`, or `with_index`
+//! for the whole index.
+//!
+//! Process-level concerns stay with the caller: the storage lock,
+//! daemonizing, signal handling and the control socket.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lru::LruCache;
+use tracing::{error, info, warn};
+
+use crate::access::AccessTracker;
+use crate::backend::deferred::REATTACH_EVERY;
+use crate::backend::timeout::DEFAULT_OP_TIMEOUT;
+use crate::backend::{
+    Backend, BreakerBackend, ChunkedBackend, DedupBackend, DeferredBackend, HttpBackend,
+    HttpConfig, MemoryBackend, PackedBackend, ReplicatedBackend, S3Backend, S3Config,
+    ShardedBackend, SmallFileBackend, TimeoutBackend,
+};
+use crate::clock::{Clock, SystemClock};
+use crate::config::{RhssConfig, TierPolicy};
+use crate::error::{FsError, Result};
+use crate::fuse::{FuseAdapter, FuseConfig};
+use crate::hooks::Hook;
+use crate::index::{LocationCache, PathIndex, SqlitePathIndex, TierId, DEFAULT_CACHE_ENTRIES};
+use crate::policy::{PopularityPolicy, ReloadablePolicy, TieringPolicy};
+use crate::preflight;
+use crate::scan::{self, DuplicatePolicy};
+use crate::tenant::TenantPolicy;
+use crate::throttle::IoLimits;
+use crate::tier::{
+    CostAwarePlacement, MirrorPlacement, MostFreePlacement, Placement, RoundRobinPlacement, Tier,
+    TierRouter,
+};
+use crate::tierer::journal::{self, journal_dir_for};
+use crate::tierer::{set_journal, Journal, OpenFileTracker, Schedule, Tierer, TiererHandle};
+use crate::PosixBackend;
+
+/// How often the access tracker flushes atime/hit counts to the index.
+const ACCESS_FLUSH: Duration = Duration::from_secs(5);
+
+/// Conflicting paths logged when the first scan refuses to mount.
+const CONFLICTS_SHOWN: usize = 20;
+
+pub struct RhssBuilder {
+    db: PathBuf,
+    index: Option<Arc<dyn PathIndex>>,
+    cache: Option<Box<dyn LocationCache>>,
+    clock: Option<Arc<dyn Clock>>,
+    fast: Vec<Arc<dyn Backend>>,
+    slow: Vec<Arc<dyn Backend>>,
+    archive: Vec<Arc<dyn Backend>>,
+    fast_placement: Option<Box<dyn Placement>>,
+    slow_placement: Option<Box<dyn Placement>>,
+    archive_placement: Option<Box<dyn Placement>>,
+    policy: Arc<dyn TieringPolicy>,
+    duplicate_policy: DuplicatePolicy,
+    journal: bool,
+    shared: bool,
+    throttle: (Option<u64>, Option<u64>),
+    io_limits: Option<Arc<IoLimits>>,
+    schedule: Option<Schedule>,
+    hooks: Vec<Hook>,
+    fuse: FuseConfig,
+}
+
+impl RhssBuilder {
+    /// Index at `db`; its directory also holds the migration journal.
+    pub fn new(db: impl Into<PathBuf>) -> Self {
+        Self {
+            db: db.into(),
+            index: None,
+            cache: None,
+            clock: None,
+            fast: Vec::new(),
+            slow: Vec::new(),
+            archive: Vec::new(),
+            fast_placement: None,
+            slow_placement: None,
+            archive_placement: None,
+            policy: Arc::new(PopularityPolicy::default()),
+            duplicate_policy: DuplicatePolicy::default(),
+            journal: true,
+            shared: false,
+            throttle: (None, None),
+            io_limits: None,
+            schedule: None,
+            hooks: Vec::new(),
+            fuse: FuseConfig::default(),
+        }
+    }
+
+    /// Backends, placements, policy and duplicate handling from a config
+    /// file, the way `rhss mount` builds them. Backend roots must exist.
+    /// FUSE options are left at their defaults; see `with_fuse_config`.
+    pub fn from_config(cfg: &RhssConfig) -> Result<Self> {
+        let op_timeout = match cfg.fuse.op_timeout_secs {
+            Some(0) => None,
+            Some(s) => Some(Duration::from_secs(s)),
+            None => Some(DEFAULT_OP_TIMEOUT),
+        };
+        // Bound every backend call so a hung disk or S3 endpoint surfaces
+        // as ETIMEDOUT instead of an unkillable kernel request.
+        let with_timeout = move |b: Arc<dyn Backend>| -> Arc<dyn Backend> {
+            match op_timeout {
+                Some(t) => TimeoutBackend::new(b, t),
+                None => b,
+            }
+        };
+        // Cold backends (a NAS, an object store) retry outages and, once
+        // one stays down, fail fast with EIO until a probe sees it back.
+        let breaker = cfg.breaker.to_config()?;
+        let cold = |b: Arc<dyn Backend>| -> Arc<dyn Backend> { BreakerBackend::new(b, breaker) };
+        let posix = |b: &crate::config::BackendConfig| -> Result<Arc<dyn Backend>> {
+            if let Some(size) = b.memory_size() {
+                return Ok(Arc::new(MemoryBackend::with_cost(
+                    b.id.clone(),
+                    crate::quota::parse_size(size)?,
+                    b.cost_per_gb_month,
+                )));
+            }
+            if let Some(mode) = b.dedup {
+                return Ok(Arc::new(DedupBackend::new(
+                    b.id.clone(),
+                    b.root.clone(),
+                    mode,
+                    b.cost_per_gb_month,
+                )?));
+            }
+            let direct_io = match &b.direct_io_above {
+                Some(size) => Some(crate::config::direct_io_limit(&b.id, size)?),
+                None => None,
+            };
+            let dir = |p: PosixBackend| match direct_io {
+                Some(above) => p.with_direct_io(above),
+                None => p,
+            };
+            let primary: Arc<dyn Backend> = Arc::new(dir(PosixBackend::with_cost(
+                b.id.clone(),
+                b.root.clone(),
+                b.cost_per_gb_month,
+            )?));
+            if b.replicas.is_empty() {
+                return Ok(primary);
+            }
+            let replicas = b
+                .replicas
+                .iter()
+                .enumerate()
+                .map(|(n, root)| -> Result<Arc<dyn Backend>> {
+                    Ok(Arc::new(dir(PosixBackend::new(
+                        format!("{}~{}", b.id, n + 1),
+                        root.clone(),
+                    )?)))
+                })
+                .collect::<Result<_>>()?;
+            Ok(ReplicatedBackend::new(primary, replicas, b.replication))
+        };
+        let tier_backend = move |b: &crate::config::BackendConfig| -> Result<Arc<dyn Backend>> {
+            let backend = shard_dirs_above(b.shard_dirs_above, posix(b)?);
+            let backend = chunk_large_files(&b.id, b.chunk_large_files.as_deref(), backend)?;
+            let backend = pack_small_files(&b.id, b.pack_small_files.as_deref(), backend)?;
+            Ok(with_timeout(backend))
+        };
+
+        let mut builder = Self::new(&cfg.db)
+            .with_policy(Arc::new(cfg.policy.to_policy()))
+            .with_duplicate_policy(cfg.duplicate_policy)
+            .with_throttle(cfg.throttle.limits()?)
+            .with_io_limits(cfg.io_limits()?)
+            .with_schedule(cfg.schedule.to_schedule()?)
+            .with_hooks(cfg.hooks()?)
+            .with_placement(TierId::Fast, make_placement(cfg.tier.fast_policy.as_ref())?)
+            .with_placement(TierId::Slow, make_placement(cfg.tier.slow_policy.as_ref())?);
+        for b in &cfg.tier.fast {
+            builder = builder.with_fast(tier_backend(b)?);
+        }
+        // A slow disk that isn't there yet doesn't stop the mount; see
+        // `DeferredBackend`.
+        for b in &cfg.tier.slow {
+            let reopen = {
+                let b = b.clone();
+                move || tier_backend(&b)
+            };
+            let backend = DeferredBackend::open(&b.id, &b.root, REATTACH_EVERY, reopen);
+            builder = builder.with_slow(cold(backend));
+        }
+
+        // Archive tier (optional). Each S3-style backend needs its creds via
+        // env vars (config holds the env-var NAMES, never the secrets).
+        if !cfg.tier.archive.is_empty() {
+            builder = builder.with_placement(
+                TierId::Archive,
+                make_placement(cfg.tier.archive_policy.as_ref())?,
+            );
+        }
+        for a in &cfg.tier.archive {
+            let staging = a.staging_dir.clone().unwrap_or_else(|| {
+                cfg.db
+                    .parent()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join(".rhss_staging")
+                    .join(&a.id)
+            });
+            if let Some(url) = &a.url {
+                let backend = if url.starts_with("http://") {
+                    http_backend(a, url, staging)?
+                } else if let Some(dir) = url.strip_prefix("packed://") {
+                    packed_backend(a, dir, staging)?
+                } else {
+                    object_backend(a, url, staging)?
+                };
+                let backend = chunk_large_files(&a.id, a.chunk_large_files.as_deref(), backend)?;
+                let backend = pack_small_files(&a.id, a.pack_small_files.as_deref(), backend)?;
+                builder = builder.with_archive(cold(with_timeout(backend)));
+                continue;
+            }
+            let env = |name: &str| {
+                std::env::var(name).map_err(|_| {
+                    FsError::Storage(format!("archive backend {} missing env var {name}", a.id))
+                })
+            };
+            let backend = S3Backend::new(S3Config {
+                id: a.id.clone(),
+                endpoint: a.endpoint.clone(),
+                bucket: a.bucket.clone(),
+                region: a.region.clone(),
+                storage_class: a.storage_class.clone(),
+                access_key: env(&a.access_key_env)?,
+                secret_key: env(&a.secret_key_env)?,
+                staging_root: staging,
+                prefix: a.prefix.clone(),
+                cost_per_gb_month: a.cost_per_gb_month,
+            })
+            .map_err(|e| FsError::Storage(format!("init archive backend {}: {e}", a.id)))?;
+            let backend = chunk_large_files(&a.id, a.chunk_large_files.as_deref(), backend)?;
+            let backend = pack_small_files(&a.id, a.pack_small_files.as_deref(), backend)?;
+            builder = builder.with_archive(cold(with_timeout(backend)));
+        }
+        Ok(builder)
+    }
+
+    /// Add a backend to the fast tier. At least one is required.
+    pub fn with_fast(mut self, backend: Arc<dyn Backend>) -> Self {
+        self.fast.push(backend);
+        self
+    }
+
+    /// Add a backend to the slow tier. At least one is required.
+    pub fn with_slow(mut self, backend: Arc<dyn Backend>) -> Self {
+        self.slow.push(backend);
+        self
+    }
+
+    /// Add a backend to the archive tier. Without any, rhss runs two tiers.
+    pub fn with_archive(mut self, backend: Arc<dyn Backend>) -> Self {
+        self.archive.push(backend);
+        self
+    }
+
+    /// How `tier` picks a backend for new files. Defaults to most-free.
+    pub fn with_placement(mut self, tier: TierId, placement: Box<dyn Placement>) -> Self {
+        let slot = match tier {
+            TierId::Fast => &mut self.fast_placement,
+            TierId::Slow => &mut self.slow_placement,
+            TierId::Archive => &mut self.archive_placement,
+        };
+        *slot = Some(placement);
+        self
+    }
+
+    /// Use `index` instead of opening the SQLite index at the builder's
+    /// `db`, e.g. a test double. `db` still locates the migration journal.
+    /// It brings its own cache and clock: `build` refuses it together with
+    /// `with_cache` or `with_clock`.
+    pub fn with_index(mut self, index: Arc<dyn PathIndex>) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Lookup cache in front of the index (`LocationCache`). Defaults to
+    /// an LRU of `DEFAULT_CACHE_ENTRIES` locations.
+    pub fn with_cache(mut self, cache: Box<dyn LocationCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Time source for file ages in the index and the tierer's schedule
+    /// (`crate::clock`), so tests can age files past `min_age_to_evict`
+    /// without waiting.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Watermarks and migration thresholds. Defaults to
+    /// `PopularityPolicy::default()`; swap it later via `Rhss::policy`.
+    pub fn with_policy(mut self, policy: Arc<dyn TieringPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// What the first scan does with a path present on several backends.
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Journal migrations next to the index so a crash mid-move is
+    /// repaired on the next `build` (default on). The journal is
+    /// process-wide: when embedding several instances, enable it for one.
+    pub fn with_journal(mut self, on: bool) -> Self {
+        self.journal = on;
+        self
+    }
+
+    /// Run next to the instance that owns the storage (`rhss mount
+    /// --shared`): `build` skips journal recovery and the first scan, and
+    /// starts neither the tierer nor access tracking, so nothing but the
+    /// FUSE side touches the storage. Pair with a read-only `FuseConfig`.
+    pub fn with_shared(mut self, on: bool) -> Self {
+        self.shared = on;
+        self
+    }
+
+    /// `(migration, cold_reads)` bandwidth limits in bytes per second,
+    /// installed process-wide by `build` (`crate::throttle`). Default
+    /// unlimited.
+    pub fn with_throttle(mut self, limits: (Option<u64>, Option<u64>)) -> Self {
+        self.throttle = limits;
+        self
+    }
+
+    /// Per-uid and per-tenant ceilings on FUSE reads and writes,
+    /// installed process-wide by `build` (`crate::throttle`). Default none.
+    pub fn with_io_limits(mut self, limits: Option<Arc<IoLimits>>) -> Self {
+        self.io_limits = limits;
+        self
+    }
+
+    /// Scheduled demotion sweeps, installed process-wide by `build`
+    /// (`crate::tierer::schedule`). Default none.
+    pub fn with_schedule(mut self, schedule: Option<Schedule>) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Event hooks, installed process-wide by `build` (`crate::hooks`).
+    /// Default none.
+    pub fn with_hooks(mut self, hooks: Vec<Hook>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Mount options, caches, filters, trash, quotas and audit. Quotas are
+    /// seeded from the index during `build`.
+    pub fn with_fuse_config(mut self, fuse: FuseConfig) -> Self {
+        self.fuse = fuse;
+        self
+    }
+
+    /// Open the index, settle interrupted migrations, scan the backends and
+    /// start the tierer. Fails on cross-backend path conflicts the
+    /// duplicate policy doesn't resolve.
+    pub fn build(self) -> Result<Rhss> {
+        // An injected index would silently ignore them.
+        if self.index.is_some() && (self.cache.is_some() || self.clock.is_some()) {
+            return Err(FsError::Storage(
+                "with_index takes an index with its own cache and clock; \
+                 drop with_cache/with_clock or give them to the index"
+                    .into(),
+            ));
+        }
+        let placement =
+            |p: Option<Box<dyn Placement>>| p.unwrap_or_else(|| Box::new(MostFreePlacement));
+        let fast = Tier::new(TierId::Fast, self.fast, placement(self.fast_placement))?;
+        let slow = Tier::new(TierId::Slow, self.slow, placement(self.slow_placement))?;
+        let mut router = TierRouter::new(fast, slow);
+        if !self.archive.is_empty() {
+            let n = self.archive.len();
+            let archive = Tier::new(
+                TierId::Archive,
+                self.archive,
+                placement(self.archive_placement),
+            )?;
+            router = router.with_archive(archive);
+            info!("archive tier configured with {n} backend(s)");
+        }
+        let router = Arc::new(router);
+        // A shared mount only reads storage its owner already checked, and
+        // may not be allowed to write it.
+        if !self.shared {
+            preflight::check(&preflight::roots(&router))?;
+        }
+
+        crate::throttle::set_limit(crate::throttle::Class::Migration, self.throttle.0);
+        crate::throttle::set_limit(crate::throttle::Class::ColdRead, self.throttle.1);
+        crate::throttle::set_io_limits(self.io_limits);
+        crate::tierer::set_schedule(self.schedule);
+        crate::hooks::set_hooks(self.hooks);
+
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let index: Arc<dyn PathIndex> = match self.index {
+            Some(index) => index,
+            None => {
+                let cache = self
+                    .cache
+                    .unwrap_or_else(|| Box::new(LruCache::new(DEFAULT_CACHE_ENTRIES)));
+                SqlitePathIndex::open_with(&self.db, cache, Arc::clone(&clock)).map_err(|e| {
+                    FsError::Storage(format!("open index {}: {e}", self.db.display()))
+                })?
+            }
+        };
+
+        // Settle migrations a previous run was killed in the middle of,
+        // before the scan could mistake their leftovers for conflicts.
+        match (self.journal && !self.shared).then(|| Journal::open(journal_dir_for(&self.db))) {
+            None => {}
+            Some(Ok(journal)) => {
+                match journal::recover(&journal, &router, &index) {
+                    Ok(st) if st != Default::default() => info!(
+                        rolled_back = st.rolled_back,
+                        rolled_forward = st.rolled_forward,
+                        abandoned = st.abandoned,
+                        "recovered interrupted migrations"
+                    ),
+                    Ok(_) => {}
+                    Err(e) => return Err(FsError::Storage(format!("journal recovery: {e}"))),
+                }
+                set_journal(Some(journal));
+            }
+            Some(Err(e)) => warn!("migration journal disabled: {e}"),
+        }
+
+        // A shared instance leaves the index to the owner.
+        if !self.shared {
+            if index.count().unwrap_or(0) == 0 {
+                info!("path index is empty, running first scan");
+            }
+            let stats = scan::first_scan(&router, &index, self.duplicate_policy)
+                .map_err(|e| FsError::Storage(format!("first scan: {e}")))?;
+            if !stats.conflicts.is_empty() {
+                for p in stats.conflicts.iter().take(CONFLICTS_SHOWN) {
+                    error!("  conflict: {}", p.display());
+                }
+                return Err(FsError::Storage(format!(
+                    "first-scan hard-fail: {} cross-backend logical-path conflicts \
+                     (set duplicate_policy to resolve them automatically)",
+                    stats.conflicts.len()
+                )));
+            }
+        }
+        // A shared instance is read-only; the owner made them.
+        if let Some(t) = self.fuse.tenants().filter(|_| !self.shared) {
+            t.prepare(&router)?;
+        }
+        if let Some(q) = self.fuse.quotas() {
+            q.seed(&router, &index)?;
+        }
+
+        let open_tracker = Arc::new(OpenFileTracker::new());
+        let policy = ReloadablePolicy::new(self.policy);
+        let (tierer, tierer_handle, access) = if self.shared {
+            let (tierer, handle) = Tierer::idle();
+            (tierer, handle, None)
+        } else {
+            let access = AccessTracker::start(Arc::clone(&index), ACCESS_FLUSH);
+            // Tenants' own minimum ages apply to the tierer only.
+            let tiering: Arc<dyn TieringPolicy> = match self.fuse.tenants() {
+                Some(t) => TenantPolicy::new(policy.clone(), Arc::clone(t)),
+                None => policy.clone(),
+            };
+            let (tierer, handle) = Tierer::spawn(
+                Arc::clone(&router),
+                Arc::clone(&index),
+                Arc::clone(&open_tracker),
+                tiering,
+                clock,
+            );
+            info!("background tierer started");
+            (tierer, handle, Some(access))
+        };
+
+        let adapter = FuseAdapter::new(
+            Arc::clone(&router),
+            Arc::clone(&index),
+            policy.clone(),
+            Arc::clone(&open_tracker),
+            (!self.shared).then(|| tierer_handle.clone()),
+            access,
+            self.fuse,
+        );
+        Ok(Rhss {
+            adapter,
+            router,
+            index,
+            open_tracker,
+            policy,
+            tierer_handle,
+            _tierer: tierer,
+        })
+    }
+}
+
+/// A ready-to-mount rhss instance. Dropping it stops the tierer; call
+/// `adapter().stop()` first if it was mounted.
+pub struct Rhss {
+    adapter: FuseAdapter,
+    router: Arc<TierRouter>,
+    index: Arc<dyn PathIndex>,
+    open_tracker: Arc<OpenFileTracker>,
+    policy: Arc<ReloadablePolicy>,
+    tierer_handle: TiererHandle,
+    _tierer: Tierer,
+}
+
+impl Rhss {
+    /// Same as `RhssBuilder::new`.
+    pub fn builder(db: impl Into<PathBuf>) -> RhssBuilder {
+        RhssBuilder::new(db)
+    }
+
+    /// Mount on a background thread. The filesystem stays mounted until
+    /// the returned session is dropped.
+    pub fn mount(&self, mount_point: &Path) -> Result<fuser::BackgroundSession> {
+        preflight::check_mount(&preflight::roots(&self.router), mount_point)?;
+        let session = self
+            .adapter
+            .spawn_mount(mount_point)
+            .map_err(|e| FsError::from_io(e, mount_point.display()))?;
+        self.adapter.attach_notifier(session.notifier());
+        Ok(session)
+    }
+
+    /// The FUSE filesystem itself; `stats()` reports its caches.
+    pub fn adapter(&self) -> &FuseAdapter {
+        &self.adapter
+    }
+
+    /// Backends per tier, for capacity (`statvfs`) and direct access.
+    pub fn router(&self) -> &Arc<TierRouter> {
+        &self.router
+    }
+
+    pub fn index(&self) -> &Arc<dyn PathIndex> {
+        &self.index
+    }
+
+    /// Files currently open through the mount.
+    pub fn open_tracker(&self) -> &Arc<OpenFileTracker> {
+        &self.open_tracker
+    }
+
+    /// Trigger, pause or wait for migrations.
+    pub fn tierer(&self) -> &TiererHandle {
+        &self.tierer_handle
+    }
+
+    /// The live tiering policy; `replace` takes effect on the next pass.
+    pub fn policy(&self) -> &Arc<ReloadablePolicy> {
+        &self.policy
+    }
+}
+
+fn http_backend(
+    a: &crate::config::ArchiveBackendConfig,
+    url: &str,
+    staging_root: PathBuf,
+) -> Result<Arc<dyn Backend>> {
+    let backend = HttpBackend::new(HttpConfig {
+        id: a.id.clone(),
+        url: url.to_string(),
+        credential_env: a.credential_env.clone(),
+        read_only: a.read_only,
+        staging_root,
+        cost_per_gb_month: a.cost_per_gb_month,
+    })
+    .map_err(|e| FsError::Storage(format!("init archive backend {}: {e}", a.id)))?;
+    Ok(Arc::new(backend))
+}
+
+/// Wrap `backend` in a `SmallFileBackend` when `pack_small_files` is set.
+fn pack_small_files(
+    id: &str,
+    threshold: Option<&str>,
+    backend: Arc<dyn Backend>,
+) -> Result<Arc<dyn Backend>> {
+    let Some(size) = threshold else {
+        return Ok(backend);
+    };
+    let threshold = crate::config::pack_threshold(id, size)?;
+    Ok(Arc::new(SmallFileBackend::new(backend, threshold)?))
+}
+
+/// Wrap `backend` in a `ShardedBackend` when `shard_dirs_above` is set.
+fn shard_dirs_above(threshold: Option<usize>, backend: Arc<dyn Backend>) -> Arc<dyn Backend> {
+    match threshold {
+        Some(n) => Arc::new(ShardedBackend::new(backend, n)),
+        None => backend,
+    }
+}
+
+/// Wrap `backend` in a `ChunkedBackend` when `chunk_large_files` is set.
+fn chunk_large_files(
+    id: &str,
+    limit: Option<&str>,
+    backend: Arc<dyn Backend>,
+) -> Result<Arc<dyn Backend>> {
+    let Some(size) = limit else {
+        return Ok(backend);
+    };
+    let limit = crate::config::chunk_limit(id, size)?;
+    Ok(Arc::new(ChunkedBackend::new(backend, limit)))
+}
+
+fn packed_backend(
+    a: &crate::config::ArchiveBackendConfig,
+    dir: &str,
+    staging_root: PathBuf,
+) -> Result<Arc<dyn Backend>> {
+    let backend = PackedBackend::new(a.id.clone(), dir, staging_root, a.cost_per_gb_month)
+        .map_err(|e| FsError::Storage(format!("init archive backend {}: {e}", a.id)))?;
+    Ok(Arc::new(backend))
+}
+
+#[cfg(feature = "object-store")]
+fn object_backend(
+    a: &crate::config::ArchiveBackendConfig,
+    url: &str,
+    staging_root: PathBuf,
+) -> Result<Arc<dyn Backend>> {
+    use crate::backend::{ObjectStoreBackend, ObjectStoreConfig};
+
+    let backend = ObjectStoreBackend::new(ObjectStoreConfig {
+        id: a.id.clone(),
+        url: url.to_string(),
+        credential_env: a.credential_env.clone(),
+        max_retries: a.max_retries,
+        retry_timeout: a.retry_timeout_secs.map(Duration::from_secs),
+        staging_root,
+        cost_per_gb_month: a.cost_per_gb_month,
+    })
+    .map_err(|e| FsError::Storage(format!("init archive backend {}: {e}", a.id)))?;
+    Ok(backend)
+}
+
+#[cfg(not(feature = "object-store"))]
+fn object_backend(
+    a: &crate::config::ArchiveBackendConfig,
+    _url: &str,
+    _staging_root: PathBuf,
+) -> Result<Arc<dyn Backend>> {
+    Err(FsError::Storage(format!(
+        "archive backend {}: url needs rhss built with the `object-store` feature",
+        a.id
+    )))
+}
+
+fn make_placement(pol: Option<&TierPolicy>) -> Result<Box<dyn Placement>> {
+    let name = pol.map(|p| p.placement.as_str()).unwrap_or("most_free");
+    Ok(match name {
+        "most_free" => Box::new(MostFreePlacement),
+        "round_robin" => Box::new(RoundRobinPlacement::new()),
+        "mirror" => Box::new(MirrorPlacement::new()),
+        "cost_aware" => Box::new(CostAwarePlacement::new()),
+        other => return Err(FsError::Storage(format!("unknown placement: {other}"))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::Location;
+    use std::collections::HashMap;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn build_indexes_existing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ssd, hdd) = (dir.path().join("ssd"), dir.path().join("hdd"));
+        std::fs::create_dir_all(ssd.join("docs")).unwrap();
+        std::fs::create_dir_all(&hdd).unwrap();
+        std::fs::write(ssd.join("docs/a.txt"), b"hello").unwrap();
+        std::fs::write(hdd.join("b.bin"), b"x").unwrap();
+
+        let rhss = RhssBuilder::new(dir.path().join("idx.db"))
+            .with_fast(Arc::new(PosixBackend::new("ssd", &ssd).unwrap()))
+            .with_slow(Arc::new(PosixBackend::new("hdd", &hdd).unwrap()))
+            .with_journal(false)
+            .build()
+            .unwrap();
+        assert_eq!(rhss.index().count().unwrap(), 2);
+        assert!(rhss.router().archive.is_none());
+        assert!(!rhss.tierer().is_paused());
+    }
+
+    #[test]
+    fn shared_build_leaves_the_index_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ssd, hdd) = (dir.path().join("ssd"), dir.path().join("hdd"));
+        std::fs::create_dir_all(&ssd).unwrap();
+        std::fs::create_dir_all(&hdd).unwrap();
+        std::fs::write(ssd.join("a.txt"), b"hello").unwrap();
+        let build = |shared: bool| {
+            RhssBuilder::new(dir.path().join("idx.db"))
+                .with_fast(Arc::new(PosixBackend::new("ssd", &ssd).unwrap()))
+                .with_slow(Arc::new(PosixBackend::new("hdd", &hdd).unwrap()))
+                .with_journal(false)
+                .with_shared(shared)
+                .build()
+                .unwrap()
+        };
+        let owner = build(false);
+        // Dropped in after the owner's scan: only the owner may index it.
+        std::fs::write(hdd.join("b.bin"), b"x").unwrap();
+        let shared = build(true);
+        assert_eq!(shared.index().count().unwrap(), 1);
+        assert!(shared.tierer().is_paused());
+        drop(owner);
+    }
+
+    #[test]
+    fn builds_over_a_memory_tier() {
+        let dir = tempfile::tempdir().unwrap();
+        let hdd = dir.path().join("hdd");
+        std::fs::create_dir_all(&hdd).unwrap();
+        std::fs::write(hdd.join("b.bin"), b"x").unwrap();
+
+        let rhss = RhssBuilder::new(dir.path().join("idx.db"))
+            .with_fast(Arc::new(MemoryBackend::new("ram", 1 << 20)))
+            .with_slow(Arc::new(PosixBackend::new("hdd", &hdd).unwrap()))
+            .with_journal(false)
+            .build()
+            .unwrap();
+        assert_eq!(rhss.index().count().unwrap(), 1);
+    }
+
+    #[test]
+    fn builds_with_an_injected_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let hdd = dir.path().join("hdd");
+        std::fs::create_dir_all(&hdd).unwrap();
+        std::fs::write(hdd.join("b.bin"), b"x").unwrap();
+        let index: Arc<dyn PathIndex> =
+            SqlitePathIndex::open_with_cache(dir.path().join("mine.db"), NonZeroUsize::MIN)
+                .unwrap();
+
+        let rhss = Rhss::builder(dir.path().join("idx.db"))
+            .with_index(Arc::clone(&index))
+            .with_fast(Arc::new(MemoryBackend::new("ram", 1 << 20)))
+            .with_slow(Arc::new(PosixBackend::new("hdd", &hdd).unwrap()))
+            .with_journal(false)
+            .build()
+            .unwrap();
+        assert!(Arc::ptr_eq(rhss.index(), &index));
+        assert_eq!(index.count().unwrap(), 1);
+        // The builder's own db was never opened.
+        assert!(!dir.path().join("idx.db").exists());
+    }
+
+    #[test]
+    fn an_injected_index_keeps_its_own_clock() {
+        let dir = tempfile::tempdir().unwrap();
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(dir.path().join("mine.db")).unwrap();
+        let err = Rhss::builder(dir.path().join("idx.db"))
+            .with_index(index)
+            .with_clock(Arc::new(crate::clock::MockClock::default()))
+            .with_fast(Arc::new(MemoryBackend::new("ram", 1 << 20)))
+            .with_slow(Arc::new(MemoryBackend::new("ram2", 1 << 20)))
+            .with_journal(false)
+            .build()
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("with_clock"), "{err}");
+    }
+
+    #[test]
+    fn lookups_go_through_an_injected_cache() {
+        /// Remembers everything, and what was put.
+        struct Recording(
+            HashMap<PathBuf, Location>,
+            Arc<parking_lot::Mutex<Vec<PathBuf>>>,
+        );
+
+        impl LocationCache for Recording {
+            fn get(&mut self, logical: &Path) -> Option<&mut Location> {
+                self.0.get_mut(logical)
+            }
+            fn put(&mut self, logical: PathBuf, loc: Location) {
+                self.1.lock().push(logical.clone());
+                self.0.insert(logical, loc);
+            }
+            fn pop(&mut self, logical: &Path) -> Option<Location> {
+                self.0.remove(logical)
+            }
+            fn retain(&mut self, keep: &mut dyn FnMut(&Path) -> bool) {
+                self.0.retain(|k, _| keep(k));
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let hdd = dir.path().join("hdd");
+        std::fs::create_dir_all(&hdd).unwrap();
+        std::fs::write(hdd.join("b.bin"), b"x").unwrap();
+        let puts = Arc::default();
+        let rhss = Rhss::builder(dir.path().join("idx.db"))
+            .with_cache(Box::new(Recording(HashMap::new(), Arc::clone(&puts))))
+            .with_fast(Arc::new(MemoryBackend::new("ram", 1 << 20)))
+            .with_slow(Arc::new(PosixBackend::new("hdd", &hdd).unwrap()))
+            .with_journal(false)
+            .build()
+            .unwrap();
+        for _ in 0..2 {
+            let loc = rhss.index().locate(Path::new("/b.bin")).unwrap().unwrap();
+            assert_eq!(loc.tier, TierId::Slow);
+        }
+        // Missed once, then served from the cache.
+        assert_eq!(*puts.lock(), [PathBuf::from("/b.bin")]);
+    }
+
+    #[test]
+    fn mounts_while_the_slow_disk_is_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let ssd = dir.path().join("ssd");
+        std::fs::create_dir_all(&ssd).unwrap();
+        std::fs::write(ssd.join("hot"), b"x").unwrap();
+        let toml = dir.path().join("rhss.toml");
+        std::fs::write(
+            &toml,
+            format!(
+                "mount = \"/mnt/rhss\"\ndb = {:?}\n\
+                 [[tier.fast]]\nid = \"ssd\"\nroot = {:?}\n\
+                 [[tier.slow]]\nid = \"nas\"\nroot = {:?}\n",
+                dir.path().join("idx.db"),
+                ssd,
+                dir.path().join("nas"),
+            ),
+        )
+        .unwrap();
+        let cfg = RhssConfig::load(&toml).unwrap();
+        let rhss = RhssBuilder::from_config(&cfg)
+            .unwrap()
+            .with_journal(false)
+            .build()
+            .unwrap();
+        assert_eq!(rhss.index().count().unwrap(), 1);
+        let nas = rhss.router().slow.find_backend("nas").unwrap();
+        assert!(!nas.available());
+    }
+
+    #[test]
+    fn build_needs_both_tiers() {
+        let dir = tempfile::tempdir().unwrap();
+        let ssd = dir.path().join("ssd");
+        std::fs::create_dir_all(&ssd).unwrap();
+        let res = RhssBuilder::new(dir.path().join("idx.db"))
+            .with_fast(Arc::new(PosixBackend::new("ssd", &ssd).unwrap()))
+            .with_journal(false)
+            .build();
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! `rhss analyze` — see `crate::analyze`. Reads the index and, when there
+//! is one, the access trace; samples files from the fast and slow
+//! backends for the compression estimate.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::access::trace::{self, TraceRecord};
+use crate::analyze::{self, AnalyzeOptions, WorkloadReport};
+use crate::backend::Backend;
+use crate::error::Result;
+
+use super::common::{fmt_bytes, CliContext};
+use super::AnalyzeArgs;
+
+pub fn run(ctx: &CliContext, args: AnalyzeArgs) -> Result<()> {
+    let cfg = ctx.load_config()?;
+    let rows = ctx.open_index()?.list_under(Path::new("/"))?;
+    let records: Vec<TraceRecord> = match args.trace {
+        Some(path) => trace::read(&path)?,
+        None => {
+            let path = cfg.access_trace.path(&cfg.db);
+            if path.exists() {
+                trace::read(&path)?
+            } else {
+                Vec::new()
+            }
+        }
+    };
+    let opts = AnalyzeOptions {
+        hot_window: Duration::from_secs(args.hot_days * 86_400),
+        top: args.top,
+    };
+    let mut report = analyze::analyze(&rows, &records, SystemTime::now(), &opts);
+
+    if args.sample > 0 {
+        let (_, router) = ctx.build_router()?;
+        let sample: Vec<(Arc<dyn Backend>, _)> = analyze::sample_rows(&rows, args.sample)
+            .into_iter()
+            .filter_map(|r| {
+                let b = router.resolve_backend(r.location.tier, &r.location.backend_id)?;
+                Some((Arc::clone(b), r.location.backend_path.clone()))
+            })
+            .collect();
+        if let Some((sampled, ratio)) = analyze::compression_ratio(&sample) {
+            report.compression = Some(analyze::compression_estimate(&rows, sampled, ratio));
+        }
+    }
+
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report, &opts);
+    }
+    Ok(())
+}
+
+fn print_report(r: &WorkloadReport, opts: &AnalyzeOptions) {
+    println!("{} files, {}", r.files, fmt_bytes(r.bytes));
+    println!();
+    println!("{:>11}  {:>8}  {:>10}", "SIZE", "FILES", "BYTES");
+    for b in &r.sizes {
+        let label = match b.max {
+            Some(max) => format!("<= {}", fmt_bytes(max)),
+            None => "larger".into(),
+        };
+        println!("{:>11}  {:>8}  {:>10}", label, b.files, fmt_bytes(b.bytes));
+    }
+    println!();
+    println!(
+        "hot = accessed within {} days",
+        opts.hot_window.as_secs() / 86_400
+    );
+    println!(
+        "{:<8}  {:>9}  {:>10}  {:>10}  {:>10}",
+        "TIER", "HOT", "HOT_BYTES", "COLD", "COLD_BYTES"
+    );
+    for t in r.tiers.iter().filter(|t| t.hot_files + t.cold_files > 0) {
+        println!(
+            "{:<8}  {:>9}  {:>10}  {:>10}  {:>10}",
+            t.tier,
+            t.hot_files,
+            fmt_bytes(t.hot_bytes),
+            t.cold_files,
+            fmt_bytes(t.cold_bytes)
+        );
+    }
+    println!();
+    println!(
+        "dedup: {} files share content, saving {}",
+        r.dedup.shared_files,
+        fmt_bytes(r.dedup.saved_bytes)
+    );
+    match &r.compression {
+        Some(c) => println!(
+            "compression: {} of {} uncompressed would shrink to ~{:.0}% ({} files sampled), \
+             saving ~{}; {} files already compressed",
+            fmt_bytes(c.candidate_bytes),
+            c.candidate_files,
+            c.ratio * 100.0,
+            c.sampled_files,
+            fmt_bytes(c.estimated_savings),
+            c.compressed_files
+        ),
+        None => println!("compression: no estimate (nothing sampled)"),
+    }
+
+    let Some(t) = &r.trace else {
+        println!();
+        println!("no access trace; enable `[access_trace]` for churn and promotion candidates");
+        return;
+    };
+    println!();
+    println!(
+        "trace: {} records over {}s: {} reads ({:.1}% from fast), {} writes, {} creates, \
+         {} deletes",
+        t.records,
+        t.span_secs,
+        t.reads,
+        t.fast_read_ratio * 100.0,
+        t.writes,
+        t.creates,
+        t.deletes
+    );
+    if !t.churn.is_empty() {
+        println!();
+        println!("{:>8}  {:>10}  DIRECTORY", "OPS", "WRITTEN");
+        for c in &t.churn {
+            println!(
+                "{:>8}  {:>10}  {}",
+                c.ops,
+                fmt_bytes(c.bytes_written),
+                c.dir.display()
+            );
+        }
+    }
+    if !t.promotion_candidates.is_empty() {
+        println!();
+        println!("promotion candidates (read from slow tiers):");
+        println!("{:>8}  {:>10}  PATH", "READS", "SIZE");
+        for c in &t.promotion_candidates {
+            println!(
+                "{:>8}  {:>10}  {}",
+                c.reads,
+                fmt_bytes(c.size),
+                c.path.display()
+            );
+        }
+    }
+}
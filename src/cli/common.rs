@@ -4,9 +4,10 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::backend::Backend;
+use crate::backend::{Backend, MemoryBackend};
 use crate::config::RhssConfig;
 use crate::error::{FsError, Result};
+use crate::hidden;
 use crate::index::{PathIndex, SqlitePathIndex};
 use crate::tier::{MostFreePlacement, Tier, TierRouter};
 use crate::PosixBackend;
@@ -15,6 +16,9 @@ use crate::PosixBackend;
 pub struct CliContext {
     pub config_path: Option<PathBuf>,
     pub json: bool,
+    /// `--volume`: act on this volume (`crate::volume`) instead of the
+    /// main storage.
+    pub volume: Option<String>,
 }
 
 impl CliContext {
@@ -38,14 +42,46 @@ impl CliContext {
             return Ok(etc);
         }
         Err(FsError::Storage(
-            "no config file found (pass --config, set RHSS_CONFIG, or place at ~/.config/rhss/config.toml)"
+            "no config file found (pass --config, set RHSS_CONFIG or RHSS_* settings, or place at ~/.config/rhss/config.toml)"
                 .into(),
         ))
     }
 
+    /// Load the config. While the storage is hidden under a running mount
+    /// (`crate::hidden`), paths under the mount point are mapped into the
+    /// hidden sibling so read-only commands and the control socket lookup
+    /// still work.
     pub fn load_config(&self) -> Result<RhssConfig> {
-        let p = self.resolve_config_path()?;
-        RhssConfig::load(&p)
+        let mut cfg = self.load_config_raw()?;
+        let hidden = hidden::hidden_path(&cfg.mount);
+        if hidden.exists() {
+            let mount = cfg.mount.clone();
+            cfg.relocate_storage(|p| hidden::relocate_path(&mount, &hidden, p));
+        }
+        Ok(cfg)
+    }
+
+    /// The config exactly as written, without hidden-storage mapping. With
+    /// no config file, `RHSS_*` environment variables alone will do. With
+    /// `volume` set, the volume's config derived from it.
+    pub fn load_config_raw(&self) -> Result<RhssConfig> {
+        let cfg = match self.resolve_config_path() {
+            Ok(path) => RhssConfig::load(&path)?,
+            Err(e) => RhssConfig::from_env()?.ok_or(e)?,
+        };
+        match &self.volume {
+            Some(name) => crate::volume::config(&cfg, name),
+            None => Ok(cfg),
+        }
+    }
+
+    /// The same context, acting on `volume` (or the main storage).
+    pub fn with_volume(&self, volume: Option<&str>) -> CliContext {
+        CliContext {
+            config_path: self.config_path.clone(),
+            json: self.json,
+            volume: volume.map(str::to_string),
+        }
     }
 
     /// Open the index read-only-ish. SQLite WAL allows concurrent readers
@@ -64,25 +100,13 @@ impl CliContext {
             .tier
             .fast
             .iter()
-            .map(|b| {
-                Ok(Arc::new(PosixBackend::with_cost(
-                    b.id.clone(),
-                    b.root.clone(),
-                    b.cost_per_gb_month,
-                )?) as Arc<dyn Backend>)
-            })
+            .map(offline_backend)
             .collect::<Result<_>>()?;
         let slow: Vec<Arc<dyn Backend>> = cfg
             .tier
             .slow
             .iter()
-            .map(|b| {
-                Ok(Arc::new(PosixBackend::with_cost(
-                    b.id.clone(),
-                    b.root.clone(),
-                    b.cost_per_gb_month,
-                )?) as Arc<dyn Backend>)
-            })
+            .map(offline_backend)
             .collect::<Result<_>>()?;
         let router = Arc::new(TierRouter::new(
             Tier::new(crate::index::TierId::Fast, fast, Box::new(MostFreePlacement))?,
@@ -92,6 +116,23 @@ impl CliContext {
     }
 }
 
+/// A backend as offline commands see it. A `mem://` backend only exists
+/// inside the daemon, so here it is empty.
+fn offline_backend(b: &crate::config::BackendConfig) -> Result<Arc<dyn Backend>> {
+    if let Some(size) = b.memory_size() {
+        return Ok(Arc::new(MemoryBackend::with_cost(
+            b.id.clone(),
+            crate::quota::parse_size(size)?,
+            b.cost_per_gb_month,
+        )));
+    }
+    Ok(Arc::new(PosixBackend::with_cost(
+        b.id.clone(),
+        b.root.clone(),
+        b.cost_per_gb_month,
+    )?))
+}
+
 fn dirs_home() -> Option<PathBuf> {
     std::env::var_os("HOME").map(PathBuf::from)
 }
@@ -145,6 +186,47 @@ pub fn fmt_timestamp(when: SystemTime) -> String {
     format!("unix:{}", secs)
 }
 
+/// `--until`: `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM`, in UTC. A bare date is
+/// the start of that day.
+pub fn parse_deadline(s: &str) -> std::result::Result<SystemTime, String> {
+    let bad = || format!("{s:?} is not a date like 2024-07-01 or 2024-07-01T18:00 (UTC)");
+    let (date, time) = s.split_once('T').unwrap_or((s, "00:00"));
+    let num = |p: Option<&str>| p.and_then(|p| p.parse::<i64>().ok()).ok_or_else(bad);
+    let mut ymd = date.splitn(3, '-');
+    let (y, m, d) = (num(ymd.next())?, num(ymd.next())?, num(ymd.next())?);
+    let mut hm = time.splitn(2, ':');
+    let (h, min) = (num(hm.next())?, num(hm.next())?);
+    let days_in_month = match m {
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if y < 1970 || !(1..=12).contains(&m) || !(1..=days_in_month).contains(&d) {
+        return Err(bad());
+    }
+    if !(0..24).contains(&h) || !(0..60).contains(&min) {
+        return Err(bad());
+    }
+    let days = crate::civil::days_from_civil(y, m, d);
+    Ok(UNIX_EPOCH + Duration::from_secs((days * 86_400 + h * 3600 + min * 60) as u64))
+}
+
+/// `2024-07-01 00:00 UTC`, what `parse_deadline` reads back.
+pub fn fmt_date(when: SystemTime) -> String {
+    let secs = when
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs() as i64;
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    let (y, m, d) = crate::civil::civil_from_days(days);
+    format!(
+        "{y:04}-{m:02}-{d:02} {:02}:{:02} UTC",
+        rem / 3600,
+        rem % 3600 / 60
+    )
+}
+
 /// Render a usage bar like `[████████░░░░░░░░]`. Width is 16 cells.
 pub fn fmt_bar(used: u64, total: u64) -> String {
     let cells = 16;
@@ -196,6 +278,18 @@ mod tests {
         assert_eq!(bar.matches('░').count(), 0);
     }
 
+    #[test]
+    fn deadlines_parse_as_utc_and_format_back() {
+        let t = parse_deadline("2024-07-01").unwrap();
+        assert_eq!(t, UNIX_EPOCH + Duration::from_secs(1_719_792_000));
+        assert_eq!(fmt_date(t), "2024-07-01 00:00 UTC");
+        let t = parse_deadline("2024-02-29T18:30").unwrap();
+        assert_eq!(fmt_date(t), "2024-02-29 18:30 UTC");
+        for bad in ["2023-02-29", "2024-13-01", "2024-07-01T24:00", "2024-07"] {
+            assert!(parse_deadline(bad).is_err(), "{bad}");
+        }
+    }
+
     #[test]
     fn fmt_timestamp_includes_unix_secs() {
         let ts = fmt_timestamp(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
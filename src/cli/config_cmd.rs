@@ -25,10 +25,65 @@ root = "/Volumes/SSD_256G/.rhss_managed"
 # [[tier.fast]]
 # id   = "ssd-512"
 # root = "/Volumes/SSD_512G/.rhss_managed"
+#
+# A RAM-only backend for scratch data; its contents are gone on unmount.
+# [[tier.fast]]
+# id   = "ram"
+# root = "mem://512M"
 
 [[tier.slow]]
 id   = "hdd-4t"
 root = "/Volumes/HDD_4T/.rhss_managed"
+# dedup = "cdc"      # store as deduplicated chunks ("fixed" or "cdc")
+# pack_small_files = "64K"   # bundle files below this size into pack files
+# chunk_large_files = "1G"   # store files above this size as 64 MiB chunks
+# shard_dirs_above = 10000   # hash-shard new files of directories this big
+# direct_io_above = "256M"   # read files above this size past the page cache
+
+# Optional: cap the bandwidth background migration and reads from the
+# slow/archive tiers may use, in bytes per second. SIGHUP re-applies.
+# [throttle]
+# migration  = "50M"
+# cold_reads = "200M"
+
+# Optional: demote everything past its minimum age on a cron schedule
+# (local time), within a maintenance window and a per-run byte budget.
+# [schedule]
+# sweep     = "0 2 * * *"
+# window    = "02:00-06:00"
+# max_bytes = "500G"
+
+# Optional: how slow/archive backends ride out outages. Idempotent calls
+# are retried with doubling backoff; after `trip_after` failures in a row,
+# cold files fail fast with EIO until a probe finds the backend again.
+# [breaker]
+# retries    = 2
+# backoff_ms = 200
+# trip_after = 5
+# probe_secs = 10
+
+# Optional: backend health probes (canary write/read/delete). `rhss
+# mount` waits up to ready_timeout_secs for green before reporting ready.
+# [health]
+# interval_secs      = 60
+# slow_ms            = 1000
+# ready_timeout_secs = 60
+
+# Optional: mark the storage lock file immutable while it is held, so
+# nobody deletes it by hand (Linux needs CAP_LINUX_IMMUTABLE).
+# [lock]
+# immutable = true
+
+# Optional: run a command (event JSON on stdin) or POST a webhook when
+# files are migrated, promoted or repaired, or the fast tier crosses its
+# high watermark. Omit `events` to get all of them.
+# [[hooks]]
+# events  = ["watermark"]
+# command = ["/usr/local/bin/page-oncall"]
+#
+# [[hooks]]
+# events = ["migrated", "promoted"]
+# url    = "http://inventory.lan:8080/rhss/events"
 
 # Optional: archive tier (S3-compatible object storage). Files on Slow that
 # haven't been accessed for `min_age_to_archive` (default 365 days) get
@@ -46,6 +101,34 @@ root = "/Volumes/HDD_4T/.rhss_managed"
 # secret_key_env  = "R2_SECRET_KEY"
 # # staging_dir   = "/var/cache/rhss/r2"    # default = <db.parent>/.rhss_staging/<id>
 # # prefix        = "rhss"                  # objects stored at <prefix>/<logical>
+#
+# Google Cloud Storage or Azure Blob instead (build with `--features
+# object-store`). Credentials come from the provider's usual env vars
+# (GOOGLE_APPLICATION_CREDENTIALS, AZURE_STORAGE_ACCOUNT_NAME/KEY, ...) or
+# from the env vars named in `credential_env`.
+#
+# [[tier.archive]]
+# id             = "gcs-archive"
+# url            = "gs://rhss-archive/rhss"     # or "az://<container>/<prefix>"
+# # credential_env = { service_account_key = "GCS_KEY" }
+# # max_retries        = 10
+# # retry_timeout_secs = 180
+#
+# Or an existing nginx/WebDAV server over plain http (terminate TLS in a
+# proxy). Basic auth from the env vars named in `credential_env`.
+#
+# [[tier.archive]]
+# id             = "dav-archive"
+# url            = "http://archive.lan:8080/rhss/"
+# # read_only      = true                       # serve an existing archive only
+# # credential_env = { username = "DAV_USER", password = "DAV_PASS" }
+#
+# Or a directory of old .tar / .tar.zst / .zip files, browsed in place
+# (read-only; each archive appears as a directory of its contents).
+#
+# [[tier.archive]]
+# id             = "old-packs"
+# url            = "packed:///srv/archives"
 "#;
 
 pub fn run(ctx: &CliContext, cmd: ConfigCmd) -> Result<()> {
@@ -61,7 +144,7 @@ fn show(ctx: &CliContext) -> Result<()> {
     if ctx.json {
         println!("{}", serde_json::to_string_pretty(&ShowJson::from(&cfg))?);
     } else {
-        println!("config:      {}", ctx.resolve_config_path()?.display());
+        println!("config:      {}", source(ctx));
         println!("mount:       {}", cfg.mount.display());
         println!("db:          {}", cfg.db.display());
         println!("fast tier:");
@@ -75,6 +158,15 @@ fn show(ctx: &CliContext) -> Result<()> {
         if !cfg.tier.archive.is_empty() {
             println!("archive tier:");
             for a in &cfg.tier.archive {
+                if let Some(url) = &a.url {
+                    let ro = if a.read_only || url.starts_with("packed://") {
+                        "  (read-only)"
+                    } else {
+                        ""
+                    };
+                    println!("  {:<14} {url}{ro}", a.id);
+                    continue;
+                }
                 println!(
                     "  {:<14} s3://{}/{}  (endpoint {}, class {})",
                     a.id, a.bucket, a.prefix, a.endpoint, a.storage_class
@@ -86,22 +178,30 @@ fn show(ctx: &CliContext) -> Result<()> {
 }
 
 fn check(ctx: &CliContext, override_path: Option<PathBuf>) -> Result<()> {
-    let path = match override_path {
-        Some(p) => p,
-        None => ctx.resolve_config_path()?,
+    let (loaded, source) = match override_path {
+        Some(p) => (crate::config::RhssConfig::load(&p), p.display().to_string()),
+        None => (ctx.load_config_raw(), source(ctx)),
     };
-    match crate::config::RhssConfig::load(&path) {
+    match loaded {
         Ok(_) => {
-            info!("config OK: {}", path.display());
+            info!("config OK: {source}");
             Ok(())
         }
         Err(e) => {
-            error!("config INVALID ({}): {e}", path.display());
+            error!("config INVALID ({source}): {e}");
             std::process::exit(1);
         }
     }
 }
 
+/// Where the config comes from: the file, or only `RHSS_*` variables.
+fn source(ctx: &CliContext) -> String {
+    match ctx.resolve_config_path() {
+        Ok(p) => p.display().to_string(),
+        Err(_) => "RHSS_* environment".into(),
+    }
+}
+
 fn init(path: Option<PathBuf>) -> Result<()> {
     let target = path.unwrap_or_else(|| PathBuf::from("rhss.toml"));
     if target.exists() {
@@ -133,6 +233,8 @@ struct BackendJson {
 #[derive(Serialize)]
 struct ArchiveJson {
     id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
     endpoint: String,
     bucket: String,
     region: String,
@@ -156,6 +258,7 @@ impl From<&crate::config::RhssConfig> for ShowJson {
             .iter()
             .map(|a| ArchiveJson {
                 id: a.id.clone(),
+                url: a.url.clone(),
                 endpoint: a.endpoint.clone(),
                 bucket: a.bucket.clone(),
                 region: a.region.clone(),
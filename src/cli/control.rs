@@ -5,21 +5,24 @@
 //! 2. Connects (`UnixStream::connect`). On `ConnectionRefused` returns a
 //!    friendly "rhss is not mounted" error so the user knows what's up.
 //! 3. Sends one JSON request line.
-//! 4. Reads one JSON response line.
+//! 4. Reads one JSON response line (after any `migrate-progress` lines).
 //! 5. Renders human or `--json` output.
 
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, IsTerminal, Write};
 use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 use tracing::error;
 
 use crate::control::{socket_path_for, Request, Response, ResponseData};
 use crate::error::{FsError, Result};
 
-use super::common::CliContext;
-use super::{FsckArgs, MigrateArgs, OneshotArgs, PinArgs, WhichArgs};
+use super::common::{fmt_bytes, fmt_date, CliContext};
+use super::{
+    FsckArgs, LogLevelArgs, MigrateArgs, OneshotArgs, PinArgs, PreheatArgs, ProfileArgs, TrashCmd,
+    UmountArgs, WhichArgs,
+};
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
 const READ_TIMEOUT: Duration = Duration::from_secs(75);
@@ -33,6 +36,9 @@ pub fn pin(ctx: &CliContext, args: PinArgs) -> Result<()> {
     let req = Request::Pin {
         path: args.path,
         tier: args.tier.into(),
+        until: args
+            .until
+            .map(|t| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()),
     };
     let resp = send(ctx, &req)?;
     render(ctx, resp, "pinned")
@@ -66,15 +72,78 @@ pub fn oneshot(ctx: &CliContext, args: OneshotArgs) -> Result<()> {
     render(ctx, resp, "oneshot triggered")
 }
 
+/// Migrate a file or a directory. A directory migration on a terminal
+/// shows a progress line on stderr; `--report` keeps the final response
+/// as JSON for scripts.
 pub fn migrate(ctx: &CliContext, args: MigrateArgs) -> Result<()> {
+    let show_progress = !ctx.json && !args.dry_run && std::io::stderr().is_terminal();
     let req = Request::Migrate {
         path: args.path,
         to: args.to.into(),
+        progress: show_progress,
+        dry_run: args.dry_run,
     };
-    let resp = send(ctx, &req)?;
+    let mut drawn = false;
+    let resp = send_with_progress(ctx, &req, &mut |d| {
+        let ResponseData::MigrateProgress { progress: p } = d else {
+            return;
+        };
+        eprint!(
+            "\r{}/{} files, {}/{} moved, {} skipped, {} failed",
+            p.files_moved,
+            p.files_to_move,
+            fmt_bytes(p.bytes_moved),
+            fmt_bytes(p.bytes_to_move),
+            p.files_skipped,
+            p.failures.len()
+        );
+        drawn = true;
+    })?;
+    if drawn {
+        eprintln!();
+    }
+    if let Some(report) = &args.report {
+        let body = serde_json::to_vec_pretty(&resp).map_err(FsError::Json)?;
+        std::fs::write(report, body).map_err(FsError::Io)?;
+    }
     render(ctx, resp, "migrated")
 }
 
+pub fn preheat(ctx: &CliContext, args: PreheatArgs) -> Result<()> {
+    let show_progress = !ctx.json && !args.release && std::io::stderr().is_terminal();
+    let req = Request::Preheat {
+        path: args.path,
+        release: args.release,
+        progress: true,
+    };
+    let mut drawn = false;
+    let resp = send_with_progress(ctx, &req, &mut |d| {
+        let ResponseData::PreheatProgress { report: r } = d else {
+            return;
+        };
+        if show_progress {
+            eprint!(
+                "\r{} copied ({}), {} already hot, {} skipped, {} failed",
+                r.files_copied,
+                fmt_bytes(r.bytes_copied),
+                r.files_hot,
+                r.files_skipped,
+                r.failures.len()
+            );
+            drawn = true;
+        }
+    })?;
+    if drawn {
+        eprintln!();
+    }
+    let label = if args.release {
+        "released"
+    } else {
+        "preheated"
+    };
+    render(ctx, resp, label)
+}
+
 pub fn freeze(ctx: &CliContext, want_paused: bool) -> Result<()> {
     let req = if want_paused {
         Request::Freeze
@@ -94,6 +163,51 @@ pub fn fsck(ctx: &CliContext, args: FsckArgs) -> Result<()> {
     render(ctx, resp, "fsck complete")
 }
 
+pub fn log_level(ctx: &CliContext, args: LogLevelArgs) -> Result<()> {
+    let req = Request::LogLevel {
+        filter: args.filter,
+    };
+    let resp = send(ctx, &req)?;
+    render(ctx, resp, "log level set")
+}
+
+/// Switch sampling on, wait, then switch it off and fetch what it saw.
+pub fn profile(ctx: &CliContext, args: ProfileArgs) -> Result<()> {
+    if !(args.rate > 0.0 && args.rate <= 1.0) {
+        return Err(FsError::InvalidOperation(format!(
+            "--rate {} is not in (0, 1]",
+            args.rate
+        )));
+    }
+    let resp = send(
+        ctx,
+        &Request::Profile {
+            rate: Some(args.rate),
+        },
+    )?;
+    if !resp.ok {
+        return render(ctx, resp, "");
+    }
+    eprintln!(
+        "sampling {:.2}% of ops for {}s...",
+        args.rate * 100.0,
+        args.duration
+    );
+    std::thread::sleep(Duration::from_secs(args.duration));
+    let resp = send(ctx, &Request::Profile { rate: Some(0.0) })?;
+    let (Some(path), Some(ResponseData::Profile(report))) = (&args.folded, &resp.data) else {
+        return render(ctx, resp, "profile stopped");
+    };
+    std::fs::write(path, report.folded()).map_err(|e| FsError::from_io(e, path.display()))?;
+    println!(
+        "wrote {} stacks from {} sampled ops to {}",
+        report.stacks.len(),
+        report.sampled_ops,
+        path.display()
+    );
+    Ok(())
+}
+
 pub fn rescan(ctx: &CliContext) -> Result<()> {
     let resp = send(ctx, &Request::Rescan)?;
     render(ctx, resp, "rescan complete")
@@ -104,6 +218,56 @@ pub fn dedup_gc(ctx: &CliContext) -> Result<()> {
     render(ctx, resp, "dedup-gc complete")
 }
 
+/// Ask the daemon to shut down and wait for the mount to go away. With no
+/// daemon left, a stale mount point is detached directly.
+pub fn umount(ctx: &CliContext, args: UmountArgs) -> Result<()> {
+    let cfg = ctx.load_config_raw()?;
+    match try_send(ctx, &Request::Shutdown)? {
+        Some(resp) if !resp.ok => return render(ctx, resp, ""),
+        Some(_) => {
+            let sock_path = socket_path_for(&ctx.load_config()?.db);
+            let deadline = Instant::now() + Duration::from_secs(args.timeout);
+            while sock_path.exists() || crate::fuse::is_mounted(&cfg.mount).unwrap_or(false) {
+                if Instant::now() >= deadline {
+                    return Err(FsError::Storage(format!(
+                        "daemon did not finish shutting down within {}s",
+                        args.timeout
+                    )));
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+        None => {
+            if !crate::fuse::is_mounted(&cfg.mount).map_err(FsError::Io)? {
+                return Err(FsError::Storage(format!(
+                    "rhss is not mounted at {}",
+                    cfg.mount.display()
+                )));
+            }
+            // Daemon gone (crashed / killed) but the kernel still holds the
+            // mount: every access fails with ENOTCONN until it's detached.
+            crate::fuse::unmount(&cfg.mount)
+                .map_err(|e| FsError::from_io(e, cfg.mount.display()))?;
+        }
+    }
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&Response::ok_empty())?);
+    } else {
+        println!("unmounted {}", cfg.mount.display());
+    }
+    Ok(())
+}
+
+pub fn trash(ctx: &CliContext, cmd: TrashCmd) -> Result<()> {
+    let (req, label) = match cmd {
+        TrashCmd::List => (Request::TrashList, "trash listed"),
+        TrashCmd::Restore { id } => (Request::TrashRestore { id }, "restored"),
+        TrashCmd::Purge { id, all } => (Request::TrashPurge { id, all }, "purged"),
+    };
+    let resp = send(ctx, &req)?;
+    render(ctx, resp, label)
+}
+
 // ===== TierArg → wire Tier =====
 
 impl From<super::TierArg> for crate::control::Tier {
@@ -119,6 +283,35 @@ impl From<super::TierArg> for crate::control::Tier {
 // ===== transport =====
 
 fn send(ctx: &CliContext, req: &Request) -> Result<Response> {
+    send_with_progress(ctx, req, &mut |_| {})
+}
+
+/// `send`, handing any `migrate-progress` lines ahead of the response to
+/// `on_progress`.
+fn send_with_progress(
+    ctx: &CliContext,
+    req: &Request,
+    on_progress: &mut dyn FnMut(&ResponseData),
+) -> Result<Response> {
+    let cfg = ctx.load_config()?;
+    try_send_with_progress(ctx, req, on_progress)?.ok_or_else(|| {
+        FsError::Storage(format!(
+            "rhss is not mounted (no daemon at {})",
+            socket_path_for(&cfg.db).display()
+        ))
+    })
+}
+
+/// Like `send`, but `None` when no daemon is listening.
+pub(super) fn try_send(ctx: &CliContext, req: &Request) -> Result<Option<Response>> {
+    try_send_with_progress(ctx, req, &mut |_| {})
+}
+
+fn try_send_with_progress(
+    ctx: &CliContext,
+    req: &Request,
+    on_progress: &mut dyn FnMut(&ResponseData),
+) -> Result<Option<Response>> {
     let cfg = ctx.load_config()?;
     let sock_path = socket_path_for(&cfg.db);
     let stream = match connect_with_timeout(&sock_path, CONNECT_TIMEOUT) {
@@ -126,10 +319,7 @@ fn send(ctx: &CliContext, req: &Request) -> Result<Response> {
         Err(e) if e.kind() == std::io::ErrorKind::NotFound
             || e.kind() == std::io::ErrorKind::ConnectionRefused =>
         {
-            return Err(FsError::Storage(format!(
-                "rhss is not mounted (no daemon at {})",
-                sock_path.display()
-            )));
+            return Ok(None);
         }
         Err(e) => return Err(FsError::Io(e)),
     };
@@ -145,10 +335,17 @@ fn send(ctx: &CliContext, req: &Request) -> Result<Response> {
     drop(writer);
 
     let mut reader = BufReader::new(stream);
-    let mut line = String::new();
-    reader.read_line(&mut line).map_err(FsError::Io)?;
-    let resp: Response = serde_json::from_str(line.trim()).map_err(FsError::Json)?;
-    Ok(resp)
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).map_err(FsError::Io)?;
+        let resp: Response = serde_json::from_str(line.trim()).map_err(FsError::Json)?;
+        match &resp.data {
+            Some(
+                d @ (ResponseData::MigrateProgress { .. } | ResponseData::PreheatProgress { .. }),
+            ) => on_progress(d),
+            _ => return Ok(Some(resp)),
+        }
+    }
 }
 
 fn connect_with_timeout(path: &Path, _timeout: Duration) -> std::io::Result<UnixStream> {
@@ -189,10 +386,36 @@ fn render_data(d: ResponseData) {
                 if frozen { "tierer FROZEN" } else { "tierer running" }
             );
         }
-        Pinned { path, tier } => match tier {
-            Some(t) => println!("pinned {} → {:?}", path.display(), t),
-            None => println!("unpinned {}", path.display()),
-        },
+        Pinned {
+            path,
+            tier,
+            until,
+            files,
+            off_tier,
+        } => {
+            let what = if files > 1 {
+                format!("{files} files under {}", path.display())
+            } else {
+                path.display().to_string()
+            };
+            match tier {
+                Some(t) => {
+                    let until = until
+                        .map(|s| UNIX_EPOCH + Duration::from_secs(s))
+                        .map(|t| format!(" until {}", fmt_date(t)))
+                        .unwrap_or_default();
+                    println!("pinned {what} → {t:?}{until}");
+                    if off_tier > 0 {
+                        println!(
+                            "{off_tier} not on {t:?} yet; `rhss migrate --to {} {}` moves them",
+                            crate::index::TierId::from(t).as_str(),
+                            path.display()
+                        );
+                    }
+                }
+                None => println!("unpinned {what}"),
+            }
+        }
         Mutability { path, immutable } => println!(
             "{} {}",
             if immutable { "locked" } else { "unlocked" },
@@ -222,6 +445,64 @@ fn render_data(d: ResponseData) {
                 );
             }
         }
+        MigratedTree {
+            path,
+            to,
+            progress,
+            dry_run,
+        } => {
+            let (verb, files, bytes) = if dry_run {
+                ("would move", progress.files_to_move, progress.bytes_to_move)
+            } else {
+                ("moved", progress.files_moved, progress.bytes_moved)
+            };
+            println!(
+                "{}: {verb} {files} of {} files to {:?} ({}), {} skipped, {} failed",
+                path.display(),
+                progress.files_scanned,
+                to,
+                fmt_bytes(bytes),
+                progress.files_skipped,
+                progress.failures.len()
+            );
+            for f in progress.failures.iter().take(50) {
+                println!("  failed: {}: {}", f.path.display(), f.error);
+            }
+        }
+        MigrateProgress { progress } => {
+            println!(
+                "{}/{} files moved",
+                progress.files_moved, progress.files_to_move
+            );
+        }
+        Preheated { path, report } => {
+            if report.files_scanned == 0 {
+                println!(
+                    "{}: released {} preheated files ({})",
+                    path.display(),
+                    report.files_released,
+                    fmt_bytes(report.bytes_released)
+                );
+            } else {
+                println!(
+                    "{}: copied {} of {} files to the fast tier ({}), {} already hot, \
+                     {} skipped, {} failed",
+                    path.display(),
+                    report.files_copied,
+                    report.files_scanned,
+                    fmt_bytes(report.bytes_copied),
+                    report.files_hot,
+                    report.files_skipped,
+                    report.failures.len()
+                );
+            }
+            for f in report.failures.iter().take(50) {
+                println!("  failed: {}: {}", f.path.display(), f.error);
+            }
+        }
+        PreheatProgress { report } => {
+            println!("{} files preheated", report.files_copied);
+        }
         FreezeState { frozen } => {
             println!("tierer is now {}", if frozen { "FROZEN" } else { "RUNNING" });
         }
@@ -229,13 +510,15 @@ fn render_data(d: ResponseData) {
             orphans,
             ghosts,
             inconsistencies,
+            partial,
             repaired,
         } => {
             println!(
-                "fsck: {} orphans, {} ghosts, {} replica inconsistencies, {} repaired",
+                "fsck: {} orphans, {} ghosts, {} replica inconsistencies, {} partial, {} repaired",
                 orphans.len(),
                 ghosts.len(),
                 inconsistencies.len(),
+                partial.len(),
                 repaired
             );
             for o in orphans.iter().take(50) {
@@ -244,6 +527,9 @@ fn render_data(d: ResponseData) {
             for g in ghosts.iter().take(50) {
                 println!("  ghost:  {}", g.display());
             }
+            for p in partial.iter().take(50) {
+                println!("  partial: {}", p.display());
+            }
             for inc in inconsistencies.iter().take(50) {
                 println!(
                     "  replica-missing: {} (expected on {:?}, missing on {:?})",
@@ -252,7 +538,11 @@ fn render_data(d: ResponseData) {
                     inc.missing
                 );
             }
-            if orphans.len() > 50 || ghosts.len() > 50 || inconsistencies.len() > 50 {
+            if orphans.len() > 50
+                || ghosts.len() > 50
+                || partial.len() > 50
+                || inconsistencies.len() > 50
+            {
                 println!("  (truncated; rerun with --json for the full list)");
             }
         }
@@ -260,12 +550,15 @@ fn render_data(d: ResponseData) {
             added,
             already_indexed,
             conflicts,
+            duplicates_removed,
         } => {
             println!(
-                "rescan: added {} new files, skipped {} already indexed, {} conflicts",
+                "rescan: added {} new files, skipped {} already indexed, {} conflicts, \
+                 {} identical duplicates removed",
                 added,
                 already_indexed,
-                conflicts.len()
+                conflicts.len(),
+                duplicates_removed.len()
             );
             for c in conflicts.iter().take(20) {
                 println!("  conflict: {}", c.display());
@@ -284,6 +577,34 @@ fn render_data(d: ResponseData) {
                 fmt_bytes(bytes_freed)
             );
         }
+        Status(report) => super::status::print_live(&report),
+        Quota { entries } => super::status::print_quota(&entries),
+        Du { entries } => super::status::print_du(&entries),
+        LogLevel { filter } => println!("log filter: {filter}"),
+        Profile(report) => print_profile(&report),
+        Trash { entries } => {
+            use crate::cli::common::fmt_bytes;
+            if entries.is_empty() {
+                println!("trash is empty");
+                return;
+            }
+            println!("{:<28}  {:>10}  {:>8}  PATH", "ID", "SIZE", "AGE");
+            let now = std::time::SystemTime::now();
+            for e in &entries {
+                println!(
+                    "{:<28}  {:>10}  {:>7}h  {}",
+                    e.id,
+                    fmt_bytes(e.size),
+                    e.age(now).as_secs() / 3600,
+                    e.logical.display()
+                );
+            }
+        }
+        TrashRestored { id, path } => println!("restored {} ({id})", path.display()),
+        TrashPurged { entries, bytes } => {
+            use crate::cli::common::fmt_bytes;
+            println!("purged {entries} entries, freed {}", fmt_bytes(bytes));
+        }
     }
 }
 
@@ -291,3 +612,25 @@ fn render_data(d: ResponseData) {
 // translation unit after macros expand.
 #[allow(dead_code)]
 fn _phantom(_p: PathBuf) {}
+
+fn print_profile(report: &crate::profile::ProfileReport) {
+    let total: u64 = report.stacks.iter().map(|s| s.micros).sum();
+    println!(
+        "{} sampled ops, {:.3}s of sampled time",
+        report.sampled_ops,
+        total as f64 / 1e6
+    );
+    if report.stacks.is_empty() {
+        return;
+    }
+    println!("{:>6}  {:>12}  {:>8}  STACK", "SELF%", "SELF_US", "HITS");
+    for s in &report.stacks {
+        println!(
+            "{:>5.1}%  {:>12}  {:>8}  {}",
+            s.micros as f64 * 100.0 / total.max(1) as f64,
+            s.micros,
+            s.hits,
+            s.stack
+        );
+    }
+}
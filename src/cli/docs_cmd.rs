@@ -0,0 +1,46 @@
+//! `rhss completions <shell>` / `rhss man` — generated from the clap
+//! definitions at runtime, so packagers get completions and man pages that
+//! match the binary they ship.
+
+use std::io;
+
+use clap::CommandFactory;
+
+use crate::error::{FsError, Result};
+
+use super::{Cli, CompletionsArgs, ManArgs};
+
+pub fn completions(args: CompletionsArgs) -> Result<()> {
+    clap_complete::generate(args.shell, &mut Cli::command(), "rhss", &mut io::stdout());
+    Ok(())
+}
+
+pub fn man(args: ManArgs) -> Result<()> {
+    let cmd = Cli::command().name("rhss");
+    match args.dir {
+        None => clap_mangen::Man::new(cmd).render(&mut io::stdout())?,
+        Some(dir) => {
+            std::fs::create_dir_all(&dir).map_err(|e| FsError::from_io(e, dir.display()))?;
+            clap_mangen::generate_to(cmd, &dir).map_err(|e| FsError::from_io(e, dir.display()))?;
+            println!("wrote man pages to {}", dir.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_man_page_per_subcommand() {
+        let dir = tempfile::tempdir().unwrap();
+        man(ManArgs {
+            dir: Some(dir.path().to_path_buf()),
+        })
+        .unwrap();
+        for page in ["rhss.1", "rhss-mount.1", "rhss-lock-status.1"] {
+            assert!(dir.path().join(page).exists(), "{page}");
+        }
+    }
+}
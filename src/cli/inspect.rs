@@ -11,7 +11,7 @@ use tracing::error;
 use crate::error::Result;
 use crate::index::{FileRow, TierId};
 
-use super::common::{fmt_age, fmt_bytes, fmt_timestamp, CliContext};
+use super::common::{fmt_age, fmt_bytes, fmt_date, fmt_timestamp, CliContext};
 use super::{TopArgs, WhichArgs};
 
 pub fn which(ctx: &CliContext, args: WhichArgs) -> Result<()> {
@@ -146,17 +146,18 @@ pub fn list_pinned(ctx: &CliContext) -> Result<()> {
         println!("(no pinned files)");
     } else {
         println!(
-            "{:<32} {:<5} {:<10} PINNED TO",
-            "LOGICAL PATH", "TIER", "SIZE"
+            "{:<32} {:<5} {:<10} {:<9} UNTIL",
+            "LOGICAL PATH", "TIER", "SIZE", "PINNED TO"
         );
         for r in &rows {
             let pin = r.pinned_tier.map(tier_name).unwrap_or("-");
             println!(
-                "{:<32} {:<5} {:<10} {}",
+                "{:<32} {:<5} {:<10} {:<9} {}",
                 truncate(&r.logical_path.display().to_string(), 32),
                 tier_name(r.location.tier),
                 fmt_bytes(r.location.size),
-                pin
+                pin,
+                r.pinned_until.map(fmt_date).unwrap_or_else(|| "-".into())
             );
         }
     }
@@ -179,9 +180,14 @@ fn print_explain(r: &FileRow) {
     );
     println!("Hit count:    {}", r.hit_count);
     println!("Popularity:   {:.1}", r.popularity);
-    match r.pinned_tier {
-        Some(t) => println!("Pinned:       yes → {}", tier_name(t)),
-        None => println!("Pinned:       no"),
+    match (r.pinned_tier, r.pinned_until) {
+        (Some(t), Some(until)) => println!(
+            "Pinned:       yes → {} until {}",
+            tier_name(t),
+            fmt_date(until)
+        ),
+        (Some(t), None) => println!("Pinned:       yes → {}", tier_name(t)),
+        (None, _) => println!("Pinned:       no"),
     }
     println!("Mutability:   {:?}", r.mutability);
     if r.compressed {
@@ -233,7 +239,7 @@ fn tier_name(t: TierId) -> &'static str {
 
 /// Accept "/Movies/x.mkv", "Movies/x.mkv", or a full path that begins with the
 /// mount point. Normalise to a leading-/ logical path.
-fn normalize_logical(p: &std::path::Path) -> PathBuf {
+pub(super) fn normalize_logical(p: &std::path::Path) -> PathBuf {
     let s = p.display().to_string();
     if s.starts_with('/') {
         PathBuf::from(s)
@@ -273,6 +279,7 @@ struct RowJson {
     hit_count: u64,
     popularity: f64,
     pinned_tier: Option<&'static str>,
+    pinned_until_unix: Option<i64>,
     state: String,
 }
 
@@ -293,6 +300,11 @@ fn row_to_json(r: &FileRow) -> RowJson {
         hit_count: r.hit_count,
         popularity: r.popularity,
         pinned_tier: r.pinned_tier.map(tier_name),
+        pinned_until_unix: r.pinned_until.map(|t| {
+            t.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0)
+        }),
         state: format!("{:?}", r.state),
     }
 }
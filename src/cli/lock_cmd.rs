@@ -0,0 +1,228 @@
+//! `rhss lock status / break / serve` — inspect or clear the storage lock
+//! (`crate::lock`), so nobody has to delete `.rhss.lock` by hand, or run
+//! the lease service (`crate::lease`). Also the lock setup and `--force`
+//! interlock shared by `rhss mount` and `rhss serve-*`.
+
+use std::io::{BufRead, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use tracing::{info, warn};
+
+use crate::audit::AuditLog;
+use crate::config::RhssConfig;
+use crate::daemon;
+use crate::error::{FsError, Result};
+use crate::lease::LeaseServer;
+use crate::lock::{self, LockStatus, StorageLock};
+
+use super::common::{fmt_age, fmt_timestamp, CliContext};
+use super::LockCmd;
+
+pub fn run(ctx: &CliContext, cmd: LockCmd) -> Result<()> {
+    match cmd {
+        LockCmd::Status => status(ctx),
+        LockCmd::Break { yes } => break_lock(ctx, yes),
+        LockCmd::Serve {
+            listen,
+            allow_remote,
+        } => serve(listen, allow_remote),
+    }
+}
+
+/// The exclusive storage lock for `cfg`; `serving` names the mount point
+/// or served address, for `rhss lock status`.
+pub(super) fn storage_lock(cfg: &RhssConfig, serving: String) -> StorageLock {
+    let lock_dir = cfg.lock_dir();
+    let mut lock = StorageLock::new(&lock_dir, &lock_dir)
+        .with_mount(serving)
+        .with_immutable(cfg.lock.immutable)
+        .with_lease(cfg.lock.lease(&lock_dir));
+    if cfg.lock.host_markers {
+        let roots = cfg
+            .tier
+            .fast
+            .iter()
+            .chain(cfg.tier.slow.iter())
+            .filter(|b| b.memory_size().is_none())
+            .flat_map(|b| std::iter::once(&b.root).chain(&b.replicas));
+        lock = lock.with_host_markers(roots);
+    }
+    lock
+}
+
+/// The lease service has no authentication, so it only leaves loopback
+/// when asked to.
+fn check_listen(listen: SocketAddr, allow_remote: bool) -> Result<()> {
+    if listen.ip().is_loopback() || allow_remote {
+        return Ok(());
+    }
+    Err(FsError::Storage(format!(
+        "not serving leases on {listen} without --allow-remote: the lease service has no \
+         authentication, anyone who can reach it can take the lock"
+    )))
+}
+
+fn serve(listen: SocketAddr, allow_remote: bool) -> Result<()> {
+    check_listen(listen, allow_remote)?;
+    let server = LeaseServer::start(listen)?;
+    println!("lease service listening on {}", server.local_addr());
+    if let Err(e) = daemon::install_signal_handlers() {
+        warn!("install signal handlers: {e}");
+    }
+    while !daemon::stop_requested() {
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    info!("signal received, shutting down");
+    drop(server);
+    Ok(())
+}
+
+fn status(ctx: &CliContext) -> Result<()> {
+    let cfg = ctx.load_config()?;
+    let dir = cfg.lock_dir();
+    let mut status = lock::inspect(&dir).map_err(|e| FsError::Storage(e.to_string()))?;
+    if let Some(lease) = cfg.lock.lease(&dir) {
+        match lease.status() {
+            Ok(resp) => status.lease = Some(resp),
+            Err(e) => warn!("{e}"),
+        }
+    }
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        print_status(&status);
+    }
+    Ok(())
+}
+
+/// Breaking is refused while the holder is a live process on this host;
+/// otherwise it needs `--yes`.
+fn break_lock(ctx: &CliContext, yes: bool) -> Result<()> {
+    let cfg = ctx.load_config()?;
+    let dir = cfg.lock_dir();
+    if !yes {
+        let status = lock::inspect(&dir).map_err(|e| FsError::Storage(e.to_string()))?;
+        print_status(&status);
+        return Err(FsError::Storage(
+            "not breaking the lock without --yes; make sure the holder is really gone".into(),
+        ));
+    }
+    let status = lock::break_lock(&dir).map_err(|e| FsError::Storage(e.to_string()))?;
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else if status.held {
+        println!("broke the lock held by {}", holder_line(&status));
+    } else {
+        println!("lock was not held; removed {}", status.lock_file.display());
+    }
+    Ok(())
+}
+
+/// `--force` without `--really-force` on a lock whose holder looks alive:
+/// ask on a terminal. Returns what to pass as `force_lock`'s `really`;
+/// without a terminal that stays `false` and `force_lock` refuses.
+pub(super) fn confirm_force(lock_dir: &Path, really: bool) -> Result<bool> {
+    if really || !std::io::stdin().is_terminal() {
+        return Ok(really);
+    }
+    let status = lock::inspect(lock_dir).map_err(|e| FsError::Storage(e.to_string()))?;
+    if !status.looks_live() {
+        return Ok(false);
+    }
+    print_status(&status);
+    eprint!(
+        "the holder looks alive; two writers will corrupt the storage. \
+         Type \"yes\" to take over anyway: "
+    );
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| FsError::from_io(e, "stdin"))?;
+    if answer.trim() != "yes" {
+        return Err(FsError::Storage("not taking over a live lock".into()));
+    }
+    Ok(true)
+}
+
+/// Log each lock `force_lock` took over, and record a `force-lock` audit
+/// event when there is an audit log.
+pub(super) fn record_takeover(audit: Option<&AuditLog>, taken: &[LockStatus]) {
+    for status in taken {
+        let note = format!(
+            "took over the storage lock from {}{}",
+            holder_line(status),
+            status
+                .heartbeat_age
+                .map_or(String::new(), |s| format!(", heartbeat {s}s ago"))
+        );
+        warn!("{}: {note}", status.lock_file.display());
+        if let Some(audit) = audit {
+            audit.event("force-lock", status.lock_file.clone(), note);
+        }
+    }
+}
+
+fn print_status(status: &LockStatus) {
+    println!("lock file:  {}", status.lock_file.display());
+    println!("state:      {}", if status.held { "held" } else { "free" });
+    println!(
+        "readers:    {}",
+        if status.readers { "yes (rhss mount --shared)" } else { "none" }
+    );
+    // A free lock file only holds what the last holder left behind.
+    if let Some(lease) = &status.lease {
+        match (&lease.holder, lease.expires_in_secs) {
+            (Some(holder), Some(secs)) if lease.ok => {
+                println!("lease:      {holder} (expires in {secs}s)")
+            }
+            _ => println!("lease:      free"),
+        }
+    }
+    let (true, Some(h)) = (status.held, &status.holder) else {
+        return;
+    };
+    println!("holder:     {}", holder_line(status));
+    if let Some(age) = status.heartbeat_age {
+        println!("heartbeat:  {age}s ago");
+    }
+    if let Some(m) = &h.mount {
+        println!("mount:      {m}");
+    }
+    let since = UNIX_EPOCH + Duration::from_secs(h.created_at);
+    println!("since:      {} ({})", fmt_age(since), fmt_timestamp(since));
+    println!("version:    {}", h.version);
+    if let Some(b) = &h.build {
+        println!("build:      {} [{}]", b.git_commit, b.features.join(", "));
+    }
+}
+
+fn holder_line(status: &LockStatus) -> String {
+    let Some(h) = &status.holder else {
+        return "an unknown process".into();
+    };
+    let state = match status.alive {
+        Some(true) => "running",
+        Some(false) => "exited, pid reused or heartbeat stopped",
+        None => "other host, unknown",
+    };
+    format!("PID {} @ {} ({state})", h.pid, h.hostname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lease_service_stays_on_loopback_unless_allowed() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert!(check_listen(addr("127.0.0.1:7390"), false).is_ok());
+        assert!(check_listen(addr("[::1]:7390"), false).is_ok());
+        assert!(check_listen(addr("0.0.0.0:7390"), false).is_err());
+        assert!(check_listen(addr("10.0.0.5:7390"), false).is_err());
+        assert!(check_listen(addr("0.0.0.0:7390"), true).is_ok());
+    }
+}
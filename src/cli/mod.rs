@@ -5,22 +5,44 @@
 //! lands in V1 via Unix-socket IPC.
 
 use std::path::PathBuf;
+use std::time::SystemTime;
 
 use clap::{Args, Parser, Subcommand};
 
-use crate::error::Result;
+use crate::build_info::BuildInfo;
+use crate::error::{FsError, Result};
+use crate::logging::LogOptions;
 
+pub mod analyze_cmd;
 pub mod common;
 pub mod config_cmd;
 pub mod control;
+pub mod docs_cmd;
 pub mod inspect;
+pub mod lock_cmd;
 pub mod mount_cmd;
+pub mod report_cmd;
+pub mod serve_cmd;
+pub mod simulate_cmd;
 pub mod status;
+pub mod supervise;
+pub mod tune_cmd;
+pub mod volume_cmd;
 
 /// `rhss` — Rust Hybrid Storage System.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(disable_version_flag = true, arg_required_else_help = true)]
 pub struct Cli {
+    /// Print the version and exit.
+    #[arg(short = 'V', long)]
+    pub version: bool,
+
+    /// With `--version`: also the git commit, cargo features and FUSE ABI
+    /// of this build. Honors `--json`.
+    #[arg(long, requires = "version")]
+    pub build_info: bool,
+
     /// Output machine-readable JSON instead of human tables.
     /// Honored by every read-only command.
     #[arg(long, global = true)]
@@ -31,18 +53,36 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub config: Option<PathBuf>,
 
+    /// Act on this volume (see `rhss volume`) instead of the main storage,
+    /// e.g. `rhss --volume pvc-1 status`.
+    #[arg(long, global = true, value_name = "NAME")]
+    pub volume: Option<String>,
+
+    #[command(flatten)]
+    pub log: LogOptions,
+
     #[command(subcommand)]
-    pub cmd: Cmd,
+    pub cmd: Option<Cmd>,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Cmd {
     /// Foreground-mount rhss (existing behavior).
-    Mount(MountArgs),
+    Mount(Box<MountArgs>),
+
+    /// Serve the namespace over WebDAV instead of mounting it, so clients
+    /// can map it as a network drive.
+    ServeWebdav(ServeWebdavArgs),
+
+    /// Serve the namespace over 9P2000.L for VM and container guests.
+    #[command(name = "serve-9p")]
+    Serve9p(Serve9pArgs),
 
     // === read-only inspect ===
 
-    /// One-screen status dashboard: tier capacity + indexed total + pinned.
+    /// Health of the running mount (tier usage, caches, pending
+    /// migrations, open handles). Falls back to the on-disk view when no
+    /// daemon is running.
     Status,
 
     /// Per-backend capacity table.
@@ -70,11 +110,17 @@ pub enum Cmd {
     Replicas(WhichArgs),
 
     /// Project monthly storage cost based on per-backend cost_per_gb_month.
+    /// See `rhss report costs` for the cost of what is stored, by directory.
     Cost,
 
+    /// Reports for chargeback and planning.
+    #[command(subcommand)]
+    Report(ReportCmd),
+
     // === control (require daemon) ===
 
-    /// Pin a file to a tier so the tierer never evicts it.
+    /// Pin a file, or everything under a directory, to a tier so the
+    /// tierer never evicts it; `--until` makes it a reservation that lapses.
     Pin(PinArgs),
 
     /// Clear a file's tier pin.
@@ -82,7 +128,8 @@ pub enum Cmd {
 
     /// Mark a file immutable (write-once). Tierer can demote aggressively;
     /// Slow tier may compress; can be deduped with other identical files.
-    Lock(WhichArgs),
+    /// `lock status` / `lock break` manage the storage lock instead.
+    Lock(LockArgs),
 
     /// Mark a file mutable again. Reverses `lock`.
     Unlock(WhichArgs),
@@ -90,9 +137,13 @@ pub enum Cmd {
     /// Trigger one tier-eviction cycle immediately.
     Oneshot(OneshotArgs),
 
-    /// Force a single file to a specific tier.
+    /// Force a file, or every file under a directory, to a specific tier.
     Migrate(MigrateArgs),
 
+    /// Copy a cold directory onto the fast tier ahead of a job, keeping the
+    /// cold copies authoritative; `--release` drops the copies again.
+    Preheat(PreheatArgs),
+
     /// Pause the background tierer.
     Freeze,
 
@@ -111,17 +162,250 @@ pub enum Cmd {
     /// Health-check the control socket.
     Ping,
 
+    /// Show or change the running daemon's log filter, e.g. `debug` or
+    /// `rhss::fuse=trace,info`, without restarting it.
+    LogLevel(LogLevelArgs),
+
+    /// Sample a fraction of the running mount's FUSE ops for a while and
+    /// show where their time went (index, backend calls), or write it as
+    /// folded stacks for a flamegraph.
+    Profile(ProfileArgs),
+
+    /// Stop the running daemon and unmount.
+    Umount(UmountArgs),
+
+    /// Inspect and recover soft-deleted files (`[trash] enabled = true`).
+    #[command(subcommand)]
+    Trash(TrashCmd),
+
+    /// Live rhss mounts on this host, from the runtime directory.
+    Mounts,
+
+    /// Quota usage against the `[quota]` limits.
+    #[command(subcommand)]
+    Quota(QuotaCmd),
+
+    /// Usage, limits and monthly cost of each `[tenants]` directory, from
+    /// the index.
+    Tenants,
+
+    /// Bytes and files per tier under a directory, from the index.
+    Du(DuArgs),
+
+    /// Per-volume slices of the tiers, each with its own index, lock and
+    /// policy: the primitives behind a CSI driver.
+    #[command(subcommand)]
+    Volume(VolumeCmd),
+
+    // === offline tuning ===
+
+    /// Replay a workload script against the policy (and variations of
+    /// it) without touching storage; report fast-tier hit rates and
+    /// migration volume.
+    Simulate(SimulateArgs),
+
+    /// Shape of the workload from the index and the access trace: file
+    /// sizes, hot and cold bytes per tier, churn, and what dedup and
+    /// compression save or could.
+    Analyze(AnalyzeArgs),
+
+    /// Find the watermarks and eviction age that keep the fast tier under
+    /// a target usage over the recorded access trace, by simulation;
+    /// `--apply` hands them to the running daemon.
+    Tune(TuneArgs),
+
     // === config ===
 
     #[command(subcommand)]
     Config(ConfigCmd),
+
+    // === packaging ===
+
+    /// Print a shell completion script.
+    Completions(CompletionsArgs),
+
+    /// Print the man page, or write one per subcommand with `--dir`.
+    Man(ManArgs),
 }
 
 #[derive(Args, Debug)]
 pub struct MountArgs {
-    /// Force startup even if a stale storage lock exists.
+    #[command(flatten)]
+    pub lock: ForceLockArgs,
+
+    /// Detach and run in the background. The launching command exits 0
+    /// once the mount is live, 1 if startup fails. Pair with `--log-file`.
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Stay in the foreground (the default), for container entrypoints
+    /// that spell it out.
+    #[arg(long, visible_alias = "no-daemonize", conflicts_with = "daemon")]
+    pub foreground: bool,
+
+    /// At shutdown, pass the stop signal on to running hook commands and,
+    /// as a container's PID 1, to every other process in the container.
+    #[arg(long)]
+    pub propagate_signals: bool,
+
+    /// Serve `/livez` and `/readyz` over HTTP at ADDR (overrides `[health]
+    /// listen`).
+    #[arg(long, value_name = "ADDR")]
+    pub probe_listen: Option<std::net::SocketAddr>,
+
+    /// Write the daemon's PID here; removed on clean shutdown.
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+
+    /// Let other users access the mount (overrides `[fuse] allow_other`).
+    #[arg(long, conflicts_with = "allow_root")]
+    pub allow_other: bool,
+
+    /// Let root access the mount (overrides `[fuse] allow_root`).
+    #[arg(long)]
+    pub allow_root: bool,
+
+    /// Mount over the directory that holds the storage (overrides
+    /// `hidden_storage` in the config). The existing tree is renamed aside
+    /// for the lifetime of the mount and restored on exit.
+    #[arg(long)]
+    pub hidden_storage: bool,
+
+    /// Mount read-only.
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Mount a read-only view at DIR next to the rhss that owns the
+    /// storage, instead of at the configured mount point. Any number can
+    /// run at once; they don't scan, tier or write anything.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["force", "hidden_storage"])]
+    pub shared: Option<PathBuf>,
+
+    /// What to do when another rhss holds the storage lock: `fail`, or
+    /// `readonly` to mount the configured mount point read-only as
+    /// `--shared` does, next to the writer.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ACTION",
+        default_value_t = IfLocked::Fail,
+        conflicts_with_all = ["force", "shared"]
+    )]
+    pub if_locked: IfLocked,
+
+    /// Replace the rhss running on this config, e.g. after an upgrade: ask
+    /// it to flush and unmount over its control socket, then mount as soon
+    /// as it has let go of the storage. Mounts normally if none is running.
+    #[arg(long, conflicts_with_all = ["force", "shared", "if_locked"])]
+    pub takeover: bool,
+
+    /// Run the mount as a child of a small supervisor that detaches it and
+    /// starts it again, with backoff, whenever it crashes.
+    #[arg(long)]
+    pub supervise: bool,
+
+    /// Disallow executing binaries from the mount.
+    #[arg(long)]
+    pub noexec: bool,
+
+    /// Source name in the mount table (overrides `[fuse] fsname`).
+    #[arg(long, value_name = "NAME", value_parser = crate::fuse::parse_mount_name)]
+    pub fsname: Option<String>,
+
+    /// Filesystem type suffix, `fuse.<SUBTYPE>` (overrides `[fuse] subtype`).
+    #[arg(long, value_name = "SUBTYPE", value_parser = crate::fuse::parse_mount_name)]
+    pub subtype: Option<String>,
+
+    /// Finder volume name (macOS only).
+    #[arg(long)]
+    pub volname: Option<String>,
+
+    /// Extra mount option, passed through verbatim. Repeatable.
+    #[arg(short = 'o', long = "option", value_name = "OPT")]
+    pub options: Vec<String>,
+
+    /// Glob the mount pretends doesn't exist, added to `[fuse] ignore_lookup`.
+    /// Repeatable.
+    #[arg(long = "ignore-lookup", value_name = "GLOB")]
+    pub ignore_lookup: Vec<String>,
+
+    /// Glob hidden from directory listings, added to `[fuse] ignore_list`.
+    /// Repeatable.
+    #[arg(long = "ignore-list", value_name = "GLOB")]
+    pub ignore_list: Vec<String>,
+
+    /// Glob exempt from both ignore lists, added to `[fuse] include`.
+    /// Repeatable.
+    #[arg(long = "include", value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Show storage uids OUTSIDE.. as INSIDE.. on the mount, added to
+    /// `[fuse] uid_map`. Repeatable.
+    #[arg(long = "uid-map", value_name = "INSIDE:OUTSIDE:COUNT")]
+    pub uid_map: Vec<crate::fuse::IdRange>,
+
+    /// Same for gids, added to `[fuse] gid_map`. Repeatable.
+    #[arg(long = "gid-map", value_name = "INSIDE:OUTSIDE:COUNT")]
+    pub gid_map: Vec<crate::fuse::IdRange>,
+
+    /// Permission bits every file reports and is created with, e.g. `0664`
+    /// (overrides `[fuse] file_mode`).
+    #[arg(long, value_name = "MODE", value_parser = crate::fuse::parse_mode)]
+    pub file_mode: Option<u32>,
+
+    /// Same for directories, e.g. `0775` (overrides `[fuse] dir_mode`).
+    #[arg(long, value_name = "MODE", value_parser = crate::fuse::parse_mode)]
+    pub dir_mode: Option<u32>,
+
+    /// Bits cleared from every new file and directory, e.g. `022`
+    /// (overrides `[fuse] umask`).
+    #[arg(long, value_name = "MODE", value_parser = crate::fuse::parse_mode)]
+    pub umask: Option<u32>,
+}
+
+/// Taking over the storage lock; shared by everything that holds it.
+#[derive(Args, Debug)]
+pub struct ForceLockArgs {
+    /// Force startup even if a stale storage lock exists. Refused when the
+    /// holder still looks alive (running here, or a fresh heartbeat from
+    /// another host) unless confirmed on a terminal or `--really-force`.
     #[arg(long, default_value_t = false)]
     pub force: bool,
+
+    /// With `--force`: take the lock over even from a live holder. Two
+    /// writers on one storage corrupt it; only for a holder you know is gone.
+    #[arg(long, requires = "force")]
+    pub really_force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeWebdavArgs {
+    /// Address to listen on. There is no authentication: keep it on
+    /// localhost unless a proxy in front handles that.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    pub listen: std::net::SocketAddr,
+
+    /// Refuse every request that would change the namespace.
+    #[arg(long)]
+    pub read_only: bool,
+
+    #[command(flatten)]
+    pub lock: ForceLockArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct Serve9pArgs {
+    /// `host:port`, or the path of a Unix socket to create. There is no
+    /// authentication: keep TCP on a private address.
+    #[arg(long, value_name = "ADDR|PATH", default_value = "127.0.0.1:5640")]
+    pub listen: crate::ninep::Endpoint,
+
+    /// Refuse every request that would change the namespace.
+    #[arg(long)]
+    pub read_only: bool,
+
+    #[command(flatten)]
+    pub lock: ForceLockArgs,
 }
 
 #[derive(Args, Debug)]
@@ -130,6 +414,43 @@ pub struct WhichArgs {
     pub path: PathBuf,
 }
 
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct LockArgs {
+    /// Logical path inside the mount. A file literally named `status`,
+    /// `break` or `serve` needs a `./` or `/` in front.
+    #[arg(required = true)]
+    pub path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub cmd: Option<LockCmd>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LockCmd {
+    /// Who holds the storage lock: pid, host, age, version, mount point.
+    Status,
+    /// Remove a stale storage lock. Refused while the holder is still
+    /// running on this host.
+    Break {
+        /// Confirm the holder is gone.
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Run a lease service for storage shared between hosts, until
+    /// SIGINT/SIGTERM. Point every host's `[lock] lease_server` at it.
+    Serve {
+        /// Address to listen on. There is no authentication, so anything
+        /// but loopback needs `--allow-remote`.
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:7390")]
+        listen: std::net::SocketAddr,
+        /// Listen on a non-loopback address. Keep it on a private network:
+        /// anyone who can reach the port can take or steal a lease.
+        #[arg(long)]
+        allow_remote: bool,
+    },
+}
+
 #[derive(Args, Debug)]
 pub struct TopArgs {
     /// How many rows. Default 20.
@@ -148,6 +469,10 @@ pub struct PinArgs {
     /// Which tier to pin to. Defaults to fast.
     #[arg(long, value_enum, default_value_t = TierArg::Fast)]
     pub tier: TierArg,
+    /// Release the pin at this date (`2024-07-01`, or `2024-07-01T18:00`;
+    /// UTC), after which the policy tiers the files as usual.
+    #[arg(long, value_name = "DATE", value_parser = common::parse_deadline)]
+    pub until: Option<SystemTime>,
 }
 
 #[derive(Args, Debug)]
@@ -159,21 +484,290 @@ pub struct OneshotArgs {
 
 #[derive(Args, Debug)]
 pub struct MigrateArgs {
-    /// Logical path inside the mount.
+    /// Logical path inside the mount; a directory migrates everything
+    /// under it.
     pub path: PathBuf,
     /// Target tier.
     #[arg(long = "to", value_enum)]
     pub to: TierArg,
+    /// Also write the result as JSON to this file.
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<PathBuf>,
+    /// Only report what would move, from index and directory metadata;
+    /// no file is read or copied.
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct PreheatArgs {
+    /// Logical directory (or file) inside the mount.
+    pub path: PathBuf,
+    /// Drop the fast-tier copies made by an earlier `preheat`.
+    #[arg(long, default_value_t = false)]
+    pub release: bool,
 }
 
 #[derive(Args, Debug)]
 pub struct FsckArgs {
-    /// Apply repairs: delete ghost index rows, leave orphans untouched
-    /// (orphans need user judgment — could be temp files or new ingests).
+    /// Apply repairs: delete ghost index rows and partially-created files
+    /// that are still empty, leave orphans untouched (orphans need user
+    /// judgment — could be temp files or new ingests).
     #[arg(long, default_value_t = false)]
     pub repair: bool,
 }
 
+#[derive(Args, Debug)]
+pub struct LogLevelArgs {
+    /// New filter, in `RUST_LOG` syntax. Omit to print the current one.
+    /// A config reload (SIGHUP) puts back `log_level` from the config.
+    pub filter: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ProfileArgs {
+    /// Fraction of ops to sample, 0 < RATE <= 1.
+    #[arg(long, default_value_t = 0.01)]
+    pub rate: f64,
+
+    /// How long to sample for.
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub duration: u64,
+
+    /// Write folded stacks (`flamegraph.pl`, `inferno-flamegraph`,
+    /// speedscope) here instead of printing a table.
+    #[arg(long, value_name = "PATH")]
+    pub folded: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct UmountArgs {
+    /// Seconds to wait for the daemon to finish shutting down.
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub timeout: u64,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum VolumeCmd {
+    /// Create a volume: a directory on every fast and slow backend, plus
+    /// its own index and lock. Succeeds if an identical one exists.
+    Create(VolumeCreateArgs),
+    /// Delete a volume and every file in it. Refused while it is mounted;
+    /// succeeds if there is no such volume.
+    Delete {
+        /// Volume name.
+        name: String,
+    },
+    /// Mount a volume at TARGET. Takes the flags of `rhss mount`.
+    Mount(Box<VolumeMountArgs>),
+    /// Stop the volume's daemon and unmount it.
+    Unmount(VolumeUnmountArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct VolumeCreateArgs {
+    /// Volume name: 1-128 of `A-Z a-z 0-9 . _ -`.
+    pub name: String,
+
+    /// Size limit for the whole volume, e.g. `10G`.
+    #[arg(long, value_name = "SIZE")]
+    pub capacity: Option<String>,
+
+    /// Fast-tier usage the volume's tierer evicts down to.
+    #[arg(long, value_name = "FRACTION")]
+    pub low_watermark: Option<f64>,
+
+    /// Fast-tier usage that starts an eviction cycle for the volume.
+    #[arg(long, value_name = "FRACTION")]
+    pub high_watermark: Option<f64>,
+}
+
+#[derive(Args, Debug)]
+pub struct VolumeMountArgs {
+    /// Volume name: 1-128 of `A-Z a-z 0-9 . _ -`.
+    pub name: String,
+
+    /// Where to mount it; recorded for `volume unmount`.
+    pub target: PathBuf,
+
+    #[command(flatten)]
+    pub mount: MountArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct VolumeUnmountArgs {
+    /// Volume name: 1-128 of `A-Z a-z 0-9 . _ -`.
+    pub name: String,
+
+    #[command(flatten)]
+    pub umount: UmountArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DuArgs {
+    /// Logical directory inside the mount. Default `/`.
+    #[arg(default_value = "/")]
+    pub path: PathBuf,
+
+    /// Also list subdirectories this many levels down.
+    #[arg(short = 'd', long, default_value_t = 1)]
+    pub depth: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct SimulateArgs {
+    /// Workload script (`<seconds> <op> <path> [size]` per line, op one of
+    /// read, write, create, delete), or an access trace recorded with
+    /// `[access_trace] enabled = true`.
+    pub trace: PathBuf,
+
+    /// Size of the simulated fast tier, e.g. `500G`.
+    #[arg(long, value_name = "SIZE", value_parser = crate::quota::parse_size)]
+    pub fast_capacity: u64,
+
+    /// Low watermarks to try (comma-separated); default from `[policy]`.
+    #[arg(long, value_name = "RATIO", value_delimiter = ',')]
+    pub low_watermark: Vec<f64>,
+
+    /// High watermarks to try.
+    #[arg(long, value_name = "RATIO", value_delimiter = ',')]
+    pub high_watermark: Vec<f64>,
+
+    /// Minimum ages before eviction to try, in seconds.
+    #[arg(long, value_name = "SECS", value_delimiter = ',')]
+    pub min_age_to_evict: Vec<u64>,
+
+    /// Seconds between tier cycles (negative = only when full).
+    #[arg(long, value_name = "SECS", allow_hyphen_values = true)]
+    pub tier_period: Option<i64>,
+}
+
+#[derive(Args, Debug)]
+pub struct AnalyzeArgs {
+    /// Access trace to read; default the mount's `[access_trace]` file,
+    /// if there is one.
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+
+    /// Files accessed within this many days count as hot.
+    #[arg(long, value_name = "DAYS", default_value_t = 7)]
+    pub hot_days: u64,
+
+    /// Rows of the churn and promotion-candidate lists.
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+
+    /// Files to compress for the compression estimate; 0 skips it.
+    #[arg(long, value_name = "N", default_value_t = 32)]
+    pub sample: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct TuneArgs {
+    /// Highest fast-tier usage to allow, e.g. `80%` or `0.8`.
+    #[arg(long, value_name = "RATIO", value_parser = tune_cmd::parse_ratio)]
+    pub target_hot_usage: f64,
+
+    /// Access trace or workload script to tune for; default the mount's
+    /// `[access_trace]` file.
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+
+    /// Size of the fast tier; default the configured fast backends' total.
+    #[arg(long, value_name = "SIZE", value_parser = crate::quota::parse_size)]
+    pub fast_capacity: Option<u64>,
+
+    /// Replace the running daemon's policy with the result (until the next
+    /// reload; put the printed `[policy]` in the config to keep it).
+    #[arg(long)]
+    pub apply: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CompletionsArgs {
+    /// e.g. `rhss completions bash > /usr/share/bash-completion/completions/rhss`.
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(Args, Debug)]
+pub struct ManArgs {
+    /// Write `rhss.1` and `rhss-<command>.1` for every subcommand here
+    /// instead of printing `rhss.1`.
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReportCmd {
+    /// Monthly cost of the indexed bytes per tier at the `[cost]` rates,
+    /// what the next tier cycle would change, and the most expensive
+    /// directories. Watermark flags price a policy change before it is made.
+    Costs(ReportCostsArgs),
+    /// Bytes and monthly cost per file owner, for chargeback. Stats every
+    /// indexed file; `[usage_report]` writes the same on a schedule.
+    Usage(ReportUsageArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ReportCostsArgs {
+    /// Directories to list.
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+
+    /// Levels below `/` of the listed directories (1 = `/team`).
+    #[arg(long, default_value_t = 1)]
+    pub depth: usize,
+
+    /// Low watermark instead of the configured one.
+    #[arg(long, value_name = "RATIO")]
+    pub low_watermark: Option<f64>,
+
+    /// High watermark instead of the configured one.
+    #[arg(long, value_name = "RATIO")]
+    pub high_watermark: Option<f64>,
+
+    /// Minimum age before eviction, in seconds, instead of the configured one.
+    #[arg(long, value_name = "SECS")]
+    pub min_age_to_evict: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+pub struct ReportUsageArgs {
+    #[arg(long, value_enum, default_value_t = crate::cost::GroupBy::Uid)]
+    pub group_by: crate::cost::GroupBy,
+
+    /// `csv` or `json` instead of a table (`--json` is `--format json`).
+    #[arg(long, value_enum)]
+    pub format: Option<crate::cost::ReportFormat>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TrashCmd {
+    /// Trashed files, oldest first.
+    List,
+    /// Put a trashed file back at its original path.
+    Restore {
+        /// Entry id from `rhss trash list`.
+        id: String,
+    },
+    /// Delete trashed files for good. Without arguments, only entries past
+    /// the retention period.
+    Purge {
+        /// Purge just this entry.
+        #[arg(conflicts_with = "all")]
+        id: Option<String>,
+        /// Purge everything.
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum QuotaCmd {
+    /// Usage per directory / uid against its limit.
+    Report,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum ConfigCmd {
     /// Print the loaded config (with defaults filled in).
@@ -189,6 +783,13 @@ pub enum ConfigCmd {
     },
 }
 
+/// `rhss mount --if-locked`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IfLocked {
+    Fail,
+    Readonly,
+}
+
 #[derive(clap::ValueEnum, Clone, Copy, Debug)]
 pub enum TierArg {
     Fast,
@@ -211,10 +812,22 @@ pub fn run(cli: Cli) -> Result<()> {
     let ctx = common::CliContext {
         config_path: cli.config.clone(),
         json: cli.json,
+        volume: cli.volume.clone(),
     };
 
-    match cli.cmd {
-        Cmd::Mount(args) => mount_cmd::run(&ctx, args),
+    if cli.version {
+        print_version(&ctx, cli.build_info)?;
+        return Ok(());
+    }
+    let Some(cmd) = cli.cmd else {
+        return Err(FsError::InvalidOperation(
+            "no command given; see `rhss --help`".into(),
+        ));
+    };
+    match cmd {
+        Cmd::Mount(args) => mount_cmd::run(&ctx, *args),
+        Cmd::ServeWebdav(args) => serve_cmd::webdav(&ctx, args),
+        Cmd::Serve9p(args) => serve_cmd::ninep(&ctx, args),
         Cmd::Status => status::status(&ctx),
         Cmd::Backends => status::backends(&ctx),
         Cmd::Stats => status::stats(&ctx),
@@ -225,18 +838,52 @@ pub fn run(cli: Cli) -> Result<()> {
         Cmd::ListPinned => inspect::list_pinned(&ctx),
         Cmd::Replicas(args) => inspect::replicas(&ctx, args),
         Cmd::Cost => status::cost(&ctx),
+        Cmd::Report(cmd) => report_cmd::run(&ctx, cmd),
         Cmd::Pin(args) => control::pin(&ctx, args),
         Cmd::Unpin(args) => control::unpin(&ctx, args),
-        Cmd::Lock(args) => control::lock(&ctx, args, true),
+        Cmd::Lock(LockArgs { cmd: Some(c), .. }) => lock_cmd::run(&ctx, c),
+        Cmd::Lock(LockArgs { path, .. }) => control::lock(
+            &ctx,
+            WhichArgs {
+                path: path.unwrap_or_default(),
+            },
+            true,
+        ),
         Cmd::Unlock(args) => control::lock(&ctx, args, false),
         Cmd::Oneshot(args) => control::oneshot(&ctx, args),
         Cmd::Migrate(args) => control::migrate(&ctx, args),
+        Cmd::Preheat(args) => control::preheat(&ctx, args),
         Cmd::Freeze => control::freeze(&ctx, true),
         Cmd::Unfreeze => control::freeze(&ctx, false),
         Cmd::Fsck(args) => control::fsck(&ctx, args),
         Cmd::Rescan => control::rescan(&ctx),
         Cmd::DedupGc => control::dedup_gc(&ctx),
         Cmd::Ping => control::ping(&ctx),
+        Cmd::LogLevel(args) => control::log_level(&ctx, args),
+        Cmd::Profile(args) => control::profile(&ctx, args),
+        Cmd::Umount(args) => control::umount(&ctx, args),
+        Cmd::Trash(c) => control::trash(&ctx, c),
+        Cmd::Mounts => status::mounts(&ctx),
+        Cmd::Quota(QuotaCmd::Report) => status::quota_report(&ctx),
+        Cmd::Tenants => status::tenants(&ctx),
+        Cmd::Du(args) => status::du(&ctx, args),
+        Cmd::Volume(c) => volume_cmd::run(&ctx, c),
+        Cmd::Simulate(args) => simulate_cmd::run(&ctx, args),
+        Cmd::Analyze(args) => analyze_cmd::run(&ctx, args),
+        Cmd::Tune(args) => tune_cmd::run(&ctx, args),
         Cmd::Config(c) => config_cmd::run(&ctx, c),
+        Cmd::Completions(args) => docs_cmd::completions(args),
+        Cmd::Man(args) => docs_cmd::man(args),
+    }
+}
+
+fn print_version(ctx: &common::CliContext, build_info: bool) -> Result<()> {
+    let info = BuildInfo::current();
+    match (build_info, ctx.json) {
+        (false, false) => println!("rhss {}", info.version),
+        (true, false) => println!("{info}"),
+        (false, true) => println!("{}", serde_json::json!({ "version": info.version })),
+        (true, true) => println!("{}", serde_json::to_string_pretty(&info)?),
     }
+    Ok(())
 }
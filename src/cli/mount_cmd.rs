@@ -1,78 +1,156 @@
 //! `rhss mount` — the original foreground-mount flow, now reachable via
 //! subcommand. Same behavior as v2.3's `rhss --config ...`.
 
-use std::path::PathBuf;
-use std::process::Command;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
-use std::time::Duration;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use tracing::{error, info, warn};
 
-use crate::access::AccessTracker;
-use crate::backend::{Backend, S3Backend, S3Config};
-use crate::config::TierPolicy;
-use crate::control::{server::OpContext, socket_path_for, ControlServer};
+use crate::access::trace::AccessTrace;
+use crate::audit::AuditLog;
+use crate::control::{server::OpContext, socket_path_for, ControlServer, Request};
+use crate::cost::{self, TierRates};
+use crate::daemon::{self, PidFile, Readiness};
 use crate::error::{FsError, Result};
+use crate::filter::{PathFilter, RuleKind};
 use crate::fuse::FuseConfig;
-use crate::index::{PathIndex, SqlitePathIndex, TierId};
+use crate::hidden::HiddenStorage;
 use crate::lock::StorageLock;
-use crate::policy::{PopularityPolicy, TieringPolicy};
+use crate::logging;
+use crate::mounts::{MountRecord, Registration, Registry};
+use crate::policy::ReloadablePolicy;
+use crate::preflight;
+use crate::probes::{self, ProbeServer};
+use crate::quota::Quotas;
 use crate::scan;
-use crate::tier::{
-    CostAwarePlacement, MirrorPlacement, MostFreePlacement, Placement, RoundRobinPlacement, Tier,
-    TierRouter,
-};
-use crate::tierer::{OpenFileTracker, Tierer};
-use crate::{FuseAdapter, PosixBackend};
-
-fn make_placement(pol: Option<&TierPolicy>) -> Result<Box<dyn Placement>> {
-    let name = pol.map(|p| p.placement.as_str()).unwrap_or("most_free");
-    Ok(match name {
-        "most_free" => Box::new(MostFreePlacement),
-        "round_robin" => Box::new(RoundRobinPlacement::new()),
-        "mirror" => Box::new(MirrorPlacement::new()),
-        "cost_aware" => Box::new(CostAwarePlacement::new()),
-        other => return Err(FsError::Storage(format!("unknown placement: {other}"))),
-    })
-}
+use crate::tier::TierRouter;
+use crate::trash::{PurgeScope, Trash};
+use crate::{FuseAdapter, RhssBuilder};
 
 use super::common::CliContext;
-use super::MountArgs;
+use super::{control, lock_cmd, supervise};
+use super::{IfLocked, MountArgs};
 
-pub fn run(ctx: &CliContext, args: MountArgs) -> Result<()> {
-    let cfg = ctx.load_config()?;
+pub fn run(ctx: &CliContext, mut args: MountArgs) -> Result<()> {
+    if supervise::is_child() {
+        // The supervisor detached already and owns the PID file.
+        args.daemon = false;
+        args.pid_file = None;
+    } else if args.supervise {
+        return supervise::run(ctx, &args);
+    }
+    if let Some(dir) = &args.shared {
+        return run_shared(ctx, &args, dir);
+    }
+    let mut cfg = ctx.load_config_raw()?;
+    let fuse_cfg = fuse_config(&cfg.fuse, &args)?;
+    let mut record = mount_record(ctx, &fuse_cfg, &cfg.mount, &cfg.db);
 
-    if let Err(e) = std::fs::create_dir_all(&cfg.mount) {
-        error!("create mount point {}: {e}", cfg.mount.display());
+    if std::env::var_os("RUST_LOG").is_none() {
+        if let Some(level) = &cfg.log_level {
+            logging::set_filter(level)?;
+        }
+    }
+    if args.if_locked == IfLocked::Readonly {
+        // Probed before hidden storage is engaged: a running owner may
+        // have hidden it. A mount that wins the race past here is caught
+        // by `acquire`.
+        let mapped = ctx.load_config()?;
+        let held = crate::lock::inspect(&mapped.lock_dir())
+            .map_err(|e| FsError::Storage(e.to_string()))?
+            .held;
+        if held {
+            warn!("storage is locked by another rhss; mounting read-only");
+            return run_shared(ctx, &args, &mapped.mount);
+        }
+    }
+
+    // Ask before detaching: the daemon has no terminal.
+    if args.lock.force {
+        let mapped = ctx.load_config()?;
+        args.lock.really_force =
+            lock_cmd::confirm_force(&mapped.lock_dir(), args.lock.really_force)?;
+    }
+
+    // Fork before any thread exists; everything below runs in the daemon.
+    let readiness = if args.daemon {
+        daemon::daemonize()?
+    } else {
+        Readiness::foreground()
+    };
+    let _pid_file = match &args.pid_file {
+        Some(p) => Some(PidFile::create(p)?),
+        None => None,
+    };
+    handle_signals_early();
+    let probes = start_probes(&cfg, &args);
+
+    // Everything above is cheap; the old instance stops serving from here.
+    if args.takeover {
+        if let Err(e) = take_over(ctx, &cfg.mount) {
+            error!("{e}");
+            std::process::exit(1);
+        }
+    }
+
+    // Checked before anything is hidden, so a refusal leaves it all as it
+    // was.
+    let hiding = cfg.hidden_storage || args.hidden_storage;
+    if let Err(e) = preflight::check_mount(&mount_roots(&cfg, hiding), &cfg.mount) {
+        error!("{e}");
         std::process::exit(1);
     }
+    let hidden = if hiding {
+        let hs = match HiddenStorage::engage(&cfg.mount) {
+            Ok(hs) => hs,
+            Err(e) => {
+                error!("{e}");
+                std::process::exit(1);
+            }
+        };
+        cfg.relocate_storage(|p| hs.relocate(p));
+        Some(hs)
+    } else {
+        None
+    };
+
+    if let Err(e) = std::fs::create_dir_all(&cfg.mount) {
+        give_up(
+            hidden,
+            format!("create mount point {}: {e}", cfg.mount.display()),
+        );
+    }
     if let Some(parent) = cfg.db.parent() {
         if !parent.as_os_str().is_empty() {
             let _ = std::fs::create_dir_all(parent);
         }
     }
 
-    let lock_dir = cfg
-        .db
-        .parent()
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from("."));
-    let lock = Arc::new(std::sync::Mutex::new(StorageLock::new(&lock_dir, &lock_dir)));
-    {
+    let lock = Arc::new(std::sync::Mutex::new(lock_cmd::storage_lock(
+        &cfg,
+        cfg.mount.display().to_string(),
+    )));
+    let taken = {
         let mut g = lock.lock().unwrap();
-        let res = if args.force {
-            g.force_lock()
-        } else {
-            g.try_lock()
-        };
-        if let Err(e) = res {
-            error!("acquire storage lock: {e}");
-            std::process::exit(1);
+        match acquire(&mut g, &args) {
+            Ok(Acquired::Owner(taken)) => taken,
+            Ok(Acquired::Shared) => {
+                drop(g);
+                if let Some(hs) = hidden {
+                    if let Err(e) = hs.release() {
+                        error!("{e}");
+                        std::process::exit(1);
+                    }
+                }
+                let mapped = ctx.load_config()?;
+                let fuse_cfg = fuse_config(&mapped.fuse, &args)?.with_read_only(true);
+                let mount = mapped.mount.clone();
+                return serve_shared(ctx, &args, mapped, fuse_cfg, &mount, readiness, probes);
+            }
+            Err(e) => give_up(hidden, format!("acquire storage lock: {e}")),
         }
-    }
+    };
     info!("acquired storage lock");
 
     let all_roots: Vec<&std::path::Path> = cfg
@@ -80,257 +158,729 @@ pub fn run(ctx: &CliContext, args: MountArgs) -> Result<()> {
         .fast
         .iter()
         .chain(cfg.tier.slow.iter())
-        .map(|b| b.root.as_path())
+        .filter(|b| b.memory_size().is_none())
+        .flat_map(|b| std::iter::once(&b.root).chain(&b.replicas))
+        .map(|p| p.as_path())
         .collect();
     if let Err(e) = scan::ensure_managed_dirs(all_roots.iter().copied()) {
-        error!("prepare backend dirs: {e}");
-        std::process::exit(1);
+        give_up(hidden, format!("prepare backend dirs: {e}"));
     }
 
-    let make_backend = |b: &crate::config::BackendConfig| -> Arc<dyn Backend> {
-        Arc::new(
-            PosixBackend::with_cost(b.id.clone(), b.root.clone(), b.cost_per_gb_month)
-                .expect("backend init"),
-        )
+    let trash = cfg.trash.enabled.then(|| Trash::new(cfg.trash.retention()));
+    let quota_rules = cfg.quota_rules()?;
+    let quota_count = quota_rules.len();
+    let quotas = if quota_rules.is_empty() {
+        None
+    } else {
+        Some(Quotas::new(quota_rules))
     };
-    let fast_backends: Vec<Arc<dyn Backend>> =
-        cfg.tier.fast.iter().map(make_backend).collect();
-    let slow_backends: Vec<Arc<dyn Backend>> =
-        cfg.tier.slow.iter().map(make_backend).collect();
-
-    let fast_pl = match make_placement(cfg.tier.fast_policy.as_ref()) {
-        Ok(p) => p,
-        Err(e) => {
-            error!("fast tier placement: {e}");
-            std::process::exit(1);
-        }
+    let tenants = cfg.tenants()?;
+    let audit = if cfg.audit.enabled {
+        let sink = cfg.audit.sink(&cfg.db);
+        let log = AuditLog::open(sink.clone(), cfg.audit.buffer())?;
+        info!("audit: recording mutating ops to {sink:?}");
+        Some(log)
+    } else {
+        None
     };
-    let slow_pl = match make_placement(cfg.tier.slow_policy.as_ref()) {
-        Ok(p) => p,
-        Err(e) => {
-            error!("slow tier placement: {e}");
-            std::process::exit(1);
-        }
-    };
-    let fast = Tier::new(TierId::Fast, fast_backends, fast_pl).expect("fast tier");
-    let slow = Tier::new(TierId::Slow, slow_backends, slow_pl).expect("slow tier");
-    let mut router = TierRouter::new(fast, slow);
-
-    // Archive tier (optional). Each S3-style backend needs its creds via env
-    // vars (config holds the env-var NAMES, never the secrets).
-    if !cfg.tier.archive.is_empty() {
-        let mut archive_backends: Vec<Arc<dyn Backend>> = Vec::new();
-        for a in &cfg.tier.archive {
-            let staging = a.staging_dir.clone().unwrap_or_else(|| {
-                cfg.db
-                    .parent()
-                    .map(PathBuf::from)
-                    .unwrap_or_else(|| PathBuf::from("."))
-                    .join(".rhss_staging")
-                    .join(&a.id)
-            });
-            let ak = match std::env::var(&a.access_key_env) {
-                Ok(v) => v,
-                Err(_) => {
-                    error!(
-                        "archive backend {} missing env var {}",
-                        a.id, a.access_key_env
-                    );
-                    std::process::exit(1);
-                }
-            };
-            let sk = match std::env::var(&a.secret_key_env) {
-                Ok(v) => v,
-                Err(_) => {
-                    error!(
-                        "archive backend {} missing env var {}",
-                        a.id, a.secret_key_env
-                    );
-                    std::process::exit(1);
-                }
-            };
-            let backend = match S3Backend::new(S3Config {
-                id: a.id.clone(),
-                endpoint: a.endpoint.clone(),
-                bucket: a.bucket.clone(),
-                region: a.region.clone(),
-                storage_class: a.storage_class.clone(),
-                access_key: ak,
-                secret_key: sk,
-                staging_root: staging,
-                prefix: a.prefix.clone(),
-                cost_per_gb_month: a.cost_per_gb_month,
-            }) {
-                Ok(b) => b as Arc<dyn Backend>,
-                Err(e) => {
-                    error!("init archive backend {}: {e}", a.id);
-                    std::process::exit(1);
-                }
-            };
-            archive_backends.push(backend);
-        }
-        let archive_pl = match make_placement(cfg.tier.archive_policy.as_ref()) {
-            Ok(p) => p,
-            Err(e) => {
-                error!("archive tier placement: {e}");
-                std::process::exit(1);
-            }
-        };
-        let archive_tier = Tier::new(TierId::Archive, archive_backends, archive_pl)
-            .map_err(|e| FsError::Storage(format!("archive tier: {e}")))
-            .unwrap_or_else(|e| {
-                error!("{e}");
-                std::process::exit(1);
-            });
-        router = router.with_archive(archive_tier);
-        info!("archive tier configured with {} backend(s)", cfg.tier.archive.len());
-    }
-
-    let router = Arc::new(router);
+    lock_cmd::record_takeover(audit.as_deref(), &taken);
 
-    let index: Arc<dyn PathIndex> = match SqlitePathIndex::open(&cfg.db) {
-        Ok(i) => i,
-        Err(e) => {
-            error!("open index {}: {e}", cfg.db.display());
-            std::process::exit(1);
-        }
+    let access_trace = if cfg.access_trace.enabled {
+        let path = cfg.access_trace.path(&cfg.db);
+        let trace = AccessTrace::open(&path, cfg.access_trace.buffer())?;
+        info!("access trace: recording to {}", path.display());
+        Some(trace)
+    } else {
+        None
     };
 
-    if index.count().unwrap_or(0) == 0 {
-        info!("path index is empty, running first scan");
+    let rhss = match RhssBuilder::from_config(&cfg).and_then(|b| {
+        b.with_fuse_config(
+            fuse_cfg
+                .with_trash(trash.clone())
+                .with_quotas(quotas.clone())
+                .with_tenants(tenants)
+                .with_audit(audit)
+                .with_access_trace(access_trace),
+        )
+        .build()
+    }) {
+        Ok(r) => r,
+        Err(e) => give_up(hidden, e),
+    };
+    if quota_count > 0 {
+        info!("quota: {quota_count} rules, usage counted");
     }
-    match scan::first_scan(&router, &index) {
-        Ok(stats) => {
-            if !stats.conflicts.is_empty() {
-                error!(
-                    count = stats.conflicts.len(),
-                    "first-scan hard-fail: cross-backend logical-path conflicts; aborting"
-                );
-                for p in stats.conflicts.iter().take(20) {
-                    error!("  conflict: {}", p.display());
-                }
-                std::process::exit(1);
-            }
-        }
-        Err(e) => {
-            error!("first scan: {e}");
-            std::process::exit(1);
-        }
+    if let Some(t) = &trash {
+        purge_expired(t, rhss.router());
     }
+    let router = Arc::clone(rhss.router());
+    let adapter = rhss.adapter().clone();
+    let policy_handle = Arc::clone(rhss.policy());
 
-    let access = AccessTracker::start(Arc::clone(&index), Duration::from_secs(5));
-    let open_tracker = Arc::new(OpenFileTracker::new());
-    let policy: Arc<dyn TieringPolicy> = Arc::new(PopularityPolicy::default());
-
-    let (_tierer, tierer_handle) = Tierer::spawn(
-        Arc::clone(&router),
-        Arc::clone(&index),
-        Arc::clone(&open_tracker),
-        Arc::clone(&policy),
-    );
-    info!("background tierer started");
-
-    // Control socket — CLI commands (`rhss pin/oneshot/...`) talk to this.
-    let control_server = match ControlServer::start(
-        socket_path_for(&cfg.db),
-        OpContext {
-            router: Arc::clone(&router),
-            index: Arc::clone(&index),
-            open_tracker: Arc::clone(&open_tracker),
-            tierer: tierer_handle.clone(),
-            config_db_path: cfg.db.clone(),
+    // Control socket — CLI commands (`rhss pin/oneshot/...`) talk to this;
+    // the gRPC admin API, when configured, runs the same ops.
+    let op_ctx = OpContext {
+        router: Arc::clone(&router),
+        index: Arc::clone(rhss.index()),
+        open_tracker: Arc::clone(rhss.open_tracker()),
+        tierer: rhss.tierer().clone(),
+        config_db_path: cfg.db.clone(),
+        mount: cfg.mount.clone(),
+        started: std::time::SystemTime::now(),
+        fuse: Some(adapter.clone()),
+        trash: trash.clone(),
+        quotas,
+        policy: Some(Arc::clone(&policy_handle)),
+    };
+    #[cfg(feature = "grpc")]
+    let grpc_server = match cfg.grpc.listen {
+        Some(addr) => match crate::control::grpc::GrpcServer::start(addr, op_ctx.clone()) {
+            Ok(srv) => Some(srv),
+            Err(e) => {
+                warn!("grpc admin on {addr} disabled: {e}");
+                None
+            }
         },
-    ) {
-        Ok(srv) => Some(srv),
+        None => None,
+    };
+    let control_server = match ControlServer::start(socket_path_for(&cfg.db), op_ctx) {
+        Ok(srv) => {
+            record.socket = Some(socket_path_for(&cfg.db));
+            Some(srv)
+        }
         Err(e) => {
             warn!("control socket disabled: {e}");
             None
         }
     };
 
-    let adapter = FuseAdapter::new(
-        Arc::clone(&router),
-        Arc::clone(&index),
-        Arc::clone(&policy),
-        Arc::clone(&open_tracker),
-        Some(tierer_handle),
-        Some(access),
-        FuseConfig::default(),
-    );
-
-    let session = match adapter.spawn_mount(&cfg.mount) {
+    let session = match rhss.mount(&cfg.mount) {
         Ok(s) => s,
-        Err(e) => {
-            error!("mount {}: {e}", cfg.mount.display());
-            std::process::exit(1);
-        }
+        Err(e) => give_up(hidden, format!("mount {}: {e}", cfg.mount.display())),
     };
     info!("rhss mounted at {}", cfg.mount.display());
 
+    // Hold READY until every fast and slow backend passes a probe, but
+    // not forever: a missing slow disk still leaves hot files usable.
+    let health = cfg.health.to_config();
+    if crate::health::wait_green(&router, &health) {
+        daemon::sd_notify("STATUS=all backends healthy");
+    } else {
+        warn!(
+            "health: backends still failing after {:?}; reporting ready anyway",
+            health.ready_timeout
+        );
+        daemon::sd_notify("STATUS=degraded: some backends are failing, see `rhss status`");
+    }
+    crate::health::spawn(Arc::clone(&router), health);
+    if let Some(secs) = cfg.usage_report.interval_secs {
+        let dir = cfg.usage_report.dir(&cfg.db);
+        info!("usage report: every {secs}s to {}", dir.display());
+        cost::spawn_usage_reports(
+            Arc::clone(rhss.index()),
+            Arc::clone(&router),
+            TierRates::resolve(&cfg.cost, &router),
+            Duration::from_secs(secs),
+            dir,
+            cfg.usage_report.group_by,
+            cfg.usage_report.format,
+        );
+    }
+    crate::otlp::start(Some(Arc::clone(&router)));
+    readiness.ready();
+    let registration = register(&record);
+
     // Silence unused warning when access is moved into adapter via Some(access).
     let _ = ctx.json;
 
-    let stop = Arc::new(AtomicBool::new(false));
-    {
-        let stop = Arc::clone(&stop);
-        if let Err(e) = ctrlc::set_handler(move || {
-            info!("signal received, shutting down");
-            stop.store(true, Ordering::SeqCst);
-        }) {
-            warn!("install signal handler: {e}");
-        }
+    if let Err(e) = daemon::install_signal_handlers() {
+        warn!("install signal handlers: {e}");
     }
-
-    while !stop.load(Ordering::SeqCst) {
+    let mut last_purge = Instant::now();
+    while !daemon::stop_requested() {
+        probes::heartbeat();
+        daemon::reap_orphans();
+        if daemon::take_reload() {
+            reload(ctx, &args, &adapter, &policy_handle);
+        }
+        if let Some(t) = trash
+            .as_ref()
+            .filter(|_| last_purge.elapsed() >= TRASH_PURGE_EVERY)
+        {
+            purge_expired(t, &router);
+            last_purge = Instant::now();
+        }
         std::thread::sleep(Duration::from_millis(200));
     }
+    info!("signal received, shutting down");
 
+    daemon::sd_notify("STOPPING=1");
+    if args.propagate_signals {
+        daemon::propagate_stop();
+    }
     info!("stopping adapter");
     adapter.stop();
+    #[cfg(feature = "grpc")]
+    drop(grpc_server);
     drop(control_server);
+    drop(registration);
     drop(session);
 
-    std::thread::sleep(Duration::from_millis(200));
-    if is_still_mounted(&cfg.mount) {
-        warn!("mount still appears active; running explicit unmount");
-        let _ = unmount(&cfg.mount);
-    }
+    ensure_unmounted(&cfg.mount);
 
+    // Put hidden storage back before letting go of the lock: a
+    // `--takeover` waiting on the lock mounts the moment it is free.
+    if let Some(hs) = hidden {
+        if let Err(e) = hs.release() {
+            // Left hidden; the next mount (or any rhss command's view)
+            // still finds it via the hidden sibling.
+            error!("{e}");
+        }
+    }
     {
         let mut g = lock.lock().unwrap();
         if let Err(e) = g.unlock() {
             warn!("release storage lock: {e}");
         }
     }
+    drop(probes);
     info!("clean shutdown");
+    crate::otlp::flush();
     Ok(())
 }
 
-fn is_still_mounted(mount: &std::path::Path) -> bool {
-    let Ok(out) = Command::new("mount").output() else {
-        return false;
+/// `rhss mount --shared DIR`: a read-only view next to the owner. Takes
+/// the shared storage lock and none of the owner's duties: no scan,
+/// tierer, health canary, control socket, trash purge or audit.
+fn run_shared(ctx: &CliContext, args: &MountArgs, mount: &Path) -> Result<()> {
+    // Mapped like the read-only commands, in case the owner hid the storage.
+    let cfg = ctx.load_config()?;
+    let fuse_cfg = fuse_config(&cfg.fuse, args)?.with_read_only(true);
+    if std::env::var_os("RUST_LOG").is_none() {
+        if let Some(level) = &cfg.log_level {
+            logging::set_filter(level)?;
+        }
+    }
+
+    let readiness = if args.daemon {
+        daemon::daemonize()?
+    } else {
+        Readiness::foreground()
+    };
+    let _pid_file = match &args.pid_file {
+        Some(p) => Some(PidFile::create(p)?),
+        None => None,
     };
-    let s = String::from_utf8_lossy(&out.stdout);
-    s.contains(mount.to_string_lossy().as_ref())
+    handle_signals_early();
+    let probes = start_probes(&cfg, args);
+    serve_shared(ctx, args, cfg, fuse_cfg, mount, readiness, probes)
 }
 
-fn unmount(mount: &std::path::Path) -> std::io::Result<()> {
-    #[cfg(target_os = "macos")]
+/// The read-only mount proper, from the detached process. Also where an
+/// owner mount ends up that lost the lock with `--if-locked readonly`.
+fn serve_shared(
+    ctx: &CliContext,
+    args: &MountArgs,
+    cfg: crate::config::RhssConfig,
+    fuse_cfg: FuseConfig,
+    mount: &Path,
+    readiness: Readiness,
+    probes: Option<ProbeServer>,
+) -> Result<()> {
+    let record = mount_record(ctx, &fuse_cfg, mount, &cfg.db);
+    if let Err(e) = preflight::check_mount(&preflight::config_roots(&cfg), mount) {
+        error!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = std::fs::create_dir_all(mount) {
+        error!("create mount point {}: {e}", mount.display());
+        std::process::exit(1);
+    }
+
+    let mut lock = StorageLock::shared(&cfg.lock_dir());
+    if let Err(e) = lock.try_lock() {
+        error!("acquire shared storage lock: {e}");
+        std::process::exit(1);
+    }
+
+    let rhss = match RhssBuilder::from_config(&cfg)
+        .and_then(|b| b.with_shared(true).with_fuse_config(fuse_cfg).build())
     {
-        let out = Command::new("diskutil")
-            .arg("unmount")
-            .arg(mount.as_os_str())
-            .output()?;
-        if !out.status.success() {
-            let _ = Command::new("umount").arg(mount.as_os_str()).output()?;
+        Ok(r) => r,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let adapter = rhss.adapter().clone();
+    let session = match rhss.mount(mount) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("mount {}: {e}", mount.display());
+            std::process::exit(1);
         }
+    };
+    info!("rhss mounted read-only at {} (shared)", mount.display());
+    // Storage metrics are the owner's to report; only spans from here.
+    crate::otlp::start(None);
+    readiness.ready();
+    let registration = register(&record);
+
+    if let Err(e) = daemon::install_signal_handlers() {
+        warn!("install signal handlers: {e}");
     }
-    #[cfg(target_os = "linux")]
-    {
-        let _ = Command::new("fusermount")
-            .arg("-u")
-            .arg(mount.as_os_str())
-            .output()?;
+    while !daemon::stop_requested() {
+        probes::heartbeat();
+        daemon::reap_orphans();
+        if daemon::take_reload() {
+            info!("SIGHUP: a shared mount has nothing to reload");
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    info!("signal received, shutting down");
+
+    daemon::sd_notify("STOPPING=1");
+    if args.propagate_signals {
+        daemon::propagate_stop();
     }
+    adapter.stop();
+    drop(registration);
+    drop(session);
+    ensure_unmounted(mount);
+    if let Err(e) = lock.unlock() {
+        warn!("release storage lock: {e}");
+    }
+    drop(probes);
+    info!("clean shutdown");
+    crate::otlp::flush();
     Ok(())
 }
+
+/// The storage roots of `cfg` as the mount will use them: where hidden
+/// storage will have moved them if `hiding`. Lets `check_mount` run
+/// before `HiddenStorage::engage`.
+fn mount_roots(cfg: &crate::config::RhssConfig, hiding: bool) -> Vec<preflight::StorageRoot> {
+    let mut roots = preflight::config_roots(cfg);
+    if hiding {
+        let hidden = crate::hidden::hidden_path(&cfg.mount);
+        for r in &mut roots {
+            r.path = crate::hidden::relocate_path(&cfg.mount, &hidden, &r.path);
+        }
+    }
+    roots
+}
+
+/// A startup failure once hidden storage may be engaged: put it back, so
+/// the roots don't stay out of sight, and exit 1.
+fn give_up(hidden: Option<HiddenStorage>, e: impl std::fmt::Display) -> ! {
+    error!("{e}");
+    if let Some(hs) = hidden {
+        if let Err(e) = hs.release() {
+            error!("{e}");
+        }
+    }
+    std::process::exit(1);
+}
+
+/// What a mount gets of the storage lock.
+#[derive(Debug)]
+enum Acquired {
+    /// The lock, and any holders `--force` took it from.
+    Owner(Vec<crate::lock::LockStatus>),
+    /// Someone else has it: mount read-only (`--if-locked readonly`).
+    Shared,
+}
+
+/// Take the storage lock for an owner mount. `--if-locked readonly` is
+/// settled here, on the acquire itself: the probe in `run` can lose a race
+/// with another mount starting.
+fn acquire(lock: &mut StorageLock, args: &MountArgs) -> Result<Acquired> {
+    if args.lock.force {
+        return lock
+            .force_lock(args.lock.really_force)
+            .map(Acquired::Owner)
+            .map_err(|e| FsError::Storage(e.to_string()));
+    }
+    match lock.try_lock() {
+        Ok(()) => Ok(Acquired::Owner(Vec::new())),
+        Err(e) if args.if_locked == IfLocked::Readonly => {
+            warn!("{e}; mounting read-only");
+            Ok(Acquired::Shared)
+        }
+        Err(e) => Err(FsError::Storage(e.to_string())),
+    }
+}
+
+/// As PID 1 the kernel drops SIGTERM and SIGINT until a handler is
+/// installed, so install it before the (possibly long) startup rather
+/// than after it. A stop received meanwhile is honoured once the mount is
+/// up. Elsewhere the default action still kills a startup cleanly.
+fn handle_signals_early() {
+    if daemon::is_init() {
+        info!("running as PID 1: handling signals from startup, reaping orphans");
+        if let Err(e) = daemon::install_signal_handlers() {
+            warn!("install signal handlers: {e}");
+        }
+    }
+}
+
+/// `--probe-listen`, else `[health] listen`. A bad address only costs the
+/// probes.
+fn start_probes(cfg: &crate::config::RhssConfig, args: &MountArgs) -> Option<ProbeServer> {
+    let addr = args.probe_listen.or(cfg.health.listen)?;
+    match ProbeServer::start(addr) {
+        Ok(srv) => Some(srv),
+        Err(e) => {
+            warn!("probes on {addr} disabled: {e}");
+            None
+        }
+    }
+}
+
+/// How long `--takeover` waits for the running instance to flush and
+/// let go.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// `--takeover`: ask the running rhss to shut down, and wait until it has
+/// unmounted and released the storage lock. It puts hidden storage back
+/// before the lock, so the mount can go ahead as usual from there while
+/// the old process is still exiting.
+fn take_over(ctx: &CliContext, mount: &Path) -> Result<()> {
+    let before = crate::lock::inspect(&ctx.load_config()?.lock_dir())
+        .map_err(|e| FsError::Storage(e.to_string()))?;
+    match control::try_send(ctx, &Request::Shutdown)? {
+        None => {
+            info!("takeover: no rhss running, mounting normally");
+            return Ok(());
+        }
+        Some(resp) if !resp.ok => {
+            return Err(FsError::Storage(format!(
+                "takeover: shutdown refused: {}",
+                resp.error.unwrap_or_default()
+            )))
+        }
+        Some(_) => {}
+    }
+    // Only a holder on this host can be watched for a crash.
+    let pid = before
+        .holder
+        .filter(|_| before.held && before.alive == Some(true))
+        .map(|h| h.pid);
+    info!("takeover: waiting for the running rhss to hand over");
+    let deadline = Instant::now() + TAKEOVER_TIMEOUT;
+    loop {
+        // Re-read each time: the lock moves back when hidden storage is
+        // restored.
+        let now = crate::lock::inspect(&ctx.load_config()?.lock_dir())
+            .map_err(|e| FsError::Storage(e.to_string()))?;
+        let mounted = crate::fuse::is_mounted(mount).unwrap_or(false);
+        let alive = pid.is_none_or(process_alive);
+        match handoff(now.held, mounted, alive) {
+            Handoff::Done => {
+                info!("takeover: storage released");
+                return Ok(());
+            }
+            Handoff::Abandoned => {
+                warn!(
+                    "takeover: the running rhss exited without unmounting {}",
+                    mount.display()
+                );
+                ensure_unmounted(mount);
+                return Ok(());
+            }
+            Handoff::Waiting => {}
+        }
+        if Instant::now() >= deadline {
+            return Err(FsError::Storage(format!(
+                "takeover: the running rhss did not let go within {}s",
+                TAKEOVER_TIMEOUT.as_secs()
+            )));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Where a `--takeover` handoff stands.
+#[derive(Debug, PartialEq, Eq)]
+enum Handoff {
+    /// The old instance is still flushing or unmounting.
+    Waiting,
+    /// Lock released and mount point free: mount now.
+    Done,
+    /// The old instance died and left its mount behind.
+    Abandoned,
+}
+
+fn handoff(held: bool, mounted: bool, holder_alive: bool) -> Handoff {
+    match (held, mounted) {
+        (false, false) => Handoff::Done,
+        (false, true) if !holder_alive => Handoff::Abandoned,
+        _ => Handoff::Waiting,
+    }
+}
+
+fn process_alive(pid: u32) -> bool {
+    let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    signal_reached(rc, std::io::Error::last_os_error().raw_os_error())
+}
+
+/// Whether `kill(pid, 0)` found the process. `EPERM` means it exists but
+/// belongs to someone else.
+fn signal_reached(rc: i32, errno: Option<i32>) -> bool {
+    rc == 0 || errno == Some(libc::EPERM)
+}
+
+/// SIGHUP: re-read the config file and apply what can change without a
+/// remount — ignore filters, cache TTLs, tiering thresholds, log level.
+/// A bad file is logged and the running config is kept.
+/// How often the main loop drops trash entries past their retention.
+const TRASH_PURGE_EVERY: Duration = Duration::from_secs(3600);
+
+fn purge_expired(trash: &Trash, router: &TierRouter) {
+    match trash.purge(router, PurgeScope::Expired) {
+        Ok(st) if st.entries > 0 => info!(
+            "trash: purged {} expired entries ({} bytes)",
+            st.entries, st.bytes
+        ),
+        Ok(_) => {}
+        Err(e) => warn!("trash: purge expired: {e}"),
+    }
+}
+
+fn reload(ctx: &CliContext, args: &MountArgs, adapter: &FuseAdapter, policy: &ReloadablePolicy) {
+    daemon::sd_notify("RELOADING=1");
+    info!("SIGHUP: reloading configuration");
+    let loaded = ctx
+        .load_config_raw()
+        .and_then(|cfg| Ok((fuse_config(&cfg.fuse, args)?, cfg)));
+    match loaded {
+        Ok((fuse_cfg, cfg)) => {
+            adapter.reload(&fuse_cfg);
+            policy.replace(Arc::new(cfg.policy.to_policy()));
+            if let Err(e) = cfg.throttle.apply() {
+                warn!("throttle: {e}");
+            }
+            match cfg.io_limits() {
+                Ok(limits) => crate::throttle::set_io_limits(limits),
+                Err(e) => warn!("throttle: {e}"),
+            }
+            if let Err(e) = cfg.schedule.apply() {
+                warn!("schedule: {e}");
+            }
+            match cfg.hooks() {
+                Ok(hooks) => crate::hooks::set_hooks(hooks),
+                Err(e) => warn!("hooks: {e}"),
+            }
+            if let Some(level) = &cfg.log_level {
+                if let Err(e) = logging::set_filter(level) {
+                    warn!("log_level: {e}");
+                }
+            }
+            info!("configuration reloaded");
+        }
+        Err(e) => error!("reload failed, keeping current configuration: {e}"),
+    }
+    daemon::sd_notify("READY=1");
+}
+
+/// Merge `[fuse]` from the config file with `rhss mount` flags. Flags only
+/// ever turn options on; `-o` options and ignore globs are appended after
+/// the file's.
+fn fuse_config(file: &crate::config::FuseOptions, args: &MountArgs) -> Result<FuseConfig> {
+    let defaults = FuseConfig::default();
+    let allow_root = file.allow_root || args.allow_root;
+    let cfg = if args.allow_other {
+        defaults.with_allow_other(true)
+    } else if allow_root {
+        // allow_root replaces the platform-default allow_other.
+        defaults.with_allow_other(false)
+    } else if let Some(on) = file.allow_other {
+        defaults.with_allow_other(on)
+    } else {
+        defaults
+    };
+    let mut options = file.options.clone();
+    options.extend(args.options.iter().cloned());
+    let cfg = match file.workers {
+        Some(n) => cfg.with_workers(n),
+        None => cfg,
+    };
+    let cfg = match file.max_inodes {
+        Some(n) => cfg.with_max_inodes(n),
+        None => cfg,
+    };
+    let cfg = match file.default_permissions {
+        Some(on) => cfg.with_default_permissions(on),
+        None => cfg,
+    };
+    let cfg = match file.attr_ttl_secs {
+        Some(s) => cfg.with_attr_ttl(Duration::from_secs_f64(s)),
+        None => cfg,
+    };
+    let cfg = match file.entry_ttl_secs {
+        Some(s) => cfg.with_entry_ttl(Duration::from_secs_f64(s)),
+        None => cfg,
+    };
+    let filter = |file_globs: &Option<Vec<String>>, flag_globs: &[String]| -> Result<PathFilter> {
+        let base = match file_globs {
+            Some(globs) => PathFilter::reserved().with_patterns(
+                RuleKind::Exclude,
+                globs,
+                "config",
+            )?,
+            None => PathFilter::with_defaults(),
+        };
+        base.with_patterns(RuleKind::Exclude, flag_globs, "command line")?
+            .with_patterns(RuleKind::Include, &file.include, "config")?
+            .with_patterns(RuleKind::Include, &args.include, "command line")
+    };
+    Ok(cfg
+        .with_lookup_filter(filter(&file.ignore_lookup, &args.ignore_lookup)?)
+        .with_list_filter(filter(&file.ignore_list, &args.ignore_list)?)
+        .with_allow_root(allow_root)
+        .with_read_only(file.read_only || args.read_only)
+        .with_noexec(file.noexec || args.noexec)
+        .with_fsname(args.fsname.clone().or_else(|| file.fsname.clone()))
+        .with_subtype(args.subtype.clone().or_else(|| file.subtype.clone()))
+        .with_volname(args.volname.clone().or_else(|| file.volname.clone()))
+        .with_id_map(file.id_map())
+        .with_id_translation(file.id_translation(&args.uid_map, &args.gid_map)?)
+        .with_modes(file.modes(args.file_mode, args.dir_mode, args.umask)?)
+        .with_custom_options(options))
+}
+
+/// What `rhss mounts` will show for this mount.
+fn mount_record(ctx: &CliContext, fuse_cfg: &FuseConfig, mount: &Path, db: &Path) -> MountRecord {
+    let mut rec = MountRecord::new(
+        std::path::absolute(mount).unwrap_or_else(|_| mount.to_path_buf()),
+        fuse_cfg.fsname().to_string(),
+        std::path::absolute(db).unwrap_or_else(|_| db.to_path_buf()),
+    );
+    rec.subtype = fuse_cfg.subtype().map(str::to_string);
+    rec.config = ctx.resolve_config_path().ok();
+    rec.read_only = fuse_cfg.is_read_only();
+    rec
+}
+
+/// A failure only costs the mount its `rhss mounts` entry.
+fn register(record: &MountRecord) -> Option<Registration> {
+    match Registry::default().register(record) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            warn!("mounts: register {}: {e}", record.mount.display());
+            None
+        }
+    }
+}
+
+/// Dropping the session already unmounted; confirm the kernel agrees and
+/// detach by hand if it doesn't.
+fn ensure_unmounted(mount: &std::path::Path) {
+    for _ in 0..10 {
+        match crate::fuse::is_mounted(mount) {
+            Ok(false) => return,
+            Ok(true) => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => {
+                warn!("check mount table: {e}");
+                return;
+            }
+        }
+    }
+    warn!("{} still mounted; detaching", mount.display());
+    if let Err(e) = crate::fuse::unmount(mount) {
+        error!(
+            "unmount {}: {e}; unmount it by hand before remounting",
+            mount.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn mount_args(argv: &[&str]) -> MountArgs {
+        let cli = super::super::Cli::try_parse_from([&["rhss", "mount"], argv].concat()).unwrap();
+        match cli.cmd {
+            Some(super::super::Cmd::Mount(args)) => *args,
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn if_locked_readonly_shares_a_held_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut owner = StorageLock::new(dir.path(), dir.path());
+        let readonly = mount_args(&["--if-locked", "readonly"]);
+        assert!(matches!(
+            acquire(&mut owner, &readonly).unwrap(),
+            Acquired::Owner(_)
+        ));
+
+        // Another mount comes up read-only next to it, instead of failing.
+        let mut second = StorageLock::new(dir.path(), dir.path());
+        assert!(matches!(
+            acquire(&mut second, &readonly).unwrap(),
+            Acquired::Shared
+        ));
+        assert!(!second.is_locked());
+        assert!(acquire(&mut second, &mount_args(&[])).is_err());
+        owner.unlock().unwrap();
+    }
+
+    #[test]
+    fn mount_inside_a_root_is_refused_before_hiding() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        let cfg = |mount: &Path| {
+            crate::config::RhssConfig::from_toml(
+                &format!(
+                    "mount = {mount:?}\n\
+                     db = {:?}\n\
+                     [[tier.fast]]\nid = \"ssd\"\nroot = {:?}\n\
+                     [[tier.slow]]\nid = \"hdd\"\nroot = {:?}\n",
+                    data.join(".rhss_managed/index.db"),
+                    data.join(".rhss_managed/ssd"),
+                    data.join(".rhss_managed/hdd"),
+                ),
+                std::iter::empty(),
+            )
+            .unwrap()
+        };
+        // Mounting over the roots works only by hiding them.
+        let over = cfg(&data);
+        preflight::check_mount(&mount_roots(&over, true), &over.mount).unwrap();
+        assert!(preflight::check_mount(&mount_roots(&over, false), &over.mount).is_err());
+
+        let inside = cfg(&data.join(".rhss_managed/ssd/mnt"));
+        let err = preflight::check_mount(&mount_roots(&inside, true), &inside.mount)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is inside fast root"), "{err}");
+        // Refused on the config alone: nothing was hidden.
+        assert!(!crate::hidden::hidden_path(&inside.mount).exists());
+        assert!(!crate::hidden::hidden_path(&data).exists());
+    }
+
+    #[test]
+    fn process_owned_by_someone_else_is_alive() {
+        assert!(signal_reached(0, None));
+        assert!(signal_reached(-1, Some(libc::EPERM)));
+        assert!(!signal_reached(-1, Some(libc::ESRCH)));
+        // init is always there, whoever we run as.
+        assert!(process_alive(1));
+    }
+
+    #[test]
+    fn takeover_mounts_once_the_lock_and_mount_point_are_free() {
+        assert_eq!(handoff(true, true, true), Handoff::Waiting);
+        // Unmounted but still flushing the lock file.
+        assert_eq!(handoff(true, false, true), Handoff::Waiting);
+        assert_eq!(handoff(false, true, true), Handoff::Waiting);
+        // No need to wait for the old process to exit.
+        assert_eq!(handoff(false, false, true), Handoff::Done);
+        assert_eq!(handoff(false, true, false), Handoff::Abandoned);
+    }
+}
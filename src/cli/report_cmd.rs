@@ -0,0 +1,157 @@
+//! `rhss report` — reports for people outside the storage team. `costs`
+//! is `crate::cost::report` under the `[policy]` of the config, or a
+//! policy change given on the command line to price it before applying;
+//! `usage` is `crate::cost::usage_by_owner`.
+
+use crate::config::PolicyOptions;
+use crate::cost::{self, CostReport, ReportFormat, TierRates, UsageReport};
+use crate::error::Result;
+
+use super::common::{fmt_bytes, CliContext};
+use super::{ReportCmd, ReportCostsArgs, ReportUsageArgs};
+
+pub fn run(ctx: &CliContext, cmd: ReportCmd) -> Result<()> {
+    match cmd {
+        ReportCmd::Costs(args) => costs(ctx, args),
+        ReportCmd::Usage(args) => usage(ctx, args),
+    }
+}
+
+fn costs(ctx: &CliContext, args: ReportCostsArgs) -> Result<()> {
+    let (cfg, router) = ctx.build_router()?;
+    let policy = PolicyOptions {
+        low_watermark: args.low_watermark.or(cfg.policy.low_watermark),
+        high_watermark: args.high_watermark.or(cfg.policy.high_watermark),
+        min_age_to_evict_secs: args.min_age_to_evict.or(cfg.policy.min_age_to_evict_secs),
+        ..cfg.policy.clone()
+    };
+    policy.validate()?;
+    let index = ctx.open_index()?;
+    let rates = TierRates::resolve(&cfg.cost, &router);
+    let report = cost::report(
+        &index,
+        &router,
+        rates,
+        &policy.to_policy(),
+        args.depth,
+        args.top,
+    )?;
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report, args.depth);
+    }
+    Ok(())
+}
+
+fn usage(ctx: &CliContext, args: ReportUsageArgs) -> Result<()> {
+    let (cfg, router) = ctx.build_router()?;
+    let index = ctx.open_index()?;
+    let rates = TierRates::resolve(&cfg.cost, &router);
+    let report = cost::usage_by_owner(&index, &router, &rates, args.group_by)?;
+    let format = args.format.or(ctx.json.then_some(ReportFormat::Json));
+    match format {
+        Some(f) => print!("{}", report.render(f)?),
+        None => print_usage(&report),
+    }
+    Ok(())
+}
+
+fn usd(v: f64) -> String {
+    if v < 0.0 {
+        format!("-${:.2}", -v)
+    } else {
+        format!("${v:.2}")
+    }
+}
+
+fn print_report(r: &CostReport, depth: usize) {
+    println!(
+        "{:<8}  {:>10}  {:>12}  {:>10}  {:>12}",
+        "TIER", "FILES", "BYTES", "$/GiB/mo", "MONTHLY"
+    );
+    for t in &r.tiers {
+        let rate = match t.rate {
+            Some(c) => format!("${c:.4}"),
+            None => "—".into(),
+        };
+        println!(
+            "{:<8}  {:>10}  {:>12}  {:>10}  {:>12}",
+            t.tier,
+            t.files,
+            fmt_bytes(t.bytes),
+            rate,
+            usd(t.monthly)
+        );
+    }
+    println!("Estimated total: {}/month", usd(r.total_monthly));
+
+    println!();
+    if r.pending.is_empty() {
+        println!("Pending: nothing for the next tier cycle to move");
+    } else {
+        println!("Pending moves of the next tier cycle:");
+        for m in &r.pending {
+            println!(
+                "  {} -> {}: {} files, {} ({}/month)",
+                m.from,
+                m.to,
+                m.files,
+                fmt_bytes(m.bytes),
+                usd(m.monthly_delta)
+            );
+        }
+        println!(
+            "Afterwards: {}/month",
+            usd(r.total_monthly + r.pending_delta)
+        );
+    }
+
+    println!();
+    if r.top_dirs.is_empty() {
+        println!("No directories {depth} level(s) below /");
+        return;
+    }
+    println!(
+        "{:<40}  {:>10}  {:>12}  {:>12}",
+        "DIR", "FILES", "BYTES", "MONTHLY"
+    );
+    for d in &r.top_dirs {
+        println!(
+            "{:<40}  {:>10}  {:>12}  {:>12}",
+            d.dir.display(),
+            d.files,
+            fmt_bytes(d.bytes),
+            usd(d.monthly)
+        );
+    }
+}
+
+fn print_usage(r: &UsageReport) {
+    println!(
+        "{:<10}  {:>10}  {:>12}  {:>12}  {:>12}  {:>12}  {:>12}",
+        r.group_by.as_str().to_uppercase(),
+        "FILES",
+        "BYTES",
+        "FAST",
+        "SLOW",
+        "ARCHIVE",
+        "MONTHLY"
+    );
+    for o in &r.owners {
+        println!(
+            "{:<10}  {:>10}  {:>12}  {:>12}  {:>12}  {:>12}  {:>12}",
+            o.owner,
+            o.files,
+            fmt_bytes(o.bytes),
+            fmt_bytes(o.fast_bytes),
+            fmt_bytes(o.slow_bytes),
+            fmt_bytes(o.archive_bytes),
+            usd(o.monthly)
+        );
+    }
+    println!("Estimated total: {}/month", usd(r.total_monthly));
+    if r.unreadable > 0 {
+        println!("({} files left out: owner unreadable)", r.unreadable);
+    }
+}
@@ -0,0 +1,226 @@
+//! `rhss serve-webdav` / `rhss serve-9p` — run the daemon without a FUSE
+//! mount and expose the namespace over a network protocol instead.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{error, info, warn};
+
+use crate::audit::AuditLog;
+use crate::control::{server::OpContext, socket_path_for, ControlServer};
+use crate::daemon;
+use crate::error::{FsError, Result};
+use crate::filter::{PathFilter, RuleKind};
+use crate::fuse::FuseConfig;
+use crate::lock::StorageLock;
+use crate::logging;
+use crate::namespace::Namespace;
+use crate::ninep::NinePServer;
+use crate::quota::Quotas;
+use crate::scan;
+use crate::trash::Trash;
+use crate::webdav::WebDavServer;
+use crate::{Rhss, RhssBuilder};
+
+use super::common::CliContext;
+use super::lock_cmd;
+use super::{ForceLockArgs, Serve9pArgs, ServeWebdavArgs};
+
+/// A daemon with no mount: tierer, control socket and the storage lock,
+/// plus a `Namespace` for whichever protocol is being served.
+struct Served {
+    rhss: Rhss,
+    ns: Arc<Namespace>,
+    control_server: Option<ControlServer>,
+    #[cfg(feature = "grpc")]
+    grpc_server: Option<crate::control::grpc::GrpcServer>,
+    lock: StorageLock,
+}
+
+/// `serving` names what is served, for `rhss lock status`.
+fn start(ctx: &CliContext, force: &ForceLockArgs, serving: String) -> Result<Served> {
+    let cfg = ctx.load_config_raw()?;
+    if std::env::var_os("RUST_LOG").is_none() {
+        if let Some(level) = &cfg.log_level {
+            logging::set_filter(level)?;
+        }
+    }
+    if let Some(parent) = cfg.db.parent() {
+        if !parent.as_os_str().is_empty() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+    }
+
+    // Same lock as `rhss mount`: one daemon per storage, whatever it serves.
+    let lock_dir = cfg.lock_dir();
+    let mut lock = lock_cmd::storage_lock(&cfg, serving);
+    let res = if force.force {
+        lock_cmd::confirm_force(&lock_dir, force.really_force).and_then(|really| {
+            lock.force_lock(really)
+                .map_err(|e| FsError::Storage(e.to_string()))
+        })
+    } else {
+        lock.try_lock()
+            .map(|()| Vec::new())
+            .map_err(|e| FsError::Storage(e.to_string()))
+    };
+    let taken = match res {
+        Ok(taken) => taken,
+        Err(e) => {
+            error!("acquire storage lock: {e}");
+            std::process::exit(1);
+        }
+    };
+    if !taken.is_empty() {
+        // No FUSE ops to audit here; open the trail just for the takeover.
+        let audit = if cfg.audit.enabled {
+            Some(AuditLog::open(cfg.audit.sink(&cfg.db), cfg.audit.buffer())?)
+        } else {
+            None
+        };
+        lock_cmd::record_takeover(audit.as_deref(), &taken);
+    }
+
+    let all_roots: Vec<&std::path::Path> = cfg
+        .tier
+        .fast
+        .iter()
+        .chain(cfg.tier.slow.iter())
+        .filter(|b| b.memory_size().is_none())
+        .flat_map(|b| std::iter::once(&b.root).chain(&b.replicas))
+        .map(|p| p.as_path())
+        .collect();
+    if let Err(e) = scan::ensure_managed_dirs(all_roots.iter().copied()) {
+        error!("prepare backend dirs: {e}");
+        std::process::exit(1);
+    }
+
+    let trash = cfg.trash.enabled.then(|| Trash::new(cfg.trash.retention()));
+    let quota_rules = cfg.quota_rules()?;
+    let quotas = if quota_rules.is_empty() {
+        None
+    } else {
+        Some(Quotas::new(quota_rules))
+    };
+    let tenants = cfg.tenants()?;
+    let filter = match &cfg.fuse.ignore_lookup {
+        Some(globs) => PathFilter::reserved().with_patterns(RuleKind::Exclude, globs, "config")?,
+        None => PathFilter::with_defaults(),
+    }
+    .with_patterns(RuleKind::Include, &cfg.fuse.include, "config")?;
+
+    let rhss = match RhssBuilder::from_config(&cfg).and_then(|b| {
+        let fuse = FuseConfig::default()
+            .with_quotas(quotas.clone())
+            .with_tenants(tenants.clone());
+        b.with_fuse_config(fuse).build()
+    }) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let ns = Namespace::new(
+        Arc::clone(rhss.router()),
+        Arc::clone(rhss.index()),
+        rhss.policy().clone(),
+        Arc::clone(rhss.open_tracker()),
+    )
+    .with_filter(filter)
+    .with_trash(trash.clone())
+    .with_quotas(quotas.clone())
+    .with_tenants(tenants);
+
+    let op_ctx = OpContext {
+        router: Arc::clone(rhss.router()),
+        index: Arc::clone(rhss.index()),
+        open_tracker: Arc::clone(rhss.open_tracker()),
+        tierer: rhss.tierer().clone(),
+        config_db_path: cfg.db.clone(),
+        mount: cfg.mount.clone(),
+        started: std::time::SystemTime::now(),
+        fuse: None,
+        trash,
+        quotas,
+        policy: Some(Arc::clone(rhss.policy())),
+    };
+    #[cfg(feature = "grpc")]
+    let grpc_server = match cfg.grpc.listen {
+        Some(addr) => match crate::control::grpc::GrpcServer::start(addr, op_ctx.clone()) {
+            Ok(srv) => Some(srv),
+            Err(e) => {
+                warn!("grpc admin on {addr} disabled: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+    let control_server = match ControlServer::start(socket_path_for(&cfg.db), op_ctx) {
+        Ok(srv) => Some(srv),
+        Err(e) => {
+            warn!("control socket disabled: {e}");
+            None
+        }
+    };
+    crate::otlp::start(Some(Arc::clone(rhss.router())));
+    Ok(Served {
+        rhss,
+        ns: Arc::new(ns),
+        control_server,
+        #[cfg(feature = "grpc")]
+        grpc_server,
+        lock,
+    })
+}
+
+impl Served {
+    /// Block until SIGINT/SIGTERM, then shut down; `server` is the
+    /// protocol listener, dropped first.
+    fn run_until_stopped<S>(mut self, server: S) -> Result<()> {
+        if let Err(e) = daemon::install_signal_handlers() {
+            warn!("install signal handlers: {e}");
+        }
+        while !daemon::stop_requested() {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        info!("signal received, shutting down");
+        drop(server);
+        #[cfg(feature = "grpc")]
+        drop(self.grpc_server);
+        drop(self.control_server);
+        self.rhss.adapter().stop();
+        drop(self.rhss);
+        if let Err(e) = self.lock.unlock() {
+            warn!("release storage lock: {e}");
+        }
+        info!("clean shutdown");
+        crate::otlp::flush();
+        Ok(())
+    }
+}
+
+pub fn webdav(ctx: &CliContext, args: ServeWebdavArgs) -> Result<()> {
+    let served = start(ctx, &args.lock, format!("webdav {}", args.listen))?;
+    let server = match WebDavServer::start(args.listen, Arc::clone(&served.ns), args.read_only) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("webdav listen on {}: {e}", args.listen);
+            std::process::exit(1);
+        }
+    };
+    served.run_until_stopped(server)
+}
+
+pub fn ninep(ctx: &CliContext, args: Serve9pArgs) -> Result<()> {
+    let served = start(ctx, &args.lock, format!("9p {}", args.listen))?;
+    let server =
+        match NinePServer::start(args.listen.clone(), Arc::clone(&served.ns), args.read_only) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("9p listen on {}: {e}", args.listen);
+                std::process::exit(1);
+            }
+        };
+    served.run_until_stopped(server)
+}
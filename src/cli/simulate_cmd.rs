@@ -0,0 +1,129 @@
+//! `rhss simulate` — see `crate::simulate`. The trace is a workload script
+//! or an access trace recorded by a mount. Every combination of the
+//! watermarks and ages given on the command line is replayed, starting
+//! from the config's `[policy]` (or the defaults without a config), and
+//! reported one row each.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::access::trace;
+use crate::config::PolicyOptions;
+use crate::error::{FsError, Result};
+use crate::simulate::{self, SimConfig, SimReport, TraceEvent};
+
+use super::common::{fmt_bytes, CliContext};
+use super::SimulateArgs;
+
+#[derive(Serialize)]
+struct Run {
+    policy: PolicyOptions,
+    report: SimReport,
+}
+
+pub fn run(ctx: &CliContext, args: SimulateArgs) -> Result<()> {
+    let trace = load_trace(&args.trace)?;
+    let base = base_policy(ctx, &args)?;
+
+    let mut runs = Vec::new();
+    for policy in variations(&base, &args) {
+        let cfg = SimConfig {
+            fast_capacity: args.fast_capacity,
+            policy,
+        };
+        let report = simulate::run(&trace, &cfg)?;
+        runs.push(Run {
+            policy: cfg.policy,
+            report,
+        });
+    }
+
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&runs)?);
+        return Ok(());
+    }
+    println!(
+        "{} events, fast tier {}",
+        trace.len(),
+        fmt_bytes(args.fast_capacity)
+    );
+    println!(
+        "{:>5}  {:>5}  {:>8}  {:>6}  {:>8}  {:>11}  {:>7}  {:>5}",
+        "LOW", "HIGH", "MIN_AGE", "HIT%", "MOVED", "MOVED_BYTES", "SPILLED", "PEAK"
+    );
+    for r in &runs {
+        let p = r.policy.to_policy();
+        println!(
+            "{:>5.2}  {:>5.2}  {:>7}s  {:>5.1}%  {:>8}  {:>11}  {:>7}  {:>4.0}%",
+            p.low_watermark,
+            p.high_watermark,
+            p.min_age_to_evict.as_secs(),
+            r.report.hit_rate() * 100.0,
+            r.report.migrations,
+            fmt_bytes(r.report.migrated_bytes),
+            r.report.spilled,
+            r.report.peak_fast_usage * 100.0
+        );
+    }
+    Ok(())
+}
+
+/// A trace recorded by a mount (`crate::access::trace`), or a script.
+pub(super) fn load_trace(path: &Path) -> Result<Vec<TraceEvent>> {
+    let bytes = std::fs::read(path).map_err(|e| FsError::from_io(e, path.display()))?;
+    if trace::is_trace(&bytes) {
+        let records = trace::decode(&bytes)
+            .map_err(|e| FsError::InvalidOperation(format!("{}: {e}", path.display())))?;
+        return Ok(simulate::from_records(&records));
+    }
+    let text = String::from_utf8(bytes).map_err(|_| {
+        FsError::InvalidOperation(format!(
+            "{}: neither a workload script nor an access trace",
+            path.display()
+        ))
+    })?;
+    simulate::parse_script(&text)
+}
+
+/// The config's `[policy]` when there is a config, with `--tier-period`.
+fn base_policy(ctx: &CliContext, args: &SimulateArgs) -> Result<PolicyOptions> {
+    let mut base = config_policy(ctx)?;
+    if args.tier_period.is_some() {
+        base.tier_period_secs = args.tier_period;
+    }
+    Ok(base)
+}
+
+/// The config's `[policy]`, or the defaults without a config.
+pub(super) fn config_policy(ctx: &CliContext) -> Result<PolicyOptions> {
+    match ctx.resolve_config_path() {
+        Ok(_) => Ok(ctx.load_config_raw()?.policy),
+        Err(_) => Ok(PolicyOptions::default()),
+    }
+}
+
+/// `base` with every combination of the values given; just `base` if none.
+fn variations(base: &PolicyOptions, args: &SimulateArgs) -> Vec<PolicyOptions> {
+    fn or_base<T: Copy>(given: &[T], base: Option<T>) -> Vec<Option<T>> {
+        if given.is_empty() {
+            vec![base]
+        } else {
+            given.iter().map(|v| Some(*v)).collect()
+        }
+    }
+    let mut out = Vec::new();
+    for low in or_base(&args.low_watermark, base.low_watermark) {
+        for high in or_base(&args.high_watermark, base.high_watermark) {
+            for age in or_base(&args.min_age_to_evict, base.min_age_to_evict_secs) {
+                out.push(PolicyOptions {
+                    low_watermark: low,
+                    high_watermark: high,
+                    min_age_to_evict_secs: age,
+                    ..base.clone()
+                });
+            }
+        }
+    }
+    out
+}
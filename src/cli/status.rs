@@ -1,13 +1,98 @@
-//! `status` / `backends` / `stats` — dashboard + per-backend table + counters.
+//! `status` / `backends` / `stats` / `quota report` / `tenants` / `du` /
+//! `mounts` — dashboard, per-backend table, counters, quota usage, tenant
+//! usage, per-directory usage and the live mounts on this host.
 
 use serde::Serialize;
 
-use crate::error::Result;
+use crate::control::{BackendHealth, DuEntry, Request, Response, ResponseData, StatusReport, Tier};
+use crate::error::{FsError, Result};
 use crate::index::TierId;
+use crate::quota::{QuotaUsage, Quotas};
 
 use super::common::{fmt_bar, fmt_bytes, CliContext};
+use super::DuArgs;
 
 pub fn status(ctx: &CliContext) -> Result<()> {
+    match super::control::try_send(ctx, &Request::Status)? {
+        Some(Response {
+            ok: true,
+            data: Some(ResponseData::Status(report)),
+            ..
+        }) => {
+            if ctx.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_live(&report);
+            }
+            Ok(())
+        }
+        Some(resp) => Err(FsError::Storage(format!(
+            "status: {}",
+            resp.error.as_deref().unwrap_or("unexpected response")
+        ))),
+        None => offline_status(ctx),
+    }
+}
+
+/// Render a running daemon's `StatusReport`.
+pub(super) fn print_live(r: &StatusReport) {
+    let state = if r.mounted {
+        "mounted at"
+    } else {
+        "NOT MOUNTED at"
+    };
+    let commit = match &r.build {
+        Some(b) => format!(" ({})", b.git_commit),
+        None => String::new(),
+    };
+    println!(
+        "rhss v{}{commit}  {state} {}  (pid {}, up {})",
+        r.version,
+        r.mount.display(),
+        r.pid,
+        fmt_uptime(r.uptime_secs)
+    );
+    println!(
+        "Tierer: {}{}  |  Pending migrations: {}",
+        if r.frozen { "frozen" } else { "running" },
+        if r.tierer_busy { " (busy)" } else { "" },
+        r.pending_migrations
+    );
+    match &r.cache {
+        Some(c) => println!(
+            "Inode cache: {} / {}  |  Open handles: {} ({} files)",
+            format_count(c.inodes as u64),
+            format_count(c.inode_limit as u64),
+            c.open_handles,
+            r.open_files
+        ),
+        None => println!("Open files: {}", r.open_files),
+    }
+    println!();
+    println!(
+        "{:<8}  {:<14}  {:>10}  {:>10}  {:>5}  HEALTH",
+        "TIER", "BACKEND", "USED", "TOTAL", "USED%"
+    );
+    for b in &r.backends {
+        let tier = tier_name(b.tier.into());
+        if let Some(e) = &b.error {
+            println!("{:<8}  {:<14}  unavailable: {e}", tier, b.id);
+            continue;
+        }
+        println!(
+            "{:<8}  {:<14}  {:>10}  {:>10}  {:>4.0}%  {}",
+            tier,
+            b.id,
+            fmt_bytes(b.used),
+            fmt_bytes(b.total),
+            pct(b.used, b.total),
+            fmt_health(b.health.as_ref())
+        );
+    }
+}
+
+/// No daemon: show what the config and index say.
+fn offline_status(ctx: &CliContext) -> Result<()> {
     let (cfg, router) = ctx.build_router()?;
     let index = ctx.open_index()?;
     let total_files = index.count()?;
@@ -27,7 +112,7 @@ pub fn status(ctx: &CliContext) -> Result<()> {
     }
 
     println!(
-        "rhss v{}  configured mount {}",
+        "rhss v{}  configured mount {} (not running)",
         env!("CARGO_PKG_VERSION"),
         cfg.mount.display()
     );
@@ -46,6 +131,187 @@ pub fn status(ctx: &CliContext) -> Result<()> {
     Ok(())
 }
 
+/// `rhss quota report`: live counters from the daemon, or a fresh count
+/// from the index and backends when it isn't running.
+pub fn quota_report(ctx: &CliContext) -> Result<()> {
+    let entries = match super::control::try_send(ctx, &Request::QuotaReport)? {
+        Some(Response {
+            ok: true,
+            data: Some(ResponseData::Quota { entries }),
+            ..
+        }) => entries,
+        Some(resp) => {
+            return Err(FsError::Storage(format!(
+                "quota report: {}",
+                resp.error.as_deref().unwrap_or("unexpected response")
+            )))
+        }
+        None => {
+            let (cfg, router) = ctx.build_router()?;
+            let quotas = Quotas::new(cfg.quota_rules()?);
+            quotas.seed(&router, &ctx.open_index()?)?;
+            quotas.report()
+        }
+    };
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        print_quota(&entries);
+    }
+    Ok(())
+}
+
+pub(super) fn print_quota(entries: &[QuotaUsage]) {
+    if entries.is_empty() {
+        println!("no quotas configured");
+        return;
+    }
+    println!("{:<32}  {:>10}  {:>10}  {:>6}", "TARGET", "USED", "LIMIT", "USE%");
+    for e in entries {
+        println!(
+            "{:<32}  {:>10}  {:>10}  {:>5.1}%",
+            e.target,
+            fmt_bytes(e.used),
+            fmt_bytes(e.limit),
+            pct(e.used, e.limit)
+        );
+    }
+}
+
+/// `rhss tenants`: each tenant's bytes per tier against its limits.
+pub fn tenants(ctx: &CliContext) -> Result<()> {
+    let (cfg, router) = ctx.build_router()?;
+    let Some(tenants) = cfg.tenants()? else {
+        println!("no tenants configured");
+        return Ok(());
+    };
+    let rates = crate::cost::TierRates::resolve(&cfg.cost, &router);
+    let stats = tenants.stats(ctx.open_index()?.as_ref(), &rates)?;
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    let limit = |l: Option<u64>| l.map(fmt_bytes).unwrap_or_else(|| "—".into());
+    println!(
+        "{:<16}  {:<20}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}",
+        "TENANT", "DIR", "FILES", "BYTES", "QUOTA", "FAST", "FAST MAX", "MONTHLY"
+    );
+    for s in &stats {
+        println!(
+            "{:<16}  {:<20}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}",
+            s.name,
+            s.dir.display(),
+            s.files,
+            fmt_bytes(s.bytes),
+            limit(s.quota),
+            fmt_bytes(s.fast_bytes),
+            limit(s.fast_limit),
+            format!("${:.2}", s.monthly)
+        );
+    }
+    Ok(())
+}
+
+/// `rhss mounts`: every live mount on this host, whatever its config.
+pub fn mounts(ctx: &CliContext) -> Result<()> {
+    let mounts = crate::mounts::Registry::default().list()?;
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&mounts)?);
+        return Ok(());
+    }
+    if mounts.is_empty() {
+        println!("no rhss mounts");
+        return Ok(());
+    }
+    println!(
+        "{:>7}  {:<16}  {:<4}  {:<32}  CONFIG",
+        "PID", "FSNAME", "MODE", "MOUNT"
+    );
+    for m in &mounts {
+        let config = m.config.as_ref().map(|p| p.display().to_string());
+        println!(
+            "{:>7}  {:<16}  {:<4}  {:<32}  {}",
+            m.pid,
+            m.fsname,
+            if m.read_only { "ro" } else { "rw" },
+            m.mount.display(),
+            config.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+/// `rhss du`: per-tier totals under a directory. Both sources read the
+/// incrementally maintained `dir_usage` table; nothing is walked.
+pub fn du(ctx: &CliContext, args: DuArgs) -> Result<()> {
+    let path = super::inspect::normalize_logical(&args.path);
+    let req = Request::Du {
+        path: path.clone(),
+        depth: args.depth,
+    };
+    let entries = match super::control::try_send(ctx, &req)? {
+        Some(Response {
+            ok: true,
+            data: Some(ResponseData::Du { entries }),
+            ..
+        }) => entries,
+        Some(resp) => {
+            return Err(FsError::Storage(format!(
+                "du: {}",
+                resp.error.as_deref().unwrap_or("unexpected response")
+            )))
+        }
+        None => ctx
+            .open_index()?
+            .dir_usage(&path, args.depth)?
+            .into_iter()
+            .map(DuEntry::from)
+            .collect(),
+    };
+    if ctx.json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        print_du(&entries);
+    }
+    Ok(())
+}
+
+/// One line per directory, tiers side by side.
+pub(super) fn print_du(entries: &[DuEntry]) {
+    if entries.is_empty() {
+        println!("nothing indexed there");
+        return;
+    }
+    println!(
+        "{:>10}  {:>10}  {:>10}  {:>12}  PATH",
+        "FAST", "SLOW", "ARCHIVE", "FILES"
+    );
+    let mut i = 0;
+    while i < entries.len() {
+        let dir = &entries[i].path;
+        let (mut bytes, mut files) = ([0u64; 3], 0u64);
+        while i < entries.len() && entries[i].path == *dir {
+            let e = &entries[i];
+            let slot = match e.tier {
+                Tier::Fast => 0,
+                Tier::Slow => 1,
+                Tier::Archive => 2,
+            };
+            bytes[slot] += e.bytes;
+            files += e.files;
+            i += 1;
+        }
+        println!(
+            "{:>10}  {:>10}  {:>10}  {:>12}  {}",
+            fmt_bytes(bytes[0]),
+            fmt_bytes(bytes[1]),
+            fmt_bytes(bytes[2]),
+            format_count(files),
+            dir.display()
+        );
+    }
+}
+
 pub fn backends(ctx: &CliContext) -> Result<()> {
     let (_cfg, router) = ctx.build_router()?;
     let mut rows = Vec::<BackendRow>::new();
@@ -71,18 +337,13 @@ pub fn backends(ctx: &CliContext) -> Result<()> {
         "TIER", "BACKEND", "USED", "TOTAL", "USED%"
     );
     for r in &rows {
-        let pct = if r.total == 0 {
-            0.0
-        } else {
-            r.used as f64 / r.total as f64 * 100.0
-        };
         println!(
             "{:<5}  {:<14}  {:>10}  {:>10}  {:>4.0}%  {}",
             r.tier,
             r.id,
             fmt_bytes(r.used),
             fmt_bytes(r.total),
-            pct,
+            pct(r.used, r.total),
             r.root
         );
     }
@@ -280,6 +541,37 @@ fn sum_for(summaries: &[(TierId, u64, u64)], tier: TierId) -> (u64, u64) {
         .unwrap_or((0, 0))
 }
 
+fn pct(used: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        used as f64 / total as f64 * 100.0
+    }
+}
+
+/// `ok 3ms`, `FAILED: <why>`, or `-` before the first probe.
+fn fmt_health(h: Option<&BackendHealth>) -> String {
+    match h {
+        None => "-".into(),
+        Some(h) if h.ok => format!("ok {}ms", h.latency_ms),
+        Some(h) => format!("FAILED: {}", h.error.as_deref().unwrap_or("unknown")),
+    }
+}
+
+/// `3d 4h`, `2h 5m`, `42s`.
+fn fmt_uptime(secs: u64) -> String {
+    let (d, h, m) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60);
+    if d > 0 {
+        format!("{d}d {h}h")
+    } else if h > 0 {
+        format!("{h}h {m}m")
+    } else if m > 0 {
+        format!("{m}m {}s", secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
 fn format_count(n: u64) -> String {
     // 1,234,567 style
     let s = n.to_string();
@@ -391,4 +683,27 @@ mod tests {
         assert_eq!(format_count(1000), "1,000");
         assert_eq!(format_count(1_234_567), "1,234,567");
     }
+
+    #[test]
+    fn uptime_formatting() {
+        assert_eq!(fmt_uptime(42), "42s");
+        assert_eq!(fmt_uptime(125), "2m 5s");
+        assert_eq!(fmt_uptime(3 * 3600 + 120), "3h 2m");
+        assert_eq!(fmt_uptime(2 * 86_400 + 5 * 3600), "2d 5h");
+    }
+
+    #[test]
+    fn health_formatting() {
+        assert_eq!(fmt_health(None), "-");
+        let mut h = BackendHealth {
+            ok: true,
+            latency_ms: 3,
+            age_secs: 10,
+            error: None,
+        };
+        assert_eq!(fmt_health(Some(&h)), "ok 3ms");
+        h.ok = false;
+        h.error = Some("slow: 1500ms > 1000ms".into());
+        assert_eq!(fmt_health(Some(&h)), "FAILED: slow: 1500ms > 1000ms");
+    }
 }
@@ -0,0 +1,232 @@
+//! `rhss mount --supervise`: a small parent process that runs the mount as
+//! a child and brings it back when it dies.
+//!
+//! The child is this same binary with the same arguments, told apart by
+//! `RHSS_SUPERVISED` in its environment; it stays in the foreground and
+//! leaves `--daemon` and `--pid-file` to the supervisor. When the child
+//! exits with an error or is killed, the supervisor lazily detaches the
+//! dead FUSE mount and starts a new child after a delay that doubles from
+//! `BACKOFF_MIN` up to `BACKOFF_MAX` (reset once a child has stayed up for
+//! `BACKOFF_MAX`). The new child recovers the migration journal and the
+//! storage lock as any fresh mount does. Each restart is a `restarted`
+//! hook event.
+//!
+//! A clean exit of the child (`rhss umount`) ends the supervisor too.
+//! SIGTERM/SIGINT are passed on to the child and waited for; SIGHUP is
+//! passed on as well.
+
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+
+use crate::daemon::{self, PidFile, Readiness, TrackedChild};
+use crate::error::{FsError, Result};
+use crate::hooks::{self, Event};
+
+use super::common::CliContext;
+use super::MountArgs;
+
+/// Set in the child's environment.
+pub const SUPERVISED_ENV: &str = "RHSS_SUPERVISED";
+
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Whether this process is a supervised mount child.
+pub fn is_child() -> bool {
+    std::env::var_os(SUPERVISED_ENV).is_some()
+}
+
+pub fn run(ctx: &CliContext, args: &MountArgs) -> Result<()> {
+    let cfg = ctx.load_config_raw()?;
+    let mount = args.shared.clone().unwrap_or_else(|| cfg.mount.clone());
+    let readiness = if args.daemon {
+        daemon::daemonize()?
+    } else {
+        Readiness::foreground()
+    };
+    let _pid_file = match &args.pid_file {
+        Some(p) => Some(PidFile::create(p)?),
+        None => None,
+    };
+    daemon::install_signal_handlers()?;
+    hooks::set_hooks(cfg.hooks()?);
+
+    // The first start has to work; only a mount that was up is restarted.
+    let (mut child, mut _tracked) = spawn()?;
+    if !wait_mounted(&mut child, &mount)? {
+        return Err(FsError::Storage(format!(
+            "supervise: mount at {} failed to start",
+            mount.display()
+        )));
+    }
+    info!("supervise: mount child {} is up", child.id());
+    readiness.ready();
+
+    let mut attempt = 0u32;
+    let mut delay = None;
+    loop {
+        let started = Instant::now();
+        let status = watch(&mut child)?;
+        if daemon::stop_requested() || status.success() {
+            info!("supervise: mount child exited ({status}), stopping");
+            return Ok(());
+        }
+        let backoff = next_backoff(delay, started.elapsed());
+        delay = Some(backoff);
+        attempt += 1;
+        error!("supervise: mount child died ({status}); restarting in {backoff:?}");
+        detach(&mount);
+        hooks::emit(restarted(&mount, attempt, status, backoff));
+        if !sleep_unless_stopped(backoff) {
+            return Ok(());
+        }
+        (child, _tracked) = spawn()?;
+    }
+}
+
+/// The delay before the next restart, given the previous one (`None`
+/// before the first restart) and how long the dead child ran: doubling from
+/// `BACKOFF_MIN` to `BACKOFF_MAX`, and back to the start after a child
+/// that stayed up for `BACKOFF_MAX`.
+fn next_backoff(last: Option<Duration>, ran: Duration) -> Duration {
+    match last {
+        Some(d) if ran < BACKOFF_MAX => (d * 2).min(BACKOFF_MAX),
+        _ => BACKOFF_MIN,
+    }
+}
+
+fn restarted(mount: &Path, attempt: u32, status: ExitStatus, delay: Duration) -> Event {
+    Event::Restarted {
+        mount: mount.to_path_buf(),
+        attempt,
+        status: status.to_string(),
+        delay_ms: delay.as_millis() as u64,
+    }
+}
+
+/// The mount child, tracked from the moment it exists so the PID 1
+/// reaper leaves its exit status to `watch`.
+fn spawn() -> Result<(Child, TrackedChild)> {
+    let exe = std::env::current_exe().map_err(FsError::Io)?;
+    TrackedChild::spawn(
+        Command::new(exe)
+            .args(std::env::args_os().skip(1))
+            .env(SUPERVISED_ENV, "1"),
+    )
+    .map_err(FsError::Io)
+}
+
+/// Wait for the child to mount. `false` if it exits first.
+fn wait_mounted(child: &mut Child, mount: &Path) -> Result<bool> {
+    loop {
+        if child.try_wait().map_err(FsError::Io)?.is_some() {
+            return Ok(false);
+        }
+        if crate::fuse::is_mounted(mount).unwrap_or(false) {
+            return Ok(true);
+        }
+        if daemon::stop_requested() {
+            stop(child)?;
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Wait for the child to exit, passing signals on.
+fn watch(child: &mut Child) -> Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait().map_err(FsError::Io)? {
+            return Ok(status);
+        }
+        if daemon::stop_requested() {
+            return stop(child);
+        }
+        if daemon::take_reload() {
+            signal(child, libc::SIGHUP);
+        }
+        daemon::reap_orphans();
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+fn stop(child: &mut Child) -> Result<ExitStatus> {
+    info!("supervise: stopping mount child {}", child.id());
+    signal(child, libc::SIGTERM);
+    child.wait().map_err(FsError::Io)
+}
+
+fn signal(child: &Child, sig: libc::c_int) {
+    unsafe { libc::kill(child.id() as libc::pid_t, sig) };
+}
+
+/// Drop the dead mount so the next child can mount over the same point.
+fn detach(mount: &Path) {
+    if !crate::fuse::is_mounted(mount).unwrap_or(false) {
+        return;
+    }
+    match crate::fuse::unmount(mount) {
+        Ok(()) => info!("supervise: detached {}", mount.display()),
+        Err(e) => warn!("supervise: detach {}: {e}", mount.display()),
+    }
+}
+
+/// `false` if a stop signal came in meanwhile.
+fn sleep_unless_stopped(d: Duration) -> bool {
+    let until = Instant::now() + d;
+    while Instant::now() < until {
+        if daemon::stop_requested() {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn backoff_doubles_to_the_cap_and_resets_after_a_healthy_run() {
+        let quick = Duration::from_secs(1);
+        let mut last = None;
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            let d = next_backoff(last, quick);
+            delays.push(d.as_secs());
+            last = Some(d);
+        }
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(next_backoff(last, BACKOFF_MAX), BACKOFF_MIN);
+        assert_eq!(
+            next_backoff(Some(Duration::from_secs(8)), BACKOFF_MAX - quick),
+            Duration::from_secs(16)
+        );
+    }
+
+    #[test]
+    fn restart_event_carries_the_exit_status() {
+        let mount = Path::new("/mnt/data");
+        let e = restarted(
+            mount,
+            3,
+            ExitStatus::from_raw(1 << 8),
+            Duration::from_secs(4),
+        );
+        assert_eq!(e.kind(), "restarted");
+        let v = serde_json::to_value(&e).unwrap();
+        assert_eq!(v["status"], "exit status: 1");
+        assert_eq!(v["attempt"], 3);
+        assert_eq!(v["delay_ms"], 4000);
+        assert_eq!(v["mount"], "/mnt/data");
+
+        let killed = restarted(mount, 1, ExitStatus::from_raw(libc::SIGKILL), BACKOFF_MIN);
+        let v = serde_json::to_value(&killed).unwrap();
+        assert_eq!(v["status"], "signal: 9 (SIGKILL)");
+    }
+}
@@ -0,0 +1,145 @@
+//! `rhss tune` — `crate::simulate::tune` over the recorded access trace,
+//! printing the resulting `[policy]` and, with `--apply`, handing it to
+//! the running daemon as `SetPolicy`.
+
+use serde::Serialize;
+
+use crate::control::Request;
+use crate::error::{FsError, Result};
+use crate::simulate::{self, Tuning};
+
+use super::common::{fmt_bytes, CliContext};
+use super::{simulate_cmd, TuneArgs};
+
+/// `80%` or `0.8`.
+pub fn parse_ratio(s: &str) -> std::result::Result<f64, String> {
+    let (num, scale) = match s.strip_suffix('%') {
+        Some(n) => (n, 100.0),
+        None => (s, 1.0),
+    };
+    let v: f64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("{s:?} is not a ratio like 80% or 0.8"))?;
+    let v = v / scale;
+    if !(v > 0.0 && v <= 1.0) {
+        return Err(format!("{s} is not within (0%, 100%]"));
+    }
+    Ok(v)
+}
+
+#[derive(Serialize)]
+struct Out<'a> {
+    target: f64,
+    fast_capacity: u64,
+    applied: bool,
+    #[serde(flatten)]
+    tuning: &'a Tuning,
+}
+
+pub fn run(ctx: &CliContext, args: TuneArgs) -> Result<()> {
+    let trace_path = match args.trace {
+        Some(p) => p,
+        None => {
+            let cfg = ctx.load_config()?;
+            cfg.access_trace.path(&cfg.db)
+        }
+    };
+    let trace = simulate_cmd::load_trace(&trace_path)?;
+    if trace.is_empty() {
+        return Err(FsError::InvalidOperation(format!(
+            "{}: no accesses to tune for",
+            trace_path.display()
+        )));
+    }
+    let fast_capacity = match args.fast_capacity {
+        Some(c) => c,
+        None => ctx.build_router()?.1.fast.capacity().0,
+    };
+    if fast_capacity == 0 {
+        return Err(FsError::InvalidOperation(
+            "fast tier capacity unknown; pass --fast-capacity".into(),
+        ));
+    }
+    let base = simulate_cmd::config_policy(ctx)?;
+    let target = args.target_hot_usage;
+    let tuning = simulate::tune(&trace, fast_capacity, &base, target)?;
+
+    if args.apply {
+        if !tuning.meets_target {
+            return Err(FsError::InvalidOperation(format!(
+                "no policy keeps the fast tier under {:.0}% (best peaks at {:.1}%); not applying",
+                target * 100.0,
+                tuning.report.peak_fast_usage * 100.0
+            )));
+        }
+        let req = Request::SetPolicy {
+            policy: tuning.policy.clone(),
+        };
+        match super::control::try_send(ctx, &req)? {
+            Some(resp) if resp.ok => {}
+            Some(resp) => {
+                return Err(FsError::Storage(format!(
+                    "set policy: {}",
+                    resp.error.as_deref().unwrap_or("(no error message)")
+                )))
+            }
+            None => {
+                return Err(FsError::Storage(
+                    "rhss is not mounted; nothing to apply to".into(),
+                ))
+            }
+        }
+    }
+
+    if ctx.json {
+        let out = Out {
+            target,
+            fast_capacity,
+            applied: args.apply,
+            tuning: &tuning,
+        };
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
+    }
+    let r = &tuning.report;
+    println!(
+        "target {:.0}% of {}: {} (peak {:.1}%), hit rate {:.1}%, {} migrations ({}), {} policies tried",
+        target * 100.0,
+        fmt_bytes(fast_capacity),
+        if tuning.meets_target {
+            "met"
+        } else {
+            "NOT met"
+        },
+        r.peak_fast_usage * 100.0,
+        r.hit_rate() * 100.0,
+        r.migrations,
+        fmt_bytes(r.migrated_bytes),
+        tuning.tried
+    );
+    let toml = toml::to_string(&tuning.policy)
+        .map_err(|e| FsError::Storage(format!("render policy: {e}")))?;
+    println!();
+    println!("[policy]");
+    print!("{toml}");
+    if args.apply {
+        println!();
+        println!("applied to the running daemon until its next reload");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratios_take_percent_or_fraction() {
+        assert_eq!(parse_ratio("80%"), Ok(0.8));
+        assert_eq!(parse_ratio("0.75"), Ok(0.75));
+        for bad in ["0", "120%", "x%", "-0.5"] {
+            assert!(parse_ratio(bad).is_err(), "{bad}");
+        }
+    }
+}
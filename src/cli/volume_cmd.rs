@@ -0,0 +1,84 @@
+//! `rhss volume create|delete|mount|unmount` — see `crate::volume`.
+//! `mount` and `unmount` are `rhss mount` and `rhss umount` run with the
+//! volume's derived config, as `rhss --volume NAME ...` does for every
+//! other command.
+
+use crate::config::PolicyOptions;
+use crate::error::{FsError, Result};
+use crate::volume::{self, VolumeSpec};
+
+use super::common::CliContext;
+use super::{control, mount_cmd, VolumeCmd, VolumeCreateArgs, VolumeMountArgs};
+
+pub fn run(ctx: &CliContext, cmd: VolumeCmd) -> Result<()> {
+    // Volumes are named from the main config, whatever `--volume` says.
+    let main = ctx.with_volume(None);
+    match cmd {
+        VolumeCmd::Create(args) => create(&main, args),
+        VolumeCmd::Delete { name } => {
+            let deleted = volume::delete(&main.load_config_raw()?, &name)?;
+            report(ctx, &name, "deleted", deleted)
+        }
+        VolumeCmd::Mount(args) => mount(&main, *args),
+        VolumeCmd::Unmount(args) => {
+            control::umount(&main.with_volume(Some(&args.name)), args.umount)
+        }
+    }
+}
+
+fn create(ctx: &CliContext, args: VolumeCreateArgs) -> Result<()> {
+    let spec = VolumeSpec {
+        capacity: args.capacity,
+        mount: None,
+        policy: PolicyOptions {
+            low_watermark: args.low_watermark,
+            high_watermark: args.high_watermark,
+            ..Default::default()
+        },
+    };
+    let created = volume::create(&ctx.load_config_raw()?, &args.name, &spec)?;
+    report(ctx, &args.name, "created", created)
+}
+
+/// `done` says whether anything changed; repeating a create or delete is
+/// not an error.
+fn report(ctx: &CliContext, name: &str, what: &str, done: bool) -> Result<()> {
+    if ctx.json {
+        println!("{}", serde_json::json!({ "volume": name, what: done }));
+    } else if done {
+        println!("{what} volume {name}");
+    } else if what == "created" {
+        println!("volume {name} already exists");
+    } else {
+        println!("no volume {name}");
+    }
+    Ok(())
+}
+
+fn mount(ctx: &CliContext, args: VolumeMountArgs) -> Result<()> {
+    if args.mount.shared.is_some() || args.mount.hidden_storage {
+        return Err(FsError::InvalidOperation(
+            "--shared and --hidden-storage don't apply to volumes".into(),
+        ));
+    }
+    let cfg = ctx.load_config_raw()?;
+    let target = std::path::absolute(&args.target)
+        .map_err(|e| FsError::from_io(e, args.target.display()))?;
+    let spec = volume::load(&cfg, &args.name)?;
+    let lock = crate::lock::inspect(&volume::meta_dir(&cfg, &args.name))
+        .map_err(|e| FsError::Storage(e.to_string()))?;
+    if lock.held {
+        let at = spec.mount.unwrap_or_default();
+        if at == target {
+            println!("volume {} already mounted at {}", args.name, at.display());
+            return Ok(());
+        }
+        return Err(FsError::AlreadyExists(format!(
+            "volume {} is mounted at {}",
+            args.name,
+            at.display()
+        )));
+    }
+    volume::set_mount(&cfg, &args.name, &target)?;
+    mount_cmd::run(&ctx.with_volume(Some(&args.name)), args.mount)
+}
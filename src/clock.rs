@@ -0,0 +1,64 @@
+//! `Clock` — where age-based decisions get the time, so tests can move it.
+//!
+//! The index's `coldest` cutoff (`min_age_to_evict` / `min_age_to_archive`),
+//! the tierer's sweep schedule and window, trash retention and the storage
+//! lock's heartbeat staleness read a `Clock` instead of `SystemTime::now()`.
+//! Production uses `SystemClock`; tests hand a `MockClock` to
+//! `SqlitePathIndex::open_with_clock`, `Tierer::spawn`,
+//! `Trash::with_clock`, `StorageLock::with_clock` or
+//! `RhssBuilder::with_clock` and `advance` it rather than sleeping.
+
+use std::fmt::Debug;
+use std::time::{Duration, SystemTime};
+
+use parking_lot::Mutex;
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real wall clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+}
+
+impl MockClock {
+    pub fn new(at: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(at),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock() += by;
+    }
+
+    pub fn set(&self, at: SystemTime) {
+        *self.now.lock() = at;
+    }
+}
+
+impl Default for MockClock {
+    /// Starts at the real current time, so rows stamped with
+    /// `SystemTime::now()` line up with it.
+    fn default() -> Self {
+        Self::new(SystemTime::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock()
+    }
+}
@@ -0,0 +1,124 @@
+//! `RHSS_*` environment overrides, layered over the config file so a
+//! container can be configured without templating one.
+//!
+//! The variable name is the setting's path upper-cased, with `__` between
+//! levels and a number for an array element:
+//!
+//! ```text
+//! RHSS_MOUNT=/mnt/rhss
+//! RHSS_LOG_LEVEL=info,rhss::tierer=debug
+//! RHSS_POLICY__HIGH_WATERMARK=0.9
+//! RHSS_TIER__FAST__0__ROOT=/ssd/.rhss_managed
+//! RHSS_FUSE__ATTR_TTL_SECS=5
+//! ```
+//!
+//! Values are read as TOML (`0.9`, `true`, `["a", "b"]`) and otherwise
+//! taken as a string, so `'"123"'` forces a string that looks like a
+//! number. An index one past the end of an array appends an element, so a
+//! tier can be declared from the environment alone. Command-line flags
+//! still win over both.
+
+use toml::{Table, Value};
+
+use crate::error::{FsError, Result};
+
+/// Prefix of the variables read as overrides.
+const ENV_PREFIX: &str = "RHSS_";
+
+/// `RHSS_*` variables that are not settings. The last two are set by
+/// `build.rs`, and cargo passes them on to `cargo run` and `cargo test`.
+const RESERVED: &[&str] = &[
+    "RHSS_CONFIG",
+    "RHSS_EVENT",
+    "RHSS_SUPERVISED",
+    "RHSS_GIT_COMMIT",
+    "RHSS_FUSER_VERSION",
+];
+
+/// The overrides among `vars`, as (variable, value), sorted so an array is
+/// filled in index order.
+pub(super) fn overrides(vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut found: Vec<_> = vars
+        .into_iter()
+        .filter(|(k, _)| k.starts_with(ENV_PREFIX) && !RESERVED.contains(&k.as_str()))
+        .collect();
+    found.sort();
+    found
+}
+
+/// Apply `overrides` to the parsed config `doc`.
+pub(super) fn apply(doc: &mut Table, overrides: &[(String, String)]) -> Result<()> {
+    for (var, raw) in overrides {
+        let path: Vec<String> = var[ENV_PREFIX.len()..]
+            .split("__")
+            .map(str::to_ascii_lowercase)
+            .collect();
+        if path.iter().any(String::is_empty) {
+            return Err(FsError::Storage(format!("{var}: empty key segment")));
+        }
+        set(doc, &path, value(raw)).map_err(|why| FsError::Storage(format!("{var}: {why}")))?;
+    }
+    Ok(())
+}
+
+/// Set `path` under `table` to `v`, creating tables and array elements on
+/// the way.
+fn set(table: &mut Table, path: &[String], v: Value) -> std::result::Result<(), String> {
+    let (key, rest) = path.split_first().expect("non-empty path");
+    let Some(next) = rest.first() else {
+        table.insert(key.clone(), v);
+        return Ok(());
+    };
+    let child = table.entry(key.clone()).or_insert_with(|| empty_for(next));
+    set_in(child, key, rest, v)
+}
+
+/// Like `set`, for the value found at `key`.
+fn set_in(
+    node: &mut Value,
+    key: &str,
+    path: &[String],
+    v: Value,
+) -> std::result::Result<(), String> {
+    let items = match node {
+        Value::Table(t) => return set(t, path, v),
+        Value::Array(items) => items,
+        _ => return Err(format!("`{key}` is not a table")),
+    };
+    let (seg, rest) = path.split_first().expect("non-empty path");
+    let i: usize = seg
+        .parse()
+        .map_err(|_| format!("`{key}` is an array; expected an index, got `{seg}`"))?;
+    if i == items.len() {
+        items.push(
+            rest.first()
+                .map_or(Value::Boolean(false), |next| empty_for(next)),
+        );
+    }
+    let len = items.len();
+    let item = items
+        .get_mut(i)
+        .ok_or_else(|| format!("index {i} is past the end of `{key}` ({len} elements)"))?;
+    if rest.is_empty() {
+        *item = v;
+        return Ok(());
+    }
+    set_in(item, seg, rest, v)
+}
+
+/// An empty array if the next segment is an index, else an empty table.
+fn empty_for(next: &str) -> Value {
+    if next.parse::<usize>().is_ok() {
+        Value::Array(Vec::new())
+    } else {
+        Value::Table(Table::new())
+    }
+}
+
+/// `raw` as a TOML value, or as a plain string if it doesn't parse as one.
+fn value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("v = {raw}"))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
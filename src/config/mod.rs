@@ -20,21 +20,972 @@
 //! ```
 //!
 //! Numeric fields and policy fields land in P2.
+//!
+//! Any setting can also come from an `RHSS_*` environment variable, e.g.
+//! `RHSS_POLICY__HIGH_WATERMARK=0.9`; see `env` for the naming. These win
+//! over the file and lose to command-line flags. With no config file at
+//! all, the variables alone make the config.
 
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use crate::audit::AuditSink;
+use crate::backend::{DedupMode, ReplicationMode};
 use crate::error::{FsError, Result};
+use crate::fuse::{parse_mode, parse_mount_name, IdMap, IdRange, IdTranslation, Modes};
+use crate::policy::PopularityPolicy;
+use crate::quota::LimitSpec;
+use crate::scan::DuplicatePolicy;
+
+mod env;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RhssConfig {
     pub mount: PathBuf,
     pub db: PathBuf,
     pub tier: TierMap,
+    /// FUSE mount options. Absent = platform defaults.
+    #[serde(default)]
+    pub fuse: FuseOptions,
+    /// Mount over the directory holding the backend roots / db; see
+    /// `crate::hidden`. `rhss mount --hidden-storage` also turns it on.
+    #[serde(default)]
+    pub hidden_storage: bool,
+    /// Tiering thresholds. Absent = `PopularityPolicy` defaults.
+    #[serde(default)]
+    pub policy: PolicyOptions,
+    /// Soft delete. Off unless `[trash]` says `enabled = true`.
+    #[serde(default)]
+    pub trash: TrashOptions,
+    /// Byte limits keyed by logical directory or `uid <n>`, e.g.
+    /// `"/projects/foo" = "50G"`. See `crate::quota`.
+    #[serde(default)]
+    pub quota: BTreeMap<String, LimitSpec>,
+    /// Trail of mutating operations. Off unless `[audit]` says
+    /// `enabled = true`.
+    #[serde(default)]
+    pub audit: AuditOptions,
+    /// Binary trace of file accesses, for `rhss simulate` and `rhss
+    /// analyze`. Off unless `[access_trace]` says `enabled = true`.
+    #[serde(default)]
+    pub access_trace: AccessTraceOptions,
+    /// gRPC admin API. Off unless `[grpc]` sets `listen`.
+    #[serde(default)]
+    pub grpc: GrpcOptions,
+    /// What the startup scan does with a path found on two backends with
+    /// different content: `"error"` (default, refuse to mount),
+    /// `"prefer-newer"` or `"prefer-hot"`. See `crate::scan`.
+    #[serde(default)]
+    pub duplicate_policy: DuplicatePolicy,
+    /// Log filter (`RUST_LOG` syntax, e.g. `"info,rhss::tierer=debug"`).
+    /// `RUST_LOG` wins at startup; SIGHUP re-applies this.
+    #[serde(default)]
+    pub log_level: Option<String>,
+    /// Bandwidth limits for migration and cold-tier reads, and ceilings
+    /// per uid or tenant. Absent = unlimited. See `crate::throttle`.
+    #[serde(default)]
+    pub throttle: ThrottleOptions,
+    /// Scheduled demotion sweeps. Absent = watermark cycles only. See
+    /// `crate::tierer::schedule`.
+    #[serde(default)]
+    pub schedule: ScheduleOptions,
+    /// Commands or webhooks told about tiering events. See `crate::hooks`.
+    #[serde(default)]
+    pub hooks: Vec<HookOptions>,
+    /// Retries and circuit breaking for slow- and archive-tier backends.
+    /// See `crate::backend::breaker`.
+    #[serde(default)]
+    pub breaker: BreakerOptions,
+    /// Periodic backend probes and the readiness gate. See `crate::health`.
+    #[serde(default)]
+    pub health: HealthOptions,
+    /// The storage lock. See `crate::lock`.
+    #[serde(default)]
+    pub lock: LockOptions,
+    /// Per-tier storage prices for `rhss report costs`. See `crate::cost`.
+    #[serde(default)]
+    pub cost: CostOptions,
+    /// Scheduled per-owner usage and cost reports. See `crate::cost`.
+    #[serde(default)]
+    pub usage_report: UsageReportOptions,
+    /// Top-level directories handed to separate teams, by tenant name.
+    /// See `crate::tenant`.
+    #[serde(default)]
+    pub tenants: BTreeMap<String, TenantOptions>,
+}
+
+/// `[lock]` — the storage lock. Read at mount.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LockOptions {
+    /// Mark the lock file immutable while held so it can't be deleted by
+    /// hand. Needs `CAP_LINUX_IMMUTABLE` on Linux. Default off.
+    #[serde(default)]
+    pub immutable: bool,
+    /// Keep a heartbeat marker (`.rhss-owner`) in every tier root, for tier
+    /// paths on NFS shared between hosts: a second host pointed at the same
+    /// roots through a different config refuses to start, and a running
+    /// daemon logs an error when another host shows up. Default off: the
+    /// marker is rewritten every 10 s, which keeps idle disks spinning.
+    #[serde(default)]
+    pub host_markers: bool,
+    /// `host:port` of an `rhss lock serve` lease service. When set the lock
+    /// also needs a lease from it, renewed with the heartbeat, so hosts
+    /// whose file locks don't reach each other still exclude one another.
+    #[serde(default)]
+    pub lease_server: Option<String>,
+    /// Name of the storage on the lease service. Every host mounting the
+    /// same storage must use the same key. Default: the lock directory.
+    #[serde(default)]
+    pub lease_key: Option<String>,
+}
+
+impl LockOptions {
+    /// The lease client for `lock_dir`'s storage, if a server is configured.
+    pub fn lease(&self, lock_dir: &Path) -> Option<crate::lease::LeaseClient> {
+        let server = self.lease_server.as_ref()?;
+        let key = self
+            .lease_key
+            .clone()
+            .unwrap_or_else(|| lock_dir.display().to_string());
+        Some(crate::lease::LeaseClient::new(server.clone(), key))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(server) = &self.lease_server {
+            let port = server.rsplit_once(':').map(|(_, p)| p.parse::<u16>());
+            if !matches!(port, Some(Ok(_))) {
+                return Err(FsError::Storage(format!(
+                    "lock.lease_server must be host:port, got {server:?}"
+                )));
+            }
+        } else if self.lease_key.is_some() {
+            return Err(FsError::Storage(
+                "lock.lease_key needs lock.lease_server".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `[cost]` — USD per GiB-month of each tier, for chargeback estimates.
+/// A tier left out is priced from its backends' `cost_per_gb_month`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CostOptions {
+    #[serde(default)]
+    pub fast: Option<f64>,
+    #[serde(default)]
+    pub slow: Option<f64>,
+    #[serde(default)]
+    pub archive: Option<f64>,
+}
+
+impl CostOptions {
+    fn validate(&self) -> Result<()> {
+        for (name, v) in [
+            ("fast", self.fast),
+            ("slow", self.slow),
+            ("archive", self.archive),
+        ] {
+            if let Some(v) = v.filter(|v| !v.is_finite() || *v < 0.0) {
+                return Err(FsError::Storage(format!(
+                    "cost.{name} must be a non-negative number, got {v}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `[usage_report]` — write `rhss report usage` to a directory on a
+/// schedule while mounted. Off unless `interval_secs` is set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageReportOptions {
+    /// Seconds between reports, e.g. 86400 for daily.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// Report directory. `None` = `<db dir>/.rhss/reports`.
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    #[serde(default)]
+    pub group_by: crate::cost::GroupBy,
+    #[serde(default)]
+    pub format: crate::cost::ReportFormat,
+}
+
+impl UsageReportOptions {
+    pub fn dir(&self, db: &Path) -> PathBuf {
+        self.dir.clone().unwrap_or_else(|| {
+            db.parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."))
+                .join(".rhss")
+                .join("reports")
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.interval_secs == Some(0) {
+            return Err(FsError::Storage(
+                "usage_report.interval_secs must be non-zero".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `[tenants.<name>]` — one team's top-level directory. See
+/// `crate::tenant`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TenantOptions {
+    /// Default `/<name>`.
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    /// Group the directory is given to; nobody else gets in.
+    #[serde(default)]
+    pub gid: Option<u32>,
+    /// Byte quota on the directory, as in `[quota]`.
+    #[serde(default)]
+    pub quota: Option<LimitSpec>,
+    /// Bytes on the fast tier past which new files start on slow.
+    #[serde(default)]
+    pub fast_limit: Option<LimitSpec>,
+    /// `"slow"` to never start files on fast. Default: as the policy says.
+    #[serde(default)]
+    pub create_tier: Option<String>,
+    /// `[policy] min_age_to_evict_secs` for this tenant's files.
+    #[serde(default)]
+    pub min_age_to_evict_secs: Option<u64>,
+    /// `[policy] min_age_to_archive_secs` for this tenant's files.
+    #[serde(default)]
+    pub min_age_to_archive_secs: Option<u64>,
+}
+
+/// `[health]` — backend probes. Unset fields keep the `HealthConfig`
+/// defaults. Read at mount.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealthOptions {
+    /// Seconds between probes; 0 = only at mount. Default 60.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// Probes slower than this many milliseconds fail. Default 1000.
+    #[serde(default)]
+    pub slow_ms: Option<u64>,
+    /// How long mount waits for green before reporting ready. Default 60.
+    #[serde(default)]
+    pub ready_timeout_secs: Option<u64>,
+    /// Serve `/livez` and `/readyz` over HTTP here, for container
+    /// probes. Off unless set. See `crate::probes`.
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+}
+
+impl HealthOptions {
+    pub fn to_config(&self) -> crate::health::HealthConfig {
+        let d = crate::health::HealthConfig::default();
+        crate::health::HealthConfig {
+            interval: match self.interval_secs {
+                Some(0) => None,
+                Some(s) => Some(Duration::from_secs(s)),
+                None => d.interval,
+            },
+            slow: self.slow_ms.map(Duration::from_millis).unwrap_or(d.slow),
+            ready_timeout: self
+                .ready_timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(d.ready_timeout),
+        }
+    }
+}
+
+/// `[breaker]` — how cold backends ride out outages. Unset fields keep
+/// the `BreakerConfig` defaults. Read at mount.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BreakerOptions {
+    /// Extra attempts for an idempotent call. Default 2.
+    #[serde(default)]
+    pub retries: Option<u32>,
+    /// First retry delay, doubled each time. Default 200.
+    #[serde(default)]
+    pub backoff_ms: Option<u64>,
+    /// Failures in a row before calls fail fast; 0 = never. Default 5.
+    #[serde(default)]
+    pub trip_after: Option<u32>,
+    /// Seconds between recovery probes. Default 10.
+    #[serde(default)]
+    pub probe_secs: Option<u64>,
+}
+
+impl BreakerOptions {
+    pub fn to_config(&self) -> Result<crate::backend::BreakerConfig> {
+        let d = crate::backend::BreakerConfig::default();
+        if self.probe_secs == Some(0) {
+            return Err(FsError::Storage(
+                "breaker.probe_secs must be non-zero".into(),
+            ));
+        }
+        Ok(crate::backend::BreakerConfig {
+            retries: self.retries.unwrap_or(d.retries),
+            backoff: self
+                .backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(d.backoff),
+            trip_after: self.trip_after.unwrap_or(d.trip_after),
+            probe_every: self
+                .probe_secs
+                .map(Duration::from_secs)
+                .unwrap_or(d.probe_every),
+        })
+    }
+}
+
+/// One `[[hooks]]` entry: a `command` argv or an http `url`, for the
+/// listed `events` (all of them when empty). Reloadable with SIGHUP.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HookOptions {
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl HookOptions {
+    pub fn to_hook(&self) -> Result<crate::hooks::Hook> {
+        let events = self.events.clone();
+        match (&self.command, &self.url) {
+            (Some(argv), None) => crate::hooks::Hook::command(argv.clone(), events),
+            (None, Some(url)) => crate::hooks::Hook::webhook(url, events),
+            _ => Err(FsError::Storage(
+                "each [[hooks]] needs exactly one of command or url".into(),
+            )),
+        }
+    }
+}
+
+/// `[schedule]` — when sweeps run, e.g. `sweep = "0 2 * * *"`. Reloadable
+/// with SIGHUP.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleOptions {
+    /// Cron expression (local time).
+    #[serde(default)]
+    pub sweep: Option<String>,
+    /// `HH:MM-HH:MM`; a sweep only moves files inside it.
+    #[serde(default)]
+    pub window: Option<String>,
+    /// Most bytes one sweep may move.
+    #[serde(default)]
+    pub max_bytes: Option<String>,
+}
+
+impl ScheduleOptions {
+    pub fn to_schedule(&self) -> Result<Option<crate::tierer::Schedule>> {
+        use crate::tierer::schedule::{Cron, Window};
+        let Some(sweep) = &self.sweep else {
+            if self.window.is_some() || self.max_bytes.is_some() {
+                return Err(FsError::Storage(
+                    "schedule.window and schedule.max_bytes need schedule.sweep".into(),
+                ));
+            }
+            return Ok(None);
+        };
+        let sweep =
+            Cron::parse(sweep).map_err(|e| FsError::Storage(format!("schedule.sweep: {e}")))?;
+        let window = match &self.window {
+            Some(w) => Some(
+                Window::parse(w).map_err(|e| FsError::Storage(format!("schedule.window: {e}")))?,
+            ),
+            None => None,
+        };
+        let max_bytes = match &self.max_bytes {
+            Some(v) => match crate::quota::parse_size(v) {
+                Ok(0) => {
+                    return Err(FsError::Storage(
+                        "schedule.max_bytes must be non-zero".into(),
+                    ))
+                }
+                Ok(n) => Some(n),
+                Err(e) => return Err(FsError::Storage(format!("schedule.max_bytes: {e}"))),
+            },
+            None => None,
+        };
+        Ok(Some(crate::tierer::Schedule {
+            sweep,
+            window,
+            max_bytes,
+        }))
+    }
+
+    /// Install this schedule process-wide (`crate::tierer::set_schedule`).
+    pub fn apply(&self) -> Result<()> {
+        crate::tierer::set_schedule(self.to_schedule()?);
+        Ok(())
+    }
+}
+
+impl RhssConfig {
+    /// The `[[hooks]]` entries, validated.
+    pub fn hooks(&self) -> Result<Vec<crate::hooks::Hook>> {
+        self.hooks.iter().map(HookOptions::to_hook).collect()
+    }
+
+    /// `[tenants]`, or `None` without any.
+    pub fn tenants(&self) -> Result<Option<Arc<crate::tenant::Tenants>>> {
+        if self.tenants.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(crate::tenant::Tenants::new(crate::tenant::parse(
+            &self.tenants,
+        )?)))
+    }
+
+    /// `[throttle]` ceilings on FUSE reads and writes per uid and tenant.
+    pub fn io_limits(&self) -> Result<Option<Arc<crate::throttle::IoLimits>>> {
+        self.throttle
+            .io_limits(&crate::tenant::parse(&self.tenants)?)
+    }
+
+    /// The `[quota]` rules plus every tenant's quota.
+    pub fn quota_rules(&self) -> Result<Vec<crate::quota::QuotaRule>> {
+        let mut rules = crate::quota::parse_rules(&self.quota)?;
+        if let Some(t) = self.tenants()? {
+            rules.extend(t.quota_rules());
+        }
+        Ok(rules)
+    }
+
+    /// Where the storage lock (`.rhss.lock`, see `crate::lock`) lives: next
+    /// to the index db.
+    pub fn lock_dir(&self) -> PathBuf {
+        self.db
+            .parent()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+}
+
+/// Keys of `given` (the config as written) missing from `known` (the
+/// parsed config written back out): everything rhss reads comes back, so
+/// what's left is a typo or a setting from another version.
+fn unknown_keys(given: &toml::Table, known: &toml::Table, at: &str, out: &mut Vec<String>) {
+    use toml::Value;
+    for (key, v) in given {
+        let path = if at.is_empty() {
+            key.clone()
+        } else {
+            format!("{at}.{key}")
+        };
+        match (v, known.get(key)) {
+            (_, None) => out.push(format!("`{path}`")),
+            (Value::Table(g), Some(Value::Table(k))) => unknown_keys(g, k, &path, out),
+            (Value::Array(g), Some(Value::Array(k))) => {
+                for (i, (g, k)) in g.iter().zip(k).enumerate() {
+                    if let (Value::Table(g), Value::Table(k)) = (g, k) {
+                        unknown_keys(g, k, &format!("{path}[{i}]"), out);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `[throttle]` — bytes per second, e.g. `migration = "50M"`, and
+/// ceilings on FUSE reads and writes per uid or tenant. Reloadable with
+/// SIGHUP.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ThrottleOptions {
+    /// Tierer copies between tiers.
+    #[serde(default)]
+    pub migration: Option<String>,
+    /// FUSE reads from backends outside the fast tier.
+    #[serde(default)]
+    pub cold_reads: Option<String>,
+    /// `[throttle.uids.<uid>]`; `"*"` is each uid without its own entry.
+    #[serde(default)]
+    pub uids: BTreeMap<String, IoLimitOptions>,
+    /// `[throttle.tenants.<name>]`, a name from `[tenants]`.
+    #[serde(default)]
+    pub tenants: BTreeMap<String, IoLimitOptions>,
+}
+
+/// One `[throttle.uids]` or `[throttle.tenants]` entry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IoLimitOptions {
+    /// Reads and writes per second.
+    #[serde(default)]
+    pub iops: Option<u64>,
+    /// Bytes read and written per second, e.g. `"50M"`.
+    #[serde(default)]
+    pub bandwidth: Option<String>,
+}
+
+impl IoLimitOptions {
+    fn to_limit(&self, what: &str) -> Result<crate::throttle::IoLimit> {
+        let bytes_per_sec = match &self.bandwidth {
+            Some(v) => Some(
+                crate::quota::parse_size(v)
+                    .map_err(|e| FsError::Storage(format!("{what}.bandwidth: {e}")))?,
+            ),
+            None => None,
+        };
+        if self.iops == Some(0) || bytes_per_sec == Some(0) {
+            return Err(FsError::Storage(format!("{what}: limits must be non-zero")));
+        }
+        if self.iops.is_none() && bytes_per_sec.is_none() {
+            return Err(FsError::Storage(format!("{what}: needs iops or bandwidth")));
+        }
+        Ok(crate::throttle::IoLimit {
+            iops: self.iops,
+            bytes_per_sec,
+        })
+    }
+}
+
+impl ThrottleOptions {
+    /// `(migration, cold_reads)` in bytes per second.
+    pub fn limits(&self) -> Result<(Option<u64>, Option<u64>)> {
+        let parse = |name: &str, v: &Option<String>| -> Result<Option<u64>> {
+            let Some(v) = v else {
+                return Ok(None);
+            };
+            match crate::quota::parse_size(v) {
+                Ok(0) => Err(FsError::Storage(format!(
+                    "throttle.{name} must be non-zero"
+                ))),
+                Ok(n) => Ok(Some(n)),
+                Err(e) => Err(FsError::Storage(format!("throttle.{name}: {e}"))),
+            }
+        };
+        Ok((
+            parse("migration", &self.migration)?,
+            parse("cold_reads", &self.cold_reads)?,
+        ))
+    }
+
+    /// The uid and tenant ceilings, with tenant names resolved against
+    /// `tenants` (`crate::tenant::parse`). `None` without any.
+    pub fn io_limits(
+        &self,
+        tenants: &[crate::tenant::Tenant],
+    ) -> Result<Option<Arc<crate::throttle::IoLimits>>> {
+        let mut uids = HashMap::new();
+        let mut default_uid = None;
+        for (uid, opts) in &self.uids {
+            let limit = opts.to_limit(&format!("throttle.uids.{uid}"))?;
+            if uid == "*" {
+                default_uid = Some(limit);
+            } else {
+                let uid = uid.parse::<u32>().map_err(|_| {
+                    FsError::Storage(format!("throttle.uids: {uid:?} is not a uid or \"*\""))
+                })?;
+                uids.insert(uid, limit);
+            }
+        }
+        let mut by_tenant = Vec::new();
+        for (name, opts) in &self.tenants {
+            let limit = opts.to_limit(&format!("throttle.tenants.{name}"))?;
+            let tenant = tenants.iter().find(|t| &t.name == name).ok_or_else(|| {
+                FsError::Storage(format!("throttle.tenants: no tenant {name:?} in [tenants]"))
+            })?;
+            by_tenant.push((tenant.dir.clone(), limit));
+        }
+        Ok(crate::throttle::IoLimits::new(uids, default_uid, by_tenant))
+    }
+
+    /// Install these limits process-wide (`crate::throttle`).
+    pub fn apply(&self) -> Result<()> {
+        use crate::throttle::{set_limit, Class};
+        let (migration, cold_reads) = self.limits()?;
+        set_limit(Class::Migration, migration);
+        set_limit(Class::ColdRead, cold_reads);
+        Ok(())
+    }
+}
+
+/// `[policy]` — tiering thresholds. Every field is optional and
+/// reloadable with SIGHUP.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PolicyOptions {
+    /// Fast-tier usage the tierer evicts down to.
+    #[serde(default)]
+    pub low_watermark: Option<f64>,
+    /// Fast-tier usage that starts an eviction cycle.
+    #[serde(default)]
+    pub high_watermark: Option<f64>,
+    /// Fast-tier usage above which new files land on Slow.
+    #[serde(default)]
+    pub panic_watermark: Option<f64>,
+    /// Seconds between tier cycles; negative = manual only (D15).
+    #[serde(default)]
+    pub tier_period_secs: Option<i64>,
+    #[serde(default)]
+    pub min_age_to_evict_secs: Option<u64>,
+    #[serde(default)]
+    pub min_age_to_archive_secs: Option<u64>,
+    #[serde(default)]
+    pub slow_archive_watermark: Option<f64>,
+}
+
+impl PolicyOptions {
+    pub fn to_policy(&self) -> PopularityPolicy {
+        let d = PopularityPolicy::default();
+        PopularityPolicy {
+            low_watermark: self.low_watermark.unwrap_or(d.low_watermark),
+            high_watermark: self.high_watermark.unwrap_or(d.high_watermark),
+            panic_watermark: self.panic_watermark.unwrap_or(d.panic_watermark),
+            tier_period: match self.tier_period_secs {
+                Some(s) if s < 0 => None,
+                Some(s) => Some(Duration::from_secs(s as u64)),
+                None => d.tier_period,
+            },
+            min_age_to_evict: self
+                .min_age_to_evict_secs
+                .map(Duration::from_secs)
+                .unwrap_or(d.min_age_to_evict),
+            min_age_to_archive: self
+                .min_age_to_archive_secs
+                .map(Duration::from_secs)
+                .unwrap_or(d.min_age_to_archive),
+            slow_archive_watermark: self
+                .slow_archive_watermark
+                .unwrap_or(d.slow_archive_watermark),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let p = self.to_policy();
+        let ok = |v: f64| v.is_finite() && (0.0..=1.0).contains(&v);
+        if !(ok(p.low_watermark) && ok(p.high_watermark) && ok(p.panic_watermark))
+            || p.low_watermark > p.high_watermark
+            || p.high_watermark > p.panic_watermark
+        {
+            return Err(FsError::Storage(format!(
+                "policy watermarks must satisfy 0 <= low ({}) <= high ({}) <= panic ({}) <= 1",
+                p.low_watermark, p.high_watermark, p.panic_watermark
+            )));
+        }
+        if !ok(p.slow_archive_watermark) {
+            return Err(FsError::Storage(format!(
+                "policy.slow_archive_watermark must be within 0..=1, got {}",
+                p.slow_archive_watermark
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// `[trash]` — see `crate::trash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashOptions {
+    #[serde(default)]
+    pub enabled: bool,
+    /// How long deleted files are kept before the daemon purges them.
+    #[serde(default = "TrashOptions::default_retention_secs")]
+    pub retention_secs: u64,
+}
+
+impl TrashOptions {
+    fn default_retention_secs() -> u64 {
+        7 * 86_400
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_secs)
+    }
+}
+
+impl Default for TrashOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_secs: Self::default_retention_secs(),
+        }
+    }
+}
+
+/// `[audit]` — see `crate::audit`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditOptions {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub sink: AuditSinkKind,
+    /// JSONL file for `sink = "file"`. `None` = `<db dir>/.rhss/audit.jsonl`.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Records queued for the writer before new ones are dropped (and
+    /// counted). `None` = 8192.
+    #[serde(default)]
+    pub buffer: Option<usize>,
+}
+
+/// `[access_trace]` — see `crate::access::trace`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessTraceOptions {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Trace file. `None` = `<db dir>/.rhss/access.trace`.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Records queued for the writer before new ones are dropped. `None`
+    /// = 16384.
+    #[serde(default)]
+    pub buffer: Option<usize>,
+}
+
+impl AccessTraceOptions {
+    pub fn path(&self, db: &Path) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| crate::access::trace::trace_path_for(db))
+    }
+
+    pub fn buffer(&self) -> usize {
+        self.buffer.unwrap_or(crate::access::trace::DEFAULT_BUFFER)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.buffer == Some(0) {
+            return Err(FsError::Storage(
+                "access_trace.buffer must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSinkKind {
+    #[default]
+    File,
+    Syslog,
+}
+
+impl AuditOptions {
+    pub fn sink(&self, db: &Path) -> AuditSink {
+        match self.sink {
+            AuditSinkKind::File => AuditSink::File(
+                self.path
+                    .clone()
+                    .unwrap_or_else(|| crate::audit::audit_path_for(db)),
+            ),
+            AuditSinkKind::Syslog => AuditSink::Syslog,
+        }
+    }
+
+    pub fn buffer(&self) -> usize {
+        self.buffer.unwrap_or(crate::audit::DEFAULT_BUFFER)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.buffer == Some(0) {
+            return Err(FsError::Storage("audit.buffer must be at least 1".into()));
+        }
+        if self.sink == AuditSinkKind::Syslog && self.path.is_some() {
+            return Err(FsError::Storage(
+                "audit.path only applies to sink = \"file\"".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `[grpc]` — see `proto/rhss/admin/v1/admin.proto`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GrpcOptions {
+    /// Address for the admin service, e.g. `"127.0.0.1:50051"`. There is
+    /// no authentication; keep it on a private address.
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+}
+
+impl GrpcOptions {
+    fn validate(&self) -> Result<()> {
+        if self.listen.is_some() && !cfg!(feature = "grpc") {
+            return Err(FsError::Storage(
+                "grpc.listen is set but rhss was built without the `grpc` feature".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `[fuse]` — mount options handed to the kernel. CLI flags on `rhss mount`
+/// override these.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FuseOptions {
+    /// `None` = platform default (on for Linux, off for macOS).
+    #[serde(default)]
+    pub allow_other: Option<bool>,
+    #[serde(default)]
+    pub allow_root: bool,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub noexec: bool,
+    /// Source name in the mount table, shown by `mount` and `df`; give
+    /// each mount its own to tell them apart. `None` = `rhss`.
+    #[serde(default)]
+    pub fsname: Option<String>,
+    /// Filesystem type suffix: `"rhss"` makes the mount `fuse.rhss` on
+    /// Linux. `None` = plain `fuse`.
+    #[serde(default)]
+    pub subtype: Option<String>,
+    /// Finder volume name (macOS only).
+    #[serde(default)]
+    pub volname: Option<String>,
+    /// Extra `-o` options passed through verbatim, e.g. `["uid=1000"]`.
+    #[serde(default)]
+    pub options: Vec<String>,
+    /// Worker threads serving FUSE ops (D12). `None` = 4.
+    #[serde(default)]
+    pub workers: Option<usize>,
+    /// Per-call backend timeout in seconds; a hung disk or S3 endpoint gets
+    /// `ETIMEDOUT` instead of wedging the request. `None` = 30, `0` = off.
+    #[serde(default)]
+    pub op_timeout_secs: Option<u64>,
+    /// Cap on the in-memory inode table. `None` = 500 000.
+    #[serde(default)]
+    pub max_inodes: Option<usize>,
+    /// Kernel-side permission checks against reported attrs. Set `false` to
+    /// have rhss check the backing files' real owner/mode instead.
+    #[serde(default)]
+    pub default_permissions: Option<bool>,
+    /// Kernel attr / entry cache lifetime in seconds. `None` = 1.
+    #[serde(default)]
+    pub attr_ttl_secs: Option<f64>,
+    #[serde(default)]
+    pub entry_ttl_secs: Option<f64>,
+    /// Globs the mount pretends don't exist (lookup → `ENOENT`, also hidden
+    /// from listings). A bare name matches in any directory; a pattern with
+    /// `/` matches the mount-relative path. `None` = `[".DS_Store", "._*"]`.
+    #[serde(default)]
+    pub ignore_lookup: Option<Vec<String>>,
+    /// Globs hidden from `readdir` only. `None` = same defaults as above.
+    #[serde(default)]
+    pub ignore_list: Option<Vec<String>>,
+    /// Globs exempt from both ignore lists (e.g. `._keep` under `._*`).
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Whose credentials requests act with: `passthrough` (default) or
+    /// `squash`, which maps every caller to `squash_uid`/`squash_gid`.
+    #[serde(default)]
+    pub id_map: IdMapKind,
+    /// `None` = 65534 (`nobody`).
+    #[serde(default)]
+    pub squash_uid: Option<u32>,
+    #[serde(default)]
+    pub squash_gid: Option<u32>,
+    /// Renumber uids between the mount and the storage, idmapped-mount
+    /// style: `[{ inside = 0, outside = 100000, count = 65536 }]` shows
+    /// storage uid 100000 as root. Empty = no translation.
+    #[serde(default)]
+    pub uid_map: Vec<IdRange>,
+    #[serde(default)]
+    pub gid_map: Vec<IdRange>,
+    /// Permission bits every regular file reports and is created with,
+    /// e.g. `"0664"` (or the TOML integer `0o664`). `None` = the storage's.
+    #[serde(default)]
+    pub file_mode: Option<ModeSpec>,
+    /// Same for directories.
+    #[serde(default)]
+    pub dir_mode: Option<ModeSpec>,
+    /// Bits cleared from every new file and directory, on top of the
+    /// caller's own umask. `None` = 0.
+    #[serde(default)]
+    pub umask: Option<ModeSpec>,
+}
+
+/// A `[fuse]` mode: an integer, or an octal string like `"0644"`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ModeSpec {
+    Bits(u32),
+    Octal(String),
+}
+
+impl ModeSpec {
+    fn bits(&self) -> Result<u32> {
+        match self {
+            ModeSpec::Bits(m) => Ok(*m),
+            ModeSpec::Octal(s) => parse_mode(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdMapKind {
+    #[default]
+    Passthrough,
+    Squash,
+}
+
+impl FuseOptions {
+    pub fn id_map(&self) -> IdMap {
+        match self.id_map {
+            IdMapKind::Passthrough => IdMap::Passthrough,
+            IdMapKind::Squash => IdMap::Squash {
+                uid: self.squash_uid.unwrap_or(NOBODY),
+                gid: self.squash_gid.unwrap_or(NOBODY),
+            },
+        }
+    }
+
+    /// `uid_map`/`gid_map` plus ranges given on the command line.
+    pub fn id_translation(&self, uids: &[IdRange], gids: &[IdRange]) -> Result<IdTranslation> {
+        IdTranslation::new(
+            self.uid_map.iter().chain(uids).copied().collect(),
+            self.gid_map.iter().chain(gids).copied().collect(),
+        )
+    }
+
+    /// `file_mode`/`dir_mode`/`umask`, each overridden by a command-line
+    /// value when given.
+    pub fn modes(&self, file: Option<u32>, dir: Option<u32>, umask: Option<u32>) -> Result<Modes> {
+        let pick = |flag: Option<u32>, spec: &Option<ModeSpec>| match flag {
+            Some(m) => Ok(Some(m)),
+            None => spec.as_ref().map(ModeSpec::bits).transpose(),
+        };
+        Modes::new(
+            pick(file, &self.file_mode)?,
+            pick(dir, &self.dir_mode)?,
+            pick(umask, &self.umask)?.unwrap_or(0),
+        )
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.id_translation(&[], &[])?;
+        self.modes(None, None, None)?;
+        for (key, name) in [("fsname", &self.fsname), ("subtype", &self.subtype)] {
+            if name.as_ref().is_some_and(|n| parse_mount_name(n).is_err()) {
+                return Err(FsError::Storage(format!(
+                    "fuse.{key} {name:?}: must be non-empty, without commas or spaces"
+                )));
+            }
+        }
+        if self.id_map == IdMapKind::Passthrough
+            && (self.squash_uid.is_some() || self.squash_gid.is_some())
+        {
+            return Err(FsError::Storage(
+                "fuse.squash_uid/squash_gid only apply to id_map = \"squash\"".into(),
+            ));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// `nobody` / `nogroup` on most systems.
+const NOBODY: u32 = 65534;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TierMap {
     pub fast: Vec<BackendConfig>,
     pub slow: Vec<BackendConfig>,
@@ -53,15 +1004,18 @@ pub struct TierMap {
     pub archive_policy: Option<TierPolicy>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TierPolicy {
     /// `most_free` (default), `round_robin`, or `mirror`.
     pub placement: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackendConfig {
     pub id: String,
+    /// Directory holding the backend's files, or `mem://<size>` (e.g.
+    /// `mem://512M`) for a RAM-only backend whose contents are lost on
+    /// exit. See `crate::backend::MemoryBackend`.
     pub root: PathBuf,
     /// Declared cost in USD per GiB per month (D26). Used by
     /// `CostAwarePlacement` and by `rhss cost` projections. Optional —
@@ -69,16 +1023,86 @@ pub struct BackendConfig {
     /// falls back to MostFree).
     #[serde(default)]
     pub cost_per_gb_month: Option<f64>,
+    /// Extra roots that keep a full copy of this backend, e.g. a second
+    /// HDD behind a slow-tier disk. Reads fall back to them when `root`
+    /// fails. See `crate::backend::ReplicatedBackend`.
+    #[serde(default)]
+    pub replicas: Vec<PathBuf>,
+    /// `sync` (default) or `async` replica writes.
+    #[serde(default)]
+    pub replication: ReplicationMode,
+    /// Store files as deduplicated chunks, cut `fixed` (1 MiB blocks) or
+    /// `cdc` (content-defined). Meant for cold disks holding many similar
+    /// large files. See `crate::backend::DedupBackend`.
+    #[serde(default)]
+    pub dedup: Option<DedupMode>,
+    /// Pack files smaller than this (e.g. `"64K"`) into shared pack
+    /// files, for cold disks holding millions of tiny files. See
+    /// `crate::backend::SmallFileBackend`.
+    #[serde(default)]
+    pub pack_small_files: Option<String>,
+    /// Store files larger than this (e.g. `"1G"`) as 64 MiB chunks, so
+    /// partial reads and interrupted migrations touch only the chunks
+    /// involved. See `crate::backend::ChunkedBackend`.
+    #[serde(default)]
+    pub chunk_large_files: Option<String>,
+    /// Spread the files of directories holding more than this many
+    /// entries over hash-prefix subdirectories, for cold disks with
+    /// directories of millions of files. See `crate::backend::ShardedBackend`.
+    #[serde(default)]
+    pub shard_dirs_above: Option<usize>,
+    /// Read files at least this large (e.g. `"256M"`) with `O_DIRECT` and
+    /// FUSE `direct_io`, so streaming big cold files doesn't evict the
+    /// page cache the hot tier depends on. Directory roots only.
+    #[serde(default)]
+    pub direct_io_above: Option<String>,
+}
+
+impl BackendConfig {
+    /// The `<size>` of a `mem://<size>` root; `None` for directories.
+    pub fn memory_size(&self) -> Option<&str> {
+        self.root.to_str()?.strip_prefix("mem://")
+    }
 }
 
 /// S3-compatible archive backend. Works with AWS S3, Cloudflare R2,
 /// Backblaze B2, Wasabi, MinIO — anything that speaks the S3 protocol.
 /// Credentials are read from env vars (never the toml file itself) so
 /// the config can safely be committed.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ArchiveBackendConfig {
     pub id: String,
+    /// Something other than S3; the S3 fields below are then unused.
+    /// `http://<host>[:<port>]/<path>` for an HTTP/WebDAV server (see
+    /// `crate::backend::http`), `packed:///<dir>` for a directory of
+    /// tar/zip archives browsed in place (see `crate::backend::packed`),
+    /// or, with the `object-store` feature,
+    /// `gs://<bucket>[/<prefix>]` / `az://<container>[/<prefix>]` for GCS
+    /// and Azure Blob (see `crate::backend::object`).
+    #[serde(default)]
+    pub url: Option<String>,
+    /// For `url` backends: credential key → env var holding the value.
+    /// `http://` takes `username` and `password` (Basic auth); GCS/Azure
+    /// take `object_store` config keys, e.g. `{ service_account_key =
+    /// "GCS_KEY" }`, with keys left out coming from the provider's usual
+    /// env vars.
+    #[serde(default)]
+    pub credential_env: BTreeMap<String, String>,
+    /// For `http://` backends: only read from the server; every write,
+    /// delete or rename fails with `EROFS`. `packed://` backends are
+    /// always read-only.
+    #[serde(default)]
+    pub read_only: bool,
+    /// For `gs://` / `az://` backends: retries per request (default 10).
+    #[serde(default)]
+    pub max_retries: Option<usize>,
+    /// For `gs://` / `az://` backends: stop retrying a request after this
+    /// long (default 180).
+    #[serde(default)]
+    pub retry_timeout_secs: Option<u64>,
+    #[serde(default)]
     pub endpoint: String,
+    #[serde(default)]
     pub bucket: String,
     #[serde(default = "default_region")]
     pub region: String,
@@ -87,9 +1111,11 @@ pub struct ArchiveBackendConfig {
     /// R2/B2/Wasabi: leave default (single class).
     #[serde(default = "default_storage_class")]
     pub storage_class: String,
-    /// Env var name holding the access key id. Required.
+    /// Env var name holding the access key id. Required for S3.
+    #[serde(default)]
     pub access_key_env: String,
-    /// Env var name holding the secret access key. Required.
+    /// Env var name holding the secret access key. Required for S3.
+    #[serde(default)]
     pub secret_key_env: String,
     /// Local on-disk staging cache (typically on the Slow tier) used when
     /// reading archive files. Defaults to `<db.parent>/.rhss_staging/<id>/`.
@@ -105,6 +1131,53 @@ pub struct ArchiveBackendConfig {
     /// Archive ≈ 0.001.
     #[serde(default)]
     pub cost_per_gb_month: Option<f64>,
+    /// Pack files smaller than this (e.g. `"64K"`) into shared pack
+    /// objects, so millions of tiny files don't cost a request each. See
+    /// `crate::backend::SmallFileBackend`.
+    #[serde(default)]
+    pub pack_small_files: Option<String>,
+    /// Store files larger than this (e.g. `"1G"`) as 64 MiB chunk
+    /// objects; a range read then fetches only the chunks it covers. See
+    /// `crate::backend::ChunkedBackend`.
+    #[serde(default)]
+    pub chunk_large_files: Option<String>,
+}
+
+/// `pack_small_files` of backend `id`, in bytes.
+pub fn pack_threshold(id: &str, size: &str) -> Result<u64> {
+    let bytes = crate::quota::parse_size(size)
+        .map_err(|e| FsError::Storage(format!("backend {id}: pack_small_files: {e}")))?;
+    let max = crate::backend::smallfile::MAX_PACK_THRESHOLD;
+    if bytes == 0 || bytes > max {
+        return Err(FsError::Storage(format!(
+            "backend {id}: pack_small_files must be between 1 and {max} bytes"
+        )));
+    }
+    Ok(bytes)
+}
+
+/// `chunk_large_files` of backend `id`, in bytes.
+pub fn chunk_limit(id: &str, size: &str) -> Result<u64> {
+    let bytes = crate::quota::parse_size(size)
+        .map_err(|e| FsError::Storage(format!("backend {id}: chunk_large_files: {e}")))?;
+    if bytes == 0 {
+        return Err(FsError::Storage(format!(
+            "backend {id}: chunk_large_files must be non-zero"
+        )));
+    }
+    Ok(bytes)
+}
+
+/// `direct_io_above` of backend `id`, in bytes.
+pub fn direct_io_limit(id: &str, size: &str) -> Result<u64> {
+    let bytes = crate::quota::parse_size(size)
+        .map_err(|e| FsError::Storage(format!("backend {id}: direct_io_above: {e}")))?;
+    if bytes == 0 {
+        return Err(FsError::Storage(format!(
+            "backend {id}: direct_io_above must be non-zero"
+        )));
+    }
+    Ok(bytes)
 }
 
 fn default_region() -> String {
@@ -116,12 +1189,85 @@ fn default_storage_class() -> String {
 }
 
 impl RhssConfig {
+    /// Rewrite every on-disk location (db, backend roots, staging dirs)
+    /// through `f`. Used by hidden-storage mode.
+    pub fn relocate_storage(&mut self, f: impl Fn(&Path) -> PathBuf) {
+        self.db = f(&self.db);
+        for b in self.tier.fast.iter_mut().chain(self.tier.slow.iter_mut()) {
+            if b.memory_size().is_none() {
+                b.root = f(&b.root);
+            }
+            for r in &mut b.replicas {
+                *r = f(r);
+            }
+        }
+        for a in &mut self.tier.archive {
+            if let Some(dir) = &a.staging_dir {
+                a.staging_dir = Some(f(dir));
+            }
+        }
+        if let Some(p) = &self.audit.path {
+            self.audit.path = Some(f(p));
+        }
+        if let Some(p) = &self.access_trace.path {
+            self.access_trace.path = Some(f(p));
+        }
+        if let Some(p) = &self.usage_report.dir {
+            self.usage_report.dir = Some(f(p));
+        }
+    }
+
+    /// Read `path`, with `RHSS_*` environment overrides on top (see
+    /// `env`).
     pub fn load(path: &Path) -> Result<Self> {
         let raw = std::fs::read_to_string(path).map_err(|e| {
             FsError::Storage(format!("read config {}: {e}", path.display()))
         })?;
-        let cfg: RhssConfig = toml::from_str(&raw)
+        Self::from_toml(&raw, std::env::vars())
+    }
+
+    /// The config given by `RHSS_*` environment variables alone, for a
+    /// deployment without a config file. `None` if none are set.
+    pub fn from_env() -> Result<Option<Self>> {
+        if env::overrides(std::env::vars()).is_empty() {
+            return Ok(None);
+        }
+        Self::from_toml("", std::env::vars()).map(Some)
+    }
+
+    /// Parse `raw`, overlay the `RHSS_*` entries of `vars`, and validate.
+    /// A key rhss doesn't know is an error, not a silent default.
+    pub fn from_toml(raw: &str, vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let mut doc: toml::Table =
+            toml::from_str(raw).map_err(|e| FsError::Storage(format!("parse config: {e}")))?;
+        let overrides = env::overrides(vars);
+        env::apply(&mut doc, &overrides)?;
+        let with = if overrides.is_empty() {
+            ""
+        } else {
+            " (with RHSS_* overrides)"
+        };
+        // Straight from the text when possible: its errors carry the line.
+        let parsed = if overrides.is_empty() {
+            toml::from_str(raw).map_err(|e| e.to_string())
+        } else {
+            toml::Value::Table(doc.clone())
+                .try_into()
+                .map_err(|e: toml::de::Error| e.to_string())
+        };
+        let cfg: RhssConfig =
+            parsed.map_err(|e| FsError::Storage(format!("parse config{with}: {e}")))?;
+        let known = toml::Table::try_from(&cfg)
             .map_err(|e| FsError::Storage(format!("parse config: {e}")))?;
+        let mut unknown = Vec::new();
+        unknown_keys(&doc, &known, "", &mut unknown);
+        if !unknown.is_empty() {
+            return Err(FsError::Storage(format!(
+                "config{with}: unknown key{} {}",
+                if unknown.len() == 1 { "" } else { "s" },
+                unknown.join(", ")
+            )));
+        }
         cfg.validate()?;
         Ok(cfg)
     }
@@ -133,11 +1279,109 @@ impl RhssConfig {
         if self.tier.slow.is_empty() {
             return Err(FsError::Storage("no slow-tier backends configured".into()));
         }
+        if self.fuse.allow_other == Some(true) && self.fuse.allow_root {
+            return Err(FsError::Storage(
+                "fuse.allow_other and fuse.allow_root are mutually exclusive".into(),
+            ));
+        }
+        for (name, v) in [
+            ("attr_ttl_secs", self.fuse.attr_ttl_secs),
+            ("entry_ttl_secs", self.fuse.entry_ttl_secs),
+        ] {
+            if let Some(v) = v {
+                if !v.is_finite() || v < 0.0 {
+                    return Err(FsError::Storage(format!(
+                        "fuse.{name} must be a non-negative number, got {v}"
+                    )));
+                }
+            }
+        }
+        self.policy.validate()?;
+        self.audit.validate()?;
+        self.access_trace.validate()?;
+        self.lock.validate()?;
+        self.cost.validate()?;
+        self.usage_report.validate()?;
+        self.grpc.validate()?;
+        self.fuse.validate()?;
+        self.throttle.limits()?;
+        self.schedule.to_schedule()?;
+        self.hooks()?;
+        self.breaker.to_config()?;
+        crate::quota::parse_rules(&self.quota)
+            .map_err(|e| FsError::Storage(format!("quota: {e}")))?;
+        let tenants = crate::tenant::parse(&self.tenants)
+            .map_err(|e| FsError::Storage(format!("tenants: {e}")))?;
+        self.throttle.io_limits(&tenants)?;
+        // rhss's own checks look at the file, not the directories above it.
+        if tenants.iter().any(|t| t.gid.is_some()) && self.fuse.default_permissions == Some(false) {
+            return Err(FsError::Storage(
+                "tenants with a gid need fuse.default_permissions".into(),
+            ));
+        }
+        for (name, globs) in [
+            ("ignore_lookup", self.fuse.ignore_lookup.as_ref()),
+            ("ignore_list", self.fuse.ignore_list.as_ref()),
+            ("include", Some(&self.fuse.include)),
+        ] {
+            if let Some(globs) = globs {
+                crate::filter::parse_globs(globs)
+                    .map_err(|e| FsError::Storage(format!("fuse.{name}: {e}")))?;
+            }
+        }
         let mut ids = std::collections::HashSet::new();
+        let mut roots = std::collections::HashSet::new();
         for b in self.tier.fast.iter().chain(self.tier.slow.iter()) {
             if !ids.insert(b.id.clone()) {
                 return Err(FsError::Storage(format!("duplicate backend id: {}", b.id)));
             }
+            if let Some(size) = &b.pack_small_files {
+                pack_threshold(&b.id, size)?;
+            }
+            if let Some(size) = &b.chunk_large_files {
+                chunk_limit(&b.id, size)?;
+            }
+            if b.shard_dirs_above == Some(0) {
+                return Err(FsError::Storage(format!(
+                    "backend {}: shard_dirs_above must be non-zero",
+                    b.id
+                )));
+            }
+            if let Some(size) = &b.direct_io_above {
+                direct_io_limit(&b.id, size)?;
+                if b.memory_size().is_some() || b.dedup.is_some() {
+                    return Err(FsError::Storage(format!(
+                        "backend {}: direct_io_above needs a plain directory root",
+                        b.id
+                    )));
+                }
+            }
+            if b.dedup.is_some() && (b.memory_size().is_some() || !b.replicas.is_empty()) {
+                return Err(FsError::Storage(format!(
+                    "backend {}: dedup needs a directory root and no replicas",
+                    b.id
+                )));
+            }
+            if let Some(size) = b.memory_size() {
+                let bytes = crate::quota::parse_size(size)
+                    .map_err(|e| FsError::Storage(format!("backend {}: {e}", b.id)))?;
+                if bytes == 0 || !b.replicas.is_empty() {
+                    return Err(FsError::Storage(format!(
+                        "backend {}: mem:// needs a non-zero size and no replicas",
+                        b.id
+                    )));
+                }
+                continue;
+            }
+            for root in std::iter::once(&b.root).chain(&b.replicas) {
+                if !roots.insert(root) {
+                    return Err(FsError::Storage(format!(
+                        "backend {}: root {} is used twice",
+                        b.id,
+                        root.display()
+                    )));
+                }
+            }
         }
         for a in &self.tier.archive {
             if !ids.insert(a.id.clone()) {
@@ -146,12 +1390,85 @@ impl RhssConfig {
                     a.id
                 )));
             }
+            if let Some(size) = &a.pack_small_files {
+                pack_threshold(&a.id, size)?;
+                let packed = a.url.as_deref().is_some_and(|u| u.starts_with("packed://"));
+                if a.read_only || packed {
+                    return Err(FsError::Storage(format!(
+                        "archive backend {}: pack_small_files needs a writable backend",
+                        a.id
+                    )));
+                }
+            }
+            if let Some(size) = &a.chunk_large_files {
+                chunk_limit(&a.id, size)?;
+                let packed = a.url.as_deref().is_some_and(|u| u.starts_with("packed://"));
+                if a.read_only || packed {
+                    return Err(FsError::Storage(format!(
+                        "archive backend {}: chunk_large_files needs a writable backend",
+                        a.id
+                    )));
+                }
+            }
+            if let Some(url) = &a.url {
+                if !a.endpoint.is_empty() || !a.bucket.is_empty() {
+                    return Err(FsError::Storage(format!(
+                        "archive backend {}: set either url or endpoint/bucket, not both",
+                        a.id
+                    )));
+                }
+                if url.starts_with("https://") {
+                    return Err(FsError::Storage(format!(
+                        "archive backend {}: https is not supported; terminate TLS in a proxy and use http://",
+                        a.id
+                    )));
+                }
+                if url.starts_with("http://") {
+                    continue;
+                }
+                if let Some(dir) = url.strip_prefix("packed://") {
+                    if !dir.starts_with('/') {
+                        return Err(FsError::Storage(format!(
+                            "archive backend {}: packed:// needs an absolute directory, got {url}",
+                            a.id
+                        )));
+                    }
+                    continue;
+                }
+                if !(url.starts_with("gs://") || url.starts_with("az://")) {
+                    return Err(FsError::Storage(format!(
+                        "archive backend {}: url must be http://..., packed://..., gs://... or az://..., got {url}",
+                        a.id
+                    )));
+                }
+                if !cfg!(feature = "object-store") {
+                    return Err(FsError::Storage(format!(
+                        "archive backend {}: url needs rhss built with the `object-store` feature",
+                        a.id
+                    )));
+                }
+            }
+            if a.read_only {
+                return Err(FsError::Storage(format!(
+                    "archive backend {}: read_only is only supported for http:// and packed:// urls",
+                    a.id
+                )));
+            }
+            if a.url.is_some() {
+                continue;
+            }
             if a.endpoint.is_empty() || a.bucket.is_empty() {
                 return Err(FsError::Storage(format!(
                     "archive backend {} missing endpoint/bucket",
                     a.id
                 )));
             }
+            if a.access_key_env.is_empty() || a.secret_key_env.is_empty() {
+                return Err(FsError::Storage(format!(
+                    "archive backend {} missing access_key_env/secret_key_env",
+                    a.id
+                )));
+            }
         }
         Ok(())
     }
@@ -186,6 +1503,102 @@ mod tests {
         assert_eq!(cfg.tier.fast[0].id, "ssd");
     }
 
+    #[test]
+    fn unknown_keys_and_bad_values_name_the_key() {
+        let base = r#"
+            mount = "/mnt/rhss"
+            db = "/var/lib/rhss/index.db"
+            [[tier.fast]]
+            id = "ssd"
+            root = "/ssd"
+            [[tier.slow]]
+            id = "hdd"
+            root = "/hdd"
+            "#;
+        let err = |extra: &str| {
+            RhssConfig::from_toml(&format!("{base}{extra}"), std::iter::empty())
+                .unwrap_err()
+                .to_string()
+        };
+        let e = err("rooot = \"/x\"\n[fuse]\nalow_other = true\n");
+        assert!(e.contains("`fuse.alow_other`, `tier.slow[0].rooot`"), "{e}");
+        let e = err("[polcy]\nhigh_watermark = 0.9\n");
+        assert!(e.contains("unknown key `polcy`"), "{e}");
+        // Type errors point at the line.
+        let e = err("[fuse]\nattr_ttl_secs = \"soon\"\n");
+        assert!(e.contains("line 11") && e.contains("attr_ttl_secs"), "{e}");
+        // Unknown keys from the environment are caught too.
+        let vars = [("RHSS_FUSE__ALOW_OTHER".to_string(), "true".to_string())];
+        let e = RhssConfig::from_toml(base, vars).unwrap_err().to_string();
+        assert!(
+            e.contains("overrides): unknown key `fuse.alow_other`"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn env_overrides_layer_over_the_file() {
+        let file = r#"
+            mount = "/mnt/rhss"
+            db = "/var/lib/rhss/index.db"
+            [[tier.fast]]
+            id = "ssd"
+            root = "/ssd"
+            [[tier.slow]]
+            id = "hdd"
+            root = "/hdd"
+        "#;
+        let vars = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let cfg = RhssConfig::from_toml(
+            file,
+            vars(&[
+                ("RHSS_MOUNT", "/mnt/other"),
+                ("RHSS_LOG_LEVEL", "debug"),
+                ("RHSS_POLICY__HIGH_WATERMARK", "0.95"),
+                ("RHSS_TIER__FAST__0__ROOT", "/nvme"),
+                ("RHSS_TIER__SLOW__1__ID", "nas"),
+                ("RHSS_TIER__SLOW__1__ROOT", "/nas"),
+                ("RHSS_CONFIG", "/ignored.toml"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(cfg.mount, Path::new("/mnt/other"));
+        assert_eq!(cfg.log_level.as_deref(), Some("debug"));
+        assert_eq!(cfg.policy.high_watermark, Some(0.95));
+        assert_eq!(cfg.tier.fast[0].root, Path::new("/nvme"));
+        assert_eq!(cfg.tier.slow[1].id, "nas");
+
+        // The environment alone is a complete config.
+        let cfg = RhssConfig::from_toml(
+            "",
+            vars(&[
+                ("RHSS_MOUNT", "/mnt/rhss"),
+                ("RHSS_DB", "/db"),
+                ("RHSS_TIER__FAST__0__ID", "ssd"),
+                ("RHSS_TIER__FAST__0__ROOT", "/ssd"),
+                ("RHSS_TIER__SLOW__0__ID", "hdd"),
+                ("RHSS_TIER__SLOW__0__ROOT", "/hdd"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(cfg.tier.slow[0].root, Path::new("/hdd"));
+
+        for bad in [
+            ("RHSS_TIER__FAST__5__ROOT", "/x"),
+            ("RHSS_MOUNT__X", "/x"),
+            ("RHSS_POLICY__HIGH_WATERMARK", "high"),
+        ] {
+            let err = RhssConfig::from_toml(file, vars(&[bad])).unwrap_err();
+            assert!(err.to_string().contains("RHSS_"), "{err}");
+        }
+    }
+
     #[test]
     fn rejects_empty_tier() {
         let dir = TempDir::new().unwrap();
@@ -262,6 +1675,110 @@ mod tests {
         assert!(RhssConfig::load(&p).is_err());
     }
 
+    #[test]
+    fn archive_url_replaces_s3_fields() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let write = |archive: &str| {
+            std::fs::write(
+                &p,
+                format!(
+                    r#"
+                    mount = "/mnt/rhss"
+                    db = "/tmp/idx.db"
+                    [[tier.fast]]
+                    id = "ssd"
+                    root = "/tmp/ssd"
+                    [[tier.slow]]
+                    id = "hdd"
+                    root = "/tmp/hdd"
+                    [[tier.archive]]
+                    id = "cold"
+                    {archive}
+                    "#
+                ),
+            )
+            .unwrap();
+        };
+        write(r#"url = "gs://bucket/rhss""#);
+        assert_eq!(RhssConfig::load(&p).is_ok(), cfg!(feature = "object-store"));
+        write("url = \"gs://bucket\"\nbucket = \"b\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write(r#"url = "s3://bucket""#);
+        assert!(RhssConfig::load(&p).is_err());
+        write("url = \"http://archive:8080/rhss/\"\nread_only = true");
+        assert!(RhssConfig::load(&p).is_ok());
+        write(r#"url = "https://archive/rhss/""#);
+        assert!(RhssConfig::load(&p).is_err());
+        write("url = \"gs://bucket\"\nread_only = true");
+        assert!(RhssConfig::load(&p).is_err());
+        write(r#"url = "packed:///srv/archives""#);
+        assert!(RhssConfig::load(&p).is_ok());
+        write(r#"url = "packed://srv/archives""#);
+        assert!(RhssConfig::load(&p).is_err());
+        write("url = \"packed:///srv\"\npack_small_files = \"64K\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write("url = \"http://archive/rhss/\"\npack_small_files = \"64K\"");
+        assert!(RhssConfig::load(&p).is_ok());
+        write("url = \"packed:///srv\"\nchunk_large_files = \"1G\"");
+        assert!(RhssConfig::load(&p).is_err());
+        // S3 still needs its credentials named.
+        write("endpoint = \"https://e\"\nbucket = \"b\"");
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
+    #[test]
+    fn parses_fuse_section() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        std::fs::write(
+            &p,
+            r#"
+            mount = "/mnt/rhss"
+            db = "/tmp/idx.db"
+            [fuse]
+            allow_other = true
+            read_only = true
+            options = ["uid=1000"]
+            [[tier.fast]]
+            id = "ssd"
+            root = "/tmp/ssd"
+            [[tier.slow]]
+            id = "hdd"
+            root = "/tmp/hdd"
+            "#,
+        )
+        .unwrap();
+        let cfg = RhssConfig::load(&p).unwrap();
+        assert_eq!(cfg.fuse.allow_other, Some(true));
+        assert!(cfg.fuse.read_only);
+        assert_eq!(cfg.fuse.options, vec!["uid=1000".to_string()]);
+    }
+
+    #[test]
+    fn rejects_allow_other_with_allow_root() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        std::fs::write(
+            &p,
+            r#"
+            mount = "/mnt/rhss"
+            db = "/tmp/idx.db"
+            [fuse]
+            allow_other = true
+            allow_root = true
+            [[tier.fast]]
+            id = "ssd"
+            root = "/tmp/ssd"
+            [[tier.slow]]
+            id = "hdd"
+            root = "/tmp/hdd"
+            "#,
+        )
+        .unwrap();
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
     #[test]
     fn rejects_duplicate_ids() {
         let dir = TempDir::new().unwrap();
@@ -282,4 +1799,373 @@ mod tests {
         .unwrap();
         assert!(RhssConfig::load(&p).is_err());
     }
+
+    #[test]
+    fn memory_roots() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let write = |fast: &str| {
+            std::fs::write(
+                &p,
+                format!(
+                    r#"
+                    mount = "/mnt/rhss"
+                    db = "/tmp/idx.db"
+                    [[tier.fast]]
+                    id = "ram"
+                    {fast}
+                    [[tier.fast]]
+                    id = "ram2"
+                    root = "mem://1G"
+                    [[tier.slow]]
+                    id = "hdd"
+                    root = "/tmp/hdd"
+                    "#
+                ),
+            )
+            .unwrap();
+        };
+        write(r#"root = "mem://512M""#);
+        let mut cfg = RhssConfig::load(&p).unwrap();
+        assert_eq!(cfg.tier.fast[0].memory_size(), Some("512M"));
+        assert_eq!(cfg.tier.slow[0].memory_size(), None);
+        cfg.relocate_storage(|r| Path::new("/hidden").join(r.strip_prefix("/").unwrap()));
+        assert_eq!(cfg.tier.fast[0].root, Path::new("mem://512M"));
+        write(r#"root = "mem://lots""#);
+        assert!(RhssConfig::load(&p).is_err());
+        write("root = \"mem://1G\"\nreplicas = [\"/tmp/r\"]");
+        assert!(RhssConfig::load(&p).is_err());
+        write("root = \"/tmp/ssd\"\ndedup = \"cdc\"");
+        let cfg = RhssConfig::load(&p).unwrap();
+        assert_eq!(cfg.tier.fast[0].dedup, Some(DedupMode::Cdc));
+        write("root = \"mem://1G\"\ndedup = \"fixed\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write("root = \"/tmp/ssd\"\ndedup = \"whole\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write("root = \"/tmp/ssd\"\npack_small_files = \"64K\"");
+        assert!(RhssConfig::load(&p).is_ok());
+        write("root = \"/tmp/ssd\"\npack_small_files = \"1G\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write("root = \"/tmp/ssd\"\nchunk_large_files = \"1G\"");
+        assert!(RhssConfig::load(&p).is_ok());
+        write("root = \"/tmp/ssd\"\nchunk_large_files = \"0\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write("root = \"/tmp/ssd\"\nshard_dirs_above = 10000");
+        assert!(RhssConfig::load(&p).is_ok());
+        write("root = \"/tmp/ssd\"\nshard_dirs_above = 0");
+        assert!(RhssConfig::load(&p).is_err());
+        write("root = \"/tmp/ssd\"\ndirect_io_above = \"256M\"");
+        assert!(RhssConfig::load(&p).is_ok());
+        write("root = \"mem://1G\"\ndirect_io_above = \"256M\"");
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
+    #[test]
+    fn rejects_negative_ttl() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        std::fs::write(
+            &p,
+            r#"
+            mount = "/mnt/rhss"
+            db = "/tmp/idx.db"
+            [fuse]
+            attr_ttl_secs = -1.0
+            [[tier.fast]]
+            id = "ssd"
+            root = "/tmp/ssd"
+            [[tier.slow]]
+            id = "hdd"
+            root = "/tmp/hdd"
+            "#,
+        )
+        .unwrap();
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
+    #[test]
+    fn tenant_quotas_join_the_quota_rules() {
+        let base = r#"
+            mount = "/mnt/rhss"
+            db = "/var/lib/rhss/index.db"
+            [[tier.fast]]
+            id = "ssd"
+            root = "/ssd"
+            [[tier.slow]]
+            id = "hdd"
+            root = "/hdd"
+            [quota]
+            "uid 1001" = "1G"
+            [tenants.research]
+            gid = 2001
+            quota = "10G"
+            "#;
+        let load =
+            |extra: &str| RhssConfig::from_toml(&format!("{base}{extra}"), std::iter::empty());
+        let rules = load("").unwrap().quota_rules().unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            rules[1].target,
+            crate::quota::QuotaTarget::Dir("/research".into())
+        );
+        assert_eq!(rules[1].limit, 10 << 30);
+        let e = load("[fuse]\ndefault_permissions = false\n")
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("default_permissions"), "{e}");
+        assert!(load("[tenants.ops]\ndir = \"/research\"\n").is_err());
+    }
+
+    #[test]
+    fn throttle_limits_are_byte_rates() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let write = |throttle: &str| {
+            std::fs::write(
+                &p,
+                format!(
+                    r#"
+                    mount = "/mnt/rhss"
+                    db = "/tmp/idx.db"
+                    [throttle]
+                    {throttle}
+                    [[tier.fast]]
+                    id = "ssd"
+                    root = "/tmp/ssd"
+                    [[tier.slow]]
+                    id = "hdd"
+                    root = "/tmp/hdd"
+                    "#
+                ),
+            )
+            .unwrap();
+        };
+        write("migration = \"50M\"");
+        let cfg = RhssConfig::load(&p).unwrap();
+        assert_eq!(cfg.throttle.limits().unwrap(), (Some(50 << 20), None));
+        write("cold_reads = \"0\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write("migration = \"fast\"");
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
+    #[test]
+    fn io_limits_name_uids_and_tenants() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let load = |extra: &str| {
+            std::fs::write(
+                &p,
+                format!(
+                    r#"
+                    mount = "/mnt/rhss"
+                    db = "/tmp/idx.db"
+                    [[tier.fast]]
+                    id = "ssd"
+                    root = "/tmp/ssd"
+                    [[tier.slow]]
+                    id = "hdd"
+                    root = "/tmp/hdd"
+                    [tenants.physics]
+                    {extra}
+                    "#
+                ),
+            )
+            .unwrap();
+            RhssConfig::load(&p)
+        };
+        let cfg = load(
+            "[throttle.uids.1001]\niops = 200\nbandwidth = \"50M\"\n\
+             [throttle.uids.\"*\"]\nbandwidth = \"1G\"\n\
+             [throttle.tenants.physics]\niops = 1000\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.throttle.uids["1001"].iops, Some(200));
+        assert!(cfg.io_limits().unwrap().unwrap().by_path());
+        assert!(load("[throttle.uids.alice]\niops = 1\n").is_err());
+        assert!(load("[throttle.uids.1001]\niops = 0\n").is_err());
+        assert!(load("[throttle.uids.1001]\n").is_err());
+        let e = load("[throttle.tenants.chem]\niops = 1\n")
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("chem"), "{e}");
+        assert!(load("").unwrap().io_limits().unwrap().is_none());
+    }
+
+    #[test]
+    fn schedule_needs_a_sweep() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let write = |schedule: &str| {
+            std::fs::write(
+                &p,
+                format!(
+                    r#"
+                    mount = "/mnt/rhss"
+                    db = "/tmp/idx.db"
+                    [schedule]
+                    {schedule}
+                    [[tier.fast]]
+                    id = "ssd"
+                    root = "/tmp/ssd"
+                    [[tier.slow]]
+                    id = "hdd"
+                    root = "/tmp/hdd"
+                    "#
+                ),
+            )
+            .unwrap();
+        };
+        write("sweep = \"30 2 * * 1-5\"\nwindow = \"02:00-06:00\"\nmax_bytes = \"500G\"");
+        let cfg = RhssConfig::load(&p).unwrap();
+        let s = cfg.schedule.to_schedule().unwrap().unwrap();
+        assert_eq!(s.max_bytes, Some(500 << 30));
+        assert!(s.window.is_some());
+        write("window = \"02:00-06:00\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write("sweep = \"nightly\"");
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
+    #[test]
+    fn hooks_need_one_action() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let write = |hook: &str| {
+            std::fs::write(
+                &p,
+                format!(
+                    r#"
+                    mount = "/mnt/rhss"
+                    db = "/tmp/idx.db"
+                    [[hooks]]
+                    {hook}
+                    [[tier.fast]]
+                    id = "ssd"
+                    root = "/tmp/ssd"
+                    [[tier.slow]]
+                    id = "hdd"
+                    root = "/tmp/hdd"
+                    "#
+                ),
+            )
+            .unwrap();
+        };
+        write("events = [\"watermark\"]\ncommand = [\"/bin/true\"]");
+        assert_eq!(RhssConfig::load(&p).unwrap().hooks().unwrap().len(), 1);
+        write("url = \"http://inv.lan/rhss\"");
+        assert!(RhssConfig::load(&p).is_ok());
+        write("events = [\"watermark\"]");
+        assert!(RhssConfig::load(&p).is_err());
+        write("command = [\"/bin/true\"]\nurl = \"http://inv.lan/rhss\"");
+        assert!(RhssConfig::load(&p).is_err());
+        write("events = [\"scrubbed\"]\ncommand = [\"/bin/true\"]");
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
+    #[test]
+    fn policy_section_overrides_defaults_and_is_validated() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let body = |policy: &str| {
+            format!(
+                r#"
+                mount = "/mnt/rhss"
+                db = "/tmp/idx.db"
+                [policy]
+                {policy}
+                [[tier.fast]]
+                id = "ssd"
+                root = "/tmp/ssd"
+                [[tier.slow]]
+                id = "hdd"
+                root = "/tmp/hdd"
+                "#
+            )
+        };
+        std::fs::write(&p, body("high_watermark = 0.7\ntier_period_secs = -1")).unwrap();
+        let cfg = RhssConfig::load(&p).unwrap();
+        let pol = cfg.policy.to_policy();
+        assert_eq!(pol.high_watermark, 0.7);
+        assert_eq!(pol.low_watermark, 0.60);
+        assert!(pol.tier_period.is_none());
+
+        std::fs::write(&p, body("low_watermark = 0.9")).unwrap();
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
+    #[test]
+    fn audit_section_defaults_next_to_db() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let body = |audit: &str| {
+            format!(
+                r#"
+                mount = "/mnt/rhss"
+                db = "/var/lib/rhss/idx.db"
+                [audit]
+                enabled = true
+                {audit}
+                [[tier.fast]]
+                id = "ssd"
+                root = "/tmp/ssd"
+                [[tier.slow]]
+                id = "hdd"
+                root = "/tmp/hdd"
+                "#
+            )
+        };
+        std::fs::write(&p, body("")).unwrap();
+        let cfg = RhssConfig::load(&p).unwrap();
+        assert_eq!(
+            cfg.audit.sink(&cfg.db),
+            AuditSink::File(PathBuf::from("/var/lib/rhss/.rhss/audit.jsonl"))
+        );
+
+        std::fs::write(&p, body("sink = \"syslog\"")).unwrap();
+        let cfg = RhssConfig::load(&p).unwrap();
+        assert_eq!(cfg.audit.sink(&cfg.db), AuditSink::Syslog);
+
+        std::fs::write(&p, body("buffer = 0")).unwrap();
+        assert!(RhssConfig::load(&p).is_err());
+    }
+
+    #[test]
+    fn fuse_id_map_squashes_to_nobody_by_default() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path().join("rhss.toml");
+        let body = |fuse: &str| {
+            format!(
+                r#"
+                mount = "/mnt/rhss"
+                db = "/var/lib/rhss/idx.db"
+                [fuse]
+                {fuse}
+                [[tier.fast]]
+                id = "ssd"
+                root = "/tmp/ssd"
+                [[tier.slow]]
+                id = "hdd"
+                root = "/tmp/hdd"
+                "#
+            )
+        };
+        std::fs::write(&p, body("")).unwrap();
+        assert_eq!(
+            RhssConfig::load(&p).unwrap().fuse.id_map(),
+            IdMap::Passthrough
+        );
+
+        std::fs::write(&p, body("id_map = \"squash\"\nsquash_gid = 100")).unwrap();
+        assert_eq!(
+            RhssConfig::load(&p).unwrap().fuse.id_map(),
+            IdMap::Squash {
+                uid: 65534,
+                gid: 100
+            }
+        );
+
+        std::fs::write(&p, body("squash_uid = 1000")).unwrap();
+        assert!(RhssConfig::load(&p).is_err());
+    }
 }
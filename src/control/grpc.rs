@@ -0,0 +1,526 @@
+//! gRPC admin service (`--features grpc`, `[grpc] listen`): the
+//! `rhss.admin.v1.Admin` service from `proto/rhss/admin/v1/admin.proto`,
+//! for fleet tooling that would rather not speak JSON over a Unix socket.
+//!
+//! Each RPC is translated into a control [`Request`] and run through the
+//! same dispatcher as the socket, on a blocking thread. The message types
+//! below mirror the .proto by hand so the build doesn't need `protoc`;
+//! keep field tags in sync with it.
+
+// `tonic::Status` is big, but it's what handlers return; generated code
+// allows this too.
+#![allow(clippy::result_large_err)]
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tonic::body::BoxBody;
+use tonic::codegen::{http, BoxFuture, Service};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::transport::server::TcpIncoming;
+use tonic::Status;
+use tracing::{error, info};
+
+use crate::config::PolicyOptions;
+use crate::error::{FsError, Result};
+
+use super::protocol::{BackendHealth, Request, Response, ResponseData, Tier as WireTier};
+use super::server::{dispatch, OpContext};
+
+/// Message types of `rhss.admin.v1`.
+pub mod pb {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Tier {
+        Unspecified = 0,
+        Fast = 1,
+        Slow = 2,
+        Archive = 3,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StatusRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BackendUsage {
+        #[prost(enumeration = "Tier", tag = "1")]
+        pub tier: i32,
+        #[prost(string, tag = "2")]
+        pub id: String,
+        #[prost(uint64, tag = "3")]
+        pub total: u64,
+        #[prost(uint64, tag = "4")]
+        pub used: u64,
+        #[prost(uint64, tag = "5")]
+        pub free: u64,
+        #[prost(string, tag = "6")]
+        pub error: String,
+        #[prost(bool, tag = "7")]
+        pub healthy: bool,
+        #[prost(uint64, tag = "8")]
+        pub health_latency_ms: u64,
+        #[prost(string, tag = "9")]
+        pub health_error: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StatusReply {
+        #[prost(string, tag = "1")]
+        pub version: String,
+        #[prost(uint32, tag = "2")]
+        pub pid: u32,
+        #[prost(string, tag = "3")]
+        pub mount: String,
+        #[prost(bool, tag = "4")]
+        pub mounted: bool,
+        #[prost(uint64, tag = "5")]
+        pub uptime_secs: u64,
+        #[prost(bool, tag = "6")]
+        pub frozen: bool,
+        #[prost(bool, tag = "7")]
+        pub tierer_busy: bool,
+        #[prost(uint64, tag = "8")]
+        pub pending_migrations: u64,
+        #[prost(uint64, tag = "9")]
+        pub open_files: u64,
+        #[prost(message, repeated, tag = "10")]
+        pub backends: Vec<BackendUsage>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CacheStatsRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CacheStatsReply {
+        #[prost(bool, tag = "1")]
+        pub attached: bool,
+        #[prost(uint64, tag = "2")]
+        pub inodes: u64,
+        #[prost(uint64, tag = "3")]
+        pub inode_limit: u64,
+        #[prost(uint64, tag = "4")]
+        pub open_handles: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MigrateRequest {
+        #[prost(string, tag = "1")]
+        pub path: String,
+        #[prost(enumeration = "Tier", tag = "2")]
+        pub to: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MigrateReply {
+        #[prost(string, tag = "1")]
+        pub path: String,
+        #[prost(enumeration = "Tier", tag = "2")]
+        pub from: i32,
+        #[prost(enumeration = "Tier", tag = "3")]
+        pub to: i32,
+        #[prost(bool, tag = "4")]
+        pub moved: bool,
+        #[prost(string, tag = "5")]
+        pub reason: String,
+        #[prost(uint64, tag = "6")]
+        pub files_scanned: u64,
+        #[prost(uint64, tag = "7")]
+        pub files_moved: u64,
+        #[prost(uint64, tag = "8")]
+        pub bytes_moved: u64,
+        #[prost(uint64, tag = "9")]
+        pub failures: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PinRequest {
+        #[prost(string, tag = "1")]
+        pub path: String,
+        #[prost(enumeration = "Tier", tag = "2")]
+        pub tier: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PinReply {
+        #[prost(string, tag = "1")]
+        pub path: String,
+        #[prost(enumeration = "Tier", tag = "2")]
+        pub tier: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SetPolicyRequest {
+        #[prost(double, optional, tag = "1")]
+        pub low_watermark: Option<f64>,
+        #[prost(double, optional, tag = "2")]
+        pub high_watermark: Option<f64>,
+        #[prost(double, optional, tag = "3")]
+        pub panic_watermark: Option<f64>,
+        #[prost(int64, optional, tag = "4")]
+        pub tier_period_secs: Option<i64>,
+        #[prost(uint64, optional, tag = "5")]
+        pub min_age_to_evict_secs: Option<u64>,
+        #[prost(uint64, optional, tag = "6")]
+        pub min_age_to_archive_secs: Option<u64>,
+        #[prost(double, optional, tag = "7")]
+        pub slow_archive_watermark: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SetPolicyReply {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScrubRequest {
+        #[prost(bool, tag = "1")]
+        pub repair: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ReplicaInconsistency {
+        #[prost(string, tag = "1")]
+        pub path: String,
+        #[prost(string, repeated, tag = "2")]
+        pub expected: Vec<String>,
+        #[prost(string, repeated, tag = "3")]
+        pub missing: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScrubReply {
+        #[prost(string, repeated, tag = "1")]
+        pub orphans: Vec<String>,
+        #[prost(string, repeated, tag = "2")]
+        pub ghosts: Vec<String>,
+        #[prost(message, repeated, tag = "3")]
+        pub inconsistencies: Vec<ReplicaInconsistency>,
+        #[prost(uint64, tag = "4")]
+        pub repaired: u64,
+    }
+}
+
+/// Owns the tokio runtime serving the admin service. Drop shuts it down.
+pub struct GrpcServer {
+    addr: SocketAddr,
+    shutdown: Option<tokio::sync::oneshot::Sender<()>>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl GrpcServer {
+    pub fn start(listen: SocketAddr, ctx: OpContext) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("rhss-grpc")
+            .enable_all()
+            .build()
+            .map_err(FsError::Io)?;
+        // Bind up front so a taken port fails startup, not a background task.
+        let std_listener = std::net::TcpListener::bind(listen).map_err(FsError::Io)?;
+        std_listener.set_nonblocking(true).map_err(FsError::Io)?;
+        let addr = std_listener.local_addr().map_err(FsError::Io)?;
+        let incoming = {
+            let _guard = runtime.enter();
+            let listener = tokio::net::TcpListener::from_std(std_listener).map_err(FsError::Io)?;
+            TcpIncoming::from_listener(listener, true, None)
+                .map_err(|e| FsError::Storage(format!("grpc listener: {e}")))?
+        };
+        info!("grpc admin listening on {addr}");
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let service = AdminService { ctx: Arc::new(ctx) };
+        let handle = std::thread::Builder::new()
+            .name("rhss-grpc".into())
+            .spawn(move || {
+                let serve = tonic::transport::Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming_shutdown(incoming, async {
+                        let _ = rx.await;
+                    });
+                if let Err(e) = runtime.block_on(serve) {
+                    error!("grpc server failed: {e}");
+                }
+            })
+            .expect("spawn grpc thread");
+
+        Ok(Self {
+            addr,
+            shutdown: Some(tx),
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for GrpcServer {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+fn to_pb(t: WireTier) -> i32 {
+    match t {
+        WireTier::Fast => pb::Tier::Fast as i32,
+        WireTier::Slow => pb::Tier::Slow as i32,
+        WireTier::Archive => pb::Tier::Archive as i32,
+    }
+}
+
+fn from_pb(t: i32) -> Option<WireTier> {
+    match pb::Tier::try_from(t).ok()? {
+        pb::Tier::Unspecified => None,
+        pb::Tier::Fast => Some(WireTier::Fast),
+        pb::Tier::Slow => Some(WireTier::Slow),
+        pb::Tier::Archive => Some(WireTier::Archive),
+    }
+}
+
+/// Data of a successful control response, or its error as a `Status`.
+fn run(ctx: &OpContext, req: Request) -> std::result::Result<Option<ResponseData>, Status> {
+    match dispatch(req, ctx) {
+        Response { ok: true, data, .. } => Ok(data),
+        Response { error, .. } => Err(Status::unknown(error.unwrap_or_default())),
+    }
+}
+
+fn unexpected(data: Option<ResponseData>) -> Status {
+    Status::internal(format!("unexpected control response {data:?}"))
+}
+
+type Reply<T> = std::result::Result<T, Status>;
+
+fn status(ctx: &OpContext, _: pb::StatusRequest) -> Reply<pb::StatusReply> {
+    let Some(ResponseData::Status(st)) = run(ctx, Request::Status)? else {
+        return Err(Status::internal("status: no report"));
+    };
+    Ok(pb::StatusReply {
+        version: st.version,
+        pid: st.pid,
+        mount: st.mount.display().to_string(),
+        mounted: st.mounted,
+        uptime_secs: st.uptime_secs,
+        frozen: st.frozen,
+        tierer_busy: st.tierer_busy,
+        pending_migrations: st.pending_migrations as u64,
+        open_files: st.open_files as u64,
+        backends: st
+            .backends
+            .into_iter()
+            .map(|b| {
+                let health = b.health.unwrap_or(BackendHealth {
+                    ok: false,
+                    latency_ms: 0,
+                    age_secs: 0,
+                    error: Some("not probed yet".into()),
+                });
+                pb::BackendUsage {
+                    tier: to_pb(b.tier),
+                    id: b.id,
+                    total: b.total,
+                    used: b.used,
+                    free: b.free,
+                    error: b.error.unwrap_or_default(),
+                    healthy: health.ok,
+                    health_latency_ms: health.latency_ms,
+                    health_error: health.error.unwrap_or_default(),
+                }
+            })
+            .collect(),
+    })
+}
+
+fn cache_stats(ctx: &OpContext, _: pb::CacheStatsRequest) -> Reply<pb::CacheStatsReply> {
+    let Some(ResponseData::Status(st)) = run(ctx, Request::Status)? else {
+        return Err(Status::internal("status: no report"));
+    };
+    Ok(match st.cache {
+        Some(c) => pb::CacheStatsReply {
+            attached: true,
+            inodes: c.inodes as u64,
+            inode_limit: c.inode_limit as u64,
+            open_handles: c.open_handles as u64,
+        },
+        None => pb::CacheStatsReply::default(),
+    })
+}
+
+fn migrate(ctx: &OpContext, req: pb::MigrateRequest) -> Reply<pb::MigrateReply> {
+    let to = from_pb(req.to).ok_or_else(|| Status::invalid_argument("`to` tier is required"))?;
+    let req = Request::Migrate {
+        path: PathBuf::from(req.path),
+        to,
+        progress: false,
+        dry_run: false,
+    };
+    match run(ctx, req)? {
+        Some(ResponseData::Migrated {
+            path,
+            from,
+            to,
+            moved,
+            reason,
+        }) => Ok(pb::MigrateReply {
+            path: path.display().to_string(),
+            from: to_pb(from),
+            to: to_pb(to),
+            moved,
+            reason: reason.unwrap_or_default(),
+            ..Default::default()
+        }),
+        Some(ResponseData::MigratedTree {
+            path, to, progress, ..
+        }) => Ok(pb::MigrateReply {
+            path: path.display().to_string(),
+            to: to_pb(to),
+            moved: progress.files_moved > 0,
+            files_scanned: progress.files_scanned,
+            files_moved: progress.files_moved,
+            bytes_moved: progress.bytes_moved,
+            failures: progress.failures.len() as u64,
+            ..Default::default()
+        }),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn pin(ctx: &OpContext, req: pb::PinRequest) -> Reply<pb::PinReply> {
+    let path = PathBuf::from(req.path);
+    let req = match from_pb(req.tier) {
+        Some(tier) => Request::Pin {
+            path,
+            tier,
+            until: None,
+        },
+        None => Request::Unpin { path },
+    };
+    match run(ctx, req)? {
+        Some(ResponseData::Pinned { path, tier, .. }) => Ok(pb::PinReply {
+            path: path.display().to_string(),
+            tier: tier.map(to_pb).unwrap_or(pb::Tier::Unspecified as i32),
+        }),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn set_policy(ctx: &OpContext, req: pb::SetPolicyRequest) -> Reply<pb::SetPolicyReply> {
+    let policy = PolicyOptions {
+        low_watermark: req.low_watermark,
+        high_watermark: req.high_watermark,
+        panic_watermark: req.panic_watermark,
+        tier_period_secs: req.tier_period_secs,
+        min_age_to_evict_secs: req.min_age_to_evict_secs,
+        min_age_to_archive_secs: req.min_age_to_archive_secs,
+        slow_archive_watermark: req.slow_archive_watermark,
+    };
+    run(ctx, Request::SetPolicy { policy })?;
+    Ok(pb::SetPolicyReply {})
+}
+
+fn scrub(ctx: &OpContext, req: pb::ScrubRequest) -> Reply<pb::ScrubReply> {
+    let show = |paths: Vec<PathBuf>| paths.iter().map(|p| p.display().to_string()).collect();
+    match run(ctx, Request::Fsck { repair: req.repair })? {
+        Some(ResponseData::Fsck {
+            orphans,
+            ghosts,
+            inconsistencies,
+            partial,
+            repaired,
+        }) => Ok(pb::ScrubReply {
+            orphans: show(orphans),
+            ghosts: show(ghosts),
+            inconsistencies: inconsistencies
+                .into_iter()
+                .map(|i| pb::ReplicaInconsistency {
+                    path: i.path.display().to_string(),
+                    expected: i.expected,
+                    missing: i.missing,
+                })
+                .collect(),
+            partial: show(partial),
+            repaired: repaired as u64,
+        }),
+        other => Err(unexpected(other)),
+    }
+}
+
+/// One RPC: decode, run `f` off the async threads, encode.
+struct Unary<Req, Rep> {
+    ctx: Arc<OpContext>,
+    f: fn(&OpContext, Req) -> Reply<Rep>,
+}
+
+impl<Req: Send + 'static, Rep: Send + 'static> UnaryService<Req> for Unary<Req, Rep> {
+    type Response = Rep;
+    type Future = BoxFuture<tonic::Response<Rep>, Status>;
+
+    fn call(&mut self, req: tonic::Request<Req>) -> Self::Future {
+        let (ctx, f) = (Arc::clone(&self.ctx), self.f);
+        Box::pin(async move {
+            let msg = req.into_inner();
+            tokio::task::spawn_blocking(move || f(&ctx, msg))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map(tonic::Response::new)
+        })
+    }
+}
+
+fn unary<Req, Rep>(
+    ctx: &Arc<OpContext>,
+    f: fn(&OpContext, Req) -> Reply<Rep>,
+    req: http::Request<BoxBody>,
+) -> BoxFuture<http::Response<BoxBody>, Infallible>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Rep: prost::Message + Send + 'static,
+{
+    let svc = Unary {
+        ctx: Arc::clone(ctx),
+        f,
+    };
+    Box::pin(async move {
+        let mut grpc = Grpc::new(tonic::codec::ProstCodec::<Rep, Req>::default());
+        Ok(grpc.unary(svc, req).await)
+    })
+}
+
+#[derive(Clone)]
+struct AdminService {
+    ctx: Arc<OpContext>,
+}
+
+impl NamedService for AdminService {
+    const NAME: &'static str = "rhss.admin.v1.Admin";
+}
+
+impl Service<http::Request<BoxBody>> for AdminService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+        let ctx = &self.ctx;
+        match req.uri().path() {
+            "/rhss.admin.v1.Admin/Status" => unary(ctx, status, req),
+            "/rhss.admin.v1.Admin/CacheStats" => unary(ctx, cache_stats, req),
+            "/rhss.admin.v1.Admin/Migrate" => unary(ctx, migrate, req),
+            "/rhss.admin.v1.Admin/Pin" => unary(ctx, pin, req),
+            "/rhss.admin.v1.Admin/SetPolicy" => unary(ctx, set_policy, req),
+            "/rhss.admin.v1.Admin/Scrub" => unary(ctx, scrub, req),
+            _ => Box::pin(async { Ok(Status::unimplemented("no such method").into_http()) }),
+        }
+    }
+}
@@ -5,8 +5,13 @@
 //! JSON per [`protocol`]. Server is single-threaded but handles each accepted
 //! connection in a worker thread — control ops are infrequent and short.
 
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod protocol;
 pub mod server;
 
-pub use protocol::{Request, Response, ResponseData, Tier};
+pub use protocol::{
+    BackendHealth, BackendUsage, CacheStats, DuEntry, Request, Response, ResponseData,
+    StatusReport, Tier,
+};
 pub use server::{socket_path_for, ControlServer};
@@ -2,13 +2,21 @@
 //!
 //! Newline-delimited JSON. One request per line, one response per line.
 //! Server reads a full line, parses, dispatches, writes a JSON response with
-//! a trailing newline, then loops. Simple, debuggable with `nc -U`.
+//! a trailing newline, then loops. Simple, debuggable with `nc -U`. The one
+//! exceptions are `migrate` and `preheat` with `progress`, which precede
+//! their response with `migrate-progress` / `preheat-progress` lines.
 
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
-use crate::index::TierId as IndexTierId;
+use crate::config::PolicyOptions;
+use crate::index::{DirUsage, TierId as IndexTierId};
+use crate::preheat::PreheatReport;
+use crate::profile::ProfileReport;
+use crate::quota::QuotaUsage;
+use crate::tierer::MigrateProgress;
+use crate::trash::TrashEntry;
 
 /// Tier name on the wire. Maps to/from `crate::index::TierId`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -45,17 +53,64 @@ impl From<IndexTierId> for Tier {
 #[serde(tag = "op", rename_all = "kebab-case")]
 pub enum Request {
     Ping,
-    Pin { path: PathBuf, tier: Tier },
+    /// A directory pins everything under it. `until` (Unix seconds) lets
+    /// the pin lapse then, back to the policy.
+    Pin {
+        path: PathBuf,
+        tier: Tier,
+        #[serde(default)]
+        until: Option<u64>,
+    },
     Unpin { path: PathBuf },
     Lock { path: PathBuf },
     Unlock { path: PathBuf },
     Oneshot { wait: bool },
-    Migrate { path: PathBuf, to: Tier },
+    /// A directory migrates everything under it. With `progress`, the
+    /// daemon writes a `migrate-progress` line after each file before the
+    /// final response. `dry_run` only plans, moving nothing.
+    Migrate {
+        path: PathBuf,
+        to: Tier,
+        #[serde(default)]
+        progress: bool,
+        #[serde(default)]
+        dry_run: bool,
+    },
+    /// Copy a cold subtree onto the fast tier, the cold copies staying
+    /// authoritative; `release` drops the copies again. `progress` works
+    /// as for `migrate`, with `preheat-progress` lines.
+    Preheat {
+        path: PathBuf,
+        #[serde(default)]
+        release: bool,
+        #[serde(default)]
+        progress: bool,
+    },
     Freeze,
     Unfreeze,
     Fsck { repair: bool },
     Rescan,
     DedupGc,
+    Status,
+    Shutdown,
+    TrashList,
+    TrashRestore { id: String },
+    TrashPurge { id: Option<String>, all: bool },
+    QuotaReport,
+    Du { path: PathBuf, depth: usize },
+    SetPolicy { policy: PolicyOptions },
+    /// Replace the daemon's log filter (`RUST_LOG` syntax), or with no
+    /// `filter` just report it. Lasts until the next reload or restart.
+    LogLevel {
+        #[serde(default)]
+        filter: Option<String>,
+    },
+    /// Set the profiler's sampling rate (0 stops it, keeping what was
+    /// collected) and report the profile so far.
+    Profile {
+        #[serde(default)]
+        rate: Option<f64>,
+    },
 }
 
 /// Responses share an envelope: `ok` + optional `data` + optional `error`.
@@ -105,13 +160,101 @@ pub struct ReplicaInconsistency {
     pub missing: Vec<String>,
 }
 
+/// What `rhss status` shows for a running daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub version: String,
+    /// Absent from daemons that predate it.
+    #[serde(default)]
+    pub build: Option<crate::build_info::BuildInfo>,
+    pub pid: u32,
+    pub mount: PathBuf,
+    /// Whether the kernel still lists the mount point.
+    pub mounted: bool,
+    pub uptime_secs: u64,
+    pub frozen: bool,
+    /// A tier cycle (or oneshot) is running right now.
+    pub tierer_busy: bool,
+    /// Journalled migrations not yet finished.
+    pub pending_migrations: usize,
+    /// Distinct files with at least one open handle.
+    pub open_files: usize,
+    /// FUSE-side caches; absent when no adapter is attached.
+    #[serde(default)]
+    pub cache: Option<CacheStats>,
+    pub backends: Vec<BackendUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub inodes: usize,
+    pub inode_limit: usize,
+    pub open_handles: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendUsage {
+    pub tier: Tier,
+    pub id: String,
+    pub total: u64,
+    pub used: u64,
+    pub free: u64,
+    /// `statvfs` failure, if the backend couldn't be queried.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+    /// Latest health probe; absent before the first one.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub health: Option<BackendHealth>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendHealth {
+    pub ok: bool,
+    pub latency_ms: u64,
+    /// Seconds since the probe ran.
+    pub age_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+}
+
+/// Recursive totals for one directory on one tier.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DuEntry {
+    pub path: PathBuf,
+    pub tier: Tier,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl From<DirUsage> for DuEntry {
+    fn from(u: DirUsage) -> Self {
+        Self {
+            path: u.dir,
+            tier: u.tier.into(),
+            files: u.files,
+            bytes: u.bytes,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "kind", rename_all = "kebab-case")]
 pub enum ResponseData {
     /// `ping` response.
     Pong { version: String, frozen: bool },
     /// `pin` / `unpin` response: confirms what's now in the row.
-    Pinned { path: PathBuf, tier: Option<Tier> },
+    Pinned {
+        path: PathBuf,
+        tier: Option<Tier>,
+        #[serde(default)]
+        until: Option<u64>,
+        /// Rows changed: 1 for a file, all of them below a directory.
+        #[serde(default)]
+        files: u64,
+        /// Of those, the ones not on `tier` yet; `migrate` moves them.
+        #[serde(default)]
+        off_tier: u64,
+    },
     /// `lock` / `unlock` response: confirms new mutability.
     Mutability { path: PathBuf, immutable: bool },
     /// `oneshot` response: whether the wait actually completed in time.
@@ -124,15 +267,36 @@ pub enum ResponseData {
         moved: bool,
         reason: Option<String>,
     },
+    /// `migrate` of a directory: totals once every file was tried, or
+    /// just the plan for a dry run.
+    MigratedTree {
+        path: PathBuf,
+        to: Tier,
+        progress: MigrateProgress,
+        #[serde(default)]
+        dry_run: bool,
+    },
+    /// Interim `migrate` line, sent after each file when asked for.
+    MigrateProgress { progress: MigrateProgress },
+    /// `preheat` response, for copying and releasing alike.
+    Preheated {
+        path: PathBuf,
+        report: PreheatReport,
+    },
+    /// Interim `preheat` line, sent after each file when asked for.
+    PreheatProgress { report: PreheatReport },
     /// `freeze` / `unfreeze`: confirms new state.
     FreezeState { frozen: bool },
     /// `fsck` response: orphans (on disk, not in index), ghosts (in index,
-    /// not on disk), and replica inconsistencies (D23: file claims N
-    /// replicas, but ≤ N actually exist on the relevant backends).
+    /// not on disk), replica inconsistencies (D23: file claims N
+    /// replicas, but ≤ N actually exist on the relevant backends), and
+    /// partial files (created, then the daemon died before a flush).
     Fsck {
         orphans: Vec<PathBuf>,
         ghosts: Vec<PathBuf>,
         inconsistencies: Vec<ReplicaInconsistency>,
+        #[serde(default)]
+        partial: Vec<PathBuf>,
         repaired: usize,
     },
     /// `rescan` response.
@@ -140,6 +304,8 @@ pub enum ResponseData {
         added: u64,
         already_indexed: u64,
         conflicts: Vec<PathBuf>,
+        #[serde(default)]
+        duplicates_removed: Vec<PathBuf>,
     },
     /// `dedup-gc` response.
     DedupGc {
@@ -147,6 +313,22 @@ pub enum ResponseData {
         blobs_removed: u64,
         bytes_freed: u64,
     },
+    /// `status` response.
+    Status(StatusReport),
+    /// `trash list` response, oldest first.
+    Trash { entries: Vec<TrashEntry> },
+    /// `trash restore` response.
+    TrashRestored { id: String, path: PathBuf },
+    /// `trash purge` response.
+    TrashPurged { entries: u64, bytes: u64 },
+    /// `quota report` response, in config order.
+    Quota { entries: Vec<QuotaUsage> },
+    /// `du` response, ordered by path then tier.
+    Du { entries: Vec<DuEntry> },
+    /// `log-level` response: the filter now in effect.
+    LogLevel { filter: String },
+    /// `profile` response.
+    Profile(ProfileReport),
 }
 
 #[cfg(test)]
@@ -158,11 +340,12 @@ mod tests {
         let req = Request::Pin {
             path: PathBuf::from("/Movies/foo.mkv"),
             tier: Tier::Fast,
+            until: None,
         };
         let s = serde_json::to_string(&req).unwrap();
         let back: Request = serde_json::from_str(&s).unwrap();
         match back {
-            Request::Pin { path, tier } => {
+            Request::Pin { path, tier, .. } => {
                 assert_eq!(path, PathBuf::from("/Movies/foo.mkv"));
                 assert_eq!(tier, Tier::Fast);
             }
@@ -201,4 +384,22 @@ mod tests {
         let s = serde_json::to_string(&Tier::Fast).unwrap();
         assert_eq!(s, r#""fast""#);
     }
+
+    #[test]
+    fn log_level_without_filter_is_a_query() {
+        let req: Request = serde_json::from_str(r#"{"op":"log-level"}"#).unwrap();
+        assert!(matches!(req, Request::LogLevel { filter: None }));
+        let req: Request =
+            serde_json::from_str(r#"{"op":"log-level","filter":"rhss=debug"}"#).unwrap();
+        assert!(matches!(req, Request::LogLevel { filter: Some(f) } if f == "rhss=debug"));
+    }
+
+    #[test]
+    fn migrate_progress_defaults_off() {
+        let req: Request = serde_json::from_str(r#"{"op":"migrate","path":"/a","to":"slow"}"#).unwrap();
+        match req {
+            Request::Migrate { progress, .. } => assert!(!progress),
+            _ => panic!("wrong variant"),
+        }
+    }
 }
@@ -5,18 +5,29 @@ use std::os::unix::fs::PermissionsExt;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use tracing::{debug, error, info, warn};
 
 use crate::backend::Backend;
 use crate::error::{FsError, Result};
-use crate::index::{Mutability, PathIndex, TierId};
+use crate::fuse::FuseAdapter;
+use crate::config::PolicyOptions;
+use crate::index::{FileRow, FileState, Mutability, PathIndex, TierId};
+use crate::policy::{PopularityPolicy, ReloadablePolicy, TieringPolicy};
+use crate::preheat::{self, PreheatReport};
+use crate::quota::Quotas;
 use crate::scan;
 use crate::tier::TierRouter;
-use crate::tierer::{migrate, OpenFileTracker, TiererHandle};
+use crate::tierer::{
+    migrate, migrate_directory, plan_migration, MigrateProgress, OpenFileTracker, TiererHandle,
+};
+use crate::trash::{PurgeScope, Trash};
 
-use super::protocol::{ReplicaInconsistency, Request, Response, ResponseData};
+use super::protocol::{
+    BackendHealth, BackendUsage, CacheStats, ReplicaInconsistency, Request, Response, ResponseData,
+    StatusReport,
+};
 
 /// Compute the canonical socket path next to the index db.
 ///
@@ -40,6 +51,16 @@ pub struct OpContext {
     pub open_tracker: Arc<OpenFileTracker>,
     pub tierer: TiererHandle,
     pub config_db_path: PathBuf,
+    pub mount: PathBuf,
+    pub started: SystemTime,
+    /// Source of cache stats for `status`; `None` when no mount is attached.
+    pub fuse: Option<FuseAdapter>,
+    /// `None` when `[trash]` is disabled.
+    pub trash: Option<Arc<Trash>>,
+    /// `None` when no `[quota]` is configured.
+    pub quotas: Option<Arc<Quotas>>,
+    /// Target of `set-policy`; `None` refuses it.
+    pub policy: Option<Arc<ReloadablePolicy>>,
 }
 
 impl ControlServer {
@@ -122,19 +143,43 @@ fn accept_loop(
 }
 
 fn handle_connection(stream: UnixStream, ctx: OpContext) -> Result<()> {
-    // One request, one response, then close. Keeps the daemon trivially
-    // observable (`nc -U sock` works).
+    // One request, one response (after any progress lines), then close.
+    // Keeps the daemon trivially observable (`nc -U sock` works).
     let mut reader = BufReader::new(stream.try_clone().map_err(FsError::Io)?);
     let mut line = String::new();
     if reader.read_line(&mut line).map_err(FsError::Io)? == 0 {
         return Ok(());
     }
+    let mut out = stream;
     let response = match serde_json::from_str::<Request>(line.trim()) {
+        Ok(Request::Migrate {
+            path,
+            to,
+            progress: true,
+            dry_run,
+        }) => op_migrate(&ctx, path, to.into(), dry_run, &mut |p| {
+            let line = Response::ok_data(ResponseData::MigrateProgress {
+                progress: p.clone(),
+            });
+            // A client that went away still gets its migration finished.
+            let _ = write_response(&mut out, &line);
+        }),
+        Ok(Request::Preheat {
+            path,
+            release,
+            progress: true,
+        }) => op_preheat(&ctx, path, release, &mut |r| {
+            let line = Response::ok_data(ResponseData::PreheatProgress { report: r.clone() });
+            let _ = write_response(&mut out, &line);
+        }),
         Ok(req) => dispatch(req, &ctx),
         Err(e) => Response::err(format!("bad request: {e}")),
     };
-    let mut out = stream;
-    let bytes = serde_json::to_vec(&response).map_err(FsError::Json)?;
+    write_response(&mut out, &response)
+}
+
+fn write_response(out: &mut UnixStream, response: &Response) -> Result<()> {
+    let bytes = serde_json::to_vec(response).map_err(FsError::Json)?;
     out.write_all(&bytes).map_err(FsError::Io)?;
     out.write_all(b"\n").map_err(FsError::Io)?;
     out.flush().map_err(FsError::Io)?;
@@ -143,22 +188,74 @@ fn handle_connection(stream: UnixStream, ctx: OpContext) -> Result<()> {
 
 // ===== dispatcher =====
 
-fn dispatch(req: Request, ctx: &OpContext) -> Response {
+pub(super) fn dispatch(req: Request, ctx: &OpContext) -> Response {
     debug!("control dispatch: {:?}", req);
     match req {
         Request::Ping => op_ping(ctx),
-        Request::Pin { path, tier } => op_pin(ctx, path, Some(tier.into())),
-        Request::Unpin { path } => op_pin(ctx, path, None),
+        Request::Pin { path, tier, until } => op_pin(ctx, path, Some(tier.into()), until),
+        Request::Unpin { path } => op_pin(ctx, path, None, None),
         Request::Lock { path } => op_set_mutability(ctx, path, Mutability::Immutable),
         Request::Unlock { path } => op_set_mutability(ctx, path, Mutability::Mutable),
         Request::Oneshot { wait } => op_oneshot(ctx, wait),
-        Request::Migrate { path, to } => op_migrate(ctx, path, to.into()),
+        Request::Migrate {
+            path, to, dry_run, ..
+        } => op_migrate(ctx, path, to.into(), dry_run, &mut |_| {}),
+        Request::Preheat { path, release, .. } => op_preheat(ctx, path, release, &mut |_| {}),
         Request::Freeze => op_freeze(ctx, true),
         Request::Unfreeze => op_freeze(ctx, false),
         Request::Fsck { repair } => op_fsck(ctx, repair),
         Request::Rescan => op_rescan(ctx),
         Request::DedupGc => op_dedup_gc(ctx),
+        Request::Status => op_status(ctx),
+        Request::Shutdown => op_shutdown(),
+        Request::TrashList => op_trash_list(ctx),
+        Request::TrashRestore { id } => op_trash_restore(ctx, &id),
+        Request::TrashPurge { id, all } => op_trash_purge(ctx, id.as_deref(), all),
+        Request::QuotaReport => Response::ok_data(ResponseData::Quota {
+            entries: ctx.quotas.as_ref().map(|q| q.report()).unwrap_or_default(),
+        }),
+        Request::Du { path, depth } => op_du(ctx, &path, depth),
+        Request::SetPolicy { policy } => op_set_policy(ctx, &policy),
+        Request::LogLevel { filter } => op_log_level(filter.as_deref()),
+        Request::Profile { rate } => op_profile(rate),
+    }
+}
+
+/// Swap in new tiering thresholds. They last until the next SIGHUP, which
+/// re-reads `[policy]` from the config file.
+fn op_set_policy(ctx: &OpContext, opts: &PolicyOptions) -> Response {
+    let Some(policy) = &ctx.policy else {
+        return Response::err("this daemon can't change its policy at runtime");
+    };
+    if let Err(e) = opts.validate() {
+        return Response::err(e.to_string());
+    }
+    policy.replace(Arc::new(opts.to_policy()));
+    info!("policy replaced via control request");
+    Response::ok_empty()
+}
+
+fn op_log_level(filter: Option<&str>) -> Response {
+    if let Some(f) = filter {
+        if let Err(e) = crate::logging::set_filter(f) {
+            return Response::err(e.to_string());
+        }
+        info!("log filter set to {f:?} via control request");
+    }
+    Response::ok_data(ResponseData::LogLevel {
+        filter: crate::logging::current_filter(),
+    })
+}
+
+fn op_profile(rate: Option<f64>) -> Response {
+    if let Some(r) = rate {
+        if !(0.0..=1.0).contains(&r) {
+            return Response::err(format!("profile rate {r} is not between 0 and 1"));
+        }
+        crate::profile::configure(r);
+        info!("profiler sampling rate set to {r} via control request");
     }
+    Response::ok_data(ResponseData::Profile(crate::profile::report()))
 }
 
 fn op_dedup_gc(ctx: &OpContext) -> Response {
@@ -231,20 +328,156 @@ fn op_ping(ctx: &OpContext) -> Response {
     })
 }
 
-fn op_pin(ctx: &OpContext, path: PathBuf, tier: Option<TierId>) -> Response {
+fn op_status(ctx: &OpContext) -> Response {
+    let backends = ctx
+        .router
+        .all_backends()
+        .map(|(tier, b)| {
+            let (s, error) = match b.statvfs() {
+                Ok(s) => (Some(s), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            BackendUsage {
+                tier: tier.into(),
+                id: b.id().to_string(),
+                total: s.map(|x| x.total_bytes).unwrap_or(0),
+                used: s.map(|x| x.used_bytes).unwrap_or(0),
+                free: s.map(|x| x.free_bytes).unwrap_or(0),
+                error,
+                health: crate::health::latest(b.id()).map(|c| BackendHealth {
+                    ok: c.ok,
+                    latency_ms: c.latency.as_millis() as u64,
+                    age_secs: c.at.elapsed().map(|d| d.as_secs()).unwrap_or(0),
+                    error: c.error,
+                }),
+            }
+        })
+        .collect();
+    let cache = ctx.fuse.as_ref().map(|f| {
+        let s = f.stats();
+        CacheStats {
+            inodes: s.inodes,
+            inode_limit: s.inode_limit,
+            open_handles: s.open_handles,
+        }
+    });
+    Response::ok_data(ResponseData::Status(StatusReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        build: Some(crate::build_info::BuildInfo::current()),
+        pid: std::process::id(),
+        mount: ctx.mount.clone(),
+        mounted: crate::fuse::is_mounted(&ctx.mount).unwrap_or(false),
+        uptime_secs: ctx.started.elapsed().map(|d| d.as_secs()).unwrap_or(0),
+        frozen: ctx.tierer.is_paused(),
+        tierer_busy: ctx.tierer.is_busy(),
+        pending_migrations: crate::tierer::journal::current()
+            .map(|j| j.in_flight())
+            .unwrap_or(0),
+        open_files: ctx.open_tracker.open_count(),
+        cache,
+        backends,
+    }))
+}
+
+/// Reply first; the main loop notices the flag and runs the usual
+/// SIGTERM shutdown path.
+fn op_shutdown() -> Response {
+    info!("shutdown requested over control socket");
+    crate::daemon::request_stop();
+    Response::ok_empty()
+}
+
+fn trash_disabled() -> Response {
+    Response::err("trash is disabled (set [trash] enabled = true)")
+}
+
+fn op_du(ctx: &OpContext, path: &Path, depth: usize) -> Response {
+    match ctx.index.dir_usage(&normalize(path), depth) {
+        Ok(rows) => Response::ok_data(ResponseData::Du {
+            entries: rows.into_iter().map(Into::into).collect(),
+        }),
+        Err(e) => Response::err(format!("du: {e}")),
+    }
+}
+
+fn op_trash_list(ctx: &OpContext) -> Response {
+    let Some(trash) = &ctx.trash else {
+        return trash_disabled();
+    };
+    match trash.list(&ctx.router) {
+        Ok(entries) => Response::ok_data(ResponseData::Trash { entries }),
+        Err(e) => Response::err(format!("trash list: {e}")),
+    }
+}
+
+fn op_trash_restore(ctx: &OpContext, id: &str) -> Response {
+    let Some(trash) = &ctx.trash else {
+        return trash_disabled();
+    };
+    match trash.restore(&ctx.router, &ctx.index, id) {
+        Ok(e) => Response::ok_data(ResponseData::TrashRestored {
+            id: e.id,
+            path: e.logical,
+        }),
+        Err(e) => Response::err(format!("trash restore: {e}")),
+    }
+}
+
+fn op_trash_purge(ctx: &OpContext, id: Option<&str>, all: bool) -> Response {
+    let Some(trash) = &ctx.trash else {
+        return trash_disabled();
+    };
+    let scope = match (id, all) {
+        (Some(id), _) => PurgeScope::One(id),
+        (None, true) => PurgeScope::All,
+        (None, false) => PurgeScope::Expired,
+    };
+    match trash.purge(&ctx.router, scope) {
+        Ok(st) => Response::ok_data(ResponseData::TrashPurged {
+            entries: st.entries,
+            bytes: st.bytes,
+        }),
+        Err(e) => Response::err(format!("trash purge: {e}")),
+    }
+}
+
+/// `pin` / `unpin` of a file, or of every file below a directory. A pin
+/// with `until` is a reservation: the tierer clears it once that passes.
+fn op_pin(ctx: &OpContext, path: PathBuf, tier: Option<TierId>, until: Option<u64>) -> Response {
     let logical = normalize(&path);
-    let mut row = match ctx.index.get(&logical) {
-        Ok(Some(r)) => r,
-        Ok(None) => return Response::err(format!("not indexed: {}", logical.display())),
+    let deadline = until.map(|s| UNIX_EPOCH + Duration::from_secs(s));
+    if deadline.is_some_and(|t| t <= SystemTime::now()) {
+        return Response::err("the --until deadline has already passed");
+    }
+    let rows = match ctx.index.get(&logical) {
+        Ok(Some(r)) => vec![r],
+        Ok(None) => match ctx.index.list_under(&logical) {
+            Ok(rows) if rows.is_empty() => {
+                return Response::err(format!("not indexed: {}", logical.display()))
+            }
+            Ok(rows) => rows,
+            Err(e) => return Response::err(format!("index error: {e}")),
+        },
         Err(e) => return Response::err(format!("index error: {e}")),
     };
-    row.pinned_tier = tier;
-    if let Err(e) = ctx.index.insert(row) {
-        return Response::err(format!("update failed: {e}"));
+    let files = rows.len() as u64;
+    let mut off_tier = 0;
+    for mut row in rows {
+        if tier.is_some_and(|t| t != row.location.tier) {
+            off_tier += 1;
+        }
+        row.pinned_tier = tier;
+        row.pinned_until = tier.and(deadline);
+        if let Err(e) = ctx.index.insert(row) {
+            return Response::err(format!("update failed: {e}"));
+        }
     }
     Response::ok_data(ResponseData::Pinned {
         path: logical,
         tier: tier.map(Into::into),
+        until: tier.and(until),
+        files,
+        off_tier,
     })
 }
 
@@ -361,11 +594,20 @@ fn op_oneshot(ctx: &OpContext, wait: bool) -> Response {
     Response::ok_data(ResponseData::OneshotCompleted { waited })
 }
 
-fn op_migrate(ctx: &OpContext, path: PathBuf, to: TierId) -> Response {
+fn op_migrate(
+    ctx: &OpContext,
+    path: PathBuf,
+    to: TierId,
+    dry_run: bool,
+    on_progress: &mut dyn FnMut(&MigrateProgress),
+) -> Response {
     let logical = normalize(&path);
+    if dry_run {
+        return op_plan_migration(ctx, logical, to);
+    }
     let row = match ctx.index.get(&logical) {
         Ok(Some(r)) => r,
-        Ok(None) => return Response::err(format!("not indexed: {}", logical.display())),
+        Ok(None) => return op_migrate_tree(ctx, logical, to, on_progress),
         Err(e) => return Response::err(format!("index error: {e}")),
     };
     let from = row.location.tier;
@@ -397,6 +639,75 @@ fn op_migrate(ctx: &OpContext, path: PathBuf, to: TierId) -> Response {
     }
 }
 
+/// `migrate` of a path with no row of its own: everything indexed below it.
+fn op_migrate_tree(
+    ctx: &OpContext,
+    dir: PathBuf,
+    to: TierId,
+    on_progress: &mut dyn FnMut(&MigrateProgress),
+) -> Response {
+    match migrate_directory(
+        &ctx.router,
+        &ctx.index,
+        &ctx.open_tracker,
+        &dir,
+        to,
+        on_progress,
+    ) {
+        Ok(progress) if progress.files_scanned == 0 => {
+            Response::err(format!("not indexed: {}", dir.display()))
+        }
+        Ok(progress) => Response::ok_data(ResponseData::MigratedTree {
+            path: dir,
+            to: to.into(),
+            progress,
+            dry_run: false,
+        }),
+        Err(e) => Response::err(format!("migrate failed: {e}")),
+    }
+}
+
+/// `migrate --dry-run`: what would move, decided from metadata alone.
+fn op_plan_migration(ctx: &OpContext, dir: PathBuf, to: TierId) -> Response {
+    match plan_migration(&ctx.router, &ctx.index, &ctx.open_tracker, &dir, to) {
+        Ok(plan) if plan.scanned == 0 => Response::err(format!("not indexed: {}", dir.display())),
+        Ok(plan) => Response::ok_data(ResponseData::MigratedTree {
+            path: dir,
+            to: to.into(),
+            progress: MigrateProgress::planned(&plan),
+            dry_run: true,
+        }),
+        Err(e) => Response::err(format!("migration plan failed: {e}")),
+    }
+}
+
+/// `preheat`: copies stop at the policy's high watermark, so the tierer
+/// doesn't start evicting to make room for them.
+fn op_preheat(
+    ctx: &OpContext,
+    path: PathBuf,
+    release: bool,
+    on_progress: &mut dyn FnMut(&PreheatReport),
+) -> Response {
+    let dir = normalize(&path);
+    let result = if release {
+        preheat::release(&ctx.router, &ctx.index, &dir)
+    } else {
+        let max_usage = match &ctx.policy {
+            Some(p) => p.high_watermark(),
+            None => PopularityPolicy::default().high_watermark,
+        };
+        preheat::preheat(&ctx.router, &ctx.index, &dir, max_usage, on_progress)
+    };
+    match result {
+        Ok(report) if !release && report.files_scanned == 0 => {
+            Response::err(format!("not indexed: {}", dir.display()))
+        }
+        Ok(report) => Response::ok_data(ResponseData::Preheated { path: dir, report }),
+        Err(e) => Response::err(format!("preheat failed: {e}")),
+    }
+}
+
 fn op_freeze(ctx: &OpContext, paused: bool) -> Response {
     ctx.tierer.set_paused(paused);
     Response::ok_data(ResponseData::FreezeState { frozen: paused })
@@ -406,6 +717,7 @@ fn op_fsck(ctx: &OpContext, repair: bool) -> Response {
     let mut orphans: Vec<PathBuf> = Vec::new();
     let mut ghosts: Vec<PathBuf> = Vec::new();
     let mut inconsistencies: Vec<ReplicaInconsistency> = Vec::new();
+    let mut partial: Vec<PathBuf> = Vec::new();
     let mut repaired = 0usize;
 
     // Build map of logical_path → location from index.
@@ -428,6 +740,27 @@ fn op_fsck(ctx: &OpContext, repair: bool) -> Response {
             ctx.router.resolve_backend(row.location.tier, &row.location.backend_id)
         {
             match backend.exists(&row.location.backend_path) {
+                Ok(true) if row.state == FileState::Creating
+                    && !ctx.open_tracker.is_open(&row.logical_path) =>
+                {
+                    // Created through the mount, never flushed or released:
+                    // the daemon died in between.
+                    partial.push(row.logical_path.clone());
+                    if repair {
+                        match repair_partial(ctx, backend, row) {
+                            Ok(()) => repaired += 1,
+                            Err(e) => warn!(
+                                "fsck repair (partial) {}: {:?}",
+                                row.logical_path.display(),
+                                e
+                            ),
+                        }
+                    }
+                    indexed_by_backend
+                        .entry((row.location.tier, row.location.backend_id.clone()))
+                        .or_default()
+                        .insert(row.location.backend_path.clone());
+                }
                 Ok(true) => {
                     indexed_by_backend
                         .entry((row.location.tier, row.location.backend_id.clone()))
@@ -441,6 +774,9 @@ fn op_fsck(ctx: &OpContext, repair: bool) -> Response {
                             warn!("fsck repair (ghost) {}: {:?}", row.logical_path.display(), e);
                         } else {
                             repaired += 1;
+                            crate::hooks::emit(crate::hooks::Event::Repaired {
+                                path: row.logical_path.clone(),
+                            });
                         }
                     }
                 }
@@ -502,28 +838,32 @@ fn op_fsck(ctx: &OpContext, repair: bool) -> Response {
         orphans,
         ghosts,
         inconsistencies,
+        partial,
         repaired,
     })
 }
 
+/// Drop a partially-created file that is still empty; one that got data
+/// before the crash is kept and marked stable.
+fn repair_partial(ctx: &OpContext, backend: &Arc<dyn Backend>, row: &FileRow) -> Result<()> {
+    let path = &row.location.backend_path;
+    if backend.metadata(path)?.size == 0 {
+        backend.remove(path)?;
+        ctx.index.remove(&row.logical_path)
+    } else {
+        ctx.index.set_state(&row.logical_path, FileState::Stable)
+    }
+}
+
 fn walk_orphans(
     backend: &Arc<dyn Backend>,
     known: &std::collections::HashSet<PathBuf>,
     out: &mut Vec<PathBuf>,
 ) -> Result<()> {
-    let root = backend.root().to_path_buf();
-    for entry in walkdir::WalkDir::new(&root).follow_links(false) {
-        let entry = entry.map_err(|e| FsError::Storage(format!("walk: {e}")))?;
-        if !entry.file_type().is_file() {
-            continue;
-        }
-        let abs = entry.path();
-        if let Ok(rel) = abs.strip_prefix(&root) {
-            let rel_buf = rel.to_path_buf();
-            if !known.contains(&rel_buf) {
-                let logical = PathBuf::from("/").join(&rel_buf);
-                out.push(logical);
-            }
+    for rel in backend.walk_files() {
+        let rel = rel?;
+        if !known.contains(&rel) {
+            out.push(PathBuf::from("/").join(&rel));
         }
     }
     Ok(())
@@ -533,13 +873,16 @@ fn op_rescan(ctx: &OpContext) -> Response {
     // Idempotent first_scan re-run. New files get indexed; already-indexed
     // files are skipped; cross-backend conflicts get reported (but DO NOT
     // hard-fail — the daemon is up and serving, just surface the conflicts).
+    // Only identical duplicates are cleaned up here: picking a winner could
+    // delete a copy that's open through the mount.
     let _ = ctx;
     let _ = SystemTime::now();
-    match scan::first_scan(&ctx.router, &ctx.index) {
+    match scan::first_scan(&ctx.router, &ctx.index, scan::DuplicatePolicy::Error) {
         Ok(stats) => Response::ok_data(ResponseData::Rescan {
             added: stats.indexed,
             already_indexed: stats.skipped_existing,
             conflicts: stats.conflicts,
+            duplicates_removed: stats.resolved.into_iter().map(|r| r.logical).collect(),
         }),
         Err(e) => Response::err(format!("rescan: {e}")),
     }
@@ -0,0 +1,547 @@
+//! Storage cost estimates for chargeback: `rhss report costs` and
+//! `rhss report usage`.
+//!
+//! Every tier has a rate in USD per GiB-month: `[cost]` in the config, or
+//! else the mean `cost_per_gb_month` its backends declare. A cost is the
+//! logical bytes in the index times the rate of the tier holding them, so
+//! it can be charged to whoever owns a directory; compression and dedup
+//! savings are not subtracted. `rhss cost` prices backend usage instead.
+//!
+//! The pending change is what the next tier cycle would move under a
+//! policy, picked the way the tierer picks (`tierer::bytes_to_free`, then
+//! `PathIndex::coldest`) without moving anything, and what that does to
+//! the monthly total.
+//!
+//! The usage report charges owners instead of directories: every indexed
+//! file is stat'ed for its uid or gid, as `Quotas::seed` does, so it reads
+//! metadata from every backend and suits a daily schedule
+//! (`[usage_report]`) rather than a dashboard. Owners are numeric ids;
+//! mapping them to teams is left to whoever consumes the CSV or JSON.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::config::CostOptions;
+use crate::error::{FsError, Result};
+use crate::index::{PathIndex, TierId};
+use crate::policy::TieringPolicy;
+use crate::tier::TierRouter;
+use crate::tierer::bytes_to_free;
+use crate::tierer::compress::compressed_path;
+
+const GIB: f64 = (1u64 << 30) as f64;
+
+const TIERS: [TierId; 3] = [TierId::Fast, TierId::Slow, TierId::Archive];
+
+/// USD per GiB-month of each tier; `None` is unpriced and costs nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TierRates {
+    pub fast: Option<f64>,
+    pub slow: Option<f64>,
+    pub archive: Option<f64>,
+}
+
+impl TierRates {
+    /// `[cost]`, falling back to the mean of the backends' declared costs.
+    pub fn resolve(opts: &CostOptions, router: &TierRouter) -> Self {
+        let declared = |tier: TierId| {
+            let costs: Vec<f64> = router
+                .all_backends()
+                .filter(|(t, _)| *t == tier)
+                .filter_map(|(_, b)| b.cost_per_gb_month())
+                .collect();
+            (!costs.is_empty()).then(|| costs.iter().sum::<f64>() / costs.len() as f64)
+        };
+        Self {
+            fast: opts.fast.or_else(|| declared(TierId::Fast)),
+            slow: opts.slow.or_else(|| declared(TierId::Slow)),
+            archive: opts.archive.or_else(|| declared(TierId::Archive)),
+        }
+    }
+
+    pub fn get(&self, tier: TierId) -> Option<f64> {
+        match tier {
+            TierId::Fast => self.fast,
+            TierId::Slow => self.slow,
+            TierId::Archive => self.archive,
+        }
+    }
+
+    /// Monthly cost of `bytes` on `tier`.
+    pub fn monthly(&self, tier: TierId, bytes: u64) -> f64 {
+        bytes as f64 / GIB * self.get(tier).unwrap_or(0.0)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CostReport {
+    pub tiers: Vec<TierCost>,
+    pub total_monthly: f64,
+    /// What the next tier cycle would move, one entry per chain.
+    pub pending: Vec<PendingMove>,
+    /// Change of `total_monthly` once `pending` has moved.
+    pub pending_delta: f64,
+    /// Most expensive directories at the requested depth, highest first.
+    pub top_dirs: Vec<DirCost>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TierCost {
+    pub tier: &'static str,
+    pub files: u64,
+    pub bytes: u64,
+    /// USD per GiB-month; `None` when unpriced.
+    pub rate: Option<f64>,
+    pub monthly: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PendingMove {
+    pub from: &'static str,
+    pub to: &'static str,
+    pub files: u64,
+    pub bytes: u64,
+    pub monthly_delta: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DirCost {
+    pub dir: PathBuf,
+    pub files: u64,
+    pub bytes: u64,
+    pub monthly: f64,
+}
+
+/// Cost of what is indexed now, of the moves `policy` has pending, and of
+/// the `top` most expensive directories `depth` levels below `/`.
+pub fn report(
+    index: &Arc<dyn PathIndex>,
+    router: &TierRouter,
+    rates: TierRates,
+    policy: &dyn TieringPolicy,
+    depth: usize,
+    top: usize,
+) -> Result<CostReport> {
+    let summary = index.tier_summary()?;
+    let tiers: Vec<TierCost> = TIERS
+        .iter()
+        .filter_map(|&tier| {
+            let (_, files, bytes) = summary.iter().find(|(t, _, _)| *t == tier)?;
+            Some(TierCost {
+                tier: tier.as_str(),
+                files: *files,
+                bytes: *bytes,
+                rate: rates.get(tier),
+                monthly: rates.monthly(tier, *bytes),
+            })
+        })
+        .collect();
+    let pending = pending_moves(index, router, &rates, policy)?;
+    Ok(CostReport {
+        total_monthly: tiers.iter().map(|t| t.monthly).sum(),
+        tiers,
+        pending_delta: pending.iter().map(|m| m.monthly_delta).sum(),
+        pending,
+        top_dirs: top_dirs(index, &rates, depth, top)?,
+    })
+}
+
+/// The eviction chains of `tierer::evict_cold`, planned only.
+fn pending_moves(
+    index: &Arc<dyn PathIndex>,
+    router: &TierRouter,
+    rates: &TierRates,
+    policy: &dyn TieringPolicy,
+) -> Result<Vec<PendingMove>> {
+    let mut chains = vec![(
+        TierId::Fast,
+        TierId::Slow,
+        policy.low_watermark(),
+        policy.high_watermark(),
+        policy.min_age_to_evict(),
+    )];
+    if router.has_archive() {
+        let wm = policy.slow_archive_watermark();
+        chains.push((
+            TierId::Slow,
+            TierId::Archive,
+            (wm - 0.10).max(0.0),
+            wm,
+            policy.min_age_to_archive(),
+        ));
+    }
+    let mut out = Vec::new();
+    for (from, to, low, high, min_age) in chains {
+        let tier = router.tier_unchecked(from);
+        // The archive chain only starts above its watermark.
+        let start = if to == TierId::Archive { high } else { low };
+        if tier.usage_ratio() <= start {
+            continue;
+        }
+        let (total, used, _) = tier.capacity();
+        let to_free = bytes_to_free(total, used, low, high);
+        if to_free == 0 {
+            continue;
+        }
+        let victims = index.coldest(from, to_free, min_age)?;
+        if victims.is_empty() {
+            continue;
+        }
+        let bytes: u64 = victims.iter().map(|(_, size)| size).sum();
+        out.push(PendingMove {
+            from: from.as_str(),
+            to: to.as_str(),
+            files: victims.len() as u64,
+            bytes,
+            monthly_delta: rates.monthly(to, bytes) - rates.monthly(from, bytes),
+        });
+    }
+    Ok(out)
+}
+
+fn top_dirs(
+    index: &Arc<dyn PathIndex>,
+    rates: &TierRates,
+    depth: usize,
+    top: usize,
+) -> Result<Vec<DirCost>> {
+    let mut dirs: BTreeMap<PathBuf, DirCost> = BTreeMap::new();
+    for u in index.dir_usage(Path::new("/"), depth)? {
+        if u.dir.components().count() != depth + 1 {
+            continue;
+        }
+        let d = dirs.entry(u.dir.clone()).or_insert_with(|| DirCost {
+            dir: u.dir.clone(),
+            ..Default::default()
+        });
+        d.files += u.files;
+        d.bytes += u.bytes;
+        d.monthly += rates.monthly(u.tier, u.bytes);
+    }
+    let mut dirs: Vec<DirCost> = dirs.into_values().collect();
+    dirs.sort_by(|a, b| b.monthly.total_cmp(&a.monthly).then(b.bytes.cmp(&a.bytes)));
+    dirs.truncate(top);
+    Ok(dirs)
+}
+
+/// What `rhss report usage` groups files by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    #[default]
+    Uid,
+    Gid,
+}
+
+impl GroupBy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GroupBy::Uid => "uid",
+            GroupBy::Gid => "gid",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl ReportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Csv => "csv",
+            ReportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UsageReport {
+    /// Unix seconds the report was taken at.
+    pub generated: u64,
+    pub group_by: GroupBy,
+    /// Most expensive first.
+    pub owners: Vec<OwnerUsage>,
+    pub total_monthly: f64,
+    /// Indexed files whose owner could not be read; not in `owners`.
+    pub unreadable: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OwnerUsage {
+    /// The uid or gid.
+    pub owner: u32,
+    pub files: u64,
+    pub bytes: u64,
+    pub fast_bytes: u64,
+    pub slow_bytes: u64,
+    pub archive_bytes: u64,
+    pub monthly: f64,
+}
+
+impl UsageReport {
+    /// One line per owner under a header, `monthly_usd` to the cent.
+    pub fn to_csv(&self) -> String {
+        let mut out = format!(
+            "{},files,bytes,fast_bytes,slow_bytes,archive_bytes,monthly_usd\n",
+            self.group_by.as_str()
+        );
+        for o in &self.owners {
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{:.2}\n",
+                o.owner, o.files, o.bytes, o.fast_bytes, o.slow_bytes, o.archive_bytes, o.monthly
+            ));
+        }
+        out
+    }
+
+    pub fn render(&self, format: ReportFormat) -> Result<String> {
+        Ok(match format {
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Json => serde_json::to_string_pretty(self)? + "\n",
+        })
+    }
+}
+
+/// Logical bytes and monthly cost of every owner's indexed files.
+pub fn usage_by_owner(
+    index: &Arc<dyn PathIndex>,
+    router: &TierRouter,
+    rates: &TierRates,
+    group_by: GroupBy,
+) -> Result<UsageReport> {
+    let mut owners: BTreeMap<u32, OwnerUsage> = BTreeMap::new();
+    let mut unreadable = 0;
+    for row in index.list_under(Path::new("/"))? {
+        let loc = &row.location;
+        // Compressed files keep their owner on the compressed bytes.
+        let meta = router
+            .resolve_backend(loc.tier, &loc.backend_id)
+            .ok_or_else(|| FsError::NotFound(loc.backend_id.clone()))
+            .and_then(|b| {
+                if row.compressed {
+                    b.metadata(&compressed_path(&loc.backend_path))
+                } else {
+                    b.metadata(&loc.backend_path)
+                }
+            });
+        let meta = match meta {
+            Ok(m) => m,
+            Err(e) => {
+                debug!("usage report: {}: {e}", row.logical_path.display());
+                unreadable += 1;
+                continue;
+            }
+        };
+        let owner = match group_by {
+            GroupBy::Uid => meta.uid,
+            GroupBy::Gid => meta.gid,
+        };
+        let o = owners.entry(owner).or_insert_with(|| OwnerUsage {
+            owner,
+            ..Default::default()
+        });
+        o.files += 1;
+        o.bytes += loc.size;
+        match loc.tier {
+            TierId::Fast => o.fast_bytes += loc.size,
+            TierId::Slow => o.slow_bytes += loc.size,
+            TierId::Archive => o.archive_bytes += loc.size,
+        }
+        o.monthly += rates.monthly(loc.tier, loc.size);
+    }
+    let mut owners: Vec<OwnerUsage> = owners.into_values().collect();
+    owners.sort_by(|a, b| b.monthly.total_cmp(&a.monthly).then(b.bytes.cmp(&a.bytes)));
+    Ok(UsageReport {
+        generated: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        group_by,
+        total_monthly: owners.iter().map(|o| o.monthly).sum(),
+        owners,
+        unreadable,
+    })
+}
+
+/// Where a scheduled report goes: `usage-<unix secs>.<csv|json>` in `dir`,
+/// written whole and renamed into place.
+pub fn write_usage_report(
+    dir: &Path,
+    report: &UsageReport,
+    format: ReportFormat,
+) -> Result<PathBuf> {
+    let io_err = |e: std::io::Error, p: &Path| FsError::from_io(e, p.display());
+    std::fs::create_dir_all(dir).map_err(|e| io_err(e, dir))?;
+    let name = format!("usage-{}.{}", report.generated, format.extension());
+    let path = dir.join(&name);
+    let tmp = dir.join(format!(".{name}.tmp"));
+    std::fs::write(&tmp, report.render(format)?).map_err(|e| io_err(e, &tmp))?;
+    std::fs::rename(&tmp, &path).map_err(|e| io_err(e, &path))?;
+    Ok(path)
+}
+
+/// Write a usage report into `dir` every `every` on an "rhss-usage-report"
+/// thread, the first one `every` after mount.
+pub fn spawn_usage_reports(
+    index: Arc<dyn PathIndex>,
+    router: Arc<TierRouter>,
+    rates: TierRates,
+    every: Duration,
+    dir: PathBuf,
+    group_by: GroupBy,
+    format: ReportFormat,
+) {
+    let spawned = std::thread::Builder::new()
+        .name("rhss-usage-report".into())
+        .spawn(move || loop {
+            std::thread::sleep(every);
+            let written = usage_by_owner(&index, &router, &rates, group_by)
+                .and_then(|r| write_usage_report(&dir, &r, format));
+            match written {
+                Ok(path) => info!("usage report: wrote {}", path.display()),
+                Err(e) => warn!("usage report: {e}"),
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("spawn usage report thread: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::index::{FileRow, FileState, Location, Mutability, SqlitePathIndex};
+    use crate::policy::PopularityPolicy;
+    use crate::tier::{MostFreePlacement, Tier};
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    fn row(path: &str, tier: TierId, size: u64, popularity: f64) -> FileRow {
+        FileRow {
+            logical_path: path.into(),
+            location: Location {
+                tier,
+                backend_id: if tier == TierId::Fast { "ssd" } else { "hdd" }.into(),
+                backend_path: path.trim_start_matches('/').into(),
+                size,
+            },
+            replicas: Vec::new(),
+            last_access: SystemTime::now() - Duration::from_secs(86_400),
+            hit_count: 0,
+            popularity,
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            mutability: Mutability::Unknown,
+            compressed: false,
+            content_hash: None,
+        }
+    }
+
+    #[test]
+    fn costs_by_tier_directory_and_pending_moves() {
+        let db = TempDir::new().unwrap();
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(db.path().join("i.db")).unwrap();
+        let ssd: Arc<dyn Backend> = Arc::new(MemoryBackend::new("ssd", 1000));
+        let hdd: Arc<dyn Backend> = Arc::new(MemoryBackend::with_cost("hdd", 10_000, Some(0.02)));
+        // 900 of 1000 bytes in use on the fast tier: above the high watermark.
+        ssd.write_at(Path::new("fill"), 0, &[0; 900]).unwrap();
+        let router = TierRouter::new(
+            Tier::new(TierId::Fast, vec![ssd], Box::new(MostFreePlacement)).unwrap(),
+            Tier::new(TierId::Slow, vec![hdd], Box::new(MostFreePlacement)).unwrap(),
+        );
+        index
+            .insert(row("/alice/a", TierId::Fast, 300, 0.0))
+            .unwrap();
+        index
+            .insert(row("/alice/b", TierId::Slow, 1000, 0.0))
+            .unwrap();
+        index.insert(row("/bob/c", TierId::Fast, 600, 1.0)).unwrap();
+
+        // Fast from the config, slow from what its backend declares.
+        let opts = CostOptions {
+            fast: Some(0.10),
+            ..Default::default()
+        };
+        let rates = TierRates::resolve(&opts, &router);
+        assert_eq!((rates.slow, rates.archive), (Some(0.02), None));
+        let policy = PopularityPolicy::default();
+        let r = report(&index, &router, rates, &policy, 1, 10).unwrap();
+
+        let usd = |fast: u64, slow: u64| (fast as f64 * 0.10 + slow as f64 * 0.02) / GIB;
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-15;
+        assert!(close(r.total_monthly, usd(900, 1000)), "{r:?}");
+        let dirs: Vec<_> = r.top_dirs.iter().map(|d| d.dir.to_str().unwrap()).collect();
+        assert_eq!(dirs, ["/bob", "/alice"]);
+        assert!(close(r.top_dirs[0].monthly, usd(600, 0)));
+        assert!(close(r.top_dirs[1].monthly, usd(300, 1000)));
+        // Down to 725 bytes frees 175, which the coldest file covers.
+        assert_eq!(r.pending.len(), 1);
+        let m = &r.pending[0];
+        assert_eq!((m.from, m.to, m.files, m.bytes), ("fast", "slow", 1, 300));
+        assert!(close(m.monthly_delta, usd(0, 300) - usd(300, 0)));
+        assert!(close(r.pending_delta, m.monthly_delta));
+    }
+
+    #[test]
+    fn usage_is_charged_to_owners() {
+        let db = TempDir::new().unwrap();
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(db.path().join("i.db")).unwrap();
+        let ssd: Arc<dyn Backend> = Arc::new(MemoryBackend::new("ssd", 1 << 20));
+        let hdd: Arc<dyn Backend> = Arc::new(MemoryBackend::new("hdd", 1 << 20));
+        for (b, path, uid, size) in [
+            (&ssd, "a", 1001, 300),
+            (&hdd, "b", 1001, 1000),
+            (&ssd, "c", 1002, 600),
+        ] {
+            b.write_at(Path::new(path), 0, &vec![0; size]).unwrap();
+            b.set_owner(Path::new(path), uid, 100).unwrap();
+        }
+        let router = TierRouter::new(
+            Tier::new(TierId::Fast, vec![ssd], Box::new(MostFreePlacement)).unwrap(),
+            Tier::new(TierId::Slow, vec![hdd], Box::new(MostFreePlacement)).unwrap(),
+        );
+        index.insert(row("/a", TierId::Fast, 300, 0.0)).unwrap();
+        index.insert(row("/b", TierId::Slow, 1000, 0.0)).unwrap();
+        index.insert(row("/c", TierId::Fast, 600, 0.0)).unwrap();
+        // Indexed, but gone from the backend.
+        index.insert(row("/d", TierId::Fast, 5, 0.0)).unwrap();
+        let rates = TierRates {
+            fast: Some(0.10),
+            slow: Some(0.02),
+            archive: None,
+        };
+
+        let r = usage_by_owner(&index, &router, &rates, GroupBy::Uid).unwrap();
+        assert_eq!(r.unreadable, 1);
+        let owners: Vec<_> = r.owners.iter().map(|o| (o.owner, o.files)).collect();
+        assert_eq!(owners, [(1002, 1), (1001, 2)]);
+        assert_eq!(
+            (r.owners[1].fast_bytes, r.owners[1].slow_bytes),
+            (300, 1000)
+        );
+        let csv = r.to_csv();
+        assert!(csv.starts_with("uid,files,bytes,"), "{csv}");
+        assert!(csv.contains("\n1001,2,1300,300,1000,0,0.00\n"), "{csv}");
+
+        let by_gid = usage_by_owner(&index, &router, &rates, GroupBy::Gid).unwrap();
+        assert_eq!(by_gid.owners.len(), 1);
+        assert_eq!(by_gid.owners[0].bytes, 1900);
+
+        let out = TempDir::new().unwrap();
+        let path = write_usage_report(out.path(), &r, ReportFormat::Json).unwrap();
+        let back: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(back["owners"][0]["owner"], 1002);
+    }
+}
@@ -0,0 +1,401 @@
+//! Running `rhss mount` as a service: `--daemon` double-fork, PID file,
+//! signal handling (SIGTERM/SIGINT stop, SIGHUP reload) and systemd
+//! readiness (`sd_notify`).
+//!
+//! With `--daemon` the launching process waits on a pipe until the child
+//! reports the mount is live (exit 0) or dies first (exit 1), so scripts
+//! and `Type=forking` units see a meaningful status. Under `Type=notify`
+//! run in the foreground instead; `sd_notify("READY=1")` is sent whenever
+//! `$NOTIFY_SOCKET` is set.
+//!
+//! The working directory is kept, so relative paths in the config still
+//! resolve after detaching.
+//!
+//! In a container, run in the foreground (`--foreground`, the default)
+//! as the entrypoint. Signal handlers go in before the mount starts, since
+//! the kernel drops SIGTERM and SIGINT aimed at a PID 1 that has none.
+//! As PID 1, rhss also reaps orphaned processes that get re-parented to it.
+//! With `--propagate-signals` the stop signal is passed on at shutdown: to
+//! hook commands still running and, as PID 1, to every other process in
+//! the container. `crate::probes` serves liveness and readiness.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Mutex;
+
+use tracing::{debug, info, warn};
+
+use crate::error::{FsError, Result};
+
+fn last_err(what: &str) -> FsError {
+    FsError::Storage(format!("daemonize: {what}: {}", io::Error::last_os_error()))
+}
+
+/// Write end of the readiness pipe (daemon mode) or nothing (foreground).
+pub struct Readiness {
+    pipe: Option<File>,
+}
+
+impl Readiness {
+    pub fn foreground() -> Self {
+        Self { pipe: None }
+    }
+
+    /// Tell whoever started us that the mount is up.
+    pub fn ready(mut self) {
+        if let Some(mut p) = self.pipe.take() {
+            let _ = p.write_all(b"1");
+        }
+        READY.store(true, Ordering::SeqCst);
+        sd_notify("READY=1");
+    }
+}
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// True once `Readiness::ready` has been called.
+pub fn is_ready() -> bool {
+    READY.load(Ordering::SeqCst)
+}
+
+/// Detach from the terminal. Must run before any thread is spawned. Returns
+/// only in the final daemon process; the original process exits once
+/// `Readiness::ready` is called (0) or the daemon dies first (1).
+pub fn daemonize() -> Result<Readiness> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(last_err("pipe"));
+    }
+    let (r, w) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(last_err("fork")),
+        0 => {}
+        _ => {
+            drop(w);
+            let mut byte = [0u8; 1];
+            let ok = matches!(File::from(r).read(&mut byte), Ok(1));
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+    }
+    drop(r);
+    if unsafe { libc::setsid() } < 0 {
+        return Err(last_err("setsid"));
+    }
+    // Second fork: the session leader exits so we can never reacquire a
+    // controlling terminal.
+    match unsafe { libc::fork() } {
+        -1 => return Err(last_err("fork")),
+        0 => {}
+        _ => unsafe { libc::_exit(0) },
+    }
+
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(|e| FsError::from_io(e, "/dev/null"))?;
+    for fd in 0..=2 {
+        if unsafe { libc::dup2(std::os::fd::AsRawFd::as_raw_fd(&null), fd) } < 0 {
+            return Err(last_err("redirect stdio"));
+        }
+    }
+    Ok(Readiness {
+        pipe: Some(File::from(w)),
+    })
+}
+
+/// Send a state line (`READY=1`, `STOPPING=1`, …) to systemd. No-op when
+/// not started by systemd.
+pub fn sd_notify(state: &str) {
+    let Some(target) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let res = UnixDatagram::unbound().and_then(|sock| {
+        let bytes = target.as_bytes();
+        if let Some(name) = bytes.strip_prefix(b"@") {
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                return sock.send_to_addr(state.as_bytes(), &addr).map(|_| ());
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = name;
+                return Err(io::Error::from(io::ErrorKind::Unsupported));
+            }
+        }
+        sock.send_to(state.as_bytes(), Path::new(&target))
+            .map(|_| ())
+    });
+    if let Err(e) = res {
+        debug!("sd_notify {state}: {e}");
+    }
+}
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+/// The signal that asked us to stop; 0 for `request_stop`.
+static STOP_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn on_signal(sig: libc::c_int) {
+    // Async-signal context: only touch atomics.
+    if sig == libc::SIGHUP {
+        RELOAD.store(true, Ordering::SeqCst);
+    } else {
+        STOP_SIGNAL.store(sig, Ordering::SeqCst);
+        STOP.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Route SIGINT/SIGTERM to shutdown and SIGHUP to config reload. The main
+/// loop polls `stop_requested` / `take_reload`.
+pub fn install_signal_handlers() -> Result<()> {
+    for sig in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        let mut sa: libc::sigaction = unsafe { std::mem::zeroed() };
+        sa.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+        sa.sa_flags = libc::SA_RESTART;
+        unsafe { libc::sigemptyset(&mut sa.sa_mask) };
+        if unsafe { libc::sigaction(sig, &sa, std::ptr::null_mut()) } != 0 {
+            return Err(last_err("sigaction"));
+        }
+    }
+    Ok(())
+}
+
+/// Same as receiving SIGTERM; used by the control socket's `shutdown`.
+pub fn request_stop() {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+/// True once per received SIGHUP (coalesced).
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+/// Running as a PID namespace's init, e.g. a container entrypoint.
+pub fn is_init() -> bool {
+    std::process::id() == 1
+}
+
+/// Child processes someone else waits for (hook commands), which
+/// `reap_orphans` must leave alone.
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+fn children() -> std::sync::MutexGuard<'static, Vec<u32>> {
+    CHILDREN.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Registers a spawned child for `reap_orphans` and `propagate_stop`
+/// until dropped. Drop it after the child has been waited for.
+pub struct TrackedChild(u32);
+
+impl TrackedChild {
+    /// Spawn `cmd` and register it in one step: the registry stays locked
+    /// until the pid is in, so `reap_orphans` can't take the status of a
+    /// child that exits straight away.
+    pub fn spawn(cmd: &mut Command) -> io::Result<(Child, Self)> {
+        let mut children = children();
+        let child = cmd.spawn()?;
+        let pid = child.id();
+        children.push(pid);
+        Ok((child, Self(pid)))
+    }
+}
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        children().retain(|&p| p != self.0);
+    }
+}
+
+/// Collect exited processes that are not a `TrackedChild`: as PID 1,
+/// orphans anywhere in the container end up ours and would otherwise stay
+/// zombies. Call from the main loop; only does anything as PID 1 on
+/// Linux.
+pub fn reap_orphans() {
+    #[cfg(target_os = "linux")]
+    if is_init() {
+        reap_untracked();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reap_untracked() {
+    // Peek first: nothing has exited on most calls.
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    if unsafe { libc::waitid(libc::P_ALL, 0, &mut info, flags) } != 0
+        || unsafe { info.si_pid() } <= 0
+    {
+        return;
+    }
+    // `waitid` only ever shows the first zombie, which may be a tracked
+    // child waiting for its owner. Reap the others by pid instead.
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return;
+    };
+    let me = std::process::id();
+    let tracked = children();
+    for entry in procs.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name.to_str().and_then(|n| n.parse::<u32>().ok()) else {
+            continue;
+        };
+        if tracked.contains(&pid) {
+            continue;
+        }
+        let stat = fs::read_to_string(entry.path().join("stat")).unwrap_or_default();
+        if zombie_parent(&stat) != Some(me) {
+            continue;
+        }
+        let pid = pid as libc::pid_t;
+        if unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) } == pid {
+            debug!("reaped orphaned process {pid}");
+        }
+    }
+}
+
+/// The parent pid from a `/proc/<pid>/stat` line if the process is a
+/// zombie. The command name may hold spaces and parentheses, so the
+/// fields are counted from its last `)`.
+#[cfg(target_os = "linux")]
+fn zombie_parent(stat: &str) -> Option<u32> {
+    let mut fields = stat.get(stat.rfind(')')? + 1..)?.split_whitespace();
+    match (fields.next()?, fields.next()?) {
+        ("Z", ppid) => ppid.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Pass the stop signal (SIGTERM after `request_stop`) on to tracked
+/// children and, as PID 1, to every other process in the PID namespace.
+pub fn propagate_stop() {
+    let sig = match STOP_SIGNAL.load(Ordering::SeqCst) {
+        0 => libc::SIGTERM,
+        sig => sig,
+    };
+    for &pid in children().iter() {
+        unsafe { libc::kill(pid as libc::pid_t, sig) };
+    }
+    if is_init() {
+        // -1 is everything we may signal except ourselves.
+        if unsafe { libc::kill(-1, sig) } == 0 {
+            info!("forwarded signal {sig} to the other processes in the container");
+        }
+    }
+}
+
+/// PID file, removed on drop. Refuses to overwrite one whose process is
+/// still alive.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Ok(old) = fs::read_to_string(path) {
+            if let Ok(pid) = old.trim().parse::<libc::pid_t>() {
+                if pid > 0 && unsafe { libc::kill(pid, 0) } == 0 {
+                    return Err(FsError::AlreadyExists(format!(
+                        "{} (pid {pid} is running)",
+                        path.display()
+                    )));
+                }
+            }
+            warn!("replacing stale pid file {}", path.display());
+        }
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| FsError::from_io(e, parent.display()))?;
+        }
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o644)
+            .open(path)
+            .map_err(|e| FsError::from_io(e, path.display()))?;
+        writeln!(f, "{}", std::process::id()).map_err(|e| FsError::from_io(e, path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_file_refuses_live_owner_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/rhss.pid");
+        let pid = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap().trim(),
+            std::process::id().to_string()
+        );
+        // Our own pid is alive, so a second daemon must not clobber it.
+        assert!(PidFile::create(&path).is_err());
+        drop(pid);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_pid_file_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rhss.pid");
+        fs::write(&path, "999999999\n").unwrap();
+        let _pid = PidFile::create(&path).unwrap();
+    }
+
+    #[test]
+    fn spawned_children_are_tracked_until_dropped() {
+        let (mut child, tracked) = TrackedChild::spawn(&mut Command::new("true")).unwrap();
+        let pid = child.id();
+        assert!(children().contains(&pid));
+        child.wait().unwrap();
+        drop(tracked);
+        assert!(!children().contains(&pid));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn zombies_are_found_by_their_stat_line() {
+        assert_eq!(zombie_parent("42 (sh) Z 1 42 42 0 -1"), Some(1));
+        assert_eq!(zombie_parent("42 (a) Z 7) S 1 42 42 0 -1"), None);
+        assert_eq!(zombie_parent("42 (a) Z 7) Z 9 42 42 0 -1"), Some(9));
+        assert_eq!(zombie_parent(""), None);
+    }
+
+    #[test]
+    fn sd_notify_reaches_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let sock_path = dir.path().join("notify");
+        let sock = UnixDatagram::bind(&sock_path).unwrap();
+        std::env::set_var("NOTIFY_SOCKET", &sock_path);
+        sd_notify("READY=1");
+        std::env::remove_var("NOTIFY_SOCKET");
+        let mut buf = [0u8; 16];
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+    }
+}
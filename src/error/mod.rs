@@ -1,27 +1,396 @@
+use std::fmt::{self, Display};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
 use thiserror::Error;
 
+use crate::index::TierId;
+
 #[derive(Error, Debug)]
 pub enum FsError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
-    
+
     #[error("Storage error: {0}")]
     Storage(String),
-    
+
     #[error("Metadata error: {0}")]
     Metadata(String),
-    
+
     #[error("File not found: {0}")]
     NotFound(String),
-    
+
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
-    
+
     #[error("Invalid operation: {0}")]
     InvalidOperation(String),
 
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+
+    #[error("Directory not empty: {0}")]
+    NotEmpty(String),
+
+    #[error("Is a directory: {0}")]
+    IsDirectory(String),
+
+    #[error("Not a directory: {0}")]
+    NotDirectory(String),
+
+    #[error("No space left on device: {0}")]
+    NoSpace(String),
+
+    #[error("Disk quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("Read-only file system: {0}")]
+    ReadOnly(String),
+
+    /// The backend can't be reached: its host or mount is gone, or its
+    /// circuit is open. Surfaces as `EIO`, like a disk that went away.
+    #[error("Backend offline: {0}")]
+    Offline(String),
+
+    #[error("Timed out: {0}")]
+    TimedOut(String),
+
+    #[error("Not supported: {0}")]
+    Unsupported(String),
+
     #[error("Serialization error: {0}")]
     Json(#[from] serde_json::Error),
+
+    /// `source`, plus where it happened. See `ResultExt::context`.
+    #[error("{context}: {source}")]
+    Context {
+        context: Box<ErrorContext>,
+        source: Box<FsError>,
+    },
 }
 
-pub type Result<T> = std::result::Result<T, FsError>; 
\ No newline at end of file
+/// Which operation failed, and on which tier, backend and backend path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub op: &'static str,
+    pub tier: Option<TierId>,
+    pub backend: Option<String>,
+    /// Backend-relative path.
+    pub path: Option<PathBuf>,
+}
+
+impl ErrorContext {
+    pub fn new(op: &'static str) -> Self {
+        Self {
+            op,
+            tier: None,
+            backend: None,
+            path: None,
+        }
+    }
+
+    pub fn tier(mut self, tier: TierId) -> Self {
+        self.tier = Some(tier);
+        self
+    }
+
+    pub fn backend(mut self, id: impl Into<String>) -> Self {
+        self.backend = Some(id.into());
+        self
+    }
+
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+}
+
+/// `read docs/a.txt on fast/ssd`
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.op)?;
+        if let Some(p) = &self.path {
+            write!(f, " {}", p.display())?;
+        }
+        match (self.tier, &self.backend) {
+            (Some(t), Some(b)) => write!(f, " on {}/{b}", t.as_str()),
+            (Some(t), None) => write!(f, " on {}", t.as_str()),
+            (None, Some(b)) => write!(f, " on {b}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Attach an `ErrorContext` to a failed result.
+pub trait ResultExt<T> {
+    /// `context` is only built on failure. An error that already carries
+    /// context keeps it: the innermost one names the backend call that
+    /// actually failed.
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Result<T>;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Result<T> {
+        self.map_err(|e| match e.context() {
+            Some(_) => e,
+            None => e.with_context(context()),
+        })
+    }
+}
+
+impl FsError {
+    /// Where the error happened, if a caller attached it.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            FsError::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Attach `context`, unless the error already carries one (see
+    /// `ResultExt::context`).
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            FsError::Context { .. } => self,
+            e => FsError::Context {
+                context: Box::new(context),
+                source: Box::new(e),
+            },
+        }
+    }
+
+    /// The error without its context, for matching on the variant.
+    pub fn root(&self) -> &FsError {
+        match self {
+            FsError::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    /// Classify an `io::Error` from a backend call into the specific variant
+    /// FUSE callers care about. `what` (usually the backend-relative path)
+    /// becomes the message. Kinds we don't special-case stay `Io` so the raw
+    /// errno survives into `to_errno`.
+    pub fn from_io(err: std::io::Error, what: impl Display) -> Self {
+        match err.raw_os_error() {
+            Some(libc::ENOTEMPTY) => return FsError::NotEmpty(what.to_string()),
+            Some(libc::EISDIR) => return FsError::IsDirectory(what.to_string()),
+            Some(libc::ENOTDIR) => return FsError::NotDirectory(what.to_string()),
+            Some(libc::ENOSPC) | Some(libc::EDQUOT) => {
+                return FsError::NoSpace(what.to_string())
+            }
+            Some(libc::EROFS) => return FsError::ReadOnly(what.to_string()),
+            Some(
+                libc::ENOTCONN
+                | libc::ECONNREFUSED
+                | libc::EHOSTDOWN
+                | libc::EHOSTUNREACH
+                | libc::ENETDOWN
+                | libc::ENETUNREACH
+                | libc::ENODEV,
+            ) => return FsError::Offline(format!("{what}: {err}")),
+            _ => {}
+        }
+        match err.kind() {
+            ErrorKind::NotFound => FsError::NotFound(what.to_string()),
+            ErrorKind::PermissionDenied => FsError::PermissionDenied(what.to_string()),
+            ErrorKind::AlreadyExists => FsError::AlreadyExists(what.to_string()),
+            ErrorKind::ConnectionRefused | ErrorKind::NotConnected => {
+                FsError::Offline(format!("{what}: {err}"))
+            }
+            _ => FsError::Io(err),
+        }
+    }
+
+    /// Classify a failed HTTP status from a remote backend (WebDAV, S3).
+    /// `what` names the request, e.g. `s3 HEAD key`; it is the message of
+    /// `NotFound`, the rest also get the status.
+    pub fn from_status(status: u16, what: impl Display) -> Self {
+        match status {
+            404 | 410 => FsError::NotFound(what.to_string()),
+            401 | 403 => FsError::PermissionDenied(format!("{what}: HTTP {status}")),
+            409 | 412 => FsError::AlreadyExists(format!("{what}: HTTP {status}")),
+            501 => FsError::Unsupported(format!("{what}: HTTP {status}")),
+            502..=504 => FsError::Offline(format!("{what}: HTTP {status}")),
+            507 => FsError::NoSpace(format!("{what}: HTTP {status}")),
+            _ => FsError::Storage(format!("{what}: HTTP {status}")),
+        }
+    }
+
+    /// Whether the backend couldn't be reached or didn't answer in time
+    /// (EIO, a dropped connection, a timeout, a storage error), as opposed
+    /// to answering with an error such as `ENOENT` or `ENOSPC`. The same
+    /// call may work once the backend is back; `BreakerBackend` counts
+    /// these toward opening its circuit.
+    pub fn is_transient(&self) -> bool {
+        match self.root() {
+            FsError::TimedOut(_) | FsError::Storage(_) | FsError::Offline(_) => true,
+            FsError::Io(io) => {
+                matches!(
+                    io.raw_os_error(),
+                    Some(
+                        libc::EIO
+                            | libc::ETIMEDOUT
+                            | libc::ENOTCONN
+                            | libc::ECONNREFUSED
+                            | libc::ECONNRESET
+                            | libc::ECONNABORTED
+                            | libc::EHOSTDOWN
+                            | libc::EHOSTUNREACH
+                            | libc::ENETDOWN
+                            | libc::ENETUNREACH
+                            | libc::ESTALE
+                            | libc::ENODEV
+                    )
+                ) || matches!(
+                    io.kind(),
+                    ErrorKind::TimedOut
+                        | ErrorKind::ConnectionRefused
+                        | ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::NotConnected
+                        | ErrorKind::BrokenPipe
+                )
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether repeating the call unchanged may succeed: transient
+    /// failures, plus an interrupted or busy call (`EINTR`, `EAGAIN`,
+    /// `EBUSY`). Only worth doing for idempotent calls.
+    pub fn is_retryable(&self) -> bool {
+        if self.is_transient() {
+            return true;
+        }
+        match self.root() {
+            FsError::Io(io) => {
+                matches!(
+                    io.raw_os_error(),
+                    Some(libc::EINTR | libc::EAGAIN | libc::EBUSY)
+                ) || matches!(io.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock)
+            }
+            _ => false,
+        }
+    }
+
+    /// errno to hand back to the kernel in `reply.error()`. Unclassified
+    /// `Io` errors pass their raw errno through; everything else without a
+    /// POSIX equivalent is `EIO`.
+    pub fn to_errno(&self) -> libc::c_int {
+        match self {
+            FsError::Io(io) => io.raw_os_error().unwrap_or(libc::EIO),
+            FsError::NotFound(_) => libc::ENOENT,
+            FsError::PermissionDenied(_) => libc::EACCES,
+            FsError::InvalidOperation(_) => libc::EINVAL,
+            FsError::AlreadyExists(_) => libc::EEXIST,
+            FsError::NotEmpty(_) => libc::ENOTEMPTY,
+            FsError::IsDirectory(_) => libc::EISDIR,
+            FsError::NotDirectory(_) => libc::ENOTDIR,
+            FsError::NoSpace(_) => libc::ENOSPC,
+            FsError::QuotaExceeded(_) => libc::EDQUOT,
+            FsError::ReadOnly(_) => libc::EROFS,
+            FsError::Offline(_) => libc::EIO,
+            FsError::TimedOut(_) => libc::ETIMEDOUT,
+            FsError::Unsupported(_) => libc::EOPNOTSUPP,
+            FsError::Storage(_) | FsError::Metadata(_) | FsError::Json(_) => libc::EIO,
+            FsError::Context { source, .. } => source.to_errno(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, FsError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn from_io_classifies_common_kinds() {
+        let e = FsError::from_io(io::Error::from_raw_os_error(libc::ENOENT), "a");
+        assert!(matches!(e, FsError::NotFound(_)));
+        let e = FsError::from_io(io::Error::from_raw_os_error(libc::EEXIST), "a");
+        assert!(matches!(e, FsError::AlreadyExists(_)));
+        let e = FsError::from_io(io::Error::from_raw_os_error(libc::ENOTEMPTY), "d");
+        assert!(matches!(e, FsError::NotEmpty(_)));
+        let e = FsError::from_io(io::Error::from_raw_os_error(libc::ENOSPC), "f");
+        assert!(matches!(e, FsError::NoSpace(_)));
+        let e = FsError::from_io(io::Error::from_raw_os_error(libc::EROFS), "f");
+        assert_eq!(e.to_errno(), libc::EROFS);
+        let e = FsError::from_io(io::Error::from_raw_os_error(libc::EHOSTDOWN), "f");
+        assert!(matches!(e, FsError::Offline(_)) && e.is_transient());
+        assert_eq!(e.to_errno(), libc::EIO);
+    }
+
+    #[test]
+    fn from_status_classifies_http_failures() {
+        let e = FsError::from_status(404, "GET a");
+        assert!(matches!(e, FsError::NotFound(ref m) if m == "GET a"));
+        for (status, errno) in [(403, libc::EACCES), (503, libc::EIO), (507, libc::ENOSPC)] {
+            assert_eq!(FsError::from_status(status, "x").to_errno(), errno);
+        }
+        assert!(FsError::from_status(503, "x").is_transient());
+        let e = FsError::from_status(418, "PUT b");
+        assert_eq!(e.to_string(), "Storage error: PUT b: HTTP 418");
+    }
+
+    #[test]
+    fn to_errno_maps_variants() {
+        assert_eq!(FsError::PermissionDenied("x".into()).to_errno(), libc::EACCES);
+        assert_eq!(FsError::IsDirectory("x".into()).to_errno(), libc::EISDIR);
+        assert_eq!(FsError::NotDirectory("x".into()).to_errno(), libc::ENOTDIR);
+        assert_eq!(FsError::Storage("x".into()).to_errno(), libc::EIO);
+        assert_eq!(FsError::QuotaExceeded("x".into()).to_errno(), libc::EDQUOT);
+        let raw = FsError::Io(io::Error::from_raw_os_error(libc::EROFS));
+        assert_eq!(raw.to_errno(), libc::EROFS);
+    }
+
+    #[test]
+    fn classifies_outages_and_retries() {
+        let io = |errno| FsError::Io(io::Error::from_raw_os_error(errno));
+        assert!(io(libc::ECONNRESET).is_transient());
+        assert!(FsError::TimedOut("x".into()).is_transient());
+        assert!(!io(libc::EAGAIN).is_transient());
+        assert!(io(libc::EAGAIN).is_retryable());
+        assert!(io(libc::ETIMEDOUT).is_retryable());
+        for answered in [
+            FsError::NotFound("x".into()),
+            FsError::NoSpace("x".into()),
+            io(libc::EROFS),
+        ] {
+            assert!(
+                !answered.is_transient() && !answered.is_retryable(),
+                "{answered}"
+            );
+        }
+        // Context doesn't hide the cause.
+        let wrapped = io(libc::EHOSTUNREACH).with_context(ErrorContext::new("read"));
+        assert!(wrapped.is_transient());
+    }
+
+    #[test]
+    fn context_wraps_once_and_keeps_the_errno() {
+        let failed: Result<()> = Err(FsError::from_io(
+            io::Error::from_raw_os_error(libc::EROFS),
+            "docs/a.txt",
+        ));
+        let e = failed
+            .context(|| {
+                ErrorContext::new("write")
+                    .tier(TierId::Slow)
+                    .backend("hdd")
+                    .path("docs/a.txt")
+            })
+            .context(|| ErrorContext::new("flush"))
+            .unwrap_err();
+        assert_eq!(e.to_errno(), libc::EROFS);
+        assert_eq!(e.context().unwrap().op, "write");
+        assert!(matches!(e.root(), FsError::ReadOnly(_)));
+        assert!(
+            e.to_string()
+                .starts_with("write docs/a.txt on slow/hdd: Read-only"),
+            "{e}"
+        );
+    }
+}
@@ -0,0 +1,389 @@
+//! C API for driving rhss in-process, declared in `include/rhss.h`.
+//!
+//! ```c
+//! rhss_t *fs = rhss_init("/etc/rhss/rhss.toml");
+//! if (!fs) { fprintf(stderr, "%s\n", rhss_last_error()); return 1; }
+//! rhss_write(fs, "/a.bin", 0, buf, len);
+//! rhss_migrate(fs, "/a.bin", RHSS_TIER_SLOW);
+//! rhss_shutdown(fs);
+//! ```
+//!
+//! Functions returning `int` or `int64_t` give a negative errno on failure
+//! and leave a message for `rhss_last_error` on the calling thread. Paths
+//! are logical (`/docs/a.txt`), as seen under the mount. A handle may be
+//! shared between threads; `rhss_shutdown` must be the last call on it.
+//! No storage lock is taken: the caller must not run `rhss mount` on the
+//! same storage alongside it.
+//!
+//! Regenerate the header after changing this file:
+//! `cbindgen --config cbindgen.toml --output include/rhss.h src/ffi.rs`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::config::RhssConfig;
+use crate::error::{FsError, Result};
+use crate::filter::{PathFilter, RuleKind};
+use crate::fuse::FuseConfig;
+use crate::index::TierId;
+use crate::namespace::Namespace;
+use crate::quota::Quotas;
+use crate::scan;
+use crate::trash::Trash;
+use crate::{Rhss, RhssBuilder};
+
+pub const RHSS_TIER_FAST: c_int = 0;
+pub const RHSS_TIER_SLOW: c_int = 1;
+pub const RHSS_TIER_ARCHIVE: c_int = 2;
+/// Tier reported for paths the index doesn't track (directories).
+pub const RHSS_TIER_NONE: c_int = -1;
+
+/// An initialized rhss instance. Opaque to C.
+pub struct RhssHandle {
+    rhss: Rhss,
+    ns: Namespace,
+    mount: Mutex<Option<fuser::BackgroundSession>>,
+}
+
+/// Attributes filled in by `rhss_stat`. Times are seconds since the epoch.
+#[repr(C)]
+pub struct RhssStat {
+    pub size: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub is_dir: bool,
+    /// One of `RHSS_TIER_*`.
+    pub tier: c_int,
+    pub atime: i64,
+    pub mtime: i64,
+    pub ctime: i64,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg).unwrap_or_else(|_| c"error message had a NUL".into());
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Run `f`, turning errors and panics into `-errno`.
+fn call<T: Into<i64>>(f: impl FnOnce() -> Result<T>) -> i64 {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(v)) => v.into(),
+        Ok(Err(e)) => {
+            let errno = e.to_errno();
+            set_last_error(e.to_string());
+            -i64::from(errno)
+        }
+        Err(_) => {
+            set_last_error("internal panic".into());
+            -i64::from(libc::EIO)
+        }
+    }
+}
+
+/// # Safety
+/// `s` is NULL or a NUL-terminated string.
+unsafe fn path_arg(s: *const c_char) -> Result<PathBuf> {
+    if s.is_null() {
+        return Err(FsError::InvalidOperation("NULL path".into()));
+    }
+    let s = CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| FsError::InvalidOperation("path is not UTF-8".into()))?;
+    Ok(PathBuf::from(s))
+}
+
+/// # Safety
+/// `h` is NULL or a live handle from `rhss_init`.
+unsafe fn handle<'a>(h: *const RhssHandle) -> Result<&'a RhssHandle> {
+    h.as_ref()
+        .ok_or_else(|| FsError::InvalidOperation("NULL rhss handle".into()))
+}
+
+fn tier_arg(tier: c_int) -> Result<TierId> {
+    match tier {
+        RHSS_TIER_FAST => Ok(TierId::Fast),
+        RHSS_TIER_SLOW => Ok(TierId::Slow),
+        RHSS_TIER_ARCHIVE => Ok(TierId::Archive),
+        other => Err(FsError::InvalidOperation(format!("unknown tier {other}"))),
+    }
+}
+
+fn epoch_secs(t: SystemTime) -> i64 {
+    match t.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// What `rhss serve-*` does before serving, minus the storage lock:
+/// managed dirs, then the library setup with the config's trash, quotas
+/// and lookup filter.
+fn init(config: &Path) -> Result<RhssHandle> {
+    let cfg = RhssConfig::load(config)?;
+    if let Some(parent) = cfg.db.parent() {
+        if !parent.as_os_str().is_empty() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+    }
+    let roots = cfg
+        .tier
+        .fast
+        .iter()
+        .chain(cfg.tier.slow.iter())
+        .filter(|b| b.memory_size().is_none())
+        .flat_map(|b| std::iter::once(&b.root).chain(&b.replicas))
+        .map(|p| p.as_path());
+    scan::ensure_managed_dirs(roots)?;
+
+    let trash = cfg.trash.enabled.then(|| Trash::new(cfg.trash.retention()));
+    let quota_rules = cfg.quota_rules()?;
+    let quotas = if quota_rules.is_empty() {
+        None
+    } else {
+        Some(Quotas::new(quota_rules))
+    };
+    let tenants = cfg.tenants()?;
+    let filter = match &cfg.fuse.ignore_lookup {
+        Some(globs) => PathFilter::reserved().with_patterns(RuleKind::Exclude, globs, "config")?,
+        None => PathFilter::with_defaults(),
+    }
+    .with_patterns(RuleKind::Include, &cfg.fuse.include, "config")?;
+
+    let fuse = FuseConfig::default()
+        .with_trash(trash.clone())
+        .with_quotas(quotas.clone())
+        .with_tenants(tenants.clone())
+        .with_lookup_filter(filter.clone());
+    let rhss = RhssBuilder::from_config(&cfg)?
+        .with_fuse_config(fuse)
+        .build()?;
+    let ns = Namespace::new(
+        Arc::clone(rhss.router()),
+        Arc::clone(rhss.index()),
+        rhss.policy().clone(),
+        Arc::clone(rhss.open_tracker()),
+    )
+    .with_filter(filter)
+    .with_trash(trash)
+    .with_quotas(quotas)
+    .with_tenants(tenants);
+    Ok(RhssHandle {
+        rhss,
+        ns,
+        mount: Mutex::new(None),
+    })
+}
+
+/// Open the storage described by the TOML config at `config_path`: index
+/// the backends and start the tierer. Returns
+/// NULL on failure; see `rhss_last_error`.
+///
+/// # Safety
+/// `config_path` is NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rhss_init(config_path: *const c_char) -> *mut RhssHandle {
+    let res = catch_unwind(|| init(&path_arg(config_path)?));
+    match res {
+        Ok(Ok(h)) => Box::into_raw(Box::new(h)),
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            std::ptr::null_mut()
+        }
+        Err(_) => {
+            set_last_error("internal panic".into());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Mount the namespace at `mount_point`, served from background threads.
+/// `-EBUSY` if this handle is already mounted.
+///
+/// # Safety
+/// `h` is a live handle; `mount_point` is a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rhss_mount(h: *mut RhssHandle, mount_point: *const c_char) -> c_int {
+    call(|| {
+        let h = handle(h)?;
+        let mount_point = path_arg(mount_point)?;
+        let mut slot = h.mount.lock().unwrap();
+        if slot.is_some() {
+            return Err(FsError::Io(std::io::Error::from_raw_os_error(libc::EBUSY)));
+        }
+        *slot = Some(h.rhss.mount(&mount_point)?);
+        Ok(0)
+    }) as c_int
+}
+
+/// Unmount what `rhss_mount` mounted. `-EINVAL` if nothing is mounted.
+///
+/// # Safety
+/// `h` is a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn rhss_unmount(h: *mut RhssHandle) -> c_int {
+    call(|| {
+        let h = handle(h)?;
+        let session = h.mount.lock().unwrap().take();
+        match session {
+            Some(s) => {
+                drop(s);
+                Ok(0)
+            }
+            None => Err(FsError::InvalidOperation("not mounted".into())),
+        }
+    }) as c_int
+}
+
+/// Read up to `len` bytes of file `path` at `offset` into `buf`. Returns
+/// the byte count, 0 at end of file.
+///
+/// # Safety
+/// `h` is a live handle, `path` a NUL-terminated string and `buf` valid
+/// for `len` bytes of writes.
+#[no_mangle]
+pub unsafe extern "C" fn rhss_read(
+    h: *mut RhssHandle,
+    path: *const c_char,
+    offset: u64,
+    buf: *mut u8,
+    len: usize,
+) -> i64 {
+    call(|| {
+        let h = handle(h)?;
+        let path = path_arg(path)?;
+        if buf.is_null() && len > 0 {
+            return Err(FsError::InvalidOperation("NULL buffer".into()));
+        }
+        let data =
+            h.ns.read(&path, offset, len.min(u32::MAX as usize) as u32)?;
+        if !data.is_empty() {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+        }
+        Ok(data.len() as i64)
+    })
+}
+
+/// Write `len` bytes from `buf` into file `path` at `offset`, creating
+/// the file (placed by the tiering policy) if it doesn't exist. Returns
+/// the byte count.
+///
+/// # Safety
+/// `h` is a live handle, `path` a NUL-terminated string and `buf` valid
+/// for `len` bytes of reads.
+#[no_mangle]
+pub unsafe extern "C" fn rhss_write(
+    h: *mut RhssHandle,
+    path: *const c_char,
+    offset: u64,
+    buf: *const u8,
+    len: usize,
+) -> i64 {
+    call(|| {
+        let h = handle(h)?;
+        let path = path_arg(path)?;
+        let data: &[u8] = if len == 0 {
+            &[]
+        } else if buf.is_null() {
+            return Err(FsError::InvalidOperation("NULL buffer".into()));
+        } else {
+            std::slice::from_raw_parts(buf, len)
+        };
+        match h.ns.create_file(&path) {
+            Ok(()) | Err(FsError::AlreadyExists(_)) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(i64::from(h.ns.write(&path, offset, data)?))
+    })
+}
+
+/// Fill `out` with the attributes and current tier of `path`.
+///
+/// # Safety
+/// `h` is a live handle, `path` a NUL-terminated string and `out` valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rhss_stat(
+    h: *mut RhssHandle,
+    path: *const c_char,
+    out: *mut RhssStat,
+) -> c_int {
+    call(|| {
+        let h = handle(h)?;
+        let path = path_arg(path)?;
+        if out.is_null() {
+            return Err(FsError::InvalidOperation("NULL stat buffer".into()));
+        }
+        let meta = h.ns.stat(&path)?;
+        let tier = match h.ns.tier_of(&path)? {
+            Some(TierId::Fast) => RHSS_TIER_FAST,
+            Some(TierId::Slow) => RHSS_TIER_SLOW,
+            Some(TierId::Archive) => RHSS_TIER_ARCHIVE,
+            None => RHSS_TIER_NONE,
+        };
+        out.write(RhssStat {
+            size: meta.size,
+            mode: meta.mode,
+            uid: meta.uid,
+            gid: meta.gid,
+            is_dir: meta.is_dir,
+            tier,
+            atime: epoch_secs(meta.atime),
+            mtime: epoch_secs(meta.mtime),
+            ctime: epoch_secs(meta.ctime),
+        });
+        Ok(0)
+    }) as c_int
+}
+
+/// Move file `path` to `tier` (`RHSS_TIER_*`) now. Returns 1 if it moved,
+/// 0 if it was skipped: already there, open or pinned.
+///
+/// # Safety
+/// `h` is a live handle and `path` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rhss_migrate(
+    h: *mut RhssHandle,
+    path: *const c_char,
+    tier: c_int,
+) -> c_int {
+    call(|| {
+        let h = handle(h)?;
+        let path = path_arg(path)?;
+        Ok(i64::from(h.ns.migrate(&path, tier_arg(tier)?)?))
+    }) as c_int
+}
+
+/// Unmount if mounted, stop the tierer and free `h`. NULL is ignored.
+///
+/// # Safety
+/// `h` is NULL or a live handle, not used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rhss_shutdown(h: *mut RhssHandle) {
+    if h.is_null() {
+        return;
+    }
+    let h = Box::from_raw(h);
+    let _ = catch_unwind(AssertUnwindSafe(move || {
+        drop(h.mount.lock().unwrap().take());
+        h.rhss.adapter().stop();
+    }));
+}
+
+/// Message for the last failed call on this thread, or NULL. Valid until
+/// the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn rhss_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| match &*e.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
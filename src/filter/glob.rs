@@ -0,0 +1,226 @@
+//! Shell-style glob patterns for `PathFilter` rules.
+//!
+//! Supported syntax: `*` (any run within one path component), `?` (one
+//! character), `[abc]` / `[a-z]` / `[!a-z]` classes, `**` (any run across
+//! components; `**/` also matches zero components) and `\` to escape the
+//! next character. A pattern without `/` matches the file name only
+//! (`._*`); one with `/` matches the whole mount-relative path
+//! (`build/**/*.o`), so a leading `/` anchors a name at the root.
+
+use std::fmt;
+use std::path::Path;
+
+use crate::error::{FsError, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Lit(char),
+    One,
+    Star,
+    DoubleStar,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Glob {
+    pattern: String,
+    toks: Vec<Tok>,
+    whole_path: bool,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let bad = |why: &str| FsError::InvalidOperation(format!("glob {pattern:?}: {why}"));
+        if pattern.is_empty() {
+            return Err(bad("empty pattern"));
+        }
+        let trimmed = pattern.trim_start_matches('/');
+        let mut toks = Vec::new();
+        let mut chars = trimmed.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => toks.push(Tok::Lit(chars.next().ok_or_else(|| bad("trailing '\\'"))?)),
+                '?' => toks.push(Tok::One),
+                '*' => {
+                    if chars.peek() == Some(&'*') {
+                        chars.next();
+                        toks.push(Tok::DoubleStar);
+                    } else {
+                        toks.push(Tok::Star);
+                    }
+                }
+                '[' => {
+                    let negated = matches!(chars.peek(), Some('!') | Some('^'));
+                    if negated {
+                        chars.next();
+                    }
+                    let mut ranges = Vec::new();
+                    let mut first = true;
+                    loop {
+                        let c = chars.next().ok_or_else(|| bad("unclosed '['"))?;
+                        // `]` right after `[` / `[!` is a literal member.
+                        if c == ']' && !first {
+                            break;
+                        }
+                        first = false;
+                        let lo = if c == '\\' {
+                            chars.next().ok_or_else(|| bad("unclosed '['"))?
+                        } else {
+                            c
+                        };
+                        let mut lookahead = chars.clone();
+                        if lookahead.next() == Some('-')
+                            && !matches!(lookahead.peek(), Some(']') | None)
+                        {
+                            chars.next();
+                            let hi = chars.next().ok_or_else(|| bad("unclosed '['"))?;
+                            if hi < lo {
+                                return Err(bad("reversed range in class"));
+                            }
+                            ranges.push((lo, hi));
+                        } else {
+                            ranges.push((lo, lo));
+                        }
+                    }
+                    toks.push(Tok::Class { negated, ranges });
+                }
+                c => toks.push(Tok::Lit(c)),
+            }
+        }
+        Ok(Self {
+            pattern: pattern.to_string(),
+            whole_path: pattern.contains('/'),
+            toks,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Match a logical path (`/a/b/c`, leading `/` optional).
+    pub fn matches_path(&self, path: &Path) -> bool {
+        let Some(s) = path.to_str() else {
+            return false;
+        };
+        let rel = s.trim_start_matches('/');
+        let subject = if self.whole_path {
+            rel
+        } else {
+            rel.rsplit('/').next().unwrap_or(rel)
+        };
+        let subject: Vec<char> = subject.chars().collect();
+        match_from(&self.toks, &subject)
+    }
+}
+
+impl fmt::Debug for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Glob({:?})", self.pattern)
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// Compile a list of patterns, failing on the first bad one.
+pub fn parse_globs<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<Glob>> {
+    patterns.iter().map(|p| Glob::new(p.as_ref())).collect()
+}
+
+fn match_from(toks: &[Tok], s: &[char]) -> bool {
+    let Some((tok, rest)) = toks.split_first() else {
+        return s.is_empty();
+    };
+    match tok {
+        Tok::Lit(c) => s.first() == Some(c) && match_from(rest, &s[1..]),
+        Tok::One => matches!(s.first(), Some(c) if *c != '/') && match_from(rest, &s[1..]),
+        Tok::Class { negated, ranges } => match s.first() {
+            Some(&c) if c != '/' => {
+                let hit = ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+                hit != *negated && match_from(rest, &s[1..])
+            }
+            _ => false,
+        },
+        Tok::Star => {
+            for i in 0..=s.len() {
+                if match_from(rest, &s[i..]) {
+                    return true;
+                }
+                if i < s.len() && s[i] == '/' {
+                    return false;
+                }
+            }
+            false
+        }
+        Tok::DoubleStar => {
+            // `**/x` also matches `x` at the current level.
+            if let Some((Tok::Lit('/'), after)) = rest.split_first() {
+                if match_from(after, s) {
+                    return true;
+                }
+            }
+            (0..=s.len()).any(|i| match_from(rest, &s[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(pat: &str, path: &str) -> bool {
+        Glob::new(pat).unwrap().matches_path(Path::new(path))
+    }
+
+    #[test]
+    fn name_patterns_match_basename() {
+        assert!(m(".DS_Store", "/a/b/.DS_Store"));
+        assert!(m("._*", "/photos/._IMG_0001.JPG"));
+        assert!(!m("._*", "/photos/IMG_0001.JPG"));
+        assert!(m("*.tmp", "/x/y.tmp"));
+        assert!(m("?.txt", "/d/a.txt"));
+        assert!(!m("?.txt", "/d/ab.txt"));
+        // The old prefix rule ignored every one-character name; a glob only
+        // does so when asked.
+        assert!(!m("._*", "/a"));
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert!(m("[ab].log", "/b.log"));
+        assert!(!m("[!ab].log", "/b.log"));
+        assert!(m("file[0-9]", "/file7"));
+        assert!(!m("file[0-9]", "/filex"));
+        assert!(m(r"\*", "/*"));
+        assert!(!m(r"\*", "/x"));
+        assert!(m("[]]", "/]"));
+    }
+
+    #[test]
+    fn path_patterns_match_whole_path() {
+        assert!(m("build/*.o", "/build/a.o"));
+        assert!(!m("build/*.o", "/build/sub/a.o"));
+        assert!(m("build/**/*.o", "/build/sub/deep/a.o"));
+        assert!(m("build/**/*.o", "/build/a.o"));
+        assert!(m("**/node_modules", "/node_modules"));
+        assert!(m("/cache/*", "/cache/x"));
+        assert!(!m("cache/*", "/other/cache/x"));
+        assert!(m("/top", "/top"));
+        assert!(!m("/top", "/sub/top"));
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        assert!(Glob::new("").is_err());
+        assert!(Glob::new("[abc").is_err());
+        assert!(Glob::new("x\\").is_err());
+        assert!(Glob::new("[z-a]").is_err());
+    }
+}
@@ -0,0 +1,243 @@
+//! Path filtering for the mount view.
+//!
+//! A `PathFilter` is a set of glob rules, each carrying a human-readable
+//! reason that shows up in debug logs when it fires ("why can't I see
+//! `foo`?"). Evaluation:
+//!
+//! 1. `reserved` rules always hide the path — rhss's own bookkeeping (the
+//!    zstd staging dir, the trash) must never leak into the mount or the
+//!    index.
+//! 2. Otherwise the path is excluded iff some `exclude` rule matches and no
+//!    `include` rule does. Includes carve exceptions out of broad excludes
+//!    (`exclude ._*`, `include ._keep`); rule order doesn't matter.
+//!
+//! The FUSE adapter keeps one filter for lookup and one for listing; `scan`
+//! uses `PathFilter::reserved()` so staging files are never indexed.
+
+use std::fmt;
+use std::path::Path;
+
+use tracing::debug;
+
+use crate::error::Result;
+
+mod glob;
+
+pub use glob::{parse_globs, Glob};
+
+/// Default excludes: Finder metadata that macOS sprays over every directory
+/// it touches.
+pub const DEFAULT_EXCLUDES: &[(&str, &str)] = &[
+    (".DS_Store", "macOS Finder metadata"),
+    ("._*", "macOS AppleDouble resource fork"),
+];
+
+/// Backend paths rhss writes for itself. Keep in sync with
+/// `tierer::compress`, `trash::TRASH_DIR`, `volume::VOLUMES_DIR`,
+/// `lock::OWNER_MARKER`, `preheat::PREHEAT_DIR` and
+/// `backend::sharded::SHARD_DIR`.
+const RESERVED: &[(&str, &str)] = &[
+    ("/.rhss_decompressed", "rhss decompression staging area"),
+    ("/.rhss_decompressed/**", "rhss decompression staging area"),
+    ("/.rhss-trash", "rhss trash"),
+    ("/.rhss-trash/**", "rhss trash"),
+    ("/.rhss-health", "rhss health check canary"),
+    ("/.rhss-owner", "rhss host marker"),
+    ("/.rhss-owner.tmp", "rhss host marker"),
+    ("/.rhss-preheat", "rhss preheated copies"),
+    ("/.rhss-preheat/**", "rhss preheated copies"),
+    ("/.rhss-volumes", "rhss volumes"),
+    ("/.rhss-volumes/**", "rhss volumes"),
+    (".rhss-shards", "rhss directory shards"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    Include,
+    Exclude,
+    Reserved,
+}
+
+impl fmt::Display for RuleKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RuleKind::Include => "include",
+            RuleKind::Exclude => "exclude",
+            RuleKind::Reserved => "reserved",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub kind: RuleKind,
+    pub glob: Glob,
+    pub reason: String,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?} ({})",
+            self.kind,
+            self.glob.as_str(),
+            self.reason
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    rules: Vec<Rule>,
+}
+
+impl PathFilter {
+    /// No rules at all — everything passes, including reserved paths.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only the reserved rules.
+    pub fn reserved() -> Self {
+        RESERVED.iter().fold(Self::new(), |f, (pat, why)| {
+            f.with_rule(
+                RuleKind::Reserved,
+                Glob::new(pat).expect("reserved glob"),
+                *why,
+            )
+        })
+    }
+
+    /// Reserved rules plus `DEFAULT_EXCLUDES`.
+    pub fn with_defaults() -> Self {
+        DEFAULT_EXCLUDES
+            .iter()
+            .fold(Self::reserved(), |f, (pat, why)| {
+                f.with_exclude(Glob::new(pat).expect("default glob"), *why)
+            })
+    }
+
+    pub fn with_include(self, glob: Glob, reason: impl Into<String>) -> Self {
+        self.with_rule(RuleKind::Include, glob, reason)
+    }
+
+    pub fn with_exclude(self, glob: Glob, reason: impl Into<String>) -> Self {
+        self.with_rule(RuleKind::Exclude, glob, reason)
+    }
+
+    pub fn with_rule(mut self, kind: RuleKind, glob: Glob, reason: impl Into<String>) -> Self {
+        self.rules.push(Rule {
+            kind,
+            glob,
+            reason: reason.into(),
+        });
+        self
+    }
+
+    /// Compile `patterns` into rules of `kind`, all sharing `reason`.
+    pub fn with_patterns<S: AsRef<str>>(
+        self,
+        kind: RuleKind,
+        patterns: &[S],
+        reason: &str,
+    ) -> Result<Self> {
+        Ok(parse_globs(patterns)?
+            .into_iter()
+            .fold(self, |f, g| f.with_rule(kind, g, reason)))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    fn first(&self, kind: RuleKind, path: &Path) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|r| r.kind == kind && r.glob.matches_path(path))
+    }
+
+    /// The rule that hides `path`, if any.
+    pub fn excluded_by(&self, path: &Path) -> Option<&Rule> {
+        if let Some(r) = self.first(RuleKind::Reserved, path) {
+            return Some(r);
+        }
+        let ex = self.first(RuleKind::Exclude, path)?;
+        match self.first(RuleKind::Include, path) {
+            Some(inc) => {
+                debug!("{} kept by {} despite {}", path.display(), inc, ex);
+                None
+            }
+            None => Some(ex),
+        }
+    }
+
+    /// `excluded_by(path).is_some()`, logging the deciding rule. `what`
+    /// names the caller ("lookup", "readdir") for the log line.
+    pub fn excludes(&self, what: &str, path: &Path) -> bool {
+        match self.excluded_by(path) {
+            Some(rule) => {
+                debug!("{what}: {} filtered by {}", path.display(), rule);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn g(p: &str) -> Glob {
+        Glob::new(p).unwrap()
+    }
+
+    #[test]
+    fn defaults_hide_finder_junk_but_not_short_names() {
+        let f = PathFilter::with_defaults();
+        assert!(f.excluded_by(Path::new("/a/.DS_Store")).is_some());
+        assert!(f.excluded_by(Path::new("/._photo.jpg")).is_some());
+        // Single-character names are ordinary files.
+        for name in ["/a", "/R", "/dir/x", "/_"] {
+            assert!(f.excluded_by(Path::new(name)).is_none(), "{name}");
+        }
+    }
+
+    #[test]
+    fn include_overrides_exclude_regardless_of_order() {
+        let f = PathFilter::new()
+            .with_include(g("._keep"), "needed by tool")
+            .with_exclude(g("._*"), "AppleDouble");
+        assert!(f.excluded_by(Path::new("/._keep")).is_none());
+        let rule = f.excluded_by(Path::new("/._other")).unwrap();
+        assert_eq!(rule.kind, RuleKind::Exclude);
+        assert_eq!(rule.reason, "AppleDouble");
+    }
+
+    #[test]
+    fn reserved_cannot_be_included_back() {
+        let f = PathFilter::reserved().with_include(g("**"), "everything");
+        let rule = f.excluded_by(Path::new("/.rhss_decompressed")).unwrap();
+        assert_eq!(rule.kind, RuleKind::Reserved);
+        assert!(f
+            .excluded_by(Path::new("/.rhss_decompressed/a/b.bin"))
+            .is_some());
+        // Only the backend-root staging dir is reserved.
+        assert!(f
+            .excluded_by(Path::new("/sub/.rhss_decompressed"))
+            .is_none());
+    }
+
+    #[test]
+    fn with_patterns_reports_bad_globs() {
+        assert!(PathFilter::new()
+            .with_patterns(RuleKind::Exclude, &["ok", "[bad"], "user")
+            .is_err());
+        let f = PathFilter::new()
+            .with_patterns(RuleKind::Exclude, &["*.tmp", "cache/**"], "user")
+            .unwrap();
+        assert_eq!(f.rules().len(), 2);
+        assert!(f.excludes("test", Path::new("/cache/x/y")));
+    }
+}
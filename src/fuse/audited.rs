@@ -0,0 +1,102 @@
+//! Reply wrappers that record the outcome of a mutating op in the audit
+//! trail (`crate::audit`) as the reply goes out. They mirror the `fuser`
+//! reply methods the `do_*` bodies use, so those bodies don't change.
+
+use std::time::Duration;
+
+use fuser::{FileAttr, ReplyAttr, ReplyCreate, ReplyEmpty, ReplyEntry, ReplyWrite};
+use libc::c_int;
+
+use crate::audit::AuditSpan;
+use crate::error::FsError;
+
+/// `fuser` replies all have an inherent `error`; this lets `Audited` use it.
+pub(super) trait ErrorReply {
+    fn send_error(self, err: c_int);
+}
+
+macro_rules! error_reply {
+    ($($t:ty),*) => {$(
+        impl ErrorReply for $t {
+            fn send_error(self, err: c_int) {
+                self.error(err)
+            }
+        }
+    )*};
+}
+
+error_reply!(ReplyAttr, ReplyCreate, ReplyEmpty, ReplyEntry, ReplyWrite);
+
+/// A reply plus the audit span it closes. `span` is `None` when auditing
+/// is off.
+pub(super) struct Audited<R> {
+    reply: R,
+    span: Option<AuditSpan>,
+}
+
+impl<R: ErrorReply> Audited<R> {
+    pub(super) fn new(reply: R, span: Option<AuditSpan>) -> Self {
+        Self { reply, span }
+    }
+
+    fn finish(&mut self, errno: Option<c_int>) {
+        if let Some(span) = self.span.take() {
+            span.finish(errno);
+        }
+    }
+
+    pub(super) fn error(mut self, err: c_int) {
+        self.finish(Some(err));
+        self.reply.send_error(err);
+    }
+
+    /// `error`, keeping the failure's context in the audit record.
+    pub(super) fn fail(mut self, err: &FsError) {
+        if let Some(span) = self.span.take() {
+            span.fail(err);
+        }
+        self.reply.send_error(err.to_errno());
+    }
+}
+
+impl Audited<ReplyEmpty> {
+    pub(super) fn ok(mut self) {
+        self.finish(None);
+        self.reply.ok();
+    }
+}
+
+impl Audited<ReplyEntry> {
+    pub(super) fn entry(mut self, ttl: &Duration, attr: &FileAttr, generation: u64) {
+        self.finish(None);
+        self.reply.entry(ttl, attr, generation);
+    }
+}
+
+impl Audited<ReplyCreate> {
+    pub(super) fn created(
+        mut self,
+        ttl: &Duration,
+        attr: &FileAttr,
+        generation: u64,
+        fh: u64,
+        flags: u32,
+    ) {
+        self.finish(None);
+        self.reply.created(ttl, attr, generation, fh, flags);
+    }
+}
+
+impl Audited<ReplyAttr> {
+    pub(super) fn attr(mut self, ttl: &Duration, attr: &FileAttr) {
+        self.finish(None);
+        self.reply.attr(ttl, attr);
+    }
+}
+
+impl Audited<ReplyWrite> {
+    pub(super) fn written(mut self, size: u32) {
+        self.finish(None);
+        self.reply.written(size);
+    }
+}
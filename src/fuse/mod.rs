@@ -5,44 +5,126 @@
 //! disk. Background tierer (P2) hasn't landed yet; new files always go to
 //! Fast for now, with no migration.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, SystemTime};
 
 use fuser::{
-    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
-    ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
+    fuse_forget_one, FileAttr, FileType, Filesystem, MountOption, Notifier, ReplyAttr, ReplyCreate,
+    ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyLseek, ReplyOpen,
+    ReplyStatfs, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
 };
-use libc::{EEXIST, EIO, ENOENT, ENOSYS};
-use parking_lot::Mutex;
-use tracing::{debug, error, info, warn};
+use libc::{EBADF, EEXIST, EIO, ENOENT, ENOSYS};
+use lru::LruCache;
+use parking_lot::{Mutex, RwLock};
+use tracing::{debug, debug_span, error, field, info, warn, Span};
 
+use crate::access::trace::{AccessTrace, TraceOp};
 use crate::access::AccessTracker;
-use crate::backend::{Backend, FileMetadata as BackendMeta};
-use crate::error::FsError;
-use crate::index::{FileRow, FileState, Location, PathIndex};
+use crate::audit::{AuditLog, AuditSpan};
+use crate::backend::{copy_between, Backend, FileMetadata as BackendMeta};
+use crate::error::{ErrorContext, FsError, Result, ResultExt};
+use crate::filter::PathFilter;
+use crate::index::{FileRow, FileState, Location, PathIndex, TierId};
 use crate::policy::TieringPolicy;
+use crate::quota::{Quotas, Reservation};
+use crate::tenant::Tenants;
 use crate::tier::TierRouter;
 use crate::tierer::{OpenFileTracker, TiererHandle};
+use crate::trash::Trash;
+
+mod audited;
+mod mountpoint;
+mod ordering;
+mod ownership;
+mod pool;
+mod priority;
+
+use audited::Audited;
+pub use mountpoint::{is_mounted, unmount};
+use ordering::NamespaceOrder;
+pub use ownership::{parse_mode, IdMap, IdRange, IdTranslation, Modes};
+use pool::WorkerPool;
+pub use pool::DEFAULT_WORKERS;
+use priority::{PidPriorities, Priority};
+pub use priority::{PRIORITY_ENV, RHSS_IOC_BACKGROUND};
+
+/// Source shown for the mount in `mount` and `df` unless configured.
+pub const DEFAULT_FSNAME: &str = "rhss";
+
+/// A `fsname` or `subtype`: non-empty, and nothing that would split or
+/// end a mount option.
+pub fn parse_mount_name(s: &str) -> Result<String> {
+    let splits = |c: char| c == ',' || c.is_whitespace() || c.is_control();
+    if s.is_empty() || s.chars().any(splits) {
+        return Err(FsError::InvalidOperation(format!(
+            "bad mount name {s:?}: must be non-empty, without commas or spaces"
+        )));
+    }
+    Ok(s.to_string())
+}
 
-const TTL: Duration = Duration::from_secs(1);
+/// Default kernel cache lifetime for entries and attrs.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
 pub struct FuseConfig {
-    ignore_names: HashSet<String>,
-    ignore_prefixes: Vec<String>,
+    lookup_filter: PathFilter,
+    list_filter: PathFilter,
+    allow_other: bool,
+    allow_root: bool,
+    read_only: bool,
+    noexec: bool,
+    fsname: String,
+    subtype: Option<String>,
+    volname: Option<String>,
+    custom_options: Vec<String>,
+    workers: usize,
+    max_inodes: usize,
+    default_permissions: bool,
+    id_map: IdMap,
+    ids: IdTranslation,
+    modes: Modes,
+    attr_ttl: Duration,
+    entry_ttl: Duration,
+    trash: Option<Arc<Trash>>,
+    quotas: Option<Arc<Quotas>>,
+    tenants: Option<Arc<Tenants>>,
+    audit: Option<Arc<AuditLog>>,
+    access_trace: Option<Arc<AccessTrace>>,
 }
 
 impl Default for FuseConfig {
     fn default() -> Self {
-        let mut ignore_names = HashSet::new();
-        ignore_names.insert(".DS_Store".to_string());
         Self {
-            ignore_names,
-            ignore_prefixes: vec!["._".to_string()],
+            lookup_filter: PathFilter::with_defaults(),
+            list_filter: PathFilter::with_defaults(),
+            // Linux has always mounted with allow_other (D20); macFUSE
+            // needs an explicit opt-in.
+            allow_other: cfg!(target_os = "linux"),
+            allow_root: false,
+            read_only: false,
+            noexec: false,
+            fsname: DEFAULT_FSNAME.to_string(),
+            subtype: None,
+            volname: None,
+            custom_options: Vec::new(),
+            workers: pool::DEFAULT_WORKERS,
+            max_inodes: DEFAULT_MAX_INODES,
+            default_permissions: true,
+            id_map: IdMap::Passthrough,
+            ids: IdTranslation::default(),
+            modes: Modes::default(),
+            attr_ttl: DEFAULT_TTL,
+            entry_ttl: DEFAULT_TTL,
+            trash: None,
+            quotas: None,
+            tenants: None,
+            audit: None,
+            access_trace: None,
         }
     }
 }
@@ -52,73 +134,445 @@ impl FuseConfig {
         Self::default()
     }
 
-    pub fn should_ignore(&self, path: &Path) -> bool {
-        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
-            return false;
-        };
-        if self.ignore_names.contains(name) {
-            return true;
+    /// Let users other than the mounting user access the mount (needed for
+    /// Samba/NFS re-export). Non-root mounts need `user_allow_other` in
+    /// `/etc/fuse.conf`.
+    pub fn with_allow_other(mut self, on: bool) -> Self {
+        self.allow_other = on;
+        self
+    }
+
+    /// Like `allow_other` but limited to root. Mutually exclusive with it.
+    pub fn with_allow_root(mut self, on: bool) -> Self {
+        self.allow_root = on;
+        self
+    }
+
+    pub fn with_read_only(mut self, on: bool) -> Self {
+        self.read_only = on;
+        self
+    }
+
+    pub fn with_noexec(mut self, on: bool) -> Self {
+        self.noexec = on;
+        self
+    }
+
+    /// Source name in the mount table, so `mount` and `df` can tell rhss
+    /// mounts apart. Defaults to `rhss`.
+    pub fn with_fsname(mut self, name: Option<String>) -> Self {
+        self.fsname = name.unwrap_or_else(|| DEFAULT_FSNAME.to_string());
+        self
+    }
+
+    pub fn fsname(&self) -> &str {
+        &self.fsname
+    }
+
+    /// Filesystem type suffix: the mount shows as `fuse.<subtype>` on
+    /// Linux. Defaults to plain `fuse`.
+    pub fn with_subtype(mut self, subtype: Option<String>) -> Self {
+        self.subtype = subtype;
+        self
+    }
+
+    pub fn subtype(&self) -> Option<&str> {
+        self.subtype.as_deref()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Volume name shown in Finder (macOS). Defaults to `rhss`.
+    pub fn with_volname(mut self, name: Option<String>) -> Self {
+        self.volname = name;
+        self
+    }
+
+    /// Extra `-o` options passed through verbatim (`key` or `key=value`).
+    pub fn with_custom_options(mut self, opts: Vec<String>) -> Self {
+        self.custom_options = opts;
+        self
+    }
+
+    /// Worker threads serving FUSE ops off the session loop (D12).
+    /// Clamped to at least 1.
+    pub fn with_workers(mut self, n: usize) -> Self {
+        self.workers = n.max(1);
+        self
+    }
+
+    /// Hard cap on the inode table (see `DEFAULT_MAX_INODES`).
+    pub fn with_max_inodes(mut self, n: usize) -> Self {
+        self.max_inodes = n;
+        self
+    }
+
+    /// Let the kernel check permissions against our attrs (`default_permissions`).
+    /// When off, the kernel forwards `access()` and rhss checks the backing
+    /// file's real owner and mode itself, in `access` and `open`.
+    pub fn with_default_permissions(mut self, on: bool) -> Self {
+        self.default_permissions = on;
+        self
+    }
+
+    /// Whose credentials requests act with. With `default_permissions` off,
+    /// rhss checks `open`/`access`/`create`/`mkdir`/`unlink`/`rmdir` against
+    /// the mapped uid/gid, and a root daemon chowns new entries to them.
+    pub fn with_id_map(mut self, map: IdMap) -> Self {
+        self.id_map = map;
+        self
+    }
+
+    /// Renumber uids and gids between the mount and the storage; see
+    /// `IdTranslation`. Fixed at mount time.
+    pub fn with_id_translation(mut self, ids: IdTranslation) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Fixed permission bits and an extra umask for the mount; see
+    /// `Modes`. Fixed at mount time.
+    pub fn with_modes(mut self, modes: Modes) -> Self {
+        self.modes = modes;
+        self
+    }
+
+    /// How long the kernel may cache attributes (`getattr`/`setattr`
+    /// replies). Long TTLs are safe: rhss invalidates the kernel cache
+    /// itself when it migrates a file.
+    pub fn with_attr_ttl(mut self, ttl: Duration) -> Self {
+        self.attr_ttl = ttl;
+        self
+    }
+
+    /// Move unlinked files into the trash instead of deleting them.
+    pub fn with_trash(mut self, trash: Option<Arc<Trash>>) -> Self {
+        self.trash = trash;
+        self
+    }
+
+    /// Enforce byte quotas on writes, truncates and renames.
+    pub fn with_quotas(mut self, quotas: Option<Arc<Quotas>>) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    pub(crate) fn quotas(&self) -> Option<&Arc<Quotas>> {
+        self.quotas.as_ref()
+    }
+
+    /// Place new files under tenant directories by their tenant's rules.
+    pub fn with_tenants(mut self, tenants: Option<Arc<Tenants>>) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
+    pub(crate) fn tenants(&self) -> Option<&Arc<Tenants>> {
+        self.tenants.as_ref()
+    }
+
+    /// Record every mutating op in the audit trail.
+    pub fn with_audit(mut self, audit: Option<Arc<AuditLog>>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Record reads, writes, creates and deletes to an access trace.
+    pub fn with_access_trace(mut self, trace: Option<Arc<AccessTrace>>) -> Self {
+        self.access_trace = trace;
+        self
+    }
+
+    /// How long the kernel may cache name → inode lookups.
+    pub fn with_entry_ttl(mut self, ttl: Duration) -> Self {
+        self.entry_ttl = ttl;
+        self
+    }
+
+    /// Paths that don't exist as far as the mount is concerned: `lookup`
+    /// answers `ENOENT`, `create` answers `EEXIST`, and `readdir` skips them.
+    /// Defaults to `PathFilter::with_defaults()`.
+    pub fn with_lookup_filter(mut self, filter: PathFilter) -> Self {
+        self.lookup_filter = filter;
+        self
+    }
+
+    /// Paths hidden from `readdir` only; they can still be opened by exact
+    /// path. Defaults to `PathFilter::with_defaults()`.
+    pub fn with_list_filter(mut self, filter: PathFilter) -> Self {
+        self.list_filter = filter;
+        self
+    }
+
+    /// Copy the settings that can change under a live mount: ignore
+    /// filters and cache TTLs. Mount options, worker count and the inode
+    /// cap are fixed at mount time.
+    pub fn apply_reload(&mut self, from: &FuseConfig) {
+        self.lookup_filter = from.lookup_filter.clone();
+        self.list_filter = from.list_filter.clone();
+        self.attr_ttl = from.attr_ttl;
+        self.entry_ttl = from.entry_ttl;
+    }
+
+    /// Whether `lookup`/`create` should pretend `path` isn't there.
+    pub fn ignored_on_lookup(&self, path: &Path) -> bool {
+        self.lookup_filter.excludes("lookup", path)
+    }
+
+    /// Whether `readdir` should leave `path` out. Anything hidden from
+    /// lookup is hidden from listings too.
+    pub fn ignored_on_list(&self, path: &Path) -> bool {
+        self.list_filter.excludes("readdir", path) || self.lookup_filter.excludes("readdir", path)
+    }
+
+    fn mount_options(&self) -> Vec<MountOption> {
+        let mut opts = vec![
+            MountOption::FSName(self.fsname.clone()),
+            MountOption::AutoUnmount,
+        ];
+        if let Some(subtype) = &self.subtype {
+            opts.push(MountOption::Subtype(subtype.clone()));
+        }
+        if self.default_permissions {
+            opts.push(MountOption::DefaultPermissions);
+        }
+        if self.allow_other {
+            opts.push(MountOption::AllowOther);
+        } else if self.allow_root {
+            opts.push(MountOption::AllowRoot);
+        }
+        if self.read_only {
+            opts.push(MountOption::RO);
+        }
+        if self.noexec {
+            opts.push(MountOption::NoExec);
+        }
+        #[cfg(target_os = "macos")]
+        {
+            let volname = self.volname.as_deref().unwrap_or("rhss");
+            opts.push(MountOption::CUSTOM(format!("volname={volname}")));
+            opts.push(MountOption::CUSTOM("local".to_string()));
+            opts.push(MountOption::CUSTOM("noapplexattr".to_string()));
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if self.volname.is_some() {
+                debug!("volname is macOS-only; ignoring on Linux");
+            }
+            // D20 / D21 — Linux perf path. macFUSE doesn't support any of
+            // these; the cfg gate is essential.
+            opts.push(MountOption::CUSTOM("max_read=1048576".to_string())); // 1 MiB
+            opts.push(MountOption::CUSTOM("max_write=1048576".to_string())); // 1 MiB
+            opts.push(MountOption::CUSTOM("max_background=16".to_string()));
+            opts.push(MountOption::CUSTOM("congestion_threshold=12".to_string()));
         }
-        self.ignore_prefixes
-            .iter()
-            .any(|prefix| name.starts_with(prefix))
+        for o in &self.custom_options {
+            opts.push(MountOption::CUSTOM(o.clone()));
+        }
+        opts
     }
 }
 
-struct InodeMap {
-    path_to_ino: HashMap<PathBuf, u64>,
-    ino_to_path: HashMap<u64, PathBuf>,
+/// Default cap on remembered inodes. Past this, least-recently-used entries
+/// are dropped (unreferenced ones first) so a long-running mount that walks a
+/// huge tree doesn't grow without bound.
+pub const DEFAULT_MAX_INODES: usize = 500_000;
+
+/// How far from the LRU end to look for an entry the kernel no longer
+/// references before falling back to evicting a referenced one.
+const EVICT_SCAN: usize = 64;
+
+struct InodeEntry {
+    path: PathBuf,
+    /// Kernel lookup count: +1 per `entry`/`created` reply, −n per `forget`.
+    /// Entries handed out by `readdir` only stay at 0.
+    nlookup: u64,
+}
+
+/// The inode ↔ path table behind the mount, bounded LRU-style by
+/// `max_inodes`. Public for `benches/`.
+pub struct InodeMap {
+    /// Ordered so a directory's descendants sit right after it; see
+    /// `rename`.
+    path_to_ino: BTreeMap<PathBuf, u64>,
+    /// ino → entry, in recency order (most recently used at the front).
+    entries: LruCache<u64, InodeEntry>,
     next_ino: u64,
+    max_entries: usize,
 }
 
 impl InodeMap {
-    fn new() -> Self {
+    pub fn new(max_entries: usize) -> Self {
         let root_path = PathBuf::from("/");
-        let mut path_to_ino = HashMap::new();
-        let mut ino_to_path = HashMap::new();
+        let mut path_to_ino = BTreeMap::new();
+        let mut entries = LruCache::unbounded();
         path_to_ino.insert(root_path.clone(), FUSE_ROOT_ID);
-        ino_to_path.insert(FUSE_ROOT_ID, root_path);
+        entries.put(
+            FUSE_ROOT_ID,
+            InodeEntry {
+                path: root_path,
+                nlookup: 0,
+            },
+        );
         Self {
             path_to_ino,
-            ino_to_path,
+            entries,
             next_ino: FUSE_ROOT_ID + 1,
+            max_entries: max_entries.max(2),
         }
     }
 
-    fn allocate(&mut self, path: PathBuf) -> u64 {
+    /// Ino for `path` without taking a kernel reference (readdir).
+    pub fn allocate(&mut self, path: PathBuf) -> u64 {
         if let Some(&ino) = self.path_to_ino.get(&path) {
+            self.entries.promote(&ino);
             return ino;
         }
         let ino = self.next_ino;
         self.next_ino += 1;
         self.path_to_ino.insert(path.clone(), ino);
-        self.ino_to_path.insert(ino, path);
+        self.entries.put(ino, InodeEntry { path, nlookup: 0 });
+        self.evict_over_cap();
+        ino
+    }
+
+    /// Ino for `path` that is about to be returned in an `entry`/`created`
+    /// reply; bumps the lookup count the kernel will later `forget`.
+    pub fn lookup_ref(&mut self, path: PathBuf) -> u64 {
+        let ino = self.allocate(path);
+        if let Some(e) = self.entries.get_mut(&ino) {
+            e.nlookup += 1;
+        }
         ino
     }
 
-    fn lookup_path(&self, ino: u64) -> Option<PathBuf> {
-        self.ino_to_path.get(&ino).cloned()
+    /// Ino for `path` if the kernel may know it. Doesn't touch recency.
+    pub fn ino_of(&self, path: &Path) -> Option<u64> {
+        self.path_to_ino.get(path).copied()
+    }
+
+    pub fn lookup_path(&mut self, ino: u64) -> Option<PathBuf> {
+        self.entries.get(&ino).map(|e| e.path.clone())
+    }
+
+    /// Kernel dropped `nlookup` references to `ino`. Once none remain the
+    /// entry is released.
+    pub fn forget(&mut self, ino: u64, nlookup: u64) {
+        if ino == FUSE_ROOT_ID {
+            return;
+        }
+        let Some(e) = self.entries.peek_mut(&ino) else {
+            return;
+        };
+        e.nlookup = e.nlookup.saturating_sub(nlookup);
+        if e.nlookup == 0 {
+            if let Some(e) = self.entries.pop(&ino) {
+                self.path_to_ino.remove(&e.path);
+            }
+        }
     }
 
     fn remove(&mut self, path: &Path) {
         if let Some(ino) = self.path_to_ino.remove(path) {
-            self.ino_to_path.remove(&ino);
+            self.entries.pop(&ino);
+        }
+    }
+
+    /// Move `from`, and everything below it if it is a directory, to
+    /// `to`. Inode numbers don't change. Only the range of paths under
+    /// `from` is visited, not the whole table.
+    pub fn rename(&mut self, from: &Path, to: PathBuf) {
+        let moved: Vec<(PathBuf, u64)> = self
+            .path_to_ino
+            .range(from.to_path_buf()..)
+            .take_while(|(p, _)| p.starts_with(from))
+            .map(|(p, &ino)| (p.clone(), ino))
+            .collect();
+        for (old, ino) in moved {
+            self.path_to_ino.remove(&old);
+            let new = match old.strip_prefix(from) {
+                Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                _ => to.clone(),
+            };
+            if let Some(e) = self.entries.peek_mut(&ino) {
+                e.path = new.clone();
+            }
+            self.path_to_ino.insert(new, ino);
         }
     }
 
-    #[allow(dead_code)]
-    fn rename(&mut self, from: &Path, to: PathBuf) {
-        if let Some(ino) = self.path_to_ino.remove(from) {
-            self.path_to_ino.insert(to.clone(), ino);
-            self.ino_to_path.insert(ino, to);
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn evict_over_cap(&mut self) {
+        while self.entries.len() > self.max_entries {
+            // Prefer something the kernel no longer references; otherwise
+            // drop the plain LRU entry (a later op on it gets ENOENT and the
+            // kernel re-looks it up).
+            let victim = self
+                .entries
+                .iter()
+                .rev()
+                .take(EVICT_SCAN)
+                .find(|(&ino, e)| ino != FUSE_ROOT_ID && e.nlookup == 0)
+                .map(|(&ino, _)| ino)
+                .or_else(|| {
+                    let ino = self
+                        .entries
+                        .iter()
+                        .rev()
+                        .map(|(&ino, _)| ino)
+                        .find(|&ino| ino != FUSE_ROOT_ID)?;
+                    warn!("inode table full; evicting referenced ino {ino}");
+                    Some(ino)
+                });
+            let Some(ino) = victim else { break };
+            if let Some(e) = self.entries.pop(&ino) {
+                self.path_to_ino.remove(&e.path);
+            }
         }
     }
 }
 
+/// A directory entry name from the kernel must be a single plain
+/// component; anything else could walk out of its parent (or the backend
+/// root) once joined.
+fn check_name(name: &OsStr) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let bytes = name.as_bytes();
+    if matches!(bytes, b"" | b"." | b"..") || bytes.iter().any(|&b| b == b'/' || b == 0) {
+        return Err(FsError::InvalidOperation(format!("bad name {name:?}")));
+    }
+    Ok(())
+}
+
+/// `FOPEN_DIRECT_IO` for files the backend reads past the page cache
+/// (`Backend::direct_io_above`): the kernel would otherwise cache them
+/// again on the FUSE side.
+fn open_flags(backend: &Arc<dyn Backend>, bpath: &Path) -> u32 {
+    let large = |above| backend.metadata(bpath).is_ok_and(|m| m.size >= above);
+    if backend.direct_io_above().is_some_and(large) {
+        fuser::consts::FOPEN_DIRECT_IO
+    } else {
+        0
+    }
+}
+
 struct FhEntry {
     logical: PathBuf,
     backend: Arc<dyn Backend>,
     backend_path: PathBuf,
+    /// Set by the first write; the index size is refreshed on release.
+    written: bool,
+    /// Opened by `create`; the index row stays `FileState::Creating`
+    /// until the first successful flush or the release.
+    creating: bool,
+    /// Marked with `RHSS_IOC_BACKGROUND`.
+    background: bool,
 }
 
 struct FuseState {
@@ -131,11 +585,36 @@ struct FuseState {
     inodes: Mutex<InodeMap>,
     fh_table: Mutex<HashMap<u64, FhEntry>>,
     next_fh: AtomicU64,
-    config: FuseConfig,
+    /// Swapped wholesale on reload; only `FuseConfig::apply_reload` fields
+    /// actually change once mounted.
+    config: RwLock<FuseConfig>,
+    /// `FuseConfig::ids` and `modes`, fixed at mount; kept outside
+    /// `config` so attr replies built under its read lock don't take it
+    /// again.
+    ids: IdTranslation,
+    modes: Modes,
     running: AtomicBool,
+    pool: WorkerPool,
+    namespace_order: NamespaceOrder,
+    priorities: PidPriorities,
+    /// Set once the session is up; used to push invalidations to the kernel.
+    notifier: OnceLock<Notifier>,
 }
 
 impl FuseState {
+    /// Context for a failed call on `backend`, with the tier it serves.
+    fn failed_on(&self, op: &'static str, backend: &dyn Backend, path: &Path) -> ErrorContext {
+        let ctx = ErrorContext::new(op).backend(backend.id()).path(path);
+        match self
+            .router
+            .all_backends()
+            .find(|(_, b)| b.id() == backend.id())
+        {
+            Some((tier, _)) => ctx.tier(tier),
+            None => ctx,
+        }
+    }
+
     fn make_attr(&self, ino: u64, meta: &BackendMeta) -> FileAttr {
         FileAttr {
             ino,
@@ -150,17 +629,26 @@ impl FuseState {
             } else {
                 FileType::RegularFile
             },
-            perm: meta.mode as u16,
-            nlink: 1,
-            uid: unsafe { libc::getuid() },
-            gid: unsafe { libc::getgid() },
+            perm: self.modes.reported(meta.is_dir, meta.mode) as u16,
+            nlink: if meta.is_dir { 2 } else { 1 },
+            uid: self.ids.mount_uid(meta.uid),
+            gid: self.ids.mount_gid(meta.gid),
             rdev: 0,
             flags: 0,
             blksize: 4096,
         }
     }
 
+    /// Root attrs come from the first backend root that answers, so the
+    /// mount point shows a real owner and stable timestamps.
     fn root_attr(&self) -> FileAttr {
+        if let Some(meta) = self
+            .router
+            .all_backends()
+            .find_map(|(_, b)| b.metadata(Path::new("")).ok())
+        {
+            return self.make_attr(FUSE_ROOT_ID, &meta);
+        }
         let now = SystemTime::now();
         FileAttr {
             ino: FUSE_ROOT_ID,
@@ -171,31 +659,65 @@ impl FuseState {
             ctime: now,
             crtime: now,
             kind: FileType::Directory,
-            perm: 0o755,
+            perm: self.modes.reported(true, 0o755) as u16,
             nlink: 2,
-            uid: unsafe { libc::getuid() },
-            gid: unsafe { libc::getgid() },
+            uid: self.ids.mount_uid(unsafe { libc::getuid() }),
+            gid: self.ids.mount_gid(unsafe { libc::getgid() }),
             rdev: 0,
             flags: 0,
             blksize: 4096,
         }
     }
 
-    fn path_for(&self, parent: u64, name: &OsStr) -> Option<PathBuf> {
-        let inodes = self.inodes.lock();
-        let mut path = inodes.lookup_path(parent)?;
+    /// `name` inside directory `parent`. Names that aren't one plain
+    /// component (`..`, `a/b`, embedded NUL) are `EINVAL`.
+    fn path_for(&self, parent: u64, name: &OsStr) -> Result<PathBuf> {
+        check_name(name)?;
+        let mut path = self
+            .inodes
+            .lock()
+            .lookup_path(parent)
+            .ok_or_else(|| FsError::NotFound(format!("inode {parent}")))?;
         path.push(name);
-        Some(path)
+        Ok(path)
     }
 
     /// Resolve a logical path to (backend, backend-relative path) by looking
     /// up the path index. Returns `None` if not indexed.
     fn resolve(&self, logical: &Path) -> Option<(Arc<dyn Backend>, PathBuf)> {
-        let loc = self.index.locate(logical).ok().flatten()?;
+        let loc = {
+            let _profiled = crate::profile::phase(|| "index");
+            self.index.locate(logical).ok().flatten()?
+        };
         let backend = self.router.resolve_backend(loc.tier, &loc.backend_id)?;
         Some((Arc::clone(backend), loc.backend_path))
     }
 
+    /// Names of the files directly in `dir` that the index places on
+    /// `backend_id`.
+    fn indexed_children(&self, dir: &Path, backend_id: &str) -> Vec<String> {
+        let rows = self.index.list_under(dir).unwrap_or_default();
+        rows.iter()
+            .filter(|r| r.location.backend_id == backend_id)
+            .filter(|r| r.logical_path.parent() == Some(dir))
+            .filter_map(|r| r.logical_path.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Like `resolve`, but also finds directories (which aren't indexed) on
+    /// whichever backend has them.
+    fn locate(&self, logical: &Path) -> Option<(Arc<dyn Backend>, PathBuf)> {
+        if let Some(r) = self.resolve(logical) {
+            return Some(r);
+        }
+        let rel = logical.strip_prefix("/").unwrap_or(logical);
+        self.router
+            .all_backends()
+            .find(|(_, b)| b.exists(rel).unwrap_or(false))
+            .map(|(_, b)| (Arc::clone(b), rel.to_path_buf()))
+    }
+
     /// Like `resolve`, but considers replicas when the primary backend
     /// can't satisfy `exists()`. Used by FUSE `open` so a downed S3 replica
     /// doesn't break access if another replica is reachable. Slightly more
@@ -206,7 +728,10 @@ impl FuseState {
     /// and return the staging path so subsequent read/writes are native-
     /// POSIX speed.
     fn resolve_with_fallback(&self, logical: &Path) -> Option<(Arc<dyn Backend>, PathBuf)> {
-        let row = self.index.get(logical).ok().flatten()?;
+        let row = {
+            let _profiled = crate::profile::phase(|| "index");
+            self.index.get(logical).ok().flatten()?
+        };
         let compressed = row.compressed;
         let logical_size = row.location.size;
 
@@ -263,126 +788,235 @@ impl FuseState {
 
     fn fh(&self, fh: u64) -> Option<(Arc<dyn Backend>, PathBuf, PathBuf)> {
         let t = self.fh_table.lock();
-        t.get(&fh)
-            .map(|e| (Arc::clone(&e.backend), e.backend_path.clone(), e.logical.clone()))
+        t.get(&fh).map(|e| {
+            (
+                Arc::clone(&e.backend),
+                e.backend_path.clone(),
+                e.logical.clone(),
+            )
+        })
     }
 
-    fn release_fh(&self, fh: u64) -> Option<PathBuf> {
-        self.fh_table.lock().remove(&fh).map(|e| e.logical)
+    fn release_fh(&self, fh: u64) -> Option<FhEntry> {
+        self.fh_table.lock().remove(&fh)
     }
-}
 
-/// Top-level FUSE adapter.
-#[derive(Clone)]
-pub struct FuseAdapter {
-    state: Arc<FuseState>,
-}
+    fn mark_written(&self, fh: u64) {
+        if let Some(e) = self.fh_table.lock().get_mut(&fh) {
+            e.written = true;
+        }
+    }
 
-impl FuseAdapter {
-    pub fn new(
-        router: Arc<TierRouter>,
-        index: Arc<dyn PathIndex>,
-        policy: Arc<dyn TieringPolicy>,
-        open_tracker: Arc<OpenFileTracker>,
-        tierer: Option<TiererHandle>,
-        access: Option<AccessTracker>,
-        config: FuseConfig,
-    ) -> Self {
-        Self {
-            state: Arc::new(FuseState {
-                router,
-                index,
-                policy,
-                open_tracker,
-                tierer,
-                access,
-                inodes: Mutex::new(InodeMap::new()),
-                fh_table: Mutex::new(HashMap::new()),
-                next_fh: AtomicU64::new(1),
-                config,
-                running: AtomicBool::new(true),
-            }),
+    /// The file behind `fh` made it to storage: clear its
+    /// `FileState::Creating` mark. Only the first call does anything.
+    fn finish_create(&self, fh: u64) {
+        let logical = match self.fh_table.lock().get_mut(&fh) {
+            Some(e) if e.creating => {
+                e.creating = false;
+                e.logical.clone()
+            }
+            _ => return,
+        };
+        self.mark_created(&logical);
+    }
+
+    fn mark_created(&self, logical: &Path) {
+        if let Err(e) = self.index.set_state(logical, FileState::Stable) {
+            debug!("index state {}: {:?}", logical.display(), e);
         }
     }
 
-    pub fn mount(&self, mount_point: &Path) -> std::io::Result<()> {
-        info!("mounting rhss at {}", mount_point.display());
-        fuser::mount2(self.clone(), mount_point, &Self::mount_options())?;
-        Ok(())
+    /// Record the file's current size in the index so `rhss du` and
+    /// `stats` see it.
+    fn sync_size(&self, logical: &Path, backend: &Arc<dyn Backend>, bpath: &Path) {
+        let res = backend
+            .metadata(bpath)
+            .and_then(|m| self.index.set_size(logical, m.size));
+        if let Err(e) = res {
+            debug!("index size {}: {:?}", logical.display(), e);
+        }
     }
 
-    pub fn spawn_mount(&self, mount_point: &Path) -> std::io::Result<fuser::BackgroundSession> {
-        info!("mounting rhss (multi-thread) at {}", mount_point.display());
-        fuser::spawn_mount2(self.clone(), mount_point, &Self::mount_options())
+    /// Storage credentials a request from `uid`/`gid` acts with.
+    fn creds(&self, uid: u32, gid: u32) -> (u32, u32) {
+        let uid = self.ids.storage_uid(uid).unwrap_or(ownership::OVERFLOW_ID);
+        let gid = self.ids.storage_gid(gid).unwrap_or(ownership::OVERFLOW_ID);
+        self.config.read().id_map.map(uid, gid)
     }
 
-    fn mount_options() -> Vec<MountOption> {
-        let mut opts = vec![
-            MountOption::DefaultPermissions,
-            MountOption::FSName("rhss".to_string()),
-            MountOption::AutoUnmount,
-        ];
-        #[cfg(target_os = "macos")]
-        {
-            opts.push(MountOption::CUSTOM("volname=rhss".to_string()));
-            opts.push(MountOption::CUSTOM("local".to_string()));
-            opts.push(MountOption::CUSTOM("noapplexattr".to_string()));
+    /// May `uid`/`gid` add or remove entries in `logical`'s parent? Only
+    /// checked when `default_permissions` is off (the kernel checks
+    /// otherwise). When removing, the parent's sticky bit also applies.
+    fn check_dir_write(&self, logical: &Path, uid: u32, gid: u32, removing: bool) -> Result<()> {
+        if self.config.read().default_permissions {
+            return Ok(());
         }
-        #[cfg(target_os = "linux")]
-        {
-            // D20 / D21 — Linux perf path. macFUSE doesn't support any of
-            // these; the cfg gate is essential.
-            opts.push(MountOption::AllowOther);
-            opts.push(MountOption::CUSTOM("max_read=1048576".to_string()));   // 1 MiB
-            opts.push(MountOption::CUSTOM("max_write=1048576".to_string()));  // 1 MiB
-            opts.push(MountOption::CUSTOM("max_background=16".to_string()));
-            opts.push(MountOption::CUSTOM("congestion_threshold=12".to_string()));
+        let parent = logical.parent().unwrap_or(Path::new("/"));
+        // A missing parent is left to the op itself to report.
+        let Some((backend, dir)) = self.locate(parent) else {
+            return Ok(());
+        };
+        backend.check_access(&dir, uid, gid, libc::W_OK | libc::X_OK)?;
+        if !removing {
+            return Ok(());
+        }
+        let dir_meta = backend.metadata(&dir)?;
+        let Some((b, entry)) = self.locate(logical) else {
+            return Ok(());
+        };
+        let entry_meta = b.metadata(&entry)?;
+        if ownership::sticky_permits(dir_meta.mode, dir_meta.uid, entry_meta.uid, uid) {
+            Ok(())
+        } else {
+            Err(FsError::Io(std::io::Error::from_raw_os_error(libc::EPERM)))
         }
-        opts
     }
 
-    pub fn stop(&self) {
-        self.state.running.store(false, Ordering::SeqCst);
-        info!("rhss stop requested");
+    /// Hand a new file or directory to its creator. Needs a root daemon;
+    /// otherwise entries stay owned by the daemon user.
+    fn chown_new(&self, backend: &Arc<dyn Backend>, rel: &Path, uid: u32, gid: u32) {
+        if !ownership::needs_chown(uid, gid) {
+            return;
+        }
+        match backend.set_owner(rel, uid, gid) {
+            Ok(()) | Err(FsError::Unsupported(_)) => {}
+            Err(e) => warn!("chown {} on {}: {:?}", rel.display(), backend.id(), e),
+        }
     }
-}
 
-fn errno(err: &FsError) -> libc::c_int {
-    match err {
-        FsError::Io(io) => io.raw_os_error().unwrap_or(EIO),
-        FsError::NotFound(_) => ENOENT,
-        FsError::PermissionDenied(_) => libc::EACCES,
-        FsError::InvalidOperation(_) => libc::EINVAL,
-        _ => EIO,
+    /// Start auditing `op` by `uid`/`gid`. `path` only runs when auditing
+    /// is on; an unresolvable path is recorded as empty.
+    fn audit_span(
+        &self,
+        op: &'static str,
+        uid: u32,
+        gid: u32,
+        path: impl FnOnce() -> Option<PathBuf>,
+    ) -> Option<AuditSpan> {
+        let log = self.config.read().audit.clone()?;
+        Some(log.begin(op, path().unwrap_or_default(), uid, gid))
     }
-}
 
-impl Filesystem for FuseAdapter {
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        if !self.state.running.load(Ordering::SeqCst) {
-            reply.error(ENOSYS);
+    /// Add to the access trace, if one is recorded. `tier` only runs then.
+    fn trace_access(
+        &self,
+        op: TraceOp,
+        logical: &Path,
+        size: u64,
+        tier: impl FnOnce() -> Option<TierId>,
+    ) {
+        let Some(trace) = self.config.read().access_trace.clone() else {
             return;
+        };
+        trace.record(op, logical.to_path_buf(), size, tier());
+    }
+
+    /// Tier `backend` belongs to.
+    fn tier_of(&self, backend: &dyn Backend) -> Option<TierId> {
+        self.router
+            .all_backends()
+            .find(|(_, b)| b.id() == backend.id())
+            .map(|(t, _)| t)
+    }
+
+    fn fh_path(&self, fh: u64) -> Option<PathBuf> {
+        self.fh_table.lock().get(&fh).map(|e| e.logical.clone())
+    }
+
+    /// Quotas covering `logical`, if any could.
+    fn quotas_for(&self, logical: &Path) -> Option<Arc<Quotas>> {
+        self.config
+            .read()
+            .quotas
+            .clone()
+            .filter(|q| q.may_apply(logical))
+    }
+
+    /// Charge quota for growing `bpath` to `end` bytes. `Ok(None)` when no
+    /// quota applies; otherwise pass the reservation to `settle_growth`.
+    fn reserve_growth(
+        &self,
+        logical: &Path,
+        backend: &Arc<dyn Backend>,
+        bpath: &Path,
+        end: u64,
+    ) -> Result<Option<(Arc<Quotas>, Reservation)>> {
+        let Some(q) = self.quotas_for(logical) else {
+            return Ok(None);
+        };
+        let meta = backend.metadata(bpath)?;
+        let r = q.reserve(logical, meta.uid, meta.size, end)?;
+        Ok(Some((q, r)))
+    }
+
+    fn settle_growth(
+        &self,
+        logical: &Path,
+        reservation: Option<(Arc<Quotas>, Reservation)>,
+        end: Option<u64>,
+    ) {
+        if let Some((q, r)) = reservation {
+            q.settle(logical, r, end);
         }
-        let Some(path) = self.state.path_for(parent, name) else {
-            reply.error(ENOENT);
+    }
+
+    /// Drop the kernel's cached attrs and dentry for `logical` after rhss
+    /// changed it behind the kernel's back (e.g. a tier migration). Page
+    /// cache is kept — migrations don't change content. No-op before the
+    /// session is up or for paths the kernel never looked up.
+    fn invalidate(&self, logical: &Path) {
+        let Some(notifier) = self.notifier.get() else {
+            return;
+        };
+        let (ino, parent) = {
+            let inodes = self.inodes.lock();
+            let parent = logical.parent().and_then(|p| inodes.ino_of(p));
+            (inodes.ino_of(logical), parent)
+        };
+        if let Some(ino) = ino {
+            // offset < 0: attributes only.
+            if let Err(e) = notifier.inval_inode(ino, -1, 0) {
+                debug!("inval_inode {}: {e}", logical.display());
+            }
+        }
+        if let (Some(parent), Some(name)) = (parent, logical.file_name()) {
+            if let Err(e) = notifier.inval_entry(parent, name) {
+                debug!("inval_entry {}: {e}", logical.display());
+            }
+        }
+    }
+
+    // ----- Op bodies. Every `do_*` runs on the worker pool and owns its
+    // `Reply*`; the session thread only decodes the request and queues it,
+    // so a slow HDD seek or S3 fetch only blocks its own request.
+
+    fn do_lookup(&self, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if !self.running.load(Ordering::SeqCst) {
+            reply.error(ENOSYS);
             return;
+        }
+        let path = match self.path_for(parent, name) {
+            Ok(p) => p,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
         };
-        if self.state.config.should_ignore(&path) {
+        if self.config.read().ignored_on_lookup(&path) {
             reply.error(ENOENT);
             return;
         }
-        debug!("lookup {}", path.display());
 
         // Two possibilities: directory (resolved via filesystem walk on any
         // backend) or file (must be in index).
-        if let Some((backend, bpath)) = self.state.resolve(&path) {
+        if let Some((backend, bpath)) = self.resolve(&path) {
             match backend.metadata(&bpath) {
                 Ok(meta) => {
-                    let ino = self.state.inodes.lock().allocate(path);
-                    let attr = self.state.make_attr(ino, &meta);
-                    reply.entry(&TTL, &attr, 0);
+                    let ino = self.inodes.lock().lookup_ref(path);
+                    let attr = self.make_attr(ino, &meta);
+                    reply.entry(&self.config.read().entry_ttl, &attr, 0);
                 }
-                Err(e) => reply.error(errno(&e)),
+                Err(e) => reply.error(e.to_errno()),
             }
             return;
         }
@@ -390,14 +1024,14 @@ impl Filesystem for FuseAdapter {
         // Maybe it's a directory. Probe each fast backend's filesystem (P1
         // simplification: directories aren't tracked in the index; they live on
         // every backend that has anything below them).
-        for (_tier, backend) in self.state.router.all_backends() {
+        for (_tier, backend) in self.router.all_backends() {
             // Strip leading "/" since backend.metadata takes a relative path.
             let rel = path.strip_prefix("/").unwrap_or(&path);
             if let Ok(meta) = backend.metadata(rel) {
                 if meta.is_dir {
-                    let ino = self.state.inodes.lock().allocate(path);
-                    let attr = self.state.make_attr(ino, &meta);
-                    reply.entry(&TTL, &attr, 0);
+                    let ino = self.inodes.lock().lookup_ref(path);
+                    let attr = self.make_attr(ino, &meta);
+                    reply.entry(&self.config.read().entry_ttl, &attr, 0);
                     return;
                 }
             }
@@ -405,191 +1039,156 @@ impl Filesystem for FuseAdapter {
         reply.error(ENOENT);
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+    fn do_getattr(&self, ino: u64, reply: ReplyAttr) {
         if ino == FUSE_ROOT_ID {
-            reply.attr(&TTL, &self.state.root_attr());
+            reply.attr(&self.config.read().attr_ttl, &self.root_attr());
             return;
         }
-        let Some(path) = self.state.inodes.lock().lookup_path(ino) else {
+        let Some(path) = self.inodes.lock().lookup_path(ino) else {
             reply.error(ENOENT);
             return;
         };
 
-        if let Some((backend, bpath)) = self.state.resolve(&path) {
+        if let Some((backend, bpath)) = self.resolve(&path) {
             match backend.metadata(&bpath) {
-                Ok(meta) => reply.attr(&TTL, &self.state.make_attr(ino, &meta)),
-                Err(e) => reply.error(errno(&e)),
+                Ok(meta) => reply.attr(&self.config.read().attr_ttl, &self.make_attr(ino, &meta)),
+                Err(e) => reply.error(e.to_errno()),
             }
             return;
         }
 
         // Directory probe (same as lookup).
-        for (_tier, backend) in self.state.router.all_backends() {
+        for (_tier, backend) in self.router.all_backends() {
             let rel = path.strip_prefix("/").unwrap_or(&path);
             if let Ok(meta) = backend.metadata(rel) {
-                reply.attr(&TTL, &self.state.make_attr(ino, &meta));
+                reply.attr(&self.config.read().attr_ttl, &self.make_attr(ino, &meta));
                 return;
             }
         }
         reply.error(ENOENT);
     }
 
-    fn read(
-        &mut self,
-        _req: &Request,
-        _ino: u64,
-        fh: u64,
-        offset: i64,
-        size: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyData,
-    ) {
-        let Some((backend, bpath, logical)) = self.state.fh(fh) else {
+    fn do_open(&self, ino: u64, uid: u32, gid: u32, flags: i32, reply: ReplyOpen) {
+        let Some(logical) = self.inodes.lock().lookup_path(ino) else {
             reply.error(ENOENT);
             return;
         };
-        match backend.read_at(&bpath, offset as u64, size) {
-            Ok(data) => {
-                if let Some(t) = &self.state.access {
-                    t.record(logical, SystemTime::now());
+        let (uid, gid) = self.creds(uid, gid);
+        if !self.config.read().default_permissions {
+            let mask = match flags & libc::O_ACCMODE {
+                libc::O_WRONLY => libc::W_OK,
+                libc::O_RDWR => libc::R_OK | libc::W_OK,
+                _ => libc::R_OK,
+            };
+            // Only a definite denial blocks the open; lookup failures are
+            // left to the resolve below.
+            if let Some((backend, bpath)) = self.locate(&logical) {
+                if let Err(e @ FsError::PermissionDenied(_)) =
+                    backend.check_access(&bpath, uid, gid, mask)
+                {
+                    reply.error(e.to_errno());
+                    return;
                 }
-                reply.data(&data);
-            }
-            Err(e) => {
-                error!("read {} offset={} size={}: {:?}", bpath.display(), offset, size, e);
-                reply.error(errno(&e));
             }
         }
-    }
-
-    fn write(
-        &mut self,
-        _req: &Request,
-        _ino: u64,
-        fh: u64,
-        offset: i64,
-        data: &[u8],
-        _write_flags: u32,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        reply: ReplyWrite,
-    ) {
-        let Some((backend, bpath, logical)) = self.state.fh(fh) else {
-            reply.error(ENOENT);
+        // D5: try primary, then replicas (mirror tiers). Reads of a
+        // preheated file go to its fast-tier copy.
+        let Some((backend, bpath)) = self
+            .preheated(&logical, flags)
+            .or_else(|| self.resolve_with_fallback(&logical))
+        else {
+            // A cold backend that is down is an I/O error, not a missing file.
+            match self.resolve(&logical) {
+                Some((b, _)) if !b.available() => reply.error(EIO),
+                _ => reply.error(ENOENT),
+            }
             return;
         };
+        let open_flags = open_flags(&backend, &bpath);
+        self.open_tracker.register(&logical);
+        let fh = self.allocate_fh(FhEntry {
+            logical: logical.clone(),
+            backend,
+            backend_path: bpath,
+            written: false,
+            creating: false,
+            background: false,
+        });
+        if let Some(t) = &self.access {
+            t.record(logical, SystemTime::now());
+        }
+        reply.opened(fh, open_flags);
+    }
 
-        // ENOSPC retry loop (D8 / P3): try the write; if ENOSPC and
-        // automatic tiering is enabled, trigger an oneshot eviction, wait
-        // for it to complete (bounded), then retry. If automatic tiering
-        // is disabled (`tier_period < 0`, see D15), return ENOSPC straight
-        // away — no surprise multi-second blocking.
-        let mut attempts = 0u32;
-        loop {
-            match backend.write_at(&bpath, offset as u64, data) {
-                Ok(n) => {
-                    if let Some(t) = &self.state.access {
-                        t.record(logical, SystemTime::now());
-                    }
-                    reply.written(n);
-                    return;
-                }
-                Err(e) => {
-                    let is_enospc = matches!(
-                        &e,
-                        FsError::Io(io) if io.raw_os_error() == Some(libc::ENOSPC)
-                    );
-                    if !is_enospc || attempts >= 1 || self.state.policy.tier_period().is_none() {
-                        if !is_enospc {
-                            error!(
-                                "write {} offset={} len={}: {:?}",
-                                bpath.display(),
-                                offset,
-                                data.len(),
-                                e
-                            );
-                        }
-                        reply.error(errno(&e));
-                        return;
-                    }
-                    attempts += 1;
-                    warn!(
-                        "write ENOSPC on {}; triggering emergency tiering",
-                        bpath.display()
-                    );
-                    if let Some(t) = &self.state.tierer {
-                        t.trigger_oneshot();
-                        let _ = t.wait_idle(Duration::from_secs(30));
-                    }
-                    // Loop and retry.
-                }
-            }
+    /// The preheated copy to open `logical` from, for a read-only open.
+    /// Any other open drops the copy: writes go to the cold tier, which
+    /// stays authoritative (`crate::preheat`).
+    fn preheated(&self, logical: &Path, flags: i32) -> Option<(Arc<dyn Backend>, PathBuf)> {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0 {
+            self.drop_preheated(logical);
+            return None;
+        }
+        let row = self.index.get(logical).ok().flatten()?;
+        crate::preheat::valid_copy(&self.router, &self.index, &row)
+    }
+
+    /// Drop the preheated copies at or below `logical` before it changes.
+    fn drop_preheated(&self, logical: &Path) {
+        if let Err(e) = crate::preheat::drop_under(&self.router, &self.index, logical) {
+            warn!("drop preheated {}: {e}", logical.display());
         }
     }
 
-    fn open(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
-        let Some(logical) = self.state.inodes.lock().lookup_path(ino) else {
+    fn do_access(&self, ino: u64, uid: u32, gid: u32, mask: i32, reply: ReplyEmpty) {
+        let Some(logical) = self.inodes.lock().lookup_path(ino) else {
             reply.error(ENOENT);
             return;
         };
-        // D5: try primary, then replicas (mirror tiers).
-        let Some((backend, bpath)) = self.state.resolve_with_fallback(&logical) else {
+        let Some((backend, bpath)) = self.locate(&logical) else {
             reply.error(ENOENT);
             return;
         };
-        self.state.open_tracker.register(&logical);
-        let fh = self.state.allocate_fh(FhEntry {
-            logical: logical.clone(),
-            backend,
-            backend_path: bpath,
-        });
-        if let Some(t) = &self.state.access {
-            t.record(logical, SystemTime::now());
-        }
-        reply.opened(fh, 0);
-    }
-
-    fn release(
-        &mut self,
-        _req: &Request,
-        _ino: u64,
-        fh: u64,
-        _flags: i32,
-        _lock_owner: Option<u64>,
-        _flush: bool,
-        reply: ReplyEmpty,
-    ) {
-        if let Some(logical) = self.state.release_fh(fh) {
-            self.state.open_tracker.release(&logical);
+        let (uid, gid) = self.creds(uid, gid);
+        match backend.check_access(&bpath, uid, gid, mask) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.to_errno()),
         }
-        reply.ok();
     }
 
-    fn create(
-        &mut self,
-        _req: &Request,
+    fn do_create(
+        &self,
         parent: u64,
         name: &OsStr,
         mode: u32,
-        _umask: u32,
-        _flags: i32,
-        reply: ReplyCreate,
+        uid: u32,
+        gid: u32,
+        reply: Audited<ReplyCreate>,
     ) {
-        let Some(logical) = self.state.path_for(parent, name) else {
-            reply.error(ENOENT);
-            return;
+        let logical = match self.path_for(parent, name) {
+            Ok(p) => p,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
         };
-        if self.state.config.should_ignore(&logical) {
+        if self.config.read().ignored_on_lookup(&logical) {
             reply.error(EEXIST);
             return;
         }
+        let (uid, gid) = self.creds(uid, gid);
+        if let Err(e) = self.check_dir_write(&logical, uid, gid, false) {
+            reply.error(e.to_errno());
+            return;
+        }
 
         // Watermark routing (D6 / D17 / D20). When Fast is over panic, new
         // files go directly to Slow so we don't hit ENOSPC on Fast.
-        let fast_usage = self.state.router.fast.usage_ratio();
-        let tier = self.state.policy.tier_for_create(fast_usage);
-        let tier_ref = match self.state.router.tier(tier) {
+        let fast_usage = self.router.fast.usage_ratio();
+        let mut tier = self.policy.tier_for_create(fast_usage);
+        if let Some(t) = self.config.read().tenants.clone() {
+            tier = t.tier_for_create(&logical, tier, self.index.as_ref());
+        }
+        let tier_ref = match self.router.tier(tier) {
             Some(t) => t,
             None => {
                 reply.error(EIO);
@@ -599,25 +1198,36 @@ impl Filesystem for FuseAdapter {
         let backend = match tier_ref.pick() {
             Ok(b) => Arc::clone(b),
             Err(e) => {
-                reply.error(errno(&e));
+                reply.error(e.to_errno());
                 return;
             }
         };
         let rel = logical.strip_prefix("/").unwrap_or(&logical).to_path_buf();
 
-        if let Err(e) = backend.create_file(&rel) {
-            error!("create {}: {:?}", logical.display(), e);
-            reply.error(errno(&e));
+        if let Err(e) = backend
+            .create_file(&rel)
+            .context(|| self.failed_on("create", backend.as_ref(), &rel))
+        {
+            error!(backend = backend.id(), "{e}");
+            reply.fail(&e);
             return;
         }
-        let _ = backend.set_permissions(&rel, mode);
+        let _ = backend.set_permissions(&rel, self.modes.created(false, mode));
+        self.chown_new(&backend, &rel, uid, gid);
         let meta = match backend.metadata(&rel) {
             Ok(m) => m,
             Err(e) => {
-                reply.error(errno(&e));
+                reply.error(e.to_errno());
                 return;
             }
         };
+        if let Some(q) = self.quotas_for(&logical) {
+            if let Err(e) = q.check_room(&logical, meta.uid) {
+                let _ = backend.remove(&rel);
+                reply.error(e.to_errno());
+                return;
+            }
+        }
 
         let row = FileRow {
             logical_path: logical.clone(),
@@ -630,80 +1240,118 @@ impl Filesystem for FuseAdapter {
             replicas: Vec::new(),
             last_access: SystemTime::now(),
             hit_count: 0,
-            popularity: self.state.policy.initial_popularity(), // D17
+            popularity: self.policy.initial_popularity(), // D17
             pinned_tier: None,
-            state: FileState::Stable,
+            pinned_until: None,
+            state: FileState::Creating,
             mutability: crate::index::Mutability::Unknown,
             compressed: false,
             content_hash: None,
         };
-        if let Err(e) = self.state.index.insert(row) {
-            reply.error(errno(&e));
+        if let Err(e) = self.index.insert(row) {
+            reply.error(e.to_errno());
             return;
         }
 
-        let ino = self.state.inodes.lock().allocate(logical.clone());
-        self.state.open_tracker.register(&logical);
-        let fh = self.state.allocate_fh(FhEntry {
+        self.trace_access(TraceOp::Create, &logical, meta.size, || Some(tier));
+        let ino = self.inodes.lock().lookup_ref(logical.clone());
+        self.open_tracker.register(&logical);
+        let fh = self.allocate_fh(FhEntry {
             logical,
             backend,
             backend_path: rel,
+            written: false,
+            creating: true,
+            background: false,
         });
-        let attr = self.state.make_attr(ino, &meta);
-        reply.created(&TTL, &attr, 0, fh, 0);
+        let attr = self.make_attr(ino, &meta);
+        reply.created(&self.config.read().entry_ttl, &attr, 0, fh, 0);
     }
 
-    fn mkdir(
-        &mut self,
-        _req: &Request,
+    fn do_mkdir(
+        &self,
         parent: u64,
         name: &OsStr,
         mode: u32,
-        _umask: u32,
-        reply: ReplyEntry,
+        uid: u32,
+        gid: u32,
+        reply: Audited<ReplyEntry>,
     ) {
-        let Some(logical) = self.state.path_for(parent, name) else {
-            reply.error(ENOENT);
-            return;
+        let logical = match self.path_for(parent, name) {
+            Ok(p) => p,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
         };
+        let (uid, gid) = self.creds(uid, gid);
+        if let Err(e) = self.check_dir_write(&logical, uid, gid, false) {
+            reply.error(e.to_errno());
+            return;
+        }
         let rel = logical.strip_prefix("/").unwrap_or(&logical).to_path_buf();
         // Create on EVERY backend so the dir is visible from anywhere.
         let mut ok_meta: Option<BackendMeta> = None;
-        for (_tier, b) in self.state.router.all_backends() {
+        let mut last_err: Option<FsError> = None;
+        let mode = self.modes.created(true, mode);
+        for (_tier, b) in self.router.all_backends() {
             if let Err(e) = b.create_dir(&rel) {
                 warn!("mkdir on {}: {:?}", b.id(), e);
+                last_err = Some(e);
             } else {
                 let _ = b.set_permissions(&rel, mode);
+                self.chown_new(b, &rel, uid, gid);
                 if ok_meta.is_none() {
                     ok_meta = b.metadata(&rel).ok();
                 }
             }
         }
         let Some(meta) = ok_meta else {
-            reply.error(EIO);
+            reply.error(last_err.map(|e| e.to_errno()).unwrap_or(EIO));
             return;
         };
-        let ino = self.state.inodes.lock().allocate(logical);
-        let attr = self.state.make_attr(ino, &meta);
-        reply.entry(&TTL, &attr, 0);
+        let ino = self.inodes.lock().lookup_ref(logical);
+        let attr = self.make_attr(ino, &meta);
+        reply.entry(&self.config.read().entry_ttl, &attr, 0);
     }
 
-    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        let Some(logical) = self.state.path_for(parent, name) else {
-            reply.error(ENOENT);
-            return;
+    fn do_unlink(&self, parent: u64, name: &OsStr, uid: u32, gid: u32, reply: Audited<ReplyEmpty>) {
+        let logical = match self.path_for(parent, name) {
+            Ok(p) => p,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
         };
+        let (uid, gid) = self.creds(uid, gid);
+        if let Err(e) = self.check_dir_write(&logical, uid, gid, true) {
+            reply.error(e.to_errno());
+            return;
+        }
         // D25: dedup-aware unlink. If the file is part of a deduped blob,
         // unref it; only delete the physical file when refcount → 0.
-        let row = self.state.index.get(&logical).ok().flatten();
-        let Some((backend, bpath)) = self.state.resolve(&logical) else {
+        let row = self.index.get(&logical).ok().flatten();
+        let Some((backend, bpath)) = self.resolve(&logical) else {
             reply.error(ENOENT);
             return;
         };
+        // Owner and logical size, to release quota once the file is gone.
+        let charged = self.quotas_for(&logical).and_then(|q| {
+            let on_disk = match &row {
+                Some(r) if r.compressed => crate::tierer::compress::compressed_path(&bpath),
+                _ => bpath.clone(),
+            };
+            let meta = backend.metadata(&on_disk).ok()?;
+            let size = match &row {
+                Some(r) if r.compressed => r.location.size,
+                _ => meta.size,
+            };
+            Some((q, meta.uid, size))
+        });
         let mut should_remove_physical = true;
         if let Some(r) = &row {
             if let Some(hash) = &r.content_hash {
-                match self.state.index.unref_blob(hash) {
+                match self.index.unref_blob(hash) {
                     Ok(true) => {
                         // Refcount hit 0 — last reference. Delete physical.
                         should_remove_physical = true;
@@ -725,27 +1373,50 @@ impl Filesystem for FuseAdapter {
             } else {
                 bpath.clone()
             };
-            if let Err(e) = backend.remove(&on_disk) {
-                reply.error(errno(&e));
+            let trash = self.config.read().trash.clone();
+            let res = match (trash, &row) {
+                (Some(trash), Some(r)) => trash.stash(&backend, r, &on_disk).map(|_| ()),
+                _ => backend.remove(&on_disk),
+            };
+            if let Err(e) = res {
+                reply.error(e.to_errno());
                 return;
             }
         }
-        if let Err(e) = self.state.index.remove(&logical) {
+        let size = row.as_ref().map_or(0, |r| r.location.size);
+        self.trace_access(TraceOp::Delete, &logical, size, || {
+            row.as_ref()
+                .map(|r| r.location.tier)
+                .or_else(|| self.tier_of(backend.as_ref()))
+        });
+        self.drop_preheated(&logical);
+        if let Err(e) = self.index.remove(&logical) {
             warn!("index.remove {}: {:?}", logical.display(), e);
         }
-        self.state.inodes.lock().remove(&logical);
+        if let Some((q, uid, size)) = charged {
+            q.release(&logical, uid, size);
+        }
+        self.inodes.lock().remove(&logical);
         reply.ok();
     }
 
-    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        let Some(logical) = self.state.path_for(parent, name) else {
-            reply.error(ENOENT);
-            return;
+    fn do_rmdir(&self, parent: u64, name: &OsStr, uid: u32, gid: u32, reply: Audited<ReplyEmpty>) {
+        let logical = match self.path_for(parent, name) {
+            Ok(p) => p,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
         };
+        let (uid, gid) = self.creds(uid, gid);
+        if let Err(e) = self.check_dir_write(&logical, uid, gid, true) {
+            reply.error(e.to_errno());
+            return;
+        }
         let rel = logical.strip_prefix("/").unwrap_or(&logical).to_path_buf();
         let mut last_err: Option<FsError> = None;
         let mut removed_anywhere = false;
-        for (_tier, b) in self.state.router.all_backends() {
+        for (_tier, b) in self.router.all_backends() {
             match b.remove(&rel) {
                 Ok(()) => removed_anywhere = true,
                 Err(e) => {
@@ -755,27 +1426,23 @@ impl Filesystem for FuseAdapter {
         }
         if !removed_anywhere {
             if let Some(e) = last_err {
-                reply.error(errno(&e));
+                reply.error(e.to_errno());
                 return;
             }
         }
-        self.state.inodes.lock().remove(&logical);
+        self.inodes.lock().remove(&logical);
         reply.ok();
     }
 
-    fn readdir(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
-    ) {
-        let Some(dir_path) = self.state.inodes.lock().lookup_path(ino) else {
+    fn do_readdir(&self, ino: u64, offset: i64, mut reply: ReplyDirectory) {
+        let Some(dir_path) = self.inodes.lock().lookup_path(ino) else {
             reply.error(ENOENT);
             return;
         };
-        let rel = dir_path.strip_prefix("/").unwrap_or(&dir_path).to_path_buf();
+        let rel = dir_path
+            .strip_prefix("/")
+            .unwrap_or(&dir_path)
+            .to_path_buf();
 
         // Merge entries from every backend into one logical view, deduping
         // (same name across backends shows up once).
@@ -784,9 +1451,12 @@ impl Filesystem for FuseAdapter {
         all.push((ino, FileType::Directory, ".".to_string()));
         all.push((ino, FileType::Directory, "..".to_string()));
 
-        for (_tier, b) in self.state.router.all_backends() {
+        for (_tier, b) in self.router.all_backends() {
             let entries = match b.list_dir(&rel) {
                 Ok(e) => e,
+                // An offline backend's files still show up, from the index;
+                // opening them fails with EIO.
+                Err(_) if !b.available() => self.indexed_children(&dir_path, b.id()),
                 Err(_) => continue,
             };
             for name in entries {
@@ -794,10 +1464,13 @@ impl Filesystem for FuseAdapter {
                     continue;
                 }
                 let entry_path = dir_path.join(&name);
-                if self.state.config.should_ignore(&entry_path) {
+                if self.config.read().ignored_on_list(&entry_path) {
                     continue;
                 }
-                let entry_rel = entry_path.strip_prefix("/").unwrap_or(&entry_path).to_path_buf();
+                let entry_rel = entry_path
+                    .strip_prefix("/")
+                    .unwrap_or(&entry_path)
+                    .to_path_buf();
                 let kind = b
                     .metadata(&entry_rel)
                     .map(|m| {
@@ -808,7 +1481,7 @@ impl Filesystem for FuseAdapter {
                         }
                     })
                     .unwrap_or(FileType::RegularFile);
-                let entry_ino = self.state.inodes.lock().allocate(entry_path);
+                let entry_ino = self.inodes.lock().allocate(entry_path);
                 all.push((entry_ino, kind, name));
             }
         }
@@ -821,46 +1494,111 @@ impl Filesystem for FuseAdapter {
         reply.ok();
     }
 
-    fn setattr(
-        &mut self,
-        _req: &Request,
+    /// The backend, backend path and logical path `setattr` acts on: the
+    /// open handle's if there is one.
+    fn setattr_target(
+        &self,
+        ino: u64,
+        fh: Option<u64>,
+    ) -> Option<(Arc<dyn Backend>, PathBuf, PathBuf)> {
+        // A handle reading a preheated copy changes the authoritative file.
+        if let Some(r) = fh.and_then(|h| self.fh(h)) {
+            if !r.1.starts_with(crate::preheat::PREHEAT_DIR) {
+                return Some(r);
+            }
+        }
+        let logical = self.inodes.lock().lookup_path(ino)?;
+        let (b, p) = self.resolve(&logical)?;
+        Some((b, p, logical))
+    }
+
+    /// `chown`: `uid`/`gid` are mount ids, `caller` the request's storage
+    /// credentials. Without `default_permissions` the kernel leaves the
+    /// ownership rules to us: only root gives files away, and an owner may
+    /// only switch the group to its own.
+    fn do_chown(
+        &self,
+        ino: u64,
+        fh: Option<u64>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        caller: (u32, u32),
+    ) -> Result<()> {
+        if uid.is_none() && gid.is_none() {
+            return Ok(());
+        }
+        let Some((backend, bpath, _)) = self.setattr_target(ino, fh) else {
+            return Err(FsError::NotFound(format!("inode {ino}")));
+        };
+        let meta = backend.metadata(&bpath)?;
+        let unmapped = |id| FsError::InvalidOperation(format!("id {id} is outside the id map"));
+        let new_uid = match uid {
+            Some(u) => self.ids.storage_uid(u).ok_or_else(|| unmapped(u))?,
+            None => meta.uid,
+        };
+        let new_gid = match gid {
+            Some(g) => self.ids.storage_gid(g).ok_or_else(|| unmapped(g))?,
+            None => meta.gid,
+        };
+        if !self.config.read().default_permissions && caller.0 != 0 {
+            let owner = caller.0 == meta.uid;
+            let regroup = new_gid != meta.gid && !(owner && new_gid == caller.1);
+            if new_uid != meta.uid || regroup {
+                return Err(FsError::Io(std::io::Error::from_raw_os_error(libc::EPERM)));
+            }
+        }
+        if (new_uid, new_gid) == (meta.uid, meta.gid) {
+            return Ok(());
+        }
+        backend.set_owner(&bpath, new_uid, new_gid)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn do_setattr(
+        &self,
         ino: u64,
         mode: Option<u32>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
         size: Option<u64>,
         atime: Option<TimeOrNow>,
         mtime: Option<TimeOrNow>,
-        _ctime: Option<SystemTime>,
         fh: Option<u64>,
-        _crtime: Option<SystemTime>,
-        _chgtime: Option<SystemTime>,
-        _bkuptime: Option<SystemTime>,
-        _flags: Option<u32>,
-        reply: ReplyAttr,
+        reply: Audited<ReplyAttr>,
     ) {
-        let resolved = match fh.and_then(|h| self.state.fh(h)) {
-            Some((b, p, _)) => (b, p),
-            None => {
-                let Some(logical) = self.state.inodes.lock().lookup_path(ino) else {
-                    reply.error(ENOENT);
-                    return;
-                };
-                let Some(r) = self.state.resolve(&logical) else {
-                    reply.error(ENOENT);
-                    return;
-                };
-                r
-            }
+        let Some((backend, bpath, logical)) = self.setattr_target(ino, fh) else {
+            reply.error(ENOENT);
+            return;
         };
-        let (backend, bpath) = resolved;
 
         if let Some(new_size) = size {
-            if let Err(e) = backend.truncate(&bpath, new_size) {
-                error!("truncate {}: {:?}", bpath.display(), e);
-                reply.error(errno(&e));
+            self.drop_preheated(&logical);
+            // Charge (or refund) quota for the size change up front.
+            let charged = match self.quotas_for(&logical) {
+                Some(q) => match backend.metadata(&bpath) {
+                    Ok(m) => match q.resize(&logical, m.uid, m.size, new_size) {
+                        Ok(()) => Some((q, m.uid, m.size)),
+                        Err(e) => {
+                            reply.error(e.to_errno());
+                            return;
+                        }
+                    },
+                    Err(_) => None,
+                },
+                None => None,
+            };
+            if let Err(e) = backend
+                .truncate(&bpath, new_size)
+                .context(|| self.failed_on("truncate", backend.as_ref(), &bpath))
+            {
+                error!(backend = backend.id(), "{e}");
+                if let Some((q, uid, before)) = charged {
+                    let _ = q.resize(&logical, uid, new_size, before);
+                }
+                reply.fail(&e);
                 return;
             }
+            if let Err(e) = self.index.set_size(&logical, new_size) {
+                debug!("index size {}: {:?}", logical.display(), e);
+            }
         }
         if let Some(new_mode) = mode {
             if let Err(e) = backend.set_permissions(&bpath, new_mode) {
@@ -882,51 +1620,100 @@ impl Filesystem for FuseAdapter {
         }
 
         match backend.metadata(&bpath) {
-            Ok(meta) => reply.attr(&TTL, &self.state.make_attr(ino, &meta)),
-            Err(e) => reply.error(errno(&e)),
+            Ok(meta) => reply.attr(&self.config.read().attr_ttl, &self.make_attr(ino, &meta)),
+            Err(e) => reply.error(e.to_errno()),
         }
     }
 
-    fn rename(
-        &mut self,
-        _req: &Request,
+    /// `from` is now `to`: carry the inode table, open handles and open
+    /// counts over, for everything below `from` too.
+    fn renamed(&self, from: &Path, to: PathBuf) {
+        let from_rel = from.strip_prefix("/").unwrap_or(from);
+        let to_rel = to.strip_prefix("/").unwrap_or(&to);
+        let moved = |p: &Path, old: &Path, new: &Path| -> Option<PathBuf> {
+            let rest = p.strip_prefix(old).ok()?;
+            Some(if rest.as_os_str().is_empty() {
+                new.to_path_buf()
+            } else {
+                new.join(rest)
+            })
+        };
+        for e in self.fh_table.lock().values_mut() {
+            if let Some(p) = moved(&e.logical, from, &to) {
+                e.logical = p;
+                if let Some(bp) = moved(&e.backend_path, from_rel, to_rel) {
+                    e.backend_path = bp;
+                }
+            }
+        }
+        self.open_tracker.rename(from, &to);
+        self.inodes.lock().rename(from, to);
+    }
+
+    fn do_rename(
+        &self,
         parent: u64,
         name: &OsStr,
         new_parent: u64,
         new_name: &OsStr,
-        _flags: u32,
-        reply: ReplyEmpty,
+        reply: Audited<ReplyEmpty>,
     ) {
-        let Some(from_logical) = self.state.path_for(parent, name) else {
-            reply.error(ENOENT);
-            return;
+        let from_logical = match self.path_for(parent, name) {
+            Ok(p) => p,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
         };
-        let Some(to_logical) = self.state.path_for(new_parent, new_name) else {
-            reply.error(ENOENT);
-            return;
+        let to_logical = match self.path_for(new_parent, new_name) {
+            Ok(p) => p,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
         };
 
+        self.drop_preheated(&from_logical);
+        self.drop_preheated(&to_logical);
         // Look up the file's current backend via the index.
-        let Some(row) = self.state.index.get(&from_logical).ok().flatten() else {
+        let Some(row) = self.index.get(&from_logical).ok().flatten() else {
             // Maybe it's a directory — rename across all backends.
             let mut ok = false;
-            for (_tier, b) in self.state.router.all_backends() {
+            let mut last_err: Option<FsError> = None;
+            for (_tier, b) in self.router.all_backends() {
                 let from_rel = from_logical.strip_prefix("/").unwrap_or(&from_logical);
                 let to_rel = to_logical.strip_prefix("/").unwrap_or(&to_logical);
-                if b.rename(from_rel, to_rel).is_ok() {
-                    ok = true;
+                match b.rename(from_rel, to_rel) {
+                    Ok(()) => ok = true,
+                    Err(e) => last_err = Some(e),
                 }
             }
             if ok {
-                self.state.inodes.lock().rename(&from_logical, to_logical);
+                if let Some(q) = self.config.read().quotas.clone() {
+                    if q.affects_rename(&from_logical, &to_logical) {
+                        q.reseed(&self.router, &self.index);
+                    }
+                }
+                if let Err(e) = self.index.rename_tree(&from_logical, &to_logical) {
+                    warn!(
+                        "index.rename_tree {} -> {}: {:?}",
+                        from_logical.display(),
+                        to_logical.display(),
+                        e
+                    );
+                }
+                self.renamed(&from_logical, to_logical);
                 reply.ok();
             } else {
-                reply.error(ENOENT);
+                reply.error(last_err.map(|e| e.to_errno()).unwrap_or(ENOENT));
             }
             return;
         };
 
-        let backend = match self.state.router.resolve_backend(row.location.tier, &row.location.backend_id) {
+        let backend = match self
+            .router
+            .resolve_backend(row.location.tier, &row.location.backend_id)
+        {
             Some(b) => Arc::clone(b),
             None => {
                 reply.error(EIO);
@@ -939,15 +1726,40 @@ impl Filesystem for FuseAdapter {
             .unwrap_or(&to_logical)
             .to_path_buf();
 
+        // Move the file's bytes between quota rules; refuse if the
+        // destination has no room.
+        let moved = match self.config.read().quotas.clone() {
+            Some(q) if q.may_apply(&from_logical) || q.may_apply(&to_logical) => {
+                match backend.metadata(&from_rel) {
+                    Ok(m) => match q.transfer(&from_logical, &to_logical, m.uid, m.size) {
+                        Ok(()) => Some((q, m.uid, m.size)),
+                        Err(e) => {
+                            reply.error(e.to_errno());
+                            return;
+                        }
+                    },
+                    Err(_) => None,
+                }
+            }
+            _ => None,
+        };
         if let Err(e) = backend.rename(&from_rel, &to_rel) {
+            if let Some((q, uid, size)) = moved {
+                let _ = q.transfer(&to_logical, &from_logical, uid, size);
+            }
             // Same-backend rename failed. Cross-backend / cross-tier rename
             // would be migrate-driven; not handled here (file would need to
             // be copied first). For v0.1 we just surface the error.
-            reply.error(errno(&e));
+            reply.error(e.to_errno());
             return;
         }
-        if let Err(e) = self.state.index.rename(&from_logical, &to_logical) {
-            warn!("index.rename {} -> {}: {:?}", from_logical.display(), to_logical.display(), e);
+        if let Err(e) = self.index.rename(&from_logical, &to_logical) {
+            warn!(
+                "index.rename {} -> {}: {:?}",
+                from_logical.display(),
+                to_logical.display(),
+                e
+            );
         }
         // Also update the backend_path in the index since the file moved
         // within the backend's directory tree.
@@ -957,58 +1769,15 @@ impl Filesystem for FuseAdapter {
             backend_path: to_rel,
             size: row.location.size,
         };
-        let _ = self.state.index.swap_location(&to_logical, new_loc);
-        self.state.inodes.lock().rename(&from_logical, to_logical);
-        reply.ok();
-    }
-
-    fn forget(&mut self, _req: &Request, _ino: u64, _nlookup: u64) {
-        // FUSE forget is advisory; we keep a flat inode map and don't grow
-        // an explicit lookup_count. For long-running mounts this means the
-        // inode map grows monotonically — to be addressed in v0.2 (issue
-        // tracked in risks.md).
-    }
-
-    fn fsync(
-        &mut self,
-        _req: &Request,
-        _ino: u64,
-        fh: u64,
-        _datasync: bool,
-        reply: ReplyEmpty,
-    ) {
-        let Some((backend, bpath, _)) = self.state.fh(fh) else {
-            reply.error(ENOENT);
-            return;
-        };
-        match backend.fsync(&bpath) {
-            Ok(()) => reply.ok(),
-            Err(e) => reply.error(errno(&e)),
-        }
-    }
-
-    fn flush(
-        &mut self,
-        _req: &Request,
-        _ino: u64,
-        fh: u64,
-        _lock_owner: u64,
-        reply: ReplyEmpty,
-    ) {
-        // Mac apps frequently call close()/flush. fsync is the safer thing
-        // to do; F_FULLFSYNC is reserved for the migrate path (D4 P3).
-        let Some((backend, bpath, _)) = self.state.fh(fh) else {
-            reply.ok();
-            return;
-        };
-        let _ = backend.fsync(&bpath);
+        let _ = self.index.swap_location(&to_logical, new_loc);
+        self.renamed(&from_logical, to_logical);
         reply.ok();
     }
 
-    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
-        let (fast_total, _fast_used, fast_free) = self.state.router.fast.capacity();
-        let (slow_total, _slow_used, slow_free) = self.state.router.slow.capacity();
-        let (arc_total, arc_free) = match &self.state.router.archive {
+    fn do_statfs(&self, reply: ReplyStatfs) {
+        let (fast_total, _fast_used, fast_free) = self.router.fast.capacity();
+        let (slow_total, _slow_used, slow_free) = self.router.slow.capacity();
+        let (arc_total, arc_free) = match &self.router.archive {
             Some(a) => {
                 let (t, _u, f) = a.capacity();
                 (t, f)
@@ -1020,7 +1789,1199 @@ impl Filesystem for FuseAdapter {
         let bsize = 4096u32;
         let blocks = total / bsize as u64;
         let bfree = free / bsize as u64;
-        let files = self.state.index.count().unwrap_or(0);
+        let files = self.index.count().unwrap_or(0);
         reply.statfs(blocks, bfree, bfree, files, 0, bsize, 255, bsize);
     }
+
+    fn do_read(&self, fh: u64, offset: i64, size: u32, reply: ReplyData) {
+        let Some((backend, bpath, logical)) = self.fh(fh) else {
+            reply.error(EBADF);
+            return;
+        };
+        let result = backend
+            .read_at(&bpath, offset as u64, size)
+            .context(|| self.failed_on("read", backend.as_ref(), &bpath));
+        match result {
+            Ok(data) => {
+                if self.router.fast.find_backend(backend.id()).is_none() {
+                    crate::throttle::take(crate::throttle::Class::ColdRead, data.len() as u64);
+                }
+                let end = offset as u64 + data.len() as u64;
+                self.trace_access(TraceOp::Read, &logical, end, || {
+                    self.tier_of(backend.as_ref())
+                });
+                if let Some(t) = &self.access {
+                    t.record(logical, SystemTime::now());
+                }
+                reply.data(&data);
+            }
+            Err(e) => {
+                error!(backend = backend.id(), offset, size, "{e}");
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    fn do_write(&self, fh: u64, offset: i64, data: &[u8], reply: Audited<ReplyWrite>) {
+        let Some((backend, bpath, logical)) = self.fh(fh) else {
+            reply.error(EBADF);
+            return;
+        };
+        let end = offset as u64 + data.len() as u64;
+        let reservation = match self.reserve_growth(&logical, &backend, &bpath, end) {
+            Ok(r) => r,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
+        };
+
+        // ENOSPC retry loop (D8 / P3): try the write; if ENOSPC and
+        // automatic tiering is enabled, trigger an oneshot eviction, wait
+        // for it to complete (bounded), then retry. If automatic tiering
+        // is disabled (`tier_period < 0`, see D15), return ENOSPC straight
+        // away — no surprise multi-second blocking.
+        let mut attempts = 0u32;
+        loop {
+            match backend.write_at(&bpath, offset as u64, data) {
+                Ok(n) => {
+                    self.settle_growth(&logical, reservation, Some(offset as u64 + n as u64));
+                    self.mark_written(fh);
+                    let end = offset as u64 + n as u64;
+                    self.trace_access(TraceOp::Write, &logical, end, || {
+                        self.tier_of(backend.as_ref())
+                    });
+                    if let Some(t) = &self.access {
+                        t.record(logical, SystemTime::now());
+                    }
+                    reply.written(n);
+                    return;
+                }
+                Err(e) => {
+                    let is_enospc = e.to_errno() == libc::ENOSPC;
+                    if !is_enospc || attempts >= 1 || self.policy.tier_period().is_none() {
+                        let e = e.with_context(self.failed_on("write", backend.as_ref(), &bpath));
+                        if !is_enospc {
+                            error!(backend = backend.id(), offset, len = data.len(), "{e}");
+                        }
+                        self.settle_growth(&logical, reservation, None);
+                        reply.fail(&e);
+                        return;
+                    }
+                    attempts += 1;
+                    warn!(
+                        "write ENOSPC on {}; triggering emergency tiering",
+                        bpath.display()
+                    );
+                    if let Some(t) = &self.tierer {
+                        t.trigger_oneshot();
+                        let _ = t.wait_idle(Duration::from_secs(30));
+                    }
+                    // Loop and retry.
+                }
+            }
+        }
+    }
+
+    fn do_copy_file_range(
+        &self,
+        fh_in: u64,
+        offset_in: i64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        reply: Audited<ReplyWrite>,
+    ) {
+        let (Some((src, src_path, _)), Some((dst, dst_path, logical_out))) =
+            (self.fh(fh_in), self.fh(fh_out))
+        else {
+            reply.error(EBADF);
+            return;
+        };
+        // The reply carries a u32 byte count; the kernel loops for the rest.
+        let len = len.min(u32::MAX as u64);
+        let (off_in, off_out) = (offset_in as u64, offset_out as u64);
+        let reservation = match self.reserve_growth(&logical_out, &dst, &dst_path, off_out + len) {
+            Ok(r) => r,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
+        };
+        let result = if src.id() == dst.id() {
+            // Whole-file copy into an empty file: let the backend reflink.
+            let whole = off_in == 0
+                && off_out == 0
+                && dst
+                    .metadata(&dst_path)
+                    .map(|m| m.size == 0)
+                    .unwrap_or(false)
+                && src
+                    .metadata(&src_path)
+                    .map(|m| len >= m.size)
+                    .unwrap_or(false);
+            if whole {
+                src.copy(&src_path, &dst_path)
+                    .and_then(|()| src.metadata(&src_path).map(|m| m.size))
+            } else {
+                src.copy_range(&src_path, off_in, &dst_path, off_out, len)
+            }
+        } else {
+            copy_between(
+                src.as_ref(),
+                &src_path,
+                off_in,
+                dst.as_ref(),
+                &dst_path,
+                off_out,
+                len,
+            )
+        };
+        self.settle_growth(
+            &logical_out,
+            reservation,
+            result.as_ref().ok().map(|n| off_out + n),
+        );
+        match result {
+            Ok(n) => {
+                self.mark_written(fh_out);
+                self.trace_access(TraceOp::Write, &logical_out, off_out + n, || {
+                    self.tier_of(dst.as_ref())
+                });
+                if let Some(t) = &self.access {
+                    t.record(logical_out, SystemTime::now());
+                }
+                reply.written(n as u32);
+            }
+            Err(e) => {
+                let e = e.with_context(self.failed_on("copy_file_range", dst.as_ref(), &dst_path));
+                error!("{e} (from {} on {})", src_path.display(), src.id());
+                reply.fail(&e);
+            }
+        }
+    }
+
+    fn do_fallocate(
+        &self,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: Audited<ReplyEmpty>,
+    ) {
+        let Some((backend, bpath, logical)) = self.fh(fh) else {
+            reply.error(EBADF);
+            return;
+        };
+        if offset < 0 || length <= 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        let end = offset as u64 + length as u64;
+        // FALLOC_FL_KEEP_SIZE (also implied by PUNCH_HOLE): size unchanged.
+        let reservation = if mode & 0x01 == 0 {
+            match self.reserve_growth(&logical, &backend, &bpath, end) {
+                Ok(r) => r,
+                Err(e) => {
+                    reply.error(e.to_errno());
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        let result = backend.allocate(&bpath, offset as u64, length as u64, mode);
+        self.settle_growth(&logical, reservation, result.as_ref().ok().map(|()| end));
+        match result {
+            Ok(()) => {
+                self.mark_written(fh);
+                reply.ok()
+            }
+            Err(e) => {
+                let quiet = matches!(e, FsError::Unsupported(_) | FsError::NoSpace(_));
+                let e = e.with_context(self.failed_on("fallocate", backend.as_ref(), &bpath));
+                if !quiet {
+                    error!(
+                        backend = backend.id(),
+                        mode = format_args!("{mode:#x}"),
+                        "{e}"
+                    );
+                }
+                reply.fail(&e);
+            }
+        }
+    }
+
+    fn do_lseek(&self, fh: u64, offset: i64, whence: i32, reply: ReplyLseek) {
+        let Some((backend, bpath, _)) = self.fh(fh) else {
+            reply.error(EBADF);
+            return;
+        };
+        if offset < 0 {
+            reply.error(libc::ENXIO);
+            return;
+        }
+        // The kernel resolves SEEK_SET/CUR/END itself; only hole/data
+        // queries reach us.
+        let result = match whence {
+            libc::SEEK_DATA => match backend.next_data(&bpath, offset as u64) {
+                Ok(Some(pos)) => Ok(pos),
+                Ok(None) => Err(FsError::Io(std::io::Error::from_raw_os_error(libc::ENXIO))),
+                Err(e) => Err(e),
+            },
+            libc::SEEK_HOLE => backend.next_hole(&bpath, offset as u64),
+            _ => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+        match result {
+            Ok(pos) => reply.offset(pos as i64),
+            Err(e) => reply.error(e.to_errno()),
+        }
+    }
+
+    /// `RHSS_IOC_BACKGROUND`; any other ioctl is ENOTTY.
+    fn do_ioctl(&self, fh: u64, cmd: u32, in_data: &[u8], reply: ReplyIoctl) {
+        if cmd != RHSS_IOC_BACKGROUND {
+            reply.error(libc::ENOTTY);
+            return;
+        }
+        let Ok(arg) = <[u8; 4]>::try_from(in_data) else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+        match self.fh_table.lock().get_mut(&fh) {
+            Some(e) => e.background = i32::from_ne_bytes(arg) != 0,
+            None => {
+                reply.error(EBADF);
+                return;
+            }
+        }
+        reply.ioctl(0, &[]);
+    }
+
+    /// Where reads and writes of `pid` on `fh` are queued.
+    /// The caller's priority, or `None` if that takes a read of `/proc`
+    /// (`PidPriorities::of`), which is left to a worker.
+    fn priority(&self, pid: u32, fh: u64) -> Option<Priority> {
+        let marked = self.fh_table.lock().get(&fh).is_some_and(|e| e.background);
+        if marked {
+            Some(Priority::Background)
+        } else {
+            self.priorities.cached(pid)
+        }
+    }
+
+    fn do_fsync(&self, fh: u64, reply: ReplyEmpty) {
+        let Some((backend, bpath, _)) = self.fh(fh) else {
+            reply.error(EBADF);
+            return;
+        };
+        match backend.fsync(&bpath) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.to_errno()),
+        }
+    }
+
+    fn do_flush(&self, fh: u64, reply: ReplyEmpty) {
+        // Mac apps frequently call close()/flush. fsync is the safer thing
+        // to do; F_FULLFSYNC is reserved for the migrate path (D4 P3).
+        let Some((backend, bpath, _)) = self.fh(fh) else {
+            reply.ok();
+            return;
+        };
+        if backend.fsync(&bpath).is_ok() {
+            self.finish_create(fh);
+        }
+        reply.ok();
+    }
+}
+
+/// Cache counters reported by `rhss status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FuseStats {
+    pub inodes: usize,
+    pub inode_limit: usize,
+    pub open_handles: usize,
+}
+
+/// A kernel request on its way to the worker pool: its span, and its op
+/// name for the profiler.
+struct OpTrace {
+    span: Span,
+    op: &'static str,
+}
+
+/// Top-level FUSE adapter.
+#[derive(Clone)]
+pub struct FuseAdapter {
+    state: Arc<FuseState>,
+}
+
+impl FuseAdapter {
+    pub fn new(
+        router: Arc<TierRouter>,
+        index: Arc<dyn PathIndex>,
+        policy: Arc<dyn TieringPolicy>,
+        open_tracker: Arc<OpenFileTracker>,
+        tierer: Option<TiererHandle>,
+        access: Option<AccessTracker>,
+        config: FuseConfig,
+    ) -> Self {
+        let pool = WorkerPool::new(config.workers);
+        let ids = config.ids.clone();
+        let modes = config.modes;
+        Self {
+            state: Arc::new(FuseState {
+                router,
+                index,
+                policy,
+                open_tracker,
+                tierer,
+                access,
+                inodes: Mutex::new(InodeMap::new(config.max_inodes)),
+                fh_table: Mutex::new(HashMap::new()),
+                next_fh: AtomicU64::new(1),
+                config: RwLock::new(config),
+                ids,
+                modes,
+                running: AtomicBool::new(true),
+                pool,
+                namespace_order: NamespaceOrder::new(),
+                priorities: PidPriorities::new(),
+                notifier: OnceLock::new(),
+            }),
+        }
+    }
+
+    pub fn mount(&self, mount_point: &Path) -> std::io::Result<()> {
+        info!("mounting rhss at {}", mount_point.display());
+        fuser::mount2(
+            self.clone(),
+            mount_point,
+            &self.state.config.read().mount_options(),
+        )?;
+        Ok(())
+    }
+
+    pub fn spawn_mount(&self, mount_point: &Path) -> std::io::Result<fuser::BackgroundSession> {
+        info!(
+            "mounting rhss at {} ({} I/O workers)",
+            mount_point.display(),
+            self.state.config.read().workers
+        );
+        fuser::spawn_mount2(
+            self.clone(),
+            mount_point,
+            &self.state.config.read().mount_options(),
+        )
+    }
+
+    /// Hand an op to the worker pool. The closure owns the `Reply*`, so the
+    /// session thread returns immediately and never waits on a backend.
+    /// The op runs inside `span`, so everything it logs carries the request.
+    /// Sampled ops are timed for `crate::profile`.
+    /// Run `op` on a worker once the namespace changes queued before it
+    /// are done (`ordering`). Until then it is parked, not on a worker.
+    fn dispatch(&self, trace: OpTrace, op: impl FnOnce(&FuseState) + Send + 'static) {
+        let barrier = self.state.namespace_order.barrier();
+        let this = self.clone();
+        let job = Box::new(move || this.dispatch_at(Priority::Normal, trace, op));
+        if let Some(job) = self.state.namespace_order.after(barrier, job) {
+            job();
+        }
+    }
+
+    /// `dispatch` for ops that change the namespace: create, mkdir,
+    /// unlink, rmdir, rename, setattr. They run one at a time, in order.
+    fn dispatch_ns(&self, trace: OpTrace, op: impl FnOnce(&FuseState) + Send + 'static) {
+        let seq = self.state.namespace_order.queue();
+        let state = Arc::clone(&self.state);
+        self.state.pool.spawn_serial(move || {
+            let _profiled = crate::profile::op(trace.op);
+            trace.span.in_scope(|| op(&state));
+            for job in state.namespace_order.finish(seq) {
+                job();
+            }
+        });
+    }
+
+    /// `dispatch_at` once `pid`'s priority is known: a worker looks it up
+    /// first when the session thread couldn't (`FuseState::priority`).
+    fn dispatch_for(
+        &self,
+        pid: u32,
+        priority: Option<Priority>,
+        trace: OpTrace,
+        op: impl FnOnce(&FuseState) + Send + 'static,
+    ) {
+        if let Some(priority) = priority {
+            return self.dispatch_at(priority, trace, op);
+        }
+        let this = self.clone();
+        self.state.pool.spawn(move || {
+            let priority = this.state.priorities.of(pid);
+            this.dispatch_at(priority, trace, op)
+        });
+    }
+
+    fn dispatch_at(
+        &self,
+        priority: Priority,
+        trace: OpTrace,
+        op: impl FnOnce(&FuseState) + Send + 'static,
+    ) {
+        let state = Arc::clone(&self.state);
+        let job = move || {
+            let _profiled = crate::profile::op(trace.op);
+            trace.span.in_scope(|| op(&state))
+        };
+        match priority {
+            Priority::Normal => self.state.pool.spawn(job),
+            Priority::Background => self.state.pool.spawn_background(job),
+        }
+    }
+
+    /// `dispatch` for a read or write of `bytes` by `req`'s caller: on the
+    /// background queue if it asked for that (`priority`), and held back
+    /// on the pool's timer first while its uid or tenant is over its
+    /// `[throttle]` ceiling (`crate::throttle::IoLimits`).
+    fn dispatch_io(
+        &self,
+        req: &Request,
+        trace: OpTrace,
+        fh: u64,
+        bytes: u64,
+        op: impl FnOnce(&FuseState) + Send + 'static,
+    ) {
+        let pid = req.pid();
+        let priority = self.state.priority(pid, fh);
+        let Some(limits) = crate::throttle::io_limits() else {
+            return self.dispatch_for(pid, priority, trace, op);
+        };
+        let path = if limits.by_path() {
+            self.state.fh_path(fh)
+        } else {
+            None
+        };
+        let delay = limits.delay(req.uid(), path.as_deref(), bytes);
+        if delay.is_zero() {
+            return self.dispatch_for(pid, priority, trace, op);
+        }
+        let this = self.clone();
+        self.state
+            .pool
+            .spawn_after(delay, move || this.dispatch_for(pid, priority, trace, op));
+    }
+
+    /// The tracing span for one kernel request: op, request id (the
+    /// kernel's `unique`), inode, file handle and caller. Debug level, so
+    /// it costs nothing unless enabled; the path is only resolved then.
+    /// Backend calls made by the op open child spans (see
+    /// `crate::backend::timeout`).
+    fn op_span(
+        &self,
+        op: &'static str,
+        req: &Request,
+        ino: u64,
+        fh: Option<u64>,
+        name: Option<&OsStr>,
+    ) -> OpTrace {
+        let span = debug_span!(
+            "fuse",
+            op,
+            req = req.unique(),
+            ino,
+            fh,
+            uid = req.uid(),
+            path = field::Empty,
+        );
+        if !span.is_disabled() {
+            let path = match name {
+                Some(name) => self.state.path_for(ino, name).ok(),
+                None => fh
+                    .and_then(|fh| self.state.fh_path(fh))
+                    .or_else(|| self.state.inodes.lock().lookup_path(ino)),
+            };
+            if let Some(path) = path {
+                span.record("path", field::display(path.display()));
+            }
+        }
+        OpTrace { span, op }
+    }
+
+    /// Hook up kernel cache invalidation once the session exists
+    /// (`BackgroundSession::notifier`). Also registers the tierer change
+    /// hook so migrations invalidate the migrated path.
+    pub fn attach_notifier(&self, notifier: Notifier) {
+        if self.state.notifier.set(notifier).is_err() {
+            return;
+        }
+        let state = Arc::downgrade(&self.state);
+        crate::tierer::set_change_hook(Some(Arc::new(move |logical: &Path| {
+            if let Some(state) = state.upgrade() {
+                state.invalidate(logical);
+            }
+        })));
+    }
+
+    pub fn stats(&self) -> FuseStats {
+        FuseStats {
+            inodes: self.state.inodes.lock().len(),
+            inode_limit: self.state.config.read().max_inodes,
+            open_handles: self.state.fh_table.lock().len(),
+        }
+    }
+
+    /// Apply the reloadable parts of `new` (see `FuseConfig::apply_reload`)
+    /// to the live mount.
+    pub fn reload(&self, new: &FuseConfig) {
+        self.state.config.write().apply_reload(new);
+        info!("fuse config reloaded");
+    }
+
+    pub fn stop(&self) {
+        crate::tierer::set_change_hook(None);
+        self.state.running.store(false, Ordering::SeqCst);
+        info!("rhss stop requested");
+    }
+}
+
+impl Filesystem for FuseAdapter {
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let trace = self.op_span("lookup", req, parent, None, Some(name));
+        let name = name.to_os_string();
+        self.dispatch(trace, move |st| st.do_lookup(parent, &name, reply));
+    }
+
+    fn getattr(&mut self, req: &Request, ino: u64, fh: Option<u64>, reply: ReplyAttr) {
+        let trace = self.op_span("getattr", req, ino, fh, None);
+        self.dispatch(trace, move |st| st.do_getattr(ino, reply));
+    }
+
+    fn read(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let trace = self.op_span("read", req, ino, Some(fh), None);
+        self.dispatch_io(req, trace, fh, size.into(), move |st| {
+            st.do_read(fh, offset, size, reply)
+        });
+    }
+
+    fn write(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let trace = self.op_span("write", req, ino, Some(fh), None);
+        // The kernel buffer is only borrowed for this callback.
+        let data = data.to_vec();
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch_io(req, trace, fh, data.len() as u64, move |st| {
+            let span = st.audit_span("write", uid, gid, || st.fh_path(fh));
+            st.do_write(fh, offset, &data, Audited::new(reply, span))
+        });
+    }
+
+    fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let trace = self.op_span("open", req, ino, None, None);
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch(trace, move |st| st.do_open(ino, uid, gid, flags, reply));
+    }
+
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        let trace = self.op_span("access", req, ino, None, None);
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch(trace, move |st| st.do_access(ino, uid, gid, mask, reply));
+    }
+
+    fn release(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let trace = self.op_span("release", req, ino, Some(fh), None);
+        let Some(entry) = self.state.release_fh(fh) else {
+            reply.ok();
+            return;
+        };
+        if !entry.written && !entry.creating {
+            self.state.open_tracker.release(&entry.logical);
+            reply.ok();
+            return;
+        }
+        // Stat + index write: off the session thread.
+        self.dispatch(trace, move |st| {
+            if entry.written {
+                st.sync_size(&entry.logical, &entry.backend, &entry.backend_path);
+            }
+            if entry.creating {
+                st.mark_created(&entry.logical);
+            }
+            st.open_tracker.release(&entry.logical);
+            reply.ok();
+        });
+    }
+
+    fn create(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let trace = self.op_span("create", req, parent, None, Some(name));
+        let name = name.to_os_string();
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch_ns(trace, move |st| {
+            let span = st.audit_span("create", uid, gid, || st.path_for(parent, &name).ok());
+            st.do_create(parent, &name, mode, uid, gid, Audited::new(reply, span))
+        });
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let trace = self.op_span("mkdir", req, parent, None, Some(name));
+        let name = name.to_os_string();
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch_ns(trace, move |st| {
+            let span = st.audit_span("mkdir", uid, gid, || st.path_for(parent, &name).ok());
+            st.do_mkdir(parent, &name, mode, uid, gid, Audited::new(reply, span))
+        });
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let trace = self.op_span("unlink", req, parent, None, Some(name));
+        let name = name.to_os_string();
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch_ns(trace, move |st| {
+            let span = st.audit_span("unlink", uid, gid, || st.path_for(parent, &name).ok());
+            st.do_unlink(parent, &name, uid, gid, Audited::new(reply, span))
+        });
+    }
+
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let trace = self.op_span("rmdir", req, parent, None, Some(name));
+        let name = name.to_os_string();
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch_ns(trace, move |st| {
+            let span = st.audit_span("rmdir", uid, gid, || st.path_for(parent, &name).ok());
+            st.do_rmdir(parent, &name, uid, gid, Audited::new(reply, span))
+        });
+    }
+
+    fn readdir(&mut self, req: &Request, ino: u64, _fh: u64, offset: i64, reply: ReplyDirectory) {
+        let trace = self.op_span("readdir", req, ino, None, None);
+        self.dispatch(trace, move |st| st.do_readdir(ino, offset, reply));
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        new_uid: Option<u32>,
+        new_gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let trace = self.op_span("setattr", req, ino, fh, None);
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch_ns(trace, move |st| {
+            let span = st.audit_span("setattr", uid, gid, || st.inodes.lock().lookup_path(ino));
+            let reply = Audited::new(reply, span);
+            if let Err(e) = st.do_chown(ino, fh, new_uid, new_gid, st.creds(uid, gid)) {
+                reply.fail(&e);
+                return;
+            }
+            st.do_setattr(ino, mode, size, atime, mtime, fh, reply)
+        });
+    }
+
+    fn rename(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let trace = self.op_span("rename", req, parent, None, Some(name));
+        let name = name.to_os_string();
+        let new_name = new_name.to_os_string();
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch_ns(trace, move |st| {
+            let span = st
+                .audit_span("rename", uid, gid, || st.path_for(parent, &name).ok())
+                .map(|s| s.with_target(st.path_for(new_parent, &new_name).unwrap_or_default()));
+            st.do_rename(
+                parent,
+                &name,
+                new_parent,
+                &new_name,
+                Audited::new(reply, span),
+            )
+        });
+    }
+
+    // forget carries no reply and only touches the in-memory map, so it
+    // stays on the session thread.
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        self.state.inodes.lock().forget(ino, nlookup);
+    }
+
+    fn batch_forget(&mut self, _req: &Request, nodes: &[fuse_forget_one]) {
+        let mut inodes = self.state.inodes.lock();
+        for n in nodes {
+            inodes.forget(n.nodeid, n.nlookup);
+        }
+        debug!(
+            "batch_forget {} nodes, {} inodes live",
+            nodes.len(),
+            inodes.len()
+        );
+    }
+
+    fn fsync(&mut self, req: &Request, ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        let trace = self.op_span("fsync", req, ino, Some(fh), None);
+        self.dispatch(trace, move |st| st.do_fsync(fh, reply));
+    }
+
+    fn flush(&mut self, req: &Request, ino: u64, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        let trace = self.op_span("flush", req, ino, Some(fh), None);
+        self.dispatch(trace, move |st| st.do_flush(fh, reply));
+    }
+
+    fn fallocate(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        let trace = self.op_span("fallocate", req, ino, Some(fh), None);
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch(trace, move |st| {
+            let span = st.audit_span("fallocate", uid, gid, || st.fh_path(fh));
+            st.do_fallocate(fh, offset, length, mode, Audited::new(reply, span))
+        });
+    }
+
+    fn lseek(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        let trace = self.op_span("lseek", req, ino, Some(fh), None);
+        self.dispatch(trace, move |st| st.do_lseek(fh, offset, whence, reply));
+    }
+
+    fn copy_file_range(
+        &mut self,
+        req: &Request,
+        _ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let trace = self.op_span("copy_file_range", req, ino_out, Some(fh_out), None);
+        let (uid, gid) = (req.uid(), req.gid());
+        self.dispatch(trace, move |st| {
+            let span = st.audit_span("copy_file_range", uid, gid, || st.fh_path(fh_out));
+            let reply = Audited::new(reply, span);
+            st.do_copy_file_range(fh_in, offset_in, fh_out, offset_out, len, reply)
+        });
+    }
+
+    fn ioctl(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        let trace = self.op_span("ioctl", req, ino, Some(fh), None);
+        let in_data = in_data.to_vec();
+        self.dispatch(trace, move |st| st.do_ioctl(fh, cmd, &in_data, reply));
+    }
+
+    fn statfs(&mut self, req: &Request, ino: u64, reply: ReplyStatfs) {
+        let trace = self.op_span("statfs", req, ino, None, None);
+        self.dispatch(trace, move |st| st.do_statfs(reply));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use crate::index::SqlitePathIndex;
+    use crate::index::{FileState, Mutability};
+    use crate::policy::PopularityPolicy;
+    use crate::tier::{MostFreePlacement, Tier};
+    use crossbeam_channel::{unbounded, Receiver, Sender};
+    use tempfile::TempDir;
+
+    fn adapter(dir: &Path, workers: usize) -> FuseAdapter {
+        let backend = |id: &str| -> Arc<dyn Backend> {
+            std::fs::create_dir_all(dir.join(id)).unwrap();
+            Arc::new(PosixBackend::new(id, dir.join(id)).unwrap())
+        };
+        let router = TierRouter::new(
+            Tier::new(
+                TierId::Fast,
+                vec![backend("ssd")],
+                Box::new(MostFreePlacement),
+            )
+            .unwrap(),
+            Tier::new(
+                TierId::Slow,
+                vec![backend("hdd")],
+                Box::new(MostFreePlacement),
+            )
+            .unwrap(),
+        );
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(dir.join("i.db")).unwrap();
+        FuseAdapter::new(
+            Arc::new(router),
+            index,
+            Arc::new(PopularityPolicy::default()),
+            Arc::new(OpenFileTracker::new()),
+            None,
+            None,
+            FuseConfig::new().with_workers(workers),
+        )
+    }
+
+    fn trace(op: &'static str) -> OpTrace {
+        OpTrace {
+            span: Span::none(),
+            op,
+        }
+    }
+
+    fn row(path: &str) -> FileRow {
+        FileRow {
+            logical_path: path.into(),
+            location: Location {
+                tier: TierId::Fast,
+                backend_id: "ssd".into(),
+                backend_path: path.trim_start_matches('/').into(),
+                size: 0,
+            },
+            replicas: Vec::new(),
+            last_access: SystemTime::now(),
+            hit_count: 0,
+            popularity: 0.0,
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            mutability: Mutability::Unknown,
+            compressed: false,
+            content_hash: None,
+        }
+    }
+
+    type Log = Sender<(&'static str, bool)>;
+
+    fn collect(rx: &Receiver<(&'static str, bool)>, n: usize) -> Vec<(&'static str, bool)> {
+        (0..n)
+            .map(|_| rx.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect()
+    }
+
+    // The handlers' `Reply*` types can't be built outside fuser, so these
+    // drive the dispatch paths with ops on the index the handlers use; the
+    // mount test further down goes through the handlers themselves.
+
+    #[test]
+    fn namespace_changes_run_in_order_before_later_lookups() {
+        let dir = TempDir::new().unwrap();
+        let fs = adapter(dir.path(), 4);
+        let (tx, rx) = unbounded();
+        // Hold the serial lane so everything below is queued at once.
+        let (open_gate, gate) = unbounded::<()>();
+        fs.dispatch_ns(trace("gate"), move |_| gate.recv().unwrap());
+
+        let change = |tag: &'static str, op: fn(&dyn PathIndex) -> Result<()>| {
+            let tx: Log = tx.clone();
+            fs.dispatch_ns(trace(tag), move |st| {
+                tx.send((tag, op(st.index.as_ref()).is_ok())).unwrap()
+            });
+        };
+        let lookup = |tag: &'static str, path: &'static str| {
+            let tx: Log = tx.clone();
+            fs.dispatch(trace(tag), move |st| {
+                let found = st.index.get(Path::new(path)).unwrap().is_some();
+                tx.send((tag, found)).unwrap()
+            });
+        };
+        change("create a", |i| i.insert(row("/a")));
+        change("unlink a", |i| i.remove(Path::new("/a")));
+        lookup("lookup a", "/a");
+        change("create b", |i| i.insert(row("/b")));
+        change("rename b c", |i| i.rename(Path::new("/b"), Path::new("/c")));
+        lookup("lookup b", "/b");
+        lookup("lookup c", "/c");
+
+        // The lookups wait for the changes queued before them.
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        open_gate.send(()).unwrap();
+        let mut got = collect(&rx, 7);
+        let changes: Vec<_> = got.iter().filter(|r| !r.0.starts_with("lookup")).collect();
+        assert_eq!(
+            changes,
+            [
+                &("create a", true),
+                &("unlink a", true),
+                &("create b", true),
+                &("rename b c", true)
+            ]
+        );
+        got.retain(|r| r.0.starts_with("lookup"));
+        got.sort();
+        assert_eq!(
+            got,
+            [("lookup a", false), ("lookup b", false), ("lookup c", true)]
+        );
+    }
+
+    #[test]
+    fn slow_read_does_not_hold_up_lookups() {
+        let dir = TempDir::new().unwrap();
+        let fs = adapter(dir.path(), 2);
+        fs.state.index.insert(row("/f")).unwrap();
+        let (tx, rx) = unbounded();
+        // A read stuck on a cold disk, queued the way `dispatch_io` does.
+        let (unstick, stuck) = unbounded::<()>();
+        fs.dispatch_at(Priority::Normal, trace("read"), move |_| {
+            stuck.recv().unwrap()
+        });
+        let lookup_tx: Log = tx.clone();
+        fs.dispatch(trace("lookup"), move |st| {
+            let found = st.index.get(Path::new("/f")).unwrap().is_some();
+            lookup_tx.send(("lookup f", found)).unwrap()
+        });
+        let getattr_tx: Log = tx.clone();
+        fs.dispatch(trace("getattr"), move |st| {
+            let root = st.inodes.lock().lookup_path(FUSE_ROOT_ID);
+            getattr_tx.send(("getattr /", root.is_some())).unwrap()
+        });
+        let mut got = collect(&rx, 2);
+        got.sort();
+        assert_eq!(got, [("getattr /", true), ("lookup f", true)]);
+        unstick.send(()).unwrap();
+    }
+
+    #[test]
+    fn parked_ops_leave_the_workers_to_reads_through_a_mount() {
+        let dir = TempDir::new().unwrap();
+        let fs = adapter(dir.path(), 2);
+        for name in ["f", "g"] {
+            std::fs::write(dir.path().join("ssd").join(name), b"data").unwrap();
+            let mut r = row(&format!("/{name}"));
+            r.location.size = 4;
+            fs.state.index.insert(r).unwrap();
+        }
+        let mnt = dir.path().join("mnt");
+        std::fs::create_dir(&mnt).unwrap();
+        // A plain mount(2), without `AutoUnmount` and so without fusermount.
+        let opts = [MountOption::FSName("rhss-test".into())];
+        let session = match fuser::spawn_mount2(fs.clone(), &mnt, &opts) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("skipping: cannot mount FUSE here: {e}");
+                return;
+            }
+        };
+        let mut f = std::fs::File::open(mnt.join("f")).unwrap();
+
+        // Hold the serial lane, as a rename stuck on a slow disk would.
+        let (open_gate, gate) = unbounded::<()>();
+        fs.dispatch_ns(trace("gate"), move |_| gate.recv().unwrap());
+        let (tx, rx) = unbounded();
+        let chmod_tx = tx.clone();
+        let g = mnt.join("g");
+        std::thread::spawn(move || {
+            let mode = std::os::unix::fs::PermissionsExt::from_mode(0o600);
+            std::fs::set_permissions(&g, mode).unwrap();
+            chmod_tx.send("chmod g").unwrap()
+        });
+        // More lookups than workers.
+        for i in 0..4 {
+            let (tx, missing) = (tx.clone(), mnt.join(format!("missing{i}")));
+            std::thread::spawn(move || {
+                assert!(std::fs::metadata(&missing).is_err());
+                tx.send("lookup").unwrap()
+            });
+        }
+        std::thread::sleep(Duration::from_millis(200));
+
+        // They wait behind the gate, but not on a worker: the read on the
+        // open handle still gets one.
+        let mut buf = [0u8; 4];
+        std::io::Read::read_exact(&mut f, &mut buf).unwrap();
+        assert_eq!(&buf, b"data");
+        assert!(rx.try_recv().is_err());
+        open_gate.send(()).unwrap();
+        let mut got: Vec<_> = (0..5)
+            .map(|_| rx.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect();
+        got.sort();
+        assert_eq!(got, ["chmod g", "lookup", "lookup", "lookup", "lookup"]);
+        drop(f);
+        drop(session);
+    }
+
+    #[test]
+    fn mount_options_honor_flags() {
+        let opts = FuseConfig::new()
+            .with_allow_other(false)
+            .with_allow_root(true)
+            .with_read_only(true)
+            .with_noexec(true)
+            .with_custom_options(vec!["uid=1000".into()])
+            .with_fsname(Some("rhss-media".into()))
+            .with_subtype(Some("rhss".into()))
+            .mount_options();
+        assert!(opts.contains(&MountOption::FSName("rhss-media".into())));
+        assert!(opts.contains(&MountOption::Subtype("rhss".into())));
+        assert!(opts.contains(&MountOption::AllowRoot));
+        assert!(!opts.contains(&MountOption::AllowOther));
+        assert!(opts.contains(&MountOption::RO));
+        assert!(opts.contains(&MountOption::NoExec));
+        assert!(opts.contains(&MountOption::CUSTOM("uid=1000".into())));
+    }
+
+    #[test]
+    fn mount_names_reject_option_separators() {
+        assert_eq!(parse_mount_name("rhss:/data").unwrap(), "rhss:/data");
+        for bad in ["", "a,b", "a b", "a\n"] {
+            assert!(parse_mount_name(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn allow_other_wins_over_allow_root() {
+        let opts = FuseConfig::new()
+            .with_allow_other(true)
+            .with_allow_root(true)
+            .mount_options();
+        assert!(opts.contains(&MountOption::AllowOther));
+        assert!(!opts.contains(&MountOption::AllowRoot));
+    }
+
+    #[test]
+    fn forget_releases_inode_after_last_reference() {
+        let mut m = InodeMap::new(16);
+        let ino = m.lookup_ref(PathBuf::from("/a"));
+        assert_eq!(m.lookup_ref(PathBuf::from("/a")), ino);
+        m.forget(ino, 1);
+        assert_eq!(m.lookup_path(ino), Some(PathBuf::from("/a")));
+        m.forget(ino, 1);
+        assert_eq!(m.lookup_path(ino), None);
+        // Root is never forgotten.
+        m.forget(FUSE_ROOT_ID, 10);
+        assert_eq!(m.lookup_path(FUSE_ROOT_ID), Some(PathBuf::from("/")));
+    }
+
+    #[test]
+    fn rename_moves_descendants() {
+        let mut m = InodeMap::new(64);
+        let mut deep = PathBuf::from("/a");
+        let mut inos = vec![m.lookup_ref(deep.clone())];
+        for level in 0..10 {
+            deep.push(format!("d{level}"));
+            inos.push(m.lookup_ref(deep.clone()));
+        }
+        let sibling = m.lookup_ref(PathBuf::from("/ab"));
+        m.rename(Path::new("/a"), PathBuf::from("/z/a"));
+        assert_eq!(m.lookup_path(inos[0]), Some(PathBuf::from("/z/a")));
+        let moved = Path::new("/z").join(deep.strip_prefix("/").unwrap());
+        assert_eq!(m.lookup_path(inos[10]), Some(moved.clone()));
+        assert_eq!(m.ino_of(&moved), Some(inos[10]));
+        assert_eq!(m.ino_of(&deep), None);
+        assert_eq!(m.lookup_path(sibling), Some(PathBuf::from("/ab")));
+    }
+
+    #[test]
+    fn check_name_rejects_escapes() {
+        for bad in ["", ".", "..", "a/b", "../etc", "/etc", "a\0b"] {
+            let err = check_name(OsStr::new(bad)).unwrap_err();
+            assert_eq!(err.to_errno(), libc::EINVAL, "{bad:?}");
+        }
+        for good in ["a", "..a", "a..", ".hidden", "with space"] {
+            check_name(OsStr::new(good)).unwrap();
+        }
+    }
+
+    #[test]
+    fn cap_evicts_unreferenced_lru_first() {
+        let mut m = InodeMap::new(3);
+        let held = m.lookup_ref(PathBuf::from("/held"));
+        let a = m.allocate(PathBuf::from("/a"));
+        let b = m.allocate(PathBuf::from("/b"));
+        // /held is the oldest non-root entry but the kernel references it.
+        assert_eq!(m.len(), 3);
+        assert_eq!(m.lookup_path(held), Some(PathBuf::from("/held")));
+        assert_eq!(m.lookup_path(a), None);
+        assert_eq!(m.lookup_path(b), Some(PathBuf::from("/b")));
+    }
 }
@@ -0,0 +1,134 @@
+//! Mount-table queries and unmount without shelling out.
+//!
+//! `is_mounted` reads `/proc/self/mountinfo` on Linux and `statfs` on
+//! macOS; `unmount` calls `umount2(MNT_DETACH)` / `unmount(MNT_FORCE)`
+//! directly. Normal shutdown never needs the latter — dropping the
+//! `BackgroundSession` unmounts — it's the fallback when the kernel still
+//! lists the mount afterwards.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// `path` with its parent canonicalised. The last component is kept as-is:
+/// `stat` on a dead FUSE mount fails with ENOTCONN, but we still need to
+/// name it.
+fn normalise(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => parent
+            .canonicalize()
+            .map(|p| p.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Undo mountinfo's octal escapes (`\040` for space, `\011` tab, `\012`
+/// newline, `\134` backslash).
+#[cfg(any(target_os = "linux", test))]
+fn unescape(field: &str) -> PathBuf {
+    use std::ffi::OsStr;
+    let b = field.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'\\' && i + 3 < b.len() && b[i + 1..=i + 3].iter().all(u8::is_ascii_digit) {
+            let oct = std::str::from_utf8(&b[i + 1..=i + 3]).unwrap_or("0");
+            if let Ok(v) = u8::from_str_radix(oct, 8) {
+                out.push(v);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(b[i]);
+        i += 1;
+    }
+    PathBuf::from(OsStr::from_bytes(&out))
+}
+
+/// Mount points listed in a `/proc/<pid>/mountinfo` body (field 5).
+#[cfg(any(target_os = "linux", test))]
+fn mount_points(mountinfo: &str) -> impl Iterator<Item = PathBuf> + '_ {
+    mountinfo
+        .lines()
+        .filter_map(|l| l.split(' ').nth(4))
+        .map(unescape)
+}
+
+/// Whether the kernel currently has something mounted at `path`.
+#[cfg(target_os = "linux")]
+pub fn is_mounted(path: &Path) -> io::Result<bool> {
+    let want = normalise(path);
+    let table = std::fs::read_to_string("/proc/self/mountinfo")?;
+    let found = mount_points(&table).any(|p| p == want);
+    Ok(found)
+}
+
+/// Whether the kernel currently has something mounted at `path`.
+#[cfg(target_os = "macos")]
+pub fn is_mounted(path: &Path) -> io::Result<bool> {
+    use std::ffi::CStr;
+    let want = normalise(path);
+    let c = c_path(&want)?;
+    let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c.as_ptr(), &mut st) } != 0 {
+        let e = io::Error::last_os_error();
+        // A FUSE mount whose daemon is gone answers ENXIO/ENOTCONN.
+        return match e.raw_os_error() {
+            Some(libc::ENXIO) | Some(libc::ENOTCONN) => Ok(true),
+            Some(libc::ENOENT) => Ok(false),
+            _ => Err(e),
+        };
+    }
+    let on = unsafe { CStr::from_ptr(st.f_mntonname.as_ptr()) };
+    Ok(Path::new(std::ffi::OsStr::from_bytes(on.to_bytes())) == want)
+}
+
+/// Detach whatever is mounted at `path`. Unprivileged Linux users may get
+/// `EPERM` here; fuser's own unmount (via `fusermount`) already ran by
+/// then, so the error is reported rather than retried.
+pub fn unmount(path: &Path) -> io::Result<()> {
+    let c = c_path(&normalise(path))?;
+    #[cfg(target_os = "linux")]
+    let rc = unsafe { libc::umount2(c.as_ptr(), libc::MNT_DETACH) };
+    #[cfg(target_os = "macos")]
+    let rc = unsafe { libc::unmount(c.as_ptr(), libc::MNT_FORCE) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mountinfo_with_escapes() {
+        let table = "\
+22 1 0:21 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+97 22 0:48 / /mnt/my\\040data rw,nosuid,nodev shared:50 - fuse.rhss rhss rw
+";
+        let points: Vec<PathBuf> = mount_points(table).collect();
+        assert_eq!(
+            points,
+            vec![PathBuf::from("/"), PathBuf::from("/mnt/my data")]
+        );
+        assert_eq!(unescape("a\\134b"), PathBuf::from("a\\b"));
+        assert_eq!(unescape("trail\\04"), PathBuf::from("trail\\04"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn plain_directory_is_not_a_mount_point() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_mounted(&dir.path().join("sub")).unwrap());
+        assert!(is_mounted(Path::new("/")).unwrap());
+    }
+}
@@ -0,0 +1,74 @@
+//! Ordering of namespace changes against the ops queued after them.
+//!
+//! Create, mkdir, unlink, rmdir, rename and setattr run one at a time on
+//! the pool's serial lane, in the order the kernel sent them
+//! (`WorkerPool::spawn_serial`). Every other op except reads and writes on
+//! an open handle is parked here until the changes queued before it are
+//! done, and only then handed to a worker. So `create` then `unlink` of a
+//! name can't swap, and a `lookup` sent after a `rename` sees the new name,
+//! while neither a slow read nor a slow rename ties up the workers.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::Mutex;
+
+pub(crate) type Job = Box<dyn FnOnce() + Send>;
+
+pub(crate) struct NamespaceOrder {
+    /// Namespace changes queued so far; only the session thread bumps it.
+    queued: AtomicU64,
+    parked: Mutex<Parked>,
+}
+
+struct Parked {
+    /// Namespace changes finished, in order.
+    done: u64,
+    /// Ops waiting for `done` to reach their barrier.
+    jobs: BTreeMap<u64, Vec<Job>>,
+}
+
+impl NamespaceOrder {
+    pub(crate) fn new() -> Self {
+        Self {
+            queued: AtomicU64::new(0),
+            parked: Mutex::new(Parked {
+                done: 0,
+                jobs: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Take the next sequence number for a namespace change.
+    pub(crate) fn queue(&self) -> u64 {
+        self.queued.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// What an op queued now has to wait for.
+    pub(crate) fn barrier(&self) -> u64 {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Change `seq` is done. Returns the ops it released, to be started.
+    pub(crate) fn finish(&self, seq: u64) -> Vec<Job> {
+        let mut parked = self.parked.lock();
+        let done = parked.done.max(seq);
+        parked.done = done;
+        let waiting = parked.jobs.split_off(&(done + 1));
+        std::mem::replace(&mut parked.jobs, waiting)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    /// `job` back if every change up to `barrier` is done; otherwise keep
+    /// it for the `finish` that gets there.
+    pub(crate) fn after(&self, barrier: u64, job: Job) -> Option<Job> {
+        let mut parked = self.parked.lock();
+        if parked.done >= barrier {
+            return Some(job);
+        }
+        parked.jobs.entry(barrier).or_default().push(job);
+        None
+    }
+}
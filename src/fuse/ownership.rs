@@ -0,0 +1,305 @@
+//! Whose credentials a request acts with, and who owns what it creates.
+//!
+//! With `allow_other`, many users share one mount served by one daemon.
+//! `IdMap::Passthrough` keeps each caller's uid/gid: permission checks use
+//! them and new files and directories are chowned to them (when the daemon
+//! runs as root). `IdMap::Squash` treats every caller as one fixed owner,
+//! like NFS `all_squash`.
+//!
+//! Separately, `IdTranslation` renumbers ids between the mount and the
+//! backing storage, like an idmapped mount: with `uid_map = [{ inside = 0,
+//! outside = 100000, count = 65536 }]`, files the storage has as uid 100000
+//! show up as root, and a file root creates or chowns lands as 100000.
+//! Storage ids outside the map show up as `OVERFLOW_ID`; callers outside
+//! it act as `OVERFLOW_ID`, and chown to an id outside it is `EINVAL`.
+//! Squashing, if configured, applies after translation.
+//!
+//! `Modes` does the same for permission bits: a fixed `file_mode` or
+//! `dir_mode` is what every regular file or directory reports and is
+//! created with, whatever the storage or the caller says, and `umask` is
+//! cleared from every new entry on top of the caller's own umask. `chmod`
+//! still reaches the storage, but a fixed mode keeps being reported.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{FsError, Result};
+
+/// What ids without a mapping turn into (`nobody`).
+pub const OVERFLOW_ID: u32 = 65534;
+
+/// `[fuse] id_map`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdMap {
+    /// Callers act as themselves.
+    #[default]
+    Passthrough,
+    /// Every caller acts as `uid`/`gid`.
+    Squash { uid: u32, gid: u32 },
+}
+
+impl IdMap {
+    /// Credentials a request from `uid`/`gid` acts with.
+    pub fn map(self, uid: u32, gid: u32) -> (u32, u32) {
+        match self {
+            IdMap::Passthrough => (uid, gid),
+            IdMap::Squash { uid, gid } => (uid, gid),
+        }
+    }
+}
+
+/// `count` consecutive ids starting at `inside` on the mount and at
+/// `outside` on the storage; the fields of a `/proc/PID/uid_map` line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdRange {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+/// `INSIDE:OUTSIDE:COUNT`, as `rhss mount --uid-map` takes it.
+impl FromStr for IdRange {
+    type Err = FsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad =
+            || FsError::InvalidOperation(format!("id map {s:?}: expected INSIDE:OUTSIDE:COUNT"));
+        let mut parts = s
+            .split(':')
+            .map(|p| p.trim().parse::<u32>().map_err(|_| bad()));
+        let range = IdRange {
+            inside: parts.next().ok_or_else(bad)??,
+            outside: parts.next().ok_or_else(bad)??,
+            count: parts.next().ok_or_else(bad)??,
+        };
+        if parts.next().is_some() {
+            return Err(bad());
+        }
+        Ok(range)
+    }
+}
+
+impl IdRange {
+    /// `id` moved from the `from` side to the other, if in range.
+    fn shift(&self, id: u32, from: u32, to: u32) -> Option<u32> {
+        let off = id.checked_sub(from)?;
+        (off < self.count).then(|| to + off)
+    }
+}
+
+/// `[fuse] uid_map` / `gid_map`. Empty maps leave ids as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdTranslation {
+    uids: Vec<IdRange>,
+    gids: Vec<IdRange>,
+}
+
+impl IdTranslation {
+    /// Refuses empty, overflowing or overlapping ranges, so every id maps
+    /// one-to-one.
+    pub fn new(uids: Vec<IdRange>, gids: Vec<IdRange>) -> Result<Self> {
+        for (what, ranges) in [("uid_map", &uids), ("gid_map", &gids)] {
+            check_ranges(what, ranges)?;
+        }
+        Ok(Self { uids, gids })
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.uids.is_empty() && self.gids.is_empty()
+    }
+
+    /// The storage uid for `uid` on the mount; `None` outside the map.
+    pub fn storage_uid(&self, uid: u32) -> Option<u32> {
+        inward(&self.uids, uid)
+    }
+
+    pub fn storage_gid(&self, gid: u32) -> Option<u32> {
+        inward(&self.gids, gid)
+    }
+
+    /// The uid the mount shows for storage uid `uid`.
+    pub fn mount_uid(&self, uid: u32) -> u32 {
+        outward(&self.uids, uid)
+    }
+
+    pub fn mount_gid(&self, gid: u32) -> u32 {
+        outward(&self.gids, gid)
+    }
+}
+
+fn inward(ranges: &[IdRange], id: u32) -> Option<u32> {
+    if ranges.is_empty() {
+        return Some(id);
+    }
+    ranges.iter().find_map(|r| r.shift(id, r.inside, r.outside))
+}
+
+fn outward(ranges: &[IdRange], id: u32) -> u32 {
+    if ranges.is_empty() {
+        return id;
+    }
+    ranges
+        .iter()
+        .find_map(|r| r.shift(id, r.outside, r.inside))
+        .unwrap_or(OVERFLOW_ID)
+}
+
+fn check_ranges(what: &str, ranges: &[IdRange]) -> Result<()> {
+    let end = |start: u32, r: &IdRange| u64::from(start) + u64::from(r.count);
+    for (i, r) in ranges.iter().enumerate() {
+        if r.count == 0 || end(r.inside, r) > 1 << 32 || end(r.outside, r) > 1 << 32 {
+            return Err(FsError::Storage(format!(
+                "{what}: {}:{}:{} is empty or runs past the last id",
+                r.inside, r.outside, r.count
+            )));
+        }
+        for o in &ranges[..i] {
+            let overlaps = |a: u32, b: u32| u64::from(a) < end(b, o) && u64::from(b) < end(a, r);
+            if overlaps(r.inside, o.inside) || overlaps(r.outside, o.outside) {
+                return Err(FsError::Storage(format!(
+                    "{what}: {}:{}:{} overlaps {}:{}:{}",
+                    r.inside, r.outside, r.count, o.inside, o.outside, o.count
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `[fuse] file_mode` / `dir_mode` / `umask`. The default changes nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modes {
+    pub file: Option<u32>,
+    pub dir: Option<u32>,
+    pub umask: u32,
+}
+
+impl Modes {
+    /// Refuses bits outside `0o7777` (`0o777` for the umask).
+    pub fn new(file: Option<u32>, dir: Option<u32>, umask: u32) -> Result<Self> {
+        for (what, mode, max) in [
+            ("file_mode", file, 0o7777),
+            ("dir_mode", dir, 0o7777),
+            ("umask", Some(umask), 0o777),
+        ] {
+            if let Some(m) = mode.filter(|m| *m > max) {
+                return Err(FsError::Storage(format!(
+                    "{what}: {m:#o} has bits outside {max:#o}"
+                )));
+            }
+        }
+        Ok(Self { file, dir, umask })
+    }
+
+    fn fixed(&self, is_dir: bool) -> Option<u32> {
+        if is_dir {
+            self.dir
+        } else {
+            self.file
+        }
+    }
+
+    /// Permission bits reported for an entry the storage has as `mode`.
+    pub fn reported(&self, is_dir: bool, mode: u32) -> u32 {
+        self.fixed(is_dir).unwrap_or(mode) & 0o7777
+    }
+
+    /// Permission bits a new entry gets when the caller asked for `mode`.
+    pub fn created(&self, is_dir: bool, mode: u32) -> u32 {
+        self.fixed(is_dir).unwrap_or(mode) & 0o7777 & !self.umask
+    }
+}
+
+/// An octal mode as written on the command line: `0644`, `0o644` or
+/// `644`.
+pub fn parse_mode(s: &str) -> Result<u32> {
+    let t = s.trim();
+    let digits = t.strip_prefix("0o").unwrap_or(t);
+    u32::from_str_radix(digits, 8).map_err(|_| {
+        FsError::InvalidOperation(format!("bad mode {s:?}: expected octal, e.g. 0644"))
+    })
+}
+
+/// Whether new entries should be chowned to `uid`/`gid`: only root can give
+/// files away, and entries already belong to the daemon user.
+pub(super) fn needs_chown(uid: u32, gid: u32) -> bool {
+    let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    euid == 0 && (uid, gid) != (euid, egid)
+}
+
+/// Sticky-directory rule for removing or renaming an entry: unless the
+/// caller is root, it must own the entry or the directory.
+pub(super) fn sticky_permits(dir_mode: u32, dir_uid: u32, entry_uid: u32, uid: u32) -> bool {
+    dir_mode & libc::S_ISVTX == 0 || uid == 0 || uid == entry_uid || uid == dir_uid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squash_replaces_every_caller() {
+        let squash = IdMap::Squash {
+            uid: 65534,
+            gid: 65534,
+        };
+        assert_eq!(squash.map(1000, 1000), (65534, 65534));
+        assert_eq!(squash.map(0, 0), (65534, 65534));
+        assert_eq!(IdMap::Passthrough.map(1000, 100), (1000, 100));
+    }
+
+    #[test]
+    fn modes_override_reported_and_created_bits() {
+        let modes = Modes::new(Some(0o664), None, 0o002).unwrap();
+        assert_eq!(modes.reported(false, 0o600), 0o664);
+        assert_eq!(modes.reported(true, 0o700), 0o700);
+        // The caller's mode is ignored for files, masked for directories.
+        assert_eq!(modes.created(false, libc::S_IFREG | 0o600), 0o664);
+        assert_eq!(modes.created(true, 0o777), 0o775);
+        assert_eq!(Modes::default().created(false, 0o644), 0o644);
+        assert!(Modes::new(Some(0o10000), None, 0).is_err());
+        assert!(Modes::new(None, None, 0o1000).is_err());
+        assert_eq!(parse_mode("0o755").unwrap(), 0o755);
+        assert_eq!(parse_mode("022").unwrap(), 0o22);
+        assert!(parse_mode("0689").is_err());
+    }
+
+    #[test]
+    fn translation_maps_ranges_both_ways() {
+        let ids = IdTranslation::new(
+            vec!["0:100000:1000".parse().unwrap()],
+            vec![
+                "0:100000:1000".parse().unwrap(),
+                "5000:5000:1".parse().unwrap(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(ids.storage_uid(0), Some(100000));
+        assert_eq!(ids.storage_uid(999), Some(100999));
+        assert_eq!(ids.storage_uid(1000), None);
+        assert_eq!(ids.mount_uid(100042), 42);
+        assert_eq!(ids.mount_uid(0), OVERFLOW_ID);
+        assert_eq!(ids.mount_gid(5000), 5000);
+        assert!(IdTranslation::default().is_identity());
+        assert_eq!(IdTranslation::default().mount_uid(7), 7);
+
+        let overlap = vec![
+            "0:100000:1000".parse().unwrap(),
+            "1000:100500:10".parse().unwrap(),
+        ];
+        assert!(IdTranslation::new(overlap, vec![]).is_err());
+        assert!(IdTranslation::new(vec!["0:4294967295:2".parse().unwrap()], vec![]).is_err());
+        assert!("0:1".parse::<IdRange>().is_err());
+    }
+
+    #[test]
+    fn sticky_dirs_protect_other_users_entries() {
+        let tmp = libc::S_IFDIR | 0o1777;
+        assert!(!sticky_permits(tmp, 0, 1001, 1000));
+        assert!(sticky_permits(tmp, 0, 1000, 1000));
+        assert!(sticky_permits(tmp, 1000, 1001, 1000));
+        assert!(sticky_permits(tmp, 0, 1001, 0));
+        assert!(sticky_permits(libc::S_IFDIR | 0o777, 0, 1001, 1000));
+    }
+}
@@ -0,0 +1,349 @@
+//! Fixed-size worker pool for FUSE ops (D12).
+//!
+//! fuser's session loop is single-threaded: whatever a callback does inline
+//! blocks every other request on the mount. Every callback that replies
+//! moves its `Reply*` into a job here and returns immediately; the worker
+//! replies when the backend call finishes.
+//!
+//! Jobs that have to wait first (`spawn_after`: a caller over its
+//! `[throttle]` ceiling) wait on a timer thread, not on a worker.
+//! Namespace changes run one at a time, in order, on a serial lane of
+//! their own (`spawn_serial`, see `super::ordering`).
+//! Background jobs (`spawn_background`, see `super::priority`) run only
+//! when no other job is queued, and on all but one worker at most; a pool
+//! of one worker keeps it for the others and gives background jobs a
+//! thread of their own.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Select, Sender, TryRecvError};
+use tracing::debug;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Default worker count. D12 asks for ≥ 4 so one 50 ms HDD seek doesn't
+/// stall the mount.
+pub const DEFAULT_WORKERS: usize = 4;
+
+pub(crate) struct WorkerPool {
+    tx: Sender<Job>,
+    background: Sender<Job>,
+    serial: Sender<Job>,
+    delayed: Sender<(Instant, Job)>,
+}
+
+struct Queues {
+    rx: Receiver<Job>,
+    background: Receiver<Job>,
+    /// Workers that may still wait on or run a background job.
+    slots: AtomicUsize,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        let (tx, rx) = unbounded::<Job>();
+        let (background, background_rx) = unbounded::<Job>();
+        let queues = Arc::new(Queues {
+            rx,
+            background: background_rx,
+            slots: AtomicUsize::new(workers - 1),
+        });
+        if workers == 1 {
+            let queues = Arc::clone(&queues);
+            thread::Builder::new()
+                .name("rhss-fuse-bg".into())
+                .spawn(move || {
+                    while let Ok(job) = queues.background.recv() {
+                        job();
+                    }
+                    debug!("fuse background lane exit");
+                })
+                .expect("spawn fuse background lane");
+        }
+        for i in 0..workers {
+            let queues = Arc::clone(&queues);
+            thread::Builder::new()
+                .name(format!("rhss-fuse-{i}"))
+                .spawn(move || {
+                    run_worker(&queues);
+                    debug!("fuse worker {i} exit");
+                })
+                .expect("spawn fuse worker");
+        }
+        let (serial, serial_rx) = unbounded::<Job>();
+        thread::Builder::new()
+            .name("rhss-fuse-ns".into())
+            .spawn(move || {
+                while let Ok(job) = serial_rx.recv() {
+                    job();
+                }
+                debug!("fuse serial lane exit");
+            })
+            .expect("spawn fuse serial lane");
+        let (delayed, timer_rx) = unbounded();
+        let timer_tx = tx.clone();
+        thread::Builder::new()
+            .name("rhss-fuse-timer".into())
+            .spawn(move || run_timer(timer_rx, timer_tx))
+            .expect("spawn fuse timer");
+        Self {
+            tx,
+            background,
+            serial,
+            delayed,
+        }
+    }
+
+    /// Queue a job. If every worker has already exited (shutdown), run it
+    /// inline so the kernel still gets its reply.
+    pub(crate) fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        if let Err(e) = self.tx.send(Box::new(job)) {
+            (e.into_inner())();
+        }
+    }
+
+    /// Queue a job to run after every earlier `spawn_serial` job has
+    /// finished.
+    pub(crate) fn spawn_serial(&self, job: impl FnOnce() + Send + 'static) {
+        if let Err(e) = self.serial.send(Box::new(job)) {
+            (e.into_inner())();
+        }
+    }
+
+    /// Queue a job behind every other one (`super::priority`).
+    pub(crate) fn spawn_background(&self, job: impl FnOnce() + Send + 'static) {
+        if let Err(e) = self.background.send(Box::new(job)) {
+            (e.into_inner())();
+        }
+    }
+
+    /// Queue a job once `delay` has passed. Jobs given the same or later
+    /// deadlines are queued in the order they were given.
+    pub(crate) fn spawn_after(&self, delay: Duration, job: impl FnOnce() + Send + 'static) {
+        if let Err(e) = self.delayed.send((Instant::now() + delay, Box::new(job))) {
+            (e.into_inner().1)();
+        }
+    }
+}
+
+fn run_worker(q: &Queues) {
+    let take = || {
+        q.slots
+            .fetch_update(atomic::Ordering::SeqCst, atomic::Ordering::SeqCst, |n| {
+                n.checked_sub(1)
+            })
+            .is_ok()
+    };
+    let give_back = || q.slots.fetch_add(1, atomic::Ordering::SeqCst);
+    let mut background_open = true;
+    loop {
+        if let Ok(job) = q.rx.try_recv() {
+            job();
+            continue;
+        }
+        // Waiting on the background queue takes a slot, held while the
+        // job runs, so some worker is always left for everything else.
+        if !background_open || !take() {
+            match q.rx.recv() {
+                Ok(job) => job(),
+                Err(_) => break,
+            }
+            continue;
+        }
+        let mut sel = Select::new();
+        sel.recv(&q.rx);
+        sel.recv(&q.background);
+        sel.ready();
+        // Both may be ready by now: the interactive job goes first.
+        match q.rx.try_recv() {
+            Ok(job) => {
+                give_back();
+                job();
+                continue;
+            }
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+        match q.background.try_recv() {
+            Ok(job) => job(),
+            Err(TryRecvError::Disconnected) => background_open = false,
+            Err(TryRecvError::Empty) => {}
+        }
+        give_back();
+    }
+    // Shutting down: whatever is still queued gets its reply now.
+    for job in q.background.try_iter() {
+        job();
+    }
+}
+
+/// A delayed job; the heap pops the earliest deadline, then the first given.
+struct Due(Instant, u64, Job);
+
+impl PartialEq for Due {
+    fn eq(&self, other: &Self) -> bool {
+        (self.0, self.1) == (other.0, other.1)
+    }
+}
+
+impl Eq for Due {}
+
+impl PartialOrd for Due {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Due {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.0, self.1).cmp(&(other.0, other.1))
+    }
+}
+
+fn run_timer(rx: Receiver<(Instant, Job)>, tx: Sender<Job>) {
+    let mut heap = BinaryHeap::<Reverse<Due>>::new();
+    let mut seq = 0;
+    loop {
+        let now = Instant::now();
+        while heap.peek().is_some_and(|Reverse(d)| d.0 <= now) {
+            let Some(Reverse(Due(_, _, job))) = heap.pop() else {
+                break;
+            };
+            if let Err(e) = tx.send(job) {
+                (e.into_inner())();
+            }
+        }
+        let next = match heap.peek() {
+            Some(Reverse(d)) => rx.recv_timeout(d.0 - now),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match next {
+            Ok((at, job)) => {
+                heap.push(Reverse(Due(at, seq, job)));
+                seq += 1;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    // Shutting down: whatever is still waiting gets its reply now.
+    for Reverse(Due(_, _, job)) in heap.into_sorted_vec().into_iter().rev() {
+        if let Err(e) = tx.send(job) {
+            (e.into_inner())();
+        }
+    }
+    debug!("fuse timer exit");
+}
+
+// Workers are detached: they exit once the last `WorkerPool` (and so the
+// last sender) is dropped. Joining here could deadlock when the final
+// `Arc<FuseState>` is released from inside a worker.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+
+    #[test]
+    fn jobs_run_concurrently() {
+        let pool = WorkerPool::new(4);
+        // All four jobs must be in flight at once to pass the barrier.
+        let barrier = Arc::new(Barrier::new(5));
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..4 {
+            let b = Arc::clone(&barrier);
+            let d = Arc::clone(&done);
+            pool.spawn(move || {
+                b.wait();
+                d.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        barrier.wait();
+        while done.load(Ordering::SeqCst) < 4 {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn delayed_jobs_wait_without_a_worker() {
+        let pool = WorkerPool::new(1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let start = Instant::now();
+        for (n, ms) in [(1, 100), (2, 100), (3, 150)] {
+            let order = Arc::clone(&order);
+            pool.spawn_after(Duration::from_millis(ms), move || {
+                order.lock().push((n, start.elapsed()))
+            });
+        }
+        // The only worker is free for undelayed jobs meanwhile.
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        pool.spawn(move || tx.send(()).unwrap());
+        rx.recv_timeout(Duration::from_millis(50)).unwrap();
+        while order.lock().len() < 3 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let order = order.lock();
+        assert_eq!(order.iter().map(|o| o.0).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(order[0].1 >= Duration::from_millis(100));
+        assert!(order[2].1 >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn background_jobs_leave_a_worker_free() {
+        let pool = WorkerPool::new(2);
+        let release = Arc::new(Barrier::new(2));
+        let ran = Arc::new(AtomicUsize::new(0));
+        // Two background jobs, one slot: the second waits for the first.
+        for _ in 0..2 {
+            let (release, ran) = (Arc::clone(&release), Arc::clone(&ran));
+            pool.spawn_background(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+                release.wait();
+            });
+        }
+        while ran.load(Ordering::SeqCst) < 1 {
+            thread::yield_now();
+        }
+        // The other worker still serves a normal job meanwhile.
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        pool.spawn(move || tx.send(()).unwrap());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+        release.wait();
+        while ran.load(Ordering::SeqCst) < 2 {
+            thread::yield_now();
+        }
+        release.wait();
+    }
+
+    #[test]
+    fn a_single_worker_is_kept_for_interactive_jobs() {
+        let pool = WorkerPool::new(1);
+        let release = Arc::new(Barrier::new(2));
+        let ran = Arc::new(AtomicUsize::new(0));
+        {
+            let (release, ran) = (Arc::clone(&release), Arc::clone(&ran));
+            pool.spawn_background(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+                release.wait();
+            });
+        }
+        while ran.load(Ordering::SeqCst) < 1 {
+            thread::yield_now();
+        }
+        // The background job is stuck, yet the worker still answers.
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        pool.spawn(move || tx.send(()).unwrap());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        release.wait();
+    }
+}
@@ -0,0 +1,112 @@
+//! Background I/O: batch jobs (backups, scrubs, bulk copies) can ask for
+//! their reads and writes to wait behind everyone else's.
+//!
+//! A process opts in with `RHSS_PRIORITY=background` in its environment,
+//! which its children inherit (`RHSS_PRIORITY=background rsync ...`), or
+//! per open file with the `RHSS_IOC_BACKGROUND` ioctl (an `int`, non-zero
+//! for background). Either way its requests go on the worker pool's
+//! background queue, which a worker only takes from when no interactive
+//! request is waiting, and never with every worker at once; the requests
+//! that reach the cold disks are the ones that wait.
+//!
+//! The environment is read from `/proc/<pid>/environ`, by a worker rather
+//! than the session thread, so it has to be set when the process starts,
+//! and the mount must be allowed to read it (same user, or rhss running as
+//! root). Anything else counts as normal.
+
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+
+/// Environment variable marking a process (and its children) background.
+pub const PRIORITY_ENV: &str = "RHSS_PRIORITY";
+
+/// `_IOW('R', 1, int)`: mark the file handle background (non-zero) or
+/// normal (zero).
+pub const RHSS_IOC_BACKGROUND: u32 = (1 << 30) | (4 << 16) | ((b'R' as u32) << 8) | 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    Background,
+}
+
+/// How long a pid's priority is trusted before `/proc` is read again;
+/// bounds the cost per request and the damage of pid reuse.
+const PID_TTL: Duration = Duration::from_secs(10);
+const PID_CACHE: usize = 1024;
+
+/// The priority of calling processes, by pid.
+pub(crate) struct PidPriorities {
+    cache: Mutex<LruCache<u32, (Priority, Instant)>>,
+}
+
+impl PidPriorities {
+    pub(crate) fn new() -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(PID_CACHE).unwrap())),
+        }
+    }
+
+    /// `pid`'s priority if it was read recently; never touches `/proc`,
+    /// so it is cheap enough for the session thread.
+    pub(crate) fn cached(&self, pid: u32) -> Option<Priority> {
+        let mut cache = self.cache.lock();
+        let &(p, at) = cache.get(&pid)?;
+        (at.elapsed() < PID_TTL).then_some(p)
+    }
+
+    /// `pid`'s priority, reading `/proc` unless it was read recently.
+    pub(crate) fn of(&self, pid: u32) -> Priority {
+        if let Some(p) = self.cached(pid) {
+            return p;
+        }
+        let p = std::fs::read(format!("/proc/{pid}/environ"))
+            .map(|env| from_environ(&env))
+            .unwrap_or(Priority::Normal);
+        self.cache.lock().put(pid, (p, Instant::now()));
+        p
+    }
+}
+
+/// The priority a NUL-separated environment block asks for.
+fn from_environ(env: &[u8]) -> Priority {
+    let key = format!("{PRIORITY_ENV}=");
+    let background = env
+        .split(|&b| b == 0)
+        .filter_map(|var| var.strip_prefix(key.as_bytes()))
+        .any(|v| v.eq_ignore_ascii_case(b"background"));
+    if background {
+        Priority::Background
+    } else {
+        Priority::Normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_comes_from_the_environment() {
+        assert_eq!(
+            from_environ(b"HOME=/root\0RHSS_PRIORITY=background\0"),
+            Priority::Background
+        );
+        assert_eq!(
+            from_environ(b"X_RHSS_PRIORITY=background\0"),
+            Priority::Normal
+        );
+        assert_eq!(from_environ(b"RHSS_PRIORITY=normal"), Priority::Normal);
+        // `_IOW('R', 1, int)` as the C macro spells it.
+        assert_eq!(RHSS_IOC_BACKGROUND, 0x4004_5201);
+        // A process we can read, without the variable.
+        let pids = PidPriorities::new();
+        assert_eq!(pids.cached(std::process::id()), None);
+        assert_eq!(pids.of(std::process::id()), Priority::Normal);
+        assert_eq!(pids.cached(std::process::id()), Some(Priority::Normal));
+        assert_eq!(pids.of(u32::MAX), Priority::Normal);
+    }
+}
@@ -0,0 +1,232 @@
+//! Backend health checks, configured under `[health]`:
+//!
+//! ```toml
+//! [health]
+//! interval_secs      = 60    # 0 = no periodic checks
+//! slow_ms            = 1000  # a probe slower than this counts as failed
+//! ready_timeout_secs = 60    # how long mount waits for green before READY
+//! ```
+//!
+//! A probe writes a small canary file (`/.rhss-health`) on a fast- or
+//! slow-tier backend, reads it back, compares and removes it. Archive
+//! backends may be read-only, so they are only asked for `statvfs`. The
+//! latest result per backend is kept process-wide and shown by `rhss
+//! status` and the gRPC `Status` call.
+//!
+//! `rhss mount` probes every backend before telling systemd (or the
+//! `--daemon` parent) it is ready, retrying until the fast and slow tiers
+//! are all green or `ready_timeout_secs` runs out. In the latter case it
+//! reports ready anyway, since hot files work without the slow tier (see
+//! `crate::backend::deferred`), and says why in `STATUS=`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{info, warn};
+
+use crate::backend::Backend;
+use crate::error::{FsError, Result};
+use crate::index::TierId;
+use crate::tier::TierRouter;
+
+/// Backend-relative path of the canary file; reserved in `crate::filter`.
+pub const CANARY: &str = ".rhss-health";
+
+const CANARY_DATA: &[u8] = b"rhss health canary\n";
+
+/// Outcome of the latest probe of one backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub ok: bool,
+    pub latency: Duration,
+    pub error: Option<String>,
+    pub at: SystemTime,
+}
+
+/// Probe settings, `[health]` in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthConfig {
+    /// Between periodic probes; `None` = only the readiness probe.
+    pub interval: Option<Duration>,
+    /// Probes slower than this fail.
+    pub slow: Duration,
+    pub ready_timeout: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(60)),
+            slow: Duration::from_secs(1),
+            ready_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Probe one backend. `write` selects the canary round trip over a bare
+/// `statvfs`.
+pub fn probe(backend: &dyn Backend, write: bool, slow: Duration) -> Check {
+    let start = Instant::now();
+    let res = if write {
+        canary(backend)
+    } else {
+        backend.statvfs().map(|_| ())
+    };
+    let latency = start.elapsed();
+    let error = match res {
+        Err(e) => Some(e.to_string()),
+        Ok(()) if latency > slow => Some(format!(
+            "slow: {}ms > {}ms",
+            latency.as_millis(),
+            slow.as_millis()
+        )),
+        Ok(()) => None,
+    };
+    Check {
+        ok: error.is_none(),
+        latency,
+        error,
+        at: SystemTime::now(),
+    }
+}
+
+fn canary(backend: &dyn Backend) -> Result<()> {
+    let path = Path::new(CANARY);
+    if !backend.exists(path)? {
+        backend.create_file(path)?;
+    }
+    backend.truncate(path, 0)?;
+    backend.write_at(path, 0, CANARY_DATA)?;
+    let back = backend.read_at(path, 0, CANARY_DATA.len() as u32)?;
+    backend.remove(path)?;
+    if back != CANARY_DATA {
+        return Err(FsError::Storage("canary read back differently".into()));
+    }
+    Ok(())
+}
+
+static LATEST: RwLock<Option<HashMap<String, Check>>> = RwLock::new(None);
+
+/// The latest probe of backend `id`, if it has been probed.
+pub fn latest(id: &str) -> Option<Check> {
+    LATEST
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()?
+        .get(id)
+        .cloned()
+}
+
+/// Probe every backend and record the results. `true` when every fast
+/// and slow backend is green; archive results are reported but don't
+/// count.
+pub fn check_all(router: &TierRouter, slow: Duration) -> bool {
+    let mut green = true;
+    let results: Vec<(String, Check)> = router
+        .all_backends()
+        .map(|(tier, b)| {
+            let check = probe(b.as_ref(), tier != TierId::Archive, slow);
+            if !check.ok {
+                warn!(
+                    "health: {} {}: {}",
+                    tier.as_str(),
+                    b.id(),
+                    check.error.as_deref().unwrap_or("failed")
+                );
+                green &= tier == TierId::Archive;
+            }
+            (b.id().to_string(), check)
+        })
+        .collect();
+    LATEST
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .extend(results);
+    green
+}
+
+/// Probe until the fast and slow tiers are green, `timeout` passes or a
+/// stop is requested. Returns whether they went green.
+pub fn wait_green(router: &TierRouter, cfg: &HealthConfig) -> bool {
+    let deadline = Instant::now() + cfg.ready_timeout;
+    loop {
+        if check_all(router, cfg.slow) {
+            return true;
+        }
+        if Instant::now() >= deadline || crate::daemon::stop_requested() {
+            return false;
+        }
+        thread::sleep(Duration::from_secs(2).min(cfg.ready_timeout));
+    }
+}
+
+/// Start periodic probes on an "rhss-health" thread. No-op without an
+/// interval.
+pub fn spawn(router: Arc<TierRouter>, cfg: HealthConfig) {
+    let Some(every) = cfg.interval else {
+        return;
+    };
+    let spawned = thread::Builder::new()
+        .name("rhss-health".into())
+        .spawn(move || {
+            let mut was_green = true;
+            loop {
+                thread::sleep(every);
+                let green = check_all(&router, cfg.slow);
+                if green && !was_green {
+                    info!("health: all backends green again");
+                }
+                was_green = green;
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("spawn health thread: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MemoryBackend, PosixBackend};
+    use crate::tier::{MostFreePlacement, Tier};
+
+    #[test]
+    fn canary_round_trip_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let b = PosixBackend::new("ssd", dir.path().to_path_buf()).unwrap();
+        let c = probe(&b, true, Duration::from_secs(5));
+        assert!(c.ok, "{:?}", c.error);
+        assert!(!dir.path().join(CANARY).exists());
+        // Too slow is a failure even when the round trip worked.
+        let c = probe(&b, true, Duration::ZERO);
+        assert!(!c.ok);
+        assert!(c.error.unwrap().starts_with("slow"));
+    }
+
+    #[test]
+    fn a_missing_slow_disk_is_not_green() {
+        let dir = tempfile::tempdir().unwrap();
+        let hdd = dir.path().join("hdd");
+        std::fs::create_dir(&hdd).unwrap();
+        let hdd_b: Arc<dyn Backend> = Arc::new(PosixBackend::new("hc-hdd", &hdd).unwrap());
+        let router = TierRouter::new(
+            Tier::new(
+                TierId::Fast,
+                vec![Arc::new(MemoryBackend::new("hc-ram", 1 << 20))],
+                Box::new(MostFreePlacement),
+            )
+            .unwrap(),
+            Tier::new(TierId::Slow, vec![hdd_b], Box::new(MostFreePlacement)).unwrap(),
+        );
+        assert!(check_all(&router, Duration::from_secs(5)));
+        assert!(latest("hc-ram").unwrap().ok);
+
+        std::fs::remove_dir(&hdd).unwrap();
+        assert!(!check_all(&router, Duration::from_secs(5)));
+        assert!(!latest("hc-hdd").unwrap().ok);
+    }
+}
@@ -0,0 +1,201 @@
+//! Hidden-storage mode: mount rhss *over* the directory that holds its own
+//! backend roots (e.g. mount at `/data` with `/data/.rhss_managed` as the
+//! fast tier), so users only ever see the merged view.
+//!
+//! Once FUSE is mounted on `/data`, the daemon can no longer reach
+//! `/data/.rhss_managed` through that path. Instead of copying the tree
+//! elsewhere, `engage` renames the mount directory to a sibling
+//! `.<name>.rhss-hidden` (atomic, same filesystem, no data moved) and
+//! recreates an empty mount point. Paths under the mount are then served
+//! from the hidden sibling via `relocate`. `release` reverses the rename.
+//!
+//! Crash recovery: the hidden sibling existing *is* the marker. It carries
+//! an `ORIGIN_FILE` naming the mount it belongs to; `recover` (run by
+//! `engage`, and safe to run any time the mount is down) moves it back.
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use tracing::{info, warn};
+
+use crate::error::{FsError, Result};
+
+/// Written inside the mount dir before the rename; holds the original path.
+pub const ORIGIN_FILE: &str = ".rhss-hidden-origin";
+
+/// `<parent>/.<name>.rhss-hidden` for `mount`.
+pub fn hidden_path(mount: &Path) -> PathBuf {
+    let name = mount
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".into());
+    mount
+        .parent()
+        .unwrap_or_else(|| Path::new("/"))
+        .join(format!(".{name}.rhss-hidden"))
+}
+
+/// Map `p` into the hidden sibling if it lies under `mount`.
+pub fn relocate_path(mount: &Path, hidden: &Path, p: &Path) -> PathBuf {
+    match p.strip_prefix(mount) {
+        Ok(rest) => hidden.join(rest),
+        Err(_) => p.to_path_buf(),
+    }
+}
+
+fn storage_err(what: &str, path: &Path, e: std::io::Error) -> FsError {
+    FsError::Storage(format!("hidden storage: {what} {}: {e}", path.display()))
+}
+
+/// Undo an interrupted relocation of `mount`. Returns whether anything was
+/// moved back. The mount must not be active.
+pub fn recover(mount: &Path) -> Result<bool> {
+    let restored = restore(mount)?;
+    if restored {
+        warn!(
+            "hidden storage: restored {} from an interrupted run",
+            mount.display()
+        );
+    }
+    Ok(restored)
+}
+
+fn restore(mount: &Path) -> Result<bool> {
+    let hidden = hidden_path(mount);
+    if !hidden.exists() {
+        // Crash between writing the origin file and the rename.
+        let stray = mount.join(ORIGIN_FILE);
+        if stray.exists() {
+            fs::remove_file(&stray).map_err(|e| storage_err("remove", &stray, e))?;
+        }
+        return Ok(false);
+    }
+    let origin = fs::read_to_string(hidden.join(ORIGIN_FILE)).unwrap_or_default();
+    if Path::new(origin.trim()) != mount {
+        return Err(FsError::Storage(format!(
+            "hidden storage: {} belongs to {:?}, not {}; resolve by hand",
+            hidden.display(),
+            origin.trim(),
+            mount.display()
+        )));
+    }
+    if mount.exists() {
+        // The placeholder mount point must be empty; anything else means
+        // the mount is still live (or someone wrote into the placeholder).
+        fs::remove_dir(mount).map_err(|e| {
+            FsError::Storage(format!(
+                "hidden storage: {} is not an empty, unmounted directory ({e}); \
+                 unmount it and retry",
+                mount.display()
+            ))
+        })?;
+    }
+    fs::rename(&hidden, mount).map_err(|e| storage_err("restore", &hidden, e))?;
+    let _ = fs::remove_file(mount.join(ORIGIN_FILE));
+    Ok(true)
+}
+
+/// An engaged relocation. Dropping it without `release` leaves the tree
+/// hidden; the next `engage`/`recover` puts it back.
+#[derive(Debug)]
+pub struct HiddenStorage {
+    mount: PathBuf,
+    hidden: PathBuf,
+}
+
+impl HiddenStorage {
+    /// Move `mount`'s contents aside and leave an empty mount point.
+    pub fn engage(mount: &Path) -> Result<Self> {
+        recover(mount)?;
+        let hidden = hidden_path(mount);
+        fs::create_dir_all(mount).map_err(|e| storage_err("create", mount, e))?;
+
+        let origin = mount.join(ORIGIN_FILE);
+        let mut f = fs::File::create(&origin).map_err(|e| storage_err("write", &origin, e))?;
+        f.write_all(mount.as_os_str().as_bytes())
+            .and_then(|_| f.sync_all())
+            .map_err(|e| storage_err("write", &origin, e))?;
+
+        fs::rename(mount, &hidden).map_err(|e| storage_err("hide", mount, e))?;
+        fs::create_dir(mount).map_err(|e| storage_err("create", mount, e))?;
+        info!(
+            "hidden storage: {} moved to {}",
+            mount.display(),
+            hidden.display()
+        );
+        Ok(Self {
+            mount: mount.to_path_buf(),
+            hidden,
+        })
+    }
+
+    pub fn hidden_root(&self) -> &Path {
+        &self.hidden
+    }
+
+    pub fn relocate(&self, p: &Path) -> PathBuf {
+        relocate_path(&self.mount, &self.hidden, p)
+    }
+
+    /// Put the tree back. Call after the FUSE mount is gone.
+    pub fn release(self) -> Result<()> {
+        restore(&self.mount)?;
+        info!("hidden storage: {} restored", self.mount.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engage_release_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mount = dir.path().join("data");
+        fs::create_dir_all(mount.join(".rhss_managed")).unwrap();
+        fs::write(mount.join(".rhss_managed/f"), b"x").unwrap();
+
+        let hs = HiddenStorage::engage(&mount).unwrap();
+        assert_eq!(fs::read_dir(&mount).unwrap().count(), 0);
+        let root = hs.relocate(&mount.join(".rhss_managed"));
+        assert_eq!(fs::read(root.join("f")).unwrap(), b"x");
+        assert_eq!(
+            hs.relocate(Path::new("/elsewhere")),
+            Path::new("/elsewhere")
+        );
+
+        hs.release().unwrap();
+        assert_eq!(fs::read(mount.join(".rhss_managed/f")).unwrap(), b"x");
+        assert!(!mount.join(ORIGIN_FILE).exists());
+        assert!(!hidden_path(&mount).exists());
+    }
+
+    #[test]
+    fn recover_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let mount = dir.path().join("data");
+        fs::create_dir_all(&mount).unwrap();
+        fs::write(mount.join("keep"), b"k").unwrap();
+
+        // Simulate kill -9 while mounted: the handle is never released.
+        std::mem::forget(HiddenStorage::engage(&mount).unwrap());
+        assert!(recover(&mount).unwrap());
+        assert_eq!(fs::read(mount.join("keep")).unwrap(), b"k");
+        assert!(!recover(&mount).unwrap());
+    }
+
+    #[test]
+    fn recover_refuses_non_empty_placeholder() {
+        let dir = tempfile::tempdir().unwrap();
+        let mount = dir.path().join("data");
+        fs::create_dir_all(&mount).unwrap();
+        std::mem::forget(HiddenStorage::engage(&mount).unwrap());
+        fs::write(mount.join("oops"), b"").unwrap();
+        assert!(recover(&mount).is_err());
+        // The hidden tree is left alone for the user to sort out.
+        assert!(hidden_path(&mount).join(ORIGIN_FILE).exists());
+    }
+}
@@ -0,0 +1,411 @@
+//! Event hooks (`[[hooks]]`): run a command or POST a webhook when the
+//! tierer acts or the supervisor restarts a mount.
+//!
+//! ```toml
+//! [[hooks]]
+//! events  = ["watermark"]                 # empty or absent = every event
+//! command = ["/usr/local/bin/page-oncall", "--sev", "2"]
+//!
+//! [[hooks]]
+//! events = ["migrated", "promoted"]
+//! url    = "http://inventory.lan:8080/rhss/events"
+//! ```
+//!
+//! Events are JSON objects tagged by `event`:
+//!
+//! - `migrated` / `promoted`: a file moved to a colder / hotter tier
+//!   (`path`, `from`, `to`, `bytes`);
+//! - `repaired`: `fsck --repair` dropped the index row of a file whose
+//!   backend copy was gone (`path`);
+//! - `watermark`: fast-tier usage rose past the high watermark (`tier`,
+//!   `usage`, `watermark`), once per crossing;
+//! - `root-lost`: a POSIX backend's root directory vanished or was
+//!   replaced, e.g. its disk was unmounted (`backend`, `root`). The backend
+//!   refuses writes until the same directory is back;
+//! - `restarted`: `rhss mount --supervise` brought a crashed mount back
+//!   (`mount`, `attempt`, the child's exit `status`, `delay_ms` before the
+//!   restart).
+//!
+//! A command gets the event on stdin and its kind in `RHSS_EVENT`, and is
+//! killed after 30 s. Webhooks are plain `http://` `POST`s, like the http
+//! archive backend. Delivery happens on one background thread behind a
+//! bounded queue, so the tierer never waits on a slow hook; when the queue
+//! is full the event is dropped with a warning. Hooks are process-wide,
+//! set at mount and again on SIGHUP.
+
+use std::io::{self, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::error::{FsError, Result};
+use crate::index::TierId;
+use crate::webdav::http::read_response_head;
+
+/// Events queued for delivery before new ones are dropped.
+const QUEUE: usize = 1024;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Every kind `events` may list.
+pub const EVENT_KINDS: [&str; 6] = [
+    "migrated",
+    "promoted",
+    "repaired",
+    "watermark",
+    "root-lost",
+    "restarted",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    Migrated {
+        path: PathBuf,
+        from: &'static str,
+        to: &'static str,
+        bytes: u64,
+    },
+    Promoted {
+        path: PathBuf,
+        from: &'static str,
+        to: &'static str,
+        bytes: u64,
+    },
+    Repaired {
+        path: PathBuf,
+    },
+    Watermark {
+        tier: &'static str,
+        usage: f64,
+        watermark: f64,
+    },
+    RootLost {
+        backend: String,
+        root: PathBuf,
+    },
+    Restarted {
+        mount: PathBuf,
+        attempt: u32,
+        status: String,
+        delay_ms: u64,
+    },
+}
+
+impl Event {
+    /// `Migrated`, or `Promoted` when `to` is the hotter tier.
+    pub fn moved(path: PathBuf, from: TierId, to: TierId, bytes: u64) -> Self {
+        let heat = |t: TierId| match t {
+            TierId::Fast => 2,
+            TierId::Slow => 1,
+            TierId::Archive => 0,
+        };
+        let (from_s, to_s) = (from.as_str(), to.as_str());
+        if heat(to) > heat(from) {
+            Event::Promoted {
+                path,
+                from: from_s,
+                to: to_s,
+                bytes,
+            }
+        } else {
+            Event::Migrated {
+                path,
+                from: from_s,
+                to: to_s,
+                bytes,
+            }
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Migrated { .. } => "migrated",
+            Event::Promoted { .. } => "promoted",
+            Event::Repaired { .. } => "repaired",
+            Event::Watermark { .. } => "watermark",
+            Event::RootLost { .. } => "root-lost",
+            Event::Restarted { .. } => "restarted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    /// argv; the first element is the program.
+    Command(Vec<String>),
+    Webhook {
+        /// `host:port` to connect to.
+        addr: String,
+        /// `Host` header value.
+        host: String,
+        target: String,
+    },
+}
+
+/// Where one set of events goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    /// Kinds delivered; empty = all.
+    events: Vec<String>,
+    action: Action,
+}
+
+impl Hook {
+    pub fn command(argv: Vec<String>, events: Vec<String>) -> Result<Self> {
+        if argv.first().is_none_or(|p| p.is_empty()) {
+            return Err(FsError::Storage("hook command is empty".into()));
+        }
+        Self::new(Action::Command(argv), events)
+    }
+
+    pub fn webhook(url: &str, events: Vec<String>) -> Result<Self> {
+        let bad = |why: &str| FsError::Storage(format!("hook url {url:?}: {why}"));
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| bad("expected http:// (terminate TLS in a proxy)"))?;
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if authority.is_empty() || authority.contains('@') {
+            return Err(bad("expected http://host[:port]/path"));
+        }
+        let addr = if authority
+            .rsplit_once(':')
+            .is_some_and(|(_, p)| !p.contains(']'))
+        {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+        let target = if path.is_empty() { "/" } else { path };
+        Self::new(
+            Action::Webhook {
+                addr,
+                host: authority.to_string(),
+                target: target.to_string(),
+            },
+            events,
+        )
+    }
+
+    fn new(action: Action, events: Vec<String>) -> Result<Self> {
+        if let Some(e) = events.iter().find(|e| !EVENT_KINDS.contains(&e.as_str())) {
+            return Err(FsError::Storage(format!(
+                "unknown hook event {e:?} (expected one of {})",
+                EVENT_KINDS.join(", ")
+            )));
+        }
+        Ok(Self { events, action })
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event.kind())
+    }
+
+    fn deliver(&self, kind: &str, body: &[u8]) -> io::Result<()> {
+        match &self.action {
+            Action::Command(argv) => run_command(argv, kind, body),
+            Action::Webhook { addr, host, target } => post(addr, host, target, body),
+        }
+    }
+}
+
+fn run_command(argv: &[String], kind: &str, body: &[u8]) -> io::Result<()> {
+    let (mut child, _tracked) = crate::daemon::TrackedChild::spawn(
+        Command::new(&argv[0])
+            .args(&argv[1..])
+            .env("RHSS_EVENT", kind)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null()),
+    )?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that doesn't read its stdin is fine.
+        let _ = stdin.write_all(body);
+    }
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return match status.success() {
+                true => Ok(()),
+                false => Err(io::Error::other(format!("exited with {status}"))),
+            };
+        }
+        if start.elapsed() >= COMMAND_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "killed after 30s"));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn post(addr: &str, host: &str, target: &str, body: &[u8]) -> io::Result<()> {
+    let sock = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses"))?;
+    let mut s = TcpStream::connect_timeout(&sock, HTTP_TIMEOUT)?;
+    s.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    s.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    let head = format!(
+        "POST {target} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: rhss\r\n\
+         Connection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+    s.write_all(head.as_bytes())?;
+    s.write_all(body)?;
+    let resp = read_response_head(&mut BufReader::new(s))?;
+    match resp.status {
+        200..=299 => Ok(()),
+        status => Err(io::Error::other(format!("HTTP {status}"))),
+    }
+}
+
+/// Installed hooks and the sender to their delivery thread.
+struct Hooks {
+    tx: SyncSender<Event>,
+}
+
+static HOOKS: RwLock<Option<Arc<Hooks>>> = RwLock::new(None);
+
+/// Replace the installed hooks; an empty list removes them. The previous
+/// delivery thread finishes what it has queued, then exits.
+pub fn set_hooks(hooks: Vec<Hook>) {
+    let installed = if hooks.is_empty() {
+        None
+    } else {
+        let (tx, rx) = mpsc::sync_channel(QUEUE);
+        std::thread::Builder::new()
+            .name("rhss-hooks".into())
+            .spawn(move || deliver_loop(hooks, rx))
+            .expect("spawn hooks thread");
+        Some(Arc::new(Hooks { tx }))
+    };
+    *HOOKS.write().unwrap_or_else(|e| e.into_inner()) = installed;
+}
+
+/// Queue `event` for every hook that wants it. Never blocks.
+pub fn emit(event: Event) {
+    let hooks = HOOKS.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let Some(hooks) = hooks else {
+        return;
+    };
+    match hooks.tx.try_send(event) {
+        Ok(()) => {}
+        Err(TrySendError::Full(e)) => warn!("hooks: queue full, dropped {} event", e.kind()),
+        Err(TrySendError::Disconnected(_)) => {}
+    }
+}
+
+fn deliver_loop(hooks: Vec<Hook>, rx: Receiver<Event>) {
+    for event in rx {
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut body = match serde_json::to_value(&event) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("hooks: encode {}: {e}", event.kind());
+                continue;
+            }
+        };
+        body["ts_ms"] = ts_ms.into();
+        let body = body.to_string().into_bytes();
+        for hook in hooks.iter().filter(|h| h.wants(&event)) {
+            match hook.deliver(event.kind(), &body) {
+                Ok(()) => debug!("hooks: delivered {}", event.kind()),
+                Err(e) => warn!("hooks: {} event to {:?}: {e}", event.kind(), hook.action),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Read};
+    use std::net::TcpListener;
+    use tempfile::TempDir;
+
+    #[test]
+    fn events_are_tagged_json() {
+        let e = Event::moved(PathBuf::from("/a"), TierId::Slow, TierId::Fast, 3);
+        assert_eq!(e.kind(), "promoted");
+        let v = serde_json::to_value(&e).unwrap();
+        assert_eq!(v["event"], "promoted");
+        assert_eq!(v["from"], "slow");
+        let e = Event::moved(PathBuf::from("/a"), TierId::Fast, TierId::Slow, 3);
+        assert_eq!(e.kind(), "migrated");
+    }
+
+    #[test]
+    fn hooks_validate() {
+        assert!(Hook::command(vec![], vec![]).is_err());
+        assert!(Hook::command(vec!["true".into()], vec!["moved".into()]).is_err());
+        assert!(Hook::webhook("https://x/y", vec![]).is_err());
+        let h = Hook::webhook("http://inv.lan/events", vec!["watermark".into()]).unwrap();
+        assert_eq!(
+            h.action,
+            Action::Webhook {
+                addr: "inv.lan:80".into(),
+                host: "inv.lan".into(),
+                target: "/events".into(),
+            }
+        );
+        assert!(!h.wants(&Event::Repaired { path: "/a".into() }));
+    }
+
+    #[test]
+    fn command_gets_the_event_on_stdin() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("event.json");
+        let script = format!("cat > {}; echo $RHSS_EVENT >> {0}", out.display());
+        let hook = Hook::command(vec!["sh".into(), "-c".into(), script], vec![]).unwrap();
+        hook.deliver("repaired", br#"{"event":"repaired"}"#)
+            .unwrap();
+        let got = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(got, "{\"event\":\"repaired\"}repaired\n");
+        let failing = Hook::command(vec!["false".into()], vec![]).unwrap();
+        assert!(failing.deliver("repaired", b"{}").is_err());
+    }
+
+    #[test]
+    fn webhook_posts_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (s, _) = listener.accept().unwrap();
+            let mut r = BufReader::new(s.try_clone().unwrap());
+            let mut head = String::new();
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                r.read_line(&mut line).unwrap();
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    len = v.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; len];
+            r.read_exact(&mut body).unwrap();
+            (&s).write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+        let hook = Hook::webhook(&url, vec![]).unwrap();
+        hook.deliver("watermark", br#"{"event":"watermark"}"#)
+            .unwrap();
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /hook HTTP/1.1"));
+        assert_eq!(body, r#"{"event":"watermark"}"#);
+    }
+}
@@ -1,9 +1,11 @@
 //! `PathIndex` — the authoritative source of truth for "which backend has
 //! which logical path."
 //!
-//! Backed by SQLite (WAL mode) with an in-memory LRU cache in front of
-//! `locate` (the hot FUSE-lookup path). See `architecture.md §4.3`.
+//! Backed by SQLite (WAL mode) with an in-memory cache in front of
+//! `locate` (the hot FUSE-lookup path), an LRU unless the caller brings
+//! its own `LocationCache`. See `architecture.md §4.3`.
 
+use std::collections::HashMap;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -13,6 +15,7 @@ use lru::LruCache;
 use parking_lot::Mutex;
 use rusqlite::{params, Connection, OptionalExtension};
 
+use crate::clock::{Clock, SystemClock};
 use crate::error::{FsError, Result};
 
 /// Which tier a file is on. Names are physical (Fast = SSD-ish, Slow =
@@ -89,6 +92,9 @@ pub struct FileRow {
     pub hit_count: u64,
     pub popularity: f64,
     pub pinned_tier: Option<TierId>,
+    /// When the pin lapses (`rhss pin --until`): the tierer then clears
+    /// both pin fields and the file is left to the policy again.
+    pub pinned_until: Option<SystemTime>,
     pub state: FileState,
     /// D24: mutability hint. Drives aggressive demotion + compression.
     pub mutability: Mutability,
@@ -106,6 +112,10 @@ pub enum FileState {
     Stable,
     Migrating,
     Scanning,
+    /// Created through the mount and not yet flushed or released. Left
+    /// behind by a crash in between; `fsck` reports such files and
+    /// `--repair` drops the empty ones. Never picked for demotion.
+    Creating,
 }
 
 /// File mutability — does the user expect this file's content to keep
@@ -150,6 +160,7 @@ impl FileState {
             FileState::Stable => "stable",
             FileState::Migrating => "migrating",
             FileState::Scanning => "scanning",
+            FileState::Creating => "creating",
         }
     }
 
@@ -158,6 +169,7 @@ impl FileState {
             "stable" => Ok(FileState::Stable),
             "migrating" => Ok(FileState::Migrating),
             "scanning" => Ok(FileState::Scanning),
+            "creating" => Ok(FileState::Creating),
             other => Err(FsError::Storage(format!("unknown state: {other}"))),
         }
     }
@@ -172,6 +184,14 @@ pub trait PathIndex: Send + Sync {
     fn swap_location(&self, logical: &Path, new_loc: Location) -> Result<()>;
     fn remove(&self, logical: &Path) -> Result<()>;
     fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// The directory `from` was renamed to `to`: move every row below it,
+    /// together with its `dir_usage` rows and the backend paths that lie
+    /// under the old directory (files stored at their logical path, which
+    /// a directory rename on the backend moved too). Returns the number of
+    /// files moved.
+    fn rename_tree(&self, from: &Path, to: &Path) -> Result<u64>;
+
     fn record_access(&self, logical: &Path, when: SystemTime, delta_hits: u64) -> Result<()>;
 
     /// Coldest N files in a tier, satisfying min_age (last_access older than
@@ -183,6 +203,17 @@ pub trait PathIndex: Send + Sync {
         min_age: Duration,
     ) -> Result<Vec<(PathBuf, u64)>>;
 
+    /// `coldest`, except files under a directory in `ages` must have gone
+    /// that directory's age without access instead of `min_age`. The first
+    /// listed directory a file is under wins.
+    fn coldest_with_ages(
+        &self,
+        tier: TierId,
+        target_bytes: u64,
+        min_age: Duration,
+        ages: &[(PathBuf, Duration)],
+    ) -> Result<Vec<(PathBuf, u64)>>;
+
     /// Total number of indexed files (used by `statfs` and progress UI).
     fn count(&self) -> Result<u64>;
 
@@ -197,6 +228,26 @@ pub trait PathIndex: Send + Sync {
     /// Every row with `pinned_tier` set. Used by `rhss list-pinned`.
     fn list_pinned(&self) -> Result<Vec<FileRow>>;
 
+    /// Clear the pins whose `pinned_until` is at or before `now`, returning
+    /// the paths released. Run by the tierer every cycle.
+    fn expire_pins(&self, now: SystemTime) -> Result<Vec<PathBuf>>;
+
+    /// Every row at or below `dir` (`/` for all), ordered by path. Used to
+    /// seed quota usage.
+    fn list_under(&self, dir: &Path) -> Result<Vec<FileRow>>;
+
+    /// Record a file's current size after a write or truncate. Keeps
+    /// `dir_usage` (and `tier_summary`) in step with what FUSE wrote.
+    fn set_size(&self, logical: &Path, size: u64) -> Result<()>;
+
+    /// Per-tier totals for `dir` and the directories up to `depth` levels
+    /// below it, ordered by path. Maintained incrementally, so this never
+    /// walks `files`. Used by `rhss du`.
+    fn dir_usage(&self, dir: &Path, depth: usize) -> Result<Vec<DirUsage>>;
+
+    /// Update just the state of a file. Other columns untouched.
+    fn set_state(&self, logical: &Path, state: FileState) -> Result<()>;
+
     /// Update just the mutability flag for a file. Used by `rhss lock/unlock`
     /// and by the auto-detect sweeper. Other columns untouched.
     fn set_mutability(&self, logical: &Path, m: Mutability) -> Result<()>;
@@ -218,6 +269,37 @@ pub trait PathIndex: Send + Sync {
     /// Decrement refcount on a blob. Returns true if it reached 0 and the
     /// physical file should be deleted.
     fn unref_blob(&self, hash: &str) -> Result<bool>;
+
+    // ===== Preheated copies (`crate::preheat`) =====
+
+    /// The fast-tier copy of `logical`, if it was preheated.
+    fn hot_copy(&self, logical: &Path) -> Result<Option<HotCopy>>;
+
+    /// Record a preheated copy, replacing any earlier one.
+    fn set_hot_copy(&self, copy: HotCopy) -> Result<()>;
+
+    /// Forget the copy of `logical`. Returns it so the caller can delete
+    /// the bytes.
+    fn remove_hot_copy(&self, logical: &Path) -> Result<Option<HotCopy>>;
+
+    /// Every copy at or below `dir` (`/` for all), ordered by path.
+    fn list_hot_copies(&self, dir: &Path) -> Result<Vec<HotCopy>>;
+}
+
+/// A fast-tier copy of a file whose row stays on its slower tier: the
+/// row's location is authoritative, and the copy is only read while the
+/// source it was taken from is unchanged. See `crate::preheat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotCopy {
+    pub logical_path: PathBuf,
+    /// Fast-tier backend holding the copy.
+    pub backend_id: String,
+    pub backend_path: PathBuf,
+    /// Where the copy was taken from: the row's location at the time.
+    pub source_backend_id: String,
+    pub source_backend_path: PathBuf,
+    pub size: u64,
+    pub source_mtime: SystemTime,
 }
 
 /// One physical-blob row in `content_blobs`.
@@ -231,15 +313,88 @@ pub struct BlobRef {
     pub compressed: bool,
 }
 
-/// SQLite-backed PathIndex with an LRU cache for hot lookups.
+/// Files and bytes below one directory on one tier (recursive).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirUsage {
+    pub dir: PathBuf,
+    pub tier: TierId,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Locations `SqlitePathIndex::open` keeps cached.
+pub const DEFAULT_CACHE_ENTRIES: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
+
+/// The lookup cache in front of `SqlitePathIndex::locate`. The index
+/// keeps it coherent, calling `pop` or `retain` whenever a row moves or
+/// goes away; an implementation only decides what to keep.
+pub trait LocationCache: Send {
+    fn get(&mut self, logical: &Path) -> Option<&mut Location>;
+    fn put(&mut self, logical: PathBuf, loc: Location);
+    fn pop(&mut self, logical: &Path) -> Option<Location>;
+    /// Drop every entry whose path `keep` rejects.
+    fn retain(&mut self, keep: &mut dyn FnMut(&Path) -> bool);
+}
+
+/// The default: least recently used locations go first.
+impl LocationCache for LruCache<PathBuf, Location> {
+    fn get(&mut self, logical: &Path) -> Option<&mut Location> {
+        self.get_mut(logical)
+    }
+
+    fn put(&mut self, logical: PathBuf, loc: Location) {
+        LruCache::put(self, logical, loc);
+    }
+
+    fn pop(&mut self, logical: &Path) -> Option<Location> {
+        LruCache::pop(self, logical)
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&Path) -> bool) {
+        let stale: Vec<PathBuf> = self
+            .iter()
+            .map(|(k, _)| k)
+            .filter(|k| !keep(k))
+            .cloned()
+            .collect();
+        for k in stale {
+            LruCache::pop(self, &k);
+        }
+    }
+}
+
+/// SQLite-backed PathIndex with a cache for hot lookups.
 pub struct SqlitePathIndex {
     inner: Mutex<Connection>,
-    cache: Mutex<LruCache<PathBuf, Location>>,
+    cache: Mutex<Box<dyn LocationCache>>,
+    /// `now` for the `coldest` age cutoff.
+    clock: Arc<dyn Clock>,
 }
 
 impl SqlitePathIndex {
     /// Open or create the index at `db_path`. WAL mode, foreign keys on.
     pub fn open(db_path: impl AsRef<Path>) -> Result<Arc<Self>> {
+        Self::open_with_cache(db_path, DEFAULT_CACHE_ENTRIES)
+    }
+
+    /// `open` with room for `entries` locations in the lookup cache.
+    pub fn open_with_cache(db_path: impl AsRef<Path>, entries: NonZeroUsize) -> Result<Arc<Self>> {
+        let cache = Box::new(LruCache::new(entries));
+        Self::open_with(db_path, cache, Arc::new(SystemClock))
+    }
+
+    /// `open` with file ages measured against `clock` (`crate::clock`).
+    pub fn open_with_clock(db_path: impl AsRef<Path>, clock: Arc<dyn Clock>) -> Result<Arc<Self>> {
+        let cache = Box::new(LruCache::new(DEFAULT_CACHE_ENTRIES));
+        Self::open_with(db_path, cache, clock)
+    }
+
+    /// `open` with its own lookup cache and clock.
+    pub fn open_with(
+        db_path: impl AsRef<Path>,
+        cache: Box<dyn LocationCache>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Arc<Self>> {
         let conn = Connection::open(db_path.as_ref())
             .map_err(|e| FsError::Storage(format!("open sqlite: {e}")))?;
         conn.execute_batch(
@@ -276,6 +431,7 @@ impl SqlitePathIndex {
         )?;
         Self::migrate_add_column(&conn, "compressed", "INTEGER NOT NULL DEFAULT 0")?;
         Self::migrate_add_column(&conn, "content_hash", "TEXT")?;
+        Self::migrate_add_column(&conn, "pinned_until", "INTEGER")?;
         // Reverse index for content-addressable dedup (D25).
         conn.execute_batch(
             r#"
@@ -293,10 +449,51 @@ impl SqlitePathIndex {
             "#,
         )
         .map_err(|e| FsError::Storage(format!("init dedup schema: {e}")))?;
+        // Recursive per-directory totals, one row per (ancestor, tier) of
+        // every indexed file. Kept in step by every mutation below.
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS dir_usage (
+                dir    TEXT NOT NULL,
+                tier   TEXT NOT NULL,
+                files  INTEGER NOT NULL,
+                bytes  INTEGER NOT NULL,
+                PRIMARY KEY (dir, tier)
+            );
+            "#,
+        )
+        .map_err(|e| FsError::Storage(format!("init usage schema: {e}")))?;
+        // Fast-tier copies of preheated files, next to their rows.
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS hot_copies (
+                logical_path         TEXT PRIMARY KEY,
+                backend_id           TEXT NOT NULL,
+                backend_path         TEXT NOT NULL,
+                source_backend_id    TEXT NOT NULL,
+                source_backend_path  TEXT NOT NULL,
+                size                 INTEGER NOT NULL,
+                source_mtime_ns      INTEGER NOT NULL
+            );
+            "#,
+        )
+        .map_err(|e| FsError::Storage(format!("init preheat schema: {e}")))?;
+        // Databases from before `dir_usage` existed: build it once.
+        let (files, dirs): (i64, i64) = conn
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM files), (SELECT COUNT(*) FROM dir_usage)",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .map_err(|e| FsError::Storage(format!("usage check: {e}")))?;
+        if files > 0 && dirs == 0 {
+            rebuild_usage(&conn)?;
+        }
 
         Ok(Arc::new(Self {
             inner: Mutex::new(conn),
-            cache: Mutex::new(LruCache::new(NonZeroUsize::new(4096).unwrap())),
+            cache: Mutex::new(cache),
+            clock,
         }))
     }
 
@@ -335,6 +532,125 @@ impl SqlitePathIndex {
     }
 }
 
+/// `(tier, size)` of the row at `logical`, if any.
+fn usage_of(conn: &Connection, logical: &Path) -> Result<Option<(String, i64)>> {
+    conn.query_row(
+        "SELECT tier, size FROM files WHERE logical_path = ?1",
+        params![logical.to_string_lossy().as_ref()],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )
+    .optional()
+    .map_err(|e| FsError::Storage(format!("usage_of: {e}")))
+}
+
+/// Add `files`/`bytes` (either may be negative) to every ancestor of
+/// `logical` on `tier`.
+fn bump_usage(conn: &Connection, logical: &Path, tier: &str, files: i64, bytes: i64) -> Result<()> {
+    if files == 0 && bytes == 0 {
+        return Ok(());
+    }
+    let mut upsert = conn
+        .prepare_cached(
+            "INSERT INTO dir_usage (dir, tier, files, bytes) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (dir, tier) DO UPDATE
+             SET files = files + excluded.files, bytes = bytes + excluded.bytes",
+        )
+        .map_err(|e| FsError::Storage(format!("bump_usage prepare: {e}")))?;
+    let mut prune = conn
+        .prepare_cached("DELETE FROM dir_usage WHERE dir = ?1 AND tier = ?2 AND files <= 0")
+        .map_err(|e| FsError::Storage(format!("bump_usage prepare: {e}")))?;
+    for dir in logical
+        .ancestors()
+        .skip(1)
+        .filter(|d| !d.as_os_str().is_empty())
+    {
+        let dir = dir.to_string_lossy();
+        upsert
+            .execute(params![dir.as_ref(), tier, files, bytes])
+            .map_err(|e| FsError::Storage(format!("bump_usage: {e}")))?;
+        if files < 0 {
+            prune
+                .execute(params![dir.as_ref(), tier])
+                .map_err(|e| FsError::Storage(format!("bump_usage prune: {e}")))?;
+        }
+    }
+    Ok(())
+}
+
+/// Recompute `dir_usage` from scratch with one pass over `files`.
+fn rebuild_usage(conn: &Connection) -> Result<()> {
+    let mut totals: HashMap<(String, String), (i64, i64)> = HashMap::new();
+    {
+        let mut stmt = conn
+            .prepare("SELECT logical_path, tier, size FROM files")
+            .map_err(|e| FsError::Storage(format!("rebuild_usage prepare: {e}")))?;
+        let rows = stmt
+            .query_map([], |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, i64>(2)?,
+                ))
+            })
+            .map_err(|e| FsError::Storage(format!("rebuild_usage query: {e}")))?;
+        for r in rows {
+            let (path, tier, size) =
+                r.map_err(|e| FsError::Storage(format!("rebuild_usage row: {e}")))?;
+            for dir in Path::new(&path)
+                .ancestors()
+                .skip(1)
+                .filter(|d| !d.as_os_str().is_empty())
+            {
+                let t = totals
+                    .entry((dir.to_string_lossy().into_owned(), tier.clone()))
+                    .or_default();
+                t.0 += 1;
+                t.1 += size;
+            }
+        }
+    }
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| FsError::Storage(format!("rebuild_usage begin: {e}")))?;
+    tx.execute("DELETE FROM dir_usage", [])
+        .map_err(|e| FsError::Storage(format!("rebuild_usage clear: {e}")))?;
+    for ((dir, tier), (files, bytes)) in totals {
+        tx.execute(
+            "INSERT INTO dir_usage (dir, tier, files, bytes) VALUES (?1, ?2, ?3, ?4)",
+            params![dir, tier, files, bytes],
+        )
+        .map_err(|e| FsError::Storage(format!("rebuild_usage insert: {e}")))?;
+    }
+    tx.commit()
+        .map_err(|e| FsError::Storage(format!("rebuild_usage commit: {e}")))
+}
+
+fn ts_nanos(t: SystemTime) -> i64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+fn hot_copy_from_row(r: &rusqlite::Row<'_>) -> rusqlite::Result<HotCopy> {
+    let logical: String = r.get(0)?;
+    let backend_path: String = r.get(2)?;
+    let source_path: String = r.get(4)?;
+    let size: i64 = r.get(5)?;
+    let mtime: i64 = r.get(6)?;
+    Ok(HotCopy {
+        logical_path: PathBuf::from(logical),
+        backend_id: r.get(1)?,
+        backend_path: PathBuf::from(backend_path),
+        source_backend_id: r.get(3)?,
+        source_backend_path: PathBuf::from(source_path),
+        size: size as u64,
+        source_mtime: UNIX_EPOCH + Duration::from_nanos(mtime.max(0) as u64),
+    })
+}
+
+const HOT_COPY_COLUMNS: &str = "logical_path, backend_id, backend_path, source_backend_id,
+                        source_backend_path, size, source_mtime_ns";
+
 fn ts_secs(t: SystemTime) -> i64 {
     t.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
 }
@@ -388,7 +704,7 @@ impl PathIndex for SqlitePathIndex {
         let conn = self.inner.lock();
         let row = conn
             .query_row(
-                "SELECT tier, backend_id, backend_path, size, last_access, hit_count, popularity, pinned_tier, state, replicas, mutability, compressed, content_hash
+                "SELECT tier, backend_id, backend_path, size, last_access, hit_count, popularity, pinned_tier, state, replicas, mutability, compressed, content_hash, pinned_until
                  FROM files WHERE logical_path = ?1",
                 params![logical.to_string_lossy().as_ref()],
                 |r| {
@@ -406,6 +722,7 @@ impl PathIndex for SqlitePathIndex {
                         r.get::<_, String>(10)?,
                         r.get::<_, i64>(11)?,
                         r.get::<_, Option<String>>(12)?,
+                        r.get::<_, Option<i64>>(13)?,
                     ))
                 },
             )
@@ -425,6 +742,7 @@ impl PathIndex for SqlitePathIndex {
             mutability,
             compressed,
             content_hash,
+            pinned_until,
         )) = row
         else {
             return Ok(None);
@@ -444,6 +762,7 @@ impl PathIndex for SqlitePathIndex {
             hit_count: hits as u64,
             popularity: pop,
             pinned_tier,
+            pinned_until: pinned_until.map(ts_from_secs),
             state: FileState::parse(&state)?,
             mutability: Mutability::parse(&mutability)?,
             compressed: compressed != 0,
@@ -454,12 +773,18 @@ impl PathIndex for SqlitePathIndex {
     fn insert(&self, row: FileRow) -> Result<()> {
         let conn = self.inner.lock();
         let replicas_json = serialize_replicas(&row.replicas)?;
-        conn.execute(
+        let tx = conn
+            .unchecked_transaction()
+            .map_err(|e| FsError::Storage(format!("insert begin: {e}")))?;
+        if let Some((tier, size)) = usage_of(&tx, &row.logical_path)? {
+            bump_usage(&tx, &row.logical_path, &tier, -1, -size)?;
+        }
+        tx.execute(
             "INSERT OR REPLACE INTO files
              (logical_path, tier, backend_id, backend_path, size, last_access,
               hit_count, popularity, pinned_tier, state, replicas,
-              mutability, compressed, content_hash)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+              mutability, compressed, content_hash, pinned_until)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
             params![
                 row.logical_path.to_string_lossy().as_ref(),
                 row.location.tier.as_str(),
@@ -475,9 +800,19 @@ impl PathIndex for SqlitePathIndex {
                 row.mutability.as_str(),
                 if row.compressed { 1i64 } else { 0i64 },
                 row.content_hash,
+                row.pinned_until.map(ts_secs),
             ],
         )
         .map_err(|e| FsError::Storage(format!("insert: {e}")))?;
+        bump_usage(
+            &tx,
+            &row.logical_path,
+            row.location.tier.as_str(),
+            1,
+            row.location.size as i64,
+        )?;
+        tx.commit()
+            .map_err(|e| FsError::Storage(format!("insert commit: {e}")))?;
         drop(conn);
         self.cache.lock().pop(&row.logical_path);
         Ok(())
@@ -485,22 +820,28 @@ impl PathIndex for SqlitePathIndex {
 
     fn swap_location(&self, logical: &Path, new_loc: Location) -> Result<()> {
         let conn = self.inner.lock();
-        let n = conn
-            .execute(
-                "UPDATE files SET tier = ?2, backend_id = ?3, backend_path = ?4, size = ?5
-                 WHERE logical_path = ?1",
-                params![
-                    logical.to_string_lossy().as_ref(),
-                    new_loc.tier.as_str(),
-                    new_loc.backend_id,
-                    new_loc.backend_path.to_string_lossy().as_ref(),
-                    new_loc.size as i64,
-                ],
-            )
-            .map_err(|e| FsError::Storage(format!("swap_location: {e}")))?;
-        if n == 0 {
+        let tx = conn
+            .unchecked_transaction()
+            .map_err(|e| FsError::Storage(format!("swap_location begin: {e}")))?;
+        let Some((old_tier, old_size)) = usage_of(&tx, logical)? else {
             return Err(FsError::NotFound(logical.to_string_lossy().to_string()));
-        }
+        };
+        tx.execute(
+            "UPDATE files SET tier = ?2, backend_id = ?3, backend_path = ?4, size = ?5
+             WHERE logical_path = ?1",
+            params![
+                logical.to_string_lossy().as_ref(),
+                new_loc.tier.as_str(),
+                new_loc.backend_id,
+                new_loc.backend_path.to_string_lossy().as_ref(),
+                new_loc.size as i64,
+            ],
+        )
+        .map_err(|e| FsError::Storage(format!("swap_location: {e}")))?;
+        bump_usage(&tx, logical, &old_tier, -1, -old_size)?;
+        bump_usage(&tx, logical, new_loc.tier.as_str(), 1, new_loc.size as i64)?;
+        tx.commit()
+            .map_err(|e| FsError::Storage(format!("swap_location commit: {e}")))?;
         drop(conn);
         self.put_cache(logical, new_loc);
         Ok(())
@@ -508,11 +849,19 @@ impl PathIndex for SqlitePathIndex {
 
     fn remove(&self, logical: &Path) -> Result<()> {
         let conn = self.inner.lock();
-        conn.execute(
-            "DELETE FROM files WHERE logical_path = ?1",
-            params![logical.to_string_lossy().as_ref()],
-        )
-        .map_err(|e| FsError::Storage(format!("remove: {e}")))?;
+        let tx = conn
+            .unchecked_transaction()
+            .map_err(|e| FsError::Storage(format!("remove begin: {e}")))?;
+        if let Some((tier, size)) = usage_of(&tx, logical)? {
+            tx.execute(
+                "DELETE FROM files WHERE logical_path = ?1",
+                params![logical.to_string_lossy().as_ref()],
+            )
+            .map_err(|e| FsError::Storage(format!("remove: {e}")))?;
+            bump_usage(&tx, logical, &tier, -1, -size)?;
+        }
+        tx.commit()
+            .map_err(|e| FsError::Storage(format!("remove commit: {e}")))?;
         drop(conn);
         self.cache.lock().pop(logical);
         Ok(())
@@ -520,18 +869,24 @@ impl PathIndex for SqlitePathIndex {
 
     fn rename(&self, from: &Path, to: &Path) -> Result<()> {
         let conn = self.inner.lock();
-        let n = conn
-            .execute(
-                "UPDATE files SET logical_path = ?2 WHERE logical_path = ?1",
-                params![
-                    from.to_string_lossy().as_ref(),
-                    to.to_string_lossy().as_ref()
-                ],
-            )
-            .map_err(|e| FsError::Storage(format!("rename: {e}")))?;
-        if n == 0 {
+        let tx = conn
+            .unchecked_transaction()
+            .map_err(|e| FsError::Storage(format!("rename begin: {e}")))?;
+        let Some((tier, size)) = usage_of(&tx, from)? else {
             return Err(FsError::NotFound(from.to_string_lossy().to_string()));
-        }
+        };
+        tx.execute(
+            "UPDATE files SET logical_path = ?2 WHERE logical_path = ?1",
+            params![
+                from.to_string_lossy().as_ref(),
+                to.to_string_lossy().as_ref()
+            ],
+        )
+        .map_err(|e| FsError::Storage(format!("rename: {e}")))?;
+        bump_usage(&tx, from, &tier, -1, -size)?;
+        bump_usage(&tx, to, &tier, 1, size)?;
+        tx.commit()
+            .map_err(|e| FsError::Storage(format!("rename commit: {e}")))?;
         drop(conn);
         let mut cache = self.cache.lock();
         if let Some(loc) = cache.pop(from) {
@@ -540,6 +895,95 @@ impl PathIndex for SqlitePathIndex {
         Ok(())
     }
 
+    fn rename_tree(&self, from: &Path, to: &Path) -> Result<u64> {
+        // One range UPDATE per table, as in `list_under`: `<from>/` up to
+        // (not incl.) `<from>0`, with `<to>` spliced in front of the rest.
+        let from = from.to_string_lossy();
+        let from = from.trim_end_matches('/');
+        let to = to.to_string_lossy();
+        let to = to.trim_end_matches('/');
+        let (from_rel, to_rel) = (from.trim_start_matches('/'), to.trim_start_matches('/'));
+        // substr() is 1-based and counts characters.
+        let rest = |base: &str| base.chars().count() as i64 + 1;
+        // The start of a `backend_path` value inside the `replicas` JSON.
+        let json_prefix = |base: &str| {
+            let quoted = serde_json::to_string(&format!("{base}/")).unwrap_or_default();
+            format!("\"backend_path\":{}", quoted.trim_end_matches('"'))
+        };
+
+        let conn = self.inner.lock();
+        let tx = conn
+            .unchecked_transaction()
+            .map_err(|e| FsError::Storage(format!("rename_tree begin: {e}")))?;
+        let moved = tx
+            .execute(
+                "UPDATE files SET
+                    logical_path = ?4 || substr(logical_path, ?5),
+                    backend_path = CASE
+                        WHEN backend_path >= ?2 AND backend_path < ?3
+                            THEN ?4 || substr(backend_path, ?5)
+                        WHEN backend_path >= ?6 AND backend_path < ?7
+                            THEN ?8 || substr(backend_path, ?9)
+                        ELSE backend_path END,
+                    replicas = replace(replicas, ?10, ?11)
+                 WHERE logical_path >= ?2 AND logical_path < ?3",
+                params![
+                    from,
+                    format!("{from}/"),
+                    format!("{from}0"),
+                    to,
+                    rest(from),
+                    format!("{from_rel}/"),
+                    format!("{from_rel}0"),
+                    to_rel,
+                    rest(from_rel),
+                    json_prefix(from_rel),
+                    json_prefix(to_rel),
+                ],
+            )
+            .map_err(|e| FsError::Storage(format!("rename_tree: {e}")))?;
+        tx.execute(
+            "UPDATE content_blobs SET backend_path = ?3 || substr(backend_path, ?4)
+             WHERE backend_path >= ?1 AND backend_path < ?2",
+            params![
+                format!("{from_rel}/"),
+                format!("{from_rel}0"),
+                to_rel,
+                rest(from_rel)
+            ],
+        )
+        .map_err(|e| FsError::Storage(format!("rename_tree blobs: {e}")))?;
+
+        // `from`'s own totals leave its ancestors and join `to`'s; the rows
+        // of `from` and everything below it just change name.
+        let totals: Vec<(String, i64, i64)> = {
+            let mut stmt = tx
+                .prepare("SELECT tier, files, bytes FROM dir_usage WHERE dir = ?1")
+                .map_err(|e| FsError::Storage(format!("rename_tree usage prepare: {e}")))?;
+            let rows = stmt
+                .query_map(params![from], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+                .map_err(|e| FsError::Storage(format!("rename_tree usage query: {e}")))?;
+            rows.collect::<std::result::Result<_, _>>()
+                .map_err(|e| FsError::Storage(format!("rename_tree usage row: {e}")))?
+        };
+        tx.execute(
+            "UPDATE dir_usage SET dir = ?4 || substr(dir, ?5)
+             WHERE dir = ?1 OR (dir >= ?2 AND dir < ?3)",
+            params![from, format!("{from}/"), format!("{from}0"), to, rest(from)],
+        )
+        .map_err(|e| FsError::Storage(format!("rename_tree usage: {e}")))?;
+        for (tier, files, bytes) in totals {
+            bump_usage(&tx, Path::new(from), &tier, -files, -bytes)?;
+            bump_usage(&tx, Path::new(to), &tier, files, bytes)?;
+        }
+        tx.commit()
+            .map_err(|e| FsError::Storage(format!("rename_tree commit: {e}")))?;
+        drop(conn);
+
+        self.cache.lock().retain(&mut |k| !k.starts_with(from));
+        Ok(moved as u64)
+    }
+
     fn record_access(&self, logical: &Path, when: SystemTime, delta_hits: u64) -> Result<()> {
         let conn = self.inner.lock();
         conn.execute(
@@ -561,17 +1005,53 @@ impl PathIndex for SqlitePathIndex {
         target_bytes: u64,
         min_age: Duration,
     ) -> Result<Vec<(PathBuf, u64)>> {
-        let cutoff = ts_secs(SystemTime::now()) - min_age.as_secs() as i64;
+        self.coldest_with_ages(tier, target_bytes, min_age, &[])
+    }
+
+    fn coldest_with_ages(
+        &self,
+        tier: TierId,
+        target_bytes: u64,
+        min_age: Duration,
+        ages: &[(PathBuf, Duration)],
+    ) -> Result<Vec<(PathBuf, u64)>> {
+        let now = ts_secs(self.clock.now());
+        let cutoff = |age: Duration| now - age.as_secs() as i64;
+        // One range per directory, as in `list_under`: `<dir>/` up to (not
+        // incl.) `<dir>0`, each with its own cutoff.
+        let mut args: Vec<rusqlite::types::Value> =
+            vec![tier.as_str().to_string().into(), cutoff(min_age).into()];
+        let mut case = String::new();
+        for (dir, age) in ages {
+            let dir = dir.to_string_lossy();
+            let dir = dir.trim_end_matches('/');
+            let n = args.len();
+            case.push_str(&format!(
+                " WHEN logical_path >= ?{} AND logical_path < ?{} THEN ?{}",
+                n + 1,
+                n + 2,
+                n + 3
+            ));
+            args.push(format!("{dir}/").into());
+            args.push(format!("{dir}0").into());
+            args.push(cutoff(*age).into());
+        }
+        let cutoff_sql = if case.is_empty() {
+            "?2".to_string()
+        } else {
+            format!("CASE{case} ELSE ?2 END")
+        };
         let conn = self.inner.lock();
         let mut stmt = conn
-            .prepare(
+            .prepare(&format!(
                 "SELECT logical_path, size FROM files
-                 WHERE tier = ?1 AND last_access <= ?2 AND pinned_tier IS NULL
-                 ORDER BY popularity ASC, last_access ASC",
-            )
+                 WHERE tier = ?1 AND last_access <= {cutoff_sql} AND pinned_tier IS NULL
+                   AND state != 'creating'
+                 ORDER BY popularity ASC, last_access ASC"
+            ))
             .map_err(|e| FsError::Storage(format!("coldest prepare: {e}")))?;
         let rows = stmt
-            .query_map(params![tier.as_str(), cutoff], |r| {
+            .query_map(rusqlite::params_from_iter(args), |r| {
                 Ok((r.get::<_, String>(0)?, r.get::<_, i64>(1)? as u64))
             })
             .map_err(|e| FsError::Storage(format!("coldest query: {e}")))?;
@@ -604,7 +1084,7 @@ impl PathIndex for SqlitePathIndex {
                 format!(
                     "SELECT logical_path, tier, backend_id, backend_path, size, last_access,
                             hit_count, popularity, pinned_tier, state, replicas,
-                        mutability, compressed, content_hash
+                        mutability, compressed, content_hash, pinned_until
                        FROM files WHERE tier = ?1
                        ORDER BY popularity {order}, last_access {order}
                        LIMIT ?2"
@@ -616,7 +1096,7 @@ impl PathIndex for SqlitePathIndex {
                 format!(
                     "SELECT logical_path, tier, backend_id, backend_path, size, last_access,
                             hit_count, popularity, pinned_tier, state, replicas,
-                        mutability, compressed, content_hash
+                        mutability, compressed, content_hash, pinned_until
                        FROM files
                        ORDER BY popularity {order}, last_access {order}
                        LIMIT ?1"
@@ -681,6 +1161,20 @@ impl PathIndex for SqlitePathIndex {
         Ok(())
     }
 
+    fn set_state(&self, logical: &Path, state: FileState) -> Result<()> {
+        let conn = self.inner.lock();
+        let n = conn
+            .execute(
+                "UPDATE files SET state = ?2 WHERE logical_path = ?1",
+                params![logical.to_string_lossy().as_ref(), state.as_str()],
+            )
+            .map_err(|e| FsError::Storage(format!("set_state: {e}")))?;
+        if n == 0 {
+            return Err(FsError::NotFound(logical.to_string_lossy().to_string()));
+        }
+        Ok(())
+    }
+
     fn set_content_hash(&self, logical: &Path, hash: &str) -> Result<()> {
         let conn = self.inner.lock();
         let n = conn
@@ -796,13 +1290,89 @@ impl PathIndex for SqlitePathIndex {
         }
     }
 
+    fn hot_copy(&self, logical: &Path) -> Result<Option<HotCopy>> {
+        let conn = self.inner.lock();
+        conn.query_row(
+            &format!("SELECT {HOT_COPY_COLUMNS} FROM hot_copies WHERE logical_path = ?1"),
+            params![logical.to_string_lossy().as_ref()],
+            hot_copy_from_row,
+        )
+        .optional()
+        .map_err(|e| FsError::Storage(format!("hot_copy: {e}")))
+    }
+
+    fn set_hot_copy(&self, copy: HotCopy) -> Result<()> {
+        let conn = self.inner.lock();
+        conn.execute(
+            "INSERT OR REPLACE INTO hot_copies
+                (logical_path, backend_id, backend_path, source_backend_id,
+                 source_backend_path, size, source_mtime_ns)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                copy.logical_path.to_string_lossy().as_ref(),
+                copy.backend_id,
+                copy.backend_path.to_string_lossy().as_ref(),
+                copy.source_backend_id,
+                copy.source_backend_path.to_string_lossy().as_ref(),
+                copy.size as i64,
+                ts_nanos(copy.source_mtime),
+            ],
+        )
+        .map_err(|e| FsError::Storage(format!("set_hot_copy: {e}")))?;
+        Ok(())
+    }
+
+    fn remove_hot_copy(&self, logical: &Path) -> Result<Option<HotCopy>> {
+        let conn = self.inner.lock();
+        let key = logical.to_string_lossy();
+        let copy = conn
+            .query_row(
+                &format!("SELECT {HOT_COPY_COLUMNS} FROM hot_copies WHERE logical_path = ?1"),
+                params![key.as_ref()],
+                hot_copy_from_row,
+            )
+            .optional()
+            .map_err(|e| FsError::Storage(format!("remove_hot_copy: {e}")))?;
+        if copy.is_some() {
+            conn.execute(
+                "DELETE FROM hot_copies WHERE logical_path = ?1",
+                params![key.as_ref()],
+            )
+            .map_err(|e| FsError::Storage(format!("remove_hot_copy: {e}")))?;
+        }
+        Ok(copy)
+    }
+
+    fn list_hot_copies(&self, dir: &Path) -> Result<Vec<HotCopy>> {
+        // Same range scan as `list_under`.
+        let dir = dir.to_string_lossy();
+        let base = dir.trim_end_matches('/');
+        let conn = self.inner.lock();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {HOT_COPY_COLUMNS} FROM hot_copies
+                   WHERE logical_path = ?1 OR (logical_path >= ?2 AND logical_path < ?3)
+                   ORDER BY logical_path"
+            ))
+            .map_err(|e| FsError::Storage(format!("list_hot_copies prepare: {e}")))?;
+        let rows: Vec<HotCopy> = stmt
+            .query_map(
+                params![base, format!("{base}/"), format!("{base}0")],
+                hot_copy_from_row,
+            )
+            .map_err(|e| FsError::Storage(format!("list_hot_copies query: {e}")))?
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| FsError::Storage(format!("list_hot_copies collect: {e}")))?;
+        Ok(rows)
+    }
+
     fn list_pinned(&self) -> Result<Vec<FileRow>> {
         let conn = self.inner.lock();
         let mut stmt = conn
             .prepare(
                 "SELECT logical_path, tier, backend_id, backend_path, size, last_access,
                         hit_count, popularity, pinned_tier, state, replicas,
-                        mutability, compressed, content_hash
+                        mutability, compressed, content_hash, pinned_until
                    FROM files
                    WHERE pinned_tier IS NOT NULL
                    ORDER BY logical_path",
@@ -815,6 +1385,113 @@ impl PathIndex for SqlitePathIndex {
             .map_err(|e| FsError::Storage(format!("list_pinned collect: {e}")))?;
         rows.into_iter().map(row_to_file).collect()
     }
+
+    fn expire_pins(&self, now: SystemTime) -> Result<Vec<PathBuf>> {
+        let conn = self.inner.lock();
+        let mut stmt = conn
+            .prepare(
+                "UPDATE files SET pinned_tier = NULL, pinned_until = NULL
+                   WHERE pinned_until IS NOT NULL AND pinned_until <= ?1
+                   RETURNING logical_path",
+            )
+            .map_err(|e| FsError::Storage(format!("expire_pins prepare: {e}")))?;
+        let paths: Vec<String> = stmt
+            .query_map(params![ts_secs(now)], |r| r.get(0))
+            .map_err(|e| FsError::Storage(format!("expire_pins query: {e}")))?
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| FsError::Storage(format!("expire_pins collect: {e}")))?;
+        Ok(paths.into_iter().map(PathBuf::from).collect())
+    }
+
+    fn list_under(&self, dir: &Path) -> Result<Vec<FileRow>> {
+        // Range scan on the primary key: `<dir>/` up to (not incl.) `<dir>0`,
+        // '0' being the byte after '/'.
+        let dir = dir.to_string_lossy();
+        let base = dir.trim_end_matches('/');
+        let conn = self.inner.lock();
+        let mut stmt = conn
+            .prepare(
+                "SELECT logical_path, tier, backend_id, backend_path, size, last_access,
+                        hit_count, popularity, pinned_tier, state, replicas,
+                        mutability, compressed, content_hash, pinned_until
+                   FROM files
+                   WHERE logical_path = ?1 OR (logical_path >= ?2 AND logical_path < ?3)
+                   ORDER BY logical_path",
+            )
+            .map_err(|e| FsError::Storage(format!("list_under prepare: {e}")))?;
+        let rows: Vec<_> = stmt
+            .query_map(
+                params![base, format!("{base}/"), format!("{base}0")],
+                parse_row,
+            )
+            .map_err(|e| FsError::Storage(format!("list_under query: {e}")))?
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| FsError::Storage(format!("list_under collect: {e}")))?;
+        rows.into_iter().map(row_to_file).collect()
+    }
+
+    fn set_size(&self, logical: &Path, size: u64) -> Result<()> {
+        let conn = self.inner.lock();
+        let tx = conn
+            .unchecked_transaction()
+            .map_err(|e| FsError::Storage(format!("set_size begin: {e}")))?;
+        let Some((tier, old)) = usage_of(&tx, logical)? else {
+            return Err(FsError::NotFound(logical.to_string_lossy().to_string()));
+        };
+        if old == size as i64 {
+            return Ok(());
+        }
+        tx.execute(
+            "UPDATE files SET size = ?2 WHERE logical_path = ?1",
+            params![logical.to_string_lossy().as_ref(), size as i64],
+        )
+        .map_err(|e| FsError::Storage(format!("set_size: {e}")))?;
+        bump_usage(&tx, logical, &tier, 0, size as i64 - old)?;
+        tx.commit()
+            .map_err(|e| FsError::Storage(format!("set_size commit: {e}")))?;
+        drop(conn);
+        if let Some(loc) = self.cache.lock().get(logical) {
+            loc.size = size;
+        }
+        Ok(())
+    }
+
+    fn dir_usage(&self, dir: &Path, depth: usize) -> Result<Vec<DirUsage>> {
+        // Same range scan as `list_under`, on `dir_usage.dir`.
+        let base = dir.to_string_lossy();
+        let base = base.trim_end_matches('/');
+        let conn = self.inner.lock();
+        let mut stmt = conn
+            .prepare(
+                "SELECT dir, tier, files, bytes
+                   FROM dir_usage
+                   WHERE dir = ?1 OR (dir >= ?2 AND dir < ?3)
+                   ORDER BY dir, tier",
+            )
+            .map_err(|e| FsError::Storage(format!("dir_usage prepare: {e}")))?;
+        let rows: Vec<(String, String, i64, i64)> = stmt
+            .query_map(params![base, format!("{base}/"), format!("{base}0")], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))
+            })
+            .map_err(|e| FsError::Storage(format!("dir_usage query: {e}")))?
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| FsError::Storage(format!("dir_usage collect: {e}")))?;
+        let mut out = Vec::new();
+        for (d, tier, files, bytes) in rows {
+            let d = PathBuf::from(d);
+            let level = d.strip_prefix(dir).map(|r| r.components().count());
+            if level.map_or(true, |l| l > depth) {
+                continue;
+            }
+            out.push(DirUsage {
+                dir: d,
+                tier: TierId::parse(&tier)?,
+                files: files.max(0) as u64,
+                bytes: bytes.max(0) as u64,
+            });
+        }
+        Ok(out)
+    }
 }
 
 type RawRow = (
@@ -832,6 +1509,7 @@ type RawRow = (
     String,         // mutability
     i64,            // compressed
     Option<String>, // content_hash
+    Option<i64>,    // pinned_until
 );
 
 fn parse_row(r: &rusqlite::Row<'_>) -> rusqlite::Result<RawRow> {
@@ -850,6 +1528,7 @@ fn parse_row(r: &rusqlite::Row<'_>) -> rusqlite::Result<RawRow> {
         r.get(11)?,
         r.get(12)?,
         r.get(13)?,
+        r.get(14)?,
     ))
 }
 
@@ -869,6 +1548,7 @@ fn row_to_file(raw: RawRow) -> Result<FileRow> {
         mutability,
         compressed,
         content_hash,
+        pinned_until,
     ) = raw;
     let pinned_tier = pinned.map(|s| TierId::parse(&s)).transpose()?;
     let replicas = parse_replicas(replicas)?;
@@ -885,6 +1565,7 @@ fn row_to_file(raw: RawRow) -> Result<FileRow> {
         hit_count: hits as u64,
         popularity: pop,
         pinned_tier,
+        pinned_until: pinned_until.map(ts_from_secs),
         state: FileState::parse(&state)?,
         mutability: Mutability::parse(&mutability)?,
         compressed: compressed != 0,
@@ -913,6 +1594,7 @@ fn serialize_replicas(rs: &[ReplicaLoc]) -> Result<Option<String>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
     use tempfile::TempDir;
 
     fn make_row(path: &str, tier: TierId, size: u64) -> FileRow {
@@ -928,6 +1610,7 @@ mod tests {
             hit_count: 0,
             popularity: 0.0,
             pinned_tier: None,
+            pinned_until: None,
             state: FileState::Stable,
             replicas: Vec::new(),
             mutability: Mutability::Unknown,
@@ -978,6 +1661,33 @@ mod tests {
         assert!(idx.locate(Path::new("/g")).unwrap().is_none());
     }
 
+    #[test]
+    fn expired_pins_are_cleared() {
+        let (_d, idx) = open();
+        let now = SystemTime::now();
+        for (path, until) in [("/due", 10), ("/later", 3600)] {
+            idx.insert(FileRow {
+                pinned_tier: Some(TierId::Fast),
+                pinned_until: Some(now + Duration::from_secs(until)),
+                ..make_row(path, TierId::Slow, 1)
+            })
+            .unwrap();
+        }
+        idx.insert(FileRow {
+            pinned_tier: Some(TierId::Fast),
+            ..make_row("/forever", TierId::Fast, 1)
+        })
+        .unwrap();
+
+        let expired = idx.expire_pins(now + Duration::from_secs(60)).unwrap();
+        assert_eq!(expired, vec![PathBuf::from("/due")]);
+        let due = idx.get(Path::new("/due")).unwrap().unwrap();
+        assert_eq!((due.pinned_tier, due.pinned_until), (None, None));
+        let pinned: Vec<_> = idx.list_pinned().unwrap();
+        assert_eq!(pinned.len(), 2);
+        assert!(pinned.iter().any(|r| r.pinned_until.is_some()));
+    }
+
     #[test]
     fn rename_moves_key() {
         let (_d, idx) = open();
@@ -987,6 +1697,63 @@ mod tests {
         assert!(idx.locate(Path::new("/new")).unwrap().is_some());
     }
 
+    #[test]
+    fn rename_tree_moves_deep_descendants() {
+        let (_d, idx) = open();
+        let mut deep = PathBuf::from("/proj");
+        for level in 0..20 {
+            deep.push(format!("d{level}"));
+            let mut row = make_row(&deep.join("f").to_string_lossy(), TierId::Fast, 10);
+            row.location.backend_path = row.logical_path.strip_prefix("/").unwrap().into();
+            idx.insert(row).unwrap();
+        }
+        let mut row = make_row("/proj/d0/m", TierId::Slow, 5);
+        row.replicas = vec![
+            ReplicaLoc::new("b1", "proj/d0/m"),
+            ReplicaLoc::new("b2", "other/m"),
+        ];
+        idx.insert(row).unwrap();
+        idx.insert(make_row("/proj0", TierId::Fast, 1)).unwrap();
+        idx.insert(make_row("/projx/f", TierId::Fast, 1)).unwrap();
+        idx.locate(Path::new("/proj/d0/f")).unwrap();
+
+        let moved = idx
+            .rename_tree(Path::new("/proj"), Path::new("/archive/p"))
+            .unwrap();
+        assert_eq!(moved, 21);
+        assert!(idx.locate(Path::new("/proj/d0/f")).unwrap().is_none());
+        let deepest = Path::new("/archive/p").join(deep.strip_prefix("/proj").unwrap());
+        let loc = idx.locate(&deepest.join("f")).unwrap().unwrap();
+        assert_eq!(
+            loc.backend_path,
+            deepest.join("f").strip_prefix("/").unwrap()
+        );
+        let m = idx.get(Path::new("/archive/p/d0/m")).unwrap().unwrap();
+        assert_eq!(m.replicas[0].backend_path, Path::new("archive/p/d0/m"));
+        assert_eq!(m.replicas[1].backend_path, Path::new("other/m"));
+        // Siblings that merely share the prefix stay put.
+        assert!(idx.locate(Path::new("/proj0")).unwrap().is_some());
+        assert!(idx.locate(Path::new("/projx/f")).unwrap().is_some());
+
+        let usage = |dir: &str| -> Vec<(String, u64, u64)> {
+            idx.dir_usage(Path::new(dir), 0)
+                .unwrap()
+                .into_iter()
+                .map(|u| (u.tier.as_str().to_string(), u.files, u.bytes))
+                .collect()
+        };
+        assert!(usage("/proj").is_empty());
+        assert_eq!(
+            usage("/archive"),
+            [("fast".to_string(), 20, 200), ("slow".to_string(), 1, 5)]
+        );
+        assert_eq!(usage("/archive/p/d0/d1"), [("fast".to_string(), 19, 190)]);
+        assert_eq!(
+            usage("/"),
+            [("fast".to_string(), 22, 202), ("slow".to_string(), 1, 5)]
+        );
+    }
+
     #[test]
     fn coldest_respects_min_age() {
         let (_d, idx) = open();
@@ -1001,6 +1768,51 @@ mod tests {
         assert_eq!(v.len(), 2);
     }
 
+    #[test]
+    fn coldest_ages_with_the_clock() {
+        let dir = TempDir::new().unwrap();
+        let clock = Arc::new(MockClock::default());
+        let idx =
+            SqlitePathIndex::open_with_clock(dir.path().join("idx.db"), clock.clone()).unwrap();
+        idx.insert(make_row("/report.pdf", TierId::Fast, 100))
+            .unwrap();
+        let month = Duration::from_secs(30 * 86_400);
+
+        clock.advance(month - Duration::from_secs(60));
+        assert!(idx.coldest(TierId::Fast, 1000, month).unwrap().is_empty());
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(
+            idx.coldest(TierId::Fast, 1000, month).unwrap(),
+            vec![(PathBuf::from("/report.pdf"), 100)]
+        );
+    }
+
+    #[test]
+    fn coldest_with_ages_holds_back_by_directory() {
+        let dir = TempDir::new().unwrap();
+        let clock = Arc::new(MockClock::default());
+        let idx =
+            SqlitePathIndex::open_with_clock(dir.path().join("idx.db"), clock.clone()).unwrap();
+        for path in ["/a/f", "/ab", "/b/f", "/z"] {
+            idx.insert(make_row(path, TierId::Fast, 1)).unwrap();
+        }
+        let hour = Duration::from_secs(3600);
+        let ages = [
+            (PathBuf::from("/a"), 2 * hour),
+            (PathBuf::from("/b/"), Duration::ZERO),
+        ];
+        clock.advance(hour + Duration::from_secs(60));
+        let mut got: Vec<PathBuf> = idx
+            .coldest_with_ages(TierId::Fast, u64::MAX, hour, &ages)
+            .unwrap()
+            .into_iter()
+            .map(|v| v.0)
+            .collect();
+        got.sort();
+        // `/ab` only shares a prefix with `/a`, so it takes the default.
+        assert_eq!(got, [Path::new("/ab"), Path::new("/b/f"), Path::new("/z")]);
+    }
+
     #[test]
     fn coldest_stops_at_target_bytes() {
         let (_d, idx) = open();
@@ -1019,6 +1831,25 @@ mod tests {
         assert_eq!(TierId::Archive.as_str(), "archive");
     }
 
+    #[test]
+    fn files_being_created_are_not_demoted() {
+        let (_d, idx) = open();
+        let mut row = make_row("/new.bin", TierId::Fast, 0);
+        row.last_access = SystemTime::UNIX_EPOCH;
+        row.state = FileState::Creating;
+        idx.insert(row).unwrap();
+        let cold = || idx.coldest(TierId::Fast, 10, Duration::ZERO).unwrap();
+        assert!(cold().is_empty());
+        idx.set_state(Path::new("/new.bin"), FileState::Stable)
+            .unwrap();
+        assert_eq!(cold().len(), 1);
+        let row = idx.get(Path::new("/new.bin")).unwrap().unwrap();
+        assert_eq!(row.state, FileState::Stable);
+        assert!(idx
+            .set_state(Path::new("/gone"), FileState::Stable)
+            .is_err());
+    }
+
     #[test]
     fn coldest_query_on_archive_tier_works() {
         let (_d, idx) = open();
@@ -1031,6 +1862,89 @@ mod tests {
         assert_eq!(v.len(), 1);
     }
 
+    #[test]
+    fn list_under_matches_whole_components() {
+        let (_d, idx) = open();
+        for p in ["/a/x", "/a/b/y", "/ab/z", "/c"] {
+            idx.insert(make_row(p, TierId::Fast, 1)).unwrap();
+        }
+        let paths = |dir: &str| -> Vec<PathBuf> {
+            idx.list_under(Path::new(dir))
+                .unwrap()
+                .into_iter()
+                .map(|r| r.logical_path)
+                .collect()
+        };
+        assert_eq!(
+            paths("/a"),
+            vec![PathBuf::from("/a/b/y"), PathBuf::from("/a/x")]
+        );
+        assert_eq!(paths("/a/x"), vec![PathBuf::from("/a/x")]);
+        assert_eq!(paths("/").len(), 4);
+    }
+
+    #[test]
+    fn dir_usage_follows_mutations() {
+        let dir = TempDir::new().unwrap();
+        let db = dir.path().join("idx.db");
+        let idx = SqlitePathIndex::open(&db).unwrap();
+        idx.insert(make_row("/a/x", TierId::Fast, 10)).unwrap();
+        idx.insert(make_row("/a/b/y", TierId::Fast, 5)).unwrap();
+        idx.insert(make_row("/c", TierId::Slow, 7)).unwrap();
+        idx.set_size(Path::new("/a/x"), 30).unwrap();
+        idx.swap_location(
+            Path::new("/a/b/y"),
+            Location {
+                tier: TierId::Slow,
+                backend_id: "b1".into(),
+                backend_path: PathBuf::from("/a/b/y"),
+                size: 5,
+            },
+        )
+        .unwrap();
+        idx.rename(Path::new("/c"), Path::new("/a/c")).unwrap();
+
+        fn usage(
+            idx: &SqlitePathIndex,
+            dir: &str,
+            depth: usize,
+        ) -> Vec<(String, TierId, u64, u64)> {
+            idx.dir_usage(Path::new(dir), depth)
+                .unwrap()
+                .into_iter()
+                .map(|u| (u.dir.display().to_string(), u.tier, u.files, u.bytes))
+                .collect()
+        }
+        let expected = vec![
+            ("/".to_string(), TierId::Fast, 1, 30),
+            ("/".to_string(), TierId::Slow, 2, 12),
+            ("/a".to_string(), TierId::Fast, 1, 30),
+            ("/a".to_string(), TierId::Slow, 2, 12),
+        ];
+        assert_eq!(usage(&idx, "/", 1), expected);
+        assert_eq!(
+            usage(&idx, "/a", 1)[2..],
+            [("/a/b".to_string(), TierId::Slow, 1, 5)]
+        );
+
+        idx.remove(Path::new("/a/b/y")).unwrap();
+        assert!(usage(&idx, "/a/b", 0).is_empty());
+
+        // A database that predates `dir_usage` gets it rebuilt on open.
+        drop(idx);
+        let conn = Connection::open(&db).unwrap();
+        conn.execute("DELETE FROM dir_usage", []).unwrap();
+        drop(conn);
+        let idx = SqlitePathIndex::open(&db).unwrap();
+        assert_eq!(
+            usage(&idx, "/", 0),
+            [
+                ("/".to_string(), TierId::Fast, 1, 30),
+                ("/".to_string(), TierId::Slow, 1, 7),
+            ]
+        );
+    }
+
     #[test]
     fn persists_across_reopen() {
         let dir = TempDir::new().unwrap();
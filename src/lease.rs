@@ -0,0 +1,345 @@
+//! Lease service for storage shared between hosts (`rhss lock serve`,
+//! `[lock] lease_server`).
+//!
+//! The storage lock (`crate::lock`) is a file lock, and file locks only
+//! exclude hosts when the filesystem carries them to a common server: NFS
+//! mounted with `nolock`, or two hosts each with their own db directory
+//! over the same tier roots, both get "their" lock. A lease service is a
+//! single arbiter every host asks instead.
+//!
+//! The protocol is one JSON object per line over TCP, a `LeaseRequest`
+//! answered by a `LeaseResponse`. A lease is held by a `holder`
+//! (`pid@host`) for `ttl_secs`; acquiring again as the same holder renews
+//! it, which the lock's heartbeat does every `lock::HEARTBEAT_INTERVAL`.
+//! A holder that stops renewing loses the lease when the TTL runs out.
+//! Leases live in memory: after a restart of the service every daemon
+//! re-acquires its lease on its next heartbeat.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+use crate::error::{FsError, Result};
+
+/// Connect, read and write timeout of a client call.
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseOp {
+    /// Take or renew the lease.
+    Acquire,
+    /// Give it up; a no-op unless `holder` holds it.
+    Release,
+    /// Who holds it, without changing anything.
+    Status,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaseRequest {
+    pub op: LeaseOp,
+    pub key: String,
+    pub holder: String,
+    #[serde(default)]
+    pub ttl_secs: u64,
+    /// Take the lease from a live holder (`--force --really-force`).
+    #[serde(default)]
+    pub steal: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LeaseResponse {
+    /// The request took effect; for `status`, that the lease is held.
+    pub ok: bool,
+    /// Current holder, if the lease is held.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// `pid@host` of this process.
+pub fn local_holder() -> String {
+    let host = whoami::fallible::hostname().unwrap_or_else(|_| "unknown".into());
+    format!("{}@{host}", std::process::id())
+}
+
+/// Leases by key. Separate from the server so it can be tested without
+/// sockets.
+#[derive(Debug, Default)]
+pub struct LeaseTable {
+    leases: HashMap<String, (String, Instant)>,
+}
+
+impl LeaseTable {
+    pub fn handle(&mut self, req: &LeaseRequest, now: Instant) -> LeaseResponse {
+        self.leases.retain(|_, (_, expires)| *expires > now);
+        let current = self.leases.get(&req.key).cloned();
+        let held = |holder: String, expires: Instant, ok: bool| LeaseResponse {
+            ok,
+            holder: Some(holder),
+            expires_in_secs: Some(expires.saturating_duration_since(now).as_secs()),
+            error: None,
+        };
+        match req.op {
+            LeaseOp::Status => match current {
+                Some((holder, expires)) => held(holder, expires, true),
+                None => LeaseResponse::default(),
+            },
+            LeaseOp::Release => {
+                if current.is_some_and(|(h, _)| h == req.holder) {
+                    self.leases.remove(&req.key);
+                }
+                LeaseResponse {
+                    ok: true,
+                    ..Default::default()
+                }
+            }
+            LeaseOp::Acquire => match current {
+                Some((holder, expires)) if holder != req.holder && !req.steal => {
+                    held(holder, expires, false)
+                }
+                _ => {
+                    let expires = now + Duration::from_secs(req.ttl_secs.max(1));
+                    self.leases
+                        .insert(req.key.clone(), (req.holder.clone(), expires));
+                    held(req.holder.clone(), expires, true)
+                }
+            },
+        }
+    }
+}
+
+/// Owns the listener and the accept thread. Drop stops accepting.
+pub struct LeaseServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl LeaseServer {
+    pub fn start(addr: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(addr).map_err(FsError::Io)?;
+        listener.set_nonblocking(true).map_err(FsError::Io)?;
+        let addr = listener.local_addr().map_err(FsError::Io)?;
+        info!("lease service listening on {addr}");
+
+        let table = Arc::new(Mutex::new(LeaseTable::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_for_thread = Arc::clone(&shutdown);
+        let handle = std::thread::Builder::new()
+            .name("rhss-lease".into())
+            .spawn(move || accept_loop(listener, table, shutdown_for_thread))
+            .map_err(|e| FsError::Storage(format!("lease: spawn listener: {e}")))?;
+        Ok(Self {
+            addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    /// Where the server ended up listening (useful with port 0).
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for LeaseServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, table: Arc<Mutex<LeaseTable>>, shutdown: Arc<AtomicBool>) {
+    while !shutdown.load(SeqCst) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let table = Arc::clone(&table);
+                let spawned = std::thread::Builder::new()
+                    .name("rhss-lease-conn".into())
+                    .spawn(move || {
+                        if let Err(e) = serve_conn(stream, &table) {
+                            debug!("lease connection from {peer}: {e}");
+                        }
+                    });
+                if let Err(e) = spawned {
+                    warn!("lease: spawn connection thread: {e}");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => {
+                error!("lease accept failed: {e}");
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+    debug!("lease accept loop exit");
+}
+
+fn serve_conn(stream: TcpStream, table: &Mutex<LeaseTable>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    let mut out = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let resp = match serde_json::from_str::<LeaseRequest>(&line) {
+            Ok(req) => {
+                let resp = table.lock().unwrap().handle(&req, Instant::now());
+                if req.op == LeaseOp::Acquire && !resp.ok {
+                    debug!("lease {} refused to {}", req.key, req.holder);
+                } else if req.steal {
+                    warn!("lease {} taken over by {}", req.key, req.holder);
+                }
+                resp
+            }
+            Err(e) => LeaseResponse {
+                error: Some(format!("bad request: {e}")),
+                ..Default::default()
+            },
+        };
+        let mut json = serde_json::to_string(&resp).map_err(io::Error::other)?;
+        json.push('\n');
+        out.write_all(json.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// One storage's lease on one server, held as `local_holder()`.
+#[derive(Debug, Clone)]
+pub struct LeaseClient {
+    server: String,
+    key: String,
+    holder: String,
+}
+
+impl LeaseClient {
+    pub fn new(server: String, key: String) -> Self {
+        Self {
+            server,
+            key,
+            holder: local_holder(),
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Take or renew the lease for `ttl`. `ok = false` with the holder
+    /// when someone else has it.
+    pub fn acquire(&self, ttl: Duration, steal: bool) -> Result<LeaseResponse> {
+        self.call(LeaseOp::Acquire, ttl.as_secs(), steal)
+    }
+
+    pub fn release(&self) -> Result<()> {
+        self.call(LeaseOp::Release, 0, false).map(|_| ())
+    }
+
+    pub fn status(&self) -> Result<LeaseResponse> {
+        self.call(LeaseOp::Status, 0, false)
+    }
+
+    fn call(&self, op: LeaseOp, ttl_secs: u64, steal: bool) -> Result<LeaseResponse> {
+        let req = LeaseRequest {
+            op,
+            key: self.key.clone(),
+            holder: self.holder.clone(),
+            ttl_secs,
+            steal,
+        };
+        let failed = |e: io::Error| FsError::Storage(format!("lease server {}: {e}", self.server));
+        let addr = self
+            .server
+            .to_socket_addrs()
+            .map_err(failed)?
+            .next()
+            .ok_or_else(|| FsError::Storage(format!("lease server {}: no address", self.server)))?;
+        let mut stream = TcpStream::connect_timeout(&addr, CALL_TIMEOUT).map_err(failed)?;
+        stream
+            .set_read_timeout(Some(CALL_TIMEOUT))
+            .map_err(failed)?;
+        stream
+            .set_write_timeout(Some(CALL_TIMEOUT))
+            .map_err(failed)?;
+        let mut json = serde_json::to_string(&req)?;
+        json.push('\n');
+        stream.write_all(json.as_bytes()).map_err(failed)?;
+        let mut line = String::new();
+        BufReader::new(stream)
+            .read_line(&mut line)
+            .map_err(failed)?;
+        let resp: LeaseResponse = serde_json::from_str(&line)?;
+        if let Some(e) = &resp.error {
+            return Err(FsError::Storage(format!(
+                "lease server {}: {e}",
+                self.server
+            )));
+        }
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leases_exclude_renew_and_expire() {
+        let server = LeaseServer::start("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().to_string();
+        let a = LeaseClient::new(addr.clone(), "store".into());
+        let mut b = LeaseClient::new(addr, "store".into());
+        b.holder = "1@elsewhere".into();
+        let ttl = Duration::from_secs(30);
+
+        assert!(a.acquire(ttl, false).unwrap().ok);
+        assert!(a.acquire(ttl, false).unwrap().ok, "renewal");
+        let refused = b.acquire(ttl, false).unwrap();
+        assert!(!refused.ok);
+        assert_eq!(refused.holder.as_deref(), Some(a.holder()));
+        assert_eq!(b.status().unwrap().holder.as_deref(), Some(a.holder()));
+
+        // Releasing someone else's lease does nothing; stealing does.
+        b.release().unwrap();
+        assert!(b.status().unwrap().ok);
+        assert!(b.acquire(ttl, true).unwrap().ok);
+        assert!(!a.acquire(ttl, false).unwrap().ok);
+        b.release().unwrap();
+        assert!(!a.status().unwrap().ok);
+
+        // Expiry, on the table directly.
+        let mut table = LeaseTable::default();
+        let now = Instant::now();
+        let req = |holder: &str| LeaseRequest {
+            op: LeaseOp::Acquire,
+            key: "k".into(),
+            holder: holder.into(),
+            ttl_secs: 10,
+            steal: false,
+        };
+        assert!(table.handle(&req("x"), now).ok);
+        assert!(!table.handle(&req("y"), now + Duration::from_secs(9)).ok);
+        assert!(table.handle(&req("y"), now + Duration::from_secs(11)).ok);
+    }
+}
@@ -2,21 +2,58 @@
 //!
 //! v2.3 plan: see `docs/plan/README.md`.
 
+// D14: macOS and Linux only. The FUSE frontend, daemon, control socket and
+// POSIX backend are Unix code throughout, and there is no WinFsp/Dokan
+// frontend; Windows users run rhss under WSL2.
+#[cfg(not(unix))]
+compile_error!("rhss builds on Unix only (D14); on Windows, run it under WSL2");
+
 pub mod access;
+pub mod analyze;
+pub mod audit;
 pub mod backend;
+pub mod build_info;
+pub mod builder;
+pub mod civil;
 pub mod cli;
+pub mod clock;
 pub mod config;
 pub mod control;
+pub mod cost;
+pub mod daemon;
 pub mod error;
+pub mod ffi;
+pub mod filter;
 pub mod fuse;
+pub mod health;
+pub mod hidden;
+pub mod hooks;
 pub mod index;
+pub mod lease;
 pub mod lock;
+pub mod mounts;
+pub mod namespace;
+pub mod ninep;
+pub mod logging;
+pub mod otlp;
 pub mod policy;
+pub mod preheat;
+pub mod preflight;
+pub mod probes;
+pub mod profile;
+pub mod quota;
 pub mod scan;
+pub mod simulate;
+pub mod tenant;
+pub mod throttle;
 pub mod tier;
 pub mod tierer;
+pub mod trash;
+pub mod volume;
+pub mod webdav;
 
 pub use backend::{Backend, BackendStats, FileMetadata, PosixBackend};
+pub use builder::{Rhss, RhssBuilder};
 pub use config::RhssConfig;
 pub use error::{FsError, Result};
 pub use fuse::FuseAdapter;
@@ -1,162 +1,552 @@
-use std::fs::{File, OpenOptions, Permissions};
+//! 存储锁：同一份存储同时只允许一个 rhss 进程。
+//!
+//! 锁本身是锁文件上的独占文件锁（`File::try_lock`，Unix 上即 `flock`），
+//! 由进程在整个生命周期内持有打开的 fd；进程退出（包括崩溃、被 kill -9）时
+//! 内核自动释放，不依赖 PID 存活判断，也不受 PID 重用影响。NFS 上 Linux 会
+//! 把 `flock` 转成 `fcntl` 字节锁，由服务器仲裁。
+//!
+//! 锁文件里的 JSON（PID、主机、版本……）只用于提示是谁持有锁，不参与判断。
+//! 存储目录的权限不动：备份程序照常能读，崩溃后也不会留下锁死的目录。
+//!
+//! `[lock] immutable = true` 时，持锁期间给锁文件加上 immutable 标志
+//! （Linux `FS_IMMUTABLE_FL`，需要 `CAP_LINUX_IMMUTABLE`；macOS
+//! `UF_IMMUTABLE`），防止有人手动删掉它。锁信息里记着标志是 rhss 加的，
+//! 崩溃后下次启动拿到锁时据此清掉。
+//!
+//! 只读的使用者（`rhss mount --shared`）不碰独占锁，而是在旁边的
+//! `.rhss.lock.shared` 上加 `LOCK_SH`：可以和独占持有者、也可以彼此同时运行，
+//! 不写锁信息。
+//!
+//! 独占持有者每 `HEARTBEAT_INTERVAL` 改写一次旁边的 `.rhss.lock.heartbeat`
+//! （锁文件可能带 immutable 标志，写不了）。另一台主机上的持有者查不了 PID，
+//! 就看心跳：超过 `HEARTBEAT_STALE` 没更新算已经不在了。`--force` 只在锁看起来
+//! 已经失效时直接接管；持有者还活着或无法确认时要求 `--really-force`（或交互
+//! 确认），见 `force_lock`。
+//!
+//! 层路径放在多台主机共享的 NFS 上时，文件锁不一定管得住别的主机（`nolock`
+//! 挂载、或者两台主机各用各的数据库目录却指向同一组层路径）。所以：
+//!
+//! - 拿到文件锁后如果发现另一台主机的心跳还是新鲜的，说明文件锁在主机之间
+//!   不生效，拒绝启动；
+//! - `[lock] host_markers = true` 时在每个层根目录里也写心跳（`.rhss-owner`），
+//!   用不同配置指向同一组层路径的主机同样会被发现；
+//! - 运行中的心跳线程每次写之前检查有没有别人写过，发现就报错；
+//! - `[lock] lease_server` 指向一个 `rhss lock serve` 租约服务时，还要从那里
+//!   拿租约（`crate::lease`），随心跳续期。
+
+use std::fs::{File, OpenOptions, TryLockError};
 use std::io::{Read, Write};
-use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, UNIX_EPOCH};
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn, error};
 
+use crate::clock::{Clock, SystemClock};
+use crate::lease::{LeaseClient, LeaseResponse};
+
 /// 锁文件信息
-#[derive(Debug, Serialize, Deserialize)]
-struct LockInfo {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockInfo {
     /// 进程 ID
-    pid: u32,
-    /// 进程启动时间（用于验证 PID 是否被重用）
-    start_time: u64,
+    pub pid: u32,
+    /// 进程启动时间，Unix 秒（用于验证 PID 是否被重用；0 = 未知）
+    pub start_time: u64,
     /// 主机名
-    hostname: String,
+    pub hostname: String,
     /// 锁创建时间
-    created_at: u64,
+    pub created_at: u64,
     /// 程序版本
-    version: String,
+    pub version: String,
+    /// 挂载点，或 serve 命令的服务地址
+    #[serde(default)]
+    pub mount: Option<String>,
+    /// 锁文件的 immutable 标志是否由持有者设置（崩溃恢复时据此清除）
+    #[serde(default)]
+    pub immutable: bool,
+    /// 持有者的构建信息（提交、特性、FUSE ABI）；旧版本写的锁文件没有
+    #[serde(default)]
+    pub build: Option<crate::build_info::BuildInfo>,
+}
+
+/// 心跳间隔
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 心跳超过这么久没更新，认为持有者已经不在了
+pub const HEARTBEAT_STALE: Duration = Duration::from_secs(60);
+
+/// 层根目录里的心跳文件（`[lock] host_markers`），在 `crate::filter` 里保留
+pub const OWNER_MARKER: &str = ".rhss-owner";
+
+/// 心跳文件内容：谁、最后一次是什么时候
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub pid: u32,
+    pub hostname: String,
+    /// Unix 秒
+    pub at: u64,
+    /// 写心跳的进程持有的锁文件（分辨层根目录属于哪份存储）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock: Option<PathBuf>,
+}
+
+/// `rhss lock status` 看到的锁状态
+#[derive(Debug, Serialize)]
+pub struct LockStatus {
+    pub lock_file: PathBuf,
+    /// 是否有进程持有 flock
+    pub held: bool,
+    /// 锁文件里记录的持有者（没持有时可能是上次退出留下的旧信息）
+    pub holder: Option<LockInfo>,
+    /// 持有进程是否在运行：本机看 PID，其他主机看心跳；`None` = 无法确认
+    /// （其他主机上没有心跳的旧版本）
+    pub alive: Option<bool>,
+    /// 持有者的心跳是多少秒前；没有心跳时为 `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heartbeat_age: Option<u64>,
+    /// 是否有共享（只读）使用者
+    pub readers: bool,
+    /// 租约服务上的状态（配置了 `[lock] lease_server` 时由调用者填）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<LeaseResponse>,
+}
+
+impl LockStatus {
+    /// 锁被持有，而且持有者没有确认已经不在了
+    pub fn looks_live(&self) -> bool {
+        self.held && self.alive != Some(false)
+    }
 }
 
 /// 存储锁管理器
 pub struct StorageLock {
-    /// 锁文件路径
+    /// 锁文件路径（已去重：同一文件的两个 fd 互相冲突）
     lock_files: Vec<PathBuf>,
-    /// 存储目录路径
-    storage_dirs: Vec<PathBuf>,
-    /// 原始目录权限（用于恢复）
-    original_permissions: Vec<Option<Permissions>>,
+    /// 持有 flock 的锁文件，关闭即释放
+    held: Vec<File>,
     /// 是否已经获取锁
     locked: bool,
+    /// 写进锁信息的挂载点
+    mount: Option<String>,
+    /// 共享（只读）模式
+    shared: bool,
+    /// 持锁期间给锁文件加 immutable 标志
+    immutable: bool,
+    /// 写心跳的线程，关闭发送端即停止
+    heartbeat: Option<(Sender<()>, JoinHandle<()>)>,
+    /// 层根目录里的心跳文件
+    markers: Vec<PathBuf>,
+    /// 租约服务
+    lease: Option<LeaseClient>,
+    /// `force_lock(true)`：跳过心跳检查，抢租约
+    steal: bool,
+    /// 心跳时间和过期判断用的时钟
+    clock: Arc<dyn Clock>,
 }
 
 impl StorageLock {
     /// 创建新的存储锁
     pub fn new(hot_path: &Path, cold_path: &Path) -> Self {
-        let lock_files = vec![
-            hot_path.join(".rhss.lock"),
-            cold_path.join(".rhss.lock"),
-        ];
-        
-        let storage_dirs = vec![
-            hot_path.to_path_buf(),
-            cold_path.to_path_buf(),
-        ];
-        
-        let original_permissions = vec![None, None];
+        let mut lock_files = vec![hot_path.join(".rhss.lock")];
+        if cold_path != hot_path {
+            lock_files.push(cold_path.join(".rhss.lock"));
+        }
         
         Self {
             lock_files,
-            storage_dirs,
-            original_permissions,
+            held: Vec::new(),
             locked: false,
+            mount: None,
+            shared: false,
+            immutable: false,
+            heartbeat: None,
+            markers: Vec::new(),
+            lease: None,
+            steal: false,
+            clock: Arc::new(SystemClock),
         }
     }
     
+    /// 创建共享（只读）锁：不排斥独占持有者，也不排斥其他共享者
+    pub fn shared(lock_dir: &Path) -> Self {
+        Self {
+            lock_files: vec![lock_dir.join(".rhss.lock.shared")],
+            held: Vec::new(),
+            locked: false,
+            mount: None,
+            shared: true,
+            immutable: false,
+            heartbeat: None,
+            markers: Vec::new(),
+            lease: None,
+            steal: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+    
+    /// 在锁信息里记录挂载点（或服务地址），供 `rhss lock status` 显示
+    pub fn with_mount(mut self, mount: impl Into<String>) -> Self {
+        self.mount = Some(mount.into());
+        self
+    }
+    
+    /// 持锁期间给锁文件加 immutable 标志（`[lock] immutable`）
+    pub fn with_immutable(mut self, on: bool) -> Self {
+        self.immutable = on;
+        self
+    }
+    
+    /// 在这些层根目录里也写心跳（`[lock] host_markers`）
+    pub fn with_host_markers<P: AsRef<Path>>(mut self, roots: impl IntoIterator<Item = P>) -> Self {
+        self.markers = roots.into_iter().map(|r| r.as_ref().join(OWNER_MARKER)).collect();
+        self.markers.sort();
+        self.markers.dedup();
+        self
+    }
+    
+    /// 还要从租约服务拿租约（`[lock] lease_server`）
+    pub fn with_lease(mut self, lease: Option<LeaseClient>) -> Self {
+        self.lease = lease;
+        self
+    }
+    
+    /// 心跳的时间和是否过期按这个时钟算（测试用 `MockClock`）
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+    
     /// 尝试获取锁
     pub fn try_lock(&mut self) -> Result<()> {
         if self.locked {
             return Ok(());
         }
+        let clock = Arc::clone(&self.clock);
         
-        // 检查所有锁文件
+        // 逐个锁文件加 flock；任何一个失败就放掉已拿到的
         for lock_file in &self.lock_files {
-            self.check_and_clean_stale_lock(lock_file)?;
-            
-            // 尝试创建锁文件
-            match OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(lock_file)
-            {
-                Ok(mut file) => {
-                    // 写入锁信息
-                    let lock_info = LockInfo {
-                        pid: process::id(),
-                        start_time: get_process_start_time(),
-                        hostname: whoami::fallible::hostname().unwrap_or_else(|_| "unknown".into()),
-                        created_at: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs(),
-                        version: env!("CARGO_PKG_VERSION").to_string(),
+            let file = open_lock_file(lock_file)
+                .map_err(|e| anyhow!("打开锁文件失败 {:?}: {}", lock_file, e))?;
+            if self.shared {
+                // 只和 `inspect` 的瞬时探测冲突，阻塞等它结束即可
+                if let Err(err) = file.lock_shared() {
+                    self.held.clear();
+                    return Err(anyhow!("锁定锁文件失败 {:?}: {}", lock_file, err));
+                }
+                self.held.push(file);
+                info!("成功获取共享存储锁: {:?}", lock_file);
+                continue;
+            }
+            if let Err(err) = file.try_lock() {
+                self.held.clear();
+                if let TryLockError::Error(err) = err {
+                    return Err(anyhow!("锁定锁文件失败 {:?}: {}", lock_file, err));
+                }
+                // 已被其他进程持有，读取信息用于提示
+                if let Ok(info) = read_lock_info(lock_file) {
+                    let heartbeat = read_heartbeat(lock_file);
+                    let state = match holder_alive(&info, heartbeat.as_ref(), &*clock) {
+                        Some(true) => "运行中",
+                        // flock 仍被持有，多半是该进程 fork 出的子进程继承了 fd
+                        Some(false) if info.hostname == local_hostname() => {
+                            "已退出或 PID 已被重用，锁由其他进程继承"
+                        }
+                        Some(false) => "在其他主机上，心跳已停止",
+                        None => "在其他主机上，没有心跳，无法确认",
                     };
-                    
-                    let json = serde_json::to_string_pretty(&lock_info)?;
-                    file.write_all(json.as_bytes())?;
-                    file.sync_all()?;
-                    
-                    info!("成功获取存储锁: {:?}", lock_file);
+                    return Err(anyhow!(
+                        "存储目录已被锁定！\n\
+                        锁定进程: PID {} @ {}（{}）\n\
+                        锁定时间: {} 秒前\n\
+                        锁文件: {:?}\n\
+                        \n\
+                        如果确定该进程已经退出，可以使用 --force 参数强制启动",
+                        info.pid,
+                        info.hostname,
+                        state,
+                        now_secs(&*clock).saturating_sub(info.created_at),
+                        lock_file
+                    ));
+                } else {
+                    return Err(anyhow!("存储目录已被锁定，但无法读取锁信息: {:?}", lock_file));
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
-                    // 锁文件已存在，读取信息
-                    if let Ok(info) = self.read_lock_info(lock_file) {
-                        return Err(anyhow!(
-                            "存储目录已被锁定！\n\
-                            锁定进程: PID {} @ {}\n\
-                            锁定时间: {} 秒前\n\
-                            锁文件: {:?}\n\
-                            \n\
-                            如果确定该进程已经退出，可以手动删除锁文件或使用 --force 参数强制启动",
-                            info.pid,
-                            info.hostname,
-                            SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs() - info.created_at,
-                            lock_file
-                        ));
-                    } else {
-                        return Err(anyhow!("存储目录已被锁定，但无法读取锁信息: {:?}", lock_file));
-                    }
+            }
+
+            // 拿到了文件锁，另一台主机却还在写心跳：文件锁在主机之间不生效
+            if !self.steal {
+                if let Some(beat) = foreign_heartbeat(&heartbeat_path(lock_file), &*clock) {
+                    self.held.clear();
+                    return Err(anyhow!(
+                        "拿到了文件锁，但 {} 上的 rhss（PID {}）{} 秒前还在写心跳：\n\
+                        文件锁在主机之间不生效（NFS 用了 nolock？），两个实例会同时写入\n\
+                        锁文件: {:?}\n\
+                        \n\
+                        可以配置 [lock] lease_server；确认它已经停止后，使用 --force --really-force 启动",
+                        beat.hostname,
+                        beat.pid,
+                        now_secs(&*clock).saturating_sub(beat.at),
+                        lock_file
+                    ));
                 }
-                Err(e) => {
-                    return Err(anyhow!("创建锁文件失败 {:?}: {}", lock_file, e));
+            }
+
+            // 拿到锁。锁文件还带着 immutable 标志说明上次没能正常退出
+            if is_immutable(&file) {
+                if !read_lock_info(lock_file).is_ok_and(|info| info.immutable) {
+                    self.held.clear();
+                    return Err(anyhow!("锁文件带有不是 rhss 设置的 immutable 标志: {:?}", lock_file));
+                }
+                warn!("上次运行未正常退出，清除锁文件的 immutable 标志: {:?}", lock_file);
+                if let Err(e) = set_immutable(&file, false) {
+                    self.held.clear();
+                    return Err(anyhow!("清除 immutable 标志失败 {:?}: {}", lock_file, e));
                 }
             }
+
+            // 改写锁信息（仅供提示）
+            let lock_info = LockInfo {
+                pid: process::id(),
+                start_time: get_process_start_time(process::id()).unwrap_or(0),
+                hostname: local_hostname(),
+                created_at: now_secs(&*clock),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                mount: self.mount.clone(),
+                immutable: self.immutable,
+                build: Some(crate::build_info::BuildInfo::current()),
+            };
+            let json = serde_json::to_string_pretty(&lock_info)?;
+            let mut info_file = OpenOptions::new().write(true).truncate(true).open(lock_file)?;
+            info_file.write_all(json.as_bytes())?;
+            info_file.sync_all()?;
+            if self.immutable {
+                match set_immutable(&file, true) {
+                    Ok(()) => info!("已给锁文件加上 immutable 标志: {:?}", lock_file),
+                    Err(e) => warn!("无法给锁文件加 immutable 标志 {:?}: {}", lock_file, e),
+                }
+            }
+            self.held.push(file);
+
+            info!("成功获取存储锁: {:?}", lock_file);
         }
         
-        // 修改目录权限，限制访问
-        for (i, dir) in self.storage_dirs.iter().enumerate() {
-            if dir.exists() {
-                // 保存原始权限
-                let metadata = std::fs::metadata(dir)?;
-                self.original_permissions[i] = Some(metadata.permissions());
-                
-                // 设置新权限：只有所有者可以读写执行 (0o700)
-                let mut new_perms = metadata.permissions();
-                new_perms.set_mode(0o700);
-                std::fs::set_permissions(dir, new_perms)?;
-                
-                info!("已限制目录访问权限: {:?} (mode=0o700)", dir);
+        if !self.shared {
+            if let Err(e) = self.check_hosts() {
+                self.held.clear();
+                return Err(e);
             }
+            self.start_heartbeat();
         }
-        
         self.locked = true;
         Ok(())
     }
     
-    /// 强制获取锁（清理现有锁）
-    pub fn force_lock(&mut self) -> Result<()> {
+    /// 层根目录里别人的心跳、租约服务上别人的租约
+    fn check_hosts(&mut self) -> Result<()> {
+        if !self.steal {
+            for marker in &self.markers {
+                if let Some(beat) = foreign_heartbeat(marker, &*self.clock) {
+                    return Err(anyhow!(
+                        "{} 上的 rhss（PID {}）{} 秒前还在使用层目录 {:?}（它的锁: {:?}）\n\
+                        多个主机共用同一组层路径，数据会被破坏；请检查各主机的配置",
+                        beat.hostname,
+                        beat.pid,
+                        now_secs(&*self.clock).saturating_sub(beat.at),
+                        marker.parent().unwrap_or(marker),
+                        beat.lock.unwrap_or_default()
+                    ));
+                }
+            }
+        }
+        if let Some(lease) = &self.lease {
+            let resp = lease
+                .acquire(HEARTBEAT_STALE, self.steal)
+                .map_err(|e| anyhow!("获取租约失败: {}", e))?;
+            if !resp.ok {
+                return Err(anyhow!(
+                    "租约服务 {} 上的 {:?} 由 {} 持有（{} 秒后过期）\n\
+                    \n\
+                    确认它已经停止后，使用 --force --really-force 接管",
+                    lease.server(),
+                    lease.key(),
+                    resp.holder.as_deref().unwrap_or("未知持有者"),
+                    resp.expires_in_secs.unwrap_or(0)
+                ));
+            }
+            info!("已获取租约 {:?} @ {}", lease.key(), lease.server());
+        }
+        Ok(())
+    }
+    
+    /// 立即写一次心跳，之后由后台线程每 `HEARTBEAT_INTERVAL` 写一次
+    fn start_heartbeat(&mut self) {
+        let mut files: Vec<PathBuf> = self.lock_files.iter().map(|f| heartbeat_path(f)).collect();
+        files.extend(self.markers.iter().cloned());
+        let lock = self.lock_files[0].clone();
+        let lease = self.lease.clone();
+        let clock = Arc::clone(&self.clock);
+        let beat = move || {
+            for f in &files {
+                // 层根目录可能还没建（`scan::ensure_managed_dirs` 在拿锁之后）
+                if !f.parent().is_some_and(Path::exists) {
+                    continue;
+                }
+                // 上次写完之后有别人写过：另一个主机（或进程）在用同一份存储
+                if let Some(other) = foreign_heartbeat(f, &*clock) {
+                    error!(
+                        "{} 上的 rhss（PID {}）也在写 {:?}：多个实例在使用同一份存储，数据会被破坏",
+                        other.hostname, other.pid, f
+                    );
+                }
+                if let Err(e) = write_heartbeat(f, &lock, &*clock) {
+                    warn!("写心跳失败 {:?}: {}", f, e);
+                }
+            }
+            if let Some(lease) = &lease {
+                match lease.acquire(HEARTBEAT_STALE, false) {
+                    Ok(resp) if resp.ok => {}
+                    Ok(resp) => error!(
+                        "租约 {:?} 已被 {} 拿走：另一个实例在使用同一份存储",
+                        lease.key(),
+                        resp.holder.as_deref().unwrap_or("未知持有者")
+                    ),
+                    Err(e) => warn!("续租失败: {}", e),
+                }
+            }
+        };
+        beat();
+        let (tx, rx) = mpsc::channel::<()>();
+        let spawned = std::thread::Builder::new()
+            .name("rhss-lock-heartbeat".into())
+            .spawn(move || {
+                // 发送端关闭（unlock）时退出
+                while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(HEARTBEAT_INTERVAL) {
+                    beat();
+                }
+            });
+        match spawned {
+            Ok(handle) => self.heartbeat = Some((tx, handle)),
+            Err(e) => warn!("无法启动心跳线程: {}", e),
+        }
+    }
+    
+    /// 这把锁的锁文件里，看起来仍然有效（持有者在运行或无法确认）的那些
+    pub fn live_holders(&self) -> Result<Vec<LockStatus>> {
+        let mut live = Vec::new();
+        for lock_file in &self.lock_files {
+            let status = status_of(lock_file, &*self.clock)?;
+            if status.looks_live() {
+                live.push(status);
+            }
+        }
+        Ok(live)
+    }
+    
+    /// 强制获取锁（清理现有锁），返回被接管的锁的状态。
+    ///
+    /// 持有者确认已经不在了（本机 PID 已退出或被重用、其他主机的心跳已停止）
+    /// 才直接接管；否则两个实例会同时写入，除非 `really` 表示调用者已经确认
+    /// 过（`--really-force` 或交互确认），一律拒绝
+    pub fn force_lock(&mut self, really: bool) -> Result<Vec<LockStatus>> {
         if self.locked {
-            return Ok(());
+            return Ok(Vec::new());
+        }
+        if !really {
+            self.check_remote_holders()?;
         }
         
-        // 强制删除所有锁文件
+        // 先全部检查，不要删到一半才发现有一把还活着
+        let mut taken = Vec::new();
+        for lock_file in &self.lock_files {
+            let status = status_of(lock_file, &*self.clock)?;
+            if status.looks_live() && !really {
+                let who = status
+                    .holder
+                    .as_ref()
+                    .map_or("未知进程".to_string(), |h| format!("PID {} @ {}", h.pid, h.hostname));
+                let state = if status.alive == Some(true) {
+                    "仍在运行"
+                } else {
+                    "无法确认是否还在运行"
+                };
+                return Err(anyhow!(
+                    "锁持有者 {}（{}）{}，强制接管后两个实例会同时写入\n\
+                    锁文件: {:?}\n\
+                    \n\
+                    确认它已经停止后，使用 --force --really-force 接管",
+                    who,
+                    status
+                        .heartbeat_age
+                        .map_or("没有心跳".to_string(), |s| format!("心跳 {s} 秒前")),
+                    state,
+                    lock_file
+                ));
+            }
+            if status.held {
+                taken.push(status);
+            }
+        }
+        
+        // 强制删除所有锁文件。新建的锁文件是新的 inode，原持有者（如果还活着）
+        // 的 flock 留在已删除的旧文件上，不再拦得住别人
         for lock_file in &self.lock_files {
             if lock_file.exists() {
                 warn!("强制删除现有锁文件: {:?}", lock_file);
+                clear_immutable(lock_file)?;
                 std::fs::remove_file(lock_file)?;
             }
         }
+        for status in &taken {
+            if status.looks_live() {
+                error!(
+                    "强制接管仍然有效的存储锁 {:?}（持有者 {}）",
+                    status.lock_file,
+                    status.holder.as_ref().map_or(0, |h| h.pid)
+                );
+            }
+        }
         
         // 重新获取锁
-        self.try_lock()
+        self.steal = really;
+        let res = self.try_lock();
+        self.steal = false;
+        res?;
+        Ok(taken)
+    }
+    
+    /// `--force` 删锁文件管不到的持有者：层根目录里别人的心跳、别人的租约
+    fn check_remote_holders(&self) -> Result<()> {
+        for marker in &self.markers {
+            if let Some(beat) = foreign_heartbeat(marker, &*self.clock) {
+                return Err(anyhow!(
+                    "{} 上的 rhss（PID {}）{} 秒前还在使用层目录 {:?}\n\
+                    \n\
+                    确认它已经停止后，使用 --force --really-force 接管",
+                    beat.hostname,
+                    beat.pid,
+                    now_secs(&*self.clock).saturating_sub(beat.at),
+                    marker.parent().unwrap_or(marker)
+                ));
+            }
+        }
+        if let Some(lease) = &self.lease {
+            let resp = lease.status().map_err(|e| anyhow!("查询租约失败: {}", e))?;
+            if let Some(holder) = resp.holder.filter(|h| resp.ok && h != lease.holder()) {
+                return Err(anyhow!(
+                    "租约服务 {} 上的 {:?} 由 {} 持有（{} 秒后过期）\n\
+                    \n\
+                    确认它已经停止后，使用 --force --really-force 接管",
+                    lease.server(),
+                    lease.key(),
+                    holder,
+                    resp.expires_in_secs.unwrap_or(0)
+                ));
+            }
+        }
+        Ok(())
     }
     
     /// 检查是否已经获取锁
@@ -170,86 +560,257 @@ impl StorageLock {
             return Ok(());
         }
         
-        // 恢复目录原始权限
-        for (i, dir) in self.storage_dirs.iter().enumerate() {
-            if dir.exists() {
-                if let Some(ref original_perms) = self.original_permissions[i] {
-                    std::fs::set_permissions(dir, original_perms.clone())?;
-                    info!("已恢复目录原始权限: {:?}", dir);
-                } else {
-                    // 如果没有保存原始权限，恢复为默认权限 (0o755)
-                    let mut default_perms = std::fs::metadata(dir)?.permissions();
-                    default_perms.set_mode(0o755);
-                    std::fs::set_permissions(dir, default_perms)?;
-                    info!("已恢复目录默认权限: {:?} (mode=0o755)", dir);
-                }
-            }
+        if let Some((tx, handle)) = self.heartbeat.take() {
+            drop(tx);
+            let _ = handle.join();
         }
-        
-        // 删除锁文件
-        for lock_file in &self.lock_files {
-            if lock_file.exists() {
-                // 验证是否是我们的锁
-                if let Ok(info) = self.read_lock_info(lock_file) {
-                    if info.pid == process::id() {
-                        std::fs::remove_file(lock_file)?;
-                        info!("已释放存储锁: {:?}", lock_file);
-                    } else {
-                        warn!("锁文件不属于当前进程，跳过: {:?}", lock_file);
-                    }
-                }
+        if let Some(lease) = &self.lease {
+            if let Err(e) = lease.release() {
+                warn!("释放租约失败: {}", e);
             }
         }
-        
-        self.locked = false;
-        Ok(())
-    }
-    
-    /// 检查并清理过期的锁
-    fn check_and_clean_stale_lock(&self, lock_file: &Path) -> Result<()> {
-        if !lock_file.exists() {
-            return Ok(());
+        for marker in &self.markers {
+            // 只删自己的：别人的心跳留着给下一个启动的人看
+            if read_heartbeat_file(marker).is_some_and(|b| is_own(&b)) {
+                let _ = std::fs::remove_file(marker);
+            }
         }
         
-        match self.read_lock_info(lock_file) {
-            Ok(info) => {
-                // 检查进程是否还在运行
-                if !is_process_running(info.pid) {
-                    warn!("检测到过期锁文件（进程 {} 已退出），正在清理...", info.pid);
-                    std::fs::remove_file(lock_file)?;
-                    return Ok(());
+        // 清空锁信息并关闭 fd 释放 flock。锁文件本身保留：删掉它会让
+        // 刚打开旧文件的进程和新建文件的进程各自拿到一把锁
+        for (file, lock_file) in self.held.drain(..).zip(&self.lock_files) {
+            if !self.shared {
+                if self.immutable {
+                    if let Err(e) = set_immutable(&file, false) {
+                        warn!("清除 immutable 标志失败 {:?}: {}", lock_file, e);
+                    }
                 }
-                
-                // 检查锁是否太旧（超过24小时）
-                let age = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() - info.created_at;
-                    
-                if age > 86400 {
-                    warn!("检测到超过24小时的锁文件，可能是异常情况，正在清理...");
-                    std::fs::remove_file(lock_file)?;
-                    return Ok(());
+                let cleared = OpenOptions::new().write(true).truncate(true).open(lock_file);
+                if let Err(e) = cleared {
+                    warn!("清空锁信息失败 {:?}: {}", lock_file, e);
                 }
+                let _ = std::fs::remove_file(heartbeat_path(lock_file));
             }
-            Err(e) => {
-                warn!("无法读取锁文件信息，可能已损坏: {:?}", e);
-                std::fs::remove_file(lock_file)?;
-                return Ok(());
-            }
+            drop(file);
+            info!("已释放存储锁: {:?}", lock_file);
         }
         
+        self.locked = false;
         Ok(())
     }
-    
-    /// 读取锁信息
-    fn read_lock_info(&self, lock_file: &Path) -> Result<LockInfo> {
-        let mut file = File::open(lock_file)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        let info: LockInfo = serde_json::from_str(&contents)?;
-        Ok(info)
+}
+
+/// 打开锁文件，不存在就创建。带 immutable 标志的文件打不开写，退回只读：
+/// 本地 flock 不需要写权限（NFS 上的独占锁需要，所以能写时总是以写打开）
+fn open_lock_file(lock_file: &Path) -> std::io::Result<File> {
+    let rw = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_file);
+    match rw {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => File::open(lock_file),
+        r => r,
+    }
+}
+
+/// 删除锁文件前清掉它的 immutable 标志
+fn clear_immutable(lock_file: &Path) -> Result<()> {
+    let file = match File::open(lock_file) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(anyhow!("打开锁文件失败 {:?}: {}", lock_file, e)),
+    };
+    if is_immutable(&file) {
+        set_immutable(&file, false)
+            .map_err(|e| anyhow!("清除 immutable 标志失败 {:?}: {}", lock_file, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn is_immutable(file: &File) -> bool {
+    rustix::fs::ioctl_getflags(file).is_ok_and(|f| f.contains(rustix::fs::IFlags::IMMUTABLE))
+}
+
+#[cfg(target_os = "linux")]
+fn set_immutable(file: &File, on: bool) -> std::io::Result<()> {
+    let mut flags = rustix::fs::ioctl_getflags(file)?;
+    flags.set(rustix::fs::IFlags::IMMUTABLE, on);
+    Ok(rustix::fs::ioctl_setflags(file, flags)?)
+}
+
+#[cfg(target_os = "macos")]
+fn file_flags(file: &File) -> std::io::Result<u32> {
+    use std::os::fd::AsRawFd;
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(file.as_raw_fd(), &mut st) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(st.st_flags)
+}
+
+#[cfg(target_os = "macos")]
+fn is_immutable(file: &File) -> bool {
+    file_flags(file).is_ok_and(|f| f & libc::UF_IMMUTABLE != 0)
+}
+
+#[cfg(target_os = "macos")]
+fn set_immutable(file: &File, on: bool) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let flags = file_flags(file)?;
+    let flags = if on {
+        flags | libc::UF_IMMUTABLE
+    } else {
+        flags & !libc::UF_IMMUTABLE
+    };
+    if unsafe { libc::fchflags(file.as_raw_fd(), flags) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn is_immutable(_file: &File) -> bool {
+    false
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn set_immutable(_file: &File, _on: bool) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// 读取锁信息
+fn read_lock_info(lock_file: &Path) -> Result<LockInfo> {
+    let mut file = File::open(lock_file)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let info: LockInfo = serde_json::from_str(&contents)?;
+    Ok(info)
+}
+
+fn local_hostname() -> String {
+    whoami::fallible::hostname().unwrap_or_else(|_| "unknown".into())
+}
+
+fn now_secs(clock: &dyn Clock) -> u64 {
+    clock
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// `<lock_file>.heartbeat`
+fn heartbeat_path(lock_file: &Path) -> PathBuf {
+    let mut s = lock_file.as_os_str().to_owned();
+    s.push(".heartbeat");
+    PathBuf::from(s)
+}
+
+/// 写临时文件再改名，读的一方不会看到写了一半的心跳
+fn write_heartbeat(path: &Path, lock: &Path, clock: &dyn Clock) -> Result<()> {
+    let beat = Heartbeat {
+        pid: process::id(),
+        hostname: local_hostname(),
+        at: now_secs(clock),
+        lock: Some(lock.to_path_buf()),
+    };
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, serde_json::to_vec(&beat)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// 锁文件旁边的心跳；没有或读不出来时为 `None`
+fn read_heartbeat(lock_file: &Path) -> Option<Heartbeat> {
+    read_heartbeat_file(&heartbeat_path(lock_file))
+}
+
+fn read_heartbeat_file(path: &Path) -> Option<Heartbeat> {
+    let bytes = std::fs::read(path).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+fn is_own(beat: &Heartbeat) -> bool {
+    beat.pid == process::id() && beat.hostname == local_hostname()
+}
+
+/// 别人写的、还新鲜的心跳：另一台主机上的，或本机上另一个还在运行的进程
+fn foreign_heartbeat(path: &Path, clock: &dyn Clock) -> Option<Heartbeat> {
+    let beat = read_heartbeat_file(path)?;
+    if is_own(&beat) || now_secs(clock).saturating_sub(beat.at) > HEARTBEAT_STALE.as_secs() {
+        return None;
     }
+    if beat.hostname == local_hostname() && get_process_start_time(beat.pid).is_none() {
+        return None;
+    }
+    Some(beat)
+}
+
+/// 查看 `lock_dir` 下的存储锁，不获取它
+pub fn inspect(lock_dir: &Path) -> Result<LockStatus> {
+    status_of(&lock_dir.join(".rhss.lock"), &SystemClock)
+}
+
+fn status_of(lock_file: &Path, clock: &dyn Clock) -> Result<LockStatus> {
+    let lock_dir = lock_file.parent().unwrap_or(Path::new("."));
+    // 独占锁用共享锁探测，共享锁用独占锁探测；探测到就立即释放
+    let held = probe(lock_file, false)?;
+    let readers = probe(&lock_dir.join(".rhss.lock.shared"), true)?;
+    let holder = read_lock_info(lock_file).ok();
+    let heartbeat = holder
+        .as_ref()
+        .and_then(|h| read_heartbeat(lock_file).filter(|b| heartbeat_of(h, b)));
+    let alive = holder
+        .as_ref()
+        .and_then(|h| holder_alive(h, heartbeat.as_ref(), clock));
+    Ok(LockStatus {
+        lock_file: lock_file.to_path_buf(),
+        held,
+        holder,
+        alive,
+        heartbeat_age: heartbeat.map(|b| now_secs(clock).saturating_sub(b.at)),
+        readers,
+        lease: None,
+    })
+}
+
+/// `lock_file` 上是否有与独占（`exclusive`）或共享锁冲突的文件锁
+fn probe(lock_file: &Path, exclusive: bool) -> Result<bool> {
+    let file = match File::open(lock_file) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(anyhow!("打开锁文件失败 {:?}: {}", lock_file, e)),
+    };
+    let res = if exclusive {
+        file.try_lock()
+    } else {
+        file.try_lock_shared()
+    };
+    match res {
+        Ok(()) => Ok(false),
+        Err(TryLockError::WouldBlock) => Ok(true),
+        Err(TryLockError::Error(e)) => Err(anyhow!("检查锁文件失败 {:?}: {}", lock_file, e)),
+    }
+}
+
+/// 打破 `lock_dir` 下的存储锁：删除锁文件，原持有者的 flock 随旧 inode 失效。
+/// 持有进程确认还在本机运行时拒绝，应先 `rhss umount`
+pub fn break_lock(lock_dir: &Path) -> Result<LockStatus> {
+    let status = inspect(lock_dir)?;
+    if status.held && status.alive == Some(true) {
+        let pid = status.holder.as_ref().map_or(0, |h| h.pid);
+        return Err(anyhow!("锁持有进程 PID {} 仍在运行，请先停止它（rhss umount）", pid));
+    }
+    clear_immutable(&status.lock_file)?;
+    match std::fs::remove_file(&status.lock_file) {
+        Ok(()) => warn!("已删除锁文件: {:?}", status.lock_file),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow!("删除锁文件失败 {:?}: {}", status.lock_file, e)),
+    }
+    Ok(status)
 }
 
 impl Drop for StorageLock {
@@ -262,34 +823,76 @@ impl Drop for StorageLock {
     }
 }
 
-/// 检查进程是否在运行
-#[cfg(unix)]
-fn is_process_running(pid: u32) -> bool {
-    // 发送信号 0 来检查进程是否存在
-    unsafe {
-        libc::kill(pid as i32, 0) == 0
+/// 心跳是不是锁信息记录的那个持有者写的
+fn heartbeat_of(info: &LockInfo, beat: &Heartbeat) -> bool {
+    beat.pid == info.pid && beat.hostname == info.hostname
+}
+
+/// 锁文件记录的进程是否仍在运行：同一主机上 PID 存在且启动时间一致。
+/// 启动时间不同说明 PID 已被重用。其他主机上的进程看它的心跳是否在
+/// `HEARTBEAT_STALE` 之内；没有心跳（旧版本）时无法判断，返回 `None`
+fn holder_alive(info: &LockInfo, heartbeat: Option<&Heartbeat>, clock: &dyn Clock) -> Option<bool> {
+    let host = whoami::fallible::hostname().ok()?;
+    if host != info.hostname {
+        let beat = heartbeat.filter(|b| heartbeat_of(info, b))?;
+        return Some(now_secs(clock).saturating_sub(beat.at) <= HEARTBEAT_STALE.as_secs());
     }
+    Some(match get_process_start_time(info.pid) {
+        Some(start) => info.start_time == 0 || start == info.start_time,
+        None => false,
+    })
 }
 
-#[cfg(not(unix))]
-fn is_process_running(_pid: u32) -> bool {
-    // Windows 上的实现会更复杂，这里简化处理
-    false
+/// 获取进程启动时间（Unix 秒）；进程不存在或平台不支持时返回 `None`
+#[cfg(target_os = "linux")]
+fn get_process_start_time(pid: u32) -> Option<u64> {
+    // /proc/<pid>/stat 第 22 个字段是开机后的时钟节拍数。comm 可能含空格和
+    // 括号，所以从最后一个 ')' 之后开始数（那里是第 3 个字段）
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let ticks: u64 = rest.split_whitespace().nth(19)?.parse().ok()?;
+    let btime: u64 = std::fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|l| l.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if hz <= 0 {
+        return None;
+    }
+    Some(btime + ticks / hz as u64)
 }
 
-/// 获取进程启动时间
-fn get_process_start_time() -> u64 {
-    // 简化实现，使用当前时间
-    // 实际应该读取 /proc/[pid]/stat 或使用系统 API
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
+/// 获取进程启动时间（Unix 秒）；进程不存在或平台不支持时返回 `None`
+#[cfg(target_os = "macos")]
+fn get_process_start_time(pid: u32) -> Option<u64> {
+    let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    let n = unsafe {
+        libc::proc_pidinfo(
+            pid as libc::c_int,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    (n == size).then_some(info.pbi_start_tvsec)
+}
+
+/// 获取进程启动时间（Unix 秒）；进程不存在或平台不支持时返回 `None`
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn get_process_start_time(_pid: u32) -> Option<u64> {
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
+    use std::time::SystemTime;
     use tempfile::TempDir;
     
     #[test]
@@ -340,4 +943,295 @@ mod tests {
         // 现在第二个锁可以成功
         assert!(lock2.try_lock().is_ok());
     }
+    
+    #[test]
+    fn test_leftover_lock_file_does_not_block() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("store");
+        std::fs::create_dir_all(&dir).unwrap();
+        
+        // 上一个进程崩溃留下的锁文件：没有 flock 持有者，内容也不可信
+        std::fs::write(dir.join(".rhss.lock"), b"{\"pid\": 1}").unwrap();
+        
+        let mut lock1 = StorageLock::new(&dir, &dir);
+        assert!(lock1.try_lock().is_ok());
+        
+        // 同一目录只锁一次，第二个实例失败
+        let mut lock2 = StorageLock::new(&dir, &dir);
+        assert!(lock2.try_lock().is_err());
+        
+        // 持有者退出（fd 关闭）后锁自动释放
+        drop(lock1);
+        assert!(lock2.try_lock().is_ok());
+        let info = read_lock_info(&dir.join(".rhss.lock")).unwrap();
+        assert_eq!(info.pid, process::id());
+    }
+    
+    #[test]
+    fn test_process_start_time() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let start = get_process_start_time(process::id()).unwrap();
+        assert!(start <= now);
+        assert_eq!(get_process_start_time(process::id()), Some(start));
+        
+        let mut info = LockInfo {
+            pid: process::id(),
+            start_time: start,
+            hostname: whoami::fallible::hostname().unwrap(),
+            created_at: now,
+            version: String::new(),
+            mount: None,
+            immutable: false,
+            build: None,
+        };
+        assert_eq!(holder_alive(&info, None, &SystemClock), Some(true));
+        
+        // 同一 PID、不同启动时间：PID 被重用
+        info.start_time = start - 1;
+        assert_eq!(holder_alive(&info, None, &SystemClock), Some(false));
+        
+        // 其他主机：看心跳
+        info.hostname = "elsewhere.invalid".into();
+        assert_eq!(holder_alive(&info, None, &SystemClock), None);
+        let mut beat = Heartbeat {
+            pid: info.pid,
+            hostname: info.hostname.clone(),
+            at: now,
+            lock: None,
+        };
+        assert_eq!(holder_alive(&info, Some(&beat), &SystemClock), Some(true));
+        beat.at = now - HEARTBEAT_STALE.as_secs() - 1;
+        assert_eq!(holder_alive(&info, Some(&beat), &SystemClock), Some(false));
+        // 别人的心跳不算
+        beat.at = now;
+        beat.pid += 1;
+        assert_eq!(holder_alive(&info, Some(&beat), &SystemClock), None);
+    }
+    
+    #[test]
+    fn test_inspect_and_break() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("store");
+        std::fs::create_dir_all(&dir).unwrap();
+        
+        let status = inspect(&dir).unwrap();
+        assert!(!status.held && status.holder.is_none());
+        
+        let mut lock = StorageLock::new(&dir, &dir).with_mount("/mnt/rhss");
+        lock.try_lock().unwrap();
+        let status = inspect(&dir).unwrap();
+        assert!(status.held);
+        assert_eq!(status.alive, Some(true));
+        assert_eq!(status.holder.unwrap().mount.as_deref(), Some("/mnt/rhss"));
+        
+        // 持有者还活着，不能打破
+        assert!(break_lock(&dir).is_err());
+        assert!(dir.join(".rhss.lock").exists());
+        
+        // 查看不会影响持有者
+        let mut other = StorageLock::new(&dir, &dir);
+        assert!(other.try_lock().is_err());
+        
+        // 持有者还活着：--force 不够，要 --really-force
+        assert_eq!(other.live_holders().unwrap().len(), 1);
+        let err = other.force_lock(false).unwrap_err().to_string();
+        assert!(err.contains("--really-force"), "{err}");
+        assert!(dir.join(".rhss.lock").exists());
+        let taken = other.force_lock(true).unwrap();
+        assert_eq!(taken.len(), 1);
+        assert!(other.is_locked());
+        assert!(read_heartbeat(&dir.join(".rhss.lock")).is_some());
+        drop(lock);
+        other.unlock().unwrap();
+        assert!(read_heartbeat(&dir.join(".rhss.lock")).is_none());
+    }
+    
+    #[test]
+    fn test_shared_lock() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("store");
+        std::fs::create_dir_all(&dir).unwrap();
+        
+        let mut writer = StorageLock::new(&dir, &dir);
+        writer.try_lock().unwrap();
+        
+        // 独占持有者在运行时，多个只读使用者照样能拿到共享锁
+        let mut reader1 = StorageLock::shared(&dir);
+        let mut reader2 = StorageLock::shared(&dir);
+        assert!(reader1.try_lock().is_ok());
+        assert!(reader2.try_lock().is_ok());
+        let status = inspect(&dir).unwrap();
+        assert!(status.held && status.readers);
+        
+        // 第二个独占者仍然失败
+        assert!(StorageLock::new(&dir, &dir).try_lock().is_err());
+        
+        reader1.unlock().unwrap();
+        reader2.unlock().unwrap();
+        assert!(!inspect(&dir).unwrap().readers);
+    }
+    
+    #[test]
+    fn test_storage_dirs_keep_their_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("store");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        
+        let mut lock = StorageLock::new(&dir, &dir);
+        lock.try_lock().unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+    
+    #[test]
+    fn test_immutable_flag_left_by_crash_is_cleared() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("store");
+        std::fs::create_dir_all(&dir).unwrap();
+        let lock_file = dir.join(".rhss.lock");
+        
+        // 模拟崩溃：锁信息记着 immutable，标志还在，但没有进程持有 flock
+        let info = LockInfo {
+            pid: 1,
+            start_time: 0,
+            hostname: "crashed".into(),
+            created_at: 0,
+            version: String::new(),
+            mount: None,
+            immutable: true,
+            build: None,
+        };
+        std::fs::write(&lock_file, serde_json::to_string(&info).unwrap()).unwrap();
+        if set_immutable(&File::open(&lock_file).unwrap(), true).is_err() {
+            // 没有 CAP_LINUX_IMMUTABLE 或文件系统不支持
+            return;
+        }
+        
+        let mut lock = StorageLock::new(&dir, &dir);
+        let res = lock.try_lock();
+        clear_immutable(&lock_file).unwrap();
+        res.unwrap();
+        assert!(!read_lock_info(&lock_file).unwrap().immutable);
+        lock.unlock().unwrap();
+        
+        // 正常持有时加上标志，释放时清掉
+        let mut lock = StorageLock::new(&dir, &dir).with_immutable(true);
+        lock.try_lock().unwrap();
+        assert!(is_immutable(&File::open(&lock_file).unwrap()));
+        assert!(std::fs::remove_file(&lock_file).is_err());
+        lock.unlock().unwrap();
+        assert!(!is_immutable(&File::open(&lock_file).unwrap()));
+    }
+    
+    #[test]
+    fn test_other_hosts_heartbeat_blocks() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("store");
+        let root = temp_dir.path().join("ssd");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&root).unwrap();
+        let elsewhere = serde_json::to_string(&Heartbeat {
+            pid: 1,
+            hostname: "elsewhere.invalid".into(),
+            at: now_secs(&SystemClock),
+            lock: Some("/other/.rhss.lock".into()),
+        })
+        .unwrap();
+        
+        // 拿到了文件锁，但另一台主机还在写心跳：文件锁在主机之间不生效
+        let sidecar = heartbeat_path(&dir.join(".rhss.lock"));
+        std::fs::write(&sidecar, &elsewhere).unwrap();
+        let mut lock = StorageLock::new(&dir, &dir);
+        let err = lock.try_lock().unwrap_err().to_string();
+        assert!(err.contains("elsewhere.invalid"), "{err}");
+        std::fs::remove_file(&sidecar).unwrap();
+        
+        // 另一台主机用自己的锁目录指向同一个层根目录
+        let marker = root.join(OWNER_MARKER);
+        std::fs::write(&marker, &elsewhere).unwrap();
+        let mut lock = StorageLock::new(&dir, &dir).with_host_markers([&root]);
+        let err = lock.try_lock().unwrap_err().to_string();
+        assert!(err.contains("/other/.rhss.lock"), "{err}");
+        assert!(!lock.is_locked());
+        assert!(lock.force_lock(false).unwrap_err().to_string().contains("--really-force"));
+        lock.force_lock(true).unwrap();
+        assert!(is_own(&read_heartbeat_file(&marker).unwrap()));
+        lock.unlock().unwrap();
+        assert!(!marker.exists());
+        
+        // 过期的心跳不算
+        let stale = Heartbeat {
+            pid: 1,
+            hostname: "elsewhere.invalid".into(),
+            at: now_secs(&SystemClock) - HEARTBEAT_STALE.as_secs() - 1,
+            lock: None,
+        };
+        std::fs::write(&marker, serde_json::to_string(&stale).unwrap()).unwrap();
+        lock.try_lock().unwrap();
+    }
+    
+    #[test]
+    fn test_force_refuses_a_live_holder() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let mut holder = StorageLock::new(dir, dir);
+        holder.try_lock().unwrap();
+        let sidecar = heartbeat_path(&dir.join(".rhss.lock"));
+        assert!(is_own(&read_heartbeat_file(&sidecar).unwrap()));
+        
+        // 持有者还在运行、心跳新鲜：不加 --really-force 一律拒绝，锁文件不动
+        let mut lock = StorageLock::new(dir, dir);
+        let err = lock.force_lock(false).unwrap_err().to_string();
+        assert!(err.contains("--really-force"), "{err}");
+        assert!(err.contains(&format!("PID {}", process::id())), "{err}");
+        assert!(!lock.is_locked());
+        assert!(holder.is_locked());
+        assert!(lock.try_lock().is_err());
+        
+        let taken = lock.force_lock(true).unwrap();
+        assert_eq!(taken.len(), 1);
+        lock.unlock().unwrap();
+    }
+    
+    #[test]
+    fn test_heartbeat_goes_stale_on_the_clock() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let clock = Arc::new(MockClock::default());
+        let beat = Heartbeat {
+            pid: 1,
+            hostname: "elsewhere.invalid".into(),
+            at: now_secs(&*clock),
+            lock: None,
+        };
+        let info = LockInfo {
+            pid: 1,
+            start_time: 0,
+            hostname: beat.hostname.clone(),
+            created_at: beat.at,
+            version: String::new(),
+            mount: None,
+            immutable: false,
+            build: None,
+        };
+        let sidecar = heartbeat_path(&dir.join(".rhss.lock"));
+        std::fs::write(&sidecar, serde_json::to_string(&beat).unwrap()).unwrap();
+        let mut lock = StorageLock::new(dir, dir).with_clock(clock.clone());
+        assert!(lock.try_lock().is_err());
+        assert_eq!(holder_alive(&info, Some(&beat), &*clock), Some(true));
+        
+        // 不用真的等：时钟走过 HEARTBEAT_STALE，另一台主机的心跳就过期了
+        clock.advance(HEARTBEAT_STALE + Duration::from_secs(1));
+        assert_eq!(holder_alive(&info, Some(&beat), &*clock), Some(false));
+        lock.try_lock().unwrap();
+        // 自己的心跳也按这个时钟写
+        assert_eq!(read_heartbeat_file(&sidecar).unwrap().at, now_secs(&*clock));
+        lock.unlock().unwrap();
+    }
 }
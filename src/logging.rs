@@ -0,0 +1,282 @@
+//! Logging bootstrap: human or JSON lines, to stderr or a rotating file.
+//!
+//! Rotation is logrotate-style and happens in-process: when the current
+//! file would exceed `max_bytes`, or the hour/day bucket rolls over,
+//! `rhss.log` becomes `rhss.log.1`, `.1` becomes `.2`, and so on; anything
+//! past `keep` is deleted. No external logrotate (or copytruncate races)
+//! needed.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{Args, ValueEnum};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::error::{FsError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line (journald / ELK friendly).
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Rotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl Rotation {
+    fn period_secs(self) -> Option<u64> {
+        match self {
+            Rotation::Never => None,
+            Rotation::Hourly => Some(3600),
+            Rotation::Daily => Some(86_400),
+        }
+    }
+}
+
+/// Global logging flags, accepted by every subcommand.
+#[derive(Args, Debug, Clone)]
+pub struct LogOptions {
+    /// Log line format.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Write logs to this file instead of stderr.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it reaches this many MiB (0 = no size limit).
+    #[arg(long, global = true, value_name = "MIB", default_value_t = 64)]
+    pub log_max_size: u64,
+
+    /// Also rotate the log file on this schedule.
+    #[arg(long, global = true, value_enum, default_value_t = Rotation::Daily)]
+    pub log_rotate: Rotation,
+
+    /// Rotated files to keep (`<file>.1` … `<file>.N`).
+    #[arg(long, global = true, value_name = "N", default_value_t = 7)]
+    pub log_keep: usize,
+
+    /// Also export spans and storage metrics to this OTLP/HTTP collector,
+    /// e.g. `http://localhost:4318` (see `crate::otlp`).
+    #[arg(long, global = true, value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+}
+
+type SetFilter = Box<dyn Fn(EnvFilter) -> std::result::Result<(), String> + Send + Sync>;
+
+/// Swaps the live filter; installed by `init`.
+static SET_FILTER: OnceLock<SetFilter> = OnceLock::new();
+
+/// Directives of the live filter, as last installed.
+static CURRENT: Mutex<String> = Mutex::new(String::new());
+
+/// Install the global subscriber. Level comes from `RUST_LOG` until
+/// `set_filter` replaces it.
+pub fn init(opts: &LogOptions) -> Result<()> {
+    let otlp = opts
+        .otlp_endpoint
+        .as_deref()
+        .map(crate::otlp::layer)
+        .transpose()?;
+    // Each format/writer combination is its own subscriber type, so the
+    // reload handle is boxed up per branch.
+    macro_rules! install {
+        ($builder:expr) => {{
+            let builder = $builder.with_filter_reloading();
+            let handle = builder.reload_handle();
+            let _ = SET_FILTER.set(Box::new(move |f| {
+                handle.reload(f).map_err(|e| e.to_string())
+            }));
+            builder.finish().with(otlp).try_init()
+        }};
+    }
+    // Closing a span logs its duration, so with e.g. `rhss=debug` a slow
+    // FUSE op shows how long it spent in each backend call.
+    let filter = EnvFilter::from_default_env();
+    *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = filter.to_string();
+    let builder = fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_span_events(FmtSpan::CLOSE);
+    let res = match (&opts.log_file, opts.log_format) {
+        (None, LogFormat::Text) => install!(builder.with_ansi(true)),
+        (None, LogFormat::Json) => install!(builder.json().with_ansi(false)),
+        (Some(path), format) => {
+            let max = (opts.log_max_size > 0).then(|| opts.log_max_size * 1024 * 1024);
+            let file = RotatingFile::open(path, max, opts.log_rotate, opts.log_keep)?;
+            let builder = builder.with_ansi(false).with_writer(Mutex::new(file));
+            match format {
+                LogFormat::Text => install!(builder),
+                LogFormat::Json => install!(builder.json()),
+            }
+        }
+    };
+    res.map_err(|e| FsError::Storage(format!("init logging: {e}")))
+}
+
+/// Replace the live log filter (`RUST_LOG` syntax). Takes effect at once,
+/// for every thread; `rhss log-level` calls this in a running daemon.
+pub fn set_filter(directives: &str) -> Result<()> {
+    let filter = EnvFilter::try_new(directives)
+        .map_err(|e| FsError::InvalidOperation(format!("log filter {directives:?}: {e}")))?;
+    let set = SET_FILTER
+        .get()
+        .ok_or_else(|| FsError::Storage("logging not initialised".into()))?;
+    let shown = filter.to_string();
+    set(filter).map_err(|e| FsError::Storage(format!("reload log filter: {e}")))?;
+    *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = shown;
+    Ok(())
+}
+
+/// The live filter's directives; empty before `init`.
+pub fn current_filter() -> String {
+    CURRENT.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Append-only log file that rotates itself by size and/or time.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: Option<u64>,
+    rotation: Rotation,
+    bucket: u64,
+    keep: usize,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl RotatingFile {
+    pub fn open(
+        path: &Path,
+        max_bytes: Option<u64>,
+        rotation: Rotation,
+        keep: usize,
+    ) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| FsError::from_io(e, parent.display()))?;
+        }
+        let file = Self::open_append(path).map_err(|e| FsError::from_io(e, path.display()))?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            written,
+            max_bytes,
+            rotation,
+            bucket: Self::bucket_at(rotation, now_secs()),
+            keep,
+        })
+    }
+
+    fn open_append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn bucket_at(rotation: Rotation, secs: u64) -> u64 {
+        rotation.period_secs().map(|p| secs / p).unwrap_or(0)
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut s = self.path.as_os_str().to_owned();
+        s.push(format!(".{n}"));
+        PathBuf::from(s)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.numbered(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = self.numbered(n);
+                if from.exists() {
+                    fs::rename(&from, self.numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.numbered(1))?;
+        }
+        self.file = Self::open_append(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+
+    fn maybe_rotate(&mut self, incoming: usize, now: u64) -> io::Result<()> {
+        let bucket = Self::bucket_at(self.rotation, now);
+        let over_size = self
+            .max_bytes
+            .map(|max| self.written > 0 && self.written + incoming as u64 > max)
+            .unwrap_or(false);
+        if over_size || bucket != self.bucket {
+            self.bucket = bucket;
+            if self.written > 0 {
+                self.rotate()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A failed rotation must not take logging down with it; keep
+        // appending to the current file.
+        let _ = self.maybe_rotate(buf.len(), now_secs());
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_by_size_and_keeps_n() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs/rhss.log");
+        let mut f = RotatingFile::open(&path, Some(10), Rotation::Never, 2).unwrap();
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            f.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(fs::read_to_string(f.numbered(1)).unwrap(), "cccccccc\n");
+        assert_eq!(fs::read_to_string(f.numbered(2)).unwrap(), "bbbbbbbb\n");
+        assert!(!f.numbered(3).exists());
+    }
+
+    #[test]
+    fn rotates_when_period_rolls_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rhss.log");
+        let mut f = RotatingFile::open(&path, None, Rotation::Hourly, 3).unwrap();
+        f.write_all(b"first\n").unwrap();
+        let next_hour = (f.bucket + 1) * 3600;
+        f.maybe_rotate(7, next_hour).unwrap();
+        f.file.write_all(b"second\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        assert_eq!(fs::read_to_string(f.numbered(1)).unwrap(), "first\n");
+    }
+}
@@ -5,18 +5,15 @@
 
 use clap::Parser;
 use tracing::error;
-use tracing_subscriber::{fmt, EnvFilter};
 
-use rhss::cli;
+use rhss::{cli, logging};
 
 fn main() {
-    fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .with_target(false)
-        .with_ansi(true)
-        .init();
-
     let parsed = cli::Cli::parse();
+    if let Err(e) = logging::init(&parsed.log) {
+        eprintln!("rhss: {e}");
+        std::process::exit(1);
+    }
     if let Err(e) = cli::run(parsed) {
         error!("{e}");
         std::process::exit(1);
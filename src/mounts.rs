@@ -0,0 +1,178 @@
+//! The live rhss mounts on this host, so tooling can find them without
+//! knowing their configs. `rhss mounts` lists them.
+//!
+//! Each `rhss mount` writes `<pid>.json` into `runtime_dir()` once the
+//! mount is up and removes it on the way out. A record left behind by a
+//! crash names a process that is gone; `Registry::list` skips and deletes
+//! those.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::error::{FsError, Result};
+
+/// One live mount.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MountRecord {
+    pub pid: u32,
+    pub mount: PathBuf,
+    /// Source name in the mount table (`[fuse] fsname`).
+    pub fsname: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtype: Option<String>,
+    /// Config file it was started with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<PathBuf>,
+    pub db: PathBuf,
+    /// Control socket; `None` for `--shared` views, which have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+    #[serde(default)]
+    pub read_only: bool,
+    /// Unix seconds.
+    pub started: u64,
+}
+
+impl MountRecord {
+    /// A record for this process, started now.
+    pub fn new(mount: PathBuf, fsname: String, db: PathBuf) -> Self {
+        Self {
+            pid: std::process::id(),
+            mount,
+            fsname,
+            subtype: None,
+            config: None,
+            db,
+            socket: None,
+            read_only: false,
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+}
+
+/// `/run/rhss/mounts` for root, else `$XDG_RUNTIME_DIR/rhss/mounts`, else
+/// `rhss-<uid>/mounts` under the temp dir.
+pub fn runtime_dir() -> PathBuf {
+    let uid = unsafe { libc::geteuid() };
+    if uid == 0 {
+        return PathBuf::from("/run/rhss/mounts");
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(d) if !d.is_empty() => PathBuf::from(d).join("rhss/mounts"),
+        _ => std::env::temp_dir().join(format!("rhss-{uid}/mounts")),
+    }
+}
+
+/// A directory of mount records.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    dir: PathBuf,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::at(runtime_dir())
+    }
+}
+
+impl Registry {
+    pub fn at(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Record `rec`; the record goes away when the returned guard drops.
+    pub fn register(&self, rec: &MountRecord) -> Result<Registration> {
+        let io_err = |e: std::io::Error, p: &Path| FsError::from_io(e, p.display());
+        std::fs::create_dir_all(&self.dir).map_err(|e| io_err(e, &self.dir))?;
+        let path = self.dir.join(format!("{}.json", rec.pid));
+        let tmp = self.dir.join(format!(".{}.json.tmp", rec.pid));
+        let text = serde_json::to_vec_pretty(rec)?;
+        std::fs::write(&tmp, text).map_err(|e| io_err(e, &tmp))?;
+        std::fs::rename(&tmp, &path).map_err(|e| io_err(e, &path))?;
+        Ok(Registration { path })
+    }
+
+    /// Every live mount, by mount point.
+    pub fn list(&self) -> Result<Vec<MountRecord>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(FsError::from_io(e, self.dir.display())),
+        };
+        let mut out = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let rec: MountRecord = match std::fs::read(&path)
+                .ok()
+                .and_then(|b| serde_json::from_slice(&b).ok())
+            {
+                Some(r) => r,
+                None => {
+                    warn!("mounts: unreadable record {}", path.display());
+                    continue;
+                }
+            };
+            if alive(rec.pid) {
+                out.push(rec);
+            } else {
+                debug!("mounts: dropping stale record {}", path.display());
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+        out.sort_by(|a, b| a.mount.cmp(&b.mount));
+        Ok(out)
+    }
+}
+
+/// EPERM: alive, just not ours to signal.
+fn alive(pid: u32) -> bool {
+    let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    rc == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Removes its record on drop.
+#[derive(Debug)]
+pub struct Registration {
+    path: PathBuf,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("mounts: remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_come_and_go_with_their_mount() {
+        let dir = tempfile::tempdir().unwrap();
+        let reg = Registry::at(dir.path());
+        let rec = MountRecord::new("/mnt/a".into(), "rhss-a".into(), "/var/a.db".into());
+        let guard = reg.register(&rec).unwrap();
+        assert_eq!(reg.list().unwrap(), vec![rec.clone()]);
+        drop(guard);
+        assert!(reg.list().unwrap().is_empty());
+
+        // A crashed mount's record is swept.
+        let stale = MountRecord {
+            pid: i32::MAX as u32,
+            ..rec
+        };
+        std::mem::forget(reg.register(&stale).unwrap());
+        assert!(reg.list().unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
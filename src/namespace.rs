@@ -0,0 +1,673 @@
+//! Path-based view of the hybrid namespace, for frontends that aren't the
+//! kernel (`crate::webdav`). It keeps the same books as the FUSE adapter:
+//! new files are placed by the tiering policy and indexed, directories
+//! exist on every backend, deletes go through the trash and byte quotas
+//! are charged.
+//!
+//! Every path is logical (`/docs/a.txt`). Paths with `..` are `EINVAL`;
+//! paths the filter hides are `ENOENT`.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use tracing::{debug, warn};
+
+use crate::backend::{sanitize_rel, Backend, BackendStats, FileMetadata};
+use crate::error::{FsError, Result};
+use crate::filter::PathFilter;
+use crate::index::{FileRow, FileState, Location, Mutability, PathIndex, TierId};
+use crate::policy::TieringPolicy;
+use crate::quota::Quotas;
+use crate::tenant::Tenants;
+use crate::tier::TierRouter;
+use crate::tierer::compress::compressed_path;
+use crate::tierer::{ensure_decompressed, migrate, OpenFileTracker};
+use crate::trash::Trash;
+
+/// Bytes moved per backend call when streaming an upload.
+const CHUNK: usize = 1 << 20;
+
+/// One entry of `Namespace::list`.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub meta: FileMetadata,
+}
+
+pub struct Namespace {
+    router: Arc<TierRouter>,
+    index: Arc<dyn PathIndex>,
+    policy: Arc<dyn TieringPolicy>,
+    open_tracker: Arc<OpenFileTracker>,
+    filter: PathFilter,
+    trash: Option<Arc<Trash>>,
+    quotas: Option<Arc<Quotas>>,
+    tenants: Option<Arc<Tenants>>,
+}
+
+impl Namespace {
+    pub fn new(
+        router: Arc<TierRouter>,
+        index: Arc<dyn PathIndex>,
+        policy: Arc<dyn TieringPolicy>,
+        open_tracker: Arc<OpenFileTracker>,
+    ) -> Self {
+        Self {
+            router,
+            index,
+            policy,
+            open_tracker,
+            filter: PathFilter::with_defaults(),
+            trash: None,
+            quotas: None,
+            tenants: None,
+        }
+    }
+
+    /// Paths that don't exist through this view, as `[fuse] ignore_lookup`.
+    pub fn with_filter(mut self, filter: PathFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Move deleted and overwritten files into the trash.
+    pub fn with_trash(mut self, trash: Option<Arc<Trash>>) -> Self {
+        self.trash = trash;
+        self
+    }
+
+    pub fn with_quotas(mut self, quotas: Option<Arc<Quotas>>) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Place new files under tenant directories by their tenant's rules.
+    pub fn with_tenants(mut self, tenants: Option<Arc<Tenants>>) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
+    /// Normalized absolute form of `logical`, or why it can't be used.
+    fn check(&self, logical: &Path) -> Result<PathBuf> {
+        let abs = Path::new("/").join(sanitize_rel(logical)?);
+        if self.filter.excludes("lookup", &abs) {
+            return Err(FsError::NotFound(abs.display().to_string()));
+        }
+        Ok(abs)
+    }
+
+    fn backend_of(&self, row: &FileRow) -> Result<Arc<dyn Backend>> {
+        self.router
+            .resolve_backend(row.location.tier, &row.location.backend_id)
+            .cloned()
+            .ok_or_else(|| FsError::Storage(format!("unknown backend {}", row.location.backend_id)))
+    }
+
+    fn quotas_for(&self, logical: &Path) -> Option<&Arc<Quotas>> {
+        self.quotas.as_ref().filter(|q| q.may_apply(logical))
+    }
+
+    pub fn stat(&self, logical: &Path) -> Result<FileMetadata> {
+        let logical = self.check(logical)?;
+        self.stat_checked(&logical)
+    }
+
+    fn stat_checked(&self, logical: &Path) -> Result<FileMetadata> {
+        if let Some(row) = self.index.get(logical)? {
+            let backend = self.backend_of(&row)?;
+            if !row.compressed {
+                return backend.metadata(&row.location.backend_path);
+            }
+            let mut meta = backend.metadata(&compressed_path(&row.location.backend_path))?;
+            meta.size = row.location.size;
+            return Ok(meta);
+        }
+        let rel = logical.strip_prefix("/").unwrap_or(logical);
+        self.router
+            .all_backends()
+            .find_map(|(_, b)| b.metadata(rel).ok().filter(|m| m.is_dir))
+            .ok_or_else(|| FsError::NotFound(logical.display().to_string()))
+    }
+
+    /// Entries of directory `dir` merged across backends, sorted by name.
+    pub fn list(&self, dir: &Path) -> Result<Vec<DirEntry>> {
+        let dir = self.check(dir)?;
+        if !self.stat_checked(&dir)?.is_dir {
+            return Err(FsError::NotDirectory(dir.display().to_string()));
+        }
+        let rel = dir.strip_prefix("/").unwrap_or(&dir);
+        let mut names = BTreeMap::new();
+        for (_, b) in self.router.all_backends() {
+            for name in b.list_dir(rel).unwrap_or_default() {
+                names.entry(name).or_insert(());
+            }
+        }
+        Ok(names
+            .into_keys()
+            .filter_map(|name| {
+                let path = dir.join(&name);
+                if self.filter.excludes("readdir", &path) || self.filter.excludes("lookup", &path) {
+                    return None;
+                }
+                let meta = self.stat_checked(&path).ok()?;
+                Some(DirEntry { name, meta })
+            })
+            .collect())
+    }
+
+    /// Backend and backend path holding file `logical`'s bytes, staged
+    /// out of compression if need be.
+    fn open(&self, logical: &Path) -> Result<(Arc<dyn Backend>, PathBuf)> {
+        let row = self
+            .index
+            .get(logical)?
+            .ok_or_else(|| FsError::NotFound(logical.display().to_string()))?;
+        let backend = self.backend_of(&row)?;
+        let path = if row.compressed {
+            ensure_decompressed(&backend, &row.location.backend_path, row.location.size)?
+        } else {
+            row.location.backend_path.clone()
+        };
+        Ok((backend, path))
+    }
+
+    /// Up to `len` bytes of file `logical` from `offset`; short at EOF.
+    pub fn read(&self, logical: &Path, offset: u64, len: u32) -> Result<Vec<u8>> {
+        let logical = self.check(logical)?;
+        let (backend, path) = self.open(&logical)?;
+        backend.read_at(&path, offset, len)
+    }
+
+    /// Write `data` into file `logical` at `offset`.
+    pub fn write(&self, logical: &Path, offset: u64, data: &[u8]) -> Result<u32> {
+        let logical = self.check(logical)?;
+        let (backend, path) = self.open(&logical)?;
+        let end = offset + data.len() as u64;
+        let reservation = match self.quotas_for(&logical) {
+            Some(q) => {
+                let meta = backend.metadata(&path)?;
+                Some((q, q.reserve(&logical, meta.uid, meta.size, end)?))
+            }
+            None => None,
+        };
+        let written = backend.write_at(&path, offset, data);
+        if let Some((q, r)) = reservation {
+            let end = written.as_ref().ok().map(|&n| offset + n as u64);
+            q.settle(&logical, r, end);
+        }
+        let n = written?;
+        self.sync_size(&logical, &backend, &path);
+        Ok(n)
+    }
+
+    /// Cut or extend file `logical` to `size` bytes.
+    pub fn truncate(&self, logical: &Path, size: u64) -> Result<()> {
+        let logical = self.check(logical)?;
+        let (backend, path) = self.open(&logical)?;
+        if let Some(q) = self.quotas_for(&logical) {
+            let meta = backend.metadata(&path)?;
+            q.resize(&logical, meta.uid, meta.size, size)?;
+            if let Err(e) = backend.truncate(&path, size) {
+                let _ = q.resize(&logical, meta.uid, size, meta.size);
+                return Err(e);
+            }
+        } else {
+            backend.truncate(&path, size)?;
+        }
+        self.index.set_size(&logical, size)
+    }
+
+    /// Flush file `logical` to stable storage.
+    pub fn fsync(&self, logical: &Path) -> Result<()> {
+        let logical = self.check(logical)?;
+        let (backend, path) = self.open(&logical)?;
+        backend.fsync(&path)
+    }
+
+    /// Change permission bits; a directory's on every backend that has it.
+    pub fn set_mode(&self, logical: &Path, mode: u32) -> Result<()> {
+        let logical = self.check(logical)?;
+        if self.index.get(&logical)?.is_some() {
+            let (backend, path) = self.open(&logical)?;
+            return backend.set_permissions(&path, mode & 0o7777);
+        }
+        self.each_dir_backend(&logical, |b, rel| b.set_permissions(rel, mode & 0o7777))
+    }
+
+    /// Change access and/or modification time.
+    pub fn set_times(
+        &self,
+        logical: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        let logical = self.check(logical)?;
+        if self.index.get(&logical)?.is_some() {
+            let (backend, path) = self.open(&logical)?;
+            return backend.set_times(&path, atime, mtime);
+        }
+        self.each_dir_backend(&logical, |b, rel| b.set_times(rel, atime, mtime))
+    }
+
+    /// Combined capacity of every backend.
+    pub fn statfs(&self) -> Result<BackendStats> {
+        let mut total = BackendStats {
+            total_bytes: 0,
+            free_bytes: 0,
+            used_bytes: 0,
+        };
+        for (_, b) in self.router.all_backends() {
+            let st = b.statvfs()?;
+            total.total_bytes += st.total_bytes;
+            total.free_bytes += st.free_bytes;
+            total.used_bytes += st.used_bytes;
+        }
+        Ok(total)
+    }
+
+    fn each_dir_backend(
+        &self,
+        logical: &Path,
+        f: impl Fn(&Arc<dyn Backend>, &Path) -> Result<()>,
+    ) -> Result<()> {
+        let rel = logical.strip_prefix("/").unwrap_or(logical);
+        let mut found = false;
+        for (_, b) in self.router.all_backends() {
+            if b.metadata(rel).is_ok_and(|m| m.is_dir) {
+                f(b, rel)?;
+                found = true;
+            }
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(FsError::NotFound(logical.display().to_string()))
+        }
+    }
+
+    fn sync_size(&self, logical: &Path, backend: &Arc<dyn Backend>, path: &Path) {
+        let res = backend
+            .metadata(path)
+            .and_then(|m| self.index.set_size(logical, m.size));
+        if let Err(e) = res {
+            debug!("index size {}: {:?}", logical.display(), e);
+        }
+    }
+
+    /// New empty file; `AlreadyExists` if anything is at `logical`.
+    pub fn create_file(&self, logical: &Path) -> Result<()> {
+        let logical = self.check(logical)?;
+        self.require_parent_dir(&logical)?;
+        if self.stat_checked(&logical).is_ok() {
+            return Err(FsError::AlreadyExists(logical.display().to_string()));
+        }
+        self.create(&logical).map(|_| ())
+    }
+
+    /// Replace file `logical` with the contents of `body`, creating it if
+    /// needed. The old contents go to the trash first when it's on.
+    /// Returns whether the file is new.
+    pub fn put(&self, logical: &Path, body: &mut dyn Read) -> Result<bool> {
+        let logical = self.check(logical)?;
+        self.require_parent_dir(&logical)?;
+        let existed = match self.index.get(&logical)? {
+            Some(_) => {
+                self.remove_file(&logical)?;
+                true
+            }
+            None if self.stat_checked(&logical).is_ok() => {
+                return Err(FsError::IsDirectory(logical.display().to_string()));
+            }
+            None => false,
+        };
+        let (backend, rel, meta) = self.create(&logical)?;
+        self.open_tracker.register(&logical);
+        let res = self.fill(&logical, &backend, &rel, meta.uid, body);
+        self.open_tracker.release(&logical);
+        let size = res?;
+        self.index.set_size(&logical, size)?;
+        Ok(!existed)
+    }
+
+    fn fill(
+        &self,
+        logical: &Path,
+        backend: &Arc<dyn Backend>,
+        rel: &Path,
+        uid: u32,
+        body: &mut dyn Read,
+    ) -> Result<u64> {
+        let mut buf = vec![0u8; CHUNK];
+        let mut off = 0u64;
+        loop {
+            let n = body.read(&mut buf).map_err(FsError::Io)?;
+            if n == 0 {
+                return Ok(off);
+            }
+            let end = off + n as u64;
+            let reservation = match self.quotas_for(logical) {
+                Some(q) => Some((q, q.reserve(logical, uid, off, end)?)),
+                None => None,
+            };
+            let written = backend.write_at(rel, off, &buf[..n]);
+            if let Some((q, r)) = reservation {
+                q.settle(logical, r, written.as_ref().ok().map(|_| end));
+            }
+            written?;
+            off = end;
+        }
+    }
+
+    fn require_parent_dir(&self, logical: &Path) -> Result<()> {
+        let parent = logical.parent().unwrap_or(Path::new("/"));
+        if self.stat_checked(parent)?.is_dir {
+            Ok(())
+        } else {
+            Err(FsError::NotDirectory(parent.display().to_string()))
+        }
+    }
+
+    /// Empty file on the tier the policy (or the path's tenant) picks,
+    /// indexed.
+    fn create(&self, logical: &Path) -> Result<(Arc<dyn Backend>, PathBuf, FileMetadata)> {
+        let mut tier = self.policy.tier_for_create(self.router.fast.usage_ratio());
+        if let Some(t) = &self.tenants {
+            tier = t.tier_for_create(logical, tier, self.index.as_ref());
+        }
+        let backend = self
+            .router
+            .tier(tier)
+            .ok_or_else(|| FsError::Storage(format!("no {tier:?} tier")))?
+            .pick()?
+            .clone();
+        let rel = logical.strip_prefix("/").unwrap_or(logical).to_path_buf();
+        backend.create_file(&rel)?;
+        let meta = backend.metadata(&rel)?;
+        if let Some(q) = self.quotas_for(logical) {
+            if let Err(e) = q.check_room(logical, meta.uid) {
+                let _ = backend.remove(&rel);
+                return Err(e);
+            }
+        }
+        let row = FileRow {
+            logical_path: logical.to_path_buf(),
+            location: Location {
+                tier,
+                backend_id: backend.id().to_string(),
+                backend_path: rel.clone(),
+                size: 0,
+            },
+            replicas: Vec::new(),
+            last_access: SystemTime::now(),
+            hit_count: 0,
+            popularity: self.policy.initial_popularity(),
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            mutability: Mutability::Unknown,
+            compressed: false,
+            content_hash: None,
+        };
+        if let Err(e) = self.index.insert(row) {
+            let _ = backend.remove(&rel);
+            return Err(e);
+        }
+        Ok((backend, rel, meta))
+    }
+
+    /// New directory on every backend.
+    pub fn mkdir(&self, logical: &Path) -> Result<()> {
+        let logical = self.check(logical)?;
+        self.require_parent_dir(&logical)?;
+        if self.stat_checked(&logical).is_ok() {
+            return Err(FsError::AlreadyExists(logical.display().to_string()));
+        }
+        let rel = logical.strip_prefix("/").unwrap_or(&logical);
+        let mut last_err = None;
+        let mut made = false;
+        for (_, b) in self.router.all_backends() {
+            match b.create_dir(rel) {
+                Ok(()) => made = true,
+                Err(e) => {
+                    warn!("mkdir on {}: {:?}", b.id(), e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        match last_err {
+            Some(e) if !made => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Delete a file, or an empty directory.
+    pub fn remove(&self, logical: &Path) -> Result<()> {
+        let logical = self.check(logical)?;
+        if self.index.get(&logical)?.is_some() {
+            return self.remove_file(&logical);
+        }
+        let rel = logical.strip_prefix("/").unwrap_or(&logical);
+        let mut last_err = None;
+        let mut removed = false;
+        for (_, b) in self.router.all_backends() {
+            match b.remove(rel) {
+                Ok(()) => removed = true,
+                Err(FsError::NotFound(_)) => {}
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e),
+            None if removed => Ok(()),
+            None => Err(FsError::NotFound(logical.display().to_string())),
+        }
+    }
+
+    fn remove_file(&self, logical: &Path) -> Result<()> {
+        let row = self
+            .index
+            .get(logical)?
+            .ok_or_else(|| FsError::NotFound(logical.display().to_string()))?;
+        let backend = self.backend_of(&row)?;
+        let on_disk = if row.compressed {
+            compressed_path(&row.location.backend_path)
+        } else {
+            row.location.backend_path.clone()
+        };
+        let charged = self.quotas_for(logical).and_then(|q| {
+            let meta = backend.metadata(&on_disk).ok()?;
+            let size = if row.compressed {
+                row.location.size
+            } else {
+                meta.size
+            };
+            Some((q, meta.uid, size))
+        });
+        // D25: a deduped blob only goes once its last reference does.
+        let last_ref = match &row.content_hash {
+            Some(hash) => self.index.unref_blob(hash).unwrap_or_else(|e| {
+                warn!("unref_blob {}: {:?}", logical.display(), e);
+                true
+            }),
+            None => true,
+        };
+        if last_ref {
+            match &self.trash {
+                Some(trash) => trash.stash(&backend, &row, &on_disk).map(|_| ())?,
+                None => backend.remove(&on_disk)?,
+            }
+        }
+        self.index.remove(logical)?;
+        if let Some((q, uid, size)) = charged {
+            q.release(logical, uid, size);
+        }
+        Ok(())
+    }
+
+    /// Tier file `logical` lives on; `None` for unindexed paths such as
+    /// directories.
+    pub fn tier_of(&self, logical: &Path) -> Result<Option<TierId>> {
+        let logical = self.check(logical)?;
+        Ok(self.index.get(&logical)?.map(|row| row.location.tier))
+    }
+
+    /// Move file `logical` to tier `to` now, as `rhss migrate` does.
+    /// `Ok(false)` when it's already there, open or pinned.
+    pub fn migrate(&self, logical: &Path, to: TierId) -> Result<bool> {
+        let logical = self.check(logical)?;
+        let Some(row) = self.index.get(&logical)? else {
+            return Err(FsError::NotFound(logical.display().to_string()));
+        };
+        if row.location.tier == to {
+            return Ok(false);
+        }
+        migrate(&self.router, &self.index, &self.open_tracker, &logical, to)
+    }
+
+    /// Rename a file within its backend, or a directory on every backend
+    /// that has it.
+    pub fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let from = self.check(from)?;
+        let to = self.check(to)?;
+        self.require_parent_dir(&to)?;
+        let to_rel = to.strip_prefix("/").unwrap_or(&to).to_path_buf();
+        let Some(row) = self.index.get(&from)? else {
+            let from_rel = from.strip_prefix("/").unwrap_or(&from);
+            let mut last_err = None;
+            let mut moved = false;
+            for (_, b) in self.router.all_backends() {
+                match b.rename(from_rel, &to_rel) {
+                    Ok(()) => moved = true,
+                    Err(e) => last_err = Some(e),
+                }
+            }
+            if !moved {
+                return Err(
+                    last_err.unwrap_or_else(|| FsError::NotFound(from.display().to_string()))
+                );
+            }
+            if let Some(q) = &self.quotas {
+                if q.affects_rename(&from, &to) {
+                    q.reseed(&self.router, &self.index);
+                }
+            }
+            return Ok(());
+        };
+        let backend = self.backend_of(&row)?;
+        let moved = match self.quotas.as_ref() {
+            Some(q) if q.may_apply(&from) || q.may_apply(&to) => {
+                let m = backend.metadata(&row.location.backend_path)?;
+                q.transfer(&from, &to, m.uid, m.size)?;
+                Some((q, m.uid, m.size))
+            }
+            _ => None,
+        };
+        if let Err(e) = backend.rename(&row.location.backend_path, &to_rel) {
+            if let Some((q, uid, size)) = moved {
+                let _ = q.transfer(&to, &from, uid, size);
+            }
+            return Err(e);
+        }
+        self.index.rename(&from, &to)?;
+        self.index.swap_location(
+            &to,
+            Location {
+                backend_path: to_rel,
+                ..row.location
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use crate::index::{SqlitePathIndex, TierId};
+    use crate::policy::PopularityPolicy;
+    use crate::tier::{MostFreePlacement, Tier};
+
+    fn setup() -> (tempfile::TempDir, Namespace) {
+        let dir = tempfile::tempdir().unwrap();
+        let tier = |id, name: &str| {
+            let root = dir.path().join(name);
+            std::fs::create_dir_all(&root).unwrap();
+            let b: Arc<dyn Backend> = Arc::new(PosixBackend::new(name, root).unwrap());
+            Tier::new(id, vec![b], Box::new(MostFreePlacement)).unwrap()
+        };
+        let router = Arc::new(TierRouter::new(
+            tier(TierId::Fast, "ssd"),
+            tier(TierId::Slow, "hdd"),
+        ));
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(dir.path().join("idx.db")).unwrap();
+        let ns = Namespace::new(
+            router,
+            index,
+            Arc::new(PopularityPolicy::default()),
+            Arc::new(OpenFileTracker::new()),
+        );
+        (dir, ns)
+    }
+
+    #[test]
+    fn put_read_list_rename_remove() {
+        let (_dir, ns) = setup();
+        ns.mkdir(Path::new("/docs")).unwrap();
+        assert!(ns
+            .put(Path::new("/docs/a.txt"), &mut &b"hello"[..])
+            .unwrap());
+        assert!(!ns
+            .put(Path::new("/docs/a.txt"), &mut &b"hello world"[..])
+            .unwrap());
+        assert_eq!(ns.read(Path::new("/docs/a.txt"), 6, 100).unwrap(), b"world");
+        assert_eq!(ns.stat(Path::new("/docs/a.txt")).unwrap().size, 11);
+
+        let names: Vec<_> = ns
+            .list(Path::new("/docs"))
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["a.txt"]);
+
+        ns.rename(Path::new("/docs/a.txt"), Path::new("/b.txt"))
+            .unwrap();
+        assert_eq!(ns.read(Path::new("/b.txt"), 0, 5).unwrap(), b"hello");
+        assert!(ns.stat(Path::new("/docs/a.txt")).is_err());
+
+        ns.remove(Path::new("/b.txt")).unwrap();
+        ns.remove(Path::new("/docs")).unwrap();
+        assert!(ns.list(Path::new("/")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_escapes_and_missing_parents() {
+        let (_dir, ns) = setup();
+        let err = ns.put(Path::new("/../x"), &mut &b""[..]).unwrap_err();
+        assert_eq!(err.to_errno(), libc::EINVAL);
+        let err = ns.put(Path::new("/no/such/x"), &mut &b""[..]).unwrap_err();
+        assert_eq!(err.to_errno(), libc::ENOENT);
+        assert_eq!(
+            ns.mkdir(Path::new("/")).unwrap_err().to_errno(),
+            libc::EEXIST
+        );
+    }
+
+    #[test]
+    fn write_in_place_and_truncate() {
+        let (_dir, ns) = setup();
+        let f = Path::new("/f.bin");
+        ns.create_file(f).unwrap();
+        assert_eq!(ns.create_file(f).unwrap_err().to_errno(), libc::EEXIST);
+        assert_eq!(ns.write(f, 0, b"hello").unwrap(), 5);
+        assert_eq!(ns.write(f, 3, b"p!").unwrap(), 2);
+        assert_eq!(ns.read(f, 0, 100).unwrap(), b"help!");
+        ns.truncate(f, 3).unwrap();
+        assert_eq!(ns.stat(f).unwrap().size, 3);
+        ns.set_mode(f, 0o600).unwrap();
+        assert_eq!(ns.stat(f).unwrap().mode & 0o777, 0o600);
+    }
+}
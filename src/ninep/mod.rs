@@ -0,0 +1,812 @@
+//! 9P2000.L frontend (`rhss serve-9p`), for VM and container guests that
+//! mount shared storage with the kernel's v9fs client:
+//!
+//! ```text
+//! mount -t 9p -o trans=tcp,port=5640,version=9p2000.L host /mnt
+//! mount -t 9p -o trans=unix,version=9p2000.L /run/rhss.9p /mnt
+//! ```
+//!
+//! One request at a time per connection. Everything acts as the daemon's
+//! user; there is no authentication, so keep TCP listeners on a private
+//! address. Symlinks, device nodes, hard links and xattrs aren't supported.
+
+mod wire;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, error, info};
+
+use crate::backend::FileMetadata;
+use crate::error::{FsError, Result};
+use crate::namespace::{DirEntry, Namespace};
+
+use wire::{Dec, Enc, MIN_MSIZE};
+
+/// Largest `msize` offered: 1 MiB of payload plus headers.
+const MAX_MSIZE: u32 = (1 << 20) + 4096;
+
+/// Header bytes in front of `Rread`/`Rreaddir` data.
+const IO_HEADER: u32 = 24;
+
+const NOFID: u32 = !0;
+
+const QT_DIR: u8 = 0x80;
+const QT_FILE: u8 = 0;
+
+const GETATTR_BASIC: u64 = 0x7ff;
+
+const SETATTR_MODE: u32 = 0x1;
+const SETATTR_UID: u32 = 0x2;
+const SETATTR_GID: u32 = 0x4;
+const SETATTR_SIZE: u32 = 0x8;
+const SETATTR_ATIME: u32 = 0x10;
+const SETATTR_MTIME: u32 = 0x20;
+const SETATTR_ATIME_SET: u32 = 0x80;
+const SETATTR_MTIME_SET: u32 = 0x100;
+
+/// `statfs` magic the Linux client expects from a 9P server.
+const V9FS_MAGIC: u32 = 0x0102_1997;
+
+// Message types (T = request; the reply is T + 1).
+const TLERROR: u8 = 6;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TRENAME: u8 = 20;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TXATTRWALK: u8 = 30;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TLOCK: u8 = 52;
+const TGETLOCK: u8 = 54;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+
+/// Where `serve-9p` listens: `host:port`, or a Unix socket path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.starts_with('/') || s.starts_with('.') {
+            return Ok(Endpoint::Unix(PathBuf::from(s)));
+        }
+        s.parse()
+            .map(Endpoint::Tcp)
+            .map_err(|_| format!("`{s}` is neither host:port nor a socket path"))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "tcp:{addr}"),
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Owns the listener + the accept thread. Drop stops accepting and removes
+/// a Unix socket.
+pub struct NinePServer {
+    endpoint: Endpoint,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+struct Shared {
+    ns: Arc<Namespace>,
+    read_only: bool,
+}
+
+impl NinePServer {
+    pub fn start(endpoint: Endpoint, ns: Arc<Namespace>, read_only: bool) -> Result<Self> {
+        let (listener, endpoint) = match endpoint {
+            Endpoint::Tcp(addr) => {
+                let l = TcpListener::bind(addr).map_err(FsError::Io)?;
+                l.set_nonblocking(true).map_err(FsError::Io)?;
+                let addr = l.local_addr().map_err(FsError::Io)?;
+                (Listener::Tcp(l), Endpoint::Tcp(addr))
+            }
+            Endpoint::Unix(path) => {
+                // We hold the storage lock, so a socket left here is stale.
+                let _ = std::fs::remove_file(&path);
+                let l = UnixListener::bind(&path).map_err(FsError::Io)?;
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+                    .map_err(FsError::Io)?;
+                l.set_nonblocking(true).map_err(FsError::Io)?;
+                (Listener::Unix(l), Endpoint::Unix(path))
+            }
+        };
+        info!("9p listening on {endpoint}");
+
+        let shared = Arc::new(Shared { ns, read_only });
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_for_thread = Arc::clone(&shutdown);
+        let handle = std::thread::Builder::new()
+            .name("rhss-9p".into())
+            .spawn(move || accept_loop(listener, shared, shutdown_for_thread))
+            .expect("spawn 9p thread");
+
+        Ok(Self {
+            endpoint,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    /// Where the server ended up listening (useful with port 0).
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+}
+
+impl Drop for NinePServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+        if let Endpoint::Unix(path) = &self.endpoint {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn accept_loop(listener: Listener, shared: Arc<Shared>, shutdown: Arc<AtomicBool>) {
+    while !shutdown.load(SeqCst) {
+        let accepted: io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> = match &listener {
+            Listener::Tcp(l) => l.accept().and_then(|(s, _)| {
+                s.set_nonblocking(false)?;
+                let _ = s.set_nodelay(true);
+                Ok((Box::new(s.try_clone()?) as _, Box::new(s) as _))
+            }),
+            Listener::Unix(l) => l.accept().and_then(|(s, _)| {
+                s.set_nonblocking(false)?;
+                Ok((Box::new(s.try_clone()?) as _, Box::new(s) as _))
+            }),
+        };
+        match accepted {
+            Ok((r, w)) => {
+                let shared = Arc::clone(&shared);
+                let _ = std::thread::Builder::new()
+                    .name("rhss-9p-client".into())
+                    .spawn(move || {
+                        let mut session = Session {
+                            shared: &shared,
+                            msize: MAX_MSIZE,
+                            fids: HashMap::new(),
+                        };
+                        if let Err(e) = session.serve(r, w) {
+                            debug!("9p client: {e}");
+                        }
+                    });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => {
+                error!("9p accept failed: {e}");
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        }
+    }
+    debug!("9p accept loop exit");
+}
+
+fn errno(e: libc::c_int) -> FsError {
+    FsError::Io(io::Error::from_raw_os_error(e))
+}
+
+/// Stable 64-bit id for a path, used as the qid path.
+fn qid_path(logical: &Path) -> u64 {
+    let mut h = DefaultHasher::new();
+    logical.hash(&mut h);
+    h.finish()
+}
+
+fn put_qid(e: &mut Enc, logical: &Path, meta: &FileMetadata) {
+    let ty = if meta.is_dir { QT_DIR } else { QT_FILE };
+    e.u8(ty).u32(0).u64(qid_path(logical));
+}
+
+fn split_time(t: SystemTime) -> (u64, u64) {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| (d.as_secs(), d.subsec_nanos() as u64))
+        .unwrap_or((0, 0))
+}
+
+/// `sec`/`nsec` from a `Tsetattr`, or now when the client didn't send one.
+fn set_time(explicit: bool, sec: u64, nsec: u64) -> SystemTime {
+    if explicit {
+        UNIX_EPOCH + Duration::new(sec, nsec as u32)
+    } else {
+        SystemTime::now()
+    }
+}
+
+/// One path component from the wire.
+fn name(bytes: &[u8]) -> Result<&OsStr> {
+    if matches!(bytes, b"" | b"." | b"..") || bytes.iter().any(|&b| b == b'/' || b == 0) {
+        return Err(FsError::InvalidOperation(format!(
+            "bad name {:?}",
+            String::from_utf8_lossy(bytes)
+        )));
+    }
+    Ok(OsStr::from_bytes(bytes))
+}
+
+struct Fid {
+    path: PathBuf,
+    /// Listing snapshot for `Treaddir`, taken when it starts at offset 0.
+    listing: Vec<DirEntry>,
+}
+
+impl Fid {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            listing: Vec::new(),
+        }
+    }
+}
+
+struct Session<'a> {
+    shared: &'a Shared,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Session<'_> {
+    fn serve(&mut self, r: Box<dyn Read + Send>, w: Box<dyn Write + Send>) -> io::Result<()> {
+        let mut r = BufReader::new(r);
+        let mut w = BufWriter::new(w);
+        while let Some((ty, tag, body)) = wire::read_msg(&mut r, self.msize)? {
+            let reply = match self.handle(ty, tag, &body) {
+                Ok(reply) => reply,
+                Err(e) => {
+                    debug!("9p T{ty}: {e}");
+                    let mut reply = Enc::new(TLERROR + 1, tag);
+                    reply.u32(e.to_errno() as u32);
+                    reply
+                }
+            };
+            reply.finish(&mut w)?;
+            w.flush()?;
+        }
+        Ok(())
+    }
+
+    fn ns(&self) -> &Namespace {
+        &self.shared.ns
+    }
+
+    fn path(&self, fid: u32) -> Result<PathBuf> {
+        self.fids
+            .get(&fid)
+            .map(|f| f.path.clone())
+            .ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn writable(&self) -> Result<()> {
+        if self.shared.read_only {
+            Err(errno(libc::EROFS))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn iounit(&self) -> u32 {
+        self.msize - IO_HEADER
+    }
+
+    fn handle(&mut self, ty: u8, tag: u16, body: &[u8]) -> Result<Enc> {
+        let mut d = Dec::new(body);
+        let mut e = Enc::new(ty.wrapping_add(1), tag);
+        match ty {
+            TVERSION => {
+                let msize = d.u32()?;
+                let version = d.str()?;
+                if msize < MIN_MSIZE {
+                    return Err(FsError::InvalidOperation(format!(
+                        "msize {msize} too small"
+                    )));
+                }
+                self.msize = msize.min(MAX_MSIZE);
+                self.fids.clear();
+                let version: &[u8] = if version.starts_with(b"9P2000.L") {
+                    b"9P2000.L"
+                } else {
+                    b"unknown"
+                };
+                e.u32(self.msize).str(version);
+            }
+            TATTACH => {
+                let fid = d.u32()?;
+                let _afid = d.u32()?;
+                let root = PathBuf::from("/");
+                let meta = self.ns().stat(&root)?;
+                put_qid(&mut e, &root, &meta);
+                self.fids.insert(fid, Fid::new(root));
+            }
+            TFLUSH => {
+                // Requests are answered in order, so whatever `oldtag`
+                // named has already been replied to.
+            }
+            TWALK => self.walk(&mut d, &mut e)?,
+            TCLUNK => {
+                let fid = d.u32()?;
+                self.fids.remove(&fid).ok_or_else(|| errno(libc::EBADF))?;
+            }
+            TREMOVE => {
+                let fid = d.u32()?;
+                let path = self.path(fid)?;
+                // The fid is gone whether or not the remove works.
+                self.fids.remove(&fid);
+                self.writable()?;
+                self.ns().remove(&path)?;
+            }
+            TGETATTR => {
+                let path = self.path(d.u32()?)?;
+                let meta = self.ns().stat(&path)?;
+                self.getattr(&mut e, &path, &meta);
+            }
+            TSETATTR => self.setattr(&mut d)?,
+            TSTATFS => {
+                self.path(d.u32()?)?;
+                let st = self.ns().statfs()?;
+                let bsize = 4096u64;
+                e.u32(V9FS_MAGIC)
+                    .u32(bsize as u32)
+                    .u64(st.total_bytes / bsize)
+                    .u64(st.free_bytes / bsize)
+                    .u64(st.free_bytes / bsize)
+                    .u64(0)
+                    .u64(0)
+                    .u64(0)
+                    .u32(255);
+            }
+            TLOPEN => {
+                let path = self.path(d.u32()?)?;
+                let flags = d.u32()? as i32;
+                let meta = self.ns().stat(&path)?;
+                let writes =
+                    flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0;
+                if writes {
+                    self.writable()?;
+                }
+                if flags & libc::O_TRUNC != 0 && !meta.is_dir {
+                    self.ns().truncate(&path, 0)?;
+                }
+                put_qid(&mut e, &path, &meta);
+                e.u32(self.iounit());
+            }
+            TLCREATE => {
+                let fid = d.u32()?;
+                let dir = self.path(fid)?;
+                let path = dir.join(name(d.str()?)?);
+                let _flags = d.u32()?;
+                let mode = d.u32()?;
+                self.writable()?;
+                self.ns().create_file(&path)?;
+                self.ns().set_mode(&path, mode)?;
+                let meta = self.ns().stat(&path)?;
+                put_qid(&mut e, &path, &meta);
+                e.u32(self.iounit());
+                // The fid now stands for the new, open file.
+                self.fids.insert(fid, Fid::new(path));
+            }
+            TREAD => {
+                let path = self.path(d.u32()?)?;
+                let offset = d.u64()?;
+                let count = d.u32()?.min(self.iounit());
+                let data = self.ns().read(&path, offset, count)?;
+                e.u32(data.len() as u32).bytes(&data);
+            }
+            TWRITE => {
+                let path = self.path(d.u32()?)?;
+                let offset = d.u64()?;
+                let count = d.u32()? as usize;
+                let data = d.bytes(count)?;
+                self.writable()?;
+                e.u32(self.ns().write(&path, offset, data)?);
+            }
+            TREADDIR => self.readdir(&mut d, &mut e)?,
+            TFSYNC => {
+                let path = self.path(d.u32()?)?;
+                if !self.ns().stat(&path)?.is_dir {
+                    self.ns().fsync(&path)?;
+                }
+            }
+            TMKDIR => {
+                let dir = self.path(d.u32()?)?;
+                let path = dir.join(name(d.str()?)?);
+                let mode = d.u32()?;
+                self.writable()?;
+                self.ns().mkdir(&path)?;
+                self.ns().set_mode(&path, mode)?;
+                let meta = self.ns().stat(&path)?;
+                put_qid(&mut e, &path, &meta);
+            }
+            TRENAME => {
+                let from = self.path(d.u32()?)?;
+                let dir = self.path(d.u32()?)?;
+                let to = dir.join(name(d.str()?)?);
+                self.rename(&from, &to)?;
+                for f in self.fids.values_mut() {
+                    if f.path == from {
+                        f.path = to.clone();
+                    }
+                }
+            }
+            TRENAMEAT => {
+                let from = self.path(d.u32()?)?.join(name(d.str()?)?);
+                let to = self.path(d.u32()?)?.join(name(d.str()?)?);
+                self.rename(&from, &to)?;
+            }
+            TUNLINKAT => {
+                let path = self.path(d.u32()?)?.join(name(d.str()?)?);
+                let flags = d.u32()? as i32;
+                self.writable()?;
+                let is_dir = self.ns().stat(&path)?.is_dir;
+                if flags & libc::AT_REMOVEDIR != 0 && !is_dir {
+                    return Err(errno(libc::ENOTDIR));
+                }
+                if flags & libc::AT_REMOVEDIR == 0 && is_dir {
+                    return Err(errno(libc::EISDIR));
+                }
+                self.ns().remove(&path)?;
+            }
+            TLOCK => {
+                // Advisory and process-local on the guest anyway: grant.
+                self.path(d.u32()?)?;
+                e.u8(0);
+            }
+            TGETLOCK => {
+                self.path(d.u32()?)?;
+                let _ty = d.u8()?;
+                let (start, len, proc_id) = (d.u64()?, d.u64()?, d.u32()?);
+                let client = d.str()?;
+                // Nobody holds a conflicting lock.
+                e.u8(libc::F_UNLCK as u8)
+                    .u64(start)
+                    .u64(len)
+                    .u32(proc_id)
+                    .str(client);
+            }
+            TXATTRWALK => return Err(errno(libc::EOPNOTSUPP)),
+            _ => return Err(errno(libc::EOPNOTSUPP)),
+        }
+        Ok(e)
+    }
+
+    fn walk(&mut self, d: &mut Dec<'_>, e: &mut Enc) -> Result<()> {
+        let fid = d.u32()?;
+        let newfid = d.u32()?;
+        let n = d.u16()?;
+        let mut path = self.path(fid)?;
+        if newfid != fid && newfid != NOFID && self.fids.contains_key(&newfid) {
+            return Err(errno(libc::EBADF));
+        }
+        let at = e.len();
+        e.u16(0);
+        let mut walked = 0u16;
+        for i in 0..n {
+            let component = d.str()?;
+            let next = if component == b".." {
+                path.parent().unwrap_or(Path::new("/")).to_path_buf()
+            } else {
+                path.join(name(component)?)
+            };
+            let meta = match self.ns().stat(&next) {
+                Ok(m) => m,
+                // Only the first element's failure is an error; after
+                // that the client gets the qids that did walk.
+                Err(err) if i == 0 => return Err(err),
+                Err(_) => break,
+            };
+            put_qid(e, &next, &meta);
+            path = next;
+            walked += 1;
+        }
+        e.patch_u16(at, walked);
+        if walked == n {
+            self.fids.insert(newfid, Fid::new(path));
+        }
+        Ok(())
+    }
+
+    fn getattr(&self, e: &mut Enc, path: &Path, meta: &FileMetadata) {
+        let (a_s, a_n) = split_time(meta.atime);
+        let (m_s, m_n) = split_time(meta.mtime);
+        let (c_s, c_n) = split_time(meta.ctime);
+        e.u64(GETATTR_BASIC);
+        put_qid(e, path, meta);
+        e.u32(meta.mode)
+            .u32(meta.uid)
+            .u32(meta.gid)
+            .u64(if meta.is_dir { 2 } else { 1 })
+            .u64(0)
+            .u64(meta.size)
+            .u64(4096)
+            .u64(meta.size.div_ceil(512))
+            .u64(a_s)
+            .u64(a_n)
+            .u64(m_s)
+            .u64(m_n)
+            .u64(c_s)
+            .u64(c_n)
+            .u64(0)
+            .u64(0)
+            .u64(0)
+            .u64(0);
+    }
+
+    fn setattr(&mut self, d: &mut Dec<'_>) -> Result<()> {
+        let path = self.path(d.u32()?)?;
+        let valid = d.u32()?;
+        let mode = d.u32()?;
+        let (_uid, _gid) = (d.u32()?, d.u32()?);
+        let size = d.u64()?;
+        let (a_s, a_n, m_s, m_n) = (d.u64()?, d.u64()?, d.u64()?, d.u64()?);
+        self.writable()?;
+        if valid & (SETATTR_UID | SETATTR_GID) != 0 {
+            // Everything belongs to the daemon's user.
+            return Err(errno(libc::EPERM));
+        }
+        if valid & SETATTR_SIZE != 0 {
+            self.ns().truncate(&path, size)?;
+        }
+        if valid & SETATTR_MODE != 0 {
+            self.ns().set_mode(&path, mode)?;
+        }
+        let atime = (valid & SETATTR_ATIME != 0)
+            .then(|| set_time(valid & SETATTR_ATIME_SET != 0, a_s, a_n));
+        let mtime = (valid & SETATTR_MTIME != 0)
+            .then(|| set_time(valid & SETATTR_MTIME_SET != 0, m_s, m_n));
+        if atime.is_some() || mtime.is_some() {
+            self.ns().set_times(&path, atime, mtime)?;
+        }
+        Ok(())
+    }
+
+    /// Entries after `offset`, where offset `n` means "after the n-th
+    /// entry" and entries 0 and 1 are `.` and `..`.
+    fn readdir(&mut self, d: &mut Dec<'_>, e: &mut Enc) -> Result<()> {
+        let fid = d.u32()?;
+        let offset = d.u64()?;
+        let count = d.u32()?.min(self.iounit()) as usize;
+        let path = self.path(fid)?;
+        if offset == 0 {
+            let listing = self.ns().list(&path)?;
+            self.fids.get_mut(&fid).expect("fid checked").listing = listing;
+        }
+        let dir_meta = self.ns().stat(&path)?;
+        let f = &self.fids[&fid];
+        let at = e.len();
+        e.u32(0);
+        let start = e.len();
+        let total = f.listing.len() as u64 + 2;
+        for i in offset..total {
+            let (entry_name, entry_path, meta): (&[u8], PathBuf, &FileMetadata) = match i {
+                0 => (b".", path.clone(), &dir_meta),
+                1 => (
+                    b"..",
+                    path.parent().unwrap_or(Path::new("/")).to_path_buf(),
+                    &dir_meta,
+                ),
+                _ => {
+                    let entry = &f.listing[i as usize - 2];
+                    (entry.name.as_bytes(), path.join(&entry.name), &entry.meta)
+                }
+            };
+            // qid[13] offset[8] type[1] name[s]
+            if e.len() - start + 24 + entry_name.len() > count {
+                break;
+            }
+            put_qid(e, &entry_path, meta);
+            let dtype = if meta.is_dir {
+                libc::DT_DIR
+            } else {
+                libc::DT_REG
+            };
+            e.u64(i + 1).u8(dtype).str(entry_name);
+        }
+        let written = (e.len() - start) as u32;
+        e.patch_u32(at, written);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.writable()?;
+        // rename(2) replaces a file at the destination.
+        if let Ok(meta) = self.ns().stat(to) {
+            if !meta.is_dir {
+                self.ns().remove(to)?;
+            }
+        }
+        self.ns().rename(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, PosixBackend};
+    use crate::index::{PathIndex, SqlitePathIndex, TierId};
+    use crate::policy::PopularityPolicy;
+    use crate::tier::{MostFreePlacement, Tier, TierRouter};
+    use crate::tierer::OpenFileTracker;
+    use std::net::TcpStream;
+
+    fn serve() -> (tempfile::TempDir, NinePServer) {
+        let dir = tempfile::tempdir().unwrap();
+        let tier = |id, name: &str| {
+            let root = dir.path().join(name);
+            std::fs::create_dir_all(&root).unwrap();
+            let b: Arc<dyn Backend> = Arc::new(PosixBackend::new(name, root).unwrap());
+            Tier::new(id, vec![b], Box::new(MostFreePlacement)).unwrap()
+        };
+        let router = Arc::new(TierRouter::new(
+            tier(TierId::Fast, "ssd"),
+            tier(TierId::Slow, "hdd"),
+        ));
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(dir.path().join("idx.db")).unwrap();
+        let ns = Namespace::new(
+            router,
+            index,
+            Arc::new(PopularityPolicy::default()),
+            Arc::new(OpenFileTracker::new()),
+        );
+        let srv = NinePServer::start("127.0.0.1:0".parse().unwrap(), Arc::new(ns), false).unwrap();
+        (dir, srv)
+    }
+
+    struct Client(TcpStream, u16);
+
+    impl Client {
+        /// Send T`ty` with `body`; the reply's (type, body).
+        fn call(&mut self, ty: u8, body: impl FnOnce(&mut Enc)) -> (u8, Vec<u8>) {
+            self.1 += 1;
+            let mut e = Enc::new(ty, self.1);
+            body(&mut e);
+            e.finish(&mut self.0).unwrap();
+            let (rty, tag, reply) = wire::read_msg(&mut self.0, MAX_MSIZE).unwrap().unwrap();
+            assert_eq!(tag, self.1);
+            (rty, reply)
+        }
+    }
+
+    #[test]
+    fn attach_create_write_read_readdir() {
+        let (_dir, srv) = serve();
+        let Endpoint::Tcp(addr) = srv.endpoint() else {
+            unreachable!()
+        };
+        let mut c = Client(TcpStream::connect(addr).unwrap(), 0);
+
+        let (ty, body) = c.call(TVERSION, |e| {
+            e.u32(65536).str(b"9P2000.L");
+        });
+        assert_eq!(ty, TVERSION + 1);
+        assert_eq!(&body[4..], b"\x08\x009P2000.L");
+        assert_eq!(
+            c.call(TATTACH, |e| {
+                e.u32(1).u32(NOFID).str(b"").str(b"").u32(0);
+            })
+            .0,
+            TATTACH + 1
+        );
+        // mkdir /d, then clone the root fid and create /d/f in it.
+        assert_eq!(
+            c.call(TMKDIR, |e| {
+                e.u32(1).str(b"d").u32(0o755).u32(0);
+            })
+            .0,
+            TMKDIR + 1
+        );
+        let (ty, body) = c.call(TWALK, |e| {
+            e.u32(1).u32(2).u16(1).str(b"d");
+        });
+        assert_eq!((ty, &body[..2]), (TWALK + 1, &[1u8, 0][..]));
+        assert_eq!(
+            c.call(TLCREATE, |e| {
+                e.u32(2)
+                    .str(b"f")
+                    .u32(libc::O_RDWR as u32)
+                    .u32(0o644)
+                    .u32(0);
+            })
+            .0,
+            TLCREATE + 1
+        );
+        let (_, body) = c.call(TWRITE, |e| {
+            e.u32(2).u64(0).u32(5).bytes(b"hello");
+        });
+        assert_eq!(body, 5u32.to_le_bytes());
+        let (_, body) = c.call(TREAD, |e| {
+            e.u32(2).u64(1).u32(100);
+        });
+        assert_eq!(&body[4..], b"ello");
+
+        // A walk to a missing name fails outright.
+        let (ty, body) = c.call(TWALK, |e| {
+            e.u32(1).u32(3).u16(1).str(b"nope");
+        });
+        assert_eq!(
+            (ty, body),
+            (TLERROR + 1, (libc::ENOENT as u32).to_le_bytes().to_vec())
+        );
+        // `..` as a name is an escape attempt, not a walk.
+        let (ty, _) = c.call(TUNLINKAT, |e| {
+            e.u32(1).str(b"..").u32(0);
+        });
+        assert_eq!(ty, TLERROR + 1);
+
+        let (_, body) = c.call(TWALK, |e| {
+            e.u32(1).u32(4).u16(1).str(b"d");
+        });
+        assert_eq!(&body[..2], &[1, 0]);
+        let (_, body) = c.call(TREADDIR, |e| {
+            e.u32(4).u64(0).u32(4096);
+        });
+        let names: Vec<_> = {
+            let mut d = Dec::new(&body[4..]);
+            std::iter::from_fn(|| {
+                d.bytes(13).ok()?;
+                d.u64().ok()?;
+                d.u8().ok()?;
+                d.str().ok().map(|n| n.to_vec())
+            })
+            .collect()
+        };
+        assert_eq!(names, [&b"."[..], b"..", b"f"]);
+    }
+
+    #[test]
+    fn endpoints_parse() {
+        assert_eq!(
+            "127.0.0.1:564".parse::<Endpoint>().unwrap(),
+            Endpoint::Tcp("127.0.0.1:564".parse().unwrap())
+        );
+        assert_eq!(
+            "/run/rhss.9p".parse::<Endpoint>().unwrap(),
+            Endpoint::Unix(PathBuf::from("/run/rhss.9p"))
+        );
+        assert!("localhost".parse::<Endpoint>().is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! 9P message framing: little-endian integers, `u16`-length strings,
+//! `size[4] type[1] tag[2]` headers.
+
+use std::io::{self, Read, Write};
+
+use crate::error::{FsError, Result};
+
+/// Smallest `msize` worth negotiating: room for a header and some data.
+pub(super) const MIN_MSIZE: u32 = 4096;
+
+pub(super) fn bad(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Read one message: (type, tag, body).
+pub(super) fn read_msg(r: &mut dyn Read, msize: u32) -> io::Result<Option<(u8, u16, Vec<u8>)>> {
+    let mut size = [0u8; 4];
+    match r.read_exact(&mut size) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let size = u32::from_le_bytes(size);
+    if !(7..=msize).contains(&size) {
+        return Err(bad("message size out of range"));
+    }
+    let mut buf = vec![0u8; size as usize - 4];
+    r.read_exact(&mut buf)?;
+    let tag = u16::from_le_bytes([buf[1], buf[2]]);
+    let ty = buf[0];
+    buf.drain(..3);
+    Ok(Some((ty, tag, buf)))
+}
+
+/// Cursor over a message body.
+pub(super) struct Dec<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Dec<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(FsError::InvalidOperation("truncated 9P message".into()));
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        self.take(n)
+    }
+
+    pub fn str(&mut self) -> Result<&'a [u8]> {
+        let n = self.u16()? as usize;
+        self.take(n)
+    }
+}
+
+/// A reply under construction. `finish` fills in the size.
+pub(super) struct Enc {
+    buf: Vec<u8>,
+}
+
+impl Enc {
+    pub fn new(ty: u8, tag: u16) -> Self {
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(&[0; 4]);
+        buf.push(ty);
+        buf.extend_from_slice(&tag.to_le_bytes());
+        Self { buf }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn str(&mut self, s: &[u8]) -> &mut Self {
+        self.u16(s.len() as u16);
+        self.buf.extend_from_slice(s);
+        self
+    }
+
+    pub fn bytes(&mut self, b: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(b);
+        self
+    }
+
+    /// Overwrite the `u16` at byte `at` (for counts known only at the end).
+    pub fn patch_u16(&mut self, at: usize, v: u16) {
+        self.buf[at..at + 2].copy_from_slice(&v.to_le_bytes());
+    }
+
+    /// Overwrite the `u32` at byte `at`.
+    pub fn patch_u32(&mut self, at: usize, v: u32) {
+        self.buf[at..at + 4].copy_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn finish(mut self, w: &mut dyn Write) -> io::Result<()> {
+        let size = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&size.to_le_bytes());
+        w.write_all(&self.buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_a_message() {
+        let mut e = Enc::new(100, 0xffff);
+        e.u32(8192).str(b"9P2000.L");
+        let mut wire = Vec::new();
+        e.finish(&mut wire).unwrap();
+        assert_eq!(&wire[..4], &(wire.len() as u32).to_le_bytes());
+
+        let (ty, tag, body) = read_msg(&mut &wire[..], 8192).unwrap().unwrap();
+        assert_eq!((ty, tag), (100, 0xffff));
+        let mut d = Dec::new(&body);
+        assert_eq!(d.u32().unwrap(), 8192);
+        assert_eq!(d.str().unwrap(), b"9P2000.L");
+        assert!(d.u8().is_err());
+    }
+}
@@ -0,0 +1,593 @@
+//! OpenTelemetry export (`--otlp-endpoint http://collector:4318`): the
+//! per-operation spans (`fuse`, `backend`) and per-backend storage gauges
+//! go to an OTLP/HTTP collector, so a mount shows up in Jaeger / Grafana
+//! next to the applications using it.
+//!
+//! Payloads use OTLP's JSON encoding, POSTed to `<endpoint>/v1/traces` and
+//! `<endpoint>/v1/metrics` over the same hand-rolled HTTP/1.1 client as
+//! `crate::backend::http`, so no protobuf or gRPC stack is needed. As
+//! there, only `http://` is spoken.
+//!
+//! Spans are exported for whatever the log filter enables; the FUSE and
+//! backend spans are debug level, so run with e.g. `RUST_LOG=rhss=debug`
+//! (or `log_level` in the config). Finished spans are queued by the
+//! tracing layer and shipped every few seconds by an "rhss-otlp" thread,
+//! which `start` spawns once the daemon has forked. When the collector
+//! falls behind, spans beyond `MAX_QUEUED` are dropped and counted.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{info, warn, Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::backend::http::split_url;
+use crate::build_info::BuildInfo;
+use crate::error::{FsError, Result};
+use crate::health;
+use crate::tier::TierRouter;
+use crate::webdav::http::{read_response_head, Body};
+
+/// Finished spans waiting for the exporter; more are dropped.
+const MAX_QUEUED: usize = 4096;
+/// Spans per `/v1/traces` request.
+const BATCH: usize = 512;
+const SPANS_EVERY: Duration = Duration::from_secs(5);
+const METRICS_EVERY: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// OTLP `SPAN_KIND_INTERNAL`.
+const KIND_INTERNAL: u8 = 1;
+/// OTLP `STATUS_CODE_ERROR`.
+const STATUS_ERROR: u8 = 2;
+
+/// Installed by `layer`; `start` and `flush` are no-ops without it.
+static EXPORTER: OnceLock<Arc<Exporter>> = OnceLock::new();
+
+/// Set up export to `endpoint` and return the tracing layer that feeds
+/// it. Called once, by `crate::logging::init`.
+pub fn layer(endpoint: &str) -> Result<OtlpLayer> {
+    let exporter = Arc::new(Exporter::new(endpoint)?);
+    EXPORTER
+        .set(Arc::clone(&exporter))
+        .map_err(|_| FsError::Storage("otlp export is already set up".into()))?;
+    Ok(OtlpLayer { exporter })
+}
+
+/// Start shipping spans and, given a router, storage metrics. Call after
+/// `daemon::daemonize`: threads don't survive the fork.
+pub fn start(router: Option<Arc<TierRouter>>) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let exporter = Arc::clone(exporter);
+    let spawned = thread::Builder::new()
+        .name("rhss-otlp".into())
+        .spawn(move || exporter.run(router.as_deref()));
+    if let Err(e) = spawned {
+        warn!("spawn otlp thread: {e}");
+    }
+}
+
+/// Export whatever spans are still queued; for shutdown.
+pub fn flush() {
+    if let Some(exporter) = EXPORTER.get() {
+        if let Err(e) = exporter.export_spans() {
+            warn!("otlp: {e}");
+        }
+    }
+}
+
+/// A span that has closed, as exported.
+#[derive(Debug, Clone)]
+struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    name: String,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, Value)>,
+    /// Message of the first error logged inside the span.
+    error: Option<String>,
+}
+
+struct Exporter {
+    addr: String,
+    host: String,
+    base: String,
+    queue: Mutex<Vec<SpanData>>,
+    dropped: AtomicU64,
+    resource: Value,
+}
+
+impl Exporter {
+    fn new(endpoint: &str) -> Result<Self> {
+        let (addr, host, base) = split_url(endpoint)?;
+        let build = BuildInfo::current();
+        let resource = json!({ "attributes": [
+            attr("service.name", json!("rhss")),
+            attr("service.version", json!(build.version)),
+            attr("vcs.ref.head.revision", json!(build.git_commit)),
+            attr("rhss.features", json!(build.features.join(","))),
+            attr("rhss.fuse_abi", json!(build.fuse_abi)),
+            attr("host.name", json!(whoami::fallible::hostname().unwrap_or_default())),
+            attr("process.pid", json!(std::process::id())),
+        ]});
+        Ok(Self {
+            host: host.to_string(),
+            base: base.trim_end_matches('/').to_string(),
+            addr,
+            queue: Mutex::default(),
+            dropped: AtomicU64::new(0),
+            resource,
+        })
+    }
+
+    fn push(&self, span: SpanData) {
+        let mut q = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        if q.len() < MAX_QUEUED {
+            q.push(span);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn run(&self, router: Option<&TierRouter>) {
+        let mut failing = false;
+        let mut last_metrics: Option<Instant> = None;
+        loop {
+            let mut res = self.export_spans();
+            if let Some(router) = router {
+                if last_metrics.is_none_or(|t| t.elapsed() >= METRICS_EVERY) {
+                    last_metrics = Some(Instant::now());
+                    res = res.and(self.export_metrics(router));
+                }
+            }
+            // One warning per outage, not one per batch.
+            match &res {
+                Err(e) if !failing => warn!("otlp: {e}; retrying quietly"),
+                Ok(()) if failing => info!("otlp: export works again"),
+                _ => {}
+            }
+            failing = res.is_err();
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                warn!("otlp: dropped {dropped} spans, the collector is not keeping up");
+            }
+            thread::sleep(SPANS_EVERY);
+        }
+    }
+
+    /// Send everything queued. A failed batch is dropped, not retried:
+    /// the spans are diagnostics, and holding them would only grow the
+    /// queue while the collector is down.
+    fn export_spans(&self) -> Result<()> {
+        let spans = std::mem::take(&mut *self.queue.lock().unwrap_or_else(|e| e.into_inner()));
+        for batch in spans.chunks(BATCH) {
+            self.post("/v1/traces", &self.traces_body(batch))?;
+        }
+        Ok(())
+    }
+
+    fn export_metrics(&self, router: &TierRouter) -> Result<()> {
+        self.post("/v1/metrics", &self.metrics_body(router, SystemTime::now()))
+    }
+
+    fn traces_body(&self, spans: &[SpanData]) -> Value {
+        let spans: Vec<Value> = spans
+            .iter()
+            .map(|s| {
+                let mut span = json!({
+                    "traceId": format!("{:032x}", s.trace_id),
+                    "spanId": format!("{:016x}", s.span_id),
+                    "name": s.name,
+                    "kind": KIND_INTERNAL,
+                    "startTimeUnixNano": unix_nanos(s.start),
+                    "endTimeUnixNano": unix_nanos(s.end),
+                    "attributes": s.attributes.iter().map(|(k, v)| attr(k, v.clone())).collect::<Vec<_>>(),
+                });
+                if let Some(parent) = s.parent_id {
+                    span["parentSpanId"] = json!(format!("{parent:016x}"));
+                }
+                if let Some(msg) = &s.error {
+                    span["status"] = json!({ "code": STATUS_ERROR, "message": msg });
+                }
+                span
+            })
+            .collect();
+        json!({ "resourceSpans": [{
+            "resource": self.resource,
+            "scopeSpans": [{ "scope": scope(), "spans": spans }],
+        }]})
+    }
+
+    /// Per-backend gauges: capacity, used and free bytes from `statvfs`,
+    /// plus the latest health probe (`crate::health`).
+    fn metrics_body(&self, router: &TierRouter, now: SystemTime) -> Value {
+        let now = unix_nanos(now);
+        let mut total = Vec::new();
+        let mut used = Vec::new();
+        let mut free = Vec::new();
+        let mut healthy = Vec::new();
+        let mut latency = Vec::new();
+        for (tier, b) in router.all_backends() {
+            let attrs = json!([
+                attr("rhss.tier", json!(tier.as_str())),
+                attr("rhss.backend", json!(b.id())),
+            ]);
+            let point = |v: Value| json!({ "attributes": attrs, "timeUnixNano": now, "asInt": v });
+            if let Ok(st) = b.statvfs() {
+                total.push(point(json!(st.total_bytes.to_string())));
+                used.push(point(json!(st.used_bytes.to_string())));
+                free.push(point(json!(st.free_bytes.to_string())));
+            }
+            if let Some(check) = health::latest(b.id()) {
+                healthy.push(point(json!(u8::from(check.ok).to_string())));
+                latency.push(point(json!(check.latency.as_millis().to_string())));
+            }
+        }
+        let gauge = |name: &str, unit: &str, description: &str, points: Vec<Value>| {
+            json!({
+                "name": name,
+                "unit": unit,
+                "description": description,
+                "gauge": { "dataPoints": points },
+            })
+        };
+        json!({ "resourceMetrics": [{
+            "resource": self.resource,
+            "scopeMetrics": [{ "scope": scope(), "metrics": [
+                gauge("rhss.backend.capacity", "By", "Backend size", total),
+                gauge("rhss.backend.used", "By", "Bytes used on the backend", used),
+                gauge("rhss.backend.free", "By", "Bytes free on the backend", free),
+                gauge("rhss.backend.healthy", "1", "1 if the latest health probe passed", healthy),
+                gauge("rhss.backend.probe_latency", "ms", "Latency of the latest health probe", latency),
+            ]}],
+        }]})
+    }
+
+    /// POST `body` to `<endpoint><path>`; anything but 2xx is an error.
+    fn post(&self, path: &str, body: &Value) -> Result<()> {
+        let target = format!("{}{path}", self.base);
+        let err = |e: io::Error| FsError::Storage(format!("POST {target}: {e}"));
+        let body = serde_json::to_vec(body)?;
+        let mut s = self.connect().map_err(err)?;
+        let head = format!(
+            "POST {target} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rhss\r\n\
+             Connection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            self.host,
+            body.len()
+        );
+        s.write_all(head.as_bytes()).map_err(err)?;
+        s.write_all(&body).map_err(err)?;
+
+        let mut r = BufReader::new(s);
+        let resp = read_response_head(&mut r).map_err(err)?;
+        let mut reply = Vec::new();
+        Body::of_response("POST", &resp, &mut r)
+            .and_then(|mut b| b.read_to_end(&mut reply))
+            .map_err(err)?;
+        if !(200..300).contains(&resp.status) {
+            return Err(FsError::Storage(format!(
+                "POST {target}: HTTP {}: {}",
+                resp.status,
+                String::from_utf8_lossy(&reply).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(s) => {
+                    s.set_read_timeout(Some(IO_TIMEOUT))?;
+                    s.set_write_timeout(Some(IO_TIMEOUT))?;
+                    return Ok(s);
+                }
+                Err(e) => last = Some(e),
+            }
+        }
+        Err(last.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses")))
+    }
+}
+
+fn scope() -> Value {
+    json!({ "name": "rhss", "version": env!("CARGO_PKG_VERSION") })
+}
+
+/// An OTLP `KeyValue`. 64-bit integers are strings in OTLP JSON.
+fn attr(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_f64() => json!({ "doubleValue": n }),
+        Value::Number(n) => json!({ "intValue": n.to_string() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn unix_nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+        .to_string()
+}
+
+/// Random, non-zero ids. `RandomState` is seeded per process, and the
+/// counter keeps successive ids apart.
+fn random_u64() -> u64 {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(SEQ.fetch_add(1, Ordering::Relaxed));
+    h.finish().max(1)
+}
+
+/// Per-span state while the span is open, kept in its extensions.
+struct Open {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    start: SystemTime,
+    attributes: Vec<(&'static str, Value)>,
+    error: Option<String>,
+}
+
+/// Collects span fields as attributes; a later value replaces an earlier
+/// one (e.g. `path`, recorded after the span is created).
+struct Fields<'a>(&'a mut Vec<(&'static str, Value)>);
+
+impl Fields<'_> {
+    fn set(&mut self, field: &Field, value: Value) {
+        match self.0.iter_mut().find(|(k, _)| *k == field.name()) {
+            Some(slot) => slot.1 = value,
+            None => self.0.push((field.name(), value)),
+        }
+    }
+}
+
+impl Visit for Fields<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field, json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field, json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set(field, json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.set(field, json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.set(field, json!(format!("{value:?}")));
+    }
+}
+
+/// Pulls the `message` out of an error event.
+struct Message(Option<String>);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+/// Tracing layer that hands closed spans to the exporter.
+pub struct OtlpLayer {
+    exporter: Arc<Exporter>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|p| {
+            p.extensions()
+                .get::<Open>()
+                .map(|o| (o.trace_id, o.span_id))
+        });
+        let (trace_id, parent_id) = match parent {
+            Some((trace, id)) => (trace, Some(id)),
+            None => (
+                (u128::from(random_u64()) << 64) | u128::from(random_u64()),
+                None,
+            ),
+        };
+        let mut attributes = Vec::new();
+        attrs.record(&mut Fields(&mut attributes));
+        span.extensions_mut().insert(Open {
+            trace_id,
+            span_id: random_u64(),
+            parent_id,
+            start: SystemTime::now(),
+            attributes,
+            error: None,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(open) = span.extensions_mut().get_mut::<Open>() {
+                values.record(&mut Fields(&mut open.attributes));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+        let mut ext = span.extensions_mut();
+        if let Some(open) = ext.get_mut::<Open>() {
+            if open.error.is_none() {
+                let mut msg = Message(None);
+                event.record(&mut msg);
+                open.error = Some(msg.0.unwrap_or_else(|| "error".into()));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<Open>() else {
+            return;
+        };
+        // `fuse` / `backend` spans carry the operation; name them by it so
+        // Jaeger lists "fuse read" rather than a wall of "fuse".
+        let name = match open.attributes.iter().find(|(k, _)| *k == "op") {
+            Some((_, Value::String(op))) => format!("{} {op}", span.name()),
+            _ => span.name().to_string(),
+        };
+        self.exporter.push(SpanData {
+            trace_id: open.trace_id,
+            span_id: open.span_id,
+            parent_id: open.parent_id,
+            name,
+            start: open.start,
+            end: SystemTime::now(),
+            attributes: open.attributes,
+            error: open.error,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::index::TierId;
+    use crate::tier::{MostFreePlacement, Tier};
+    use std::net::TcpListener;
+    use tracing::{debug_span, error, field};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    /// Accept one request, answer 200 and return its head and body.
+    fn collector(listener: TcpListener) -> thread::JoinHandle<(String, Value)> {
+        thread::spawn(move || {
+            let (s, _) = listener.accept().unwrap();
+            let mut r = BufReader::new(s);
+            let mut head = String::new();
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                io::BufRead::read_line(&mut r, &mut line).unwrap();
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    len = v.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; len];
+            r.read_exact(&mut body).unwrap();
+            r.get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (head, serde_json::from_slice(&body).unwrap())
+        })
+    }
+
+    #[test]
+    fn nested_spans_export_as_one_trace() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/otel", listener.local_addr().unwrap());
+        let exporter = Arc::new(Exporter::new(&url).unwrap());
+        let layer = OtlpLayer {
+            exporter: Arc::clone(&exporter),
+        };
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            let fuse = debug_span!("fuse", op = "read", req = 7u64, path = field::Empty);
+            fuse.record("path", "/a/b");
+            fuse.in_scope(|| {
+                debug_span!("backend", id = "ssd", op = "read").in_scope(|| {
+                    error!("read /a/b: boom");
+                });
+            });
+        });
+
+        let server = collector(listener);
+        exporter.export_spans().unwrap();
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /otel/v1/traces HTTP/1.1"), "{head}");
+
+        let spans = body["resourceSpans"][0]["scopeSpans"][0]["spans"]
+            .as_array()
+            .unwrap();
+        // Children close first.
+        let (backend, fuse) = (&spans[0], &spans[1]);
+        assert_eq!(backend["name"], "backend read");
+        assert_eq!(fuse["name"], "fuse read");
+        assert_eq!(backend["traceId"], fuse["traceId"]);
+        assert_eq!(backend["parentSpanId"], fuse["spanId"]);
+        assert!(fuse.get("parentSpanId").is_none());
+        assert_eq!(backend["status"]["code"], STATUS_ERROR);
+        assert!(fuse.get("status").is_none());
+        let attrs = fuse["attributes"].as_array().unwrap();
+        assert!(attrs.contains(&attr("path", json!("/a/b"))));
+        assert!(attrs.contains(&json!({ "key": "req", "value": { "intValue": "7" } })));
+        assert!(exporter.queue.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn metrics_cover_every_backend() {
+        let exporter = Exporter::new("http://127.0.0.1:4318").unwrap();
+        let ram: Arc<dyn Backend> = Arc::new(MemoryBackend::new("otlp-ram", 1 << 20));
+        let router = TierRouter::new(
+            Tier::new(TierId::Fast, vec![ram], Box::new(MostFreePlacement)).unwrap(),
+            Tier::new(
+                TierId::Slow,
+                vec![Arc::new(MemoryBackend::new("otlp-ram2", 2 << 20))],
+                Box::new(MostFreePlacement),
+            )
+            .unwrap(),
+        );
+        let body = exporter.metrics_body(&router, UNIX_EPOCH + Duration::from_secs(1));
+        let metrics = &body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(metrics[0]["name"], "rhss.backend.capacity");
+        let points = metrics[0]["gauge"]["dataPoints"].as_array().unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1]["asInt"], (2u64 << 20).to_string());
+        assert_eq!(points[1]["timeUnixNano"], "1000000000");
+        assert!(points[1]["attributes"]
+            .as_array()
+            .unwrap()
+            .contains(&attr("rhss.tier", json!("slow"))));
+    }
+}
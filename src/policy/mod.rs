@@ -12,8 +12,12 @@
 //! - `DAMPING` ramps 50 000 → 1 000 000 over a week
 //! - initial popularity = `MULTIPLIER * 0.238 ≈ 857` (D17)
 
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
+use parking_lot::RwLock;
+
 use crate::index::TierId;
 
 pub const MULTIPLIER: f64 = 3600.0;
@@ -74,6 +78,14 @@ pub trait TieringPolicy: Send + Sync {
             TierId::Fast
         }
     }
+
+    /// Directories whose files wait their own time before leaving `tier`
+    /// (fast: instead of `min_age_to_evict`, slow: `min_age_to_archive`),
+    /// as `(dir, min_age)`, first match wins. Tenants set these
+    /// (`crate::tenant::TenantPolicy`); none by default.
+    fn min_ages(&self, _tier: TierId) -> Vec<(PathBuf, Duration)> {
+        Vec::new()
+    }
 }
 
 /// Default policy: EMA + 3 watermarks (D6, D17) + archive demotion.
@@ -133,6 +145,61 @@ impl TieringPolicy for PopularityPolicy {
     }
 }
 
+/// A policy that can be swapped at runtime (SIGHUP reload). Holders of the
+/// `Arc<dyn TieringPolicy>` see the new thresholds on their next call.
+pub struct ReloadablePolicy {
+    inner: RwLock<Arc<dyn TieringPolicy>>,
+}
+
+impl ReloadablePolicy {
+    pub fn new(inner: Arc<dyn TieringPolicy>) -> Arc<Self> {
+        Arc::new(Self {
+            inner: RwLock::new(inner),
+        })
+    }
+
+    pub fn replace(&self, inner: Arc<dyn TieringPolicy>) {
+        *self.inner.write() = inner;
+    }
+
+    fn get(&self) -> Arc<dyn TieringPolicy> {
+        Arc::clone(&self.inner.read())
+    }
+}
+
+impl TieringPolicy for ReloadablePolicy {
+    fn low_watermark(&self) -> f64 {
+        self.get().low_watermark()
+    }
+    fn high_watermark(&self) -> f64 {
+        self.get().high_watermark()
+    }
+    fn panic_watermark(&self) -> f64 {
+        self.get().panic_watermark()
+    }
+    fn tier_period(&self) -> Option<Duration> {
+        self.get().tier_period()
+    }
+    fn min_age_to_evict(&self) -> Duration {
+        self.get().min_age_to_evict()
+    }
+    fn initial_popularity(&self) -> f64 {
+        self.get().initial_popularity()
+    }
+    fn min_age_to_archive(&self) -> Duration {
+        self.get().min_age_to_archive()
+    }
+    fn slow_archive_watermark(&self) -> f64 {
+        self.get().slow_archive_watermark()
+    }
+    fn tier_for_create(&self, fast_usage: f64) -> TierId {
+        self.get().tier_for_create(fast_usage)
+    }
+    fn min_ages(&self, tier: TierId) -> Vec<(PathBuf, Duration)> {
+        self.get().min_ages(tier)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -187,4 +254,16 @@ mod tests {
         assert_eq!(p.tier_for_create(0.5), TierId::Fast);
         assert_eq!(p.tier_for_create(0.96), TierId::Slow);
     }
+
+    #[test]
+    fn reloadable_policy_swaps_thresholds() {
+        let p = ReloadablePolicy::new(Arc::new(PopularityPolicy::default()));
+        assert_eq!(p.tier_for_create(0.9), TierId::Fast);
+        p.replace(Arc::new(PopularityPolicy {
+            panic_watermark: 0.5,
+            ..Default::default()
+        }));
+        assert_eq!(p.panic_watermark(), 0.5);
+        assert_eq!(p.tier_for_create(0.9), TierId::Slow);
+    }
 }
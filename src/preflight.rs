@@ -0,0 +1,252 @@
+//! Startup checks on the storage layout, so a bad config fails the mount
+//! with one clear message instead of surfacing later as `ENOSPC`, `EROFS`
+//! or a file turning up twice.
+//!
+//! `RhssBuilder::build` checks the fast- and slow-tier roots (except for a
+//! read-only `with_shared` mount, which may not be allowed to write them):
+//!
+//! - each is a writable directory;
+//! - none lies inside another;
+//! - the slow tier has at least `MIN_FREE` bytes free, since eviction has
+//!   nowhere to go otherwise (a full fast tier only gets a warning: the
+//!   tierer empties it);
+//! - fast and slow roots sit on different filesystems. Sharing one only
+//!   gets a warning, since tiering then moves data without freeing
+//!   anything, but nothing breaks.
+//!
+//! `Rhss::mount` then refuses a mount point that would cover a root, or
+//! that lies inside one: the mount would show up as a directory of its
+//! own tier, and a scan or the tierer walking that root would recurse into
+//! rhss itself. Mounting over the directory that holds the roots is what
+//! hidden-storage mode (`crate::hidden`) is for; a mount point inside a
+//! root is never supported. `rhss mount` runs the same check on the config
+//! before it creates the mount point, so a refused mount leaves nothing
+//! behind in the tier.
+//!
+//! Backends without a directory root (`mem://`) and slow backends that were
+//! missing at startup (`crate::backend::deferred`) are skipped.
+
+use std::path::{Path, PathBuf};
+
+use rustix::fs::Access;
+use tracing::warn;
+
+use crate::config::RhssConfig;
+use crate::error::{FsError, Result};
+use crate::index::TierId;
+use crate::tier::TierRouter;
+
+/// Free space below which a slow-tier root fails the preflight.
+pub const MIN_FREE: u64 = 16 << 20;
+
+/// One backend root, as the checks see it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageRoot {
+    pub tier: TierId,
+    pub backend: String,
+    pub path: PathBuf,
+}
+
+impl StorageRoot {
+    fn name(&self) -> String {
+        format!(
+            "{} root {} ({})",
+            self.tier.as_str(),
+            self.path.display(),
+            self.backend
+        )
+    }
+}
+
+/// The fast- and slow-tier roots of `router` worth checking.
+pub fn roots(router: &TierRouter) -> Vec<StorageRoot> {
+    router
+        .all_backends()
+        .filter(|(tier, b)| *tier != TierId::Archive && b.available() && b.root().is_dir())
+        .map(|(tier, b)| StorageRoot {
+            tier,
+            backend: b.id().to_string(),
+            path: b.root().to_path_buf(),
+        })
+        .collect()
+}
+
+/// The fast- and slow-tier directory roots (and replicas) `cfg` names,
+/// whether or not they exist yet.
+pub fn config_roots(cfg: &RhssConfig) -> Vec<StorageRoot> {
+    let tiers = [
+        (TierId::Fast, &cfg.tier.fast),
+        (TierId::Slow, &cfg.tier.slow),
+    ];
+    tiers
+        .into_iter()
+        .flat_map(|(tier, backends)| backends.iter().map(move |b| (tier, b)))
+        .filter(|(_, b)| b.memory_size().is_none())
+        .flat_map(|(tier, b)| {
+            std::iter::once(&b.root)
+                .chain(&b.replicas)
+                .map(move |root| StorageRoot {
+                    tier,
+                    backend: b.id.clone(),
+                    path: root.clone(),
+                })
+        })
+        .collect()
+}
+
+/// Check `roots` as described in the module docs. Every problem found is
+/// listed in the error.
+pub fn check(roots: &[StorageRoot]) -> Result<()> {
+    let mut problems = Vec::new();
+    let mut resolved = Vec::new();
+    for r in roots {
+        let path = match r.path.canonicalize() {
+            Ok(p) => p,
+            Err(e) => {
+                problems.push(format!("{}: {e}", r.name()));
+                continue;
+            }
+        };
+        if let Err(e) = rustix::fs::access(&path, Access::WRITE_OK | Access::EXEC_OK) {
+            problems.push(format!("{} is not writable: {e}", r.name()));
+        }
+        match rustix::fs::statvfs(&path) {
+            Ok(s) => {
+                let free = s.f_bavail * s.f_frsize;
+                if free < MIN_FREE && r.tier == TierId::Slow {
+                    problems.push(format!(
+                        "{} has only {free} bytes free; eviction needs at least {MIN_FREE}",
+                        r.name()
+                    ));
+                } else if free < MIN_FREE {
+                    warn!("preflight: {} has only {free} bytes free", r.name());
+                }
+            }
+            Err(e) => problems.push(format!("{}: statvfs: {e}", r.name())),
+        }
+        let dev = rustix::fs::stat(&path).map(|st| st.st_dev).ok();
+        resolved.push((r, path, dev));
+    }
+
+    for (i, (a, a_path, a_dev)) in resolved.iter().enumerate() {
+        for (b, b_path, b_dev) in &resolved[i + 1..] {
+            if a_path == b_path {
+                problems.push(format!(
+                    "{} and {} are the same directory",
+                    a.name(),
+                    b.name()
+                ));
+            } else if a_path.starts_with(b_path) {
+                problems.push(format!("{} is inside {}", a.name(), b.name()));
+            } else if b_path.starts_with(a_path) {
+                problems.push(format!("{} is inside {}", b.name(), a.name()));
+            } else if a.tier != b.tier && a_dev.is_some() && a_dev == b_dev {
+                warn!(
+                    "preflight: {} and {} are on the same filesystem; \
+                     tiering between them frees no space",
+                    a.name(),
+                    b.name()
+                );
+            }
+        }
+    }
+    failed(problems)
+}
+
+/// Refuse a `mount` point that is, contains or lies inside one of `roots`.
+/// Paths that don't exist yet are compared as given.
+pub fn check_mount(roots: &[StorageRoot], mount: &Path) -> Result<()> {
+    let resolve = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+    let mount = resolve(mount);
+    let problems = roots
+        .iter()
+        .filter_map(|r| {
+            let root = resolve(&r.path);
+            if root.starts_with(&mount) {
+                Some(format!(
+                    "{} is under the mount point {}; move it, or enable hidden_storage",
+                    r.name(),
+                    mount.display()
+                ))
+            } else if mount.starts_with(&root) {
+                Some(format!(
+                    "the mount point {} is inside {}; mount outside the storage roots",
+                    mount.display(),
+                    r.name()
+                ))
+            } else {
+                None
+            }
+        })
+        .collect();
+    failed(problems)
+}
+
+fn failed(problems: Vec<String>) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(FsError::Storage(format!(
+        "storage preflight failed: {}",
+        problems.join("; ")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(tier: TierId, backend: &str, path: &Path) -> StorageRoot {
+        StorageRoot {
+            tier,
+            backend: backend.into(),
+            path: path.to_path_buf(),
+        }
+    }
+
+    #[test]
+    fn nested_and_missing_roots_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let ssd = dir.path().join("ssd");
+        let hdd = dir.path().join("hdd");
+        std::fs::create_dir_all(ssd.join("inner")).unwrap();
+        std::fs::create_dir(&hdd).unwrap();
+
+        check(&[
+            root(TierId::Fast, "ssd", &ssd),
+            root(TierId::Slow, "hdd", &hdd),
+        ])
+        .unwrap();
+
+        let err = check(&[
+            root(TierId::Fast, "ssd", &ssd),
+            root(TierId::Slow, "hdd", &ssd.join("inner")),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("slow root") && err.contains("is inside fast root"),
+            "{err}"
+        );
+
+        let err = check(&[root(TierId::Slow, "gone", &dir.path().join("gone"))]).unwrap_err();
+        assert!(err.to_string().contains("(gone)"), "{err}");
+    }
+
+    #[test]
+    fn mount_over_or_inside_a_root_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let ssd = dir.path().join("data/ssd");
+        std::fs::create_dir_all(&ssd).unwrap();
+        let roots = [root(TierId::Fast, "ssd", &ssd)];
+        assert!(check_mount(&roots, &dir.path().join("data")).is_err());
+        assert!(check_mount(&roots, &ssd).is_err());
+        check_mount(&roots, &dir.path().join("mnt")).unwrap();
+
+        // Not created yet, as `rhss mount` checks before creating it.
+        let err = check_mount(&roots, &ssd.join("mnt")).unwrap_err();
+        assert!(err.to_string().contains("is inside fast root"), "{err}");
+        // A sibling that merely shares a name prefix is fine.
+        check_mount(&roots, &dir.path().join("data/ssd2")).unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+//! Preheating: fast-tier copies of a cold subtree ahead of a known batch
+//! job (`rhss preheat <dir>`), released again once it is done
+//! (`rhss preheat --release <dir>`).
+//!
+//! A preheated file keeps its index row, and its authoritative bytes, on
+//! the slow or archive tier; the copy is recorded next to the row as a
+//! `HotCopy`. FUSE opens the copy for reads only while the source is
+//! unchanged (same backend, path, size and mtime as when it was copied).
+//! Opening the file for writing, truncating, unlinking or renaming it
+//! drops the copy, and so does migrating it, so writes only ever reach
+//! the authoritative bytes and releasing never has anything to write back.
+//!
+//! Copies live under `.rhss-preheat/` on a fast backend, reserved in
+//! `crate::filter`. The tierer does not evict them; instead preheating
+//! stops short of the policy's high watermark.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::backend::Backend;
+use crate::error::{ErrorContext, FsError, Result};
+use crate::index::{FileRow, HotCopy, PathIndex, TierId};
+use crate::tier::TierRouter;
+use crate::tierer::MigrateFailure;
+
+/// Backend-relative directory holding the copies.
+pub const PREHEAT_DIR: &str = ".rhss-preheat";
+
+/// Where the copy of `logical` goes on a fast backend.
+pub fn copy_path(logical: &Path) -> PathBuf {
+    Path::new(PREHEAT_DIR).join(logical.strip_prefix("/").unwrap_or(logical))
+}
+
+/// Totals of a `preheat` or `release` call.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreheatReport {
+    /// Indexed files under the directory.
+    pub files_scanned: u64,
+    pub files_copied: u64,
+    pub bytes_copied: u64,
+    /// Already on the fast tier, or preheated before and still valid.
+    pub files_hot: u64,
+    /// Left cold: compressed on their tier, or no room on the fast tier
+    /// below the high watermark.
+    pub files_skipped: u64,
+    pub failures: Vec<MigrateFailure>,
+    /// `release`: copies dropped and the bytes they held.
+    pub files_released: u64,
+    pub bytes_released: u64,
+}
+
+/// Copy every file at or below `dir` that isn't on the fast tier to it,
+/// keeping the fast tier at or below `max_usage` (the policy's high
+/// watermark). `on_progress` sees the totals after each file.
+pub fn preheat(
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+    dir: &Path,
+    max_usage: f64,
+    on_progress: &mut dyn FnMut(&PreheatReport),
+) -> Result<PreheatReport> {
+    let rows = index.list_under(dir)?;
+    let mut report = PreheatReport {
+        files_scanned: rows.len() as u64,
+        ..Default::default()
+    };
+    let (total, used, _) = router.fast.capacity();
+    let mut room = ((total as f64 * max_usage) as u64).saturating_sub(used);
+    for row in rows {
+        if row.location.tier == TierId::Fast || valid_copy(router, index, &row).is_some() {
+            report.files_hot += 1;
+        } else if row.compressed || row.location.size > room {
+            report.files_skipped += 1;
+        } else {
+            match copy_one(router, index, &row) {
+                Ok(()) => {
+                    report.files_copied += 1;
+                    report.bytes_copied += row.location.size;
+                    room -= row.location.size;
+                }
+                Err(e) => {
+                    warn!("preheat {}: {e}", row.logical_path.display());
+                    report.failures.push(MigrateFailure {
+                        path: row.logical_path,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        on_progress(&report);
+    }
+    Ok(report)
+}
+
+/// Drop every copy at or below `dir`.
+pub fn release(
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+    dir: &Path,
+) -> Result<PreheatReport> {
+    let mut report = PreheatReport::default();
+    for copy in index.list_hot_copies(dir)? {
+        if drop_copy(router, index, &copy.logical_path)? {
+            report.files_released += 1;
+            report.bytes_released += copy.size;
+        }
+    }
+    Ok(report)
+}
+
+/// Forget the copy of `logical`, if any, and delete its bytes. Returns
+/// whether there was one.
+pub fn drop_copy(router: &TierRouter, index: &Arc<dyn PathIndex>, logical: &Path) -> Result<bool> {
+    let Some(copy) = index.remove_hot_copy(logical)? else {
+        return Ok(false);
+    };
+    if let Some(b) = router.resolve_backend(TierId::Fast, &copy.backend_id) {
+        if let Err(e) = b.remove(&copy.backend_path) {
+            // Gone already, or the backend is down; the row is what counts.
+            debug!("remove preheated {}: {e}", copy.backend_path.display());
+        }
+    }
+    Ok(true)
+}
+
+/// Drop the copies at or below `path` (a file or a directory).
+pub fn drop_under(router: &TierRouter, index: &Arc<dyn PathIndex>, path: &Path) -> Result<()> {
+    for copy in index.list_hot_copies(path)? {
+        drop_copy(router, index, &copy.logical_path)?;
+    }
+    Ok(())
+}
+
+/// The fast backend and path to read `row` from, if it has a copy taken
+/// from its current source that is still unchanged there.
+pub fn valid_copy(
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+    row: &FileRow,
+) -> Option<(Arc<dyn Backend>, PathBuf)> {
+    let copy = index.hot_copy(&row.logical_path).ok().flatten()?;
+    let src = router.resolve_backend(row.location.tier, &row.location.backend_id)?;
+    let unchanged = copy.source_backend_id == row.location.backend_id
+        && copy.source_backend_path == row.location.backend_path
+        && copy.size == row.location.size
+        && src
+            .metadata(&row.location.backend_path)
+            .is_ok_and(|m| m.size == copy.size && m.mtime == copy.source_mtime);
+    let dst = router.resolve_backend(TierId::Fast, &copy.backend_id)?;
+    if !unchanged || !dst.exists(&copy.backend_path).unwrap_or(false) {
+        debug!("stale preheated copy of {}", row.logical_path.display());
+        return None;
+    }
+    Some((Arc::clone(dst), copy.backend_path))
+}
+
+fn copy_one(router: &TierRouter, index: &Arc<dyn PathIndex>, row: &FileRow) -> Result<()> {
+    let src = router
+        .resolve_backend(row.location.tier, &row.location.backend_id)
+        .ok_or_else(|| {
+            FsError::Storage(format!(
+                "source backend {} not found",
+                row.location.backend_id
+            ))
+        })?;
+    let dst = router.fast.pick()?;
+    let dst_path = copy_path(&row.logical_path);
+    let src_meta = src.metadata(&row.location.backend_path)?;
+    // A leftover from an earlier copy would be written over in place;
+    // `create_file` also makes the directories above the copy.
+    let _ = dst.remove(&dst_path);
+    let copied = dst
+        .create_file(&dst_path)
+        .and_then(|()| {
+            crate::tierer::copy_streaming(src, &row.location.backend_path, dst, &dst_path)
+        })
+        .and_then(|()| dst.fsync(&dst_path));
+    if let Err(e) = copied {
+        let _ = dst.remove(&dst_path);
+        return Err(e.with_context(
+            ErrorContext::new("preheat")
+                .tier(TierId::Fast)
+                .backend(dst.id())
+                .path(&dst_path),
+        ));
+    }
+    index.set_hot_copy(HotCopy {
+        logical_path: row.logical_path.clone(),
+        backend_id: dst.id().to_string(),
+        backend_path: dst_path,
+        source_backend_id: row.location.backend_id.clone(),
+        source_backend_path: row.location.backend_path.clone(),
+        size: src_meta.size,
+        source_mtime: src_meta.mtime,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use crate::index::{FileState, Location, Mutability, SqlitePathIndex};
+    use crate::tier::{MostFreePlacement, Tier};
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    #[test]
+    fn preheat_copies_until_the_source_changes() {
+        let (ssd, hdd, db) = (
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+            TempDir::new().unwrap(),
+        );
+        let fast: Arc<dyn Backend> =
+            Arc::new(PosixBackend::new("ssd", ssd.path().to_path_buf()).unwrap());
+        let slow: Arc<dyn Backend> =
+            Arc::new(PosixBackend::new("hdd", hdd.path().to_path_buf()).unwrap());
+        let router = TierRouter::new(
+            Tier::new(
+                TierId::Fast,
+                vec![Arc::clone(&fast)],
+                Box::new(MostFreePlacement),
+            )
+            .unwrap(),
+            Tier::new(
+                TierId::Slow,
+                vec![Arc::clone(&slow)],
+                Box::new(MostFreePlacement),
+            )
+            .unwrap(),
+        );
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(db.path().join("i.db")).unwrap();
+        std::fs::create_dir(hdd.path().join("job")).unwrap();
+        std::fs::write(hdd.path().join("job/in.dat"), b"cold bytes").unwrap();
+        index
+            .insert(FileRow {
+                logical_path: "/job/in.dat".into(),
+                location: Location {
+                    tier: TierId::Slow,
+                    backend_id: "hdd".into(),
+                    backend_path: "job/in.dat".into(),
+                    size: 10,
+                },
+                replicas: Vec::new(),
+                last_access: SystemTime::now(),
+                hit_count: 0,
+                popularity: 0.0,
+                pinned_tier: None,
+                pinned_until: None,
+                state: FileState::Stable,
+                mutability: Mutability::Unknown,
+                compressed: false,
+                content_hash: None,
+            })
+            .unwrap();
+
+        let report = preheat(&router, &index, Path::new("/job"), 1.0, &mut |_| {}).unwrap();
+        assert_eq!((report.files_copied, report.bytes_copied), (1, 10));
+        let row = index.get(Path::new("/job/in.dat")).unwrap().unwrap();
+        assert_eq!(
+            row.location.tier,
+            TierId::Slow,
+            "the cold copy stays authoritative"
+        );
+        let (b, p) = valid_copy(&router, &index, &row).unwrap();
+        assert_eq!(b.id(), "ssd");
+        assert_eq!(std::fs::read(ssd.path().join(&p)).unwrap(), b"cold bytes");
+
+        // Again: nothing to do.
+        let again = preheat(&router, &index, Path::new("/"), 1.0, &mut |_| {}).unwrap();
+        assert_eq!((again.files_copied, again.files_hot), (0, 1));
+
+        // The source changed behind the copy's back: not used any more.
+        std::fs::write(hdd.path().join("job/in.dat"), b"new bytes!").unwrap();
+        let past = SystemTime::now() - std::time::Duration::from_secs(60);
+        slow.set_times(Path::new("job/in.dat"), None, Some(past))
+            .unwrap();
+        assert!(valid_copy(&router, &index, &row).is_none());
+
+        let released = release(&router, &index, Path::new("/")).unwrap();
+        assert_eq!((released.files_released, released.bytes_released), (1, 10));
+        assert!(!ssd.path().join(&p).exists());
+        assert!(index.list_hot_copies(Path::new("/")).unwrap().is_empty());
+    }
+}
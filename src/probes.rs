@@ -0,0 +1,176 @@
+//! Liveness and readiness over HTTP, for container orchestrators that
+//! can't read `sd_notify`. Off unless `[health] listen` or `rhss mount
+//! --probe-listen` names an address:
+//!
+//! - `GET /livez` is 200 while the main loop keeps turning. It is 503 once
+//!   the loop has been stuck for `STALL`, so a wedged daemon gets
+//!   restarted. Startup (scan, health wait) counts as alive.
+//! - `GET /readyz` is 200 from the moment the mount is live (when `READY=1`
+//!   goes to systemd) until shutdown starts, 503 otherwise.
+//!
+//! Bodies are one line of plain text. There is no authentication; the
+//! answers carry nothing but up or down.
+
+use std::io::{self, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::SeqCst};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, info};
+
+use crate::daemon;
+use crate::error::{FsError, Result};
+use crate::webdav::http::{self, Response};
+
+/// `/livez` fails once the main loop hasn't called `heartbeat` for this
+/// long.
+pub const STALL: Duration = Duration::from_secs(30);
+
+/// Unix seconds of the latest `heartbeat`; 0 before the main loop starts.
+static HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Called by the main loop on every turn.
+pub fn heartbeat() {
+    HEARTBEAT.store(now_secs(), SeqCst);
+}
+
+/// Owns the listening socket and the accept thread. Drop stops it.
+pub struct ProbeServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl ProbeServer {
+    pub fn start(listen: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(listen).map_err(FsError::Io)?;
+        listener.set_nonblocking(true).map_err(FsError::Io)?;
+        let addr = listener.local_addr().map_err(FsError::Io)?;
+        info!("probes: /livez and /readyz on http://{addr}/");
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_for_thread = Arc::clone(&shutdown);
+        let handle = std::thread::Builder::new()
+            .name("rhss-probes".into())
+            .spawn(move || accept_loop(listener, &shutdown_for_thread))
+            .map_err(FsError::Io)?;
+        Ok(Self {
+            addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    /// Where the server ended up listening (useful with port 0).
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for ProbeServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, shutdown: &AtomicBool) {
+    while !shutdown.load(SeqCst) {
+        match listener.accept() {
+            // Answers are instant; no thread per client.
+            Ok((stream, peer)) => {
+                if let Err(e) = answer(stream) {
+                    debug!("probe client {peer}: {e}");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => {
+                debug!("probe accept failed: {e}");
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        }
+    }
+}
+
+fn answer(stream: TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut out = stream.try_clone()?;
+    let Some(req) = http::read_request(&mut BufReader::new(stream))? else {
+        return Ok(());
+    };
+    let path = req.target.split('?').next().unwrap_or_default();
+    let (status, text) = match (req.method.as_str(), path) {
+        ("GET" | "HEAD", "/livez") => live(),
+        ("GET" | "HEAD", "/readyz") => ready(),
+        ("GET" | "HEAD", _) => (404, "not found".to_string()),
+        _ => (405, "method not allowed".to_string()),
+    };
+    let resp = Response::new(status).text(if req.method == "HEAD" {
+        String::new()
+    } else {
+        format!("{text}\n")
+    });
+    resp.write_to(&mut out, true)?;
+    out.flush()
+}
+
+fn live() -> (u16, String) {
+    let last = HEARTBEAT.load(SeqCst);
+    let stalled = now_secs().saturating_sub(last);
+    if last != 0 && stalled >= STALL.as_secs() {
+        return (503, format!("main loop stalled for {stalled}s"));
+    }
+    (200, "ok".into())
+}
+
+fn ready() -> (u16, String) {
+    if daemon::stop_requested() {
+        (503, "shutting down".into())
+    } else if !daemon::is_ready() {
+        (503, "starting".into())
+    } else {
+        (200, "ok".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut s = TcpStream::connect(addr).unwrap();
+        write!(s, "GET {path} HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        s.read_to_string(&mut resp).unwrap();
+        resp
+    }
+
+    #[test]
+    fn livez_follows_the_heartbeat() {
+        let srv = ProbeServer::start("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = srv.local_addr();
+        assert!(get(addr, "/livez").starts_with("HTTP/1.1 200"));
+        HEARTBEAT.store(now_secs() - STALL.as_secs() - 1, SeqCst);
+        let resp = get(addr, "/livez");
+        assert!(
+            resp.starts_with("HTTP/1.1 503") && resp.contains("stalled"),
+            "{resp}"
+        );
+        heartbeat();
+        assert!(get(addr, "/livez").starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/nope").starts_with("HTTP/1.1 404"));
+    }
+}
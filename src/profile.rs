@@ -0,0 +1,237 @@
+//! Sampling profiler for where a FUSE op's time goes, without perf or an
+//! external tracer. `rhss profile` switches it on in a running mount.
+//!
+//! One op in every `1 / rate` is sampled. A sampled op times itself
+//! (`fuse:<op>`) and the phases it runs through on its worker thread: index
+//! lookups (`index`) and backend calls (`backend:<id>:<op>`, timed from
+//! the caller's side by `crate::backend::timeout`, so they are missing
+//! when `op_timeout_secs = 0`). Each phase is charged its self time, i.e.
+//! minus the phases nested inside it, under its full stack, which is
+//! exactly what flamegraph tools take as "folded" input:
+//!
+//! ```text
+//! fuse:read;index 412
+//! fuse:read;backend:hdd:read 18234
+//! ```
+//!
+//! (microseconds). Ops that aren't sampled pay one atomic increment; phases
+//! outside a sampled op, one thread-local check.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Sample every Nth op; 0 = off.
+static PERIOD: AtomicU64 = AtomicU64::new(0);
+static SEEN: AtomicU64 = AtomicU64::new(0);
+static SAMPLED: AtomicU64 = AtomicU64::new(0);
+static STACKS: Mutex<Option<HashMap<String, Stack>>> = Mutex::new(None);
+
+thread_local! {
+    /// Open frames of the op being sampled on this thread, outermost first.
+    static OPEN: RefCell<Vec<Open>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Open {
+    name: Cow<'static, str>,
+    start: Instant,
+    children: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Stack {
+    hits: u64,
+    micros: u64,
+}
+
+/// Sample `rate` of all ops from now on (clamped to `0..=1`). Turning
+/// sampling on starts a fresh profile; turning it off keeps the one
+/// collected for `report`.
+pub fn configure(rate: f64) {
+    let rate = if rate.is_finite() {
+        rate.clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let period = if rate > 0.0 {
+        (1.0 / rate).round().max(1.0) as u64
+    } else {
+        0
+    };
+    if period > 0 {
+        *STACKS.lock() = Some(HashMap::new());
+        SAMPLED.store(0, Ordering::Relaxed);
+        SEEN.store(0, Ordering::Relaxed);
+    }
+    PERIOD.store(period, Ordering::Relaxed);
+}
+
+/// The sampling rate in effect; 0 when off.
+pub fn rate() -> f64 {
+    match PERIOD.load(Ordering::Relaxed) {
+        0 => 0.0,
+        p => 1.0 / p as f64,
+    }
+}
+
+/// Start timing op `op` if it is one to sample. Hold the frame for the
+/// duration of the op.
+pub fn op(op: &'static str) -> Option<Frame> {
+    let period = PERIOD.load(Ordering::Relaxed);
+    if period == 0 || !SEEN.fetch_add(1, Ordering::Relaxed).is_multiple_of(period) {
+        return None;
+    }
+    // A nested op (one op handling another inline) is part of the outer.
+    if OPEN.with(|o| !o.borrow().is_empty()) {
+        return None;
+    }
+    SAMPLED.fetch_add(1, Ordering::Relaxed);
+    Some(push(Cow::Owned(format!("fuse:{op}"))))
+}
+
+/// Time a phase of the op being sampled on this thread, if any. `name` is
+/// only called then.
+pub fn phase<N: Into<Cow<'static, str>>>(name: impl FnOnce() -> N) -> Option<Frame> {
+    if OPEN.with(|o| o.borrow().is_empty()) {
+        return None;
+    }
+    Some(push(name().into()))
+}
+
+fn push(name: Cow<'static, str>) -> Frame {
+    OPEN.with(|o| {
+        o.borrow_mut().push(Open {
+            name,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        })
+    });
+    Frame {
+        _thread: PhantomData,
+    }
+}
+
+/// An open frame; closes on drop. Frames nest, so they must be dropped
+/// in reverse order, which scoped guards are.
+pub struct Frame {
+    /// Frames belong to the thread's stack.
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        let closed = OPEN.with(|o| {
+            let mut open = o.borrow_mut();
+            let key = open
+                .iter()
+                .map(|f| f.name.as_ref())
+                .collect::<Vec<_>>()
+                .join(";");
+            let frame = open.pop()?;
+            let spent = frame.start.elapsed();
+            if let Some(parent) = open.last_mut() {
+                parent.children += spent;
+            }
+            Some((key, spent.saturating_sub(frame.children)))
+        });
+        let Some((key, spent)) = closed else {
+            return;
+        };
+        if let Some(stacks) = STACKS.lock().as_mut() {
+            let s = stacks.entry(key).or_default();
+            s.hits += 1;
+            s.micros += spent.as_micros() as u64;
+        }
+    }
+}
+
+/// One stack of a profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StackTime {
+    /// Frames, outermost first, joined by `;`.
+    pub stack: String,
+    /// Times this exact stack was closed.
+    pub hits: u64,
+    /// Self time, in microseconds.
+    pub micros: u64,
+}
+
+/// What has been sampled since sampling was last switched on.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileReport {
+    /// Rate in effect when the report was taken.
+    pub rate: f64,
+    pub sampled_ops: u64,
+    /// Most time first.
+    pub stacks: Vec<StackTime>,
+}
+
+impl ProfileReport {
+    /// Folded stacks (`<stack> <micros>` per line), as `flamegraph.pl`,
+    /// `inferno-flamegraph` and speedscope read them.
+    pub fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|s| format!("{} {}\n", s.stack, s.micros))
+            .collect()
+    }
+}
+
+pub fn report() -> ProfileReport {
+    let mut stacks: Vec<StackTime> = STACKS
+        .lock()
+        .iter()
+        .flatten()
+        .map(|(stack, s)| StackTime {
+            stack: stack.clone(),
+            hits: s.hits,
+            micros: s.micros,
+        })
+        .collect();
+    stacks.sort_by(|a, b| b.micros.cmp(&a.micros).then_with(|| a.stack.cmp(&b.stack)));
+    ProfileReport {
+        rate: rate(),
+        sampled_ops: SAMPLED.load(Ordering::Relaxed),
+        stacks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampled_ops_charge_self_time_to_their_stacks() {
+        configure(0.5);
+        assert_eq!(rate(), 0.5);
+        // Outside a sampled op, phases are free.
+        assert!(phase(|| "index").is_none());
+        for _ in 0..4 {
+            let Some(_op) = op("read") else { continue };
+            {
+                let _index = phase(|| "index");
+                std::thread::sleep(Duration::from_millis(2));
+            }
+            let _backend = phase(|| format!("backend:{}:read", "hdd"));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        configure(0.0);
+        assert!(op("read").is_none());
+
+        let r = report();
+        assert_eq!(r.sampled_ops, 2);
+        let get = |k: &str| r.stacks.iter().find(|s| s.stack == k).unwrap().clone();
+        assert_eq!(get("fuse:read;index").hits, 2);
+        assert!(get("fuse:read;backend:hdd:read").micros >= 10_000);
+        // The op itself only keeps what its phases didn't account for.
+        assert!(get("fuse:read").micros < get("fuse:read;backend:hdd:read").micros);
+        assert_eq!(r.stacks[0].stack, "fuse:read;backend:hdd:read");
+        assert!(r.folded().starts_with("fuse:read;backend:hdd:read "));
+    }
+}
@@ -0,0 +1,406 @@
+//! Byte quotas per directory and per owner uid.
+//!
+//! Configured under `[quota]`, one entry per limit:
+//!
+//! ```toml
+//! [quota]
+//! "/projects/foo" = "50G"
+//! "uid 1001" = "100G"
+//! ```
+//!
+//! Usage is logical bytes (what `stat` reports, before compression or
+//! dedup), counted from the index and backend metadata at mount and kept up
+//! to date by the FUSE layer on every write, truncate, unlink and rename.
+//! Trashed files no longer count. The owner is whatever uid the backend
+//! reports for the file. A file may fall under several rules (nested
+//! directories, a directory and its owner); growth is refused with
+//! `EDQUOT` as soon as any of them would go over.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::error::{FsError, Result};
+use crate::index::PathIndex;
+use crate::tier::TierRouter;
+use crate::tierer::compress::compressed_path;
+
+/// What a rule limits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaTarget {
+    /// Everything at or below this logical directory.
+    Dir(PathBuf),
+    /// Every file owned by this uid.
+    Uid(u32),
+}
+
+impl std::fmt::Display for QuotaTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuotaTarget::Dir(p) => write!(f, "{}", p.display()),
+            QuotaTarget::Uid(u) => write!(f, "uid {u}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaRule {
+    pub target: QuotaTarget,
+    pub limit: u64,
+}
+
+impl QuotaRule {
+    fn covers(&self, logical: &Path, uid: u32) -> bool {
+        match &self.target {
+            QuotaTarget::Dir(d) => logical.starts_with(d),
+            QuotaTarget::Uid(u) => *u == uid,
+        }
+    }
+}
+
+/// A `[quota]` value: bytes, or a size string like `"50G"`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LimitSpec {
+    Bytes(u64),
+    Human(String),
+}
+
+/// `"512"`, `"64K"`, `"50G"`, `"1.5TiB"`… Units are binary (K = 1024).
+pub fn parse_size(s: &str) -> Result<u64> {
+    let t = s.trim();
+    let split = t
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(t.len());
+    let (num, unit) = t.split_at(split);
+    let num: f64 = num
+        .parse()
+        .map_err(|_| FsError::InvalidOperation(format!("bad size {s:?}")))?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        "P" | "PB" | "PIB" => 50,
+        _ => return Err(FsError::InvalidOperation(format!("bad size unit in {s:?}"))),
+    };
+    Ok((num * (1u64 << shift) as f64) as u64)
+}
+
+/// Turn the `[quota]` table into rules, rejecting malformed keys.
+pub fn parse_rules(table: &BTreeMap<String, LimitSpec>) -> Result<Vec<QuotaRule>> {
+    table
+        .iter()
+        .map(|(key, spec)| {
+            let limit = match spec {
+                LimitSpec::Bytes(n) => *n,
+                LimitSpec::Human(s) => parse_size(s)?,
+            };
+            let target = if let Some(uid) = key.strip_prefix("uid") {
+                let uid = uid.trim_start_matches([' ', ':']);
+                QuotaTarget::Uid(uid.parse().map_err(|_| {
+                    FsError::InvalidOperation(format!("quota key {key:?}: bad uid"))
+                })?)
+            } else if key.starts_with('/') {
+                QuotaTarget::Dir(PathBuf::from(key))
+            } else {
+                return Err(FsError::InvalidOperation(format!(
+                    "quota key {key:?}: expected an absolute path or `uid <n>`"
+                )));
+            };
+            Ok(QuotaRule { target, limit })
+        })
+        .collect()
+}
+
+/// One line of `rhss quota report`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub target: String,
+    pub used: u64,
+    pub limit: u64,
+}
+
+/// Bytes charged ahead of a growing write; hand back to `Quotas::settle`.
+#[derive(Debug)]
+#[must_use]
+pub struct Reservation {
+    uid: u32,
+    before: u64,
+    charged: u64,
+}
+
+#[derive(Debug)]
+pub struct Quotas {
+    rules: Vec<QuotaRule>,
+    /// Bytes in use, parallel to `rules`. One lock so a check-and-charge
+    /// across several rules is atomic.
+    used: Mutex<Vec<u64>>,
+}
+
+impl Quotas {
+    pub fn new(rules: Vec<QuotaRule>) -> Arc<Self> {
+        let used = Mutex::new(vec![0; rules.len()]);
+        Arc::new(Self { rules, used })
+    }
+
+    /// Recount usage from scratch: every indexed file under a directory
+    /// rule, or every file when uid rules exist. Stats each file, so run it
+    /// at mount, not per request.
+    pub fn seed(&self, router: &TierRouter, index: &Arc<dyn PathIndex>) -> Result<()> {
+        let mut used = vec![0u64; self.rules.len()];
+        let has_uid = self
+            .rules
+            .iter()
+            .any(|r| matches!(r.target, QuotaTarget::Uid(_)));
+        let roots: Vec<&Path> = if has_uid {
+            vec![Path::new("/")]
+        } else {
+            self.rules
+                .iter()
+                .filter_map(|r| match &r.target {
+                    QuotaTarget::Dir(d) => Some(d.as_path()),
+                    QuotaTarget::Uid(_) => None,
+                })
+                .collect()
+        };
+        let mut seen = std::collections::HashSet::new();
+        for root in roots {
+            for row in index.list_under(root)? {
+                if !seen.insert(row.logical_path.clone()) {
+                    continue;
+                }
+                let Some(b) = router.resolve_backend(row.location.tier, &row.location.backend_id)
+                else {
+                    continue;
+                };
+                // Compressed files are immutable; the row holds the logical size.
+                let (size, uid) = if row.compressed {
+                    let meta = b.metadata(&compressed_path(&row.location.backend_path));
+                    (row.location.size, meta.map(|m| m.uid).unwrap_or(0))
+                } else {
+                    match b.metadata(&row.location.backend_path) {
+                        Ok(m) => (m.size, m.uid),
+                        Err(e) => {
+                            debug!("quota seed: {}: {e}", row.logical_path.display());
+                            continue;
+                        }
+                    }
+                };
+                for (i, r) in self.rules.iter().enumerate() {
+                    if r.covers(&row.logical_path, uid) {
+                        used[i] += size;
+                    }
+                }
+            }
+        }
+        *self.used.lock() = used;
+        Ok(())
+    }
+
+    /// Whether any rule could cover `logical`, whoever owns it; lets
+    /// callers skip the extra `stat` for unaffected files.
+    pub fn may_apply(&self, logical: &Path) -> bool {
+        self.rules.iter().any(|r| match &r.target {
+            QuotaTarget::Dir(d) => logical.starts_with(d),
+            QuotaTarget::Uid(_) => true,
+        })
+    }
+
+    /// Whether a directory rename can move bytes between rules.
+    pub fn affects_rename(&self, from: &Path, to: &Path) -> bool {
+        self.rules.iter().any(|r| match &r.target {
+            QuotaTarget::Dir(d) => from.starts_with(d) != to.starts_with(d) || d.starts_with(from),
+            QuotaTarget::Uid(_) => false,
+        })
+    }
+
+    fn exceeded(&self, rule: &QuotaRule) -> FsError {
+        FsError::QuotaExceeded(format!("{} (limit {} bytes)", rule.target, rule.limit))
+    }
+
+    /// Add `bytes` to every rule covering the file, or to none of them if
+    /// any would go over its limit.
+    pub fn charge(&self, logical: &Path, uid: u32, bytes: u64) -> Result<()> {
+        let mut used = self.used.lock();
+        for (i, r) in self.rules.iter().enumerate() {
+            if r.covers(logical, uid) && used[i].saturating_add(bytes) > r.limit {
+                return Err(self.exceeded(r));
+            }
+        }
+        for (i, r) in self.rules.iter().enumerate() {
+            if r.covers(logical, uid) {
+                used[i] += bytes;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn release(&self, logical: &Path, uid: u32, bytes: u64) {
+        let mut used = self.used.lock();
+        for (i, r) in self.rules.iter().enumerate() {
+            if r.covers(logical, uid) {
+                used[i] = used[i].saturating_sub(bytes);
+            }
+        }
+    }
+
+    /// Refuse new files where a covering rule is already full.
+    pub fn check_room(&self, logical: &Path, uid: u32) -> Result<()> {
+        let used = self.used.lock();
+        match self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(i, r)| r.covers(logical, uid) && used[*i] >= r.limit)
+        {
+            Some((_, r)) => Err(self.exceeded(r)),
+            None => Ok(()),
+        }
+    }
+
+    /// Charge for a file of `before` bytes growing to `end`. Nothing is
+    /// charged when `end` doesn't extend it.
+    pub fn reserve(&self, logical: &Path, uid: u32, before: u64, end: u64) -> Result<Reservation> {
+        let charged = end.saturating_sub(before);
+        if charged > 0 {
+            self.charge(logical, uid, charged)?;
+        }
+        Ok(Reservation {
+            uid,
+            before,
+            charged,
+        })
+    }
+
+    /// Refund whatever part of `r` the operation didn't use; `end` is where
+    /// it actually stopped (`None` when it failed outright).
+    pub fn settle(&self, logical: &Path, r: Reservation, end: Option<u64>) {
+        let used = end.map(|e| e.saturating_sub(r.before)).unwrap_or(0);
+        if used < r.charged {
+            self.release(logical, r.uid, r.charged - used);
+        }
+    }
+
+    /// Account a truncate from `before` to `after` bytes.
+    pub fn resize(&self, logical: &Path, uid: u32, before: u64, after: u64) -> Result<()> {
+        if after > before {
+            self.charge(logical, uid, after - before)
+        } else {
+            self.release(logical, uid, before - after);
+            Ok(())
+        }
+    }
+
+    /// Move a file's bytes between the rules of its old and new path.
+    /// Nothing changes if the destination has no room.
+    pub fn transfer(&self, from: &Path, to: &Path, uid: u32, bytes: u64) -> Result<()> {
+        let mut used = self.used.lock();
+        for (i, r) in self.rules.iter().enumerate() {
+            if r.covers(to, uid) && !r.covers(from, uid) && used[i].saturating_add(bytes) > r.limit
+            {
+                return Err(self.exceeded(r));
+            }
+        }
+        for (i, r) in self.rules.iter().enumerate() {
+            match (r.covers(from, uid), r.covers(to, uid)) {
+                (true, false) => used[i] = used[i].saturating_sub(bytes),
+                (false, true) => used[i] += bytes,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn report(&self) -> Vec<QuotaUsage> {
+        let used = self.used.lock();
+        self.rules
+            .iter()
+            .zip(used.iter())
+            .map(|(r, &used)| QuotaUsage {
+                target: r.target.to_string(),
+                used,
+                limit: r.limit,
+            })
+            .collect()
+    }
+
+    /// `seed`, logging instead of failing; for callers that must go on.
+    pub fn reseed(&self, router: &TierRouter, index: &Arc<dyn PathIndex>) {
+        if let Err(e) = self.seed(router, index) {
+            warn!("quota: recount usage: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quotas() -> Arc<Quotas> {
+        let mut t = BTreeMap::new();
+        t.insert("/projects/foo".to_string(), LimitSpec::Human("1K".into()));
+        t.insert("uid 1001".to_string(), LimitSpec::Bytes(1500));
+        Quotas::new(parse_rules(&t).unwrap())
+    }
+
+    #[test]
+    fn parses_sizes_and_keys() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("50G").unwrap(), 50 << 30);
+        assert_eq!(parse_size("1.5 KiB").unwrap(), 1536);
+        assert!(parse_size("12X").is_err());
+
+        let mut t = BTreeMap::new();
+        t.insert("uid:7".to_string(), LimitSpec::Bytes(1));
+        assert_eq!(parse_rules(&t).unwrap()[0].target, QuotaTarget::Uid(7));
+        t.insert("projects".to_string(), LimitSpec::Bytes(1));
+        assert!(parse_rules(&t).is_err());
+    }
+
+    #[test]
+    fn charge_is_all_or_nothing_across_rules() {
+        let q = quotas();
+        let foo = Path::new("/projects/foo/a");
+        q.charge(foo, 1001, 1000).unwrap();
+        // Fits the uid limit but not the directory's.
+        assert!(matches!(
+            q.charge(foo, 1001, 100),
+            Err(FsError::QuotaExceeded(_))
+        ));
+        // Elsewhere only the uid rule applies.
+        q.charge(Path::new("/other"), 1001, 500).unwrap();
+        assert!(q.charge(Path::new("/other"), 1001, 1).is_err());
+        assert!(q.check_room(Path::new("/other/new"), 1001).is_err());
+        q.charge(Path::new("/other"), 2000, 1 << 20).unwrap();
+
+        let report = q.report();
+        assert_eq!(report[0].used, 1000);
+        assert_eq!(report[1].used, 1500);
+
+        q.release(foo, 1001, 1000);
+        assert_eq!(q.report()[1].used, 500);
+    }
+
+    #[test]
+    fn reservation_refunds_unused_growth_and_rename_moves_bytes() {
+        let q = quotas();
+        let f = Path::new("/projects/foo/f");
+        let r = q.reserve(f, 0, 100, 600).unwrap();
+        q.settle(f, r, Some(300));
+        assert_eq!(q.report()[0].used, 200);
+
+        let r = q.reserve(f, 0, 300, 1000).unwrap();
+        q.settle(f, r, None);
+        assert_eq!(q.report()[0].used, 200);
+
+        q.transfer(f, Path::new("/tmp/f"), 0, 200).unwrap();
+        assert_eq!(q.report()[0].used, 0);
+        assert!(q.transfer(Path::new("/tmp/big"), f, 0, 2048).is_err());
+    }
+}
@@ -5,19 +5,56 @@
 //! no-op. Resumable: rows are inserted as we go; if we crash, the next run
 //! continues from where we left off.
 //!
-//! Conflicts (same logical path on multiple backends) **hard-fail** — see D13.
+//! Duplicates (same logical path on more than one backend, e.g. a copy
+//! left behind by an interrupted manual move) go through a
+//! `DuplicatePolicy`:
+//!
+//! - identical content (same size and sha256) is always resolved by
+//!   dropping the copy the index doesn't point at;
+//! - differing content is resolved by `prefer-newer` (mtime) or
+//!   `prefer-hot` (Fast > Slow > Archive), or reported as a conflict under
+//!   `error` (the default), which hard-fails the mount — see D13.
+//!
+//! The losing copy is deleted. Copies listed as mirror replicas are not
+//! duplicates. Dedup-linked rows (`content_hash` set) are never re-pointed,
+//! since their file may be a blob other rows share.
 
-use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
-use walkdir::WalkDir;
 
 use crate::backend::Backend;
 use crate::error::{FsError, Result};
+use crate::filter::PathFilter;
 use crate::index::{FileRow, FileState, Location, PathIndex, TierId};
 use crate::tier::TierRouter;
+use crate::tierer::hash_file;
+
+/// How `first_scan` settles a logical path found on two backends with
+/// different content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Keep the copy with the newer mtime.
+    PreferNewer,
+    /// Keep the copy on the hotter tier.
+    PreferHot,
+    /// Report a conflict and leave both copies alone.
+    #[default]
+    Error,
+}
+
+/// One duplicate `first_scan` cleaned up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    pub logical: PathBuf,
+    /// `tier:backend` of the surviving copy.
+    pub kept: String,
+    pub removed: String,
+    pub identical: bool,
+}
 
 /// Stats reported back to the caller for logging / UI.
 #[derive(Debug, Default, Clone)]
@@ -25,20 +62,20 @@ pub struct ScanStats {
     pub indexed: u64,
     pub skipped_existing: u64,
     pub conflicts: Vec<PathBuf>,
+    pub resolved: Vec<Resolution>,
 }
 
 /// Run a single full scan over both tiers.
 ///
-/// Conflicts are collected and returned in `ScanStats.conflicts`. If any are
+/// Unresolved duplicates are returned in `ScanStats.conflicts`. If any are
 /// present the caller MUST treat this as a hard failure and abort the mount.
-pub fn first_scan(router: &TierRouter, index: &Arc<dyn PathIndex>) -> Result<ScanStats> {
+pub fn first_scan(
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+    policy: DuplicatePolicy,
+) -> Result<ScanStats> {
     let mut stats = ScanStats::default();
 
-    // Map of logical_path -> (tier, backend_id) we've claimed during THIS scan
-    // (in addition to any already-indexed). This is how we detect cross-backend
-    // conflicts cleanly.
-    let mut claimed: HashMap<PathBuf, (TierId, String)> = HashMap::new();
-
     for (tier_id, backend) in router.all_backends() {
         info!(
             tier = ?tier_id,
@@ -46,7 +83,7 @@ pub fn first_scan(router: &TierRouter, index: &Arc<dyn PathIndex>) -> Result<Sca
             root = %backend.root().display(),
             "scanning backend"
         );
-        scan_one(backend, tier_id, index, &mut claimed, &mut stats)?;
+        scan_one(router, backend, tier_id, index, policy, &mut stats)?;
     }
 
     if !stats.conflicts.is_empty() {
@@ -55,55 +92,43 @@ pub fn first_scan(router: &TierRouter, index: &Arc<dyn PathIndex>) -> Result<Sca
             "first-scan: cross-backend logical-path conflicts"
         );
     }
-    info!(indexed = stats.indexed, skipped = stats.skipped_existing, "scan complete");
+    info!(
+        indexed = stats.indexed,
+        skipped = stats.skipped_existing,
+        duplicates_removed = stats.resolved.len(),
+        "scan complete"
+    );
     Ok(stats)
 }
 
 fn scan_one(
+    router: &TierRouter,
     backend: &Arc<dyn Backend>,
     tier: TierId,
     index: &Arc<dyn PathIndex>,
-    claimed: &mut HashMap<PathBuf, (TierId, String)>,
+    policy: DuplicatePolicy,
     stats: &mut ScanStats,
 ) -> Result<()> {
-    let root = backend.root().to_path_buf();
-    for entry in WalkDir::new(&root).follow_links(false).into_iter() {
-        let entry = entry.map_err(|e| FsError::Storage(format!("walk: {e}")))?;
-        if entry.file_type().is_dir() {
-            continue;
-        }
-        if !entry.file_type().is_file() {
-            // skip symlinks / sockets / etc.
+    let reserved = PathFilter::reserved();
+    // Regular files only: symlinks / sockets / etc. are skipped.
+    for rel in backend.walk_files() {
+        let rel = rel?;
+        let logical = PathBuf::from("/").join(&rel);
+        if reserved.excludes("scan", &logical) {
             continue;
         }
 
-        let abs = entry.path();
-        let rel = match abs.strip_prefix(&root) {
-            Ok(r) => r.to_path_buf(),
-            Err(_) => continue,
-        };
-        let logical = PathBuf::from("/").join(&rel);
-
-        // Conflict detection: did another backend already register this logical
-        // path during THIS scan?
-        if let Some((other_tier, other_id)) = claimed.get(&logical) {
-            if other_id != backend.id() {
-                warn!(
-                    logical = %logical.display(),
-                    a = %format!("{:?}:{}", other_tier, other_id),
-                    b = %format!("{:?}:{}", tier, backend.id()),
-                    "conflict during scan"
-                );
-                stats.conflicts.push(logical.clone());
-                continue;
+        // Rows are inserted as we go, so the index also knows about paths
+        // claimed earlier in THIS scan.
+        if let Some(row) = index.get(&logical)? {
+            let ours = row.location.backend_id == backend.id()
+                || row.replicas.iter().any(|r| r.backend_id == backend.id());
+            if ours {
+                // A prior run already indexed this copy (idempotent).
+                stats.skipped_existing += 1;
+            } else {
+                resolve_duplicate(router, index, row, tier, backend, &rel, policy, stats)?;
             }
-        }
-
-        // Did a prior run already index this path? If so, leave it alone
-        // (idempotent). The previous run wrote its tier+backend; trust it.
-        if index.locate(&logical)?.is_some() {
-            claimed.insert(logical.clone(), (tier, backend.id().to_string()));
-            stats.skipped_existing += 1;
             continue;
         }
 
@@ -124,18 +149,129 @@ fn scan_one(
             hit_count: 0,
             popularity: 0.0,
             pinned_tier: None,
+            pinned_until: None,
             state: FileState::Stable,
             mutability: crate::index::Mutability::Unknown,
             compressed: false,
             content_hash: None,
         };
         index.insert(row)?;
-        claimed.insert(logical, (tier, backend.id().to_string()));
         stats.indexed += 1;
     }
     Ok(())
 }
 
+/// Fast = 2, Slow = 1, Archive = 0.
+fn heat(tier: TierId) -> u8 {
+    match tier {
+        TierId::Fast => 2,
+        TierId::Slow => 1,
+        TierId::Archive => 0,
+    }
+}
+
+/// `row` is indexed on another backend; `rel` on `backend` is a second
+/// copy. Delete whichever copy loses under `policy`.
+#[allow(clippy::too_many_arguments)]
+fn resolve_duplicate(
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+    row: FileRow,
+    tier: TierId,
+    backend: &Arc<dyn Backend>,
+    rel: &Path,
+    policy: DuplicatePolicy,
+    stats: &mut ScanStats,
+) -> Result<()> {
+    let logical = row.logical_path.clone();
+    let held_loc = &row.location;
+    let here = format!("{}:{}", tier.as_str(), backend.id());
+    let there = format!("{}:{}", held_loc.tier.as_str(), held_loc.backend_id);
+    let conflict = |stats: &mut ScanStats| {
+        warn!(
+            logical = %logical.display(),
+            a = %there,
+            b = %here,
+            "conflict during scan"
+        );
+        stats.conflicts.push(logical.clone());
+    };
+    let Some(held) = router.resolve_backend(held_loc.tier, &held_loc.backend_id) else {
+        conflict(stats);
+        return Ok(());
+    };
+    let this_meta = backend.metadata(rel)?;
+    // `None`: the indexed copy is gone and this one is all that's left.
+    let held_meta = match held.metadata(&held_loc.backend_path) {
+        Ok(m) => Some(m),
+        Err(FsError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+    let identical = match &held_meta {
+        Some(m) => {
+            !row.compressed
+                && m.size == this_meta.size
+                && hash_file(backend, rel)? == hash_file(held, &held_loc.backend_path)?
+        }
+        None => false,
+    };
+    let keep_this = match held_meta {
+        None => true,
+        Some(_) if identical => false,
+        Some(m) => match policy {
+            DuplicatePolicy::PreferNewer => this_meta.mtime > m.mtime,
+            DuplicatePolicy::PreferHot => heat(tier) > heat(held_loc.tier),
+            DuplicatePolicy::Error => {
+                conflict(stats);
+                return Ok(());
+            }
+        },
+    };
+    if keep_this && row.content_hash.is_some() {
+        conflict(stats);
+        return Ok(());
+    }
+
+    let (kept, removed) = if keep_this {
+        let _ = held.remove(&held_loc.backend_path);
+        for r in &row.replicas {
+            if let Some(b) = router.resolve_backend(held_loc.tier, &r.backend_id) {
+                let _ = b.remove(&r.backend_path);
+            }
+        }
+        index.insert(FileRow {
+            location: Location {
+                tier,
+                backend_id: backend.id().to_string(),
+                backend_path: rel.to_path_buf(),
+                size: this_meta.size,
+            },
+            replicas: Vec::new(),
+            compressed: false,
+            content_hash: None,
+            ..row
+        })?;
+        (here, there)
+    } else {
+        backend.remove(rel)?;
+        (there, here)
+    };
+    warn!(
+        logical = %logical.display(),
+        kept = %kept,
+        removed = %removed,
+        identical,
+        "duplicate resolved"
+    );
+    stats.resolved.push(Resolution {
+        logical,
+        kept,
+        removed,
+        identical,
+    });
+    Ok(())
+}
+
 /// Verify and prepare backend root directories. Creates `.rhss_managed/` if
 /// missing. Returns an error if any root cannot be created.
 pub fn ensure_managed_dirs(roots: impl IntoIterator<Item = impl AsRef<Path>>) -> Result<()> {
@@ -162,10 +298,7 @@ mod tests {
     use std::sync::Arc;
     use tempfile::TempDir;
 
-    fn make_router(
-        fast_roots: &[&Path],
-        slow_roots: &[&Path],
-    ) -> TierRouter {
+    fn make_router(fast_roots: &[&Path], slow_roots: &[&Path]) -> TierRouter {
         let fast: Vec<Arc<dyn Backend>> = fast_roots
             .iter()
             .enumerate()
@@ -199,9 +332,8 @@ mod tests {
         std::fs::write(hdd.path().join("dir/b.bin"), b"bytes").unwrap();
 
         let router = make_router(&[ssd.path()], &[hdd.path()]);
-        let index = SqlitePathIndex::open(db.path().join("idx.db")).unwrap()
-            as Arc<dyn PathIndex>;
-        let stats = first_scan(&router, &index).unwrap();
+        let index = SqlitePathIndex::open(db.path().join("idx.db")).unwrap() as Arc<dyn PathIndex>;
+        let stats = first_scan(&router, &index, DuplicatePolicy::Error).unwrap();
         assert_eq!(stats.indexed, 2);
         assert!(stats.conflicts.is_empty());
 
@@ -223,13 +355,69 @@ mod tests {
         std::fs::write(ssd_b.path().join("dup"), b"b").unwrap();
 
         let router = make_router(&[ssd_a.path(), ssd_b.path()], &[hdd.path()]);
-        let index = SqlitePathIndex::open(db.path().join("idx.db")).unwrap()
-            as Arc<dyn PathIndex>;
-        let stats = first_scan(&router, &index).unwrap();
+        let index = SqlitePathIndex::open(db.path().join("idx.db")).unwrap() as Arc<dyn PathIndex>;
+        let stats = first_scan(&router, &index, DuplicatePolicy::Error).unwrap();
         assert_eq!(stats.conflicts.len(), 1);
         assert_eq!(stats.conflicts[0], Path::new("/dup"));
     }
 
+    fn scan_dup(
+        policy: DuplicatePolicy,
+        fast: &[u8],
+        slow: &[u8],
+    ) -> ([TempDir; 3], ScanStats, Arc<dyn PathIndex>) {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+        std::fs::write(ssd.path().join("dup"), fast).unwrap();
+        std::fs::write(hdd.path().join("dup"), slow).unwrap();
+        // Make the slow copy strictly newer.
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(hdd.path().join("dup"))
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let router = make_router(&[ssd.path()], &[hdd.path()]);
+        let index = SqlitePathIndex::open(db.path().join("idx.db")).unwrap() as Arc<dyn PathIndex>;
+        let stats = first_scan(&router, &index, policy).unwrap();
+        ([ssd, hdd, db], stats, index)
+    }
+
+    #[test]
+    fn identical_duplicate_drops_unindexed_copy_under_any_policy() {
+        let ([ssd, hdd, _db], stats, index) = scan_dup(DuplicatePolicy::Error, b"same", b"same");
+        assert!(stats.conflicts.is_empty());
+        assert_eq!(stats.resolved.len(), 1);
+        assert!(stats.resolved[0].identical);
+        assert!(ssd.path().join("dup").exists());
+        assert!(!hdd.path().join("dup").exists());
+        let row = index.get(Path::new("/dup")).unwrap().unwrap();
+        assert_eq!(row.location.backend_id, "ssd-0");
+    }
+
+    #[test]
+    fn divergent_duplicate_follows_policy() {
+        let ([ssd, hdd, _db], stats, index) =
+            scan_dup(DuplicatePolicy::PreferNewer, b"old", b"newer");
+        assert_eq!(stats.resolved[0].kept, "slow:hdd-0");
+        assert!(!ssd.path().join("dup").exists());
+        assert_eq!(std::fs::read(hdd.path().join("dup")).unwrap(), b"newer");
+        let row = index.get(Path::new("/dup")).unwrap().unwrap();
+        assert_eq!(row.location.backend_id, "hdd-0");
+        assert_eq!(row.location.size, 5);
+
+        let ([ssd, hdd, _db], stats, _) = scan_dup(DuplicatePolicy::PreferHot, b"old", b"newer");
+        assert_eq!(stats.resolved[0].kept, "fast:ssd-0");
+        assert!(ssd.path().join("dup").exists());
+        assert!(!hdd.path().join("dup").exists());
+
+        let ([ssd, hdd, _db], stats, _) = scan_dup(DuplicatePolicy::Error, b"old", b"newer");
+        assert_eq!(stats.conflicts, vec![PathBuf::from("/dup")]);
+        assert!(ssd.path().join("dup").exists() && hdd.path().join("dup").exists());
+    }
+
     #[test]
     fn idempotent_rescan_no_duplicates() {
         let ssd = TempDir::new().unwrap();
@@ -239,13 +427,29 @@ mod tests {
         std::fs::write(ssd.path().join("x"), b"hi").unwrap();
 
         let router = make_router(&[ssd.path()], &[hdd.path()]);
-        let index = SqlitePathIndex::open(db.path().join("idx.db")).unwrap()
-            as Arc<dyn PathIndex>;
-        let s1 = first_scan(&router, &index).unwrap();
-        let s2 = first_scan(&router, &index).unwrap();
+        let index = SqlitePathIndex::open(db.path().join("idx.db")).unwrap() as Arc<dyn PathIndex>;
+        let s1 = first_scan(&router, &index, DuplicatePolicy::Error).unwrap();
+        let s2 = first_scan(&router, &index, DuplicatePolicy::Error).unwrap();
         assert_eq!(s1.indexed, 1);
         assert_eq!(s2.indexed, 0);
         assert_eq!(s2.skipped_existing, 1);
         assert_eq!(index.count().unwrap(), 1);
     }
+
+    #[test]
+    fn skips_decompression_staging() {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+
+        std::fs::write(ssd.path().join("real"), b"hi").unwrap();
+        std::fs::create_dir_all(ssd.path().join(".rhss_decompressed/d")).unwrap();
+        std::fs::write(ssd.path().join(".rhss_decompressed/d/big.bin"), b"x").unwrap();
+
+        let router = make_router(&[ssd.path()], &[hdd.path()]);
+        let index = SqlitePathIndex::open(db.path().join("idx.db")).unwrap() as Arc<dyn PathIndex>;
+        let stats = first_scan(&router, &index, DuplicatePolicy::Error).unwrap();
+        assert_eq!(stats.indexed, 1);
+        assert!(index.locate(Path::new("/real")).unwrap().is_some());
+    }
 }
@@ -0,0 +1,505 @@
+//! Offline replay of a workload against a tiering policy: `rhss simulate`.
+//!
+//! Nothing is read or written on disk. Files exist only as rows in an
+//! in-memory index whose clock (`MockClock`) jumps from event to event, so
+//! a month of trace replays in seconds and the same trace and policy always
+//! give the same report. Victims are picked by the real `coldest` query and
+//! the real watermark arithmetic (`crate::tierer::bytes_to_free`), and new
+//! files are placed by the policy's own `tier_for_create`; what is modelled
+//! is only the byte accounting of the fast tier, whose size is given.
+//!
+//! A pass of the tierer runs every `tier_period`, and also, as a write
+//! hitting ENOSPC would trigger one, whenever a file doesn't fit on the
+//! fast tier. New files go to the slow tier above the panic watermark or
+//! when they still don't fit after that (`spilled`); a file that can't
+//! grow fails the write (`enospc`). The slow tier never fills up, and
+//! nothing is archived.
+//!
+//! A workload is a script, one event per line:
+//!
+//! ```text
+//! # seconds  op      path               size
+//! 0          create  /media/a.mkv       4G
+//! 30         read    /media/a.mkv
+//! 3600       write   /db/wal            64M
+//! 7200       delete  /media/a.mkv
+//! ```
+//!
+//! `size` is the file's size after a `create`, and the size a `write`
+//! grows it to at least (writes never shrink a file); reads of a file the
+//! trace never created bring it in as if it had been there, placed as a
+//! new file would be (`seeded`), at the read's `size`.
+//!
+//! `tune` searches watermarks and ages for the policy that keeps the fast
+//! tier under a target usage: `rhss tune`.
+//!
+//! A trace recorded by a mount (`crate::access::trace`) replays the same
+//! way, through `from_records`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+pub use crate::access::trace::TraceOp;
+
+use crate::access::trace::TraceRecord;
+use crate::clock::{Clock, MockClock};
+use crate::config::PolicyOptions;
+use crate::error::{FsError, Result};
+use crate::index::{FileRow, FileState, Location, Mutability, PathIndex, SqlitePathIndex, TierId};
+use crate::policy::{PopularityPolicy, TieringPolicy};
+use crate::quota::parse_size;
+
+/// Where the simulated clock starts; any fixed point keeps runs repeatable.
+const EPOCH: Duration = Duration::from_secs(1_700_000_000);
+
+/// One event of a workload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceEvent {
+    /// Since the start of the trace.
+    pub at: Duration,
+    pub op: TraceOp,
+    pub path: PathBuf,
+    /// See the module docs; 0 when not known.
+    pub size: u64,
+}
+
+/// A recorded trace as events, timed from its first record.
+pub fn from_records(records: &[TraceRecord]) -> Vec<TraceEvent> {
+    let start = records.iter().map(|r| r.ts_ms).min().unwrap_or(0);
+    let mut out: Vec<TraceEvent> = records
+        .iter()
+        .map(|r| TraceEvent {
+            at: Duration::from_millis(r.ts_ms - start),
+            op: r.op,
+            path: r.path.clone(),
+            size: r.size,
+        })
+        .collect();
+    out.sort_by_key(|e| e.at);
+    out
+}
+
+/// Parse a workload script (see the module docs), in time order.
+pub fn parse_script(text: &str) -> Result<Vec<TraceEvent>> {
+    let mut out = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bad = |what: String| FsError::InvalidOperation(format!("line {}: {what}", n + 1));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (at, op, path, size) = match fields[..] {
+            [at, op, path] => (at, op, path, None),
+            [at, op, path, size] => (at, op, path, Some(size)),
+            _ => return Err(bad("expected `<seconds> <op> <path> [size]`".into())),
+        };
+        let at: f64 = at
+            .parse()
+            .ok()
+            .filter(|s: &f64| s.is_finite() && *s >= 0.0)
+            .ok_or_else(|| bad(format!("bad time {at:?}")))?;
+        if !path.starts_with('/') {
+            return Err(bad(format!("path {path:?} is not absolute")));
+        }
+        out.push(TraceEvent {
+            at: Duration::from_secs_f64(at),
+            op: op.parse().map_err(|e: FsError| bad(e.to_string()))?,
+            path: PathBuf::from(path),
+            size: match size {
+                Some(s) => parse_size(s).map_err(|e| bad(e.to_string()))?,
+                None => 0,
+            },
+        });
+    }
+    out.sort_by_key(|e| e.at);
+    Ok(out)
+}
+
+/// What to replay a trace against.
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub fast_capacity: u64,
+    pub policy: PolicyOptions,
+}
+
+/// Outcome of one replay.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SimReport {
+    pub events: u64,
+    pub reads: u64,
+    /// Reads served by the fast tier.
+    pub fast_hits: u64,
+    pub fast_bytes_read: u64,
+    pub slow_bytes_read: u64,
+    /// Tierer passes, periodic and on a full fast tier.
+    pub passes: u64,
+    /// Files demoted, and their bytes.
+    pub migrations: u64,
+    pub migrated_bytes: u64,
+    /// Reads of files the trace didn't create.
+    pub seeded: u64,
+    /// New files put on the slow tier because the fast one was (nearly)
+    /// full.
+    pub spilled: u64,
+    /// Writes that found no room on the fast tier even after a pass.
+    pub enospc: u64,
+    pub peak_fast_usage: f64,
+    pub final_fast_usage: f64,
+}
+
+impl SimReport {
+    /// Fraction of reads served by the fast tier; 0 without reads.
+    pub fn hit_rate(&self) -> f64 {
+        if self.reads == 0 {
+            0.0
+        } else {
+            self.fast_hits as f64 / self.reads as f64
+        }
+    }
+}
+
+/// Replay `trace` (in time order) against `cfg`.
+pub fn run(trace: &[TraceEvent], cfg: &SimConfig) -> Result<SimReport> {
+    cfg.policy.validate()?;
+    if cfg.fast_capacity == 0 {
+        return Err(FsError::InvalidOperation(
+            "fast tier capacity must be above 0".into(),
+        ));
+    }
+    let start = UNIX_EPOCH + EPOCH;
+    let clock = Arc::new(MockClock::new(start));
+    let mut sim = Sim {
+        index: SqlitePathIndex::open_with_clock(":memory:", clock.clone())?,
+        clock,
+        policy: cfg.policy.to_policy(),
+        capacity: cfg.fast_capacity,
+        used: 0,
+        report: SimReport::default(),
+    };
+    let mut next_pass = sim.policy.tier_period;
+    for ev in trace {
+        while let Some(at) = next_pass.filter(|t| *t <= ev.at) {
+            sim.clock.set(start + at);
+            sim.pass()?;
+            next_pass = sim
+                .policy
+                .tier_period
+                .map(|p| at + p.max(Duration::from_secs(1)));
+        }
+        sim.clock.set(start + ev.at);
+        sim.apply(ev)?;
+    }
+    sim.report.final_fast_usage = sim.usage();
+    Ok(sim.report)
+}
+
+/// Minimum ages before eviction `tune` tries, besides the base policy's.
+const TUNE_AGES: [u64; 4] = [0, 600, 3600, 86_400];
+
+/// What `tune` settled on.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tuning {
+    pub policy: PolicyOptions,
+    pub report: SimReport,
+    /// Whether the fast tier stayed at or under the target throughout. If
+    /// no policy manages, this is the one that came closest.
+    pub meets_target: bool,
+    /// Policies replayed.
+    pub tried: usize,
+}
+
+/// The watermarks and minimum age, on top of `base`, that keep the fast
+/// tier's usage at or under `target` over `trace` with the best hit rate
+/// (then the fewest migrated bytes). The panic watermark is set to
+/// `target` so new files never push usage past it.
+pub fn tune(
+    trace: &[TraceEvent],
+    fast_capacity: u64,
+    base: &PolicyOptions,
+    target: f64,
+) -> Result<Tuning> {
+    if !(target > 0.0 && target <= 1.0) {
+        return Err(FsError::InvalidOperation(format!(
+            "target usage {target} is not within (0, 1]"
+        )));
+    }
+    let mut ages: Vec<u64> = TUNE_AGES.to_vec();
+    ages.extend(base.min_age_to_evict_secs);
+    ages.sort_unstable();
+    ages.dedup();
+
+    // Whole percents, so the policy reads well in a config.
+    let pct = |v: f64| (v * 100.0).round() / 100.0;
+    let mut best: Option<Tuning> = None;
+    let mut tried = 0;
+    for high in [target, target - 0.05, target - 0.1].map(pct) {
+        for gap in [0.05, 0.1, 0.2] {
+            let low = pct(high - gap);
+            if low < 0.0 {
+                continue;
+            }
+            for age in &ages {
+                let policy = PolicyOptions {
+                    low_watermark: Some(low),
+                    high_watermark: Some(high),
+                    panic_watermark: Some(target),
+                    min_age_to_evict_secs: Some(*age),
+                    ..base.clone()
+                };
+                let report = run(
+                    trace,
+                    &SimConfig {
+                        fast_capacity,
+                        policy: policy.clone(),
+                    },
+                )?;
+                tried += 1;
+                let cand = Tuning {
+                    meets_target: report.peak_fast_usage <= target + 1e-9 && report.enospc == 0,
+                    policy,
+                    report,
+                    tried: 0,
+                };
+                if best.as_ref().is_none_or(|b| better(&cand, b)) {
+                    best = Some(cand);
+                }
+            }
+        }
+    }
+    let mut best = best.ok_or_else(|| {
+        FsError::InvalidOperation(format!("target usage {target} leaves no room to tune"))
+    })?;
+    best.tried = tried;
+    Ok(best)
+}
+
+fn better(a: &Tuning, b: &Tuning) -> bool {
+    if a.meets_target != b.meets_target {
+        return a.meets_target;
+    }
+    if !a.meets_target {
+        return a.report.peak_fast_usage < b.report.peak_fast_usage;
+    }
+    let (ha, hb) = (a.report.hit_rate(), b.report.hit_rate());
+    if ha != hb {
+        return ha > hb;
+    }
+    a.report.migrated_bytes < b.report.migrated_bytes
+}
+
+struct Sim {
+    index: Arc<SqlitePathIndex>,
+    clock: Arc<MockClock>,
+    policy: PopularityPolicy,
+    capacity: u64,
+    /// Bytes on the fast tier.
+    used: u64,
+    report: SimReport,
+}
+
+impl Sim {
+    fn usage(&self) -> f64 {
+        self.used as f64 / self.capacity as f64
+    }
+
+    fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    fn apply(&mut self, ev: &TraceEvent) -> Result<()> {
+        self.report.events += 1;
+        let row = self.index.get(&ev.path)?;
+        match (ev.op, row) {
+            (TraceOp::Read, Some(row)) => self.read(&row)?,
+            (TraceOp::Read, None) => {
+                self.report.seeded += 1;
+                let row = self.create(&ev.path, ev.size)?;
+                self.read(&row)?;
+            }
+            (TraceOp::Create | TraceOp::Write, None) => {
+                self.create(&ev.path, ev.size)?;
+            }
+            (TraceOp::Create, Some(row)) => self.resize(&row, ev.size)?,
+            (TraceOp::Write, Some(row)) => {
+                let size = row.location.size.max(ev.size);
+                self.resize(&row, size)?
+            }
+            (TraceOp::Delete, Some(row)) => {
+                if row.location.tier == TierId::Fast {
+                    self.used -= row.location.size;
+                }
+                self.index.remove(&ev.path)?;
+            }
+            (TraceOp::Delete, None) => {}
+        }
+        self.report.peak_fast_usage = self.report.peak_fast_usage.max(self.usage());
+        Ok(())
+    }
+
+    fn read(&mut self, row: &FileRow) -> Result<()> {
+        self.report.reads += 1;
+        if row.location.tier == TierId::Fast {
+            self.report.fast_hits += 1;
+            self.report.fast_bytes_read += row.location.size;
+        } else {
+            self.report.slow_bytes_read += row.location.size;
+        }
+        self.index.record_access(&row.logical_path, self.now(), 1)
+    }
+
+    /// Whether `more` bytes fit on the fast tier, after a pass if needed.
+    fn make_room(&mut self, more: u64) -> Result<bool> {
+        if self.used + more <= self.capacity {
+            return Ok(true);
+        }
+        self.pass()?;
+        Ok(self.used + more <= self.capacity)
+    }
+
+    fn create(&mut self, path: &Path, size: u64) -> Result<FileRow> {
+        let mut tier = self.policy.tier_for_create(self.usage());
+        if tier == TierId::Fast && !self.make_room(size)? {
+            tier = TierId::Slow;
+        }
+        if tier == TierId::Fast {
+            self.used += size;
+        } else {
+            self.report.spilled += 1;
+        }
+        let row = FileRow {
+            logical_path: path.to_path_buf(),
+            location: location(path, tier, size),
+            replicas: Vec::new(),
+            last_access: self.now(),
+            hit_count: 0,
+            popularity: self.policy.initial_popularity(),
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            mutability: Mutability::Unknown,
+            compressed: false,
+            content_hash: None,
+        };
+        self.index.insert(row.clone())?;
+        Ok(row)
+    }
+
+    fn resize(&mut self, row: &FileRow, size: u64) -> Result<()> {
+        let old = row.location.size;
+        if row.location.tier == TierId::Fast && size > old {
+            if !self.make_room(size - old)? {
+                self.report.enospc += 1;
+                return Ok(());
+            }
+            // The pass may have moved this very file.
+            if self.index.locate(&row.logical_path)?.map(|l| l.tier) == Some(TierId::Fast) {
+                self.used += size - old;
+            }
+        } else if row.location.tier == TierId::Fast {
+            self.used -= old - size;
+        }
+        self.index.set_size(&row.logical_path, size)?;
+        self.index.record_access(&row.logical_path, self.now(), 1)
+    }
+
+    /// One Fast → Slow eviction chain, as `crate::tierer` runs it.
+    fn pass(&mut self) -> Result<()> {
+        self.report.passes += 1;
+        let p = self.policy;
+        if self.usage() <= p.low_watermark {
+            return Ok(());
+        }
+        let to_free = crate::tierer::bytes_to_free(
+            self.capacity,
+            self.used,
+            p.low_watermark,
+            p.high_watermark,
+        );
+        if to_free == 0 {
+            return Ok(());
+        }
+        for (path, size) in self
+            .index
+            .coldest(TierId::Fast, to_free, p.min_age_to_evict)?
+        {
+            self.index
+                .swap_location(&path, location(&path, TierId::Slow, size))?;
+            self.used -= size;
+            self.report.migrations += 1;
+            self.report.migrated_bytes += size;
+        }
+        Ok(())
+    }
+}
+
+fn location(path: &Path, tier: TierId, size: u64) -> Location {
+    Location {
+        tier,
+        backend_id: tier.as_str().into(),
+        backend_path: path.strip_prefix("/").unwrap_or(path).to_path_buf(),
+        size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(high: f64, min_age_secs: u64) -> SimConfig {
+        SimConfig {
+            fast_capacity: 1000,
+            policy: PolicyOptions {
+                low_watermark: Some(0.5),
+                high_watermark: Some(high),
+                panic_watermark: Some(0.95),
+                tier_period_secs: Some(60),
+                min_age_to_evict_secs: Some(min_age_secs),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn replays_a_script_against_the_policy() {
+        // A hot file read all along, and a stream of files read once.
+        let mut script = String::from("0 create /hot 200\n");
+        for i in 0..20 {
+            let t = 10 + i * 30;
+            script += &format!("{t} create /cold/{i} 100\n{} read /hot\n", t + 5);
+        }
+        script += "700 read /cold/0\n701 read /hot  # comment\n702 delete /cold/19\n";
+        let trace = parse_script(&script).unwrap();
+        assert_eq!(trace.len(), 44);
+
+        let r = run(&trace, &config(0.85, 120)).unwrap();
+        assert_eq!(r, run(&trace, &config(0.85, 120)).unwrap(), "deterministic");
+        assert_eq!(r.reads, 22);
+        // The hot file stays, the first cold one is long gone.
+        assert_eq!(r.fast_hits, 21);
+        assert!(r.migrations > 0);
+        assert_eq!(r.migrated_bytes, r.migrations * 100);
+        assert!(r.peak_fast_usage <= 1.0);
+        assert_eq!((r.spilled, r.enospc, r.seeded), (0, 0, 0));
+
+        // Files may not be demoted younger than the whole trace: the fast
+        // tier fills up and new files spill.
+        let r = run(&trace, &config(0.85, 3600)).unwrap();
+        assert_eq!(r.migrations, 0);
+        assert!(r.spilled > 0);
+
+        let t = tune(&trace, 1000, &config(0.85, 120).policy, 0.7).unwrap();
+        assert!(t.meets_target, "{t:?}");
+        assert!(t.report.peak_fast_usage <= 0.7);
+        assert_eq!(t.policy.panic_watermark, Some(0.7));
+        assert!(t.tried > 1);
+        assert!(tune(&trace, 1000, &PolicyOptions::default(), 1.5).is_err());
+
+        for bad in ["x create /a", "1 copy /a", "1 read a", "1 write /a 3Q"] {
+            assert!(parse_script(bad).is_err(), "{bad}");
+        }
+    }
+}
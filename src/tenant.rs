@@ -0,0 +1,444 @@
+//! Tenants: top-level directories of one mount handed to separate teams.
+//!
+//! Configured under `[tenants.<name>]`:
+//!
+//! ```toml
+//! [tenants.research]
+//! dir = "/research"      # default "/<name>"
+//! gid = 2001             # only this group (and root) gets in
+//! quota = "10T"
+//! fast_limit = "500G"    # new files go to slow past this much on fast
+//! # create_tier = "slow" # never start files on fast
+//! min_age_to_evict_secs = 604800  # a week untouched before leaving fast
+//! # min_age_to_archive_secs = ... # likewise for slow to archive
+//! ```
+//!
+//! Isolation is POSIX permissions, applied by `prepare` at mount: the
+//! tenant directory exists on every backend with group `gid` and mode
+//! 2770, and the kernel refuses everyone outside the group the way into
+//! it. That takes `[fuse] default_permissions` (the default), which the
+//! config check insists on for tenants with a `gid`. The quota is an
+//! ordinary directory rule merged into `[quota]`
+//! (`RhssConfig::quota_rules`). Placement applies where files are
+//! created, in the FUSE adapter and in `Namespace`. The tierer still
+//! evicts from one list, coldest first, so a tenant's cold files make room
+//! for everyone's hot ones, but each tenant's own minimum ages replace the
+//! `[policy]` ones for its files (`TenantPolicy`). rhss has no at-rest
+//! encryption, so there are no per-tenant keys; encrypt the backing disks
+//! per tenant if that is needed.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::config::TenantOptions;
+use crate::cost::TierRates;
+use crate::error::{FsError, Result};
+use crate::index::{PathIndex, TierId};
+use crate::policy::TieringPolicy;
+use crate::quota::{self, LimitSpec, QuotaRule, QuotaTarget};
+use crate::tier::TierRouter;
+
+/// Mode of a tenant directory with a `gid`: group-only, setgid so new
+/// entries stay in the group.
+pub const TENANT_DIR_MODE: u32 = 0o2770;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tenant {
+    pub name: String,
+    pub dir: PathBuf,
+    pub gid: Option<u32>,
+    pub quota: Option<u64>,
+    pub fast_limit: Option<u64>,
+    pub create_tier: Option<TierId>,
+    /// Replaces `[policy] min_age_to_evict_secs` for the tenant's files.
+    pub min_age_to_evict: Option<Duration>,
+    /// Replaces `[policy] min_age_to_archive_secs` for the tenant's files.
+    pub min_age_to_archive: Option<Duration>,
+}
+
+/// Turn `[tenants]` into tenants, rejecting nested or shared directories.
+pub fn parse(table: &BTreeMap<String, TenantOptions>) -> Result<Vec<Tenant>> {
+    let size = |spec: &Option<LimitSpec>| -> Result<Option<u64>> {
+        match spec {
+            None => Ok(None),
+            Some(LimitSpec::Bytes(n)) => Ok(Some(*n)),
+            Some(LimitSpec::Human(s)) => quota::parse_size(s).map(Some),
+        }
+    };
+    let mut dirs = HashSet::new();
+    let mut out = Vec::new();
+    for (name, opts) in table {
+        let bad = |why: String| FsError::InvalidOperation(format!("tenant {name:?}: {why}"));
+        let dir = opts
+            .dir
+            .clone()
+            .unwrap_or_else(|| Path::new("/").join(name));
+        let mut parts = dir.components();
+        if parts.next() != Some(Component::RootDir)
+            || !matches!(parts.next(), Some(Component::Normal(_)))
+            || parts.next().is_some()
+        {
+            return Err(bad(format!(
+                "dir must be a top-level directory like /{name}, got {}",
+                dir.display()
+            )));
+        }
+        if !dirs.insert(dir.clone()) {
+            return Err(bad(format!("{} belongs to another tenant", dir.display())));
+        }
+        let create_tier = match opts.create_tier.as_deref() {
+            None => None,
+            Some("fast") => Some(TierId::Fast),
+            Some("slow") => Some(TierId::Slow),
+            Some(other) => {
+                return Err(bad(format!(
+                    "create_tier must be \"fast\" or \"slow\", got {other:?}"
+                )))
+            }
+        };
+        out.push(Tenant {
+            name: name.clone(),
+            dir,
+            gid: opts.gid,
+            quota: size(&opts.quota).map_err(|e| bad(format!("quota: {e}")))?,
+            fast_limit: size(&opts.fast_limit).map_err(|e| bad(format!("fast_limit: {e}")))?,
+            create_tier,
+            min_age_to_evict: opts.min_age_to_evict_secs.map(Duration::from_secs),
+            min_age_to_archive: opts.min_age_to_archive_secs.map(Duration::from_secs),
+        });
+    }
+    Ok(out)
+}
+
+#[derive(Debug)]
+pub struct Tenants {
+    tenants: Vec<Tenant>,
+}
+
+impl Tenants {
+    pub fn new(tenants: Vec<Tenant>) -> Arc<Self> {
+        Arc::new(Self { tenants })
+    }
+
+    /// The tenant whose directory holds `logical`.
+    pub fn tenant_of(&self, logical: &Path) -> Option<&Tenant> {
+        self.tenants.iter().find(|t| logical.starts_with(&t.dir))
+    }
+
+    /// Directory quota rules for the tenants that have a quota.
+    pub fn quota_rules(&self) -> Vec<QuotaRule> {
+        self.tenants
+            .iter()
+            .filter_map(|t| {
+                Some(QuotaRule {
+                    target: QuotaTarget::Dir(t.dir.clone()),
+                    limit: t.quota?,
+                })
+            })
+            .collect()
+    }
+
+    /// The tenants' own minimum ages before files leave `tier`, by
+    /// directory (`TieringPolicy::min_ages`).
+    pub fn min_ages(&self, tier: TierId) -> Vec<(PathBuf, Duration)> {
+        self.tenants
+            .iter()
+            .filter_map(|t| {
+                let age = match tier {
+                    TierId::Fast => t.min_age_to_evict,
+                    TierId::Slow => t.min_age_to_archive,
+                    TierId::Archive => None,
+                }?;
+                Some((t.dir.clone(), age))
+            })
+            .collect()
+    }
+
+    /// Create every tenant directory on every backend and give those with
+    /// a `gid` to the group, mode `TENANT_DIR_MODE`. Redone at each mount,
+    /// so a directory that drifted is put back.
+    pub fn prepare(&self, router: &TierRouter) -> Result<()> {
+        for t in &self.tenants {
+            let rel = t.dir.strip_prefix("/").unwrap_or(&t.dir);
+            for (_, b) in router.all_backends() {
+                if !b.exists(rel).unwrap_or(false) {
+                    b.create_dir(rel).map_err(|e| {
+                        FsError::Storage(format!("tenant {}: mkdir on {}: {e}", t.name, b.id()))
+                    })?;
+                }
+                let Some(gid) = t.gid else {
+                    continue;
+                };
+                let meta = b.metadata(rel)?;
+                match b.set_owner(rel, meta.uid, gid) {
+                    Ok(()) | Err(FsError::Unsupported(_)) => {}
+                    Err(e) => warn!("tenant {}: chgrp on {}: {e}", t.name, b.id()),
+                }
+                if let Err(e) = b.set_permissions(rel, TENANT_DIR_MODE) {
+                    warn!("tenant {}: chmod on {}: {e}", t.name, b.id());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The tier a new file at `logical` starts on, given the one the policy
+    /// picked. A tenant only ever moves it from fast to slow: by
+    /// `create_tier`, or once its files on fast reach `fast_limit`.
+    pub fn tier_for_create(&self, logical: &Path, tier: TierId, index: &dyn PathIndex) -> TierId {
+        let Some(t) = self.tenant_of(logical).filter(|_| tier == TierId::Fast) else {
+            return tier;
+        };
+        if t.create_tier == Some(TierId::Slow) {
+            return TierId::Slow;
+        }
+        let Some(limit) = t.fast_limit else {
+            return tier;
+        };
+        match index.dir_usage(&t.dir, 0) {
+            Ok(usage) => {
+                let on_fast: u64 = usage
+                    .iter()
+                    .filter(|u| u.dir == t.dir && u.tier == TierId::Fast)
+                    .map(|u| u.bytes)
+                    .sum();
+                if on_fast >= limit {
+                    debug!(
+                        "tenant {}: {on_fast} bytes on fast, creating on slow",
+                        t.name
+                    );
+                    return TierId::Slow;
+                }
+                tier
+            }
+            Err(e) => {
+                warn!("tenant {}: fast usage: {e}", t.name);
+                tier
+            }
+        }
+    }
+
+    /// Usage and monthly cost of every tenant, from the index.
+    pub fn stats(&self, index: &dyn PathIndex, rates: &TierRates) -> Result<Vec<TenantStats>> {
+        let mut out = Vec::new();
+        for t in &self.tenants {
+            let mut s = TenantStats {
+                name: t.name.clone(),
+                dir: t.dir.clone(),
+                gid: t.gid,
+                quota: t.quota,
+                fast_limit: t.fast_limit,
+                ..Default::default()
+            };
+            for u in index.dir_usage(&t.dir, 0)? {
+                if u.dir != t.dir {
+                    continue;
+                }
+                s.files += u.files;
+                s.bytes += u.bytes;
+                match u.tier {
+                    TierId::Fast => s.fast_bytes += u.bytes,
+                    TierId::Slow => s.slow_bytes += u.bytes,
+                    TierId::Archive => s.archive_bytes += u.bytes,
+                }
+                s.monthly += rates.monthly(u.tier, u.bytes);
+            }
+            out.push(s);
+        }
+        Ok(out)
+    }
+}
+
+/// The tierer's policy with the tenants' minimum ages on top; everything
+/// else is `inner`'s, reloads included.
+pub struct TenantPolicy {
+    inner: Arc<dyn TieringPolicy>,
+    tenants: Arc<Tenants>,
+}
+
+impl TenantPolicy {
+    pub fn new(inner: Arc<dyn TieringPolicy>, tenants: Arc<Tenants>) -> Arc<Self> {
+        Arc::new(Self { inner, tenants })
+    }
+}
+
+impl TieringPolicy for TenantPolicy {
+    fn low_watermark(&self) -> f64 {
+        self.inner.low_watermark()
+    }
+    fn high_watermark(&self) -> f64 {
+        self.inner.high_watermark()
+    }
+    fn panic_watermark(&self) -> f64 {
+        self.inner.panic_watermark()
+    }
+    fn tier_period(&self) -> Option<Duration> {
+        self.inner.tier_period()
+    }
+    fn min_age_to_evict(&self) -> Duration {
+        self.inner.min_age_to_evict()
+    }
+    fn initial_popularity(&self) -> f64 {
+        self.inner.initial_popularity()
+    }
+    fn min_age_to_archive(&self) -> Duration {
+        self.inner.min_age_to_archive()
+    }
+    fn slow_archive_watermark(&self) -> f64 {
+        self.inner.slow_archive_watermark()
+    }
+    fn tier_for_create(&self, fast_usage: f64) -> TierId {
+        self.inner.tier_for_create(fast_usage)
+    }
+    fn min_ages(&self, tier: TierId) -> Vec<(PathBuf, Duration)> {
+        let mut ages = self.tenants.min_ages(tier);
+        ages.extend(self.inner.min_ages(tier));
+        ages
+    }
+}
+
+/// One line of `rhss tenants`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TenantStats {
+    pub name: String,
+    pub dir: PathBuf,
+    pub gid: Option<u32>,
+    pub files: u64,
+    pub bytes: u64,
+    pub fast_bytes: u64,
+    pub slow_bytes: u64,
+    pub archive_bytes: u64,
+    pub quota: Option<u64>,
+    pub fast_limit: Option<u64>,
+    pub monthly: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend};
+    use crate::index::{FileRow, FileState, Location, Mutability, SqlitePathIndex};
+    use crate::tier::{MostFreePlacement, Tier};
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    fn opts(toml: &str) -> BTreeMap<String, TenantOptions> {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn tenants_parse_and_reject_overlaps() {
+        let t = parse(&opts(
+            "[a]\nquota = \"1K\"\ncreate_tier = \"slow\"\n[b]\ndir = \"/team-b\"\ngid = 7",
+        ))
+        .unwrap();
+        assert_eq!(t[0].dir, Path::new("/a"));
+        assert_eq!(
+            (t[0].quota, t[0].create_tier),
+            (Some(1024), Some(TierId::Slow))
+        );
+        assert_eq!(
+            (t[1].dir.as_path(), t[1].gid),
+            (Path::new("/team-b"), Some(7))
+        );
+        let rules = Tenants::new(t).quota_rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].target, QuotaTarget::Dir("/a".into()));
+
+        let t = Tenants::new(parse(&opts("[a]\nmin_age_to_archive_secs = 60")).unwrap());
+        assert!(t.min_ages(TierId::Fast).is_empty());
+        assert_eq!(
+            t.min_ages(TierId::Slow),
+            [(PathBuf::from("/a"), Duration::from_secs(60))]
+        );
+
+        for bad in [
+            "[a]\ndir = \"/x/y\"",
+            "[a]\ndir = \"x\"",
+            "[a]\ndir = \"/x\"\n[b]\ndir = \"/x\"",
+            "[a]\ncreate_tier = \"archive\"",
+            "[a]\nfast_limit = \"lots\"",
+        ] {
+            assert!(parse(&opts(bad)).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn placement_prepare_and_stats() {
+        let db = TempDir::new().unwrap();
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(db.path().join("i.db")).unwrap();
+        let ssd: Arc<dyn Backend> = Arc::new(MemoryBackend::new("ssd", 1 << 20));
+        let hdd: Arc<dyn Backend> = Arc::new(MemoryBackend::new("hdd", 1 << 20));
+        let router = TierRouter::new(
+            Tier::new(
+                TierId::Fast,
+                vec![Arc::clone(&ssd)],
+                Box::new(MostFreePlacement),
+            )
+            .unwrap(),
+            Tier::new(
+                TierId::Slow,
+                vec![Arc::clone(&hdd)],
+                Box::new(MostFreePlacement),
+            )
+            .unwrap(),
+        );
+        let tenants = Tenants::new(
+            parse(&opts(
+                "[a]\ngid = 7\nfast_limit = 100\n[b]\ncreate_tier = \"slow\"",
+            ))
+            .unwrap(),
+        );
+
+        tenants.prepare(&router).unwrap();
+        for b in [&ssd, &hdd] {
+            let m = b.metadata(Path::new("a")).unwrap();
+            assert!(m.is_dir);
+            assert_eq!((m.gid, m.mode & 0o7777), (7, TENANT_DIR_MODE));
+            assert!(b.metadata(Path::new("b")).unwrap().is_dir);
+        }
+        // Idempotent.
+        tenants.prepare(&router).unwrap();
+
+        let create = |p: &str| tenants.tier_for_create(Path::new(p), TierId::Fast, index.as_ref());
+        assert_eq!(create("/a/new"), TierId::Fast);
+        assert_eq!(create("/b/new"), TierId::Slow);
+        assert_eq!(create("/other/new"), TierId::Fast);
+
+        index
+            .insert(FileRow {
+                logical_path: "/a/big".into(),
+                location: Location {
+                    tier: TierId::Fast,
+                    backend_id: "ssd".into(),
+                    backend_path: "a/big".into(),
+                    size: 100,
+                },
+                replicas: Vec::new(),
+                last_access: SystemTime::now(),
+                hit_count: 0,
+                popularity: 0.0,
+                pinned_tier: None,
+                pinned_until: None,
+                state: FileState::Stable,
+                mutability: Mutability::Unknown,
+                compressed: false,
+                content_hash: None,
+            })
+            .unwrap();
+        assert_eq!(create("/a/new"), TierId::Slow, "over the fast limit");
+
+        let rates = TierRates {
+            fast: Some(1.0),
+            ..Default::default()
+        };
+        let stats = tenants.stats(index.as_ref(), &rates).unwrap();
+        assert_eq!((stats[0].files, stats[0].fast_bytes), (1, 100));
+        assert_eq!(stats[1].bytes, 0);
+    }
+}
@@ -0,0 +1,297 @@
+//! Bandwidth limits for background migration and cold-tier reads.
+//!
+//! Configured under `[throttle]`, in bytes per second:
+//!
+//! ```toml
+//! [throttle]
+//! migration = "50M"    # tierer copies between tiers
+//! cold_reads = "200M"  # FUSE reads served from the slow/archive tiers
+//! ```
+//!
+//! Each limit is a token bucket holding at most one second's worth of
+//! bytes. Callers account for IO after the fact; once the bucket is in
+//! debt they sleep until it's paid off, so a busy demotion can't saturate
+//! a disk that interactive reads also need. Limits are process-wide, set
+//! at mount and again on SIGHUP.
+//!
+//! FUSE reads and writes can also be capped per caller, so one team's
+//! bulk job can't starve the others sharing the mount (`IoLimits`):
+//!
+//! ```toml
+//! [throttle.uids.1001]
+//! iops = 200
+//! bandwidth = "50M"
+//!
+//! [throttle.uids."*"]     # every other uid, each on its own
+//! bandwidth = "200M"
+//!
+//! [throttle.tenants.physics]   # a `[tenants]` entry, all its users together
+//! iops = 1000
+//! ```
+//!
+//! Those requests aren't slept on: the FUSE layer asks `IoLimits::delay`
+//! how long to hold one back and queues it on a timer, so a throttled
+//! caller never ties up the workers everyone else needs.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// What the bytes were moved for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// Tierer copies (promotion, demotion, archiving).
+    Migration,
+    /// Foreground reads from a backend outside the fast tier.
+    ColdRead,
+}
+
+/// Bytes (or, for an IOPS ceiling, requests) per second.
+pub struct TokenBucket {
+    bytes_per_sec: u64,
+    /// Available bytes (negative: owed) and when that was last updated.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            state: Mutex::new((bytes_per_sec as f64, Instant::now())),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Charge `bytes`, sleeping for as long as the bucket is in debt.
+    pub fn take(&self, bytes: u64) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Charge `bytes` and return how long until the bucket is out of debt,
+    /// without waiting. Successive calls return non-decreasing deadlines,
+    /// so callers that wait them out stay in order.
+    pub fn reserve(&self, bytes: u64) -> Duration {
+        let rate = self.bytes_per_sec as f64;
+        let mut st = self.state.lock();
+        let now = Instant::now();
+        let refill = now.duration_since(st.1).as_secs_f64() * rate;
+        st.0 = (st.0 + refill).min(rate) - bytes as f64;
+        st.1 = now;
+        if st.0 >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-st.0 / rate)
+    }
+}
+
+static BUCKETS: RwLock<[Option<Arc<TokenBucket>>; 2]> = RwLock::new([None, None]);
+
+fn slot(class: Class) -> usize {
+    match class {
+        Class::Migration => 0,
+        Class::ColdRead => 1,
+    }
+}
+
+/// Limit `class` to `bytes_per_sec`, or lift its limit with `None`.
+pub fn set_limit(class: Class, bytes_per_sec: Option<u64>) {
+    let bucket = bytes_per_sec.map(|r| Arc::new(TokenBucket::new(r)));
+    BUCKETS.write().unwrap_or_else(|e| e.into_inner())[slot(class)] = bucket;
+}
+
+/// The current limit of `class`, in bytes per second.
+pub fn limit(class: Class) -> Option<u64> {
+    bucket(class).map(|b| b.bytes_per_sec())
+}
+
+fn bucket(class: Class) -> Option<Arc<TokenBucket>> {
+    BUCKETS.read().unwrap_or_else(|e| e.into_inner())[slot(class)].clone()
+}
+
+/// Account for `bytes` of `class` IO; blocks while over the limit. Free
+/// when `class` has no limit.
+pub fn take(class: Class, bytes: u64) {
+    if let Some(b) = bucket(class) {
+        b.take(bytes);
+    }
+}
+
+/// A ceiling on FUSE reads and writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoLimit {
+    /// Requests per second.
+    pub iops: Option<u64>,
+    pub bytes_per_sec: Option<u64>,
+}
+
+struct Buckets {
+    ops: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl Buckets {
+    fn new(limit: IoLimit) -> Self {
+        Self {
+            ops: limit.iops.map(TokenBucket::new),
+            bytes: limit.bytes_per_sec.map(TokenBucket::new),
+        }
+    }
+
+    fn reserve(&self, bytes: u64) -> Duration {
+        let ops = self.ops.as_ref().map_or(Duration::ZERO, |b| b.reserve(1));
+        let data = self
+            .bytes
+            .as_ref()
+            .map_or(Duration::ZERO, |b| b.reserve(bytes));
+        ops.max(data)
+    }
+}
+
+/// Per-uid and per-tenant ceilings on FUSE reads and writes. Each uid
+/// listed gets its own buckets, as does every other uid when there is a
+/// default; a tenant's buckets are shared by everything under its
+/// directory. A request waits for the slowest bucket it's charged to.
+pub struct IoLimits {
+    uids: HashMap<u32, Buckets>,
+    default_uid: Option<IoLimit>,
+    /// Buckets of the uids `default_uid` applies to, made on first use.
+    others: Mutex<HashMap<u32, Arc<Buckets>>>,
+    tenants: Vec<(PathBuf, Buckets)>,
+}
+
+impl IoLimits {
+    /// `None` when there is nothing to limit.
+    pub fn new(
+        uids: HashMap<u32, IoLimit>,
+        default_uid: Option<IoLimit>,
+        tenants: Vec<(PathBuf, IoLimit)>,
+    ) -> Option<Arc<Self>> {
+        if uids.is_empty() && default_uid.is_none() && tenants.is_empty() {
+            return None;
+        }
+        Some(Arc::new(Self {
+            uids: uids
+                .into_iter()
+                .map(|(u, l)| (u, Buckets::new(l)))
+                .collect(),
+            default_uid,
+            others: Mutex::new(HashMap::new()),
+            tenants: tenants
+                .into_iter()
+                .map(|(dir, l)| (dir, Buckets::new(l)))
+                .collect(),
+        }))
+    }
+
+    /// Whether `delay` looks at the path at all.
+    pub fn by_path(&self) -> bool {
+        !self.tenants.is_empty()
+    }
+
+    /// Charge one request of `bytes` by `uid` on `path` and return how
+    /// long to hold it back.
+    pub fn delay(&self, uid: u32, path: Option<&Path>, bytes: u64) -> Duration {
+        let by_uid = match (self.uids.get(&uid), self.default_uid) {
+            (Some(b), _) => b.reserve(bytes),
+            (None, Some(limit)) => {
+                let b = Arc::clone(
+                    self.others
+                        .lock()
+                        .entry(uid)
+                        .or_insert_with(|| Arc::new(Buckets::new(limit))),
+                );
+                b.reserve(bytes)
+            }
+            (None, None) => Duration::ZERO,
+        };
+        let by_tenant = path
+            .and_then(|p| self.tenants.iter().find(|(dir, _)| p.starts_with(dir)))
+            .map_or(Duration::ZERO, |(_, b)| b.reserve(bytes));
+        by_uid.max(by_tenant)
+    }
+}
+
+static IO_LIMITS: RwLock<Option<Arc<IoLimits>>> = RwLock::new(None);
+
+/// Install per-uid and per-tenant ceilings, or lift them with `None`.
+/// Buckets start full again.
+pub fn set_io_limits(limits: Option<Arc<IoLimits>>) {
+    *IO_LIMITS.write().unwrap_or_else(|e| e.into_inner()) = limits;
+}
+
+/// The ceilings FUSE reads and writes are currently held to.
+pub fn io_limits() -> Option<Arc<IoLimits>> {
+    IO_LIMITS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst_then_paces() {
+        let b = TokenBucket::new(10_000);
+        let start = Instant::now();
+        b.take(10_000);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        b.take(2_000);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn limits_are_per_class() {
+        set_limit(Class::ColdRead, Some(1 << 20));
+        assert_eq!(limit(Class::ColdRead), Some(1 << 20));
+        assert_eq!(limit(Class::Migration), None);
+        let start = Instant::now();
+        take(Class::Migration, 100 << 20);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        set_limit(Class::ColdRead, None);
+        assert_eq!(limit(Class::ColdRead), None);
+    }
+
+    #[test]
+    fn io_limits_charge_uid_and_tenant() {
+        let limit = |iops, bytes_per_sec| IoLimit {
+            iops,
+            bytes_per_sec,
+        };
+        let limits = IoLimits::new(
+            HashMap::from([(1001, limit(Some(10), None))]),
+            Some(limit(None, Some(1000))),
+            vec![("/physics".into(), limit(None, Some(100)))],
+        )
+        .unwrap();
+        let ms = |d: Duration| d.as_millis();
+        // Ten requests are the burst; the eleventh waits about 100 ms.
+        for _ in 0..10 {
+            assert_eq!(limits.delay(1001, None, 1 << 30), Duration::ZERO);
+        }
+        assert!((50..=100).contains(&ms(limits.delay(1001, None, 0))));
+        // Uids without an entry each get the default on their own.
+        assert_eq!(limits.delay(7, None, 1000), Duration::ZERO);
+        assert!(ms(limits.delay(7, None, 500)) >= 400);
+        assert_eq!(limits.delay(8, None, 1000), Duration::ZERO);
+        // The tenant bucket is shared and the slower one wins.
+        assert_eq!(
+            limits.delay(9, Some(Path::new("/physics/a")), 100),
+            Duration::ZERO
+        );
+        assert!(ms(limits.delay(10, Some(Path::new("/physics/b")), 50)) >= 400);
+        assert_eq!(
+            limits.delay(10, Some(Path::new("/chem/b")), 50),
+            Duration::ZERO
+        );
+        assert!(IoLimits::new(HashMap::new(), None, Vec::new()).is_none());
+    }
+}
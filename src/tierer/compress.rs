@@ -61,6 +61,7 @@ pub fn compress_between(
     let mut offset = 0u64;
     loop {
         let chunk = src.read_at(src_path, offset, CHUNK as u32)?;
+        crate::throttle::take(crate::throttle::Class::Migration, chunk.len() as u64);
         if chunk.is_empty() {
             break;
         }
@@ -80,6 +81,8 @@ pub fn compress_between(
         offset,
         dst_zst.display()
     );
+    // The stream went straight to disk, past any replication.
+    dst.sync_replicas(&dst_zst)?;
     Ok(hash)
 }
 
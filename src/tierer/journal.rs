@@ -0,0 +1,342 @@
+//! Crash-safe journal of in-flight migrations.
+//!
+//! `migrate` copies, commits the index row, then unlinks the source. A
+//! `kill -9` in between leaves either a half-written destination (index
+//! still on the source) or a stale source (index already moved). Before the
+//! first byte is copied, `migrate` records the operation as one small JSON
+//! file under `<db.parent>/.rhss/journal/`; the file is removed once the
+//! source is gone. On startup `recover` replays whatever is left:
+//!
+//! - index still points at the source → roll back (delete the copies);
+//! - index points at a destination → roll forward (delete the source);
+//! - row gone (file deleted meanwhile) → leave the bytes, `fsck` reports
+//!   orphans.
+//!
+//! The index is the source of truth, so no phase needs recording.
+//! Hidden-storage relocation carries its own marker (`crate::hidden`).
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::error::{FsError, Result};
+use crate::index::{PathIndex, TierId};
+use crate::tier::TierRouter;
+
+/// `<db.parent>/.rhss/journal`.
+pub fn journal_dir_for(db: &Path) -> PathBuf {
+    db.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .join(".rhss")
+        .join("journal")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationEntry {
+    pub logical: PathBuf,
+    pub src_tier: String,
+    pub src_backend: String,
+    pub src_path: PathBuf,
+    pub dst_tier: String,
+    /// Backends being written. Empty for a dedup hit (the blob already
+    /// existed and must never be rolled back).
+    pub dst_backends: Vec<String>,
+    /// On-disk destination path (`.zst` suffix included when compressing).
+    pub dst_path: PathBuf,
+}
+
+pub struct Journal {
+    dir: PathBuf,
+    seq: AtomicU64,
+}
+
+impl Journal {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Arc<Self>> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .map_err(|e| FsError::Storage(format!("journal dir {}: {e}", dir.display())))?;
+        Ok(Arc::new(Self {
+            dir,
+            seq: AtomicU64::new(0),
+        }))
+    }
+
+    /// Durably record `entry`. The returned ticket removes it on `finish`.
+    pub fn begin(&self, entry: &MigrationEntry) -> Result<Ticket> {
+        let n = self.seq.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .dir
+            .join(format!("migrate-{}-{n}.json", std::process::id()));
+        let tmp = path.with_extension("tmp");
+        let body = serde_json::to_vec(entry)?;
+        let write = || -> std::io::Result<()> {
+            let mut f = fs::File::create(&tmp)?;
+            f.write_all(&body)?;
+            f.sync_all()?;
+            fs::rename(&tmp, &path)?;
+            // Make the rename itself durable.
+            fs::File::open(&self.dir)?.sync_all()
+        };
+        write().map_err(|e| FsError::from_io(e, path.display()))?;
+        Ok(Ticket { path })
+    }
+
+    pub fn finish(&self, ticket: Ticket) {
+        if let Err(e) = fs::remove_file(&ticket.path) {
+            warn!("journal: remove {}: {e}", ticket.path.display());
+        }
+    }
+
+    /// Entries currently recorded. Unlike `pending`, safe to call while
+    /// migrations are running.
+    pub fn in_flight(&self) -> usize {
+        fs::read_dir(&self.dir)
+            .map(|rd| {
+                rd.flatten()
+                    .filter(|e| e.path().extension().is_some_and(|x| x == "json"))
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Entries left behind by a previous run, with their files.
+    pub fn pending(&self) -> Result<Vec<(PathBuf, MigrationEntry)>> {
+        let mut out = Vec::new();
+        let rd = fs::read_dir(&self.dir)
+            .map_err(|e| FsError::Storage(format!("journal dir {}: {e}", self.dir.display())))?;
+        for ent in rd.flatten() {
+            let path = ent.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("json") => {}
+                // Crashed mid-`begin`: nothing was copied yet.
+                Some("tmp") => {
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+                _ => continue,
+            }
+            match fs::read(&path)
+                .map_err(FsError::from)
+                .and_then(|b| serde_json::from_slice::<MigrationEntry>(&b).map_err(FsError::from))
+            {
+                Ok(entry) => out.push((path, entry)),
+                Err(e) => warn!("journal: unreadable {}: {e}", path.display()),
+            }
+        }
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(out)
+    }
+}
+
+pub struct Ticket {
+    path: PathBuf,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryStats {
+    pub rolled_back: usize,
+    pub rolled_forward: usize,
+    pub abandoned: usize,
+}
+
+/// Settle every pending entry against the index. Run before the tierer
+/// starts so nothing is in flight.
+pub fn recover(
+    journal: &Journal,
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+) -> Result<RecoveryStats> {
+    let mut stats = RecoveryStats::default();
+    for (file, e) in journal.pending()? {
+        let src_tier = TierId::parse(&e.src_tier)?;
+        let dst_tier = TierId::parse(&e.dst_tier)?;
+        let loc = index.locate(&e.logical)?;
+        let on_src = loc
+            .as_ref()
+            .map(|l| l.tier == src_tier && l.backend_id == e.src_backend)
+            .unwrap_or(false);
+        // Never delete whatever the index currently serves.
+        let live = |tier: TierId, id: &str, path: &Path| {
+            loc.as_ref()
+                .map(|l| l.tier == tier && l.backend_id == id && l.backend_path == path)
+                .unwrap_or(false)
+        };
+        match &loc {
+            None => {
+                warn!(
+                    "journal: {} no longer indexed; leaving its bytes for fsck",
+                    e.logical.display()
+                );
+                stats.abandoned += 1;
+            }
+            Some(_) if on_src => {
+                let src_meta = router
+                    .resolve_backend(src_tier, &e.src_backend)
+                    .and_then(|b| b.metadata(&e.src_path).ok());
+                for id in &e.dst_backends {
+                    if live(dst_tier, id, &e.dst_path) {
+                        continue;
+                    }
+                    if let Some(b) = router.resolve_backend(dst_tier, id) {
+                        // Partial copies the next attempt can resume from
+                        // stay; that attempt overwrites or extends them.
+                        let resumable = src_meta.as_ref().is_some_and(|m| {
+                            b.resume_copy(&e.dst_path, m).is_ok_and(|done| done > 0)
+                        });
+                        if !resumable {
+                            let _ = b.remove(&e.dst_path);
+                        }
+                    }
+                }
+                info!("journal: rolled back migration of {}", e.logical.display());
+                stats.rolled_back += 1;
+            }
+            Some(_) => {
+                // Mirror within a tier can include the source itself.
+                let src_is_dst = dst_tier == src_tier && e.dst_backends.contains(&e.src_backend);
+                if !src_is_dst && !live(src_tier, &e.src_backend, &e.src_path) {
+                    if let Some(b) = router.resolve_backend(src_tier, &e.src_backend) {
+                        let _ = b.remove(&e.src_path);
+                    }
+                }
+                info!("journal: completed migration of {}", e.logical.display());
+                stats.rolled_forward += 1;
+            }
+        }
+        let _ = fs::remove_file(&file);
+    }
+    Ok(stats)
+}
+
+static JOURNAL: RwLock<Option<Arc<Journal>>> = RwLock::new(None);
+
+/// Install (or clear, with `None`) the process-wide journal `migrate` uses.
+pub fn set_journal(journal: Option<Arc<Journal>>) {
+    *JOURNAL.write().unwrap_or_else(|e| e.into_inner()) = journal;
+}
+
+pub(crate) fn current() -> Option<Arc<Journal>> {
+    JOURNAL.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, PosixBackend};
+    use crate::index::{FileRow, FileState, Location, Mutability, SqlitePathIndex};
+    use crate::tier::{MostFreePlacement, Tier};
+    use std::time::UNIX_EPOCH;
+    use tempfile::TempDir;
+
+    struct Fixture {
+        _dirs: Vec<TempDir>,
+        ssd: PathBuf,
+        hdd: PathBuf,
+        router: TierRouter,
+        index: Arc<dyn PathIndex>,
+        journal: Arc<Journal>,
+    }
+
+    fn fixture() -> Fixture {
+        let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+        let ssd = dirs[0].path().to_path_buf();
+        let hdd = dirs[1].path().to_path_buf();
+        let ssd_b: Arc<dyn Backend> = Arc::new(PosixBackend::new("ssd", ssd.clone()).unwrap());
+        let hdd_b: Arc<dyn Backend> = Arc::new(PosixBackend::new("hdd", hdd.clone()).unwrap());
+        let router = TierRouter::new(
+            Tier::new(TierId::Fast, vec![ssd_b], Box::new(MostFreePlacement)).unwrap(),
+            Tier::new(TierId::Slow, vec![hdd_b], Box::new(MostFreePlacement)).unwrap(),
+        );
+        let db = dirs[2].path().join("idx.db");
+        let index = SqlitePathIndex::open(&db).unwrap() as Arc<dyn PathIndex>;
+        let journal = Journal::open(journal_dir_for(&db)).unwrap();
+        Fixture {
+            _dirs: dirs,
+            ssd,
+            hdd,
+            router,
+            index,
+            journal,
+        }
+    }
+
+    fn row(tier: TierId, backend: &str) -> FileRow {
+        FileRow {
+            logical_path: PathBuf::from("/f"),
+            location: Location {
+                tier,
+                backend_id: backend.into(),
+                backend_path: PathBuf::from("f"),
+                size: 1,
+            },
+            last_access: UNIX_EPOCH,
+            hit_count: 0,
+            popularity: 0.0,
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            replicas: Vec::new(),
+            mutability: Mutability::Unknown,
+            compressed: false,
+            content_hash: None,
+        }
+    }
+
+    fn entry() -> MigrationEntry {
+        MigrationEntry {
+            logical: PathBuf::from("/f"),
+            src_tier: "fast".into(),
+            src_backend: "ssd".into(),
+            src_path: PathBuf::from("f"),
+            dst_tier: "slow".into(),
+            dst_backends: vec!["hdd".into()],
+            dst_path: PathBuf::from("f"),
+        }
+    }
+
+    #[test]
+    fn uncommitted_migration_rolls_back() {
+        let fx = fixture();
+        fs::write(fx.ssd.join("f"), b"x").unwrap();
+        fs::write(fx.hdd.join("f"), b"partial").unwrap();
+        fx.index.insert(row(TierId::Fast, "ssd")).unwrap();
+        // Killed before `finish`.
+        fx.journal.begin(&entry()).unwrap();
+
+        let st = recover(&fx.journal, &fx.router, &fx.index).unwrap();
+        assert_eq!(st.rolled_back, 1);
+        assert!(fx.ssd.join("f").exists());
+        assert!(!fx.hdd.join("f").exists());
+        assert!(fx.journal.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn committed_migration_rolls_forward() {
+        let fx = fixture();
+        fs::write(fx.ssd.join("f"), b"x").unwrap();
+        fs::write(fx.hdd.join("f"), b"x").unwrap();
+        fx.index.insert(row(TierId::Slow, "hdd")).unwrap();
+        // Killed before `finish`.
+        fx.journal.begin(&entry()).unwrap();
+
+        let st = recover(&fx.journal, &fx.router, &fx.index).unwrap();
+        assert_eq!(st.rolled_forward, 1);
+        assert!(!fx.ssd.join("f").exists());
+        assert!(fx.hdd.join("f").exists());
+    }
+
+    #[test]
+    fn finished_entries_leave_nothing_pending() {
+        let fx = fixture();
+        let t = fx.journal.begin(&entry()).unwrap();
+        assert_eq!(fx.journal.pending().unwrap().len(), 1);
+        fx.journal.finish(t);
+        assert!(fx.journal.pending().unwrap().is_empty());
+    }
+}
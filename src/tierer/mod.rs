@@ -4,23 +4,29 @@
 //!   tier (using that tier's Placement to pick the destination backend).
 //!   Skips files that are currently open (autotier-style; D7). Preserves
 //!   `atime`/`mtime` (D16). Updates the index in a single SQLite swap.
+//!   `migrate_directory()` applies it to a subtree, reporting progress,
+//!   after `plan_migration()` has picked the files from metadata alone.
 //!
 //! - `Tierer::run` is the background loop: sleeps `tier_period`, evicts the
 //!   `coldest_N` files from Fast when usage > `low_watermark`, runs a daily
-//!   full sweep (D19).
+//!   full sweep (D19). With a `[schedule]`, it also wakes for scheduled
+//!   sweeps that demote everything old enough (see `schedule`).
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError};
 use tracing::{debug, info, warn};
 
 use crate::backend::Backend;
-use crate::error::{FsError, Result};
+use crate::clock::Clock;
+use crate::error::{ErrorContext, FsError, Result};
+use crate::hooks::{emit, Event};
 use crate::index::{Location, PathIndex, ReplicaLoc, TierId};
 use crate::policy::TieringPolicy;
+use crate::throttle::{self, Class};
 use crate::tier::TierRouter;
 
 fn compressed_or_raw(path: &Path, compressed: bool) -> std::path::PathBuf {
@@ -32,12 +38,65 @@ fn compressed_or_raw(path: &Path, compressed: bool) -> std::path::PathBuf {
 }
 
 pub mod compress;
+pub mod journal;
 pub mod open_tracker;
+pub mod plan;
+pub mod schedule;
 pub use compress::{compress_between, ensure_decompressed, hash_file};
+pub use journal::{set_journal, Journal};
 pub use open_tracker::OpenFileTracker;
+pub use plan::{plan_migration, MigrationPlan};
+pub use schedule::{set_schedule, Schedule};
 
 const COPY_BUF_SIZE: usize = 1 << 20; // 1 MiB chunks
 
+/// Called with the logical path after rhss itself changed a file behind the
+/// kernel's back (a completed migration). The FUSE layer installs one to
+/// invalidate cached attrs so long TTLs don't serve stale metadata.
+pub type ChangeHook = Arc<dyn Fn(&Path) + Send + Sync>;
+
+static CHANGE_HOOK: RwLock<Option<ChangeHook>> = RwLock::new(None);
+
+/// Install (or clear, with `None`) the process-wide change hook.
+pub fn set_change_hook(hook: Option<ChangeHook>) {
+    *CHANGE_HOOK.write().unwrap_or_else(|e| e.into_inner()) = hook;
+}
+
+fn notify_changed(logical: &Path) {
+    let hook = CHANGE_HOOK.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(hook) = hook {
+        hook(logical);
+    }
+}
+
+fn journal_begin(
+    row: &crate::index::FileRow,
+    logical: &Path,
+    target: TierId,
+    dst_backends: &[String],
+    dst_path: &Path,
+) -> Result<Option<(Arc<Journal>, journal::Ticket)>> {
+    let Some(j) = journal::current() else {
+        return Ok(None);
+    };
+    let ticket = j.begin(&journal::MigrationEntry {
+        logical: logical.to_path_buf(),
+        src_tier: row.location.tier.as_str().to_string(),
+        src_backend: row.location.backend_id.clone(),
+        src_path: row.location.backend_path.clone(),
+        dst_tier: target.as_str().to_string(),
+        dst_backends: dst_backends.to_vec(),
+        dst_path: dst_path.to_path_buf(),
+    })?;
+    Ok(Some((j, ticket)))
+}
+
+fn journal_finish(ticket: Option<(Arc<Journal>, journal::Ticket)>) {
+    if let Some((j, t)) = ticket {
+        j.finish(t);
+    }
+}
+
 /// Migrate a single file. Returns `Ok(false)` if the file was skipped because
 /// it's currently open (this is normal; retry next tier cycle).
 pub fn migrate(
@@ -60,9 +119,12 @@ pub fn migrate(
     if row.location.tier == target_tier {
         return Ok(false);
     }
-    if row.pinned_tier.is_some() {
+    // A pinned file only moves to the tier it is pinned to.
+    if row.pinned_tier.is_some_and(|t| t != target_tier) {
         return Ok(false);
     }
+    // A preheated copy was taken from the location about to go away.
+    crate::preheat::drop_copy(router, index, logical)?;
 
     let src_backend = router
         .resolve_backend(row.location.tier, &row.location.backend_id)
@@ -122,6 +184,7 @@ pub fn migrate(
                         backend_path: existing.backend_path.clone(),
                         size: row.location.size,
                     };
+                    let ticket = journal_begin(&row, logical, target_tier, &[], &existing.backend_path)?;
                     let mut full_row = row.clone();
                     full_row.location = new_loc;
                     full_row.replicas = Vec::new();
@@ -131,7 +194,14 @@ pub fn migrate(
                     index.insert(full_row)?;
                     // Source unlink (we no longer need it).
                     let _ = src_backend.remove(&row.location.backend_path);
+                    journal_finish(ticket);
                     debug!("dedup hit: {} reuses blob", logical.display());
+                    emit(Event::moved(
+                        logical.to_path_buf(),
+                        row.location.tier,
+                        target_tier,
+                        row.location.size,
+                    ));
                     return Ok(true);
                 }
             }
@@ -139,6 +209,17 @@ pub fn migrate(
         }
     }
 
+    // 0. Journal the operation so a crash anywhere below is settled on the
+    //    next startup (see `journal`).
+    let dst_ids: Vec<String> = dst_backends.iter().map(|b| b.id().to_string()).collect();
+    let ticket = journal_begin(
+        &row,
+        logical,
+        target_tier,
+        &dst_ids,
+        &compressed_or_raw(&dst_path, should_compress),
+    )?;
+
     // 1. Copy src -> all dst backends (compressed or raw). Roll back any
     //    failure.
     let mut written: Vec<&Arc<dyn Backend>> = Vec::with_capacity(dst_backends.len());
@@ -160,7 +241,13 @@ pub fn migrate(
             for already in &written {
                 let _ = already.remove(&compressed_or_raw(&dst_path, should_compress));
             }
-            return Err(e);
+            journal_finish(ticket);
+            return Err(e.with_context(
+                ErrorContext::new("migrate")
+                    .tier(target_tier)
+                    .backend(dst.id())
+                    .path(&dst_path),
+            ));
         }
         let actual_path = compressed_or_raw(&dst_path, should_compress);
         if let Err(e) = dst.fsync(&actual_path) {
@@ -173,7 +260,13 @@ pub fn migrate(
             for already in &written {
                 let _ = already.remove(&compressed_or_raw(&dst_path, should_compress));
             }
-            return Err(e);
+            journal_finish(ticket);
+            return Err(e.with_context(
+                ErrorContext::new("fsync")
+                    .tier(target_tier)
+                    .backend(dst.id())
+                    .path(&actual_path),
+            ));
         }
         written.push(dst);
     }
@@ -247,60 +340,235 @@ pub fn migrate(
             warn!("migrate {} src-unlink failed: {:?}", logical.display(), e);
         }
     }
-
+    journal_finish(ticket);
+
+    notify_changed(logical);
+    emit(Event::moved(
+        logical.to_path_buf(),
+        row.location.tier,
+        target_tier,
+        row.location.size,
+    ));
     Ok(true)
 }
 
-fn copy_streaming(
+/// Running totals of a `migrate_directory` call. Reported once the plan
+/// is made and after every file, and returned once the walk is done.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MigrateProgress {
+    /// Indexed files under the directory.
+    pub files_scanned: u64,
+    /// What the plan decided to move.
+    pub files_to_move: u64,
+    pub bytes_to_move: u64,
+    pub files_moved: u64,
+    pub bytes_moved: u64,
+    /// Files left where they were: open, pinned, or already on the target.
+    pub files_skipped: u64,
+    pub failures: Vec<MigrateFailure>,
+}
+
+impl MigrateProgress {
+    /// Where a migration following `plan` starts: nothing moved yet, and
+    /// files missing from their backend already counted as failures.
+    pub fn planned(plan: &MigrationPlan) -> Self {
+        Self {
+            files_scanned: plan.scanned,
+            files_to_move: plan.moves.len() as u64,
+            bytes_to_move: plan.bytes,
+            files_skipped: plan.skipped,
+            failures: plan
+                .missing
+                .iter()
+                .map(|path| MigrateFailure {
+                    path: path.clone(),
+                    error: "missing on its backend".into(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MigrateFailure {
+    pub path: std::path::PathBuf,
+    pub error: String,
+}
+
+/// Migrate every indexed file at or below `dir`. What to move is planned
+/// from metadata first (see `plan`); a file that then fails to move is
+/// recorded in `failures` and the walk carries on. `on_progress` sees the
+/// totals after planning and after each file.
+pub fn migrate_directory(
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+    open: &OpenFileTracker,
+    dir: &Path,
+    target_tier: TierId,
+    on_progress: &mut dyn FnMut(&MigrateProgress),
+) -> Result<MigrateProgress> {
+    let plan = plan_migration(router, index, open, dir, target_tier)?;
+    let mut progress = MigrateProgress::planned(&plan);
+    on_progress(&progress);
+    for row in plan.moves {
+        match migrate(router, index, open, &row.logical_path, target_tier) {
+            Ok(true) => {
+                progress.files_moved += 1;
+                progress.bytes_moved += row.location.size;
+            }
+            // Opened or pinned since the plan was made.
+            Ok(false) => progress.files_skipped += 1,
+            Err(e) => {
+                warn!("migrate {}: {e}", row.logical_path.display());
+                progress.failures.push(MigrateFailure {
+                    path: row.logical_path,
+                    error: e.to_string(),
+                });
+            }
+        }
+        on_progress(&progress);
+    }
+    Ok(progress)
+}
+
+pub(crate) fn copy_streaming(
     src: &Arc<dyn Backend>,
     src_path: &Path,
     dst: &Arc<dyn Backend>,
     dst_path: &Path,
 ) -> Result<()> {
-    // P3.5: try kernel fast paths first (Linux copy_file_range, macOS APFS
-    // clonefile). Both fail gracefully across-FS / when unavailable —
-    // we just fall back to the streaming loop below.
+    let meta = src.metadata(src_path).ok();
+    // A destination that kept part of an earlier, interrupted copy of this
+    // same source (chunked backends) picks up where that one stopped.
+    let resume = match &meta {
+        Some(meta) => dst.resume_copy(dst_path, meta)?,
+        None => 0,
+    };
+    if resume > 0 {
+        debug!("resuming copy of {} at byte {resume}", src_path.display());
+        return stream_from(src, src_path, dst, dst_path, resume);
+    }
+
+    // Local on both ends: let the kernel move the data (P3.5). A reflink
+    // shares extents, holes included, so it goes before the sparse path.
+    let (src_abs, dst_abs) = (src.resolve(src_path), dst.resolve(dst_path));
+    if clone_file(&src_abs, &dst_abs) {
+        debug!("reflinked {}", src_path.display());
+        return dst.sync_replicas(dst_path);
+    }
+
+    // Sparse sources (VM images, preallocated torrents) go segment by
+    // segment so holes stay holes on the destination; copy_file_range and
+    // the plain loop below would write out every zero.
+    if let Some(meta) = meta {
+        if meta.size > 0
+            && src
+                .next_hole(src_path, 0)
+                .map(|h| h < meta.size)
+                .unwrap_or(false)
+        {
+            crate::backend::copy_sparse(src.as_ref(), src_path, dst.as_ref(), dst_path)?;
+            return Ok(());
+        }
+    }
+
+    if kernel_copy(&src_abs, &dst_abs) {
+        return dst.sync_replicas(dst_path);
+    }
+    stream_from(src, src_path, dst, dst_path, 0)
+}
+
+/// Make `dst` a reflink of `src` (`FICLONE` on btrfs/XFS/bcachefs; on
+/// macOS `fs::copy`, which clones on APFS and copies kernel-side
+/// elsewhere). False when either path isn't a local file or the
+/// filesystem can't share extents, e.g. across devices.
+fn clone_file(src: &Path, dst: &Path) -> bool {
     #[cfg(target_os = "linux")]
     {
-        use std::fs::File;
-        use std::os::unix::io::AsRawFd;
-        if let (Ok(s), Ok(d)) = (
-            File::open(src.resolve(src_path)),
+        let Ok(s) = std::fs::File::open(src) else {
+            return false;
+        };
+        let Ok(d) = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dst)
+        else {
+            return false;
+        };
+        rustix::fs::ioctl_ficlone(&d, &s).is_ok()
+    }
+    #[cfg(target_os = "macos")]
+    {
+        std::fs::copy(src, dst).is_ok()
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = (src, dst);
+        false
+    }
+}
+
+/// Copy `src` to `dst` with `copy_file_range(2)`, looping over the short
+/// copies it makes on large files. Nothing passes through user space, so
+/// file size doesn't matter. False if the kernel can't (different
+/// filesystem types on older kernels, non-local paths); the caller then
+/// streams the whole file again.
+fn kernel_copy(src: &Path, dst: &Path) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let (Ok(s), Ok(d)) = (
+            std::fs::File::open(src),
             std::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
                 .truncate(true)
-                .open(dst.resolve(dst_path)),
-        ) {
-            let len = s.metadata().map(|m| m.len()).unwrap_or(0);
-            if len > 0 {
-                // SAFETY: both fds are valid for the duration of the call.
-                let rc = unsafe {
-                    libc::copy_file_range(
-                        s.as_raw_fd(),
-                        std::ptr::null_mut(),
-                        d.as_raw_fd(),
-                        std::ptr::null_mut(),
-                        len as usize,
-                        0,
-                    )
-                };
-                if rc as i64 == len as i64 {
-                    return Ok(());
+                .open(dst),
+        ) else {
+            return false;
+        };
+        let len = s.metadata().map(|m| m.len()).unwrap_or(0);
+        // Throttled: small steps, so the limit holds within each second.
+        let step = match throttle::limit(Class::Migration) {
+            Some(_) => COPY_BUF_SIZE as u64,
+            None => 1 << 30,
+        };
+        let mut done = 0u64;
+        while done < len {
+            let want = (len - done).min(step) as usize;
+            match rustix::fs::copy_file_range(&s, None, &d, None, want) {
+                Ok(0) => break,
+                Ok(n) => {
+                    done += n as u64;
+                    throttle::take(Class::Migration, n as u64);
                 }
-                // Otherwise fall through to streaming.
-            } else {
-                return Ok(());
+                Err(_) => return false,
             }
         }
+        done == len
     }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (src, dst);
+        false
+    }
+}
 
-    let mut offset = 0u64;
+/// The plain copy loop, starting at byte `offset`.
+fn stream_from(
+    src: &Arc<dyn Backend>,
+    src_path: &Path,
+    dst: &Arc<dyn Backend>,
+    dst_path: &Path,
+    mut offset: u64,
+) -> Result<()> {
     loop {
         let chunk = src.read_at(src_path, offset, COPY_BUF_SIZE as u32)?;
         if chunk.is_empty() {
             return Ok(());
         }
+        throttle::take(Class::Migration, chunk.len() as u64);
         let written = dst.write_at(dst_path, offset, &chunk)? as u64;
         offset += written;
         if (chunk.len() as u64) < COPY_BUF_SIZE as u64 {
@@ -362,6 +630,10 @@ impl TiererHandle {
     pub fn is_paused(&self) -> bool {
         self.paused.load(Ordering::SeqCst)
     }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::SeqCst)
+    }
 }
 
 impl Tierer {
@@ -370,6 +642,7 @@ impl Tierer {
         index: Arc<dyn PathIndex>,
         open_tracker: Arc<OpenFileTracker>,
         policy: Arc<dyn TieringPolicy>,
+        clock: Arc<dyn Clock>,
     ) -> (Self, TiererHandle) {
         let (tx, rx) = bounded::<TierMessage>(16);
         let busy = Arc::new(AtomicBool::new(false));
@@ -384,6 +657,7 @@ impl Tierer {
                     index,
                     open_tracker,
                     policy,
+                    clock,
                     rx,
                     busy_for_thread,
                     paused_for_thread,
@@ -406,6 +680,20 @@ impl Tierer {
         )
     }
 
+    /// A tierer that never runs, for a shared instance that must not move
+    /// files. Its handle reports paused and drops every request.
+    pub fn idle() -> (Self, TiererHandle) {
+        let (tx, _) = bounded::<TierMessage>(1);
+        let tierer = Self {
+            tx,
+            busy: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(true)),
+            handle: None,
+        };
+        let h = tierer.handle();
+        (tierer, h)
+    }
+
     pub fn handle(&self) -> TiererHandle {
         TiererHandle {
             tx: self.tx.clone(),
@@ -424,34 +712,50 @@ impl Drop for Tierer {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn tierer_loop(
     router: Arc<TierRouter>,
     index: Arc<dyn PathIndex>,
     open_tracker: Arc<OpenFileTracker>,
     policy: Arc<dyn TieringPolicy>,
+    clock: Arc<dyn Clock>,
     rx: Receiver<TierMessage>,
     busy: Arc<AtomicBool>,
     paused: Arc<AtomicBool>,
 ) {
-    let mut last_full_sweep = Instant::now();
+    let mut last_full_sweep = clock.now();
     let day = Duration::from_secs(86_400);
+    let mut sched: Option<Arc<Schedule>> = None;
+    let mut next_sweep: Option<SystemTime> = None;
 
     loop {
-        let wait = policy.tier_period().unwrap_or(Duration::from_secs(60 * 60));
+        // Picks up a schedule replaced by SIGHUP at the next wakeup.
+        let current = schedule::current();
+        if current.as_ref().map(Arc::as_ptr) != sched.as_ref().map(Arc::as_ptr) {
+            next_sweep = current
+                .as_ref()
+                .and_then(|s| s.sweep.next_after(clock.now()));
+            sched = current;
+        }
+        let until_sweep =
+            next_sweep.map(|t| t.duration_since(clock.now()).unwrap_or(Duration::ZERO));
+        let wait = match (policy.tier_period(), until_sweep) {
+            (Some(p), Some(s)) => Some(p.min(s)),
+            (p, s) => p.or(s),
+        };
 
         // Wait either for the next period or an oneshot signal.
-        let msg = if policy.tier_period().is_none() {
+        let msg = match wait {
             // Manual-only: block until a message arrives.
-            match rx.recv() {
+            None => match rx.recv() {
                 Ok(m) => m,
                 Err(_) => return,
-            }
-        } else {
-            match rx.recv_timeout(wait) {
+            },
+            Some(wait) => match rx.recv_timeout(wait) {
                 Ok(m) => m,
                 Err(crossbeam_channel::RecvTimeoutError::Timeout) => TierMessage::Oneshot,
                 Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return,
-            }
+            },
         };
 
         match msg {
@@ -468,33 +772,73 @@ fn tierer_loop(
             }
         }
 
+        let sweep_due = next_sweep.is_some_and(|t| t <= clock.now());
+        if sweep_due {
+            next_sweep = sched.as_ref().and_then(|s| s.sweep.next_after(clock.now()));
+        }
+
         if paused.load(Ordering::SeqCst) {
             debug!("tierer: paused — skipping eviction pass");
             continue;
         }
 
         busy.store(true, Ordering::SeqCst);
+        if let Some(s) = sched.as_ref().filter(|_| sweep_due) {
+            scheduled_sweep(&router, &index, &open_tracker, &policy, &*clock, s);
+        }
+        expire_pins(&index, &*clock);
         evict_cold(&router, &index, &open_tracker, &policy);
 
-        if last_full_sweep.elapsed() >= day {
+        let now = clock.now();
+        if now.duration_since(last_full_sweep).is_ok_and(|d| d >= day) {
             full_sweep(&index, &policy);
-            last_full_sweep = Instant::now();
+            last_full_sweep = now;
         }
         busy.store(false, Ordering::SeqCst);
     }
 }
 
+/// Hand files whose `pin --until` deadline passed back to the policy.
+fn expire_pins(index: &Arc<dyn PathIndex>, clock: &dyn Clock) {
+    match index.expire_pins(clock.now()) {
+        Ok(paths) => {
+            for p in &paths {
+                info!("pin on {} expired", p.display());
+            }
+        }
+        Err(e) => warn!("expire pins: {e}"),
+    }
+}
+
+/// Whether the last eviction pass saw the fast tier above its high
+/// watermark, so `watermark` events fire on the way up only.
+static FAST_ABOVE_HIGH: AtomicBool = AtomicBool::new(false);
+
 fn evict_cold(
     router: &TierRouter,
     index: &Arc<dyn PathIndex>,
     open_tracker: &Arc<OpenFileTracker>,
     policy: &Arc<dyn TieringPolicy>,
 ) {
+    // Tell hooks once per crossing, not on every pass spent above it.
+    let usage = router.fast.usage_ratio();
+    let above = usage > policy.high_watermark();
+    if above && !FAST_ABOVE_HIGH.swap(true, Ordering::Relaxed) {
+        emit(Event::Watermark {
+            tier: TierId::Fast.as_str(),
+            usage,
+            watermark: policy.high_watermark(),
+        });
+    } else if !above {
+        FAST_ABOVE_HIGH.store(false, Ordering::Relaxed);
+    }
+
     // Chain 1: Fast → Slow on the usual watermarks.
     evict_chain(
         router,
         index,
         open_tracker,
+        policy,
         TierId::Fast,
         TierId::Slow,
         policy.low_watermark(),
@@ -514,6 +858,7 @@ fn evict_cold(
                 router,
                 index,
                 open_tracker,
+                policy,
                 TierId::Slow,
                 TierId::Archive,
                 target_usage,
@@ -534,6 +879,63 @@ fn evict_cold(
     }
 }
 
+/// A `[schedule]` sweep: demote everything past its minimum age, coldest
+/// first, until the budget is spent or the window closes.
+fn scheduled_sweep(
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+    open_tracker: &Arc<OpenFileTracker>,
+    policy: &Arc<dyn TieringPolicy>,
+    clock: &dyn Clock,
+    schedule: &Schedule,
+) {
+    if !schedule.open_at(clock.now()) {
+        info!("tierer: scheduled sweep skipped, outside its window");
+        return;
+    }
+    let mut budget = schedule.max_bytes.unwrap_or(u64::MAX);
+    let mut moved = 0u64;
+    let mut chains = vec![(TierId::Fast, TierId::Slow, policy.min_age_to_evict())];
+    if router.has_archive() {
+        chains.push((TierId::Slow, TierId::Archive, policy.min_age_to_archive()));
+    }
+    info!("tierer: scheduled sweep starting");
+    'chains: for (src_tier, dst_tier, min_age) in chains {
+        let victims = match coldest_past_age(index, policy, src_tier, budget, min_age) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("coldest query for {:?}: {:?}", src_tier, e);
+                continue;
+            }
+        };
+        for (path, size) in victims {
+            if budget == 0 || !schedule.open_at(clock.now()) {
+                break 'chains;
+            }
+            match migrate(router, index, open_tracker, &path, dst_tier) {
+                Ok(true) => {
+                    budget = budget.saturating_sub(size);
+                    moved += size;
+                }
+                Ok(false) => debug!("skipped {} (open or pinned)", path.display()),
+                // The destination is down: every other file would just wait
+                // out the same timeout. Try the next chain.
+                Err(e) if e.is_transient() => {
+                    warn!(
+                        "migrate {}: {}; skipping the rest of {:?}",
+                        path.display(),
+                        e,
+                        dst_tier
+                    );
+                    continue 'chains;
+                }
+                Err(e) => warn!("migrate {}: {:?}", path.display(), e),
+            }
+        }
+    }
+    info!(bytes = moved, "tierer: scheduled sweep done");
+}
+
 fn evict_immutable_to_archive(
     router: &TierRouter,
     index: &Arc<dyn PathIndex>,
@@ -559,16 +961,33 @@ fn evict_immutable_to_archive(
         match migrate(router, index, open_tracker, &path, TierId::Archive) {
             Ok(true) => debug!("immutable demote {} → Archive", path.display()),
             Ok(false) => {}
+            Err(e) if e.is_transient() => {
+                warn!(
+                    "immutable migrate {}: {}; stopping until the next pass",
+                    path.display(),
+                    e
+                );
+                return;
+            }
             Err(e) => warn!("immutable migrate {}: {:?}", path.display(), e),
         }
     }
 }
 
+/// What an eviction chain frees on a tier with `used` of `total` bytes in
+/// use: enough to get back to halfway between the watermarks. Also what
+/// `crate::simulate` replays.
+pub fn bytes_to_free(total: u64, used: u64, low_wm: f64, high_wm: f64) -> u64 {
+    let target_used = (total as f64 * (low_wm + high_wm) / 2.0) as u64;
+    used.saturating_sub(target_used)
+}
+
 #[allow(clippy::too_many_arguments)]
 fn evict_chain(
     router: &TierRouter,
     index: &Arc<dyn PathIndex>,
     open_tracker: &Arc<OpenFileTracker>,
+    policy: &Arc<dyn TieringPolicy>,
     src_tier: TierId,
     dst_tier: TierId,
     low_wm: f64,
@@ -581,11 +1000,8 @@ fn evict_chain(
     if usage <= low_wm {
         return;
     }
-
-    let target_usage = (low_wm + high_wm) / 2.0;
     let (total, used, _free) = capacity_fn();
-    let target_used = (total as f64 * target_usage) as u64;
-    let to_free = used.saturating_sub(target_used);
+    let to_free = bytes_to_free(total, used, low_wm, high_wm);
     if to_free == 0 {
         return;
     }
@@ -597,7 +1013,7 @@ fn evict_chain(
         "tierer: starting eviction chain"
     );
 
-    let victims = match index.coldest(src_tier, to_free, min_age) {
+    let victims = match coldest_past_age(index, policy, src_tier, to_free, min_age) {
         Ok(v) => v,
         Err(e) => {
             warn!("coldest query for {:?}: {:?}", src_tier, e);
@@ -609,11 +1025,32 @@ fn evict_chain(
         match migrate(router, index, open_tracker, &path, dst_tier) {
             Ok(true) => debug!("{:?} -> {:?}: {}", src_tier, dst_tier, path.display()),
             Ok(false) => debug!("skipped {} (open or pinned)", path.display()),
+            Err(e) if e.is_transient() => {
+                warn!(
+                    "migrate {}: {}; stopping the chain until the next pass",
+                    path.display(),
+                    e
+                );
+                return;
+            }
             Err(e) => warn!("migrate {}: {:?}", path.display(), e),
         }
     }
 }
 
+/// The coldest files on `src_tier`, about `target` bytes of them, that
+/// have gone `min_age` without access, or as long as their directory asks
+/// for (`TieringPolicy::min_ages`, set per tenant).
+fn coldest_past_age(
+    index: &Arc<dyn PathIndex>,
+    policy: &Arc<dyn TieringPolicy>,
+    src_tier: TierId,
+    target: u64,
+    min_age: Duration,
+) -> Result<Vec<(PathBuf, u64)>> {
+    index.coldest_with_ages(src_tier, target, min_age, &policy.min_ages(src_tier))
+}
+
 fn full_sweep(index: &Arc<dyn PathIndex>, _policy: &Arc<dyn TieringPolicy>) {
     // Recompute popularity for every file based on the access counts that
     // accumulated since last sweep. This is the autotier "calc_popularity +
@@ -633,6 +1070,7 @@ fn full_sweep(index: &Arc<dyn PathIndex>, _policy: &Arc<dyn TieringPolicy>) {
 mod tests {
     use super::*;
     use crate::backend::PosixBackend;
+    use crate::clock::{MockClock, SystemClock};
     use crate::index::{FileRow, FileState, Location, SqlitePathIndex};
     use crate::tier::{MostFreePlacement, Tier};
     use std::path::PathBuf;
@@ -672,6 +1110,7 @@ mod tests {
             hit_count: 0,
             popularity: 0.0,
             pinned_tier: None,
+            pinned_until: None,
             state: FileState::Stable,
             replicas: Vec::new(),
             mutability: crate::index::Mutability::Unknown,
@@ -706,6 +1145,194 @@ mod tests {
         assert_eq!(got, data);
     }
 
+    #[test]
+    fn migrate_fires_change_hook() {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+        let (router, idx, open) = build(ssd.path(), hdd.path(), &db.path().join("idx.db"));
+        std::fs::write(ssd.path().join("hook.bin"), b"x").unwrap();
+        idx.insert(fixture_row("/hook.bin")).unwrap();
+
+        let seen = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        set_change_hook(Some(Arc::new(move |p: &Path| sink.lock().push(p.to_path_buf()))));
+        migrate(&router, &idx, &open, Path::new("/hook.bin"), TierId::Slow).unwrap();
+        set_change_hook(None);
+        // Other tests may migrate concurrently; only check ours is there.
+        assert!(seen.lock().contains(&PathBuf::from("/hook.bin")));
+    }
+
+    #[test]
+    fn migrate_directory_reports_progress() {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+        let (router, idx, open) = build(ssd.path(), hdd.path(), &db.path().join("idx.db"));
+        // Directories exist on every backend, as FUSE mkdir leaves them.
+        std::fs::create_dir(ssd.path().join("d")).unwrap();
+        std::fs::create_dir(hdd.path().join("d")).unwrap();
+        for (name, data) in [("a", &b"aaaa"[..]), ("b", b"bb"), ("c", b"c")] {
+            std::fs::write(ssd.path().join("d").join(name), data).unwrap();
+            let mut r = fixture_row(&format!("/d/{name}"));
+            r.location.size = data.len() as u64;
+            idx.insert(r).unwrap();
+        }
+        // Indexed but missing on disk: fails without stopping the walk.
+        idx.insert(fixture_row("/d/ghost")).unwrap();
+        open.register(Path::new("/d/c"));
+        idx.insert(fixture_row("/other")).unwrap();
+
+        let mut seen = Vec::new();
+        let done = migrate_directory(
+            &router,
+            &idx,
+            &open,
+            Path::new("/d"),
+            TierId::Slow,
+            &mut |p| seen.push(p.files_moved),
+        )
+        .unwrap();
+        // Once for the plan, then per file moved: a, b.
+        assert_eq!(seen, vec![0, 1, 2]);
+        assert_eq!(done.files_scanned, 4);
+        assert_eq!((done.files_to_move, done.bytes_to_move), (2, 6));
+        assert_eq!(done.files_moved, 2);
+        assert_eq!(done.bytes_moved, 6);
+        assert_eq!(done.files_skipped, 1);
+        assert_eq!(done.failures.len(), 1);
+        assert_eq!(done.failures[0].path, PathBuf::from("/d/ghost"));
+        let tier_of = |p: &str| idx.locate(Path::new(p)).unwrap().unwrap().tier;
+        assert_eq!(tier_of("/d/a"), TierId::Slow);
+        assert_eq!(tier_of("/other"), TierId::Fast);
+    }
+
+    #[test]
+    fn scheduled_sweep_stops_at_its_budget() {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+        let (router, idx, open) = build(ssd.path(), hdd.path(), &db.path().join("idx.db"));
+        for name in ["s1", "s2", "s3"] {
+            std::fs::write(ssd.path().join(name), b"four").unwrap();
+            let mut r = fixture_row(&format!("/{name}"));
+            r.location.size = 4;
+            idx.insert(r).unwrap();
+        }
+        let policy: Arc<dyn TieringPolicy> = Arc::new(crate::policy::PopularityPolicy::default());
+        let schedule = Schedule {
+            sweep: schedule::Cron::parse("0 2 * * *").unwrap(),
+            window: None,
+            max_bytes: Some(6),
+        };
+        scheduled_sweep(&router, &idx, &open, &policy, &SystemClock, &schedule);
+        let on_slow = ["/s1", "/s2", "/s3"]
+            .iter()
+            .filter(|p| idx.locate(Path::new(p)).unwrap().unwrap().tier == TierId::Slow)
+            .count();
+        assert_eq!(on_slow, 2);
+    }
+
+    #[test]
+    fn scheduled_sweep_reads_its_window_off_the_clock() {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+        let (router, idx, open) = build(ssd.path(), hdd.path(), &db.path().join("idx.db"));
+        std::fs::write(ssd.path().join("f"), b"four").unwrap();
+        idx.insert(fixture_row("/f")).unwrap();
+        let policy: Arc<dyn TieringPolicy> = Arc::new(crate::policy::PopularityPolicy::default());
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(10 * 86_400));
+        // Whichever half of the day the mock time falls in, take the other.
+        let window = ["00:00-12:00", "12:00-00:00"]
+            .into_iter()
+            .map(|w| schedule::Window::parse(w).unwrap())
+            .find(|w| !w.contains(clock.now()))
+            .unwrap();
+        let schedule = Schedule {
+            sweep: schedule::Cron::parse("0 2 * * *").unwrap(),
+            window: Some(window),
+            max_bytes: None,
+        };
+        let tier = || idx.locate(Path::new("/f")).unwrap().unwrap().tier;
+        scheduled_sweep(&router, &idx, &open, &policy, &clock, &schedule);
+        assert_eq!(tier(), TierId::Fast);
+        clock.advance(Duration::from_secs(12 * 3600));
+        scheduled_sweep(&router, &idx, &open, &policy, &clock, &schedule);
+        assert_eq!(tier(), TierId::Slow);
+    }
+
+    #[test]
+    fn pins_expire_by_the_clock() {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+        let (_router, idx, _open) = build(ssd.path(), hdd.path(), &db.path().join("idx.db"));
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(86_400));
+        idx.insert(FileRow {
+            pinned_tier: Some(TierId::Fast),
+            pinned_until: Some(clock.now() + Duration::from_secs(60)),
+            ..fixture_row("/p")
+        })
+        .unwrap();
+        let pinned = || idx.get(Path::new("/p")).unwrap().unwrap().pinned_tier;
+        expire_pins(&idx, &clock);
+        assert_eq!(pinned(), Some(TierId::Fast));
+        clock.advance(Duration::from_secs(61));
+        expire_pins(&idx, &clock);
+        assert_eq!(pinned(), None);
+    }
+
+    #[test]
+    fn tenants_keep_their_own_minimum_ages() {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+        let (_router, idx, _open) = build(ssd.path(), hdd.path(), &db.path().join("idx.db"));
+        let ago = |mins: u64| SystemTime::now() - Duration::from_secs(mins * 60);
+        for (path, idle) in [
+            ("/a/old", 120),
+            ("/a/new", 20),
+            ("/b/old", 120),
+            ("/z", 120),
+        ] {
+            let mut r = fixture_row(path);
+            r.location.size = 1;
+            r.last_access = ago(idle);
+            idx.insert(r).unwrap();
+        }
+        let tenants = crate::tenant::parse(
+            &toml::from_str("[a]\nmin_age_to_evict_secs = 600\n[b]\nmin_age_to_evict_secs = 86400")
+                .unwrap(),
+        )
+        .unwrap();
+        let policy: Arc<dyn TieringPolicy> = crate::tenant::TenantPolicy::new(
+            Arc::new(crate::policy::PopularityPolicy::default()),
+            crate::tenant::Tenants::new(tenants),
+        );
+        let hour = Duration::from_secs(3600);
+        let mut got: Vec<PathBuf> = coldest_past_age(&idx, &policy, TierId::Fast, u64::MAX, hour)
+            .unwrap()
+            .into_iter()
+            .map(|v| v.0)
+            .collect();
+        got.sort();
+        // `a` lets files go after ten minutes, `b` holds them for a day.
+        assert_eq!(
+            got,
+            [Path::new("/a/new"), Path::new("/a/old"), Path::new("/z")]
+        );
+        // The archive chain has no overrides: the policy's age for all.
+        assert!(policy.min_ages(TierId::Slow).is_empty());
+        // Still stops once the target is met.
+        assert_eq!(
+            coldest_past_age(&idx, &policy, TierId::Fast, 2, hour)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
     #[test]
     fn migrate_skips_open_files() {
         let ssd = TempDir::new().unwrap();
@@ -743,6 +1370,19 @@ mod tests {
         assert!(!moved);
     }
 
+    #[test]
+    fn kernel_copies_are_complete() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        let data: Vec<u8> = (0..5_000_000u32).map(|i| (i % 253) as u8).collect();
+        std::fs::write(&src, &data).unwrap();
+        std::fs::write(&dst, b"stale bytes past the end of nothing").unwrap();
+        if clone_file(&src, &dst) || kernel_copy(&src, &dst) {
+            assert_eq!(std::fs::read(&dst).unwrap(), data);
+        }
+        assert!(!kernel_copy(&dir.path().join("missing"), &dst));
+    }
+
     #[test]
     fn migrate_preserves_mtime() {
         let ssd = TempDir::new().unwrap();
@@ -34,6 +34,23 @@ impl OpenFileTracker {
         }
     }
 
+    /// `from` was renamed to `to`; open files at or below it keep
+    /// counting under their new paths.
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let mut g = self.counts.lock();
+        let moved: Vec<PathBuf> = g.keys().filter(|p| p.starts_with(from)).cloned().collect();
+        for old in moved {
+            if let (Some(c), Ok(rest)) = (g.remove(&old), old.strip_prefix(from)) {
+                let new = if rest.as_os_str().is_empty() {
+                    to.to_path_buf()
+                } else {
+                    to.join(rest)
+                };
+                *g.entry(new).or_insert(0) += c;
+            }
+        }
+    }
+
     pub fn is_open(&self, path: &Path) -> bool {
         self.counts.lock().get(path).copied().unwrap_or(0) > 0
     }
@@ -0,0 +1,171 @@
+//! Metadata-only migration planning.
+//!
+//! Deciding what a directory migration will move needs nothing but index
+//! rows and a listing of each source backend: rows already on the target,
+//! pinned or open are skipped, and rows whose file is gone are reported
+//! instead of failing halfway through a copy. No file is opened or read
+//! until `migrate` actually moves it, so checking a whole mount costs one
+//! directory walk per backend rather than a stat (or hash) per file.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::index::{FileRow, PathIndex, TierId};
+use crate::tier::TierRouter;
+
+use super::{compressed_or_raw, OpenFileTracker};
+
+/// Below this many rows on one backend, stat them one by one; walking a
+/// large backend to check a handful of files costs more than it saves.
+const WALK_ABOVE: usize = 256;
+
+/// What migrating a directory to one tier would do.
+#[derive(Debug, Default)]
+pub struct MigrationPlan {
+    /// Indexed files at or below the directory.
+    pub scanned: u64,
+    /// Rows to move, in path order.
+    pub moves: Vec<FileRow>,
+    /// Their total size.
+    pub bytes: u64,
+    /// Rows left alone: open, pinned, or already on the target tier.
+    pub skipped: u64,
+    /// Rows whose file isn't on their backend.
+    pub missing: Vec<PathBuf>,
+}
+
+/// Plan moving every indexed file at or below `dir` to `target_tier`.
+pub fn plan_migration(
+    router: &TierRouter,
+    index: &Arc<dyn PathIndex>,
+    open: &OpenFileTracker,
+    dir: &Path,
+    target_tier: TierId,
+) -> Result<MigrationPlan> {
+    let rows = index.list_under(dir)?;
+    let mut plan = MigrationPlan {
+        scanned: rows.len() as u64,
+        ..Default::default()
+    };
+    let mut by_backend: HashMap<(TierId, String), Vec<FileRow>> = HashMap::new();
+    for row in rows {
+        if row.location.tier == target_tier
+            || row.pinned_tier.is_some_and(|t| t != target_tier)
+            || open.is_open(&row.logical_path)
+        {
+            plan.skipped += 1;
+            continue;
+        }
+        by_backend
+            .entry((row.location.tier, row.location.backend_id.clone()))
+            .or_default()
+            .push(row);
+    }
+
+    for ((tier, id), rows) in by_backend {
+        let Some(backend) = router.resolve_backend(tier, &id) else {
+            plan.missing
+                .extend(rows.into_iter().map(|r| r.logical_path));
+            continue;
+        };
+        let listed: Option<HashSet<PathBuf>> = (rows.len() > WALK_ABOVE)
+            .then(|| backend.walk_files().filter_map(|p| p.ok()).collect());
+        for row in rows {
+            let on_disk = compressed_or_raw(&row.location.backend_path, row.compressed);
+            let present = match &listed {
+                Some(set) => set.contains(&on_disk),
+                None => backend.exists(&on_disk).unwrap_or(false),
+            };
+            if present {
+                plan.bytes += row.location.size;
+                plan.moves.push(row);
+            } else {
+                plan.missing.push(row.logical_path);
+            }
+        }
+    }
+    plan.moves
+        .sort_by(|a, b| a.logical_path.cmp(&b.logical_path));
+    plan.missing.sort();
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, PosixBackend};
+    use crate::index::{FileState, Location, Mutability, SqlitePathIndex};
+    use crate::tier::{MostFreePlacement, Tier};
+    use std::time::UNIX_EPOCH;
+    use tempfile::TempDir;
+
+    fn row(path: &str, size: u64) -> FileRow {
+        FileRow {
+            logical_path: PathBuf::from(path),
+            location: Location {
+                tier: TierId::Fast,
+                backend_id: "ssd".into(),
+                backend_path: PathBuf::from(path.trim_start_matches('/')),
+                size,
+            },
+            last_access: UNIX_EPOCH,
+            hit_count: 0,
+            popularity: 0.0,
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            replicas: Vec::new(),
+            mutability: Mutability::Unknown,
+            compressed: false,
+            content_hash: None,
+        }
+    }
+
+    #[test]
+    fn plans_from_a_single_walk() {
+        let ssd = TempDir::new().unwrap();
+        let hdd = TempDir::new().unwrap();
+        let db = TempDir::new().unwrap();
+        let ssd_b: Arc<dyn Backend> =
+            Arc::new(PosixBackend::new("ssd", ssd.path().to_path_buf()).unwrap());
+        let hdd_b: Arc<dyn Backend> =
+            Arc::new(PosixBackend::new("hdd", hdd.path().to_path_buf()).unwrap());
+        let router = TierRouter::new(
+            Tier::new(TierId::Fast, vec![ssd_b], Box::new(MostFreePlacement)).unwrap(),
+            Tier::new(TierId::Slow, vec![hdd_b], Box::new(MostFreePlacement)).unwrap(),
+        );
+        let idx = SqlitePathIndex::open(db.path().join("idx.db")).unwrap() as Arc<dyn PathIndex>;
+        let open = OpenFileTracker::new();
+
+        // Enough rows that the backend is listed rather than stat'ed.
+        std::fs::create_dir(ssd.path().join("d")).unwrap();
+        for i in 0..WALK_ABOVE {
+            std::fs::write(ssd.path().join(format!("d/{i:04}")), b"xy").unwrap();
+            idx.insert(row(&format!("/d/{i:04}"), 2)).unwrap();
+        }
+        // Compressed files are found under their `.zst` name.
+        std::fs::write(ssd.path().join("d/z.zst"), b"z").unwrap();
+        idx.insert(FileRow {
+            compressed: true,
+            ..row("/d/z", 1)
+        })
+        .unwrap();
+        idx.insert(row("/d/ghost", 5)).unwrap();
+        idx.insert(FileRow {
+            pinned_tier: Some(TierId::Fast),
+            ..row("/d/pinned", 7)
+        })
+        .unwrap();
+
+        let plan = plan_migration(&router, &idx, &open, Path::new("/d"), TierId::Slow).unwrap();
+        assert_eq!(plan.scanned, WALK_ABOVE as u64 + 3);
+        assert_eq!(plan.moves.len(), WALK_ABOVE + 1);
+        assert_eq!(plan.bytes, 2 * WALK_ABOVE as u64 + 1);
+        assert_eq!(plan.skipped, 1);
+        assert_eq!(plan.missing, vec![PathBuf::from("/d/ghost")]);
+        // Planning moved nothing.
+        assert!(ssd.path().join("d/0000").exists());
+    }
+}
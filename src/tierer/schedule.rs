@@ -0,0 +1,264 @@
+//! Scheduled sweeps, configured under `[schedule]`:
+//!
+//! ```toml
+//! [schedule]
+//! sweep     = "0 2 * * *"     # cron: minute hour day-of-month month day-of-week
+//! window    = "02:00-06:00"   # stop moving files once this closes
+//! max_bytes = "500G"          # per-run budget
+//! ```
+//!
+//! The watermark cycle only evicts when the fast tier fills up. A sweep
+//! instead demotes everything old enough to go (`min_age_to_evict`, and
+//! `min_age_to_archive` when an archive tier exists), coldest first, so
+//! bulk moves happen at a quiet hour rather than whenever usage crosses
+//! a line. Times are local. Like `crate::throttle`, the schedule is
+//! process-wide, set at mount and again on SIGHUP.
+
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{FsError, Result};
+
+/// A five-field cron expression. Fields take `*`, `n`, `a-b` and
+/// comma lists, each optionally stepped (`*/15`, `1-5/2`); day-of-week
+/// runs 0-7 with both ends meaning Sunday. Names aren't supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day-of-month / day-of-week were `*`. When both are
+    /// restricted, a day matching either one will do.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [min, hour, dom, mon, dow] = fields[..] else {
+            return Err(FsError::Storage(format!(
+                "cron needs 5 fields (minute hour day month weekday), got {:?}",
+                expr
+            )));
+        };
+        let mut weekdays = field(dow, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: field(min, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(dom, 1, 31)?,
+            months: field(mon, 1, 12)?,
+            weekdays,
+            any_day: dom == "*",
+            any_weekday: dow == "*",
+        })
+    }
+
+    fn matches(&self, t: &LocalTime) -> bool {
+        let bit = |set: u64, v: u32| set & (1 << v) != 0;
+        let day = bit(self.days, t.day);
+        let weekday = bit(self.weekdays, t.weekday);
+        let day_ok = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        bit(self.minutes, t.minute)
+            && bit(self.hours, t.hour)
+            && bit(self.months, t.month)
+            && day_ok
+    }
+
+    /// The first whole minute after `t` the expression matches, searching
+    /// a year ahead. `None` for dates that never come (`0 0 31 2 *`).
+    pub fn next_after(&self, t: SystemTime) -> Option<SystemTime> {
+        let secs = t.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let first = (secs / 60 + 1) * 60;
+        (0..366 * 24 * 60)
+            .map(|m| UNIX_EPOCH + Duration::from_secs(first + m * 60))
+            .find(|&at| self.matches(&LocalTime::at(at)))
+    }
+}
+
+/// Bitmask of the values one cron field allows.
+fn field(spec: &str, lo: u32, hi: u32) -> Result<u64> {
+    let bad = || FsError::Storage(format!("bad cron field {spec:?} (allowed {lo}-{hi})"));
+    let mut set = 0u64;
+    for item in spec.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>().map_err(|_| bad())?),
+            None => (item, 1),
+        };
+        let (from, to) = match range {
+            "*" => (lo, hi),
+            r => match r.split_once('-') {
+                Some((a, b)) => (a.parse().map_err(|_| bad())?, b.parse().map_err(|_| bad())?),
+                None => {
+                    let v = r.parse().map_err(|_| bad())?;
+                    (v, if step > 1 { hi } else { v })
+                }
+            },
+        };
+        if step == 0 || from < lo || to > hi || from > to {
+            return Err(bad());
+        }
+        for v in (from..=to).step_by(step as usize) {
+            set |= 1 << v;
+        }
+    }
+    Ok(set)
+}
+
+/// `HH:MM-HH:MM`, local time. May wrap midnight (`22:00-04:00`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let bad = || FsError::Storage(format!("window must be HH:MM-HH:MM, got {spec:?}"));
+        let minute_of_day = |s: &str| -> Result<u32> {
+            let (h, m) = s.trim().split_once(':').ok_or_else(bad)?;
+            match (h.parse::<u32>(), m.parse::<u32>()) {
+                (Ok(h), Ok(m)) if h < 24 && m < 60 => Ok(h * 60 + m),
+                _ => Err(bad()),
+            }
+        };
+        let (start, end) = spec.split_once('-').ok_or_else(bad)?;
+        let w = Self {
+            start: minute_of_day(start)?,
+            end: minute_of_day(end)?,
+        };
+        if w.start == w.end {
+            return Err(bad());
+        }
+        Ok(w)
+    }
+
+    pub fn contains(&self, t: SystemTime) -> bool {
+        let lt = LocalTime::at(t);
+        let m = lt.hour * 60 + lt.minute;
+        if self.start < self.end {
+            (self.start..self.end).contains(&m)
+        } else {
+            m >= self.start || m < self.end
+        }
+    }
+}
+
+/// When sweeps run and how much each may move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub sweep: Cron,
+    pub window: Option<Window>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Schedule {
+    /// Whether a sweep may move files at `t`.
+    pub fn open_at(&self, t: SystemTime) -> bool {
+        self.window.is_none_or(|w| w.contains(t))
+    }
+}
+
+static SCHEDULE: RwLock<Option<Arc<Schedule>>> = RwLock::new(None);
+
+/// Install the sweep schedule, or stop scheduled sweeps with `None`.
+pub fn set_schedule(schedule: Option<Schedule>) {
+    *SCHEDULE.write().unwrap_or_else(|e| e.into_inner()) = schedule.map(Arc::new);
+}
+
+pub fn current() -> Option<Arc<Schedule>> {
+    SCHEDULE.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Broken-down local time, as cron fields count it.
+struct LocalTime {
+    minute: u32,
+    hour: u32,
+    day: u32,
+    month: u32,
+    /// 0 = Sunday.
+    weekday: u32,
+}
+
+impl LocalTime {
+    fn at(t: SystemTime) -> Self {
+        let secs = t
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let secs = secs as libc::time_t;
+        // SAFETY: localtime_r only writes the `tm` we hand it.
+        let tm = unsafe {
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&secs, &mut tm);
+            tm
+        };
+        Self {
+            minute: tm.tm_min as u32,
+            hour: tm.tm_hour as u32,
+            day: tm.tm_mday as u32,
+            month: tm.tm_mon as u32 + 1,
+            weekday: tm.tm_wday as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cron_fields_parse() {
+        assert_eq!(
+            field("*/15", 0, 59).unwrap(),
+            1 | 1 << 15 | 1 << 30 | 1 << 45
+        );
+        assert_eq!(
+            field("1-5/2,9", 0, 59).unwrap(),
+            1 << 1 | 1 << 3 | 1 << 5 | 1 << 9
+        );
+        assert!(Cron::parse("0 2 * *").is_err());
+        assert!(Cron::parse("60 2 * * *").is_err());
+        assert!(Cron::parse("0 2 * * mon").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        // 7 is Sunday too.
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays, 1 | 1 << 7);
+    }
+
+    #[test]
+    fn next_run_is_the_next_matching_minute() {
+        let now = SystemTime::now();
+        let at = Cron::parse("* * * * *").unwrap().next_after(now).unwrap();
+        let wait = at.duration_since(now).unwrap();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(60));
+
+        let lt = LocalTime::at(now);
+        let hourly = Cron::parse(&format!("{} * * * *", lt.minute)).unwrap();
+        let wait = hourly.next_after(now).unwrap().duration_since(now).unwrap();
+        assert!(wait > Duration::from_secs(59 * 60) && wait <= Duration::from_secs(3600));
+
+        assert_eq!(Cron::parse("0 0 31 2 *").unwrap().next_after(now), None);
+    }
+
+    #[test]
+    fn windows_may_wrap_midnight() {
+        let lt = LocalTime::at(SystemTime::now());
+        let now = lt.hour * 60 + lt.minute;
+        let hhmm = |m: u32| format!("{:02}:{:02}", m / 60 % 24, m % 60);
+        let around = Window::parse(&format!("{}-{}", hhmm(now + 1440 - 1), hhmm(now + 2))).unwrap();
+        assert!(around.contains(SystemTime::now()));
+        let later = Window::parse(&format!("{}-{}", hhmm(now + 5), hhmm(now + 10))).unwrap();
+        assert!(!later.contains(SystemTime::now()));
+        assert!(Window::parse("02:00").is_err());
+        assert!(Window::parse("25:00-03:00").is_err());
+        assert!(Window::parse("02:00-02:00").is_err());
+    }
+}
@@ -0,0 +1,387 @@
+//! Soft delete: with `[trash] enabled = true`, `unlink` through the mount
+//! moves the file into `.rhss-trash/<id>/` on the backend that held it
+//! (a same-backend rename, so no data is copied) instead of removing it.
+//!
+//! Each entry directory holds the bytes (`data`) and an `entry.json`
+//! describing where they came from, so `rhss trash restore` can put the
+//! file back and re-index it. Entries older than the retention period are
+//! purged by the daemon. The trash dir is reserved (`crate::filter`): it
+//! never shows up in the mount or the index.
+//!
+//! Restored files come back with fresh popularity stats and without a
+//! dedup link; a deduped file is only trashed when its last reference goes.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::backend::Backend;
+use crate::clock::{Clock, SystemClock};
+use crate::error::{FsError, Result};
+use crate::index::{FileRow, FileState, Location, Mutability, PathIndex, TierId};
+use crate::tier::TierRouter;
+
+/// Trash directory name at every backend root.
+pub const TRASH_DIR: &str = ".rhss-trash";
+
+const DATA: &str = "data";
+const ENTRY: &str = "entry.json";
+
+/// One trashed file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: String,
+    pub logical: PathBuf,
+    pub tier: String,
+    pub backend: String,
+    /// Index `backend_path` before deletion.
+    pub backend_path: PathBuf,
+    /// The file actually moved (`.zst` suffix when compressed).
+    pub on_disk: PathBuf,
+    pub size: u64,
+    pub compressed: bool,
+    /// Unix seconds.
+    pub deleted_at: u64,
+}
+
+impl TrashEntry {
+    fn dir(&self) -> PathBuf {
+        Path::new(TRASH_DIR).join(&self.id)
+    }
+
+    pub fn age(&self, now: SystemTime) -> Duration {
+        let deleted = UNIX_EPOCH + Duration::from_secs(self.deleted_at);
+        now.duration_since(deleted).unwrap_or_default()
+    }
+}
+
+/// What `purge` removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PurgeScope<'a> {
+    /// Entries older than the retention period.
+    Expired,
+    All,
+    One(&'a str),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PurgeStats {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub struct Trash {
+    retention: Duration,
+    seq: AtomicU64,
+    /// Stamps `deleted_at` and decides what has expired.
+    clock: Arc<dyn Clock>,
+}
+
+impl Trash {
+    pub fn new(retention: Duration) -> Arc<Self> {
+        Self::with_clock(retention, Arc::new(SystemClock))
+    }
+
+    /// `new`, with entry ages measured against `clock` (`crate::clock`).
+    pub fn with_clock(retention: Duration, clock: Arc<dyn Clock>) -> Arc<Self> {
+        Arc::new(Self {
+            retention,
+            seq: AtomicU64::new(0),
+            clock,
+        })
+    }
+
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    fn next_id(&self) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let n = self.seq.fetch_add(1, Ordering::Relaxed);
+        format!("{nanos:x}-{}-{n}", std::process::id())
+    }
+
+    /// Move `on_disk` (the file behind `row`) into `backend`'s trash.
+    pub fn stash(
+        &self,
+        backend: &Arc<dyn Backend>,
+        row: &FileRow,
+        on_disk: &Path,
+    ) -> Result<TrashEntry> {
+        let entry = TrashEntry {
+            id: self.next_id(),
+            logical: row.logical_path.clone(),
+            tier: row.location.tier.as_str().to_string(),
+            backend: backend.id().to_string(),
+            backend_path: row.location.backend_path.clone(),
+            on_disk: on_disk.to_path_buf(),
+            size: row.location.size,
+            compressed: row.compressed,
+            deleted_at: self
+                .clock
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        let dir = entry.dir();
+        backend.create_dir(&dir)?;
+        let body = serde_json::to_vec_pretty(&entry)?;
+        backend.write_at(&dir.join(ENTRY), 0, &body)?;
+        if let Err(e) = backend.rename(on_disk, &dir.join(DATA)) {
+            let _ = backend.remove(&dir.join(ENTRY));
+            let _ = backend.remove(&dir);
+            return Err(e);
+        }
+        info!(
+            "trash: {} → {}:{}",
+            entry.logical.display(),
+            entry.backend,
+            dir.display()
+        );
+        Ok(entry)
+    }
+
+    /// Every entry on every backend, oldest first.
+    pub fn list(&self, router: &TierRouter) -> Result<Vec<TrashEntry>> {
+        let mut out = Vec::new();
+        for (_tier, backend) in router.all_backends() {
+            out.extend(list_backend(backend));
+        }
+        out.sort_by(|a, b| (a.deleted_at, &a.id).cmp(&(b.deleted_at, &b.id)));
+        Ok(out)
+    }
+
+    /// Put entry `id` back where it was and index it again. Fails if the
+    /// logical path has since been reused.
+    pub fn restore(
+        &self,
+        router: &TierRouter,
+        index: &Arc<dyn PathIndex>,
+        id: &str,
+    ) -> Result<TrashEntry> {
+        let (tier, backend, entry) = find(router, id)?;
+        if index.locate(&entry.logical)?.is_some() {
+            return Err(FsError::AlreadyExists(entry.logical.display().to_string()));
+        }
+        if let Some(parent) = entry.on_disk.parent() {
+            if !parent.as_os_str().is_empty() {
+                backend.create_dir(parent)?;
+            }
+        }
+        let dir = entry.dir();
+        backend.rename(&dir.join(DATA), &entry.on_disk)?;
+        let meta = backend.metadata(&entry.on_disk)?;
+        index.insert(FileRow {
+            logical_path: entry.logical.clone(),
+            location: Location {
+                tier,
+                backend_id: backend.id().to_string(),
+                backend_path: entry.backend_path.clone(),
+                size: entry.size,
+            },
+            replicas: Vec::new(),
+            last_access: meta.mtime,
+            hit_count: 0,
+            popularity: 0.0,
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            mutability: Mutability::Unknown,
+            compressed: entry.compressed,
+            content_hash: None,
+        })?;
+        let _ = backend.remove(&dir.join(ENTRY));
+        let _ = backend.remove(&dir);
+        info!("trash: restored {}", entry.logical.display());
+        Ok(entry)
+    }
+
+    pub fn purge(&self, router: &TierRouter, scope: PurgeScope<'_>) -> Result<PurgeStats> {
+        let now = self.clock.now();
+        let mut stats = PurgeStats::default();
+        let mut found = false;
+        for (_tier, backend) in router.all_backends() {
+            for e in list_backend(backend) {
+                let hit = match scope {
+                    PurgeScope::Expired => e.age(now) >= self.retention,
+                    PurgeScope::All => true,
+                    PurgeScope::One(id) => e.id == id,
+                };
+                if !hit {
+                    continue;
+                }
+                found = true;
+                let dir = e.dir();
+                for p in [dir.join(DATA), dir.join(ENTRY), dir] {
+                    if let Err(e) = backend.remove(&p) {
+                        if !matches!(e, FsError::NotFound(_)) {
+                            warn!("trash: remove {}: {e}", p.display());
+                        }
+                    }
+                }
+                stats.entries += 1;
+                stats.bytes += e.size;
+            }
+        }
+        if let PurgeScope::One(id) = scope {
+            if !found {
+                return Err(FsError::NotFound(format!("trash entry {id}")));
+            }
+        }
+        Ok(stats)
+    }
+}
+
+fn list_backend(backend: &Arc<dyn Backend>) -> Vec<TrashEntry> {
+    let Ok(ids) = backend.list_dir(Path::new(TRASH_DIR)) else {
+        return Vec::new();
+    };
+    ids.into_iter()
+        .filter_map(|id| {
+            let path = Path::new(TRASH_DIR).join(&id).join(ENTRY);
+            let size = backend.metadata(&path).ok()?.size;
+            let body = backend.read_at(&path, 0, size as u32).ok()?;
+            match serde_json::from_slice::<TrashEntry>(&body) {
+                Ok(e) => Some(e),
+                Err(err) => {
+                    warn!("trash: unreadable {}: {err}", path.display());
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn find(router: &TierRouter, id: &str) -> Result<(TierId, Arc<dyn Backend>, TrashEntry)> {
+    for (tier, backend) in router.all_backends() {
+        if let Some(e) = list_backend(backend).into_iter().find(|e| e.id == id) {
+            return Ok((tier, Arc::clone(backend), e));
+        }
+    }
+    Err(FsError::NotFound(format!("trash entry {id}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::PosixBackend;
+    use crate::clock::MockClock;
+    use crate::index::SqlitePathIndex;
+    use crate::tier::{MostFreePlacement, Tier};
+    use tempfile::TempDir;
+
+    fn setup() -> (Vec<TempDir>, TierRouter, Arc<dyn PathIndex>) {
+        let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+        let ssd: Arc<dyn Backend> =
+            Arc::new(PosixBackend::new("ssd", dirs[0].path().to_path_buf()).unwrap());
+        let hdd: Arc<dyn Backend> =
+            Arc::new(PosixBackend::new("hdd", dirs[1].path().to_path_buf()).unwrap());
+        let router = TierRouter::new(
+            Tier::new(TierId::Fast, vec![ssd], Box::new(MostFreePlacement)).unwrap(),
+            Tier::new(TierId::Slow, vec![hdd], Box::new(MostFreePlacement)).unwrap(),
+        );
+        let index =
+            SqlitePathIndex::open(dirs[2].path().join("idx.db")).unwrap() as Arc<dyn PathIndex>;
+        (dirs, router, index)
+    }
+
+    fn row() -> FileRow {
+        FileRow {
+            logical_path: PathBuf::from("/docs/a.txt"),
+            location: Location {
+                tier: TierId::Fast,
+                backend_id: "ssd".into(),
+                backend_path: PathBuf::from("docs/a.txt"),
+                size: 5,
+            },
+            replicas: Vec::new(),
+            last_access: UNIX_EPOCH,
+            hit_count: 3,
+            popularity: 1.0,
+            pinned_tier: None,
+            pinned_until: None,
+            state: FileState::Stable,
+            mutability: Mutability::Unknown,
+            compressed: false,
+            content_hash: None,
+        }
+    }
+
+    #[test]
+    fn stash_list_restore_round_trip() {
+        let (dirs, router, index) = setup();
+        let ssd = router.resolve_backend(TierId::Fast, "ssd").unwrap();
+        ssd.create_dir(Path::new("docs")).unwrap();
+        ssd.write_at(Path::new("docs/a.txt"), 0, b"hello").unwrap();
+        let trash = Trash::new(Duration::from_secs(3600));
+
+        let e = trash.stash(ssd, &row(), Path::new("docs/a.txt")).unwrap();
+        assert!(!dirs[0].path().join("docs/a.txt").exists());
+        assert_eq!(trash.list(&router).unwrap(), vec![e.clone()]);
+
+        trash.restore(&router, &index, &e.id).unwrap();
+        assert_eq!(
+            std::fs::read(dirs[0].path().join("docs/a.txt")).unwrap(),
+            b"hello"
+        );
+        let back = index.get(Path::new("/docs/a.txt")).unwrap().unwrap();
+        assert_eq!(back.location.backend_id, "ssd");
+        assert!(trash.list(&router).unwrap().is_empty());
+        // Path is taken again: a second restore of the same id can't happen.
+        assert!(trash.restore(&router, &index, &e.id).is_err());
+    }
+
+    #[test]
+    fn purge_respects_retention_and_ids() {
+        let (_dirs, router, _index) = setup();
+        let ssd = router.resolve_backend(TierId::Fast, "ssd").unwrap();
+        let trash = Trash::new(Duration::from_secs(3600));
+        ssd.create_dir(Path::new("docs")).unwrap();
+        ssd.write_at(Path::new("docs/a.txt"), 0, b"hello").unwrap();
+        let e = trash.stash(ssd, &row(), Path::new("docs/a.txt")).unwrap();
+
+        assert_eq!(
+            trash.purge(&router, PurgeScope::Expired).unwrap().entries,
+            0
+        );
+        assert!(trash.purge(&router, PurgeScope::One("nope")).is_err());
+        let st = trash.purge(&router, PurgeScope::One(&e.id)).unwrap();
+        assert_eq!((st.entries, st.bytes), (1, 5));
+        assert!(trash.list(&router).unwrap().is_empty());
+        assert!(!ssd
+            .exists(Path::new(TRASH_DIR).join(&e.id).as_path())
+            .unwrap());
+    }
+
+    #[test]
+    fn entries_expire_after_retention() {
+        let (_dirs, router, _index) = setup();
+        let ssd = router.resolve_backend(TierId::Fast, "ssd").unwrap();
+        let clock = Arc::new(MockClock::default());
+        let trash = Trash::with_clock(Duration::from_secs(300), clock.clone());
+        ssd.create_dir(Path::new("docs")).unwrap();
+        ssd.write_at(Path::new("docs/a.txt"), 0, b"hello").unwrap();
+        trash.stash(ssd, &row(), Path::new("docs/a.txt")).unwrap();
+
+        clock.advance(Duration::from_secs(299));
+        assert_eq!(
+            trash.purge(&router, PurgeScope::Expired).unwrap().entries,
+            0
+        );
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            trash.purge(&router, PurgeScope::Expired).unwrap().entries,
+            1
+        );
+        assert!(trash.list(&router).unwrap().is_empty());
+    }
+}
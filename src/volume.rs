@@ -0,0 +1,279 @@
+//! Volumes: named slices of the configured tiers, each mounted, tiered and
+//! locked on its own. `rhss volume create|delete|mount|unmount` drive them
+//! and are what a CSI driver would call.
+//!
+//! A volume `NAME` keeps its files in `.rhss-volumes/NAME` under every
+//! fast- and slow-tier root (and replica); the main mount hides that
+//! directory. Its index, storage lock, control socket and `volume.toml`
+//! live in `volumes/NAME` next to the main index. `volume.toml` holds what
+//! the volume does differently:
+//!
+//! ```toml
+//! capacity = "10G"          # quota on the whole volume
+//! mount = "/mnt/vol/NAME"   # where `rhss volume mount` last put it
+//!
+//! [policy]                  # same fields as the main [policy]
+//! high_watermark = 0.8
+//! ```
+//!
+//! Everything else comes from the main config. Archive backends are not
+//! shared with volumes, and RAM (`mem://`) backends are per process
+//! anyway. `create` is idempotent for an identical spec, and `delete` of a
+//! volume that isn't there succeeds, as CSI expects.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{PolicyOptions, RhssConfig};
+use crate::error::{FsError, Result};
+use crate::quota::{parse_size, LimitSpec};
+
+/// Directory under each backend root holding the volumes' files.
+pub const VOLUMES_DIR: &str = ".rhss-volumes";
+
+const SPEC_FILE: &str = "volume.toml";
+
+/// What `rhss volume create` records for a volume.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VolumeSpec {
+    /// Size limit for the whole volume, e.g. `"10G"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<String>,
+    /// Where the volume was last mounted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<PathBuf>,
+    /// Overrides the main `[policy]` as a whole.
+    #[serde(default)]
+    pub policy: PolicyOptions,
+}
+
+impl VolumeSpec {
+    fn validate(&self) -> Result<()> {
+        if let Some(c) = &self.capacity {
+            parse_size(c)?;
+        }
+        self.policy.validate()
+    }
+
+    /// Same volume as far as `create` is concerned: the mount point is
+    /// not part of the spec.
+    fn same_as(&self, other: &VolumeSpec) -> bool {
+        self.capacity == other.capacity && self.policy == other.policy
+    }
+}
+
+/// Names go into paths, so: 1–128 of `[A-Za-z0-9._-]`, starting with a
+/// letter or digit.
+pub fn validate_name(name: &str) -> Result<()> {
+    let ok = (1..=128).contains(&name.len())
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if ok {
+        Ok(())
+    } else {
+        Err(FsError::InvalidOperation(format!(
+            "bad volume name {name:?}: use 1-128 of A-Z a-z 0-9 . _ -, starting with a letter or digit"
+        )))
+    }
+}
+
+/// Where volume `name` keeps its index, lock and `volume.toml`.
+pub fn meta_dir(cfg: &RhssConfig, name: &str) -> PathBuf {
+    cfg.lock_dir().join("volumes").join(name)
+}
+
+/// Volume `name`'s directory under the backend root `root`.
+pub fn data_dir(root: &Path, name: &str) -> PathBuf {
+    root.join(VOLUMES_DIR).join(name)
+}
+
+/// Every directory holding volume `name`'s files.
+fn data_dirs(cfg: &RhssConfig, name: &str) -> Vec<PathBuf> {
+    cfg.tier
+        .fast
+        .iter()
+        .chain(&cfg.tier.slow)
+        .filter(|b| b.memory_size().is_none())
+        .flat_map(|b| std::iter::once(&b.root).chain(&b.replicas))
+        .map(|root| data_dir(root, name))
+        .collect()
+}
+
+fn io_err(e: std::io::Error, path: &Path) -> FsError {
+    FsError::from_io(e, path.display())
+}
+
+/// The spec of volume `name`; `NotFound` if it doesn't exist.
+pub fn load(cfg: &RhssConfig, name: &str) -> Result<VolumeSpec> {
+    validate_name(name)?;
+    let path = meta_dir(cfg, name).join(SPEC_FILE);
+    let raw = std::fs::read_to_string(&path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => FsError::NotFound(format!("volume {name}")),
+        _ => io_err(e, &path),
+    })?;
+    toml::from_str(&raw).map_err(|e| FsError::Storage(format!("{}: {e}", path.display())))
+}
+
+fn store(cfg: &RhssConfig, name: &str, spec: &VolumeSpec) -> Result<()> {
+    let dir = meta_dir(cfg, name);
+    std::fs::create_dir_all(&dir).map_err(|e| io_err(e, &dir))?;
+    let text = toml::to_string(spec).map_err(|e| FsError::Storage(e.to_string()))?;
+    // Write-then-rename so a crash never leaves half a spec.
+    let tmp = dir.join(format!("{SPEC_FILE}.tmp"));
+    std::fs::write(&tmp, text).map_err(|e| io_err(e, &tmp))?;
+    let path = dir.join(SPEC_FILE);
+    std::fs::rename(&tmp, &path).map_err(|e| io_err(e, &path))
+}
+
+/// Create volume `name`. `Ok(false)` if it already exists with the same
+/// spec; `AlreadyExists` if it exists with a different one.
+pub fn create(cfg: &RhssConfig, name: &str, spec: &VolumeSpec) -> Result<bool> {
+    validate_name(name)?;
+    spec.validate()?;
+    match load(cfg, name) {
+        Ok(existing) if existing.same_as(spec) => return Ok(false),
+        Ok(_) => {
+            return Err(FsError::AlreadyExists(format!(
+                "volume {name} exists with a different spec"
+            )))
+        }
+        Err(FsError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+    for dir in data_dirs(cfg, name) {
+        std::fs::create_dir_all(&dir).map_err(|e| io_err(e, &dir))?;
+    }
+    // The spec goes last: it is what makes the volume exist.
+    store(cfg, name, spec)?;
+    Ok(true)
+}
+
+/// Delete volume `name` and every file in it. `Ok(false)` if there was no
+/// such volume. Refused while it is mounted.
+pub fn delete(cfg: &RhssConfig, name: &str) -> Result<bool> {
+    validate_name(name)?;
+    let meta = meta_dir(cfg, name);
+    if !meta.exists() {
+        return Ok(false);
+    }
+    let lock = crate::lock::inspect(&meta).map_err(|e| FsError::Storage(e.to_string()))?;
+    if lock.held || lock.readers {
+        return Err(FsError::InvalidOperation(format!(
+            "volume {name} is mounted; `rhss volume unmount {name}` first"
+        )));
+    }
+    // Spec first, so a half-done delete reads as gone and can be re-run.
+    let spec = meta.join(SPEC_FILE);
+    match std::fs::remove_file(&spec) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(io_err(e, &spec)),
+    }
+    for dir in data_dirs(cfg, name).into_iter().chain([meta]) {
+        match std::fs::remove_dir_all(&dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_err(e, &dir)),
+        }
+    }
+    Ok(true)
+}
+
+/// Record that volume `name` is being mounted at `mount`.
+pub fn set_mount(cfg: &RhssConfig, name: &str, mount: &Path) -> Result<()> {
+    let mut spec = load(cfg, name)?;
+    spec.mount = Some(mount.to_path_buf());
+    store(cfg, name, &spec)
+}
+
+/// The config that mounts volume `name`: the main one re-rooted into the
+/// volume, with its spec applied.
+pub fn config(cfg: &RhssConfig, name: &str) -> Result<RhssConfig> {
+    let spec = load(cfg, name)?;
+    let meta = meta_dir(cfg, name);
+    let mut vol = cfg.clone();
+    vol.mount = spec.mount.clone().unwrap_or_else(|| meta.join("mnt"));
+    vol.db = meta.join("index.db");
+    vol.hidden_storage = false;
+    for b in vol.tier.fast.iter_mut().chain(&mut vol.tier.slow) {
+        if b.memory_size().is_none() {
+            b.root = data_dir(&b.root, name);
+            for r in &mut b.replicas {
+                *r = data_dir(r, name);
+            }
+        }
+    }
+    vol.tier.archive.clear();
+    vol.policy = spec.policy;
+    vol.quota.clear();
+    if let Some(c) = spec.capacity {
+        vol.quota.insert("/".into(), LimitSpec::Human(c));
+    }
+    Ok(vol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn main_config(dir: &Path) -> RhssConfig {
+        let raw = format!(
+            r#"
+            mount = "{d}/mnt"
+            db = "{d}/db/index.db"
+            [[tier.fast]]
+            id = "ssd"
+            root = "{d}/ssd"
+            [[tier.slow]]
+            id = "hdd"
+            root = "{d}/hdd"
+            "#,
+            d = dir.display()
+        );
+        RhssConfig::from_toml(&raw, std::iter::empty()).unwrap()
+    }
+
+    #[test]
+    fn create_mount_config_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = main_config(dir.path());
+        let spec = VolumeSpec {
+            capacity: Some("1G".into()),
+            ..Default::default()
+        };
+        assert!(create(&cfg, "pvc-1", &spec).unwrap());
+        assert!(dir.path().join("ssd/.rhss-volumes/pvc-1").is_dir());
+        // Idempotent for the same spec, refused for another.
+        assert!(!create(&cfg, "pvc-1", &spec).unwrap());
+        let bigger = VolumeSpec {
+            capacity: Some("2G".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            create(&cfg, "pvc-1", &bigger),
+            Err(FsError::AlreadyExists(_))
+        ));
+
+        set_mount(&cfg, "pvc-1", &dir.path().join("target")).unwrap();
+        let vol = config(&cfg, "pvc-1").unwrap();
+        assert_eq!(vol.mount, dir.path().join("target"));
+        assert_eq!(
+            vol.tier.slow[0].root,
+            dir.path().join("hdd/.rhss-volumes/pvc-1")
+        );
+        assert_ne!(vol.lock_dir(), cfg.lock_dir());
+        assert!(vol.quota.contains_key("/"));
+
+        assert!(delete(&cfg, "pvc-1").unwrap());
+        assert!(!dir.path().join("ssd/.rhss-volumes/pvc-1").exists());
+        assert!(!delete(&cfg, "pvc-1").unwrap());
+        assert!(matches!(config(&cfg, "pvc-1"), Err(FsError::NotFound(_))));
+
+        for bad in ["", "../x", ".hidden", "a/b"] {
+            assert!(validate_name(bad).is_err(), "{bad:?}");
+        }
+    }
+}
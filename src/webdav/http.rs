@@ -0,0 +1,440 @@
+//! Just enough HTTP/1.1 for WebDAV clients: one request at a time per
+//! connection, `Content-Length` or chunked request bodies, keep-alive.
+//! The response side (`read_response_head`, `Body::of_response`) serves
+//! the HTTP client in `crate::backend::http`.
+
+use std::io::{self, BufRead, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Longest request or header line accepted.
+const MAX_LINE: usize = 16 << 10;
+const MAX_HEADERS: usize = 128;
+
+pub(crate) struct Request {
+    pub method: String,
+    /// Request target as sent: percent-encoded path, maybe a query.
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// The client wants the connection closed after this request.
+    pub fn wants_close(&self) -> bool {
+        self.header("connection")
+            .is_some_and(|v| v.eq_ignore_ascii_case("close"))
+    }
+}
+
+fn bad(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_line(r: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut buf = Vec::new();
+    let n = r.take(MAX_LINE as u64).read_until(b'\n', &mut buf)?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if buf.last() != Some(&b'\n') {
+        return Err(bad("line too long"));
+    }
+    while matches!(buf.last(), Some(b'\n' | b'\r')) {
+        buf.pop();
+    }
+    String::from_utf8(buf)
+        .map(Some)
+        .map_err(|_| bad("non-UTF-8 header"))
+}
+
+/// Next request head, or `None` once the client hangs up between requests.
+pub(crate) fn read_request(r: &mut dyn BufRead) -> io::Result<Option<Request>> {
+    let line = loop {
+        match read_line(r)? {
+            None => return Ok(None),
+            // Tolerate stray CRLFs between pipelined requests.
+            Some(l) if l.is_empty() => continue,
+            Some(l) => break l,
+        }
+    };
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad("malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(bad("unsupported HTTP version"));
+    }
+    Ok(Some(Request {
+        method: method.to_ascii_uppercase(),
+        target: target.to_string(),
+        headers: read_headers(r)?,
+    }))
+}
+
+fn read_headers(r: &mut dyn BufRead) -> io::Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(r)?.ok_or_else(|| bad("truncated headers"))?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(bad("too many headers"));
+        }
+        let (k, v) = line
+            .split_once(':')
+            .ok_or_else(|| bad("malformed header"))?;
+        headers.push((k.trim().to_string(), v.trim().to_string()));
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Status line and headers of a response.
+pub(crate) struct ResponseHead {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+impl ResponseHead {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+pub(crate) fn read_response_head(r: &mut dyn BufRead) -> io::Result<ResponseHead> {
+    let line = read_line(r)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+    let mut parts = line.split_whitespace();
+    let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+        return Err(bad("malformed status line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(bad("unsupported HTTP version"));
+    }
+    let status = status.parse().map_err(|_| bad("malformed status"))?;
+    Ok(ResponseHead {
+        status,
+        headers: read_headers(r)?,
+    })
+}
+
+enum Framing {
+    Length(u64),
+    /// A response without length or chunking: the body runs to EOF.
+    ToEof,
+    /// Bytes left in the current chunk; `None` before the first chunk
+    /// header and after the last one has been read.
+    Chunked {
+        left: u64,
+        done: bool,
+    },
+}
+
+/// A message body, read straight off the connection.
+pub(crate) struct Body<'a> {
+    r: &'a mut dyn BufRead,
+    framing: Framing,
+}
+
+fn framing(headers: &[(String, String)], unsized_body: Framing) -> io::Result<Framing> {
+    let chunked = find_header(headers, "transfer-encoding")
+        .is_some_and(|v| v.to_ascii_lowercase().contains("chunked"));
+    if chunked {
+        return Ok(Framing::Chunked {
+            left: 0,
+            done: false,
+        });
+    }
+    match find_header(headers, "content-length") {
+        Some(v) => Ok(Framing::Length(
+            v.parse().map_err(|_| bad("bad Content-Length"))?,
+        )),
+        None => Ok(unsized_body),
+    }
+}
+
+impl<'a> Body<'a> {
+    pub fn new(req: &Request, r: &'a mut dyn BufRead) -> io::Result<Self> {
+        let framing = framing(&req.headers, Framing::Length(0))?;
+        Ok(Self { r, framing })
+    }
+
+    /// Body of the response to a `method` request.
+    pub fn of_response(
+        method: &str,
+        head: &ResponseHead,
+        r: &'a mut dyn BufRead,
+    ) -> io::Result<Self> {
+        let bodiless = method == "HEAD" || matches!(head.status, 100..=199 | 204 | 304);
+        let framing = if bodiless {
+            Framing::Length(0)
+        } else {
+            framing(&head.headers, Framing::ToEof)?
+        };
+        Ok(Self { r, framing })
+    }
+
+    /// Whether the request carries any body at all.
+    pub fn is_empty(&self) -> bool {
+        matches!(self.framing, Framing::Length(0))
+    }
+
+    /// Consume what the handler didn't, so the next request starts clean.
+    pub fn drain(&mut self) -> io::Result<()> {
+        io::copy(self, &mut io::sink()).map(|_| ())
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.framing {
+            Framing::ToEof => self.r.read(buf),
+            Framing::Length(left) => {
+                if *left == 0 || buf.is_empty() {
+                    return Ok(0);
+                }
+                let want = buf.len().min(*left as usize);
+                let n = self.r.read(&mut buf[..want])?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                *left -= n as u64;
+                Ok(n)
+            }
+            Framing::Chunked { left, done } => {
+                if *done || buf.is_empty() {
+                    return Ok(0);
+                }
+                if *left == 0 {
+                    let line = read_line(self.r)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    *left = u64::from_str_radix(size, 16).map_err(|_| bad("bad chunk size"))?;
+                    if *left == 0 {
+                        // Trailers, then the blank line that ends the body.
+                        while !read_line(self.r)?.unwrap_or_default().is_empty() {}
+                        *done = true;
+                        return Ok(0);
+                    }
+                }
+                let want = buf.len().min(*left as usize);
+                let n = self.r.read(&mut buf[..want])?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                *left -= n as u64;
+                if *left == 0 {
+                    // CRLF after the chunk data.
+                    read_line(self.r)?;
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        423 => "Locked",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        507 => "Insufficient Storage",
+        _ => "Unknown",
+    }
+}
+
+/// A buffered response. GET bodies are streamed with `write_head` instead.
+pub(crate) struct Response {
+    pub status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    pub fn xml(self, body: String) -> Self {
+        let mut r = self.header("Content-Type", "application/xml; charset=utf-8");
+        r.body = body.into_bytes();
+        r
+    }
+
+    pub fn text(self, body: String) -> Self {
+        let mut r = self.header("Content-Type", "text/plain; charset=utf-8");
+        r.body = body.into_bytes();
+        r
+    }
+
+    pub fn write_to(&self, w: &mut dyn Write, close: bool) -> io::Result<()> {
+        write_head(w, self.status, &self.headers, self.body.len() as u64, close)?;
+        w.write_all(&self.body)?;
+        w.flush()
+    }
+}
+
+pub(super) fn write_head(
+    w: &mut dyn Write,
+    status: u16,
+    headers: &[(&'static str, String)],
+    content_length: u64,
+    close: bool,
+) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {status} {}\r\n", reason(status));
+    head.push_str(&format!("Date: {}\r\n", http_date(SystemTime::now())));
+    head.push_str("Server: rhss\r\n");
+    for (k, v) in headers {
+        head.push_str(&format!("{k}: {v}\r\n"));
+    }
+    head.push_str(&format!("Content-Length: {content_length}\r\n"));
+    if close {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    w.write_all(head.as_bytes())
+}
+
+/// Decode `%XX` escapes in a request path. `None` for malformed escapes.
+pub(crate) fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' {
+            let hex = std::str::from_utf8(b.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(b[i]);
+            i += 1;
+        }
+    }
+    Some(out)
+}
+
+/// Escape everything but unreserved characters and `/`.
+pub(crate) fn percent_encode(b: &[u8]) -> String {
+    let mut out = String::with_capacity(b.len());
+    for &c in b {
+        if c.is_ascii_alphanumeric() || b"-._~/".contains(&c) {
+            out.push(c as char);
+        } else {
+            out.push_str(&format!("%{c:02X}"));
+        }
+    }
+    out
+}
+
+/// (year, month, day, hour, minute, second, weekday with 0 = Sunday).
+fn civil(t: SystemTime) -> (i64, u32, u32, u32, u32, u32, u32) {
+    let secs = t
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400) as u32);
+    let (year, month, day) = crate::civil::civil_from_days(days);
+    let weekday = (days + 4).rem_euclid(7) as u32;
+    (
+        year,
+        month as u32,
+        day as u32,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        weekday,
+    )
+}
+
+/// RFC 7231 IMF-fixdate: `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub(super) fn http_date(t: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (y, mo, d, h, mi, s, wd) = civil(t);
+    format!(
+        "{}, {d:02} {} {y:04} {h:02}:{mi:02}:{s:02} GMT",
+        DAYS[wd as usize],
+        MONTHS[mo as usize - 1]
+    )
+}
+
+/// RFC 3339 in UTC, for `creationdate`.
+pub(super) fn rfc3339(t: SystemTime) -> String {
+    let (y, mo, d, h, mi, s, _) = civil(t);
+    format!("{y:04}-{mo:02}-{d:02}T{h:02}:{mi:02}:{s:02}Z")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn dates_match_the_rfc_examples() {
+        let t = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(http_date(t), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(rfc3339(t), "1994-11-06T08:49:37Z");
+        let leap = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(http_date(leap), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn chunked_body_is_reassembled() {
+        let raw = b"PUT /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: y\r\n\r\nNEXT";
+        let mut r = &raw[..];
+        let req = read_request(&mut r).unwrap().unwrap();
+        assert_eq!((req.method.as_str(), req.target.as_str()), ("PUT", "/a"));
+        let mut body = Vec::new();
+        Body::new(&req, &mut r)
+            .unwrap()
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(body, b"hello world");
+        assert_eq!(r, b"NEXT");
+    }
+
+    #[test]
+    fn percent_coding_round_trips() {
+        let raw = "/my docs/ü%.txt".as_bytes();
+        let enc = percent_encode(raw);
+        assert_eq!(enc, "/my%20docs/%C3%BC%25.txt");
+        assert_eq!(percent_decode(&enc).unwrap(), raw);
+        assert!(percent_decode("/bad%2").is_none());
+    }
+}
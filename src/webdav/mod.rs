@@ -0,0 +1,674 @@
+//! WebDAV frontend (`rhss serve-webdav`): the hybrid namespace over HTTP,
+//! so Windows Explorer, macOS Finder and davfs2 can map it as a network
+//! drive without a kernel extension.
+//!
+//! Class 1 plus advisory class 2: `LOCK` hands out tokens so clients that
+//! insist on locking (Finder, Office) can write, but nothing is enforced.
+//! There is no authentication; bind to localhost or put a proxy in front.
+
+pub(crate) mod http;
+
+use std::ffi::OsStr;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::SeqCst};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, error, info, warn};
+
+use crate::backend::FileMetadata;
+use crate::error::{FsError, Result};
+use crate::namespace::Namespace;
+
+use http::{http_date, percent_decode, percent_encode, rfc3339, Body, Request, Response};
+
+/// Bytes per `Namespace::read` when streaming a `GET`.
+const CHUNK: u32 = 1 << 20;
+
+/// Idle keep-alive connections are dropped after this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const ALLOW: &str =
+    "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, MOVE, COPY, PROPFIND, PROPPATCH, LOCK, UNLOCK";
+
+/// Owns the listening socket + the accept thread. Drop stops accepting;
+/// connections already open finish their current request.
+pub struct WebDavServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+struct Dav {
+    ns: Arc<Namespace>,
+    read_only: bool,
+    next_lock: AtomicU64,
+}
+
+impl WebDavServer {
+    pub fn start(listen: SocketAddr, ns: Arc<Namespace>, read_only: bool) -> Result<Self> {
+        let listener = TcpListener::bind(listen).map_err(FsError::Io)?;
+        listener.set_nonblocking(true).map_err(FsError::Io)?;
+        let addr = listener.local_addr().map_err(FsError::Io)?;
+        info!("webdav listening on http://{addr}/");
+
+        let dav = Arc::new(Dav {
+            ns,
+            read_only,
+            next_lock: AtomicU64::new(1),
+        });
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_for_thread = Arc::clone(&shutdown);
+        let handle = std::thread::Builder::new()
+            .name("rhss-webdav".into())
+            .spawn(move || accept_loop(listener, dav, shutdown_for_thread))
+            .expect("spawn webdav thread");
+
+        Ok(Self {
+            addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    /// Where the server ended up listening (useful with port 0).
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for WebDavServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, dav: Arc<Dav>, shutdown: Arc<AtomicBool>) {
+    while !shutdown.load(SeqCst) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let dav = Arc::clone(&dav);
+                let _ = std::thread::Builder::new()
+                    .name("rhss-dav-client".into())
+                    .spawn(move || {
+                        if let Err(e) = handle_connection(stream, &dav) {
+                            debug!("webdav client {peer}: {e}");
+                        }
+                    });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => {
+                error!("webdav accept failed: {e}");
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        }
+    }
+    debug!("webdav accept loop exit");
+}
+
+fn handle_connection(stream: TcpStream, dav: &Dav) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let _ = stream.set_nodelay(true);
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut out = stream;
+    loop {
+        let req = match http::read_request(&mut reader) {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let _ = Response::new(400).write_to(&mut out, true);
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        let close = req.wants_close();
+        let mut body = match Body::new(&req, &mut reader) {
+            Ok(b) => b,
+            Err(e) => {
+                let _ = Response::new(400).write_to(&mut out, true);
+                return Err(e);
+            }
+        };
+        if !body.is_empty()
+            && req
+                .header("expect")
+                .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"))
+        {
+            out.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        dav.handle(&req, &mut body, &mut out, close)?;
+        body.drain()?;
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// HTTP status for a failed namespace operation.
+fn status_of(e: &FsError) -> u16 {
+    if let FsError::Offline(_) = e.root() {
+        return 503;
+    }
+    match e.to_errno() {
+        libc::ENOENT => 404,
+        libc::EEXIST | libc::EISDIR => 405,
+        libc::EACCES | libc::EPERM | libc::EROFS => 403,
+        libc::EINVAL => 400,
+        libc::ENOTDIR | libc::ENOTEMPTY => 409,
+        libc::ENOSPC | libc::EDQUOT => 507,
+        libc::ETIMEDOUT => 504,
+        libc::EOPNOTSUPP => 501,
+        _ => 500,
+    }
+}
+
+/// Logical path named by a request target or `Destination` header, which
+/// may be an absolute URI. `None` if it isn't a usable path.
+fn target_path(target: &str) -> Option<PathBuf> {
+    let path = match target.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+        None => target,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let raw = percent_decode(if path.is_empty() { "/" } else { path })?;
+    if raw.first() != Some(&b'/') || raw.contains(&0) {
+        return None;
+    }
+    Some(PathBuf::from(OsStr::from_bytes(&raw)))
+}
+
+fn href(path: &Path, is_dir: bool) -> String {
+    let mut s = percent_encode(path.as_os_str().as_bytes());
+    if is_dir && !s.ends_with('/') {
+        s.push('/');
+    }
+    s
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn etag(meta: &FileMetadata) -> String {
+    let mtime = meta
+        .mtime
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", meta.size, mtime)
+}
+
+/// One `<D:response>` of a `PROPFIND` multistatus.
+fn prop_response(path: &Path, meta: &FileMetadata) -> String {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut props = format!(
+        "<D:displayname>{}</D:displayname>\
+         <D:getlastmodified>{}</D:getlastmodified>\
+         <D:creationdate>{}</D:creationdate>\
+         <D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+         <D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>",
+        xml_escape(&name),
+        http_date(meta.mtime),
+        rfc3339(meta.ctime),
+    );
+    if meta.is_dir {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        props.push_str(&format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>application/octet-stream</D:getcontenttype>\
+             <D:getetag>{}</D:getetag>",
+            meta.size,
+            etag(meta)
+        ));
+    }
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{props}</D:prop>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        xml_escape(&href(path, meta.is_dir))
+    )
+}
+
+fn multistatus(responses: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <D:multistatus xmlns:D=\"DAV:\">{responses}</D:multistatus>\n"
+    )
+}
+
+/// `Range: bytes=…` against a file of `size` bytes: the single inclusive
+/// range asked for, `Ok(None)` to send everything, `Err` if unsatisfiable.
+fn parse_range(header: Option<&str>, size: u64) -> std::result::Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return Ok(None);
+    };
+    // Multiple ranges would need multipart/byteranges; send it all.
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let (start, end) = spec.split_once('-').ok_or(())?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", n) => {
+            let n: u64 = n.parse().map_err(|_| ())?;
+            (size.saturating_sub(n), size.saturating_sub(1))
+        }
+        (s, "") => (s.parse().map_err(|_| ())?, size.saturating_sub(1)),
+        (s, e) => (
+            s.parse().map_err(|_| ())?,
+            e.parse::<u64>()
+                .map_err(|_| ())?
+                .min(size.saturating_sub(1)),
+        ),
+    };
+    if size == 0 || start > end {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+/// `Namespace::read` as a `Read`, for `COPY`.
+struct NsReader<'a> {
+    ns: &'a Namespace,
+    path: &'a Path,
+    off: u64,
+}
+
+impl Read for NsReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK as usize) as u32;
+        let data = self
+            .ns
+            .read(self.path, self.off, len)
+            .map_err(|e| io::Error::from_raw_os_error(e.to_errno()))?;
+        buf[..data.len()].copy_from_slice(&data);
+        self.off += data.len() as u64;
+        Ok(data.len())
+    }
+}
+
+impl Dav {
+    fn handle(
+        &self,
+        req: &Request,
+        body: &mut Body<'_>,
+        out: &mut TcpStream,
+        close: bool,
+    ) -> io::Result<()> {
+        let Some(path) = target_path(&req.target) else {
+            return Response::new(400).write_to(out, close);
+        };
+        let method = req.method.as_str();
+        let mutating = matches!(
+            method,
+            "PUT" | "DELETE" | "MKCOL" | "MOVE" | "COPY" | "PROPPATCH" | "LOCK"
+        );
+        if mutating && self.read_only {
+            debug!("{method} {} -> 403 (read-only)", path.display());
+            return Response::new(403).write_to(out, close);
+        }
+        if matches!(method, "GET" | "HEAD") {
+            return self.get(req, &path, method == "HEAD", out, close);
+        }
+        let res = match method {
+            "OPTIONS" => Ok(Response::new(200)
+                .header("DAV", "1, 2")
+                .header("Allow", ALLOW)
+                .header("MS-Author-Via", "DAV")),
+            "PROPFIND" => self.propfind(req, &path),
+            "PROPPATCH" => self.proppatch(&path),
+            "PUT" => self.put(&path, body),
+            "DELETE" => self.remove_tree(&path).map(|_| Response::new(204)),
+            "MKCOL" => self.mkcol(&path, body),
+            "MOVE" | "COPY" => self.move_or_copy(req, &path, method == "MOVE"),
+            "LOCK" => self.lock(req, &path),
+            "UNLOCK" => Ok(Response::new(204)),
+            _ => Ok(Response::new(501).header("Allow", ALLOW)),
+        };
+        let resp = res.unwrap_or_else(|e| {
+            if status_of(&e) >= 500 {
+                warn!("webdav {method} {}: {e}", path.display());
+            }
+            Response::new(status_of(&e))
+        });
+        debug!("{method} {} -> {}", path.display(), resp.status);
+        resp.write_to(out, close)
+    }
+
+    fn get(
+        &self,
+        req: &Request,
+        path: &Path,
+        head_only: bool,
+        out: &mut TcpStream,
+        close: bool,
+    ) -> io::Result<()> {
+        let meta = match self.ns.stat(path) {
+            Ok(m) if m.is_dir => {
+                return Response::new(405)
+                    .header("Allow", ALLOW)
+                    .write_to(out, close)
+            }
+            Ok(m) => m,
+            Err(e) => return Response::new(status_of(&e)).write_to(out, close),
+        };
+        let mut headers = vec![
+            ("Content-Type", "application/octet-stream".to_string()),
+            ("Last-Modified", http_date(meta.mtime)),
+            ("ETag", etag(&meta)),
+            ("Accept-Ranges", "bytes".to_string()),
+        ];
+        let (status, start, end) = match parse_range(req.header("range"), meta.size) {
+            Ok(Some((s, e))) => {
+                headers.push(("Content-Range", format!("bytes {s}-{e}/{}", meta.size)));
+                (206, s, e + 1)
+            }
+            Ok(None) => (200, 0, meta.size),
+            Err(()) => {
+                return Response::new(416)
+                    .header("Content-Range", format!("bytes */{}", meta.size))
+                    .write_to(out, close);
+            }
+        };
+        http::write_head(out, status, &headers, end - start, close)?;
+        if !head_only {
+            let mut off = start;
+            while off < end {
+                let len = (end - off).min(CHUNK as u64) as u32;
+                let data = self
+                    .ns
+                    .read(path, off, len)
+                    .map_err(|e| io::Error::from_raw_os_error(e.to_errno()))?;
+                if data.is_empty() {
+                    // Shrunk under us; the length already went out, so the
+                    // only honest thing left is to drop the connection.
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                out.write_all(&data)?;
+                off += data.len() as u64;
+            }
+        }
+        debug!("{} {} -> {status}", req.method, path.display());
+        out.flush()
+    }
+
+    fn propfind(&self, req: &Request, path: &Path) -> Result<Response> {
+        let meta = self.ns.stat(path)?;
+        let mut xml = prop_response(path, &meta);
+        // `infinity` (and no header, which means the same) is served as 1:
+        // clients walk deeper trees one level at a time anyway.
+        if meta.is_dir && req.header("depth").map(str::trim) != Some("0") {
+            for entry in self.ns.list(path)? {
+                xml.push_str(&prop_response(&path.join(&entry.name), &entry.meta));
+            }
+        }
+        Ok(Response::new(207).xml(multistatus(&xml)))
+    }
+
+    /// Dead properties aren't stored; report them set so Explorer's
+    /// timestamp updates don't fail the copy.
+    fn proppatch(&self, path: &Path) -> Result<Response> {
+        let meta = self.ns.stat(path)?;
+        let xml = format!(
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop/>\
+             <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+            xml_escape(&href(path, meta.is_dir))
+        );
+        Ok(Response::new(207).xml(multistatus(&xml)))
+    }
+
+    fn put(&self, path: &Path, body: &mut Body<'_>) -> Result<Response> {
+        match self.ns.put(path, body) {
+            Ok(true) => Ok(Response::new(201)),
+            Ok(false) => Ok(Response::new(204)),
+            // Missing parent collection.
+            Err(FsError::NotFound(_)) => Ok(Response::new(409)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn mkcol(&self, path: &Path, body: &Body<'_>) -> Result<Response> {
+        if !body.is_empty() {
+            return Ok(Response::new(415));
+        }
+        match self.ns.mkdir(path) {
+            Ok(()) => Ok(Response::new(201)),
+            Err(FsError::NotFound(_)) => Ok(Response::new(409)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `DELETE` on a collection takes everything under it.
+    fn remove_tree(&self, path: &Path) -> Result<()> {
+        if self.ns.stat(path)?.is_dir {
+            for entry in self.ns.list(path)? {
+                self.remove_tree(&path.join(&entry.name))?;
+            }
+        }
+        self.ns.remove(path)
+    }
+
+    fn copy_tree(&self, from: &Path, to: &Path, meta: &FileMetadata, deep: bool) -> Result<()> {
+        if !meta.is_dir {
+            let mut src = NsReader {
+                ns: &self.ns,
+                path: from,
+                off: 0,
+            };
+            return self.ns.put(to, &mut src).map(|_| ());
+        }
+        self.ns.mkdir(to)?;
+        if deep {
+            for entry in self.ns.list(from)? {
+                let name = Path::new(&entry.name);
+                self.copy_tree(&from.join(name), &to.join(name), &entry.meta, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn move_or_copy(&self, req: &Request, from: &Path, is_move: bool) -> Result<Response> {
+        let Some(to) = req.header("destination").and_then(target_path) else {
+            return Ok(Response::new(400));
+        };
+        let from_abs = Path::new("/").join(from);
+        let to_abs = Path::new("/").join(&to);
+        if from_abs == to_abs || to_abs.starts_with(&from_abs) {
+            return Ok(Response::new(403));
+        }
+        let meta = self.ns.stat(from)?;
+        let existed = self.ns.stat(&to).is_ok();
+        if existed {
+            if req.header("overwrite").map(str::trim) == Some("F") {
+                return Ok(Response::new(412));
+            }
+            self.remove_tree(&to)?;
+        }
+        let res = if is_move {
+            self.ns.rename(from, &to)
+        } else {
+            let deep = req.header("depth").map(str::trim) != Some("0");
+            self.copy_tree(from, &to, &meta, deep)
+        };
+        match res {
+            Ok(()) if existed => Ok(Response::new(204)),
+            Ok(()) => Ok(Response::new(201)),
+            Err(FsError::NotFound(_)) => Ok(Response::new(409)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Advisory only: a fresh token (or the one being refreshed), never a
+    /// conflict. Locking an unmapped URL creates an empty file, per RFC 4918.
+    fn lock(&self, req: &Request, path: &Path) -> Result<Response> {
+        let refreshed = req
+            .header("if")
+            .and_then(|v| v.split_once("<opaquelocktoken:"))
+            .and_then(|(_, rest)| rest.split_once('>'))
+            .map(|(tok, _)| format!("opaquelocktoken:{tok}"));
+        let (status, is_dir) = match self.ns.stat(path) {
+            Ok(meta) => (200, meta.is_dir),
+            Err(FsError::NotFound(_)) => match self.ns.put(path, &mut io::empty()) {
+                Ok(_) => (201, false),
+                Err(FsError::NotFound(_)) => return Ok(Response::new(409)),
+                Err(e) => return Err(e),
+            },
+            Err(e) => return Err(e),
+        };
+        let token = refreshed.unwrap_or_else(|| {
+            let stamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0);
+            format!(
+                "opaquelocktoken:rhss-{}-{stamp:x}-{}",
+                std::process::id(),
+                self.next_lock.fetch_add(1, SeqCst)
+            )
+        });
+        let xml = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+             <D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+             <D:depth>0</D:depth><D:timeout>Second-3600</D:timeout>\
+             <D:locktoken><D:href>{token}</D:href></D:locktoken>\
+             <D:lockroot><D:href>{}</D:href></D:lockroot>\
+             </D:activelock></D:lockdiscovery></D:prop>\n",
+            xml_escape(&href(path, is_dir))
+        );
+        Ok(Response::new(status)
+            .header("Lock-Token", format!("<{token}>"))
+            .xml(xml))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, PosixBackend};
+    use crate::index::{PathIndex, SqlitePathIndex, TierId};
+    use crate::policy::PopularityPolicy;
+    use crate::tier::{MostFreePlacement, Tier, TierRouter};
+    use crate::tierer::OpenFileTracker;
+    use std::io::BufRead;
+
+    fn serve(read_only: bool) -> (tempfile::TempDir, WebDavServer) {
+        let dir = tempfile::tempdir().unwrap();
+        let tier = |id, name: &str| {
+            let root = dir.path().join(name);
+            std::fs::create_dir_all(&root).unwrap();
+            let b: Arc<dyn Backend> = Arc::new(PosixBackend::new(name, root).unwrap());
+            Tier::new(id, vec![b], Box::new(MostFreePlacement)).unwrap()
+        };
+        let router = Arc::new(TierRouter::new(
+            tier(TierId::Fast, "ssd"),
+            tier(TierId::Slow, "hdd"),
+        ));
+        let index: Arc<dyn PathIndex> = SqlitePathIndex::open(dir.path().join("idx.db")).unwrap();
+        let ns = Namespace::new(
+            router,
+            index,
+            Arc::new(PopularityPolicy::default()),
+            Arc::new(OpenFileTracker::new()),
+        );
+        let srv =
+            WebDavServer::start("127.0.0.1:0".parse().unwrap(), Arc::new(ns), read_only).unwrap();
+        (dir, srv)
+    }
+
+    /// Send one request on a fresh connection; (status, body).
+    fn call(srv: &WebDavServer, head: &str, body: &[u8]) -> (u16, String) {
+        let mut s = TcpStream::connect(srv.local_addr()).unwrap();
+        write!(
+            s,
+            "{head}\r\nHost: x\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        s.write_all(body).unwrap();
+        let mut r = BufReader::new(s);
+        let mut status = String::new();
+        r.read_line(&mut status).unwrap();
+        let mut rest = String::new();
+        r.read_to_string(&mut rest).unwrap();
+        let body = rest.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status.split(' ').nth(1).unwrap().parse().unwrap(), body)
+    }
+
+    #[test]
+    fn put_get_propfind_move_delete() {
+        let (_dir, srv) = serve(false);
+        assert_eq!(call(&srv, "MKCOL /my%20docs HTTP/1.1", b"").0, 201);
+        assert_eq!(call(&srv, "MKCOL /my%20docs HTTP/1.1", b"").0, 405);
+        assert_eq!(call(&srv, "PUT /nope/a.txt HTTP/1.1", b"x").0, 409);
+        assert_eq!(
+            call(&srv, "PUT /my%20docs/a.txt HTTP/1.1", b"hello world").0,
+            201
+        );
+
+        assert_eq!(
+            call(&srv, "GET /my%20docs/a.txt HTTP/1.1", b""),
+            (200, "hello world".into())
+        );
+        assert_eq!(
+            call(
+                &srv,
+                "GET /my%20docs/a.txt HTTP/1.1\r\nRange: bytes=6-",
+                b""
+            ),
+            (206, "world".into())
+        );
+        let (status, xml) = call(&srv, "PROPFIND /my%20docs HTTP/1.1\r\nDepth: 1", b"");
+        assert_eq!(status, 207);
+        assert!(xml.contains("<D:href>/my%20docs/a.txt</D:href>"), "{xml}");
+        assert!(xml.contains("<D:getcontentlength>11</D:getcontentlength>"));
+
+        let dest = format!("http://{}/b.txt", srv.local_addr());
+        let mv = format!("MOVE /my%20docs/a.txt HTTP/1.1\r\nDestination: {dest}");
+        assert_eq!(call(&srv, &mv, b"").0, 201);
+        assert_eq!(call(&srv, "GET /b.txt HTTP/1.1", b"").1, "hello world");
+        assert_eq!(call(&srv, "DELETE /my%20docs HTTP/1.1", b"").0, 204);
+        assert_eq!(call(&srv, "PROPFIND /my%20docs HTTP/1.1", b"").0, 404);
+        assert_eq!(call(&srv, "GET /../etc/passwd HTTP/1.1", b"").0, 400);
+    }
+
+    #[test]
+    fn read_only_refuses_writes() {
+        let (_dir, srv) = serve(true);
+        assert_eq!(call(&srv, "PUT /a.txt HTTP/1.1", b"x").0, 403);
+        assert_eq!(call(&srv, "MKCOL /d HTTP/1.1", b"").0, 403);
+        assert_eq!(call(&srv, "PROPFIND / HTTP/1.1\r\nDepth: 0", b"").0, 207);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range(None, 10), Ok(None));
+        assert_eq!(parse_range(Some("bytes=2-4"), 10), Ok(Some((2, 4))));
+        assert_eq!(parse_range(Some("bytes=-3"), 10), Ok(Some((7, 9))));
+        assert_eq!(parse_range(Some("bytes=5-100"), 10), Ok(Some((5, 9))));
+        assert_eq!(parse_range(Some("bytes=10-"), 10), Err(()));
+        assert_eq!(parse_range(Some("bytes=0-"), 0), Err(()));
+    }
+}
@@ -9,10 +9,12 @@ use std::time::{Duration, SystemTime};
 
 use rhss::access::AccessTracker;
 use rhss::backend::Backend;
+use rhss::clock::SystemClock;
 use rhss::control::server::OpContext;
 use rhss::control::{socket_path_for, ControlServer, Request, Response, ResponseData};
 use rhss::index::{FileRow, FileState, Location, PathIndex, SqlitePathIndex, TierId};
-use rhss::policy::{PopularityPolicy, TieringPolicy};
+use rhss::config::PolicyOptions;
+use rhss::policy::{PopularityPolicy, ReloadablePolicy, TieringPolicy};
 use rhss::tier::{MostFreePlacement, Tier, TierRouter};
 use rhss::tierer::{OpenFileTracker, Tierer};
 use rhss::PosixBackend;
@@ -25,7 +27,10 @@ struct Harness {
     _tierer: Tierer,
     _access: AccessTracker,
     index: Arc<dyn PathIndex>,
+    policy: Arc<ReloadablePolicy>,
     ssd_root: PathBuf,
+    #[cfg(feature = "grpc")]
+    ctx: OpContext,
 }
 
 fn build_harness() -> Harness {
@@ -48,27 +53,31 @@ fn build_harness() -> Harness {
     let index: Arc<dyn PathIndex> = SqlitePathIndex::open(&db).unwrap();
     let access = AccessTracker::start(Arc::clone(&index), Duration::from_secs(60));
     let open_tracker = Arc::new(OpenFileTracker::new());
-    let policy: Arc<dyn TieringPolicy> = Arc::new(PopularityPolicy::default());
+    let policy = ReloadablePolicy::new(Arc::new(PopularityPolicy::default()));
 
     let (tierer, tierer_handle) = Tierer::spawn(
         Arc::clone(&router),
         Arc::clone(&index),
         Arc::clone(&open_tracker),
-        Arc::clone(&policy),
+        Arc::clone(&policy) as Arc<dyn TieringPolicy>,
+        Arc::new(SystemClock),
     );
 
+    let ctx = OpContext {
+        router: Arc::clone(&router),
+        index: Arc::clone(&index),
+        open_tracker: Arc::clone(&open_tracker),
+        tierer: tierer_handle,
+        config_db_path: db.clone(),
+        mount: tempdir.path().join("mnt"),
+        started: SystemTime::now(),
+        fuse: None,
+        trash: None,
+        quotas: None,
+        policy: Some(Arc::clone(&policy)),
+    };
     let socket = socket_path_for(&db);
-    let server = ControlServer::start(
-        socket.clone(),
-        OpContext {
-            router: Arc::clone(&router),
-            index: Arc::clone(&index),
-            open_tracker: Arc::clone(&open_tracker),
-            tierer: tierer_handle,
-            config_db_path: db.clone(),
-        },
-    )
-    .unwrap();
+    let server = ControlServer::start(socket.clone(), ctx.clone()).unwrap();
 
     // Brief settle for socket bind.
     std::thread::sleep(Duration::from_millis(50));
@@ -81,7 +90,10 @@ fn build_harness() -> Harness {
         _tierer: tierer,
         _access: access,
         index,
+        policy,
         ssd_root: ssd,
+        #[cfg(feature = "grpc")]
+        ctx,
     }
 }
 
@@ -134,6 +146,7 @@ fn pin_then_unpin_roundtrips() {
             hit_count: 0,
             popularity: 0.0,
             pinned_tier: None,
+            pinned_until: None,
             state: FileState::Stable,
             replicas: Vec::new(),
             mutability: rhss::index::Mutability::Unknown,
@@ -147,6 +160,7 @@ fn pin_then_unpin_roundtrips() {
         &Request::Pin {
             path: PathBuf::from("/a.bin"),
             tier: rhss::control::Tier::Fast,
+            until: None,
         },
     );
     assert!(resp.ok, "pin failed: {resp:?}");
@@ -163,6 +177,48 @@ fn pin_then_unpin_roundtrips() {
     assert!(resp.ok);
     let row = h.index.get(std::path::Path::new("/a.bin")).unwrap().unwrap();
     assert_eq!(row.pinned_tier, None);
+
+    // A reservation on a directory pins what is below it, until the
+    // deadline; one already past is refused.
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let pin_until = |until| Request::Pin {
+        path: PathBuf::from("/"),
+        tier: rhss::control::Tier::Fast,
+        until: Some(until),
+    };
+    assert!(!round_trip(&h.socket, &pin_until(now - 60)).ok);
+    let resp = round_trip(&h.socket, &pin_until(now + 3600));
+    match resp.data {
+        Some(ResponseData::Pinned { files, until, .. }) => {
+            assert_eq!((files, until), (1, Some(now + 3600)))
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    let row = h.index.get(std::path::Path::new("/a.bin")).unwrap().unwrap();
+    assert!(row.pinned_until.is_some());
+}
+
+#[test]
+fn status_reports_backends_and_unmounted_state() {
+    let h = build_harness();
+    let resp = round_trip(&h.socket, &Request::Status);
+    assert!(resp.ok);
+    match resp.data {
+        Some(ResponseData::Status(r)) => {
+            // Harness has no FUSE session.
+            assert!(!r.mounted);
+            assert!(r.cache.is_none());
+            assert_eq!(r.pid, std::process::id());
+            assert_eq!(r.pending_migrations, 0);
+            let ids: Vec<&str> = r.backends.iter().map(|b| b.id.as_str()).collect();
+            assert_eq!(ids, ["ssd0", "hdd0"]);
+            assert!(r.backends.iter().all(|b| b.error.is_none() && b.total > 0));
+        }
+        other => panic!("expected Status, got {:?}", other),
+    }
 }
 
 #[test]
@@ -188,6 +244,29 @@ fn freeze_unfreeze_toggles_state() {
     }
 }
 
+#[test]
+fn set_policy_swaps_thresholds() {
+    let h = build_harness();
+    let policy = PolicyOptions {
+        high_watermark: Some(0.5),
+        low_watermark: Some(0.3),
+        ..Default::default()
+    };
+    let resp = round_trip(&h.socket, &Request::SetPolicy { policy });
+    assert!(resp.ok, "{:?}", resp.error);
+    assert_eq!(h.policy.high_watermark(), 0.5);
+
+    // low above high is refused and the running policy kept.
+    let policy = PolicyOptions {
+        low_watermark: Some(0.9),
+        high_watermark: Some(0.5),
+        ..Default::default()
+    };
+    let resp = round_trip(&h.socket, &Request::SetPolicy { policy });
+    assert!(!resp.ok);
+    assert_eq!(h.policy.low_watermark(), 0.3);
+}
+
 #[test]
 fn migrate_moves_an_indexed_file() {
     let h = build_harness();
@@ -205,6 +284,7 @@ fn migrate_moves_an_indexed_file() {
             hit_count: 0,
             popularity: 0.0,
             pinned_tier: None,
+            pinned_until: None,
             state: FileState::Stable,
             replicas: Vec::new(),
             mutability: rhss::index::Mutability::Unknown,
@@ -218,6 +298,8 @@ fn migrate_moves_an_indexed_file() {
         &Request::Migrate {
             path: PathBuf::from("/m.bin"),
             to: rhss::control::Tier::Slow,
+            progress: false,
+            dry_run: false,
         },
     );
     assert!(resp.ok, "migrate failed: {resp:?}");
@@ -229,6 +311,70 @@ fn migrate_moves_an_indexed_file() {
     assert_eq!(loc.tier, TierId::Slow);
 }
 
+#[test]
+fn migrate_directory_streams_progress() {
+    let h = build_harness();
+    // Directories exist on every backend, as FUSE mkdir leaves them.
+    let hdd_root = h.ssd_root.join("../../hdd/.rhss_managed");
+    std::fs::create_dir(h.ssd_root.join("dir")).unwrap();
+    std::fs::create_dir(hdd_root.join("dir")).unwrap();
+    for name in ["a", "b"] {
+        std::fs::write(h.ssd_root.join("dir").join(name), b"data").unwrap();
+        h.index
+            .insert(FileRow {
+                logical_path: PathBuf::from(format!("/dir/{name}")),
+                location: Location {
+                    tier: TierId::Fast,
+                    backend_id: "ssd0".into(),
+                    backend_path: PathBuf::from(format!("dir/{name}")),
+                    size: 4,
+                },
+                last_access: SystemTime::now(),
+                hit_count: 0,
+                popularity: 0.0,
+                pinned_tier: None,
+                pinned_until: None,
+                state: FileState::Stable,
+                replicas: Vec::new(),
+                mutability: rhss::index::Mutability::Unknown,
+                compressed: false,
+                content_hash: None,
+            })
+            .unwrap();
+    }
+
+    let stream = UnixStream::connect(&h.socket).unwrap();
+    let req = Request::Migrate {
+        path: PathBuf::from("/dir"),
+        to: rhss::control::Tier::Slow,
+        progress: true,
+        dry_run: false,
+    };
+    let mut body = serde_json::to_vec(&req).unwrap();
+    body.push(b'\n');
+    (&stream).write_all(&body).unwrap();
+    let lines: Vec<Response> = BufReader::new(stream)
+        .lines()
+        .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+        .collect();
+    // The plan, one line per file moved, then the totals.
+    assert_eq!(lines.len(), 4, "{lines:?}");
+    match &lines[0].data {
+        Some(ResponseData::MigrateProgress { progress }) => {
+            assert_eq!((progress.files_to_move, progress.files_moved), (2, 0))
+        }
+        other => panic!("expected MigrateProgress, got {other:?}"),
+    }
+    match &lines[3].data {
+        Some(ResponseData::MigratedTree { progress, .. }) => {
+            assert_eq!(progress.files_moved, 2);
+            assert_eq!(progress.bytes_moved, 8);
+            assert!(progress.failures.is_empty());
+        }
+        other => panic!("expected MigratedTree, got {other:?}"),
+    }
+}
+
 #[test]
 fn fsck_finds_orphan() {
     let h = build_harness();
@@ -241,17 +387,63 @@ fn fsck_finds_orphan() {
             orphans,
             ghosts,
             inconsistencies,
+            partial,
             repaired,
         }) => {
             assert_eq!(repaired, 0);
             assert!(ghosts.is_empty());
             assert!(inconsistencies.is_empty());
+            assert!(partial.is_empty());
             assert!(orphans.iter().any(|p| p.ends_with("rogue.bin")));
         }
         other => panic!("expected Fsck, got {other:?}"),
     }
 }
 
+#[test]
+fn fsck_repair_cleans_stale_empty_creates() {
+    let h = build_harness();
+    // Both created through the mount before a crash; only one got data.
+    for (name, data) in [("empty.bin", &b""[..]), ("half.bin", &b"half"[..])] {
+        std::fs::write(h.ssd_root.join(name), data).unwrap();
+        h.index
+            .insert(FileRow {
+                logical_path: PathBuf::from(format!("/{name}")),
+                location: Location {
+                    tier: TierId::Fast,
+                    backend_id: "ssd0".into(),
+                    backend_path: PathBuf::from(name),
+                    size: data.len() as u64,
+                },
+                last_access: SystemTime::now(),
+                hit_count: 0,
+                popularity: 0.0,
+                pinned_tier: None,
+                pinned_until: None,
+                state: FileState::Creating,
+                replicas: Vec::new(),
+                mutability: rhss::index::Mutability::Unknown,
+                compressed: false,
+                content_hash: None,
+            })
+            .unwrap();
+    }
+    let resp = round_trip(&h.socket, &Request::Fsck { repair: true });
+    match resp.data {
+        Some(ResponseData::Fsck {
+            partial, repaired, ..
+        }) => {
+            assert_eq!(partial.len(), 2);
+            assert_eq!(repaired, 2);
+        }
+        other => panic!("expected Fsck, got {other:?}"),
+    }
+    assert!(!h.ssd_root.join("empty.bin").exists());
+    let row = |p: &str| h.index.get(std::path::Path::new(p)).unwrap();
+    assert!(row("/empty.bin").is_none());
+    assert_eq!(row("/half.bin").unwrap().state, FileState::Stable);
+}
+
 #[test]
 fn rescan_ingests_new_file() {
     let h = build_harness();
@@ -291,3 +483,70 @@ fn bad_request_returns_friendly_error() {
     assert!(!resp.ok);
     assert!(resp.error.unwrap().contains("bad request"));
 }
+
+#[cfg(feature = "grpc")]
+#[test]
+fn grpc_admin_runs_control_ops() {
+    use rhss::control::grpc::{pb, GrpcServer};
+    use tonic::codec::ProstCodec;
+
+    let h = build_harness();
+    let server = GrpcServer::start("127.0.0.1:0".parse().unwrap(), h.ctx.clone()).unwrap();
+    let url = format!("http://{}", server.local_addr());
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let channel = tonic::transport::Endpoint::from_shared(url)
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = tonic::client::Grpc::new(channel);
+
+        client.ready().await.unwrap();
+        let reply: pb::StatusReply = client
+            .unary(
+                tonic::Request::new(pb::StatusRequest {}),
+                "/rhss.admin.v1.Admin/Status".parse().unwrap(),
+                ProstCodec::<pb::StatusRequest, pb::StatusReply>::default(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.pid, std::process::id());
+        assert_eq!(reply.backends.len(), 2);
+
+        client.ready().await.unwrap();
+        client
+            .unary(
+                tonic::Request::new(pb::SetPolicyRequest {
+                    high_watermark: Some(0.6),
+                    low_watermark: Some(0.4),
+                    ..Default::default()
+                }),
+                "/rhss.admin.v1.Admin/SetPolicy".parse().unwrap(),
+                ProstCodec::<pb::SetPolicyRequest, pb::SetPolicyReply>::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(h.policy.high_watermark(), 0.6);
+
+        // Daemon-side failures surface as UNKNOWN with the message.
+        client.ready().await.unwrap();
+        let err = client
+            .unary(
+                tonic::Request::new(pb::MigrateRequest {
+                    path: "/missing".into(),
+                    to: pb::Tier::Slow as i32,
+                }),
+                "/rhss.admin.v1.Admin/Migrate".parse().unwrap(),
+                ProstCodec::<pb::MigrateRequest, pb::MigrateReply>::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unknown);
+    });
+}
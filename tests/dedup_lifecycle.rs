@@ -51,6 +51,7 @@ fn two_identical_immutable_files_share_one_blob() {
                 hit_count: 0,
                 popularity: 0.0,
                 pinned_tier: None,
+                pinned_until: None,
                 state: FileState::Stable,
                 mutability: Mutability::Immutable,
                 compressed: false,
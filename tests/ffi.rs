@@ -0,0 +1,65 @@
+//! The C API end to end: init from a config file, write/read/stat a file
+//! through it, migrate it, and shut down.
+
+use std::ffi::{CStr, CString};
+
+use rhss::ffi::*;
+
+#[test]
+fn c_api_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let config = root.join("rhss.toml");
+    std::fs::write(
+        &config,
+        format!(
+            r#"
+            mount = "{root}/mnt"
+            db = "{root}/state/index.db"
+            [[tier.fast]]
+            id = "ssd"
+            root = "{root}/ssd/.rhss_managed"
+            [[tier.slow]]
+            id = "hdd"
+            root = "{root}/hdd/.rhss_managed"
+            "#,
+            root = root.display()
+        ),
+    )
+    .unwrap();
+    let config = CString::new(config.to_str().unwrap()).unwrap();
+    let bad = c"/nonexistent/rhss.toml";
+    unsafe {
+        assert!(rhss_init(bad.as_ptr()).is_null());
+        assert!(!rhss_last_error().is_null());
+    }
+    let path = c"/a.bin";
+
+    unsafe {
+        let h = rhss_init(config.as_ptr());
+        assert!(!h.is_null());
+
+        assert_eq!(rhss_write(h, path.as_ptr(), 0, b"hello".as_ptr(), 5), 5);
+        let mut buf = [0u8; 16];
+        let n = rhss_read(h, path.as_ptr(), 1, buf.as_mut_ptr(), buf.len());
+        assert_eq!(&buf[..n as usize], b"ello");
+
+        let mut st = std::mem::zeroed::<RhssStat>();
+        assert_eq!(rhss_stat(h, path.as_ptr(), &mut st), 0);
+        assert_eq!((st.size, st.is_dir, st.tier), (5, false, RHSS_TIER_FAST));
+
+        assert_eq!(rhss_migrate(h, path.as_ptr(), RHSS_TIER_SLOW), 1);
+        assert_eq!(rhss_migrate(h, path.as_ptr(), RHSS_TIER_SLOW), 0);
+        assert_eq!(rhss_stat(h, path.as_ptr(), &mut st), 0);
+        assert_eq!(st.tier, RHSS_TIER_SLOW);
+        assert!(root.join("hdd/.rhss_managed/a.bin").exists());
+
+        let missing = c"/missing";
+        assert_eq!(rhss_stat(h, missing.as_ptr(), &mut st), -libc::ENOENT);
+        let msg = CStr::from_ptr(rhss_last_error()).to_str().unwrap();
+        assert!(msg.contains("missing"), "{msg}");
+        assert_eq!(rhss_unmount(h), -libc::EINVAL);
+
+        rhss_shutdown(h);
+    }
+}
@@ -78,6 +78,7 @@ fn mirror_migration_writes_to_all_backends() {
             hit_count: 0,
             popularity: 0.0,
             pinned_tier: None,
+            pinned_until: None,
             state: FileState::Stable,
             mutability: rhss::index::Mutability::Unknown,
             compressed: false,